@@ -33,4 +33,12 @@ pub enum Error {
     /// A loaded glTF buffer is not of the required length.
     #[error(display = "Loaded buffer does not match required length")]
     BufferLength(gltf::json::Path),
+
+    /// A skin has more joints than the renderer can skin meshes with.
+    #[error(
+        display = "Skin has {} joints, more than the renderer supports: {}",
+        _0,
+        _1
+    )]
+    TooManyJoints(usize, usize),
 }
@@ -110,6 +110,7 @@ pub fn load_material(
     if let AlphaMode::Mask = material.alpha_mode() {
         prefab.alpha_cutoff = material.alpha_cutoff();
     }
+    prefab.two_sided = material.double_sided();
 
     Ok(prefab)
 }
@@ -4,10 +4,10 @@ use amethyst_animation::{JointPrefab, SkinPrefab, SkinnablePrefab};
 use amethyst_assets::Prefab;
 use amethyst_core::nalgebra::Matrix4;
 use amethyst_error::Error;
-use amethyst_renderer::JointTransformsPrefab;
+use amethyst_renderer::{JointTransformsPrefab, MAX_JOINTS};
 
 use super::Buffers;
-use crate::GltfPrefab;
+use crate::{error, GltfPrefab};
 
 pub fn load_skin(
     skin: &gltf::Skin<'_>,
@@ -25,6 +25,9 @@ pub fn load_skin(
             )
         })
         .collect::<Vec<_>>();
+    if joints.len() > MAX_JOINTS {
+        return Err(error::Error::TooManyJoints(joints.len(), MAX_JOINTS).into());
+    }
 
     let reader = skin.reader(|buffer| buffers.buffer(&buffer));
 
@@ -2,11 +2,13 @@
 
 use amethyst_assets::PrefabData;
 use amethyst_core::{
-    nalgebra::{Matrix4, Orthographic3, Perspective3},
+    nalgebra::{Matrix4, Orthographic3, Perspective3, Vector3},
     specs::prelude::{Component, Entity, HashMapStorage, Write, WriteStorage},
+    transform::GlobalTransform,
 };
 use amethyst_error::Error;
 
+use gfx::Rect;
 use serde::{Deserialize, Serialize};
 
 /// The projection mode of a `Camera`.
@@ -76,6 +78,17 @@ impl Camera {
             std::f32::consts::FRAC_PI_3,
         ))
     }
+
+    /// Whether the projection is orthographic, showing everything at the same size however far
+    /// away it is.
+    ///
+    /// Skies look the same in every direction of an orthographic camera, so the sky passes draw
+    /// them as a backdrop for it instead, see `DrawSkyBox`.
+    #[cfg_attr(feature = "cargo-clippy", allow(float_cmp))] // only exact zeros divide by depth
+    pub fn is_orthographic(&self) -> bool {
+        let w = self.proj.row(3);
+        w[0] == 0.0 && w[1] == 0.0 && w[2] == 0.0
+    }
 }
 
 impl Component for Camera {
@@ -90,6 +103,242 @@ pub struct ActiveCamera {
     pub entity: Option<Entity>,
 }
 
+/// Region of the render target a camera draws into, for split-screen or picture-in-picture.
+///
+/// Coordinates are fractions of the target size, with (0, 0) in the bottom left corner and
+/// (1, 1) in the top right. When any camera has a `Viewport`, the flat, shaded and pbm passes
+/// draw the scene once for each such camera and ignore `ActiveCamera`. The camera's projection
+/// should use the aspect ratio of its viewport, see `Viewport::aspect_ratio`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Viewport {
+    /// Left edge of the region.
+    pub x: f32,
+    /// Bottom edge of the region.
+    pub y: f32,
+    /// Width of the region.
+    pub width: f32,
+    /// Height of the region.
+    pub height: f32,
+}
+
+impl Viewport {
+    /// Creates a viewport from its bottom left corner and size.
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Viewport {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Returns the aspect ratio of the region, on a target of the given size.
+    pub fn aspect_ratio(&self, target_width: f32, target_height: f32) -> f32 {
+        (self.width * target_width) / (self.height * target_height)
+    }
+
+    /// Returns `proj` followed by a mapping of the whole screen onto the region.
+    pub fn projection(&self, proj: &Matrix4<f32>) -> Matrix4<f32> {
+        let scale = Vector3::new(self.width, self.height, 1.0);
+        let offset = Vector3::new(
+            2.0 * self.x + self.width - 1.0,
+            2.0 * self.y + self.height - 1.0,
+            0.0,
+        );
+        Matrix4::new_translation(&offset) * Matrix4::new_nonuniform_scaling(&scale) * proj
+    }
+
+    /// Returns the pixels covered by the region on a target of the given size.
+    ///
+    /// Edges are rounded the same way for all viewports, so adjacent regions don't overlap or
+    /// leave gaps.
+    pub(crate) fn scissor(&self, target_width: u16, target_height: u16) -> Rect {
+        let edge = |fraction: f32, size: u16| {
+            (fraction.max(0.0).min(1.0) * f32::from(size)).round() as u16
+        };
+        let (left, right) = (
+            edge(self.x, target_width),
+            edge(self.x + self.width, target_width),
+        );
+        let (bottom, top) = (
+            edge(self.y, target_height),
+            edge(self.y + self.height, target_height),
+        );
+        Rect {
+            x: left,
+            y: bottom,
+            w: right.saturating_sub(left),
+            h: top.saturating_sub(bottom),
+        }
+    }
+
+    /// Returns whether the region spans the whole target.
+    pub(crate) fn covers_target(&self) -> bool {
+        self.x <= 0.0 && self.y <= 0.0 && self.x + self.width >= 1.0 && self.y + self.height >= 1.0
+    }
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Viewport::new(0.0, 0.0, 1.0, 1.0)
+    }
+}
+
+impl Component for Viewport {
+    type Storage = HashMapStorage<Self>;
+}
+
+/// Renders a camera's view into a named pipeline target instead of the screen.
+///
+/// The flat, shaded and pbm passes drawing into that target use the cameras pointed at it,
+/// together with their `Viewport`s, and the ones drawing anywhere else ignore them. Register the
+/// target with `TargetTextures` to sample the result like any other texture, e.g. for mirrors,
+/// monitors or minimaps.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CameraTarget {
+    /// Name of the target, as given to `Target::named`.
+    pub target: String,
+}
+
+impl CameraTarget {
+    /// Points a camera at the target with the given name.
+    pub fn new<N: Into<String>>(target: N) -> Self {
+        CameraTarget {
+            target: target.into(),
+        }
+    }
+}
+
+impl Component for CameraTarget {
+    type Storage = HashMapStorage<Self>;
+}
+
+/// Renders a camera's view once for each eye, for stereo displays and VR headsets.
+///
+/// The scene and sky passes draw the camera twice, into the left and right halves of its
+/// `Viewport`, or of the whole target without one. The eyes sit `ipd` apart along the camera's
+/// local x axis, centered on its `GlobalTransform`, and each uses its own projection in place of
+/// the `Camera`'s, usually the asymmetric frustum a headset reports for that eye. Both skies are
+/// drawn with the same orientation, so they line up for either eye.
+///
+/// Screen-space passes like fog, SSAO and godrays still work from the `Camera` alone.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct StereoCamera {
+    /// Interpupillary distance, between the centers of the eyes, in world units.
+    pub ipd: f32,
+    /// Projection of the left eye, for the aspect ratio of half the viewport.
+    pub left: Matrix4<f32>,
+    /// Projection of the right eye, for the aspect ratio of half the viewport.
+    pub right: Matrix4<f32>,
+}
+
+impl StereoCamera {
+    /// Creates a stereo camera with separate projections for the left and right eye.
+    pub fn new(ipd: f32, left: Matrix4<f32>, right: Matrix4<f32>) -> Self {
+        StereoCamera { ipd, left, right }
+    }
+
+    /// Creates a stereo camera using `proj` for both eyes.
+    pub fn symmetric(ipd: f32, proj: Matrix4<f32>) -> Self {
+        StereoCamera::new(ipd, proj, proj)
+    }
+
+    /// Returns the camera, transform and region of the target of each eye, the left one first.
+    ///
+    /// `transform` and `viewport` belong to the camera the eyes are part of.
+    pub fn eyes(
+        &self,
+        transform: &GlobalTransform,
+        viewport: Option<&Viewport>,
+    ) -> [(Camera, GlobalTransform, Viewport); 2] {
+        let viewport = viewport.cloned().unwrap_or_default();
+        let half = viewport.width / 2.0;
+        let eye = |proj: Matrix4<f32>, side: f32, x: f32| {
+            let offset = Matrix4::new_translation(&Vector3::new(side * self.ipd / 2.0, 0.0, 0.0));
+            (
+                Camera { proj },
+                GlobalTransform(transform.0 * offset),
+                Viewport::new(x, viewport.y, half, viewport.height),
+            )
+        };
+        [
+            eye(self.left, -1.0, viewport.x),
+            eye(self.right, 1.0, viewport.x + half),
+        ]
+    }
+}
+
+impl Component for StereoCamera {
+    type Storage = HashMapStorage<Self>;
+}
+
+/// Photographic exposure settings of a camera, scaling the colors `DrawTonemap` maps.
+///
+/// The settings combine into an exposure value at ISO 100,
+/// `EV100 = log2(aperture² / shutter * 100 / iso)`, which grows by one for each stop less light
+/// reaching the sensor. Scenes aren't lit in physical units, so the EV is taken relative to that
+/// of the defaults, the "sunny 16" rule of f/16 at 1/100 s and ISO 100: the colors are multiplied
+/// with `2^(EV100 of the defaults - EV100 + compensation)`. The defaults thus keep the scene as
+/// it is, opening the aperture or lengthening the shutter by a stop doubles its brightness.
+///
+/// `compensation` adds stops on top of the settings, e.g. for an auto-exposure system that
+/// adapts to the scene's brightness while the settings stay under manual control. Add the
+/// component to the entity of the `ActiveCamera`, or to the first camera while none is active.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct CameraExposure {
+    /// The f-number, the focal length divided by the diameter of the aperture, `16.0` by
+    /// default.
+    pub aperture: f32,
+    /// How long the shutter stays open, in seconds, `0.01` by default.
+    pub shutter: f32,
+    /// Sensitivity of the sensor, `100.0` by default.
+    pub iso: f32,
+    /// Stops of exposure added to the settings, brightening the scene for positive values. Zero
+    /// by default.
+    pub compensation: f32,
+}
+
+impl Default for CameraExposure {
+    fn default() -> Self {
+        CameraExposure::new(16.0, 0.01, 100.0)
+    }
+}
+
+impl CameraExposure {
+    /// Creates exposure settings of the given f-number, shutter time in seconds and ISO.
+    pub fn new(aperture: f32, shutter: f32, iso: f32) -> Self {
+        CameraExposure {
+            aperture,
+            shutter,
+            iso,
+            compensation: 0.0,
+        }
+    }
+
+    /// Sets the stops of exposure added to the settings.
+    pub fn with_compensation(mut self, compensation: f32) -> Self {
+        self.compensation = compensation;
+        self
+    }
+
+    /// Returns the exposure value of the settings at ISO 100, without the compensation.
+    pub fn ev100(&self) -> f32 {
+        let ratio = self.aperture * self.aperture / self.shutter.max(1e-6);
+        (ratio * 100.0 / self.iso.max(1e-6)).log2()
+    }
+
+    /// Returns the factor the scene colors are multiplied with, `1.0` for the defaults.
+    pub fn exposure(&self) -> f32 {
+        let reference = CameraExposure::default().ev100();
+        (reference - self.ev100() + self.compensation).exp2()
+    }
+}
+
+impl Component for CameraExposure {
+    type Storage = HashMapStorage<Self>;
+}
+
 /// Projection prefab
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum CameraPrefab {
@@ -430,3 +679,75 @@ mod serde_persp {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use amethyst_core::{
+        nalgebra::{Matrix4, Vector3, Vector4},
+        transform::GlobalTransform,
+    };
+
+    use super::{CameraExposure, StereoCamera, Viewport};
+
+    #[test]
+    fn projection_maps_screen_onto_region() {
+        let right_half = Viewport::new(0.5, 0.0, 0.5, 1.0);
+        let proj = right_half.projection(&Matrix4::identity());
+        let corner = |x, y| proj * Vector4::new(x, y, 0.0, 1.0);
+        assert_eq!(corner(-1.0, -1.0), Vector4::new(0.0, -1.0, 0.0, 1.0));
+        assert_eq!(corner(1.0, 1.0), Vector4::new(1.0, 1.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn adjacent_scissors_tile_the_target() {
+        let left = Viewport::new(0.0, 0.0, 1.0 / 3.0, 1.0).scissor(100, 50);
+        let right = Viewport::new(1.0 / 3.0, 0.0, 2.0 / 3.0, 1.0).scissor(100, 50);
+        assert_eq!((left.x, left.w, left.h), (0, 33, 50));
+        assert_eq!((right.x, right.w), (33, 67));
+    }
+
+    #[test]
+    fn only_full_regions_cover_the_target() {
+        assert!(Viewport::default().covers_target());
+        assert!(Viewport::new(-0.5, -0.5, 2.0, 2.0).covers_target());
+        assert!(!Viewport::new(0.0, 0.0, 0.5, 1.0).covers_target());
+        assert!(!Viewport::new(0.0, 0.1, 1.0, 1.0).covers_target());
+    }
+
+    #[test]
+    fn eyes_split_the_viewport_and_stand_apart() {
+        let stereo = StereoCamera::symmetric(0.064, Matrix4::identity());
+        // The camera is turned to look along +X, so its local x axis points along +Z.
+        let turned = Matrix4::new_rotation(Vector3::new(0.0, -std::f32::consts::FRAC_PI_2, 0.0));
+        let transform =
+            GlobalTransform(Matrix4::new_translation(&Vector3::new(1.0, 2.0, 3.0)) * turned);
+        let [left, right] = stereo.eyes(&transform, Some(&Viewport::new(0.5, 0.0, 0.5, 0.5)));
+
+        assert_eq!(left.2, Viewport::new(0.5, 0.0, 0.25, 0.5));
+        assert_eq!(right.2, Viewport::new(0.75, 0.0, 0.25, 0.5));
+        let position = |transform: &GlobalTransform| transform.0.column(3).xyz();
+        let between = position(&right.1) - position(&left.1);
+        assert!((between - Vector3::new(0.0, 0.0, 0.064)).norm() < 1e-6);
+        let center = (position(&left.1) + position(&right.1)) / 2.0;
+        assert!((center - Vector3::new(1.0, 2.0, 3.0)).norm() < 1e-6);
+    }
+
+    #[test]
+    fn exposure_doubles_per_stop() {
+        let assert_exposure = |settings: CameraExposure, expected: f32| {
+            let exposure = settings.exposure();
+            assert!(
+                (exposure - expected).abs() < 1e-3 * expected,
+                "{:?} exposed by {}, expected {}",
+                settings,
+                exposure,
+                expected
+            );
+        };
+        assert_exposure(CameraExposure::default(), 1.0);
+        assert_exposure(CameraExposure::new(16.0, 0.02, 100.0), 2.0);
+        assert_exposure(CameraExposure::new(8.0, 0.01, 100.0), 4.0);
+        assert_exposure(CameraExposure::new(16.0, 0.01, 50.0), 0.5);
+        assert_exposure(CameraExposure::default().with_compensation(-2.0), 0.25);
+    }
+}
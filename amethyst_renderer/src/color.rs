@@ -64,6 +64,43 @@ impl Rgba {
     pub fn white() -> Rgba {
         Rgba::WHITE
     }
+
+    /// Returns the linear color of a black body glowing at `kelvin`, scaled so its brightest
+    /// channel is `1.0`.
+    ///
+    /// Candles are around 1900K, incandescent bulbs 2700K, the midday sun 5500K and an overcast
+    /// sky 7000K. About 6600K is white, lower temperatures are orange and higher ones blue. The
+    /// temperature is clamped to the 1000K to 40000K the approximation is fitted for.
+    pub fn from_temperature(kelvin: f32) -> Rgba {
+        // Tanner Helland's fit of the black body colors, giving sRGB channels from 0 to 255.
+        let t = kelvin.max(1000.0).min(40000.0) / 100.0;
+        let red = if t <= 66.0 {
+            255.0
+        } else {
+            329.698_73 * (t - 60.0).powf(-0.133_204_76)
+        };
+        let green = if t <= 66.0 {
+            99.470_8 * t.ln() - 161.119_57
+        } else {
+            288.122_17 * (t - 60.0).powf(-0.075_514_85)
+        };
+        let blue = if t >= 66.0 {
+            255.0
+        } else if t <= 19.0 {
+            0.0
+        } else {
+            138.517_73 * (t - 10.0).ln() - 305.044_8
+        };
+        let linear = |srgb: f32| {
+            let c = srgb.max(0.0).min(255.0) / 255.0;
+            if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        };
+        Rgba(linear(red), linear(green), linear(blue), 1.0)
+    }
 }
 
 impl Default for Rgba {
@@ -147,3 +184,24 @@ impl From<Rgba> for vec4 {
         [r, g, b, a].into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Rgba;
+
+    #[test]
+    fn temperatures_run_from_orange_to_blue() {
+        let Rgba(r, g, b, _) = Rgba::from_temperature(6600.0);
+        assert!(r > 0.99 && g > 0.99 && b > 0.99);
+
+        let Rgba(r, g, b, _) = Rgba::from_temperature(2700.0);
+        assert_eq!(r, 1.0);
+        assert!(r > g && g > b);
+
+        let Rgba(r, g, b, _) = Rgba::from_temperature(10000.0);
+        assert_eq!(b, 1.0);
+        assert!(b > g && g > r);
+
+        assert_eq!(Rgba::from_temperature(0.0), Rgba::from_temperature(1000.0));
+    }
+}
@@ -0,0 +1,245 @@
+//! Bounding volumes and view frustum tests.
+
+use amethyst_core::{
+    nalgebra::{Matrix4, Point3, Vector3, Vector4},
+    specs::prelude::{Component, DenseVecStorage},
+    GlobalTransform,
+};
+
+use crate::cam::Camera;
+
+/// Sphere enclosing an entity's mesh, in the entity's local space.
+///
+/// Passes with frustum culling enabled skip entities whose bounds are outside of the camera's
+/// view. Entities without bounds are always drawn.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BoundingSphere {
+    /// Center of the sphere.
+    pub center: Point3<f32>,
+    /// Radius of the sphere.
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    /// Creates a sphere around `center`.
+    pub fn new(center: Point3<f32>, radius: f32) -> Self {
+        BoundingSphere { center, radius }
+    }
+}
+
+impl Component for BoundingSphere {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Axis aligned box enclosing an entity's mesh, in the entity's local space.
+///
+/// A tighter fit than `BoundingSphere` for long or flat meshes. If an entity has both, the
+/// sphere is tested first.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Aabb {
+    /// Corner with the smallest coordinates.
+    pub min: Point3<f32>,
+    /// Corner with the largest coordinates.
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    /// Creates a box from its two opposite corners.
+    pub fn new(min: Point3<f32>, max: Point3<f32>) -> Self {
+        Aabb { min, max }
+    }
+
+    /// Returns the smallest box containing all `points`, or `None` if there are none.
+    pub fn from_points<I>(points: I) -> Option<Self>
+    where
+        I: IntoIterator<Item = [f32; 3]>,
+    {
+        let mut points = points.into_iter();
+        let first = Vector3::from(points.next()?);
+        let (min, max) = points.fold((first, first), |(min, max), p| {
+            let p = Vector3::from(p);
+            (min.inf(&p), max.sup(&p))
+        });
+        Some(Aabb::new(Point3::from(min), Point3::from(max)))
+    }
+}
+
+impl Component for Aabb {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// The volume visible through a camera, as six planes facing inwards.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frustum {
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// Returns the frustum of `camera` placed at `transform`.
+    pub fn new(camera: &Camera, transform: &GlobalTransform) -> Self {
+        let view = transform.0.try_inverse().unwrap_or_else(Matrix4::identity);
+        Frustum::from_matrix(&(camera.proj * view))
+    }
+
+    /// Returns the frustum of a combined projection and view matrix.
+    pub fn from_matrix(matrix: &Matrix4<f32>) -> Self {
+        let row = |i: usize| matrix.row(i).transpose();
+        let normalize = |plane: Vector4<f32>| {
+            let length = plane.xyz().norm();
+            if length > 0.0 {
+                plane / length
+            } else {
+                plane
+            }
+        };
+        Frustum {
+            planes: [
+                normalize(row(3) + row(0)),
+                normalize(row(3) - row(0)),
+                normalize(row(3) + row(1)),
+                normalize(row(3) - row(1)),
+                normalize(row(3) + row(2)),
+                normalize(row(3) - row(2)),
+            ],
+        }
+    }
+
+    /// Returns whether any part of the world space sphere is inside the frustum.
+    pub fn contains_sphere(&self, center: &Point3<f32>, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.xyz().dot(&center.coords) + plane.w >= -radius)
+    }
+
+    /// Returns whether any part of a box, given by its world space center and half extents
+    /// along the world axes, is inside the frustum.
+    ///
+    /// Boxes near the corners of the frustum may be reported as inside, which is fine for
+    /// culling.
+    pub fn contains_box(&self, center: &Point3<f32>, half_extents: &Vector3<f32>) -> bool {
+        self.planes.iter().all(|plane| {
+            let normal = plane.xyz();
+            let radius = normal.abs().dot(half_extents);
+            normal.dot(&center.coords) + plane.w >= -radius
+        })
+    }
+
+    /// Returns whether an entity at `global` can be seen, going by its local space bounds.
+    ///
+    /// Entities without bounds always can.
+    pub fn contains(
+        &self,
+        global: &GlobalTransform,
+        sphere: Option<&BoundingSphere>,
+        aabb: Option<&Aabb>,
+    ) -> bool {
+        let m = &global.0;
+        if let Some(sphere) = sphere {
+            let center = transform_point(m, &sphere.center);
+            let scale = (0..3).map(|i| m.column(i).xyz().norm()).fold(0.0, f32::max);
+            if !self.contains_sphere(&center, sphere.radius * scale) {
+                return false;
+            }
+        }
+        if let Some(aabb) = aabb {
+            let center = Point3::from((aabb.min.coords + aabb.max.coords) * 0.5);
+            let half = (aabb.max.coords - aabb.min.coords) * 0.5;
+            // Extents of the transformed box along the world axes.
+            let half =
+                Vector3::from_fn(|i, _| (0..3).map(|j| m[(i, j)].abs() * half[j]).sum::<f32>());
+            if !self.contains_box(&transform_point(m, &center), &half) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+fn transform_point(m: &Matrix4<f32>, p: &Point3<f32>) -> Point3<f32> {
+    Point3::from((m * p.to_homogeneous()).xyz())
+}
+
+#[cfg(test)]
+mod tests {
+    use amethyst_core::{
+        nalgebra::{Matrix4, Point3, Vector3},
+        GlobalTransform,
+    };
+
+    use super::{Aabb, BoundingSphere, Frustum};
+    use crate::cam::{Camera, Projection};
+
+    /// A 90° camera at `position`, looking along -z.
+    fn frustum(position: [f32; 3]) -> Frustum {
+        let camera = Camera::from(Projection::perspective(1.0, std::f32::consts::FRAC_PI_2));
+        let transform = GlobalTransform(Matrix4::new_translation(&Vector3::from(position)));
+        Frustum::new(&camera, &transform)
+    }
+
+    fn at(position: [f32; 3]) -> GlobalTransform {
+        GlobalTransform(Matrix4::new_translation(&Vector3::from(position)))
+    }
+
+    fn sphere(radius: f32) -> BoundingSphere {
+        BoundingSphere::new(Point3::origin(), radius)
+    }
+
+    #[test]
+    fn sphere_in_front_is_visible() {
+        let frustum = frustum([0.0; 3]);
+        assert!(frustum.contains(&at([0.0, 0.0, -10.0]), Some(&sphere(1.0)), None));
+        assert!(frustum.contains(&at([5.0, -5.0, -10.0]), Some(&sphere(1.0)), None));
+    }
+
+    #[test]
+    fn sphere_outside_is_culled() {
+        let frustum = frustum([0.0; 3]);
+        // Behind the camera, beside the view and beyond the far plane.
+        assert!(!frustum.contains(&at([0.0, 0.0, 10.0]), Some(&sphere(1.0)), None));
+        assert!(!frustum.contains(&at([30.0, 0.0, -10.0]), Some(&sphere(1.0)), None));
+        assert!(!frustum.contains(&at([0.0, 0.0, -3000.0]), Some(&sphere(1.0)), None));
+    }
+
+    #[test]
+    fn sphere_crossing_a_plane_is_visible() {
+        let frustum = frustum([0.0; 3]);
+        // The center is just outside the right plane, x = -z.
+        assert!(frustum.contains(&at([10.5, 0.0, -10.0]), Some(&sphere(1.0)), None));
+    }
+
+    #[test]
+    fn camera_transform_is_applied() {
+        let frustum = frustum([0.0, 0.0, 20.0]);
+        assert!(frustum.contains(&at([0.0; 3]), Some(&sphere(1.0)), None));
+        assert!(!frustum.contains(&at([0.0, 0.0, 30.0]), Some(&sphere(1.0)), None));
+    }
+
+    #[test]
+    fn scaled_sphere_grows() {
+        let frustum = frustum([0.0; 3]);
+        let scaled = GlobalTransform(
+            Matrix4::new_translation(&Vector3::new(0.0, 0.0, 10.0)) * Matrix4::new_scaling(20.0),
+        );
+        assert!(frustum.contains(&scaled, Some(&sphere(1.0)), None));
+    }
+
+    #[test]
+    fn boxes_are_tested() {
+        let frustum = frustum([0.0; 3]);
+        let aabb = Aabb::from_points(vec![[-1.0, -1.0, -1.0], [1.0, 1.0, 1.0]]).unwrap();
+        assert!(frustum.contains(&at([0.0, 0.0, -10.0]), None, Some(&aabb)));
+        assert!(!frustum.contains(&at([0.0, 0.0, 10.0]), None, Some(&aabb)));
+
+        let long = Aabb::new(
+            Point3::new(-100.0, -1.0, -1.0),
+            Point3::new(100.0, 1.0, 1.0),
+        );
+        assert!(frustum.contains(&at([50.0, 0.0, -10.0]), None, Some(&long)));
+    }
+
+    #[test]
+    fn unbounded_entities_are_visible() {
+        let frustum = frustum([0.0; 3]);
+        assert!(frustum.contains(&at([0.0, 0.0, 10.0]), None, None));
+    }
+}
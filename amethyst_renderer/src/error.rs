@@ -7,8 +7,12 @@ use std::{error, fmt};
 pub(crate) enum Error {
     /// A render target with the given name does not exist.
     NoSuchTarget(String),
-    /// Failed to create a pipeline state object (PSO).
-    ProgramCreation,
+    /// The shader of the given stage failed to compile, with the driver's log.
+    ShaderCompile(&'static str, String),
+    /// The compiled shaders failed to link into a program, with the driver's log.
+    ProgramLink(String),
+    /// Failed to compile the pass with the given type.
+    CompilePass(String),
     /// Failed to interact with the ECS.
     PixelDataMismatch(String),
     /// The window handle associated with the renderer has been destroyed.
@@ -23,6 +27,54 @@ pub(crate) enum Error {
     DecodeImageError,
     /// Failed to create texture.
     CreateTextureError,
+    /// Failed to read the cubemap face with the given index and path.
+    CubemapFaceIo(usize, String),
+    /// Failed to decode the cubemap face with the given index and path.
+    CubemapFaceDecode(usize, String),
+    /// A cubemap face does not have the requested square size.
+    CubemapFaceSize(usize, u16, (u32, u32)),
+    /// A texture that has to be a cubemap is not one.
+    NotCubemap,
+    /// The texture data of the given variant doesn't match the kind its metadata declares.
+    TextureKindMismatch(&'static str, String),
+    /// Cubemap data holds the given number of values, instead of the given number for its faces.
+    CubemapDataSize(usize, usize),
+    /// Failed to read the panorama at the given path.
+    PanoramaIo(String),
+    /// Failed to decode the panorama at the given path.
+    PanoramaDecode(String),
+    /// Failed to read the cubemap layout image at the given path.
+    CubemapLayoutIo(String),
+    /// Failed to decode the cubemap layout image at the given path.
+    CubemapLayoutDecode(String),
+    /// A cubemap layout image of the given size can't be split into the given grid of faces.
+    CubemapLayoutSize((u32, u32), (u32, u32)),
+    /// The face order of a cubemap strip doesn't name every face exactly once.
+    CubemapLayoutOrder,
+    /// The target with the given name has no color buffer that can be sampled.
+    TargetNotReadable(String),
+    /// The mesh has no vertex buffer with the format being updated.
+    NoSuchVertexBuffer,
+    /// Failed to write new vertices into a vertex buffer.
+    UpdateVertexBuffer,
+    /// A KTX file is malformed or uses an unsupported layout.
+    KtxDecode(&'static str),
+    /// A KTX file has the given format field with an unsupported value.
+    KtxUnsupportedFormat(&'static str, u32),
+    /// A DDS file is malformed or uses an unsupported layout.
+    DdsDecode(&'static str),
+    /// A DDS file has the given format field with an unsupported value.
+    DdsUnsupportedFormat(&'static str, u32),
+    /// A DDS file holds surfaces of the given block compressed format.
+    DdsBlockCompressed(&'static str),
+    /// A skin or skinned mesh refers to the given number of joints, more than `MAX_JOINTS`.
+    TooManyJoints(usize),
+    /// Failed to create the named mesh a pass draws, the pass is named first.
+    CreatePassMesh(&'static str, &'static str),
+    /// Failed to create an OpenGL context without a window.
+    CreateHeadlessContext,
+    /// Failed to read the pixels of a target back from the GPU.
+    ReadPixels,
 }
 
 impl error::Error for Error {}
@@ -33,7 +85,11 @@ impl fmt::Display for Error {
 
         match *self {
             NoSuchTarget(ref e) => write!(fmt, "Nonexistent target: {}", e),
-            ProgramCreation => write!(fmt, "Program compilation failed"),
+            ShaderCompile(stage, ref log) => {
+                write!(fmt, "Failed to compile {} shader:\n{}", stage, log)
+            }
+            ProgramLink(ref log) => write!(fmt, "Failed to link shader program:\n{}", log),
+            CompilePass(ref pass) => write!(fmt, "Failed to compile pass {}", pass),
             PixelDataMismatch(ref e) => write!(fmt, "Pixel data and metadata do not match: {}", e),
             WindowDestroyed => write!(fmt, "Window has been destroyed"),
             LoadSpritesheetError => write!(fmt, "Failed to parse SpriteSheet"),
@@ -48,6 +104,80 @@ impl fmt::Display for Error {
             ),
             DecodeImageError => write!(fmt, "Image decoding failed"),
             CreateTextureError => write!(fmt, "Failed to create texture from texture data"),
+            CubemapFaceIo(face, ref path) => {
+                write!(fmt, "Failed to read cubemap face {} from {:?}", face, path)
+            }
+            CubemapFaceDecode(face, ref path) => {
+                write!(fmt, "Failed to decode cubemap face {} from {:?}", face, path)
+            }
+            CubemapFaceSize(face, size, (w, h)) => write!(
+                fmt,
+                "Cubemap face {} is {}x{}, expected {}x{}",
+                face, w, h, size, size,
+            ),
+            NotCubemap => write!(fmt, "Texture is not a cubemap"),
+            TextureKindMismatch(variant, ref kind) => write!(
+                fmt,
+                "Texture data {} doesn't match the kind {} of its metadata",
+                variant, kind
+            ),
+            CubemapDataSize(len, expected) => write!(
+                fmt,
+                "Cubemap data holds {} values, expected {} or its faces' mip levels",
+                len, expected
+            ),
+            PanoramaIo(ref path) => write!(fmt, "Failed to read panorama from {:?}", path),
+            PanoramaDecode(ref path) => write!(fmt, "Failed to decode panorama from {:?}", path),
+            CubemapLayoutIo(ref path) => {
+                write!(fmt, "Failed to read cubemap layout from {:?}", path)
+            }
+            CubemapLayoutDecode(ref path) => {
+                write!(fmt, "Failed to decode cubemap layout from {:?}", path)
+            }
+            CubemapLayoutSize((w, h), (columns, rows)) => write!(
+                fmt,
+                "Cubemap layout is {}x{}, which doesn't split into {}x{} square faces",
+                w, h, columns, rows
+            ),
+            CubemapLayoutOrder => write!(
+                fmt,
+                "Cubemap strip has to name every face exactly once in its face order"
+            ),
+            TargetNotReadable(ref e) => write!(fmt, "Target cannot be sampled: {}", e),
+            NoSuchVertexBuffer => write!(fmt, "Mesh has no vertex buffer with the given format"),
+            UpdateVertexBuffer => write!(fmt, "Failed to update vertex buffer"),
+            KtxDecode(e) => write!(fmt, "Failed to decode KTX texture: {}", e),
+            KtxUnsupportedFormat(field, value) => write!(
+                fmt,
+                "KTX texture has unsupported {} {:#x}, only uncompressed RGBA8 and RGBA32F \
+                 can be loaded",
+                field, value
+            ),
+            DdsDecode(e) => write!(fmt, "Failed to decode DDS texture: {}", e),
+            DdsUnsupportedFormat(field, value) => write!(
+                fmt,
+                "DDS texture has unsupported {} {:#x}, only uncompressed RGBA8 and RGBA32F \
+                 can be loaded",
+                field, value
+            ),
+            DdsBlockCompressed(block) => write!(
+                fmt,
+                "DDS texture is {} block compressed, which gfx_core 0.8 has no surface type for; \
+                 convert it to uncompressed RGBA8",
+                block
+            ),
+            TooManyJoints(count) => write!(
+                fmt,
+                "Skin uses {} joints, but skinned meshes can use at most {}, split the mesh into \
+                 parts with fewer joints each",
+                count,
+                crate::skinning::MAX_JOINTS
+            ),
+            CreatePassMesh(pass, mesh) => {
+                write!(fmt, "Failed to create the {} mesh of {}", mesh, pass)
+            }
+            CreateHeadlessContext => write!(fmt, "Failed to create an OpenGL context"),
+            ReadPixels => write!(fmt, "Failed to read the pixels of a target"),
         }
     }
 }
@@ -0,0 +1,350 @@
+//! DirectDraw surface container (`.dds`) loading.
+
+use gfx::format::ChannelType;
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+
+use amethyst_assets::SimpleFormat;
+use amethyst_error::Error;
+
+use crate::{
+    error,
+    formats::{
+        ktx::{read_u32, texture_data, Decoded, Texels},
+        TextureData, TextureMetadata,
+    },
+    tex::Texture,
+};
+
+const MAGIC: &[u8; 4] = b"DDS ";
+const HEADER_SIZE: u32 = 124;
+const DX10_HEADER_SIZE: usize = 20;
+
+const DDSD_MIPMAPCOUNT: u32 = 0x2_0000;
+const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDSCAPS2_CUBEMAP_ALLFACES: u32 = 0xfc00;
+const DDSCAPS2_VOLUME: u32 = 0x20_0000;
+
+const D3DFMT_A32B32G32R32F: u32 = 116;
+
+const DXGI_FORMAT_R32G32B32A32_FLOAT: u32 = 2;
+const DXGI_FORMAT_R8G8B8A8_UNORM: u32 = 28;
+const DXGI_FORMAT_R8G8B8A8_UNORM_SRGB: u32 = 29;
+const DXGI_FORMAT_B8G8R8A8_UNORM: u32 = 87;
+const DXGI_FORMAT_B8G8R8A8_UNORM_SRGB: u32 = 91;
+
+const D3D10_RESOURCE_DIMENSION_TEXTURE2D: u32 = 3;
+const D3D10_RESOURCE_MISC_TEXTURECUBE: u32 = 0x4;
+
+/// Allows loading of DirectDraw surface containers.
+///
+/// Like `KtxFormat`, both 2D textures and cubemaps are loaded together with their mip levels,
+/// and the surface format, channel type, size, kind and mip levels of the `TextureMetadata`
+/// passed in are replaced by the ones of the file. Files without a DX10 header don't say
+/// whether they hold sRGB data, so their 8-bit texels keep the channel type of the metadata.
+///
+/// Only uncompressed RGBA8 (optionally in BGRA order) and RGBA32F surfaces can be loaded. Block
+/// compressed BC1 to BC7 files are refused with an error naming their format: gfx_core 0.8 has
+/// no surface types for them, so they can't be uploaded without decompressing them first.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DdsFormat;
+
+impl DdsFormat {
+    /// Loads a DDS texture from a byte slice.
+    pub fn from_data(data: &[u8], options: TextureMetadata) -> Result<TextureData, Error> {
+        let srgb = options.channel == ChannelType::Srgb;
+        Ok(texture_data(decode(data, srgb)?, options))
+    }
+}
+
+impl SimpleFormat<Texture> for DdsFormat {
+    const NAME: &'static str = "DDS";
+
+    type Options = TextureMetadata;
+
+    fn import(&self, bytes: Vec<u8>, options: TextureMetadata) -> Result<TextureData, Error> {
+        DdsFormat::from_data(&bytes, options)
+    }
+}
+
+/// Returns whether `data` starts like a DDS file.
+pub(crate) fn is_dds(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Decodes a DDS file, whose 8-bit texels are sRGB if `srgb` and the file doesn't say otherwise.
+pub(crate) fn decode(data: &[u8], srgb: bool) -> Result<Decoded, Error> {
+    if !is_dds(data) {
+        return Err(invalid("missing DDS identifier"));
+    }
+    let field = |offset: usize| get_u32(data, offset);
+    if field(4)? != HEADER_SIZE {
+        return Err(invalid("header has the wrong size"));
+    }
+    let height = field(12)?;
+    let width = field(16)?;
+    let levels = if field(8)? & DDSD_MIPMAPCOUNT != 0 {
+        field(28)?.max(1) as usize
+    } else {
+        1
+    };
+    let caps2 = field(112)?;
+    if caps2 & DDSCAPS2_VOLUME != 0 {
+        return Err(invalid("3D textures are not supported"));
+    }
+    let mut faces = match caps2 & (DDSCAPS2_CUBEMAP | DDSCAPS2_CUBEMAP_ALLFACES) {
+        0 => 1,
+        cube if cube == DDSCAPS2_CUBEMAP | DDSCAPS2_CUBEMAP_ALLFACES => 6,
+        _ => return Err(invalid("cubemaps have to store all six faces")),
+    };
+
+    let pf_flags = field(80)?;
+    let four_cc = field(84)?;
+    let (texels, bgra, offset) = if pf_flags & DDPF_FOURCC != 0 && four_cc == fourcc(b"DX10") {
+        if field(132)? != D3D10_RESOURCE_DIMENSION_TEXTURE2D || field(140)? != 1 {
+            return Err(invalid("only 2D textures and cubemaps are supported"));
+        }
+        if field(136)? & D3D10_RESOURCE_MISC_TEXTURECUBE != 0 {
+            faces = 6;
+        }
+        let (texels, bgra) = dxgi_texels(field(128)?)?;
+        (texels, bgra, 128 + DX10_HEADER_SIZE)
+    } else if pf_flags & DDPF_FOURCC != 0 {
+        (legacy_texels(four_cc)?, false, 128)
+    } else if pf_flags & DDPF_RGB != 0 && field(88)? == 32 {
+        let masks = (field(92)?, field(96)?, field(100)?, field(104)?);
+        let bgra = match masks {
+            (0xff, 0xff00, 0xff_0000, 0xff00_0000) => false,
+            (0xff_0000, 0xff00, 0xff, 0xff00_0000) => true,
+            _ => return Err(invalid("only RGBA8 and BGRA8 pixel masks are supported")),
+        };
+        (Texels::Rgba8 { srgb }, bgra, 128)
+    } else {
+        return Err(invalid("only 32-bit RGB pixel formats are supported"));
+    };
+
+    if width == 0 || height == 0 || width > 0xffff || height > 0xffff {
+        return Err(Error::from(error::Error::UnsupportedTextureSize(
+            width, height,
+        )));
+    }
+    if faces == 6 && width != height {
+        return Err(invalid("cubemap faces are not square"));
+    }
+    if levels > 32 - width.max(height).leading_zeros() as usize {
+        return Err(invalid("too many mip levels"));
+    }
+
+    // Every face is stored with its mip levels, which is the order `Decoded` holds them in.
+    let len: usize = (0..levels)
+        .map(|level| {
+            (width >> level).max(1) as usize * (height >> level).max(1) as usize * texels.bytes()
+        })
+        .sum::<usize>()
+        * faces;
+    let mut out = data
+        .get(offset..offset + len)
+        .ok_or_else(|| invalid("texel data is truncated"))?
+        .to_vec();
+    if bgra {
+        for texel in out.chunks_mut(4) {
+            texel.swap(0, 2);
+        }
+    }
+
+    Ok(Decoded {
+        width,
+        height,
+        faces,
+        levels,
+        texels,
+        data: out,
+    })
+}
+
+/// Decodes the top level of a DDS file holding a single RGBA8 image, e.g. a face of a cubemap.
+pub(crate) fn decode_image(data: &[u8]) -> Result<RgbaImage, Error> {
+    let dds = decode(data, true)?;
+    match dds.texels {
+        Texels::Rgba8 { .. } if dds.faces == 1 => {
+            let len = dds.width as usize * dds.height as usize * 4;
+            let mut data = dds.data;
+            data.truncate(len);
+            Ok(RgbaImage::from_raw(dds.width, dds.height, data)
+                .expect("Decoded image has the wrong size"))
+        }
+        _ => Err(invalid("only RGBA8 2D textures can be used as images")),
+    }
+}
+
+/// Texels of a DX10 header's format, and whether they are in BGRA order.
+fn dxgi_texels(format: u32) -> Result<(Texels, bool), Error> {
+    Ok(match format {
+        DXGI_FORMAT_R8G8B8A8_UNORM => (Texels::Rgba8 { srgb: false }, false),
+        DXGI_FORMAT_R8G8B8A8_UNORM_SRGB => (Texels::Rgba8 { srgb: true }, false),
+        DXGI_FORMAT_B8G8R8A8_UNORM => (Texels::Rgba8 { srgb: false }, true),
+        DXGI_FORMAT_B8G8R8A8_UNORM_SRGB => (Texels::Rgba8 { srgb: true }, true),
+        DXGI_FORMAT_R32G32B32A32_FLOAT => (Texels::Rgba32F, false),
+        70..=72 => return Err(compressed("BC1")),
+        73..=75 => return Err(compressed("BC2")),
+        76..=78 => return Err(compressed("BC3")),
+        79..=81 => return Err(compressed("BC4")),
+        82..=84 => return Err(compressed("BC5")),
+        94..=96 => return Err(compressed("BC6H")),
+        97..=99 => return Err(compressed("BC7")),
+        _ => return Err(unsupported("dxgiFormat", format)),
+    })
+}
+
+/// Texels of a header without DX10 extension that names its format by a four character code.
+fn legacy_texels(four_cc: u32) -> Result<Texels, Error> {
+    let block = match &four_cc.to_le_bytes() {
+        _ if four_cc == D3DFMT_A32B32G32R32F => return Ok(Texels::Rgba32F),
+        b"DXT1" => "BC1",
+        b"DXT2" | b"DXT3" => "BC2",
+        b"DXT4" | b"DXT5" => "BC3",
+        b"ATI1" | b"BC4U" | b"BC4S" => "BC4",
+        b"ATI2" | b"BC5U" | b"BC5S" => "BC5",
+        _ => return Err(unsupported("fourCC", four_cc)),
+    };
+    Err(compressed(block))
+}
+
+fn fourcc(code: &[u8; 4]) -> u32 {
+    read_u32(code, 0)
+}
+
+fn get_u32(data: &[u8], offset: usize) -> Result<u32, Error> {
+    if data.len() < offset + 4 {
+        return Err(invalid("header is truncated"));
+    }
+    Ok(read_u32(data, offset))
+}
+
+fn invalid(reason: &'static str) -> Error {
+    Error::from(error::Error::DdsDecode(reason))
+}
+
+fn unsupported(field: &'static str, value: u32) -> Error {
+    Error::from(error::Error::DdsUnsupportedFormat(field, value))
+}
+
+fn compressed(block: &'static str) -> Error {
+    Error::from(error::Error::DdsBlockCompressed(block))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, fourcc, Texels, DDPF_FOURCC, DDPF_RGB};
+
+    /// Builds a DDS file of `faces` faces with `levels` levels of `size`×`size` RGBA8 texels.
+    ///
+    /// Every texel of a face's level holds `[face, level, 0, 255]`.
+    fn dds(size: u32, faces: u32, levels: u32, masks: [u32; 4]) -> Vec<u8> {
+        let mut data = b"DDS ".to_vec();
+        let caps2 = if faces == 6 { 0xfe00 } else { 0 };
+        let mut header = [0u32; 31];
+        header[0] = 124;
+        header[1] = 0x2_1007;
+        header[2] = size;
+        header[3] = size;
+        header[6] = levels;
+        header[18] = 32;
+        header[19] = DDPF_RGB | 0x1;
+        header[21] = 32;
+        header[22..26].copy_from_slice(&masks);
+        header[27] = caps2;
+        for field in header.iter() {
+            data.extend_from_slice(&field.to_le_bytes());
+        }
+        for face in 0..faces {
+            for level in 0..levels {
+                for _ in 0..(size >> level).max(1).pow(2) {
+                    data.extend_from_slice(&[face as u8, level as u8, 0, 255]);
+                }
+            }
+        }
+        data
+    }
+
+    const RGBA: [u32; 4] = [0xff, 0xff00, 0xff_0000, 0xff00_0000];
+    const BGRA: [u32; 4] = [0xff_0000, 0xff00, 0xff, 0xff00_0000];
+
+    /// Replaces the pixel format of `data` with a four character code.
+    fn with_fourcc(mut data: Vec<u8>, code: u32) -> Vec<u8> {
+        data[80..84].copy_from_slice(&DDPF_FOURCC.to_le_bytes());
+        data[84..88].copy_from_slice(&code.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn textures_keep_their_mip_levels() {
+        let dds = decode(&dds(4, 1, 3, RGBA), true).unwrap();
+        assert_eq!((dds.width, dds.height, dds.faces, dds.levels), (4, 4, 1, 3));
+        assert_eq!(dds.texels, Texels::Rgba8 { srgb: true });
+        assert_eq!(dds.data.len(), (16 + 4 + 1) * 4);
+        assert_eq!(&dds.data[16 * 4..][..4], &[0, 1, 0, 255]);
+        assert_eq!(&dds.data[20 * 4..], &[0, 2, 0, 255]);
+    }
+
+    #[test]
+    fn cubemap_faces_are_followed_by_their_levels() {
+        let dds = decode(&dds(2, 6, 2, RGBA), false).unwrap();
+        assert_eq!((dds.faces, dds.levels), (6, 2));
+        let face_len = (4 + 1) * 4;
+        for face in 0..6 {
+            let data = &dds.data[face * face_len..][..face_len];
+            assert_eq!(&data[..4], &[face as u8, 0, 0, 255]);
+            assert_eq!(&data[16..], &[face as u8, 1, 0, 255]);
+        }
+    }
+
+    #[test]
+    fn bgra_texels_are_swizzled() {
+        let dds = decode(&dds(1, 6, 1, BGRA), false).unwrap();
+        assert_eq!(&dds.data[4..8], &[0, 0, 1, 255]);
+    }
+
+    #[test]
+    fn dx10_headers_name_the_channel_type() {
+        let mut data = with_fourcc(dds(2, 1, 1, RGBA), fourcc(b"DX10"));
+        let mut dx10 = Vec::new();
+        // DXGI_FORMAT_B8G8R8A8_UNORM_SRGB, 2D, one texture.
+        for field in &[91u32, 3, 0, 1, 0] {
+            dx10.extend_from_slice(&field.to_le_bytes());
+        }
+        data.splice(128..128, dx10);
+
+        let dds = decode(&data, false).unwrap();
+        assert_eq!(dds.texels, Texels::Rgba8 { srgb: true });
+        assert_eq!(&dds.data[..4], &[0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn block_compressed_files_name_their_format() {
+        for &(code, block) in &[(b"DXT1", "BC1"), (b"DXT5", "BC3"), (b"ATI2", "BC5")] {
+            let data = with_fourcc(dds(4, 1, 1, RGBA), fourcc(code));
+            let err = decode(&data, true).expect_err("block compressed");
+            assert!(err.to_string().contains(block), "{} for {}", err, block);
+        }
+
+        let mut data = with_fourcc(dds(4, 6, 1, RGBA), fourcc(b"DX10"));
+        let mut dx10 = Vec::new();
+        // DXGI_FORMAT_BC7_UNORM_SRGB, as a cubemap.
+        for field in &[99u32, 3, 4, 1, 0] {
+            dx10.extend_from_slice(&field.to_le_bytes());
+        }
+        data.splice(128..128, dx10);
+        let err = decode(&data, true).expect_err("BC7 is block compressed");
+        assert!(err.to_string().contains("BC7"));
+    }
+
+    #[test]
+    fn truncated_files_are_refused() {
+        let mut data = dds(4, 6, 3, RGBA);
+        data.truncate(data.len() - 1);
+        assert!(decode(&data, true).is_err());
+    }
+}
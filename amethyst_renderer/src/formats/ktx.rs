@@ -0,0 +1,469 @@
+//! Khronos texture container (`.ktx` and `.ktx2`) loading.
+
+use gfx::{
+    format::{ChannelType, SurfaceType},
+    texture::Kind,
+};
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+
+use amethyst_assets::SimpleFormat;
+use amethyst_error::Error;
+
+use crate::{
+    error,
+    formats::{ImageData, TextureData, TextureMetadata},
+    tex::Texture,
+};
+
+const KTX1: [u8; 12] = [
+    0xab, 0x4b, 0x54, 0x58, 0x20, 0x31, 0x31, 0xbb, 0x0d, 0x0a, 0x1a, 0x0a,
+];
+const KTX2: [u8; 12] = [
+    0xab, 0x4b, 0x54, 0x58, 0x20, 0x32, 0x30, 0xbb, 0x0d, 0x0a, 0x1a, 0x0a,
+];
+const ENDIANNESS: u32 = 0x0403_0201;
+
+const GL_UNSIGNED_BYTE: u32 = 0x1401;
+const GL_FLOAT: u32 = 0x1406;
+const GL_RGBA: u32 = 0x1908;
+const GL_BGRA: u32 = 0x80e1;
+const GL_RGBA8: u32 = 0x8058;
+const GL_SRGB8_ALPHA8: u32 = 0x8c43;
+const GL_RGBA32F: u32 = 0x8814;
+
+const VK_R8G8B8A8_UNORM: u32 = 37;
+const VK_R8G8B8A8_SRGB: u32 = 43;
+const VK_B8G8R8A8_UNORM: u32 = 44;
+const VK_B8G8R8A8_SRGB: u32 = 50;
+const VK_R32G32B32A32_SFLOAT: u32 = 109;
+
+/// Allows loading of KTX and KTX2 texture containers.
+///
+/// Both 2D textures and cubemaps are supported, together with the mip levels stored in the
+/// file, so a skybox can be loaded from a single file instead of six images. The surface
+/// format, channel type, size, kind and mip levels of the `TextureMetadata` passed in are
+/// replaced by the ones of the file; the sampler and `dynamic` flag are kept.
+///
+/// Only uncompressed, little endian RGBA8 (linear or sRGB, optionally in BGRA order) and RGBA32F
+/// data can be loaded. Images are expected with their top row first, which is what most tools
+/// write. Block compressed and supercompressed files are refused, as the graphics backend has
+/// no compressed surface formats.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct KtxFormat;
+
+impl KtxFormat {
+    /// Loads a KTX or KTX2 texture from a byte slice.
+    pub fn from_data(data: &[u8], options: TextureMetadata) -> Result<TextureData, Error> {
+        Ok(texture_data(decode(data)?, options))
+    }
+}
+
+/// Turns a decoded container into `TextureData`, taking its size, kind and format.
+pub(crate) fn texture_data(texture: Decoded, options: TextureMetadata) -> TextureData {
+    let Decoded {
+        width,
+        height,
+        faces,
+        levels,
+        texels,
+        data,
+    } = texture;
+    let options = options.with_mip_levels(levels as u8);
+    let (w, h) = (width as u16, height as u16);
+    match texels {
+        Texels::Rgba8 { srgb } => {
+            let channel = if srgb {
+                ChannelType::Srgb
+            } else {
+                ChannelType::Unorm
+            };
+            let options = options
+                .with_format(SurfaceType::R8_G8_B8_A8)
+                .with_channel(channel);
+            if faces == 1 {
+                TextureData::U8(data, options.with_size(w, h))
+            } else if levels == 1 {
+                let mut faces = data.chunks(data.len() / 6).map(|face| ImageData {
+                    rgba: RgbaImage::from_raw(width, height, face.to_vec())
+                        .expect("Decoded face has the wrong size"),
+                });
+                let data = [
+                    faces.next().unwrap(),
+                    faces.next().unwrap(),
+                    faces.next().unwrap(),
+                    faces.next().unwrap(),
+                    faces.next().unwrap(),
+                    faces.next().unwrap(),
+                ];
+                TextureData::CubeImage(data, options.with_kind(Kind::Cube(w)))
+            } else {
+                TextureData::CubeU8(data, options.with_kind(Kind::Cube(w)))
+            }
+        }
+        Texels::Rgba32F => {
+            let data = data
+                .chunks(4)
+                .map(|bytes| f32::from_bits(read_u32(bytes, 0)))
+                .collect();
+            let options = options
+                .with_format(SurfaceType::R32_G32_B32_A32)
+                .with_channel(ChannelType::Float);
+            if faces == 1 {
+                TextureData::F32(data, options.with_size(w, h))
+            } else {
+                TextureData::CubeF32(data, options.with_kind(Kind::Cube(w)))
+            }
+        }
+    }
+}
+
+impl SimpleFormat<Texture> for KtxFormat {
+    const NAME: &'static str = "KTX";
+
+    type Options = TextureMetadata;
+
+    fn import(&self, bytes: Vec<u8>, options: TextureMetadata) -> Result<TextureData, Error> {
+        KtxFormat::from_data(&bytes, options)
+    }
+}
+
+/// Layout of the decoded texels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Texels {
+    /// Four bytes per texel, in RGBA order.
+    Rgba8 { srgb: bool },
+    /// Four little endian `f32`s per texel, in RGBA order.
+    Rgba32F,
+}
+
+impl Texels {
+    pub(crate) fn bytes(self) -> usize {
+        match self {
+            Texels::Rgba8 { .. } => 4,
+            Texels::Rgba32F => 16,
+        }
+    }
+}
+
+/// Texture decoded from a KTX or DDS container.
+#[derive(Debug)]
+pub(crate) struct Decoded {
+    pub width: u32,
+    pub height: u32,
+    /// One, or six for cubemaps in `+X, -X, +Y, -Y, +Z, -Z` order.
+    pub faces: usize,
+    pub levels: usize,
+    pub texels: Texels,
+    /// Every face one after another, each followed by its mip levels.
+    pub data: Vec<u8>,
+}
+
+/// Header fields of a container, and where its images are.
+struct Layout {
+    width: u32,
+    height: u32,
+    depth: u32,
+    layers: u32,
+    faces: usize,
+    texels: Texels,
+    bgra: bool,
+    /// Byte offset of every face of every level, in level-major order.
+    images: Vec<usize>,
+}
+
+pub(crate) fn decode(data: &[u8]) -> Result<Decoded, Error> {
+    let layout = if data.starts_with(&KTX1) {
+        ktx1_layout(data)?
+    } else if data.starts_with(&KTX2) {
+        ktx2_layout(data)?
+    } else {
+        return Err(invalid("missing KTX identifier"));
+    };
+    let Layout {
+        width,
+        height,
+        depth,
+        layers,
+        faces,
+        texels,
+        bgra,
+        images,
+    } = layout;
+
+    if width == 0 || height == 0 || width > 0xffff || height > 0xffff {
+        return Err(Error::from(error::Error::UnsupportedTextureSize(
+            width, height,
+        )));
+    }
+    if depth > 1 || layers > 1 {
+        return Err(invalid("3D textures and texture arrays are not supported"));
+    }
+    if faces == 6 && width != height {
+        return Err(invalid("cubemap faces are not square"));
+    }
+    let levels = images.len() / faces;
+    if levels > 32 - width.max(height).leading_zeros() as usize {
+        return Err(invalid("too many mip levels"));
+    }
+
+    let mut out = Vec::new();
+    for face in 0..faces {
+        for level in 0..levels {
+            let len = (width >> level).max(1) as usize
+                * (height >> level).max(1) as usize
+                * texels.bytes();
+            let start = images[level * faces + face];
+            let image = data
+                .get(start..start + len)
+                .ok_or_else(|| invalid("texel data is truncated"))?;
+            out.extend_from_slice(image);
+        }
+    }
+    if bgra {
+        for texel in out.chunks_mut(4) {
+            texel.swap(0, 2);
+        }
+    }
+
+    Ok(Decoded {
+        width,
+        height,
+        faces,
+        levels,
+        texels,
+        data: out,
+    })
+}
+
+fn ktx1_layout(data: &[u8]) -> Result<Layout, Error> {
+    let field = |offset: usize| get_u32(data, offset);
+    if field(12)? != ENDIANNESS {
+        return Err(invalid("big endian files are not supported"));
+    }
+    let gl_type = field(16)?;
+    let gl_format = field(24)?;
+    let internal_format = field(28)?;
+    let texels = match (gl_type, internal_format) {
+        (GL_UNSIGNED_BYTE, GL_RGBA8) | (GL_UNSIGNED_BYTE, GL_RGBA) => Texels::Rgba8 { srgb: false },
+        (GL_UNSIGNED_BYTE, GL_SRGB8_ALPHA8) => Texels::Rgba8 { srgb: true },
+        (GL_FLOAT, GL_RGBA32F) if gl_format == GL_RGBA => Texels::Rgba32F,
+        _ => return Err(unsupported("glInternalFormat", internal_format)),
+    };
+    let bgra = match gl_format {
+        GL_RGBA => false,
+        GL_BGRA if texels != Texels::Rgba32F => true,
+        _ => return Err(unsupported("glFormat", gl_format)),
+    };
+    let (faces, levels) = counts(field(52)?, field(56)?)?;
+
+    // Every level is its image size, followed by that many bytes for each face.
+    let mut offset = 64 + field(60)? as usize;
+    let mut images = Vec::with_capacity(levels * faces);
+    for _ in 0..levels {
+        let size = get_u32(data, offset)? as usize;
+        offset += 4;
+        for _ in 0..faces {
+            images.push(offset);
+            offset += (size + 3) & !3;
+        }
+    }
+
+    Ok(Layout {
+        width: field(36)?,
+        height: field(40)?.max(1),
+        depth: field(44)?,
+        layers: field(48)?,
+        faces,
+        texels,
+        bgra,
+        images,
+    })
+}
+
+fn ktx2_layout(data: &[u8]) -> Result<Layout, Error> {
+    let field = |offset: usize| get_u32(data, offset);
+    let vk_format = field(12)?;
+    let (texels, bgra) = match vk_format {
+        VK_R8G8B8A8_UNORM => (Texels::Rgba8 { srgb: false }, false),
+        VK_R8G8B8A8_SRGB => (Texels::Rgba8 { srgb: true }, false),
+        VK_B8G8R8A8_UNORM => (Texels::Rgba8 { srgb: false }, true),
+        VK_B8G8R8A8_SRGB => (Texels::Rgba8 { srgb: true }, true),
+        VK_R32G32B32A32_SFLOAT => (Texels::Rgba32F, false),
+        _ => return Err(unsupported("vkFormat", vk_format)),
+    };
+    if field(44)? != 0 {
+        return Err(invalid("supercompressed files are not supported"));
+    }
+    let width = field(20)?;
+    let height = field(24)?.max(1);
+    let (faces, levels) = counts(field(36)?, field(40)?)?;
+
+    // The level index lists the offset of each level, whose faces follow one another.
+    let face_len = |level: usize| {
+        let w = (width >> level).max(1) as usize;
+        let h = (height >> level).max(1) as usize;
+        w * h * texels.bytes()
+    };
+    let mut images = Vec::with_capacity(levels * faces);
+    for level in 0..levels {
+        let offset = get_u32(data, 80 + 24 * level)? as usize;
+        for face in 0..faces {
+            images.push(offset + face * face_len(level));
+        }
+    }
+
+    Ok(Layout {
+        width,
+        height,
+        depth: field(28)?,
+        layers: field(32)?,
+        faces,
+        texels,
+        bgra,
+        images,
+    })
+}
+
+/// Checks the face and level counts of a header, where zero levels means one.
+fn counts(faces: u32, levels: u32) -> Result<(usize, usize), Error> {
+    if faces != 1 && faces != 6 {
+        return Err(invalid("only 2D textures and cubemaps are supported"));
+    }
+    if levels > 16 {
+        return Err(invalid("too many mip levels"));
+    }
+    Ok((faces as usize, levels.max(1) as usize))
+}
+
+fn get_u32(data: &[u8], offset: usize) -> Result<u32, Error> {
+    if data.len() < offset + 4 {
+        return Err(invalid("header is truncated"));
+    }
+    Ok(read_u32(data, offset))
+}
+
+pub(crate) fn read_u32(data: &[u8], offset: usize) -> u32 {
+    data[offset..offset + 4]
+        .iter()
+        .rev()
+        .fold(0, |value, &byte| (value << 8) | u32::from(byte))
+}
+
+fn invalid(reason: &'static str) -> Error {
+    Error::from(error::Error::KtxDecode(reason))
+}
+
+fn unsupported(field: &'static str, value: u32) -> Error {
+    Error::from(error::Error::KtxUnsupportedFormat(field, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, Texels, GL_BGRA, GL_RGBA, GL_RGBA8, GL_UNSIGNED_BYTE, KTX1, KTX2};
+
+    /// Builds a KTX file with `faces` faces of `size`×`size` RGBA8 texels and `levels` levels.
+    ///
+    /// Every texel of a face's level holds `[face, level, 0, 255]`.
+    fn ktx1(size: u32, faces: u32, levels: u32, gl_format: u32) -> Vec<u8> {
+        let mut data = KTX1.to_vec();
+        for field in &[
+            0x0403_0201,
+            GL_UNSIGNED_BYTE,
+            1,
+            gl_format,
+            GL_RGBA8,
+            GL_RGBA,
+            size,
+            size,
+            0,
+            0,
+            faces,
+            levels,
+            0,
+        ] {
+            data.extend_from_slice(&field.to_le_bytes());
+        }
+        for level in 0..levels {
+            let texels = (size >> level).max(1).pow(2);
+            data.extend_from_slice(&(texels * 4).to_le_bytes());
+            for face in 0..faces {
+                for _ in 0..texels {
+                    data.extend_from_slice(&[face as u8, level as u8, 0, 255]);
+                }
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn textures_keep_their_mip_levels() {
+        let ktx = decode(&ktx1(4, 1, 3, GL_RGBA)).unwrap();
+        assert_eq!((ktx.width, ktx.height, ktx.faces, ktx.levels), (4, 4, 1, 3));
+        assert_eq!(ktx.texels, Texels::Rgba8 { srgb: false });
+        assert_eq!(ktx.data.len(), (16 + 4 + 1) * 4);
+        assert_eq!(&ktx.data[16 * 4..][..4], &[0, 1, 0, 255]);
+        assert_eq!(&ktx.data[20 * 4..], &[0, 2, 0, 255]);
+    }
+
+    #[test]
+    fn cubemap_faces_are_followed_by_their_levels() {
+        let ktx = decode(&ktx1(2, 6, 2, GL_RGBA)).unwrap();
+        assert_eq!((ktx.faces, ktx.levels), (6, 2));
+        let face_len = (4 + 1) * 4;
+        for face in 0..6 {
+            let data = &ktx.data[face * face_len..][..face_len];
+            assert_eq!(&data[..4], &[face as u8, 0, 0, 255]);
+            assert_eq!(&data[16..], &[face as u8, 1, 0, 255]);
+        }
+    }
+
+    #[test]
+    fn bgra_texels_are_swizzled() {
+        let ktx = decode(&ktx1(1, 1, 1, GL_BGRA)).unwrap();
+        assert_eq!(ktx.data, vec![0, 0, 0, 255]);
+        let ktx = decode(&ktx1(1, 6, 1, GL_BGRA)).unwrap();
+        assert_eq!(&ktx.data[4..8], &[0, 0, 1, 255]);
+    }
+
+    #[test]
+    fn ktx2_levels_are_read_from_the_index() {
+        let mut data = KTX2.to_vec();
+        // 2x2 sRGB RGBA8 with two levels.
+        for field in &[43u32, 1, 2, 2, 0, 0, 1, 2, 0, 0, 0, 0, 0] {
+            data.extend_from_slice(&field.to_le_bytes());
+        }
+        data.extend_from_slice(&[0; 16]);
+        for &(offset, len) in &[(128u64, 16u64), (144, 4)] {
+            data.extend_from_slice(&offset.to_le_bytes());
+            data.extend_from_slice(&len.to_le_bytes());
+            data.extend_from_slice(&len.to_le_bytes());
+        }
+        data.resize(128, 0);
+        data.extend_from_slice(&[1; 16]);
+        data.extend_from_slice(&[2; 4]);
+
+        let ktx = decode(&data).unwrap();
+        assert_eq!(ktx.texels, Texels::Rgba8 { srgb: true });
+        assert_eq!(ktx.levels, 2);
+        assert_eq!(&ktx.data[..16], &[1; 16]);
+        assert_eq!(&ktx.data[16..], &[2; 4]);
+    }
+
+    #[test]
+    fn compressed_files_are_refused() {
+        let mut data = ktx1(4, 1, 1, GL_RGBA);
+        // GL_COMPRESSED_RGBA_S3TC_DXT5_EXT, with a glType of 0.
+        data[16..20].copy_from_slice(&0u32.to_le_bytes());
+        data[28..32].copy_from_slice(&0x83f3u32.to_le_bytes());
+        let err = decode(&data).expect_err("DXT5 is block compressed");
+        assert!(err.to_string().contains("0x83f3"));
+    }
+
+    #[test]
+    fn truncated_files_are_refused() {
+        let mut data = ktx1(4, 6, 3, GL_RGBA);
+        data.truncate(data.len() - 1);
+        assert!(decode(&data).is_err());
+    }
+}
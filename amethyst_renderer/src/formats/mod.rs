@@ -1,7 +1,7 @@
 //! Provides texture formats
 //!
 
-pub use self::{mesh::*, mtl::*, texture::*};
+pub use self::{dds::DdsFormat, ktx::KtxFormat, mesh::*, mtl::*, texture::*};
 
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
@@ -11,6 +11,8 @@ use amethyst_error::Error;
 
 use crate::{shape::InternalShape, Mesh, ShapePrefab, Texture};
 
+pub(crate) mod dds;
+mod ktx;
 mod mesh;
 mod mtl;
 mod texture;
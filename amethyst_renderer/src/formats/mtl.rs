@@ -30,6 +30,8 @@ where
     pub emission: Option<TexturePrefab<F>>,
     /// Emission texture offset
     pub emission_offset: TextureOffset,
+    /// Factor the emission map is multiplied with
+    pub emission_intensity: f32,
     /// Normal map.
     pub normal: Option<TexturePrefab<F>>,
     /// Normal texture offset
@@ -54,6 +56,8 @@ where
     pub transparent: bool,
     /// Alpha cutoff: the value below which we do not draw the pixel
     pub alpha_cutoff: f32,
+    /// Draw the back faces as well
+    pub two_sided: bool,
 }
 
 impl<F> Default for MaterialPrefab<F>
@@ -66,6 +70,7 @@ where
             albedo_offset: TextureOffset::default(),
             emission: None,
             emission_offset: TextureOffset::default(),
+            emission_intensity: 1.0,
             normal: None,
             normal_offset: TextureOffset::default(),
             metallic: None,
@@ -78,6 +83,7 @@ where
             caveat_offset: TextureOffset::default(),
             transparent: false,
             alpha_cutoff: 0.01,
+            two_sided: false,
         }
     }
 }
@@ -122,6 +128,7 @@ where
             albedo_offset: self.albedo_offset.clone(),
             emission: load_handle(entity, &self.emission, tp_data, &mat_default.0.emission),
             emission_offset: self.emission_offset.clone(),
+            emission_intensity: self.emission_intensity,
             normal: load_handle(entity, &self.normal, tp_data, &mat_default.0.normal),
             normal_offset: self.normal_offset.clone(),
             metallic: load_handle(entity, &self.metallic, tp_data, &mat_default.0.metallic),
@@ -138,6 +145,7 @@ where
             caveat: load_handle(entity, &self.caveat, tp_data, &mat_default.0.caveat),
             caveat_offset: self.caveat_offset.clone(),
             alpha_cutoff: self.alpha_cutoff,
+            two_sided: self.two_sided,
         };
         material.insert(entity, mtl)?;
         if self.transparent {
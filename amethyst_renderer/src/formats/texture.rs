@@ -6,6 +6,8 @@ use gfx::{
 use image::{DynamicImage, ImageFormat, RgbaImage};
 use serde::{Deserialize, Serialize};
 
+use std::mem::size_of;
+
 use amethyst_assets::{
     AssetStorage, Format, Handle, Loader, PrefabData, ProcessingState, ProgressCounter,
     SimpleFormat,
@@ -15,6 +17,7 @@ use amethyst_error::{Error, ResultExt};
 
 use crate::{
     error,
+    formats::{DdsFormat, KtxFormat},
     tex::{FilterMethod, Texture, TextureBuilder},
     types::SurfaceFormat,
     Renderer,
@@ -104,6 +107,15 @@ impl TextureMetadata {
         self
     }
 
+    /// Sets the filter method of the sampler to anisotropic filtering taking up to `level`
+    /// samples, e.g. for ground textures seen at grazing angles.
+    ///
+    /// The level is clamped to the most samples the device supports when the texture is created,
+    /// on devices without anisotropic filtering the texture is filtered trilinearly.
+    pub fn with_anisotropy(self, level: u8) -> Self {
+        self.with_filter(FilterMethod::Anisotropic(level))
+    }
+
     /// Mipmapping
     pub fn with_mip_levels(mut self, mip_levels: u8) -> Self {
         self.mip_levels = mip_levels;
@@ -173,6 +185,14 @@ pub enum TextureData {
     /// Cube Image data
     #[serde(skip)]
     CubeImage([ImageData; 6], TextureMetadata),
+
+    /// Cube float data, the six faces stored one after another in `+X, -X, +Y, -Y, +Z, -Z` order.
+    /// The metadata has to describe a `Kind::Cube` texture with a matching float format.
+    CubeF32(Vec<f32>, TextureMetadata),
+
+    /// Cube byte data, laid out like `CubeF32` with every face followed by its mip levels.
+    /// The metadata has to describe a `Kind::Cube` texture with a matching format.
+    CubeU8(Vec<u8>, TextureMetadata),
 }
 
 impl From<[f32; 4]> for TextureData {
@@ -192,6 +212,94 @@ impl TextureData {
     pub fn color(value: [f32; 4]) -> Self {
         TextureData::Rgba(value, TextureMetadata::srgb())
     }
+
+    /// Checks that the data matches the kind its metadata declares.
+    ///
+    /// The cube variants need a `Kind::Cube` of the size of their faces, with `CubeF32` and
+    /// `CubeU8` holding either the first or every mip level of each face, all other variants
+    /// need a `Kind::D2`. Textures are validated before they are created, so a mismatch fails
+    /// loading instead of leaving the texture undefined. Loaders assembling texture data can
+    /// validate it themselves to report a mismatch right away.
+    pub fn validate(&self) -> Result<(), Error> {
+        use self::TextureData::*;
+        match *self {
+            Image(_, ref meta)
+            | Rgba(_, ref meta)
+            | F32(_, ref meta)
+            | F64(_, ref meta)
+            | U8(_, ref meta)
+            | U16(_, ref meta)
+            | U32(_, ref meta)
+            | U64(_, ref meta) => match meta.kind {
+                Kind::D2(..) => Ok(()),
+                kind => Err(kind_mismatch(self.variant(), kind)),
+            },
+            CubeImage(ref faces, ref meta) => {
+                let size = cube_size(self.variant(), meta.kind)?;
+                for (face, image) in faces.iter().enumerate() {
+                    let dimensions = image.rgba.dimensions();
+                    if dimensions != (u32::from(size), u32::from(size)) {
+                        return Err(error::Error::CubemapFaceSize(face, size, dimensions).into());
+                    }
+                }
+                Ok(())
+            }
+            CubeF32(ref data, ref meta) => check_cube_data::<f32>(self.variant(), data.len(), meta),
+            CubeU8(ref data, ref meta) => check_cube_data::<u8>(self.variant(), data.len(), meta),
+        }
+    }
+
+    /// Returns the name of the variant, for errors.
+    fn variant(&self) -> &'static str {
+        use self::TextureData::*;
+        match *self {
+            Image(..) => "Image",
+            Rgba(..) => "Rgba",
+            F32(..) => "F32",
+            F64(..) => "F64",
+            U8(..) => "U8",
+            U16(..) => "U16",
+            U32(..) => "U32",
+            U64(..) => "U64",
+            CubeImage(..) => "CubeImage",
+            CubeF32(..) => "CubeF32",
+            CubeU8(..) => "CubeU8",
+        }
+    }
+}
+
+fn kind_mismatch(variant: &'static str, kind: Kind) -> Error {
+    error::Error::TextureKindMismatch(variant, format!("{:?}", kind)).into()
+}
+
+/// Returns the face size of the cubemap `kind`.
+fn cube_size(variant: &'static str, kind: Kind) -> Result<u16, Error> {
+    match kind {
+        Kind::Cube(size) => Ok(size),
+        kind => Err(kind_mismatch(variant, kind)),
+    }
+}
+
+/// Checks that cubemap data of `len` values of `T` holds either the first or every mip level of
+/// each face, like `TextureBuilder` expects.
+fn check_cube_data<T>(
+    variant: &'static str,
+    len: usize,
+    meta: &TextureMetadata,
+) -> Result<(), Error> {
+    let size = usize::from(cube_size(variant, meta.kind)?);
+    let texel = usize::from(meta.format.get_total_bits() / 8) / size_of::<T>();
+    let level_len = |level: u8| {
+        let size = size.checked_shr(u32::from(level)).unwrap_or(0).max(1);
+        size * size * texel
+    };
+    let first = 6 * level_len(0);
+    let all = 6 * (0..meta.mip_levels.max(1)).map(level_len).sum::<usize>();
+    if len == first || len == all {
+        Ok(())
+    } else {
+        Err(error::Error::CubemapDataSize(len, first).into())
+    }
 }
 
 impl<'a> PrefabData<'a> for TextureData {
@@ -405,6 +513,7 @@ pub fn create_texture_asset(
     renderer: &mut Renderer,
 ) -> Result<ProcessingState<Texture>, Error> {
     use self::TextureData::*;
+    data.validate()?;
     let t = match data {
         Image(image_data, options) => {
             create_texture_asset_from_image(image_data, options, renderer)
@@ -462,6 +571,22 @@ pub fn create_texture_asset(
         CubeImage(image_data, options) => {
             create_cubemap_asset_from_images(image_data, options, renderer)
         }
+
+        CubeF32(data, options) => {
+            let kind = options.kind;
+            let tb = apply_options(TextureBuilder::new(data), options).with_kind(kind);
+            renderer
+                .create_texture(tb)
+                .with_context(|_| error::Error::BuildTextureError)
+        }
+
+        CubeU8(data, options) => {
+            let kind = options.kind;
+            let tb = apply_options(TextureBuilder::new(data), options).with_kind(kind);
+            renderer
+                .create_texture(tb)
+                .with_context(|_| error::Error::BuildTextureError)
+        }
     };
     t.map(ProcessingState::Loaded)
 }
@@ -528,11 +653,7 @@ fn create_cubemap_asset_from_images(
         );
     }
 
-    let data: Vec<u8> = images
-        .iter()
-        .map(|val| val.clone().rgba.into_raw())
-        .flat_map(|v| v)
-        .collect();
+    let data = cubemap_data(&images, options.mip_levels);
 
     let tb = apply_options(
         TextureBuilder::new(data)
@@ -545,6 +666,42 @@ fn create_cubemap_asset_from_images(
         .with_context(|_| error::Error::CreateTextureError)
 }
 
+/// Lays out the cubemap faces for upload, each face followed by its downsampled mip levels
+/// when more than one level is requested.
+fn cubemap_data(images: &[ImageData; 6], levels: u8) -> Vec<u8> {
+    let mut data = Vec::new();
+    for face in images.iter() {
+        let mut level = face.rgba.clone();
+        data.extend_from_slice(&level);
+        for _ in 1..levels {
+            level = downsample(&level);
+            data.extend_from_slice(&level);
+        }
+    }
+    data
+}
+
+/// Halves an image in both dimensions by averaging each 2x2 block of texels.
+///
+/// The texels are averaged as stored, without converting sRGB data to linear first.
+fn downsample(image: &RgbaImage) -> RgbaImage {
+    let (w, h) = image.dimensions();
+    RgbaImage::from_fn((w / 2).max(1), (h / 2).max(1), |x, y| {
+        let mut sum = [0u32; 4];
+        for &(dx, dy) in &[(0, 0), (1, 0), (0, 1), (1, 1)] {
+            let texel = image.get_pixel((2 * x + dx).min(w - 1), (2 * y + dy).min(h - 1));
+            for (sum, channel) in sum.iter_mut().zip(texel.data.iter()) {
+                *sum += u32::from(*channel);
+            }
+        }
+        let mut data = [0u8; 4];
+        for (out, sum) in data.iter_mut().zip(sum.iter()) {
+            *out = ((sum + 2) / 4) as u8;
+        }
+        image::Rgba { data }
+    })
+}
+
 /// Aggregate texture format
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub enum TextureFormat {
@@ -556,6 +713,10 @@ pub enum TextureFormat {
     Bmp,
     /// Tga
     Tga,
+    /// Ktx
+    Ktx,
+    /// Dds
+    Dds,
 }
 
 impl SimpleFormat<Texture> for TextureFormat {
@@ -569,6 +730,8 @@ impl SimpleFormat<Texture> for TextureFormat {
             TextureFormat::Png => SimpleFormat::import(&PngFormat, bytes, options),
             TextureFormat::Bmp => SimpleFormat::import(&BmpFormat, bytes, options),
             TextureFormat::Tga => SimpleFormat::import(&TgaFormat, bytes, options),
+            TextureFormat::Ktx => SimpleFormat::import(&KtxFormat, bytes, options),
+            TextureFormat::Dds => SimpleFormat::import(&DdsFormat, bytes, options),
         }
     }
 }
@@ -602,7 +765,11 @@ mod serde_helper {
 
 #[cfg(test)]
 mod tests {
-    use super::TextureData;
+    use image::{Rgba, RgbaImage};
+
+    use gfx::texture::Kind;
+
+    use super::{cubemap_data, downsample, ImageData, TextureData, TextureMetadata};
 
     #[test]
     fn texture_data_from_f32_3() {
@@ -613,4 +780,72 @@ mod tests {
             _ => panic!("Expected [f32; 3] to turn into TextureData::Rgba"),
         }
     }
+
+    #[test]
+    fn downsample_averages_blocks() {
+        let image = RgbaImage::from_fn(2, 2, |x, y| Rgba {
+            data: [(x * 100) as u8, (y * 200) as u8, 50, 255],
+        });
+        let level = downsample(&image);
+        assert_eq!(level.dimensions(), (1, 1));
+        assert_eq!(level.get_pixel(0, 0).data, [50, 100, 50, 255]);
+    }
+
+    #[test]
+    fn cubemap_data_contains_every_mip_level() {
+        let face = || ImageData {
+            rgba: RgbaImage::new(4, 4),
+        };
+        let faces = [face(), face(), face(), face(), face(), face()];
+        assert_eq!(cubemap_data(&faces, 1).len(), 6 * 16 * 4);
+        assert_eq!(cubemap_data(&faces, 3).len(), 6 * (16 + 4 + 1) * 4);
+    }
+
+    #[test]
+    fn kind_has_to_match_the_data() {
+        let cube = TextureMetadata::srgb().with_kind(Kind::Cube(2));
+        assert!(TextureData::CubeU8(vec![0; 6 * 2 * 2 * 4], cube.clone())
+            .validate()
+            .is_ok());
+        assert!(TextureData::U8(vec![0; 2 * 2 * 4], cube.clone())
+            .validate()
+            .is_err());
+
+        let err = TextureData::CubeU8(vec![0; 6 * 2 * 2 * 4], TextureMetadata::srgb())
+            .validate()
+            .expect_err("2D metadata for cube data");
+        assert_eq!(
+            err.to_string(),
+            "Texture data CubeU8 doesn't match the kind D2(1, 1, Single) of its metadata"
+        );
+
+        let faces = || {
+            let face = ImageData {
+                rgba: RgbaImage::new(2, 2),
+            };
+            [
+                face.clone(),
+                face.clone(),
+                face.clone(),
+                face.clone(),
+                face.clone(),
+                face,
+            ]
+        };
+        assert!(TextureData::CubeImage(faces(), cube).validate().is_ok());
+        let small = TextureMetadata::srgb().with_kind(Kind::Cube(1));
+        assert!(TextureData::CubeImage(faces(), small).validate().is_err());
+    }
+
+    #[test]
+    fn cube_data_holds_the_first_or_every_level() {
+        let meta = TextureMetadata::srgb()
+            .with_kind(Kind::Cube(4))
+            .with_mip_levels(3);
+        let valid = |len| TextureData::CubeU8(vec![0; len], meta.clone()).validate();
+        assert!(valid(6 * 16 * 4).is_ok());
+        assert!(valid(6 * (16 + 4 + 1) * 4).is_ok());
+        assert!(valid(6 * (16 + 4) * 4).is_err());
+        assert!(valid(5 * 16 * 4).is_err());
+    }
 }
@@ -0,0 +1,544 @@
+//! Drawing pipelines into an offscreen target, without a window.
+
+use amethyst_core::specs::prelude::{Resources, SystemData};
+use amethyst_error::{Error, ResultExt};
+use gfx::{format::Formatted, memory::Pod};
+use glutin::GlContext;
+use winit::EventsLoop;
+
+use crate::{
+    error,
+    formats::ImageData,
+    mesh::{Mesh, MeshBuilder, VertexDataSet},
+    pipe::{PassTimer, PipelineBuild, PipelineData, PolyPipeline, Target, TargetBuilder},
+    renderer::max_anisotropy,
+    screenshot::frame_image,
+    tex::{Texture, TextureBuilder},
+    types::{ColorFormat, Device, Encoder, Factory},
+};
+
+/// Renderer drawing into an offscreen target instead of a window, for testing passes
+///
+/// It creates an OpenGL context of its own, without a window, and draws the backbuffer stages of
+/// a pipeline into a texture of the size it was created with. `read_pixels` reads that texture
+/// back, so tests can check what the passes drew. Nothing resizes the targets or handles events.
+///
+/// Creating the context still needs a display server on Linux. CI machines without one or
+/// without a GPU can run the tests under `xvfb-run`, with Mesa's software rasterizer.
+///
+/// ```rust,ignore
+/// let mut renderer = HeadlessRenderer::new((64, 64))?;
+/// let mut pipe = renderer.create_pipe(
+///     Pipeline::build()
+///         .with_stage(Stage::with_backbuffer().clear_target([1.0, 0.0, 0.0, 1.0], 1.0)),
+/// )?;
+/// renderer.draw_world(&mut pipe, &mut world.res);
+/// assert_eq!(renderer.read_pixels()?.rgba.get_pixel(0, 0).data, [255, 0, 0, 255]);
+/// ```
+pub struct HeadlessRenderer {
+    /// The gfx factory used for creation of buffers.
+    pub factory: Factory,
+
+    device: Device,
+    encoder: Encoder,
+    main_target: Target,
+    max_anisotropy: u8,
+    _context: glutin::Context,
+    _events: EventsLoop,
+}
+
+impl HeadlessRenderer {
+    /// Creates a `HeadlessRenderer` drawing into a backbuffer of `size` pixels.
+    pub fn new(size: (u32, u32)) -> Result<Self, Error> {
+        #[cfg(target_os = "macos")]
+        use glutin::{GlProfile, GlRequest};
+
+        let events = EventsLoop::new();
+        let ctx = glutin::ContextBuilder::new();
+        #[cfg(target_os = "macos")]
+        let ctx = ctx
+            .with_gl_profile(GlProfile::Core)
+            .with_gl(GlRequest::Latest);
+        let context = glutin::Context::new(&events, ctx, false)
+            .with_context(|_| error::Error::CreateHeadlessContext)?;
+        unsafe { context.make_current() }.with_context(|_| error::Error::CreateHeadlessContext)?;
+
+        let (mut device, mut factory) =
+            gfx_device_gl::create(|name| context.get_proc_address(name) as *const _);
+        let (_, main_target) = TargetBuilder::new("")
+            .with_depth_buf(true)
+            .build(&mut factory, size)?;
+        let encoder = factory.create_command_buffer().into();
+        let max_anisotropy = max_anisotropy(&mut device);
+
+        Ok(HeadlessRenderer {
+            factory,
+            device,
+            encoder,
+            main_target,
+            max_anisotropy,
+            _context: context,
+            _events: events,
+        })
+    }
+
+    /// Builds a new mesh from the given vertices.
+    pub fn create_mesh<T>(&mut self, mb: MeshBuilder<T>) -> Result<Mesh, Error>
+    where
+        T: VertexDataSet,
+    {
+        mb.build(&mut self.factory)
+    }
+
+    /// Builds a new texture resource.
+    pub fn create_texture<D, T>(&mut self, tb: TextureBuilder<D, T>) -> Result<Texture, Error>
+    where
+        D: AsRef<[T]>,
+        T: Pod + Copy,
+    {
+        tb.clamp_anisotropy(self.max_anisotropy)
+            .build(&mut self.factory)
+    }
+
+    /// Builds a new renderer pipeline, without multisampling.
+    pub fn create_pipe<B, P>(&mut self, pb: B) -> Result<P, Error>
+    where
+        P: PolyPipeline,
+        B: PipelineBuild<Pipeline = P>,
+    {
+        pb.build(&mut self.factory, &self.main_target, 0)
+    }
+
+    /// Draws a frame with the given pipeline.
+    pub fn draw<'a, P>(&mut self, pipe: &mut P, data: <P as PipelineData<'a>>::Data)
+    where
+        P: PolyPipeline,
+    {
+        use gfx::Device;
+
+        pipe.apply(
+            &mut self.encoder,
+            &mut PassTimer::disabled(),
+            self.factory.clone(),
+            data,
+        );
+        self.encoder.flush(&mut self.device);
+        self.device.cleanup();
+    }
+
+    /// Draws a frame with the given pipeline, fetching the data of its passes from `res` like the
+    /// `RenderSystem` does.
+    ///
+    /// Resources the passes read are set up with their defaults first, the ones without a default
+    /// have to be added beforehand.
+    pub fn draw_world<P>(&mut self, pipe: &mut P, res: &mut Resources)
+    where
+        P: PolyPipeline,
+    {
+        <P as PipelineData<'_>>::Data::setup(res);
+        self.draw(pipe, <P as PipelineData<'_>>::Data::fetch(res));
+    }
+
+    /// Returns the size of the backbuffer.
+    pub fn size(&self) -> (u32, u32) {
+        self.main_target.size()
+    }
+
+    /// Reads back what was drawn into the backbuffer, waiting for the GPU to finish drawing.
+    ///
+    /// Like screenshots of the window, the image is opaque, with its first row at the top.
+    pub fn read_pixels(&mut self) -> Result<ImageData, Error> {
+        use gfx::{memory::Typed, Device, Factory};
+
+        let texture = self
+            .main_target
+            .color_buf(0)
+            .and_then(|buf| buf.texture.as_ref())
+            .ok_or_else(|| error::Error::TargetNotReadable(String::new()))?;
+        let (width, height) = self.main_target.size();
+        let buffer = self
+            .factory
+            .create_download_buffer::<[u8; 4]>((width * height) as usize)
+            .with_context(|_| error::Error::ReadPixels)?;
+        let info = texture
+            .get_info()
+            .to_raw_image_info(ColorFormat::get_format(), 0);
+        self.encoder
+            .copy_texture_to_buffer_raw(texture, None, info, buffer.raw(), 0)
+            .map_err(|_| error::Error::ReadPixels)?;
+        self.encoder.flush(&mut self.device);
+        self.device.cleanup();
+
+        let pixels = self
+            .factory
+            .read_mapping(&buffer)
+            .with_context(|_| error::Error::ReadPixels)?
+            .iter()
+            .flat_map(|pixel| pixel.iter().cloned())
+            .collect();
+        Ok(ImageData {
+            rgba: frame_image(pixels, width, height),
+        })
+    }
+}
+
+impl Drop for HeadlessRenderer {
+    fn drop(&mut self) {
+        use gfx::Device;
+        self.device.cleanup();
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::Arc;
+
+    use amethyst_assets::{Asset, AssetStorage, Handle, Loader, ProcessingState};
+    use amethyst_core::{
+        nalgebra::{Point3, Vector2, Vector3},
+        specs::prelude::{Builder, Resources, World},
+        transform::GlobalTransform,
+    };
+    use gfx::{format::ChannelType, texture::Kind};
+    use rayon::ThreadPoolBuilder;
+
+    use crate::{
+        cam::Camera,
+        culling::BoundingSphere,
+        formats::{MeshData, TextureData},
+        mesh::{Mesh, MeshBuilder},
+        mtl::{Material, MaterialDefaults, MissingTextures, TextureOffset},
+        pass::{DrawColorGrading, DrawFlat, DrawFxaa, DrawSkyBox, SkyBox},
+        pipe::{Pipeline, Stage, Target},
+        tex::{Texture, TextureBuilder, TextureHandle},
+        vertex::PosTex,
+    };
+
+    use super::HeadlessRenderer;
+
+    /// A world with the asset storages and the default textures the passes read, all white.
+    pub(crate) fn world(renderer: &mut HeadlessRenderer) -> World {
+        let mut world = World::new();
+        let pool = Arc::new(ThreadPoolBuilder::new().num_threads(1).build().unwrap());
+        world.add_resource(Loader::new(".", pool.clone()));
+        world.add_resource(pool);
+        world.add_resource(AssetStorage::<Mesh>::new());
+        world.add_resource(AssetStorage::<Texture>::new());
+
+        let white = renderer
+            .create_texture(Texture::from_color_val([1.0; 4]))
+            .unwrap();
+        let white = insert(&mut world.res, [1.0; 4].into(), white);
+        world.add_resource(MaterialDefaults(material(white.clone())));
+        world.add_resource(MissingTextures {
+            color: white.clone(),
+            normal: white,
+        });
+        world
+    }
+
+    /// Adds `asset` to its storage in `res` as if `data` had been loaded into it.
+    pub(crate) fn insert<A: Asset>(res: &mut Resources, data: A::Data, asset: A) -> Handle<A> {
+        let handle =
+            res.fetch::<Loader>()
+                .load_from_data(data, (), &res.fetch::<AssetStorage<A>>());
+        let pool = res.fetch::<Arc<rayon::ThreadPool>>().clone();
+        let mut asset = Some(asset);
+        res.fetch_mut::<AssetStorage<A>>().process(
+            |_| Ok(ProcessingState::Loaded(asset.take().unwrap())),
+            0,
+            &pool,
+            None,
+        );
+        handle
+    }
+
+    fn material(texture: TextureHandle) -> Material {
+        Material {
+            alpha_cutoff: 0.01,
+            albedo: texture.clone(),
+            albedo_offset: TextureOffset::default(),
+            emission: texture.clone(),
+            emission_offset: TextureOffset::default(),
+            emission_intensity: 1.0,
+            normal: texture.clone(),
+            normal_offset: TextureOffset::default(),
+            metallic: texture.clone(),
+            metallic_offset: TextureOffset::default(),
+            roughness: texture.clone(),
+            roughness_offset: TextureOffset::default(),
+            ambient_occlusion: texture.clone(),
+            ambient_occlusion_offset: TextureOffset::default(),
+            caveat: texture,
+            caveat_offset: TextureOffset::default(),
+            two_sided: false,
+        }
+    }
+
+    /// Draws a quad covering the view of a camera at the origin, whose bounds are behind it.
+    fn misplaced_quad(frustum_culling: bool) -> [u8; 4] {
+        let mut renderer = HeadlessRenderer::new((4, 4)).unwrap();
+        let mut world = world(&mut renderer);
+        let vertex = |x, y| PosTex {
+            position: Vector3::new(x, y, -5.0),
+            tex_coord: Vector2::new(0.0, 0.0),
+        };
+        let vertices = vec![
+            vertex(-10.0, -10.0),
+            vertex(10.0, -10.0),
+            vertex(10.0, 10.0),
+            vertex(-10.0, -10.0),
+            vertex(10.0, 10.0),
+            vertex(-10.0, 10.0),
+        ];
+        let quad = renderer.create_mesh(MeshBuilder::new(vertices)).unwrap();
+        let quad = insert(&mut world.res, MeshData::PosTex(Vec::new()), quad);
+        let albedo = world.read_resource::<MaterialDefaults>().0.clone();
+
+        world.register::<Camera>();
+        world.register::<GlobalTransform>();
+        world.register::<BoundingSphere>();
+        world.register::<Material>();
+        world.register::<Handle<Mesh>>();
+        world
+            .create_entity()
+            .with(Camera::standard_3d(4.0, 4.0))
+            .with(GlobalTransform::default())
+            .build();
+        world
+            .create_entity()
+            .with(quad)
+            .with(albedo)
+            .with(GlobalTransform::default())
+            .with(BoundingSphere::new(Point3::new(0.0, 0.0, 50.0), 1.0))
+            .build();
+
+        let pass = DrawFlat::<PosTex>::new();
+        let pass = if frustum_culling {
+            pass.with_frustum_culling()
+        } else {
+            pass
+        };
+        let mut pipe = renderer
+            .create_pipe(
+                Pipeline::build().with_stage(
+                    Stage::with_backbuffer()
+                        .clear_target([0.0, 0.0, 0.0, 1.0], 1.0)
+                        .with_pass(pass),
+                ),
+            )
+            .unwrap();
+        renderer.draw_world(&mut pipe, &mut world.res);
+        renderer.read_pixels().unwrap().rgba.get_pixel(2, 2).data
+    }
+
+    /// Draws a white triangle over the lower right half of a black scene, then smooths its edge
+    /// with `DrawFxaa`.
+    fn fxaa_triangle() -> image::RgbaImage {
+        let mut renderer = HeadlessRenderer::new((16, 16)).unwrap();
+        let mut world = world(&mut renderer);
+        let vertex = |x, y| PosTex {
+            position: Vector3::new(x, y, -5.0),
+            tex_coord: Vector2::new(0.0, 0.0),
+        };
+        let vertices = vec![
+            vertex(-10.0, -10.0),
+            vertex(10.0, -10.0),
+            vertex(10.0, 10.0),
+        ];
+        let triangle = renderer.create_mesh(MeshBuilder::new(vertices)).unwrap();
+        let triangle = insert(&mut world.res, MeshData::PosTex(Vec::new()), triangle);
+        let albedo = world.read_resource::<MaterialDefaults>().0.clone();
+
+        world.register::<Camera>();
+        world.register::<GlobalTransform>();
+        world.register::<Material>();
+        world.register::<Handle<Mesh>>();
+        world
+            .create_entity()
+            .with(Camera::standard_3d(16.0, 16.0))
+            .with(GlobalTransform::default())
+            .build();
+        world
+            .create_entity()
+            .with(triangle)
+            .with(albedo)
+            .with(GlobalTransform::default())
+            .build();
+
+        let mut pipe = renderer
+            .create_pipe(
+                Pipeline::build()
+                    .with_target(Target::named("scene").with_depth_buf(true))
+                    .with_stage(
+                        Stage::with_target("scene")
+                            .clear_target([0.0, 0.0, 0.0, 1.0], 1.0)
+                            .with_pass(DrawFlat::<PosTex>::new()),
+                    )
+                    .with_stage(Stage::with_backbuffer().with_pass(DrawFxaa::new("scene"))),
+            )
+            .unwrap();
+        renderer.draw_world(&mut pipe, &mut world.res);
+        renderer.read_pixels().unwrap().rgba
+    }
+
+    /// Creates a cubemap of one pixel per face.
+    pub(crate) fn cube(renderer: &mut HeadlessRenderer, rgba: [u8; 4]) -> Texture {
+        let faces = rgba
+            .iter()
+            .cloned()
+            .cycle()
+            .take(6 * 4)
+            .collect::<Vec<u8>>();
+        renderer
+            .create_texture(TextureBuilder::new(faces).with_kind(Kind::Cube(1)))
+            .unwrap()
+    }
+
+    // These need an OpenGL context, run them with `cargo test -- --ignored` where one can be
+    // created, e.g. under `xvfb-run` like the Linux build on Travis does.
+
+    #[test]
+    #[ignore]
+    fn stages_clear_the_backbuffer() {
+        let mut renderer = HeadlessRenderer::new((4, 4)).unwrap();
+        let mut pipe = renderer
+            .create_pipe(
+                Pipeline::build()
+                    .with_stage(Stage::with_backbuffer().clear_target([1.0, 0.0, 0.0, 1.0], 1.0)),
+            )
+            .unwrap();
+        renderer.draw_world(&mut pipe, &mut World::new().res);
+
+        let image = renderer.read_pixels().unwrap().rgba;
+        assert_eq!(image.dimensions(), (4, 4));
+        assert!(image.pixels().all(|pixel| pixel.data == [255, 0, 0, 255]));
+    }
+
+    #[test]
+    #[ignore]
+    fn disabled_stages_are_skipped() {
+        let mut renderer = HeadlessRenderer::new((4, 4)).unwrap();
+        let mut pipe = renderer
+            .create_pipe(
+                Pipeline::build()
+                    .with_stage(Stage::with_backbuffer().clear_target([0.0, 1.0, 0.0, 1.0], 1.0))
+                    .with_stage(
+                        Stage::with_backbuffer()
+                            .clear_target([0.0, 0.0, 1.0, 1.0], 1.0)
+                            .enabled(false),
+                    ),
+            )
+            .unwrap();
+        renderer.draw_world(&mut pipe, &mut World::new().res);
+
+        let image = renderer.read_pixels().unwrap().rgba;
+        assert_eq!(image.get_pixel(2, 2).data, [0, 255, 0, 255]);
+    }
+
+    #[test]
+    #[ignore]
+    fn bounds_out_of_view_are_culled() {
+        assert_eq!(misplaced_quad(false), [255, 255, 255, 255]);
+        assert_eq!(misplaced_quad(true), [0, 0, 0, 255]);
+    }
+
+    #[test]
+    #[ignore]
+    fn the_lowest_sky_layer_replaces_the_target() {
+        let mut renderer = HeadlessRenderer::new((4, 4)).unwrap();
+        let mut world = world(&mut renderer);
+        let red = cube(&mut renderer, [255, 0, 0, 255]);
+        let red = insert(&mut world.res, [1.0, 0.0, 0.0, 1.0].into(), red);
+        let blue = cube(&mut renderer, [0, 0, 255, 255]);
+        let blue = insert(&mut world.res, [0.0, 0.0, 1.0, 1.0].into(), blue);
+
+        // Half transparent, which the lowest layer ignores.
+        let mut far = SkyBox::new(red);
+        far.set_tint([1.0, 1.0, 1.0, 0.5], 1.0);
+        // Fully transparent, so it doesn't show over the lowest layer.
+        let mut near = SkyBox::new(blue);
+        near.set_tint([1.0, 1.0, 1.0, 0.0], 1.0);
+        near.set_layer(1);
+        world.register::<SkyBox>();
+        world.create_entity().with(near).build();
+        world.create_entity().with(far).build();
+
+        let mut pipe = renderer
+            .create_pipe(
+                Pipeline::build().with_stage(
+                    Stage::with_backbuffer()
+                        .clear_target([0.0, 1.0, 0.0, 1.0], 1.0)
+                        .with_pass(DrawSkyBox::new().with_draw_without_camera(true)),
+                ),
+            )
+            .unwrap();
+        renderer.draw_world(&mut pipe, &mut world.res);
+
+        let image = renderer.read_pixels().unwrap().rgba;
+        assert_eq!(image.get_pixel(2, 2).data, [255, 0, 0, 255]);
+    }
+
+    #[test]
+    #[ignore]
+    fn fxaa_only_blends_along_edges() {
+        let image = fxaa_triangle();
+        // The triangle is drawn without multisampling, so only the filter blends its edge.
+        let blended = image
+            .pixels()
+            .filter(|pixel| pixel.data[0] > 0 && pixel.data[0] < 255)
+            .count();
+        assert!(blended > 0, "no pixel along the edge was blended");
+        assert_eq!(image.get_pixel(0, 0).data, [0, 0, 0, 255]);
+        assert_eq!(image.get_pixel(15, 15).data, [255, 255, 255, 255]);
+    }
+
+    #[test]
+    #[ignore]
+    fn the_neutral_lut_keeps_colors() {
+        let color = [0.25, 0.5, 0.75, 1.0];
+        let mut renderer = HeadlessRenderer::new((4, 4)).unwrap();
+        let mut pipe = renderer
+            .create_pipe(
+                Pipeline::build().with_stage(Stage::with_backbuffer().clear_target(color, 1.0)),
+            )
+            .unwrap();
+        renderer.draw_world(&mut pipe, &mut World::new().res);
+        let expected = renderer.read_pixels().unwrap().rgba.get_pixel(2, 2).data;
+
+        let mut world = world(&mut renderer);
+        let pixels = match DrawColorGrading::neutral_lut(16) {
+            TextureData::U8(pixels, _) => pixels,
+            _ => unreachable!("neutral_lut creates 8-bit data"),
+        };
+        let lut = renderer
+            .create_texture(
+                TextureBuilder::new(pixels)
+                    .with_size(16 * 16, 16)
+                    .with_channel_type(ChannelType::Unorm),
+            )
+            .unwrap();
+        let lut = insert(&mut world.res, DrawColorGrading::neutral_lut(16), lut);
+        let mut pipe = renderer
+            .create_pipe(
+                Pipeline::build()
+                    .with_target(Target::named("scene"))
+                    .with_stage(Stage::with_target("scene").clear_target(color, 1.0))
+                    .with_stage(
+                        Stage::with_backbuffer().with_pass(DrawColorGrading::new("scene", lut)),
+                    ),
+            )
+            .unwrap();
+        renderer.draw_world(&mut pipe, &mut world.res);
+
+        let graded = renderer.read_pixels().unwrap().rgba.get_pixel(2, 2).data;
+        for (graded, expected) in graded.iter().zip(&expected) {
+            assert!(
+                (i16::from(*graded) - i16::from(*expected)).abs() <= 2,
+                "{:?} was graded to {:?}",
+                expected,
+                graded
+            );
+        }
+    }
+}
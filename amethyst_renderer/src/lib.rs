@@ -29,15 +29,19 @@
 pub use crate::{
     blink::{Blink, BlinkSystem},
     bundle::RenderBundle,
-    cam::{ActiveCamera, ActiveCameraPrefab, Camera, CameraPrefab, Projection},
+    cam::{
+        ActiveCamera, ActiveCameraPrefab, Camera, CameraExposure, CameraPrefab, CameraTarget,
+        Projection, StereoCamera, Viewport,
+    },
     color::Rgba,
     config::DisplayConfig,
+    culling::{Aabb, BoundingSphere, Frustum},
     debug_drawing::{DebugLines, DebugLinesComponent},
     formats::{
         build_mesh_with_combo, create_mesh_asset, create_texture_asset, BmpFormat,
-        ComboMeshCreator, GraphicsPrefab, ImageData, JpgFormat, MaterialPrefab, MeshCreator,
-        MeshData, ObjFormat, PngFormat, TextureData, TextureFormat, TextureMetadata, TexturePrefab,
-        TgaFormat,
+        ComboMeshCreator, DdsFormat, GraphicsPrefab, ImageData, JpgFormat, KtxFormat,
+        MaterialPrefab, MeshCreator, MeshData, ObjFormat, PngFormat, TextureData, TextureFormat,
+        TextureMetadata, TexturePrefab, TgaFormat,
     },
     hidden::{Hidden, HiddenPropagate},
     hide_system::HideHierarchySystem,
@@ -45,24 +49,39 @@ pub use crate::{
         DeviceEvent, ElementState, Event, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent,
     },
     light::{DirectionalLight, Light, LightPrefab, PointLight, SpotLight, SunLight},
-    mesh::{vertex_data, Mesh, MeshBuilder, MeshHandle, VertexBuffer},
-    mtl::{Material, MaterialDefaults, TextureOffset},
+    mesh::{vertex_data, Indices, LodMesh, LodView, Mesh, MeshBuilder, MeshHandle, VertexBuffer},
+    mtl::{Material, MaterialDefaults, MissingTextures, TextureOffset, TextureRegion},
     pass::{
-        get_camera, load_cubemap, set_vertex_args, DebugLinesParams, DrawDebugLines, DrawFlat,
-        DrawFlat2D, DrawFlatSeparate, DrawPbm, DrawPbmSeparate, DrawShaded, DrawShadedSeparate,
-        DrawSkyBox, DrawSkybox, SkyBox, SkyboxColor,
+        cubemap_faces_from_layout, get_camera, get_cameras, in_frustum, irradiance_cubemap,
+        load_cubemap, load_cubemap_async, load_cubemap_from_images, load_cubemap_from_memory,
+        load_cubemap_hdr, load_cubemap_layout, load_cubemap_with_mips, load_equirect,
+        prefilter_cubemap, set_vertex_args, sort_back_to_front, Atmosphere, AutoExposure, Backdrop,
+        BackdropLayer, CubemapFace, CubemapFaces, CubemapFilter, CubemapFilters, CubemapFormat,
+        CubemapLayout, CubemapOptions, CubemapOrientation, DebugLinesParams, Decal, DrawAtmosphere,
+        DrawBackdrop, DrawBloom, DrawColorGrading, DrawDebugLines, DrawDecals,
+        DrawDeferredLighting, DrawDepth, DrawFlat, DrawFlat2D, DrawFlatInstanced, DrawFlatSeparate,
+        DrawFog, DrawFxaa, DrawGBuffer, DrawGodrays, DrawOutline, DrawParticles, DrawPbm,
+        DrawPbmSeparate, DrawReflectionProbes, DrawSdfText, DrawShaded, DrawShadedSeparate,
+        DrawShadowMap, DrawSkyBox, DrawSkyGradient, DrawSkyOverlays, DrawSkyPanorama, DrawSkybox,
+        DrawSsao, DrawTonemap, DrawWireframe, EntityUniform, FaceTransform, FogMode, NoUniform,
+        Particle, ParticleBlend, Particles, ReflectionProbe, SdfFont, SdfGlyph, SdfGlyphQuad,
+        SdfText, Selected, ShadowCaster, ShadowReceiver, ShadowSettings, SkyBox, SkyBoxBundle,
+        SkyBoxSystem, SkyGradient, SkyOverlay, SkyPanorama, SkyboxColor, SpriteBlend,
+        TonemapOperator, Wireframe, GBUFFER_COLOR_BUFS,
     },
     pipe::{
-        ColorBuffer, Data, DepthBuffer, DepthMode, Effect, EffectBuilder, Init, Meta, NewEffect,
-        Pipeline, PipelineBuild, PipelineBuilder, PipelineData, PolyPipeline, PolyStage,
-        PolyStages, Stage, StageBuilder, Target, TargetBuilder, Targets,
+        watch_shaders, ColorBuffer, Data, DepthBuffer, DepthMode, Effect, EffectBuilder,
+        ForwardPipeline, ForwardPipelineBuilder, Init, Meta, NewEffect, PassTimer, Pipeline,
+        PipelineBuild, PipelineBuilder, PipelineData, PolyPipeline, PolyStage, PolyStages, Stage,
+        StageBuilder, Target, TargetBuilder, Targets, BUILTIN_SHADER_DIR,
     },
     renderer::Renderer,
-    resources::{AmbientColor, ScreenDimensions, WindowMessages},
+    resources::{AmbientColor, ScreenDimensions, TargetTextures, WindowMessages},
+    screenshot::Screenshots,
     shape::{InternalShape, Shape, ShapePrefab, ShapeUpload},
     skinning::{
         AnimatedComboMeshCreator, AnimatedVertexBufferCombination, JointIds, JointTransforms,
-        JointTransformsPrefab, JointWeights,
+        JointTransformsPrefab, JointWeights, MAX_JOINTS,
     },
     sprite::{
         Flipped, Sprite, SpriteRender, SpriteSheet, SpriteSheetFormat, SpriteSheetHandle,
@@ -74,17 +93,26 @@ pub use crate::{
         FilterMethod, SamplerInfo, SurfaceType, Texture, TextureBuilder, TextureHandle, WrapMode,
     },
     transparent::{
-        Blend, BlendChannel, BlendValue, ColorMask, Equation, Factor, Transparent, ALPHA, REPLACE,
+        Blend, BlendChannel, BlendValue, ColorMask, Equation, Factor, SortKey, Transparent,
+        ADDITIVE, ALPHA, PREMULTIPLIED, REPLACE,
+    },
+    types::{
+        Encoder, Factory, PipelineState, RawBuffer, RawShaderResourceView, Resources, Sampler,
+        Slice,
     },
-    types::{Encoder, Factory, PipelineState, Resources},
     vertex::{
-        Attribute, AttributeFormat, Attributes, Color, Normal, PosColor, PosColorNorm,
+        Attribute, AttributeFormat, Attributes, Color, Normal, PosColor, PosColorNorm, PosColorTex,
         PosNormTangTex, PosNormTex, PosTex, Position, Query, Separate, Tangent, TexCoord,
         VertexBufferCombination, VertexFormat, With,
     },
     visibility::{Visibility, VisibilitySortingSystem},
 };
 
+#[cfg(feature = "opengl")]
+pub use crate::headless::HeadlessRenderer;
+#[cfg(feature = "gpu_timing")]
+pub use crate::pipe::PassTimings;
+
 mod error;
 pub mod mouse;
 pub mod pipe;
@@ -97,8 +125,11 @@ mod bundle;
 mod cam;
 mod color;
 mod config;
+mod culling;
 mod debug_drawing;
 mod formats;
+#[cfg(feature = "opengl")]
+mod headless;
 mod hidden;
 mod hide_system;
 mod input;
@@ -108,6 +139,7 @@ mod mtl;
 mod pass;
 mod renderer;
 mod resources;
+mod screenshot;
 mod shape;
 mod skinning;
 mod sprite;
@@ -38,6 +38,12 @@ pub struct DirectionalLight {
     pub color: Rgba,
     /// Direction that the light is pointing.
     pub direction: [f32; 3], //TODO: Replace with a nalgebra type
+    /// Color temperature in Kelvin tinting `color`, e.g. to follow the sun through the day.
+    ///
+    /// `color` is multiplied by `Rgba::from_temperature` of it, so a white `color` takes the
+    /// color of the temperature and `color` alone scales its brightness. `0.0`, the default,
+    /// leaves `color` as it is.
+    pub color_temperature: f32,
 }
 
 impl Default for DirectionalLight {
@@ -45,10 +51,23 @@ impl Default for DirectionalLight {
         DirectionalLight {
             color: Rgba::default(),
             direction: [-1.0, -1.0, -1.0],
+            color_temperature: 0.0,
         }
     }
 }
 
+impl DirectionalLight {
+    /// Returns `color` tinted by `color_temperature`, the color the light shines in.
+    pub fn tinted_color(&self) -> Rgba {
+        if self.color_temperature <= 0.0 {
+            return self.color;
+        }
+        let Rgba(r, g, b, a) = self.color;
+        let Rgba(tr, tg, tb, _) = Rgba::from_temperature(self.color_temperature);
+        Rgba(r * tr, g * tg, b * tb, a)
+    }
+}
+
 impl From<DirectionalLight> for Light {
     fn from(dir: DirectionalLight) -> Self {
         Light::Directional(dir)
@@ -191,3 +210,24 @@ pub struct LightPrefab {
     light: Option<Light>,
     ambient_color: Option<AmbientColor>,
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::color::Rgba;
+
+    use super::DirectionalLight;
+
+    #[test]
+    fn temperature_tints_the_color() {
+        let mut light = DirectionalLight {
+            color: Rgba(0.5, 0.5, 0.5, 1.0),
+            ..DirectionalLight::default()
+        };
+        assert_eq!(light.tinted_color(), light.color);
+
+        light.color_temperature = 2000.0;
+        let Rgba(r, g, b, a) = light.tinted_color();
+        assert_eq!((r, a), (0.5, 1.0));
+        assert!(g < 0.5 && b < g);
+    }
+}
@@ -1,19 +1,27 @@
 //! Mesh resource.
 
 use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fmt,
+    hash::{Hash, Hasher},
     iter::{once, Chain, Once},
     marker::PhantomData,
+    sync::Mutex,
 };
 
 use gfx::Primitive;
 use serde::{Deserialize, Serialize};
 
 use amethyst_assets::Handle;
-use amethyst_core::nalgebra::{Matrix4, Point3, Rotation3, Translation3, Unit, Vector3};
+use amethyst_core::{
+    nalgebra::{Matrix4, Point3, Rotation3, Translation3, Unit, Vector3},
+    specs::prelude::{Component, DenseVecStorage},
+};
 use amethyst_error::Error;
 
 use crate::{
-    types::{Factory, RawBuffer, Slice},
+    error,
+    types::{Encoder, Factory, RawBuffer, Slice},
     vertex::{Attributes, VertexFormat},
 };
 
@@ -22,6 +30,47 @@ use crate::{
 pub struct VertexBuffer {
     attrs: Attributes<'static>,
     raw: RawBuffer,
+    len: usize,
+}
+
+impl VertexBuffer {
+    /// Returns the names and formats of the attributes of each vertex, with their byte offsets.
+    pub fn attributes(&self) -> Attributes<'static> {
+        self.attrs
+    }
+
+    /// Returns the size of each vertex in bytes.
+    pub fn stride(&self) -> usize {
+        self.raw.get_info().stride
+    }
+
+    /// Returns the number of vertices in the buffer.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether the buffer holds no vertices.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Describes the layout of the buffer, e.g.
+/// `24 vertices of 20 bytes: position R32_G32_B32/Float at 0, tex_coord R32_G32/Float at 12`.
+impl fmt::Display for VertexBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} vertices of {} bytes:", self.len, self.stride())?;
+        for (i, &(name, element)) in self.attrs.iter().enumerate() {
+            let separator = if i == 0 { " " } else { ", " };
+            let (surface, channel) = (element.format.0, element.format.1);
+            write!(
+                f,
+                "{}{} {:?}/{:?} at {}",
+                separator, name, surface, channel, element.offset
+            )?;
+        }
+        Ok(())
+    }
 }
 
 /// Vertex data that can be built into `VertexBuffer`
@@ -73,6 +122,7 @@ where
         Ok(VertexBuffer {
             attrs: V::ATTRIBUTES,
             raw: vbuf,
+            len: verts.len(),
         })
     }
 }
@@ -127,6 +177,42 @@ where
 /// A handle to a mesh.
 pub type MeshHandle = Handle<Mesh>;
 
+/// Indices into the vertices of a mesh, each three of a triangle list forming a triangle
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Indices {
+    /// 16-bit indices, for meshes of up to 65536 vertices
+    U16(Vec<u16>),
+    /// 32-bit indices
+    U32(Vec<u32>),
+}
+
+impl Indices {
+    /// Returns the number of indices.
+    pub fn len(&self) -> usize {
+        match *self {
+            Indices::U16(ref indices) => indices.len(),
+            Indices::U32(ref indices) => indices.len(),
+        }
+    }
+
+    /// Returns whether there are no indices.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl From<Vec<u16>> for Indices {
+    fn from(indices: Vec<u16>) -> Self {
+        Indices::U16(indices)
+    }
+}
+
+impl From<Vec<u32>> for Indices {
+    fn from(indices: Vec<u32>) -> Self {
+        Indices::U32(indices)
+    }
+}
+
 /// Represents a polygonal mesh.
 #[derive(Clone, Debug)]
 pub struct Mesh {
@@ -181,6 +267,79 @@ impl Mesh {
         None
     }
 
+    /// Returns the vertex buffers of the mesh, e.g. to check which attributes it provides.
+    pub fn vertex_buffers(&self) -> &[VertexBuffer] {
+        &self.vbufs
+    }
+
+    /// Returns the names of the requested attributes that none of the mesh's vertex buffers
+    /// provides with the same format and offset.
+    ///
+    /// `buffer` also fails while this is empty if the attributes are spread over several buffers.
+    pub fn missing_attributes<'a>(&self, attributes: Attributes<'a>) -> Vec<&'a str> {
+        missing_attributes(self.vbufs.iter().map(|vbuf| vbuf.attrs), attributes)
+    }
+
+    /// Replaces the contents of the mesh's vertex buffer with the format `V`.
+    ///
+    /// Meshes are created in immutable memory, so the first update moves the buffer into
+    /// dynamic memory. After that, vertices that fit into the buffer are written in place,
+    /// reusing the allocation: as many vertices as before replace all of them, and fewer are
+    /// written to the front of the buffer, leaving the rest unused until a later update grows the
+    /// mesh again. More vertices than the buffer has room for make it reallocate with exactly
+    /// enough room for them.
+    ///
+    /// The mesh draws as many vertices as its shortest buffer holds, so meshes with several
+    /// buffers should have all of them updated to the same length. Meshes built with indices
+    /// keep drawing all of their indices, which have to stay within the new vertices.
+    pub fn update_vertices<V>(
+        &mut self,
+        verts: &[V],
+        fac: &mut Factory,
+        enc: &mut Encoder,
+    ) -> Result<(), Error>
+    where
+        V: VertexFormat,
+    {
+        use gfx::{
+            buffer::{Info, Role},
+            handle::Buffer,
+            memory::{Bind, Typed, Usage},
+            Factory, IndexBuffer,
+        };
+
+        let vbuf = self
+            .vbufs
+            .iter_mut()
+            .find(|vbuf| vbuf.attrs == V::ATTRIBUTES)
+            .ok_or(error::Error::NoSuchVertexBuffer)?;
+
+        if !verts.is_empty() {
+            let size = verts.len() * V::size();
+            let info = vbuf.raw.get_info();
+            if info.usage != Usage::Dynamic || info.size < size {
+                vbuf.raw = fac.create_buffer_raw(Info {
+                    role: Role::Vertex,
+                    usage: Usage::Dynamic,
+                    bind: Bind::empty(),
+                    size,
+                    stride: V::size(),
+                })?;
+            }
+
+            let buf: Buffer<_, V> = Typed::new(vbuf.raw.clone());
+            enc.update_buffer(&buf, verts, 0)
+                .map_err(|_| error::Error::UpdateVertexBuffer)?;
+        }
+        vbuf.len = verts.len();
+
+        // Indexed meshes keep drawing all of their indices.
+        if let IndexBuffer::Auto = self.slice.buffer {
+            self.slice.end = self.vbufs.iter().map(|vbuf| vbuf.len).min().unwrap_or(0) as u32;
+        }
+        Ok(())
+    }
+
     /// Returns associated `Slice`
     pub fn slice(&self) -> &Slice {
         &self.slice
@@ -196,12 +355,124 @@ impl Mesh {
     }
 }
 
+/// Swaps an entity's mesh for coarser ones as it gets farther away from the camera.
+///
+/// The flat, shaded and pbm passes draw the level for the entity's distance from the camera in
+/// place of its `MeshHandle`. The entity still needs a `MeshHandle`, usually the finest level,
+/// which other passes keep drawing.
+///
+/// The current level is remembered for every `LodView` on its own, so the hysteresis of one
+/// camera doesn't depend on the distance from another.
+#[derive(Debug)]
+pub struct LodMesh {
+    distances: Vec<f32>,
+    meshes: Vec<MeshHandle>,
+    hysteresis: f32,
+    current: Mutex<HashMap<LodView, usize>>,
+}
+
+/// One view of the scene, a camera or one eye of it drawing into a target, for which a
+/// `LodMesh` keeps its current level.
+///
+/// Passes drawing the same target see the same views, so they agree on the level they draw.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LodView(u64);
+
+impl LodView {
+    /// Returns the view with the given index among the views drawing into `target`.
+    pub fn new(target: &str, index: usize) -> Self {
+        let mut hasher = DefaultHasher::new();
+        target.hash(&mut hasher);
+        index.hash(&mut hasher);
+        LodView(hasher.finish())
+    }
+}
+
+impl LodMesh {
+    /// Creates a `LodMesh` drawing `mesh` up close.
+    pub fn new(mesh: MeshHandle) -> Self {
+        LodMesh {
+            distances: vec![0.0],
+            meshes: vec![mesh],
+            hysteresis: 0.0,
+            current: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Adds a level drawing `mesh` from `distance` onwards, up to the next level's distance.
+    ///
+    /// Levels can be added in any order.
+    pub fn with_level(mut self, distance: f32, mesh: MeshHandle) -> Self {
+        let i = self
+            .distances
+            .iter()
+            .position(|&start| start > distance)
+            .unwrap_or_else(|| self.distances.len());
+        self.distances.insert(i, distance);
+        self.meshes.insert(i, mesh);
+        self
+    }
+
+    /// Sets how far past a level's distance the entity has to move before the level changes,
+    /// in either direction. This keeps entities near a threshold from switching back and forth.
+    ///
+    /// Defaults to `0.0`.
+    pub fn with_hysteresis(mut self, hysteresis: f32) -> Self {
+        self.hysteresis = hysteresis;
+        self
+    }
+
+    /// Returns the index of the level to draw at `distance` in `view`, and remembers it as the
+    /// current level of that view.
+    pub fn select(&self, view: LodView, distance: f32) -> usize {
+        let mut current = self.current.lock().expect("LodMesh levels poisoned");
+        let current = current.entry(view).or_insert(0);
+        *current = select_level(&self.distances, *current, distance, self.hysteresis);
+        *current
+    }
+
+    /// Returns the mesh to draw at `distance` in `view`, see `select`.
+    pub fn mesh(&self, view: LodView, distance: f32) -> &MeshHandle {
+        &self.meshes[self.select(view, distance)]
+    }
+
+    /// Returns the mesh of the level with the given index, the closest being `0`.
+    pub fn level(&self, index: usize) -> Option<&MeshHandle> {
+        self.meshes.get(index)
+    }
+}
+
+impl Component for LodMesh {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Returns the level to switch to from `current`, given the sorted distances levels start at.
+fn select_level(distances: &[f32], current: usize, distance: f32, hysteresis: f32) -> usize {
+    let level = |distance: f32| {
+        distances
+            .iter()
+            .rposition(|&start| distance >= start)
+            .unwrap_or(0)
+    };
+    let farther = level(distance - hysteresis);
+    let nearer = level(distance + hysteresis);
+    if farther > current {
+        farther
+    } else if nearer < current {
+        nearer
+    } else {
+        current
+    }
+}
+
 /// Builds new meshes.
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct MeshBuilder<T> {
     prim: Primitive,
     transform: Matrix4<f32>,
     vertices: T,
+    #[serde(default)]
+    indices: Option<Indices>,
 }
 
 impl<D, V> MeshBuilder<((D, PhantomData<V>), ())>
@@ -216,6 +487,7 @@ where
             prim: Primitive::TriangleList,
             transform: Matrix4::identity(),
             vertices: (vertex_data(verts), ()),
+            indices: None,
         }
     }
 }
@@ -235,9 +507,18 @@ where
             prim: self.prim,
             transform: self.transform,
             vertices: (vertex_data(verts), self.vertices),
+            indices: self.indices,
         }
     }
 
+    /// Draws the vertices in the order of `indices`, which lets primitives share vertices.
+    ///
+    /// Without indices, which is the default, the vertices are drawn in order.
+    pub fn with_indices<I: Into<Indices>>(mut self, indices: I) -> Self {
+        self.indices = Some(indices.into());
+        self
+    }
+
     /// Sets the primitive type of the mesh.
     ///
     /// By default, meshes are constructed as triangle lists.
@@ -279,15 +560,34 @@ where
 
     /// Builds and returns the new mesh.
     pub fn build(self, fac: &mut Factory) -> Result<Mesh, Error> {
-        use gfx::IndexBuffer;
-        let count = self.vertices.len();
+        use gfx::{buffer::Role, memory::Bind, Factory, IndexBuffer};
+
+        let (count, buffer) = match self.indices {
+            None => (self.vertices.len(), IndexBuffer::Auto),
+            Some(Indices::U16(ref indices)) => (
+                indices.len(),
+                IndexBuffer::Index16(fac.create_buffer_immutable(
+                    indices,
+                    Role::Index,
+                    Bind::empty(),
+                )?),
+            ),
+            Some(Indices::U32(ref indices)) => (
+                indices.len(),
+                IndexBuffer::Index32(fac.create_buffer_immutable(
+                    indices,
+                    Role::Index,
+                    Bind::empty(),
+                )?),
+            ),
+        };
 
         let slice = Slice {
             start: 0,
             end: count as u32,
             base_vertex: 0,
             instances: None,
-            buffer: IndexBuffer::Auto,
+            buffer,
         };
 
         Ok(Mesh {
@@ -309,3 +609,92 @@ fn check_attributes_are_sorted(attrs: Attributes<'_>) -> bool {
     }
     true
 }
+
+fn missing_attributes<'a, 'b, I>(layouts: I, attributes: Attributes<'a>) -> Vec<&'a str>
+where
+    I: Iterator<Item = Attributes<'b>> + Clone,
+{
+    attributes
+        .iter()
+        .filter(|attr| !layouts.clone().any(|layout| layout.contains(attr)))
+        .map(|&(name, _)| name)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{missing_attributes, select_level, LodMesh, LodView, Mesh};
+    use crate::vertex::{Normal, PosNormTex, PosTex, Query, TexCoord, VertexFormat};
+
+    const DISTANCES: [f32; 3] = [0.0, 10.0, 50.0];
+
+    #[test]
+    fn level_follows_distance() {
+        for &(distance, level) in &[
+            (0.0, 0),
+            (9.9, 0),
+            (10.0, 1),
+            (49.0, 1),
+            (50.0, 2),
+            (1e6, 2),
+        ] {
+            assert_eq!(select_level(&DISTANCES, 0, distance, 0.0), level);
+            assert_eq!(select_level(&DISTANCES, 2, distance, 0.0), level);
+        }
+    }
+
+    #[test]
+    fn hysteresis_delays_switching() {
+        // Moving away, level 0 is kept until 2 past the threshold.
+        assert_eq!(select_level(&DISTANCES, 0, 11.0, 2.0), 0);
+        assert_eq!(select_level(&DISTANCES, 0, 12.0, 2.0), 1);
+        assert_eq!(select_level(&DISTANCES, 0, 60.0, 2.0), 2);
+        // Coming back, level 1 is kept until 2 before it.
+        assert_eq!(select_level(&DISTANCES, 1, 9.0, 2.0), 1);
+        assert_eq!(select_level(&DISTANCES, 1, 7.9, 2.0), 0);
+        assert_eq!(select_level(&DISTANCES, 2, 30.0, 2.0), 1);
+    }
+
+    #[test]
+    fn each_view_switches_on_its_own() {
+        use std::sync::Arc;
+
+        use amethyst_assets::{AssetStorage, Loader};
+        use rayon::ThreadPoolBuilder;
+
+        let pool = Arc::new(ThreadPoolBuilder::new().num_threads(1).build().unwrap());
+        let loader = Loader::new(".", pool);
+        let storage = AssetStorage::<Mesh>::new();
+        let mut handles =
+            (0..3).map(|_| loader.load_from_data(Vec::<PosTex>::new().into(), (), &storage));
+        let lod = LodMesh::new(handles.next().unwrap())
+            .with_level(10.0, handles.next().unwrap())
+            .with_level(50.0, handles.next().unwrap())
+            .with_hysteresis(2.0);
+
+        let near = LodView::new("main", 0);
+        let far = LodView::new("main", 1);
+        assert_eq!(lod.select(far, 60.0), 2);
+        // The near view starts over from the closest level instead of the far view's level.
+        assert_eq!(lod.select(near, 11.0), 0);
+        assert_eq!(lod.select(far, 49.0), 2);
+        assert_eq!(lod.select(near, 12.0), 1);
+        assert_eq!(LodView::new("main", 0), near);
+        assert_ne!(LodView::new("mirror", 0), near);
+    }
+
+    #[test]
+    fn missing_attributes_are_named() {
+        let layouts = [PosTex::ATTRIBUTES];
+        assert!(missing_attributes(layouts.iter().cloned(), PosTex::ATTRIBUTES).is_empty());
+        assert_eq!(
+            missing_attributes(layouts.iter().cloned(), PosNormTex::ATTRIBUTES),
+            // `tex_coord` comes after the normal in `PosNormTex`, at another offset.
+            vec!["normal", "tex_coord"]
+        );
+
+        let layouts = [PosNormTex::ATTRIBUTES];
+        let queried = <PosNormTex as Query<(Normal, TexCoord)>>::QUERIED_ATTRIBUTES;
+        assert!(missing_attributes(layouts.iter().cloned(), queried).is_empty());
+    }
+}
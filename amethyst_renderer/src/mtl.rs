@@ -4,7 +4,10 @@ use amethyst_core::specs::prelude::{Component, DenseVecStorage};
 
 use serde::{Deserialize, Serialize};
 
-use crate::tex::TextureHandle;
+use crate::{
+    formats::{TextureData, TextureMetadata},
+    tex::TextureHandle,
+};
 
 /// Material reference this part of the texture
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
@@ -24,10 +27,43 @@ impl Default for TextureOffset {
     }
 }
 
+/// Draws only this part of an entity's texture, e.g. one tile of an atlas
+///
+/// Replaces the `albedo_offset` of the entity's `Material` in `DrawFlat` and `DrawFlatSeparate`,
+/// and the part of a `TextureHandle` image that `DrawFlat2D` draws, whose quad shrinks to the
+/// size of the region. Entities can share a material or texture this way and still show
+/// different tiles, without a texture of their own each.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct TextureRegion(pub TextureOffset);
+
+impl TextureRegion {
+    /// Creates the region of a tile in a grid of `columns` by `rows` equally sized tiles.
+    ///
+    /// Tiles are counted from the top left of the texture, the way images are laid out.
+    pub fn tile(columns: u32, rows: u32, column: u32, row: u32) -> Self {
+        let width = 1.0 / columns as f32;
+        let height = 1.0 / rows as f32;
+        let left = column as f32 * width;
+        let top = 1.0 - row as f32 * height;
+        TextureRegion(TextureOffset {
+            u: (left, left + width),
+            v: (top - height, top),
+        })
+    }
+}
+
+impl Component for TextureRegion {
+    type Storage = DenseVecStorage<Self>;
+}
+
 /// Material struct.
 #[derive(Clone, PartialEq)]
 pub struct Material {
     /// Alpha cutoff: the value at which we do not draw the pixel
+    ///
+    /// Pixels whose albedo alpha is below the cutoff are discarded by the lit passes, which cuts
+    /// masked geometry like leaves or fences out of opaque quads. Unlike `Transparent` blending
+    /// this needs no sorting, and it goes well with `two_sided` for foliage.
     pub alpha_cutoff: f32,
     /// Diffuse map.
     pub albedo: TextureHandle,
@@ -37,6 +73,8 @@ pub struct Material {
     pub emission: TextureHandle,
     /// Emission texture offset
     pub emission_offset: TextureOffset,
+    /// Factor the emission map is multiplied with, values above 1.0 make it glow with `DrawBloom`.
+    pub emission_intensity: f32,
     /// Normal map.
     pub normal: TextureHandle,
     /// Normal texture offset
@@ -57,6 +95,8 @@ pub struct Material {
     pub caveat: TextureHandle,
     /// Caveat texture offset
     pub caveat_offset: TextureOffset,
+    /// Draw the back faces as well, lit from their own side, e.g. for foliage or cloth.
+    pub two_sided: bool,
 }
 
 impl Component for Material {
@@ -65,8 +105,74 @@ impl Component for Material {
 
 /// A resource providing default textures for `Material`.
 /// These will be be used by the renderer in case a texture
-/// handle points to a texture which is not loaded already.
+/// handle points to a texture which is not loaded already
+/// and `MissingTextures` has no replacement for it.
 /// Additionally, you can use it to fill up the fields of
 /// `Material` you don't want to specify.
 #[derive(Clone)]
 pub struct MaterialDefaults(pub Material);
+
+/// A resource providing the textures shown in place of textures that aren't loaded
+///
+/// While a material's texture or a sky's cubemap isn't in the asset storage, because it is still
+/// loading or failed to load, passes draw these instead of falling back to `MaterialDefaults`,
+/// so missing assets stand out at a glance. The other maps of a material still fall back to
+/// `MaterialDefaults`. The `RenderSystem` inserts a magenta checker for color maps and a flat
+/// normal map unless this resource was added before it is set up.
+#[derive(Clone)]
+pub struct MissingTextures {
+    /// Shown in place of albedo and emission maps and sky textures.
+    pub color: TextureHandle,
+    /// Shown in place of normal maps.
+    pub normal: TextureHandle,
+}
+
+impl MissingTextures {
+    /// Creates a `size` by `size` texture of magenta and black squares, `square` pixels wide.
+    pub fn checker(size: u16, square: u16) -> TextureData {
+        let square = square.max(1);
+        let mut pixels = Vec::with_capacity(size as usize * size as usize * 4);
+        for y in 0..size {
+            for x in 0..size {
+                if (x / square + y / square) % 2 == 0 {
+                    pixels.extend_from_slice(&[255, 0, 255, 255]);
+                } else {
+                    pixels.extend_from_slice(&[0, 0, 0, 255]);
+                }
+            }
+        }
+        TextureData::U8(pixels, TextureMetadata::srgb_scale().with_size(size, size))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MissingTextures, TextureRegion};
+    use crate::formats::TextureData;
+
+    #[test]
+    fn tiles_are_counted_from_the_top_left() {
+        let TextureRegion(offset) = TextureRegion::tile(4, 2, 1, 0);
+        assert_eq!(offset.u, (0.25, 0.5));
+        assert_eq!(offset.v, (0.5, 1.0));
+
+        let TextureRegion(offset) = TextureRegion::tile(4, 2, 3, 1);
+        assert_eq!(offset.u, (0.75, 1.0));
+        assert_eq!(offset.v, (0.0, 0.5));
+    }
+
+    #[test]
+    fn checkers_alternate_by_square() {
+        let pixels = match MissingTextures::checker(4, 2) {
+            TextureData::U8(pixels, _) => pixels,
+            _ => panic!("Checker isn't byte data"),
+        };
+        let pixel = |x: usize, y: usize| &pixels[(y * 4 + x) * 4..][..4];
+
+        assert_eq!(pixels.len(), 4 * 4 * 4);
+        assert_eq!(pixel(1, 1), [255, 0, 255, 255]);
+        assert_eq!(pixel(2, 1), [0, 0, 0, 255]);
+        assert_eq!(pixel(1, 2), [0, 0, 0, 255]);
+        assert_eq!(pixel(3, 3), [255, 0, 255, 255]);
+    }
+}
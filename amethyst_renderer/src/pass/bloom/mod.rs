@@ -0,0 +1,277 @@
+//! Screen-space bloom post-processing pass.
+
+use derivative::Derivative;
+use gfx::memory::Typed;
+use log::error;
+
+use amethyst_error::{Error, ResultExt};
+
+use crate::{
+    error,
+    pass::util::{fullscreen_slice, target_view},
+    pipe::{
+        pass::{Pass, PassData},
+        Effect, NewEffect, Target, TargetBuilder, Targets,
+    },
+    tex::{FilterMethod, SamplerInfo, WrapMode},
+    types::{Encoder, Factory, Sampler, ShaderResourceView},
+};
+
+static VERT_SRC: &[u8] = include_bytes!("../shaders/vertex/fullscreen.glsl");
+static EXTRACT_SRC: &[u8] = include_bytes!("../shaders/fragment/bloom_extract.glsl");
+static BLUR_SRC: &[u8] = include_bytes!("../shaders/fragment/bloom_blur.glsl");
+static COMPOSITE_SRC: &[u8] = include_bytes!("../shaders/fragment/bloom_composite.glsl");
+
+/// Adds a glow around the bright parts of a scene
+///
+/// The scene has to be rendered into a separate pipeline target first, whose name is given to
+/// `new`. The pass extracts the pixels brighter than the threshold into a half resolution
+/// buffer, blurs that buffer with a separable Gaussian, and draws the scene with the blurred
+/// result added on top into the stage's target.
+///
+/// The scene target has to be built `with_hdr`. In a normalized target every color is clamped
+/// to 1.0, so a bright light can't be told apart from a white wall and the glow has nothing to
+/// work with. The blur buffers are float buffers for the same reason.
+///
+/// ```ignore
+/// Pipeline::build()
+///     .with_target(Target::named("scene").with_depth_buf(true).with_hdr())
+///     .with_stage(
+///         Stage::with_target("scene")
+///             .clear_target([0.0, 0.0, 0.0, 1.0], 1.0)
+///             .with_pass(DrawShaded::<PosNormTex>::new()),
+///     )
+///     .with_stage(Stage::with_backbuffer().with_pass(DrawBloom::new("scene")))
+/// ```
+#[derive(Derivative, Clone)]
+#[derivative(Debug)]
+pub struct DrawBloom {
+    input: String,
+    threshold: f32,
+    intensity: f32,
+    blur_passes: u32,
+    #[derivative(Debug = "ignore")]
+    state: Option<BloomState>,
+}
+
+#[derive(Clone)]
+struct BloomState {
+    extract: Effect,
+    blur: Effect,
+    ping: Target,
+    pong: Target,
+    scene: ShaderResourceView<[f32; 4]>,
+    size: (u32, u32),
+    resized: bool,
+    sampler: Sampler,
+}
+
+impl DrawBloom {
+    /// Create instance of `DrawBloom` pass reading the scene from the target named `input`
+    pub fn new<N: Into<String>>(input: N) -> Self {
+        DrawBloom {
+            input: input.into(),
+            threshold: 0.8,
+            intensity: 1.0,
+            blur_passes: 2,
+            state: None,
+        }
+    }
+
+    /// Set the brightness above which pixels start to glow, 0.8 by default.
+    ///
+    /// Brightness is the luminance of the pixel, so 1.0 is white.
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Set how strongly the glow is added to the scene, 1.0 by default.
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    /// Set how often the horizontal and vertical blur are applied, 2 by default.
+    ///
+    /// More passes spread the glow further at the cost of fill rate.
+    pub fn with_blur_passes(mut self, blur_passes: u32) -> Self {
+        self.blur_passes = blur_passes;
+        self
+    }
+}
+
+impl<'a> PassData<'a> for DrawBloom {
+    type Data = ();
+}
+
+impl Pass for DrawBloom {
+    fn compile(&mut self, mut effect: NewEffect<'_>) -> Result<Effect, Error> {
+        use gfx::Factory;
+
+        let input = effect
+            .target(&self.input)
+            .ok_or_else(|| error::Error::NoSuchTarget(self.input.clone()))?;
+        let scene = target_view(input)
+            .ok_or_else(|| error::Error::TargetNotReadable(self.input.clone()))?;
+        let size = input.size();
+        let (ping, pong) = bloom_targets(effect.factory, size)?;
+
+        let extract = effect
+            .simple_into(&ping, VERT_SRC, EXTRACT_SRC)
+            .with_texture("source")
+            .with_raw_global("threshold")
+            .with_output("color", None)
+            .build()?;
+        let blur = effect
+            .simple_into(&ping, VERT_SRC, BLUR_SRC)
+            .with_texture("source")
+            .with_raw_global("direction")
+            .with_output("color", None)
+            .build()?;
+        let sampler = effect
+            .factory
+            .create_sampler(SamplerInfo::new(FilterMethod::Bilinear, WrapMode::Clamp));
+
+        self.state = Some(BloomState {
+            extract,
+            blur,
+            ping,
+            pong,
+            scene,
+            size,
+            resized: false,
+            sampler,
+        });
+
+        effect
+            .simple(VERT_SRC, COMPOSITE_SRC)
+            .with_texture("scene")
+            .with_texture("bloom")
+            .with_raw_global("intensity")
+            .with_output("color", None)
+            .build()
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        mut factory: Factory,
+        (): <Self as PassData<'a>>::Data,
+    ) {
+        let state = match self.state {
+            Some(ref mut state) => state,
+            None => return,
+        };
+
+        if state.resized {
+            match bloom_targets(&mut factory, state.size) {
+                Ok((ping, pong)) => {
+                    state.ping = ping;
+                    state.pong = pong;
+                    state.extract.retarget(&state.ping);
+                    state.resized = false;
+                }
+                Err(err) => {
+                    error!("Failed to resize the bloom targets: {}", err);
+                    return;
+                }
+            }
+        }
+
+        let scene = state.scene.raw().clone();
+
+        // Bright pass into `ping`.
+        state.extract.data.textures.push(scene.clone());
+        state.extract.data.samplers.push(state.sampler.clone());
+        state.extract.update_global("threshold", self.threshold);
+        state.extract.draw(&fullscreen_slice(), encoder);
+        state.extract.clear();
+
+        // Blur back and forth between `ping` and `pong`, ending up in `ping` again.
+        let texel = (
+            1.0 / state.ping.size().0 as f32,
+            1.0 / state.ping.size().1 as f32,
+        );
+        for _ in 0..self.blur_passes {
+            let BloomState {
+                ref mut blur,
+                ref ping,
+                ref pong,
+                ref sampler,
+                ..
+            } = *state;
+            blur_into(blur, encoder, ping, pong, [texel.0, 0.0], sampler);
+            blur_into(blur, encoder, pong, ping, [0.0, texel.1], sampler);
+        }
+
+        let bloom = state.ping.color_buf(0).and_then(|cb| cb.as_input.as_ref());
+        let bloom = match bloom {
+            Some(bloom) => bloom.raw().clone(),
+            None => return,
+        };
+        effect.data.textures.push(scene);
+        effect.data.samplers.push(state.sampler.clone());
+        effect.data.textures.push(bloom);
+        effect.data.samplers.push(state.sampler.clone());
+        effect.update_global("intensity", self.intensity);
+        effect.draw(&fullscreen_slice(), encoder);
+        effect.clear();
+    }
+
+    fn new_targets(&mut self, targets: &Targets) {
+        let state = match self.state {
+            Some(ref mut state) => state,
+            None => return,
+        };
+        let input = match targets.get(&self.input) {
+            Some(input) => input,
+            None => {
+                error!("Bloom input target {:?} not found!", self.input);
+                return;
+            }
+        };
+        if let Some(scene) = target_view(input) {
+            state.scene = scene;
+        }
+        if input.size() != state.size {
+            state.size = input.size();
+            state.resized = true;
+        }
+    }
+}
+
+/// Creates the two half resolution buffers the bright pass is blurred in.
+fn bloom_targets(factory: &mut Factory, size: (u32, u32)) -> Result<(Target, Target), Error> {
+    let half = ((size.0 / 2).max(1), (size.1 / 2).max(1));
+    let (_, ping) = TargetBuilder::new("bloom_ping")
+        .with_hdr()
+        .build(factory, half)
+        .with_context(|_| error::Error::CreateTextureError)?;
+    let (_, pong) = TargetBuilder::new("bloom_pong")
+        .with_hdr()
+        .build(factory, half)
+        .with_context(|_| error::Error::CreateTextureError)?;
+    Ok((ping, pong))
+}
+
+fn blur_into(
+    effect: &mut Effect,
+    encoder: &mut Encoder,
+    source: &Target,
+    dest: &Target,
+    direction: [f32; 2],
+    sampler: &Sampler,
+) {
+    let source = match source.color_buf(0).and_then(|cb| cb.as_input.as_ref()) {
+        Some(source) => source.raw().clone(),
+        None => return,
+    };
+    effect.retarget(dest);
+    effect.data.textures.push(source);
+    effect.data.samplers.push(sampler.clone());
+    effect.update_global("direction", direction);
+    effect.draw(&fullscreen_slice(), encoder);
+    effect.clear();
+}
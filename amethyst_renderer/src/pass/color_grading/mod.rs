@@ -0,0 +1,209 @@
+//! Color grading post-processing pass.
+
+use derivative::Derivative;
+use gfx::memory::Typed;
+use log::error;
+
+use amethyst_assets::AssetStorage;
+use amethyst_core::specs::prelude::Read;
+use amethyst_error::Error;
+
+use crate::{
+    error,
+    formats::{TextureData, TextureMetadata},
+    pass::util::{fullscreen_slice, target_view},
+    pipe::{
+        pass::{Pass, PassData},
+        Effect, NewEffect, Targets,
+    },
+    tex::{FilterMethod, SamplerInfo, Texture, TextureHandle, WrapMode},
+    types::{Encoder, Factory, Sampler, ShaderResourceView},
+};
+
+static VERT_SRC: &[u8] = include_bytes!("../shaders/vertex/fullscreen.glsl");
+static FRAG_SRC: &[u8] = include_bytes!("../shaders/fragment/color_grading.glsl");
+
+/// Grades the colors of a target through a lookup table, for the stylized look of a scene
+///
+/// The lookup table is a 3D texture of `N` cubed colors unwrapped into a 2D texture `N * N`
+/// texels wide and `N` high: `N` slices of `N` by `N` texels lie side by side, blue selecting the
+/// slice, red running across each slice from the left and green down from the top row. This is
+/// the layout most grading tools export, and `neutral_lut` creates one. A graded copy of a
+/// screenshot of the neutral table in an image editor gives the table of that grade.
+///
+/// Load the table with `TextureMetadata::unorm()`, as its texels are the sRGB colors each sRGB
+/// input color is mapped onto. The pass samples it with its own bilinear filter.
+///
+/// Like `DrawTonemap`, the pass reads the linear colors of the scene from the target named in
+/// `new`, so add it after tonemapping, which maps them into the `[0, 1]` range the table covers.
+/// Colors are encoded like `DrawSkyBox` encodes the sky, see `with_srgb_output`.
+///
+/// ```ignore
+/// Stage::with_backbuffer()
+///     .with_pass(DrawColorGrading::new("tonemapped", lut).with_amount(0.8))
+///     .with_pass(DrawUi::new())
+/// ```
+#[derive(Derivative, Clone)]
+#[derivative(Debug)]
+pub struct DrawColorGrading {
+    input: String,
+    lut: TextureHandle,
+    amount: f32,
+    srgb_output: Option<bool>,
+    #[derivative(Debug = "ignore")]
+    source: Option<(ShaderResourceView<[f32; 4]>, Sampler)>,
+}
+
+impl DrawColorGrading {
+    /// Create instance of `DrawColorGrading` pass grading the target named `input` through the
+    /// lookup table `lut`
+    pub fn new<N: Into<String>>(input: N, lut: TextureHandle) -> Self {
+        DrawColorGrading {
+            input: input.into(),
+            lut,
+            amount: 1.0,
+            srgb_output: None,
+            source: None,
+        }
+    }
+
+    /// Set how much of the grade is applied, from `0.0` for the input colors to `1.0` for the
+    /// colors of the lookup table, which is the default.
+    pub fn with_amount(mut self, amount: f32) -> Self {
+        self.amount = amount;
+        self
+    }
+
+    /// Whether to encode the graded colors to sRGB before writing them, instead of deciding by
+    /// the target.
+    pub fn with_srgb_output(mut self, srgb: bool) -> Self {
+        self.srgb_output = Some(srgb);
+        self
+    }
+
+    /// Creates the lookup table of `size` cubed colors that maps every color onto itself.
+    pub fn neutral_lut(size: u16) -> TextureData {
+        let size = size.max(2);
+        let last = f32::from(size - 1);
+        let channel = |i: u16| (f32::from(i) / last * 255.0).round() as u8;
+        let mut pixels = Vec::with_capacity(size as usize * size as usize * size as usize * 4);
+        for green in 0..size {
+            for blue in 0..size {
+                for red in 0..size {
+                    pixels.extend_from_slice(&[channel(red), channel(green), channel(blue), 255]);
+                }
+            }
+        }
+        TextureData::U8(
+            pixels,
+            TextureMetadata::unorm().with_size(size * size, size),
+        )
+    }
+}
+
+impl<'a> PassData<'a> for DrawColorGrading {
+    type Data = Read<'a, AssetStorage<Texture>>;
+}
+
+impl Pass for DrawColorGrading {
+    fn compile(&mut self, effect: NewEffect<'_>) -> Result<Effect, Error> {
+        use gfx::Factory;
+
+        let input = effect
+            .target(&self.input)
+            .ok_or_else(|| error::Error::NoSuchTarget(self.input.clone()))?;
+        let view = target_view(input)
+            .ok_or_else(|| error::Error::TargetNotReadable(self.input.clone()))?;
+        let sampler = effect
+            .factory
+            .create_sampler(SamplerInfo::new(FilterMethod::Bilinear, WrapMode::Clamp));
+        self.source = Some((view, sampler));
+
+        let mut builder = effect.simple(VERT_SRC, FRAG_SRC);
+        builder
+            .with_texture("source")
+            .with_texture("lut")
+            .with_raw_global("lut_size")
+            .with_raw_global("amount")
+            .with_raw_global("encode_srgb")
+            .with_output("color", None);
+        if let Some(srgb) = self.srgb_output {
+            builder.with_srgb_output(srgb);
+        }
+        builder.build()
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        _factory: Factory,
+        tex_storage: <Self as PassData<'a>>::Data,
+    ) {
+        let (view, sampler) = match self.source {
+            Some(ref source) => source,
+            None => return,
+        };
+        let lut = match tex_storage.get(&self.lut) {
+            Some(lut) => lut,
+            None => return,
+        };
+
+        effect.add_raw_texture(view.raw(), sampler);
+        // The table has to be filtered linearly, whichever sampler it was loaded with.
+        effect.add_raw_texture(lut.view(), sampler);
+        let encode_srgb = if effect.srgb_output() { 1.0 } else { 0.0 };
+        effect.update_global("lut_size", lut.size().1.max(2) as f32);
+        effect.update_global("amount", self.amount);
+        effect.update_global("encode_srgb", encode_srgb);
+        effect.draw(&fullscreen_slice(), encoder);
+        effect.clear();
+    }
+
+    fn new_targets(&mut self, targets: &Targets) {
+        match (
+            targets.get(&self.input).and_then(target_view),
+            self.source.as_mut(),
+        ) {
+            (Some(view), Some(source)) => source.0 = view,
+            (None, _) => error!("Color grading input target {:?} not found!", self.input),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DrawColorGrading;
+    use crate::formats::TextureData;
+
+    #[test]
+    fn neutral_lut_follows_the_documented_layout() {
+        let size = 16;
+        let pixels = match DrawColorGrading::neutral_lut(size as u16) {
+            TextureData::U8(pixels, metadata) => {
+                assert_eq!(
+                    metadata.size,
+                    Some((size as u16 * size as u16, size as u16))
+                );
+                pixels
+            }
+            _ => panic!("Expected 8-bit texture data"),
+        };
+        assert_eq!(pixels.len(), size * size * size * 4);
+
+        let channel = |i: usize| (i as f32 / (size - 1) as f32 * 255.0).round() as u8;
+        for &(red, green, blue) in &[(0, 0, 0), (15, 15, 15), (3, 8, 12), (14, 1, 5)] {
+            // Blue selects the slice, red runs across it and green down from the top row.
+            let x = blue * size + red;
+            let texel = (green * size * size + x) * 4;
+            assert_eq!(
+                pixels[texel..texel + 4],
+                [channel(red), channel(green), channel(blue), 255],
+                "texel ({}, {})",
+                x,
+                green
+            );
+        }
+    }
+}
@@ -4,7 +4,7 @@ use std::marker::PhantomData;
 
 use derivative::Derivative;
 use gfx::{pso::buffer::ElemStride, Primitive};
-use log::{debug, trace};
+use log::{debug, error, trace};
 
 use amethyst_core::{
     nalgebra as na,
@@ -17,7 +17,9 @@ use crate::{
     cam::{ActiveCamera, Camera},
     debug_drawing::{DebugLine, DebugLines, DebugLinesComponent},
     mesh::Mesh,
-    pass::util::{get_camera, set_attribute_buffers, set_vertex_args, setup_vertex_args},
+    pass::util::{
+        build_pass_mesh, get_camera, set_attribute_buffers, set_vertex_args, setup_vertex_args,
+    },
     pipe::{
         pass::{Pass, PassData},
         DepthMode, Effect, NewEffect,
@@ -30,6 +32,7 @@ use crate::{
 use super::*;
 
 /// Parameters for renderer of debug lines. The params affect all lines.
+#[derive(Clone, Copy, Debug)]
 pub struct DebugLinesParams {
     /// Width of lines in units, default is 1.0 / 400.0 units
     pub line_width: f32,
@@ -139,9 +142,19 @@ where
 
         effect.update_global("line_width", lines_params.line_width);
 
-        let mesh = Mesh::build(debug_lines)
-            .build(&mut factory)
-            .expect("Failed to create debug lines mesh");
+        let mesh = match build_pass_mesh(
+            Mesh::build(debug_lines),
+            &mut factory,
+            "DrawDebugLines",
+            "lines",
+        ) {
+            Ok(mesh) => mesh,
+            Err(err) => {
+                error!("{}", err);
+                effect.clear();
+                return;
+            }
+        };
 
         if !set_attribute_buffers(effect, &mesh, &[V::QUERIED_ATTRIBUTES]) {
             effect.clear();
@@ -0,0 +1,196 @@
+//! Decal projection pass.
+
+use std::mem;
+
+use derivative::Derivative;
+use gfx::{memory::Typed, pso::buffer::ElemStride};
+use glsl_layout::Uniform;
+use log::error;
+
+use amethyst_assets::AssetStorage;
+use amethyst_core::{
+    specs::prelude::{Component, DenseVecStorage, Join, Read, ReadStorage},
+    transform::GlobalTransform,
+};
+use amethyst_error::Error;
+
+use crate::{
+    cam::{ActiveCamera, Camera},
+    error,
+    hidden::{Hidden, HiddenPropagate},
+    mesh::Mesh,
+    pass::util::{add_texture, build_cube_mesh, get_camera, set_view_args, CubeVertex, ViewArgs},
+    pipe::{
+        pass::{Pass, PassData},
+        Effect, NewEffect, Targets,
+    },
+    tex::{Texture, TextureHandle},
+    transparent::{ColorMask, ALPHA},
+    types::{Encoder, Factory, ShaderResourceView},
+    vertex::VertexFormat,
+};
+
+static VERT_SRC: &[u8] = include_bytes!("../shaders/vertex/decal.glsl");
+static FRAG_SRC: &[u8] = include_bytes!("../shaders/fragment/decal.glsl");
+
+/// Texture projected onto the scene by `DrawDecals`, e.g. a bullet hole or a sign
+///
+/// The decal covers the box from -1 to 1 along every axis of the entity's `GlobalTransform`, so
+/// the transform's scale sets the half extents of the box. The texture is projected along the
+/// box's Z axis onto whatever is inside it, with U running along X and V along Y.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Decal {
+    /// Projected texture, blended using its alpha.
+    pub texture: TextureHandle,
+    /// Color the texture is multiplied with.
+    pub tint: [f32; 4],
+}
+
+impl Decal {
+    /// Creates an untinted decal.
+    pub fn new(texture: TextureHandle) -> Self {
+        Decal {
+            texture,
+            tint: [1.0; 4],
+        }
+    }
+}
+
+impl Component for Decal {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Draws `Decal`s onto the geometry drawn before
+///
+/// The pass reconstructs the position of every pixel from the depth buffer of the target named
+/// in `new`, so like for `DrawSsao` the scene has to be drawn into a target with a depth buffer
+/// first. That target can't be the one the decals are drawn into. Decals are drawn on top of
+/// everything inside their box, so keep the boxes tight around the surface they decorate.
+#[derive(Derivative, Clone)]
+#[derivative(Debug)]
+pub struct DrawDecals {
+    input: String,
+    #[derivative(Debug = "ignore")]
+    depth: Option<ShaderResourceView<f32>>,
+    mesh: Option<Mesh>,
+}
+
+impl DrawDecals {
+    /// Create instance of `DrawDecals` pass reading depth from the target named `input`
+    pub fn new<N: Into<String>>(input: N) -> Self {
+        DrawDecals {
+            input: input.into(),
+            depth: None,
+            mesh: None,
+        }
+    }
+}
+
+impl<'a> PassData<'a> for DrawDecals {
+    type Data = (
+        Read<'a, ActiveCamera>,
+        ReadStorage<'a, Camera>,
+        ReadStorage<'a, GlobalTransform>,
+        Read<'a, AssetStorage<Texture>>,
+        ReadStorage<'a, Hidden>,
+        ReadStorage<'a, HiddenPropagate>,
+        ReadStorage<'a, Decal>,
+    );
+}
+
+impl Pass for DrawDecals {
+    fn compile(&mut self, mut effect: NewEffect<'_>) -> Result<Effect, Error> {
+        let input = effect
+            .target(&self.input)
+            .ok_or_else(|| error::Error::NoSuchTarget(self.input.clone()))?;
+        let depth = input
+            .depth_buf()
+            .and_then(|db| db.as_input.clone())
+            .ok_or_else(|| error::Error::TargetNotReadable(self.input.clone()))?;
+        self.depth = Some(depth);
+
+        // Its faces point inwards, so only the ones behind the viewer are drawn from either side
+        // of the box.
+        let mesh = build_cube_mesh(&mut effect.factory, "DrawDecals")?;
+        let mut effect = effect
+            .simple(VERT_SRC, FRAG_SRC)
+            .with_raw_vertex_buffer(CubeVertex::ATTRIBUTES, CubeVertex::size() as ElemStride, 0)
+            .with_raw_constant_buffer(
+                "ViewArgs",
+                mem::size_of::<<ViewArgs as Uniform>::Std140>(),
+                1,
+            )
+            .with_raw_global("model")
+            .with_raw_global("inv_model")
+            .with_raw_global("inv_proj")
+            .with_raw_global("inv_view")
+            .with_raw_global("tint")
+            .with_texture("albedo")
+            .with_texture("depth")
+            .with_blended_output("color", ColorMask::all(), ALPHA, None)
+            .build()?;
+
+        // The box is bound once and only the textures change between draws.
+        if let Some(vbuf) = mesh.buffer(CubeVertex::ATTRIBUTES) {
+            effect.add_vertex_buffer(vbuf);
+        }
+        self.mesh = Some(mesh);
+        Ok(effect)
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        _factory: Factory,
+        (active, camera, global, tex_storage, hidden, hidden_prop, decal): <Self as PassData<
+            'a,
+        >>::Data,
+    ) {
+        let (camera, depth, mesh) = match (
+            get_camera(active, &camera, &global),
+            self.depth.as_ref(),
+            self.mesh.as_ref(),
+        ) {
+            (Some(camera), Some(depth), Some(mesh)) => (camera, depth, mesh),
+            _ => return,
+        };
+
+        set_view_args(effect, encoder, Some(camera));
+        let (camera, transform) = camera;
+        let inv_proj = match camera.proj.try_inverse() {
+            Some(inv_proj) => inv_proj,
+            None => return,
+        };
+        effect.update_global("inv_proj", Into::<[[f32; 4]; 4]>::into(inv_proj));
+        effect.update_global("inv_view", Into::<[[f32; 4]; 4]>::into(transform.0));
+
+        for (decal, global, _, _) in (&decal, &global, !&hidden, !&hidden_prop).join() {
+            let (texture, inv_model) =
+                match (tex_storage.get(&decal.texture), global.0.try_inverse()) {
+                    (Some(texture), Some(inv_model)) => (texture, inv_model),
+                    _ => continue,
+                };
+            add_texture(effect, texture);
+            effect.add_raw_texture(depth.raw(), texture.sampler());
+            effect.update_global("model", Into::<[[f32; 4]; 4]>::into(global.0));
+            effect.update_global("inv_model", Into::<[[f32; 4]; 4]>::into(inv_model));
+            effect.update_global("tint", decal.tint);
+
+            effect.draw(mesh.slice(), encoder);
+
+            effect.clear_textures();
+        }
+    }
+
+    fn new_targets(&mut self, targets: &Targets) {
+        match targets
+            .get(&self.input)
+            .and_then(|target| target.depth_buf())
+            .and_then(|db| db.as_input.clone())
+        {
+            Some(depth) => self.depth = Some(depth),
+            None => error!("Decal depth target {:?} not found!", self.input),
+        }
+    }
+}
@@ -0,0 +1,227 @@
+//! G-buffer filling pass.
+
+use std::marker::PhantomData;
+
+use derivative::Derivative;
+use gfx::pso::buffer::ElemStride;
+
+use amethyst_assets::AssetStorage;
+use amethyst_core::{
+    specs::prelude::{Join, Read, ReadExpect, ReadStorage},
+    transform::GlobalTransform,
+};
+use amethyst_error::Error;
+
+use crate::{
+    cam::{ActiveCamera, Camera, CameraTarget, StereoCamera, Viewport},
+    culling::{Aabb, BoundingSphere},
+    hidden::{Hidden, HiddenPropagate},
+    mesh::{LodMesh, LodView, Mesh, MeshHandle},
+    mtl::{Material, MaterialDefaults, MissingTextures},
+    pass::util::{
+        culling_frustum, draw_mesh, get_cameras, is_culled, select_lod, set_alpha_cutoff,
+        set_emission, set_view, setup_alpha_cutoff, setup_emission, setup_textures,
+        setup_vertex_args,
+    },
+    pipe::{
+        pass::{Pass, PassData},
+        DepthMode, Effect, NewEffect,
+    },
+    tex::Texture,
+    transparent::Transparent,
+    types::{Encoder, Factory},
+    vertex::{Normal, Position, Query, TexCoord},
+    visibility::Visibility,
+    Rgba,
+};
+
+use super::*;
+
+/// Draw the surface attributes of opaque meshes into a G-buffer
+///
+/// The pass has to draw into a target with `GBUFFER_COLOR_BUFS` color buffers and a depth
+/// buffer, which `DrawDeferredLighting` reads afterwards. Only the `Visibility` resource's
+/// unordered entities are drawn, or every entity without a `Transparent` component if there is
+/// no such resource; transparent surfaces have to be drawn by a forward pass after the lighting.
+///
+/// # Type Parameters:
+///
+/// * `V`: `VertexFormat`
+#[derive(Derivative, Clone, Debug, PartialEq)]
+#[derivative(Default(bound = "V: Query<(Position, Normal, TexCoord)>"))]
+pub struct DrawGBuffer<V> {
+    _pd: PhantomData<V>,
+    frustum_culling: bool,
+}
+
+impl<V> DrawGBuffer<V>
+where
+    V: Query<(Position, Normal, TexCoord)>,
+{
+    /// Create instance of `DrawGBuffer` pass
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Skip entities whose `BoundingSphere` or `Aabb` is out of the camera's view.
+    ///
+    /// Entities without bounds are always drawn.
+    pub fn with_frustum_culling(mut self) -> Self {
+        self.frustum_culling = true;
+        self
+    }
+}
+
+impl<'a, V> PassData<'a> for DrawGBuffer<V>
+where
+    V: Query<(Position, Normal, TexCoord)>,
+{
+    type Data = (
+        Read<'a, ActiveCamera>,
+        ReadStorage<'a, Camera>,
+        ReadStorage<'a, Viewport>,
+        ReadStorage<'a, CameraTarget>,
+        ReadStorage<'a, StereoCamera>,
+        Read<'a, AssetStorage<Mesh>>,
+        Read<'a, AssetStorage<Texture>>,
+        ReadExpect<'a, MaterialDefaults>,
+        ReadExpect<'a, MissingTextures>,
+        Option<Read<'a, Visibility>>,
+        ReadStorage<'a, Hidden>,
+        ReadStorage<'a, HiddenPropagate>,
+        ReadStorage<'a, Transparent>,
+        ReadStorage<'a, MeshHandle>,
+        ReadStorage<'a, LodMesh>,
+        ReadStorage<'a, Material>,
+        ReadStorage<'a, GlobalTransform>,
+        ReadStorage<'a, Rgba>,
+        ReadStorage<'a, BoundingSphere>,
+        ReadStorage<'a, Aabb>,
+    );
+}
+
+impl<V> Pass for DrawGBuffer<V>
+where
+    V: Query<(Position, Normal, TexCoord)>,
+{
+    fn compile(&mut self, effect: NewEffect<'_>) -> Result<Effect, Error> {
+        let mut builder = effect.simple(GEOMETRY_VERT_SRC, GEOMETRY_FRAG_SRC);
+        builder.with_raw_vertex_buffer(V::QUERIED_ATTRIBUTES, V::size() as ElemStride, 0);
+        setup_vertex_args(&mut builder);
+        setup_emission(&mut builder);
+        setup_alpha_cutoff(&mut builder);
+        setup_textures(&mut builder, &TEXTURES);
+        builder.with_outputs(&OUTPUTS, Some(DepthMode::LessEqualWrite));
+        builder.with_two_sided_variant();
+        builder.build()
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        _factory: Factory,
+        (
+            active,
+            camera,
+            viewports,
+            camera_targets,
+            stereo_cameras,
+            mesh_storage,
+            tex_storage,
+            material_defaults,
+            missing,
+            visibility,
+            hidden,
+            hidden_prop,
+            transparent,
+            mesh,
+            lod_mesh,
+            material,
+            global,
+            rgba,
+            bounding_sphere,
+            aabb,
+        ): <Self as PassData<'a>>::Data,
+    ) {
+        let cameras = get_cameras(
+            active,
+            &camera,
+            &viewports,
+            &camera_targets,
+            &stereo_cameras,
+            &global,
+            effect.target_name(),
+        );
+        for (index, view) in cameras.iter().enumerate() {
+            let lod_view = LodView::new(effect.target_name(), index);
+            let frustum = culling_frustum(self.frustum_culling, view);
+            let view = set_view(effect, view);
+            let camera = view.get();
+
+            let mut draw =
+                |mesh: &MeshHandle,
+                 lod: Option<&LodMesh>,
+                 material: &Material,
+                 global: &GlobalTransform,
+                 rgba: Option<&Rgba>,
+                 bounds: (Option<&BoundingSphere>, Option<&Aabb>)| {
+                    if is_culled(&frustum, Some(global), bounds) {
+                        return;
+                    }
+                    let mesh = select_lod(mesh, lod, lod_view, camera, Some(global));
+                    set_emission(effect, Some(material));
+                    set_alpha_cutoff(effect, Some(material));
+                    draw_mesh(
+                        encoder,
+                        effect,
+                        false,
+                        mesh_storage.get(mesh),
+                        None,
+                        &tex_storage,
+                        Some(material),
+                        &material_defaults,
+                        &missing,
+                        rgba,
+                        camera,
+                        Some(global),
+                        &[V::QUERIED_ATTRIBUTES],
+                        &TEXTURES,
+                    );
+                };
+
+            match visibility {
+                None => {
+                    for ((mesh, lod), material, global, rgba, bounds, _, _, _) in (
+                        (&mesh, lod_mesh.maybe()),
+                        &material,
+                        &global,
+                        rgba.maybe(),
+                        (bounding_sphere.maybe(), aabb.maybe()),
+                        !&transparent,
+                        !&hidden,
+                        !&hidden_prop,
+                    )
+                        .join()
+                    {
+                        draw(mesh, lod, material, global, rgba, bounds);
+                    }
+                }
+                Some(ref visibility) => {
+                    for ((mesh, lod), material, global, rgba, bounds, _) in (
+                        (&mesh, lod_mesh.maybe()),
+                        &material,
+                        &global,
+                        rgba.maybe(),
+                        (bounding_sphere.maybe(), aabb.maybe()),
+                        &visibility.visible_unordered,
+                    )
+                        .join()
+                    {
+                        draw(mesh, lod, material, global, rgba, bounds);
+                    }
+                }
+            }
+        }
+    }
+}
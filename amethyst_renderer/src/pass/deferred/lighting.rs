@@ -0,0 +1,159 @@
+//! Fullscreen lighting pass reading the G-buffer.
+
+use derivative::Derivative;
+use gfx::memory::Typed;
+use log::error;
+
+use amethyst_core::{
+    specs::prelude::{Read, ReadStorage},
+    transform::GlobalTransform,
+};
+use amethyst_error::Error;
+
+use crate::{
+    cam::{ActiveCamera, Camera},
+    error,
+    light::Light,
+    pass::{
+        shaded_util::{set_light_args, setup_light_buffers},
+        util::{fullscreen_slice, get_camera},
+    },
+    pipe::{
+        pass::{Pass, PassData},
+        Effect, NewEffect, Target, Targets,
+    },
+    resources::AmbientColor,
+    tex::{FilterMethod, SamplerInfo, WrapMode},
+    types::{Encoder, Factory, Sampler, ShaderResourceView},
+};
+
+use super::*;
+
+/// Light the G-buffer drawn by `DrawGBuffer`
+///
+/// Every pixel covered by the G-buffer is lit once by the point, directional and spot lights and
+/// the ambient light, like `DrawShaded` would light it. Pixels nothing was drawn to are
+/// discarded, so the pass should follow a clear of its own target or a sky pass.
+#[derive(Derivative, Clone)]
+#[derivative(Debug)]
+pub struct DrawDeferredLighting {
+    input: String,
+    #[derivative(Debug = "ignore")]
+    sources: Option<GBufferViews>,
+}
+
+#[derive(Clone)]
+struct GBufferViews {
+    colors: Vec<ShaderResourceView<[f32; 4]>>,
+    depth: ShaderResourceView<f32>,
+    sampler: Sampler,
+}
+
+impl DrawDeferredLighting {
+    /// Create instance of `DrawDeferredLighting` pass reading the G-buffer target named `input`
+    pub fn new<N: Into<String>>(input: N) -> Self {
+        DrawDeferredLighting {
+            input: input.into(),
+            sources: None,
+        }
+    }
+}
+
+/// Returns the readable views of a G-buffer target's color and depth buffers.
+fn gbuffer_views(
+    target: &Target,
+) -> Option<(Vec<ShaderResourceView<[f32; 4]>>, ShaderResourceView<f32>)> {
+    let colors = (0..GBUFFER_COLOR_BUFS)
+        .map(|i| target.color_buf(i).and_then(|cb| cb.as_input.clone()))
+        .collect::<Option<Vec<_>>>()?;
+    let depth = target.depth_buf().and_then(|db| db.as_input.clone())?;
+    Some((colors, depth))
+}
+
+impl<'a> PassData<'a> for DrawDeferredLighting {
+    type Data = (
+        Read<'a, ActiveCamera>,
+        ReadStorage<'a, Camera>,
+        Read<'a, AmbientColor>,
+        ReadStorage<'a, GlobalTransform>,
+        ReadStorage<'a, Light>,
+    );
+}
+
+impl Pass for DrawDeferredLighting {
+    fn compile(&mut self, effect: NewEffect<'_>) -> Result<Effect, Error> {
+        use gfx::Factory;
+
+        let input = effect
+            .target(&self.input)
+            .ok_or_else(|| error::Error::NoSuchTarget(self.input.clone()))?;
+        let (colors, depth) = gbuffer_views(input)
+            .ok_or_else(|| error::Error::TargetNotReadable(self.input.clone()))?;
+        let sampler = effect
+            .factory
+            .create_sampler(SamplerInfo::new(FilterMethod::Scale, WrapMode::Clamp));
+        self.sources = Some(GBufferViews {
+            colors,
+            depth,
+            sampler,
+        });
+
+        let mut builder = effect.simple(LIGHTING_VERT_SRC, LIGHTING_FRAG_SRC);
+        setup_light_buffers(&mut builder);
+        for name in &INPUTS {
+            builder.with_texture(name);
+        }
+        builder
+            .with_texture("gbuffer_depth")
+            .with_raw_global("inv_proj")
+            .with_raw_global("inv_view")
+            .with_output("color", None);
+        builder.build()
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        _factory: Factory,
+        (active, camera, ambient, global, light): <Self as PassData<'a>>::Data,
+    ) {
+        let sources = match self.sources {
+            Some(ref sources) => sources,
+            None => return,
+        };
+        let camera = get_camera(active, &camera, &global);
+        let (inv_proj, transform) = match camera {
+            Some((cam, transform)) => match cam.proj.try_inverse() {
+                Some(inv_proj) => (inv_proj, transform),
+                None => return,
+            },
+            None => return,
+        };
+
+        set_light_args(effect, encoder, &light, &global, &ambient, camera);
+        effect.update_global("inv_proj", Into::<[[f32; 4]; 4]>::into(inv_proj));
+        effect.update_global("inv_view", Into::<[[f32; 4]; 4]>::into(transform.0));
+        for view in &sources.colors {
+            effect.add_raw_texture(view.raw(), &sources.sampler);
+        }
+        effect.add_raw_texture(sources.depth.raw(), &sources.sampler);
+
+        effect.draw(&fullscreen_slice(), encoder);
+        effect.clear();
+    }
+
+    fn new_targets(&mut self, targets: &Targets) {
+        match (
+            targets.get(&self.input).and_then(gbuffer_views),
+            self.sources.as_mut(),
+        ) {
+            (Some((colors, depth)), Some(sources)) => {
+                sources.colors = colors;
+                sources.depth = depth;
+            }
+            (None, _) => error!("G-buffer target {:?} not found!", self.input),
+            _ => {}
+        }
+    }
+}
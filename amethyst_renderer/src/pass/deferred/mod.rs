@@ -0,0 +1,49 @@
+//! Deferred shading passes.
+//!
+//! `DrawGBuffer` draws the surface attributes of the scene into a target with several color
+//! buffers, the G-buffer, and `DrawDeferredLighting` then lights every pixel of it once, no
+//! matter how many meshes overlap. The G-buffer target needs `GBUFFER_COLOR_BUFS` color buffers
+//! and a depth buffer:
+//!
+//! ```ignore
+//! Pipeline::build()
+//!     .with_target(
+//!         Target::named("gbuffer")
+//!             .with_num_color_bufs(GBUFFER_COLOR_BUFS)
+//!             .with_depth_buf(true),
+//!     )
+//!     .with_stage(
+//!         Stage::with_target("gbuffer")
+//!             .clear_target([0.0, 0.0, 0.0, 0.0], 1.0)
+//!             .with_pass(DrawGBuffer::<PosNormTex>::new()),
+//!     )
+//!     .with_stage(
+//!         Stage::with_backbuffer()
+//!             .clear_target([0.0, 0.0, 0.0, 1.0], 1.0)
+//!             .with_pass(DrawDeferredLighting::new("gbuffer")),
+//!     )
+//! ```
+
+pub use self::{geometry::DrawGBuffer, lighting::DrawDeferredLighting};
+
+mod geometry;
+mod lighting;
+
+use crate::pass::util::TextureType;
+
+static GEOMETRY_VERT_SRC: &[u8] = include_bytes!("../shaders/vertex/basic.glsl");
+static GEOMETRY_FRAG_SRC: &[u8] = include_bytes!("../shaders/fragment/gbuffer.glsl");
+static LIGHTING_VERT_SRC: &[u8] = include_bytes!("../shaders/vertex/fullscreen.glsl");
+static LIGHTING_FRAG_SRC: &[u8] = include_bytes!("../shaders/fragment/deferred_lighting.glsl");
+
+/// Number of color buffers of the G-buffer target.
+///
+/// They hold, in order, the albedo, the world space normal mapped into the 0 to 1 range, and
+/// the emission of the nearest opaque surface of each pixel.
+pub const GBUFFER_COLOR_BUFS: usize = 3;
+
+static OUTPUTS: [&str; GBUFFER_COLOR_BUFS] = ["out_albedo", "out_normal", "out_emission"];
+static INPUTS: [&str; GBUFFER_COLOR_BUFS] =
+    ["gbuffer_albedo", "gbuffer_normal", "gbuffer_emission"];
+
+static TEXTURES: [TextureType; 2] = [TextureType::Albedo, TextureType::Emission];
@@ -0,0 +1,215 @@
+//! Depth-only drawing pass.
+
+use std::marker::PhantomData;
+
+use derivative::Derivative;
+use gfx::pso::buffer::ElemStride;
+
+use amethyst_assets::AssetStorage;
+use amethyst_core::{
+    specs::prelude::{Join, Read, ReadExpect, ReadStorage},
+    transform::GlobalTransform,
+};
+use amethyst_error::Error;
+
+use crate::{
+    cam::{ActiveCamera, Camera, CameraTarget, StereoCamera, Viewport},
+    hidden::{Hidden, HiddenPropagate},
+    mesh::{LodMesh, LodView, Mesh, MeshHandle},
+    mtl::{Material, MaterialDefaults, MissingTextures},
+    pass::util::{
+        add_textures, get_cameras, select_lod, set_alpha_cutoff, set_attribute_buffers,
+        set_vertex_args, set_view, setup_alpha_cutoff, setup_textures, setup_vertex_args,
+        TextureType,
+    },
+    pipe::{
+        pass::{Pass, PassData},
+        DepthMode, Effect, NewEffect,
+    },
+    tex::Texture,
+    transparent::{ColorMask, Transparent, REPLACE},
+    types::{Encoder, Factory},
+    vertex::{Position, Query, TexCoord},
+    visibility::Visibility,
+    Rgba,
+};
+
+use super::*;
+
+/// Draw the depth of opaque meshes without shading them
+///
+/// Running this pass before `DrawPbm::with_depth_prepass` fills the depth buffer with the
+/// nearest opaque surface of every pixel, so the expensive physically based shading then only
+/// runs once per pixel instead of once for every overlapping mesh. Both passes have to draw into
+/// the same target and see the same entities, so this pass draws every entity with a
+/// `Material` that `DrawPbm` would draw without sorting: the `Visibility` resource's unordered
+/// entities if it exists, and every entity without a `Transparent` component otherwise.
+///
+/// Fragments whose albedo alpha is below the material's `alpha_cutoff` are discarded, the same
+/// way `DrawPbm` cuts them out, so cut-out foliage doesn't hide what's behind it.
+///
+/// # Type Parameters:
+///
+/// * `V`: `VertexFormat`
+#[derive(Derivative, Clone, Debug, PartialEq)]
+#[derivative(Default(bound = "V: Query<(Position, TexCoord)>"))]
+pub struct DrawDepth<V> {
+    _pd: PhantomData<V>,
+}
+
+impl<V> DrawDepth<V>
+where
+    V: Query<(Position, TexCoord)>,
+{
+    /// Create instance of `DrawDepth` pass
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl<'a, V> PassData<'a> for DrawDepth<V>
+where
+    V: Query<(Position, TexCoord)>,
+{
+    type Data = (
+        Read<'a, ActiveCamera>,
+        ReadStorage<'a, Camera>,
+        ReadStorage<'a, Viewport>,
+        ReadStorage<'a, CameraTarget>,
+        ReadStorage<'a, StereoCamera>,
+        Read<'a, AssetStorage<Mesh>>,
+        Read<'a, AssetStorage<Texture>>,
+        ReadExpect<'a, MaterialDefaults>,
+        ReadExpect<'a, MissingTextures>,
+        Option<Read<'a, Visibility>>,
+        ReadStorage<'a, Hidden>,
+        ReadStorage<'a, HiddenPropagate>,
+        ReadStorage<'a, Transparent>,
+        ReadStorage<'a, MeshHandle>,
+        ReadStorage<'a, LodMesh>,
+        ReadStorage<'a, Material>,
+        ReadStorage<'a, GlobalTransform>,
+    );
+}
+
+impl<V> Pass for DrawDepth<V>
+where
+    V: Query<(Position, TexCoord)>,
+{
+    fn compile(&mut self, effect: NewEffect<'_>) -> Result<Effect, Error> {
+        let mut builder = effect.simple(VERT_SRC, FRAG_SRC);
+        setup_vertex_args(&mut builder);
+        setup_textures(&mut builder, &[TextureType::Albedo]);
+        setup_alpha_cutoff(&mut builder);
+        builder
+            .with_raw_vertex_buffer(V::QUERIED_ATTRIBUTES, V::size() as ElemStride, 0)
+            .with_blended_output(
+                "color",
+                ColorMask::empty(),
+                REPLACE,
+                Some(DepthMode::LessEqualWrite),
+            );
+        builder.with_two_sided_variant();
+        builder.build()
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        _factory: Factory,
+        (
+            active,
+            camera,
+            viewports,
+            camera_targets,
+            stereo_cameras,
+            mesh_storage,
+            tex_storage,
+            material_defaults,
+            missing,
+            visibility,
+            hidden,
+            hidden_prop,
+            transparent,
+            mesh,
+            lod_mesh,
+            material,
+            global,
+        ): <Self as PassData<'a>>::Data,
+    ) {
+        let cameras = get_cameras(
+            active,
+            &camera,
+            &viewports,
+            &camera_targets,
+            &stereo_cameras,
+            &global,
+            effect.target_name(),
+        );
+        for (index, view) in cameras.iter().enumerate() {
+            let lod_view = LodView::new(effect.target_name(), index);
+            let view = set_view(effect, view);
+            let camera = view.get();
+
+            let mut draw = |mesh: &MeshHandle,
+                            lod: Option<&LodMesh>,
+                            material: &Material,
+                            global: &GlobalTransform| {
+                let mesh = select_lod(mesh, lod, lod_view, camera, Some(global));
+                let mesh = match mesh_storage.get(mesh) {
+                    Some(mesh) => mesh,
+                    None => return,
+                };
+                if set_attribute_buffers(effect, mesh, &[V::QUERIED_ATTRIBUTES]) {
+                    set_vertex_args(effect, encoder, camera, global, Rgba::WHITE);
+                    set_alpha_cutoff(effect, Some(material));
+                    add_textures(
+                        effect,
+                        encoder,
+                        &tex_storage,
+                        material,
+                        &material_defaults.0,
+                        &missing,
+                        &[TextureType::Albedo],
+                    );
+                    if material.two_sided {
+                        effect.draw_two_sided(mesh.slice(), encoder);
+                    } else {
+                        effect.draw(mesh.slice(), encoder);
+                    }
+                }
+                effect.clear();
+            };
+
+            match visibility {
+                None => {
+                    for ((mesh, lod), material, global, _, _, _) in (
+                        (&mesh, lod_mesh.maybe()),
+                        &material,
+                        &global,
+                        !&transparent,
+                        !&hidden,
+                        !&hidden_prop,
+                    )
+                        .join()
+                    {
+                        draw(mesh, lod, material, global);
+                    }
+                }
+                Some(ref visibility) => {
+                    for ((mesh, lod), material, global, _) in (
+                        (&mesh, lod_mesh.maybe()),
+                        &material,
+                        &global,
+                        &visibility.visible_unordered,
+                    )
+                        .join()
+                    {
+                        draw(mesh, lod, material, global);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,8 @@
+//! Depth prepass.
+
+pub use self::interleaved::DrawDepth;
+
+mod interleaved;
+
+static VERT_SRC: &[u8] = include_bytes!("../shaders/vertex/basic.glsl");
+static FRAG_SRC: &[u8] = include_bytes!("../shaders/fragment/depth.glsl");
@@ -0,0 +1,326 @@
+//! Flat forward drawing pass that batches repeated meshes into instanced draws.
+
+use std::{collections::HashMap, marker::PhantomData};
+
+use derivative::Derivative;
+use gfx::pso::buffer::ElemStride;
+use gfx_core::state::{Blend, ColorMask};
+use glsl_layout::Uniform;
+
+use amethyst_assets::AssetStorage;
+use amethyst_core::{
+    specs::prelude::{Entity, Join, Read, ReadExpect, ReadStorage},
+    transform::GlobalTransform,
+};
+use amethyst_error::Error;
+
+use crate::{
+    cam::{ActiveCamera, Camera},
+    hidden::{Hidden, HiddenPropagate},
+    mesh::{Mesh, MeshHandle},
+    mtl::{Material, MaterialDefaults, MissingTextures},
+    pass::util::{
+        add_textures, default_transparency, get_camera, set_attribute_buffers, set_view_args,
+        setup_textures, ViewArgs,
+    },
+    pipe::{
+        pass::{Pass, PassData},
+        DepthMode, Effect, NewEffect,
+    },
+    tex::Texture,
+    types::{Encoder, Factory},
+    vertex::{Position, Query, TexCoord, VertexFormat},
+    visibility::Visibility,
+    Rgba,
+};
+
+use super::*;
+
+/// Draw mesh without lighting, using one instanced draw call per mesh and albedo texture.
+///
+/// Entities sharing the same `MeshHandle` and albedo texture are collected into a batch, and
+/// their model matrices and tints are uploaded as a per-instance vertex buffer. The texture
+/// offsets of the first material in a batch are used for the whole batch.
+///
+/// Entities in `Visibility::visible_ordered` are drawn one by one in the given order, so
+/// transparent objects still blend correctly.
+///
+/// # Type Parameters
+///
+/// * `V`: `VertexFormat`
+#[derive(Derivative, Clone, Debug, PartialEq)]
+#[derivative(Default(bound = "V: Query<(Position, TexCoord)>, Self: Pass"))]
+pub struct DrawFlatInstanced<V> {
+    _pd: PhantomData<V>,
+    #[derivative(Default(value = "default_transparency()"))]
+    transparency: Option<(ColorMask, Blend, Option<DepthMode>)>,
+}
+
+impl<V> DrawFlatInstanced<V>
+where
+    V: Query<(Position, TexCoord)>,
+    Self: Pass,
+{
+    /// Create instance of `DrawFlatInstanced` pass
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Transparency is enabled by default.
+    /// If you pass false to this function transparency will be disabled.
+    ///
+    /// If you pass true and this was disabled previously default settings will be reinstated.
+    /// If you pass true and this was already enabled this will do nothing.
+    pub fn with_transparency(mut self, input: bool) -> Self {
+        if input {
+            if self.transparency.is_none() {
+                self.transparency = default_transparency();
+            }
+        } else {
+            self.transparency = None;
+        }
+        self
+    }
+
+    /// Set transparency settings to custom values.
+    pub fn with_transparency_settings(
+        mut self,
+        mask: ColorMask,
+        blend: Blend,
+        depth: Option<DepthMode>,
+    ) -> Self {
+        self.transparency = Some((mask, blend, depth));
+        self
+    }
+}
+
+impl<'a, V> PassData<'a> for DrawFlatInstanced<V>
+where
+    V: Query<(Position, TexCoord)>,
+{
+    type Data = (
+        Read<'a, ActiveCamera>,
+        ReadStorage<'a, Camera>,
+        Read<'a, AssetStorage<Mesh>>,
+        Read<'a, AssetStorage<Texture>>,
+        ReadExpect<'a, MaterialDefaults>,
+        ReadExpect<'a, MissingTextures>,
+        Option<Read<'a, Visibility>>,
+        ReadStorage<'a, Hidden>,
+        ReadStorage<'a, HiddenPropagate>,
+        ReadStorage<'a, MeshHandle>,
+        ReadStorage<'a, Material>,
+        ReadStorage<'a, GlobalTransform>,
+        ReadStorage<'a, Rgba>,
+    );
+}
+
+impl<V> Pass for DrawFlatInstanced<V>
+where
+    V: Query<(Position, TexCoord)>,
+{
+    fn compile(&mut self, effect: NewEffect<'_>) -> Result<Effect, Error> {
+        use std::mem;
+        let mut builder = effect.simple(INSTANCED_VERT_SRC, FRAG_SRC);
+        builder
+            .with_raw_constant_buffer(
+                "ViewArgs",
+                mem::size_of::<<ViewArgs as Uniform>::Std140>(),
+                1,
+            )
+            .with_raw_vertex_buffer(V::QUERIED_ATTRIBUTES, V::size() as ElemStride, 0)
+            .with_raw_vertex_buffer(
+                ModelInstance::ATTRIBUTES,
+                ModelInstance::size() as ElemStride,
+                1,
+            );
+        setup_textures(&mut builder, &TEXTURES);
+        match self.transparency {
+            Some((mask, blend, depth)) => builder.with_blended_output("color", mask, blend, depth),
+            None => builder.with_output("color", Some(DepthMode::LessEqualWrite)),
+        };
+        builder.build()
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        mut factory: Factory,
+        (
+            active,
+            camera,
+            mesh_storage,
+            tex_storage,
+            material_defaults,
+            missing,
+            visibility,
+            hidden,
+            hidden_prop,
+            mesh,
+            material,
+            global,
+            rgba,
+        ): <Self as PassData<'a>>::Data,
+    ) {
+        let camera = get_camera(active, &camera, &global);
+        set_view_args(effect, encoder, camera);
+
+        let mut batches = InstanceBatches::default();
+
+        match visibility {
+            None => {
+                for (mesh, material, global, rgba, _, _) in (
+                    &mesh,
+                    &material,
+                    &global,
+                    rgba.maybe(),
+                    !&hidden,
+                    !&hidden_prop,
+                )
+                    .join()
+                {
+                    batches.push(mesh, material, global, rgba);
+                }
+            }
+            Some(ref visibility) => {
+                for (mesh, material, global, rgba, _) in (
+                    &mesh,
+                    &material,
+                    &global,
+                    rgba.maybe(),
+                    &visibility.visible_unordered,
+                )
+                    .join()
+                {
+                    batches.push(mesh, material, global, rgba);
+                }
+            }
+        }
+
+        for batch in batches.batches.values() {
+            draw_instances::<V>(
+                encoder,
+                effect,
+                &mut factory,
+                mesh_storage.get(batch.mesh),
+                &tex_storage,
+                batch.material,
+                &material_defaults,
+                &missing,
+                &batch.data,
+            );
+        }
+
+        if let Some(ref visibility) = visibility {
+            let ordered = visibility.visible_ordered.iter().filter_map(|e: &Entity| {
+                Some((mesh.get(*e)?, material.get(*e)?, global.get(*e)?, rgba.get(*e)))
+            });
+            for (mesh, material, global, rgba) in ordered {
+                let mut data = Vec::new();
+                push_instance(&mut data, global, rgba);
+                draw_instances::<V>(
+                    encoder,
+                    effect,
+                    &mut factory,
+                    mesh_storage.get(mesh),
+                    &tex_storage,
+                    material,
+                    &material_defaults,
+                    &missing,
+                    &data,
+                );
+            }
+        }
+    }
+}
+
+/// Instance data grouped by mesh and albedo texture.
+#[derive(Default)]
+struct InstanceBatches<'a> {
+    batches: HashMap<(u32, u32), InstanceBatch<'a>>,
+}
+
+struct InstanceBatch<'a> {
+    mesh: &'a MeshHandle,
+    material: &'a Material,
+    data: Vec<ModelInstance>,
+}
+
+impl<'a> InstanceBatches<'a> {
+    fn push(
+        &mut self,
+        mesh: &'a MeshHandle,
+        material: &'a Material,
+        global: &GlobalTransform,
+        rgba: Option<&Rgba>,
+    ) {
+        let batch = self
+            .batches
+            .entry((mesh.id(), material.albedo.id()))
+            .or_insert_with(|| InstanceBatch {
+                mesh,
+                material,
+                data: Vec::new(),
+            });
+        push_instance(&mut batch.data, global, rgba);
+    }
+}
+
+fn push_instance(data: &mut Vec<ModelInstance>, global: &GlobalTransform, rgba: Option<&Rgba>) {
+    let rgba = rgba.cloned().unwrap_or(Rgba::WHITE);
+    data.push(ModelInstance {
+        model: global.0.into(),
+        color: [rgba.0, rgba.1, rgba.2, rgba.3],
+    });
+}
+
+fn draw_instances<V>(
+    encoder: &mut Encoder,
+    effect: &mut Effect,
+    factory: &mut Factory,
+    mesh: Option<&Mesh>,
+    tex_storage: &AssetStorage<Texture>,
+    material: &Material,
+    material_defaults: &MaterialDefaults,
+    missing: &MissingTextures,
+    instances: &[ModelInstance],
+) where
+    V: Query<(Position, TexCoord)>,
+{
+    use gfx::{
+        buffer,
+        memory::{Bind, Typed},
+        Factory,
+    };
+
+    let mesh = match mesh {
+        Some(mesh) => mesh,
+        None => return,
+    };
+
+    if instances.is_empty() || !set_attribute_buffers(effect, mesh, &[V::QUERIED_ATTRIBUTES]) {
+        effect.clear();
+        return;
+    }
+
+    let vbuf = factory
+        .create_buffer_immutable(instances, buffer::Role::Vertex, Bind::empty())
+        .expect("Unable to create immutable buffer for `DrawFlatInstanced`");
+    effect.data.vertex_bufs.push(vbuf.raw().clone());
+
+    add_textures(
+        effect,
+        encoder,
+        tex_storage,
+        material,
+        &material_defaults.0,
+        missing,
+        &TEXTURES,
+    );
+
+    let mut slice = mesh.slice().clone();
+    slice.instances = Some((instances.len() as u32, 0));
+    effect.draw(&slice, encoder);
+    effect.clear();
+}
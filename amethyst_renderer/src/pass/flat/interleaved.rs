@@ -9,22 +9,31 @@ use glsl_layout::Uniform;
 
 use amethyst_assets::AssetStorage;
 use amethyst_core::{
-    specs::prelude::{Join, Read, ReadExpect, ReadStorage},
+    specs::prelude::{Entities, Join, Read, ReadExpect, ReadStorage},
     transform::GlobalTransform,
 };
 use amethyst_error::Error;
 
 use crate::{
-    cam::{ActiveCamera, Camera},
+    cam::{ActiveCamera, Camera, CameraTarget, StereoCamera, Viewport},
+    culling::{Aabb, BoundingSphere},
     hidden::{Hidden, HiddenPropagate},
-    mesh::{Mesh, MeshHandle},
-    mtl::{Material, MaterialDefaults},
-    pass::util::{default_transparency, draw_mesh, get_camera, setup_textures, VertexArgs},
+    mesh::{LodMesh, LodView, Mesh, MeshHandle},
+    mtl::{Material, MaterialDefaults, MissingTextures, TextureRegion},
+    pass::{
+        uniform::{set_entity_uniform, with_entity_uniform},
+        util::{
+            culling_frustum, default_transparency, draw_mesh, get_cameras, is_culled,
+            region_material, select_lod, set_view, setup_textures, sort_back_to_front, VertexArgs,
+        },
+        EntityUniform, NoUniform,
+    },
     pipe::{
         pass::{Pass, PassData},
         DepthMode, Effect, NewEffect,
     },
     tex::Texture,
+    transparent::{SortKey, Transparent},
     types::{Encoder, Factory},
     vertex::{Position, Query, TexCoord},
     visibility::Visibility,
@@ -38,15 +47,22 @@ use super::*;
 /// See the [crate level documentation](index.html) for information about interleaved and separate
 /// passes.
 ///
+/// If `V` has a `Color` attribute, like `PosColorTex`, the texture is multiplied with the color of
+/// the vertices as well as with the entity's `Rgba`.
+///
 /// # Type Parameters
 ///
 /// * `V`: `VertexFormat`
-#[derive(Derivative, Clone, Debug, PartialEq)]
-#[derivative(Default(bound = "V: Query<(Position, TexCoord)>, Self: Pass"))]
-pub struct DrawFlat<V> {
-    _pd: PhantomData<V>,
+/// * `U`: `EntityUniform` uploaded for each entity, see `with_uniform`
+#[derive(Derivative, Clone, PartialEq)]
+#[derivative(Debug, Default(bound = "V: Query<(Position, TexCoord)>, Self: Pass"))]
+pub struct DrawFlat<V, U = NoUniform> {
+    _pd: PhantomData<(V, U)>,
     #[derivative(Default(value = "default_transparency()"))]
     transparency: Option<(ColorMask, Blend, Option<DepthMode>)>,
+    frustum_culling: bool,
+    #[derivative(Debug = "ignore", Default(value = "FRAG_SRC"))]
+    frag_src: &'static [u8],
 }
 
 impl<V> DrawFlat<V>
@@ -59,6 +75,33 @@ where
         Default::default()
     }
 
+    /// Draw with the fragment shader `frag_src`, uploading the uniform block of each entity's
+    /// `EntityUniform` component `U` before drawing it.
+    ///
+    /// The shader replaces `flat.glsl` and has to declare the same inputs and textures, plus the
+    /// block `U::NAME`, e.g. a `Dissolve` discarding fragments of a noise texture below its
+    /// amount:
+    ///
+    /// ```glsl
+    /// layout (std140) uniform Dissolve {
+    ///     float amount;
+    /// } dissolve;
+    /// ```
+    pub fn with_uniform<U: EntityUniform>(self, frag_src: &'static [u8]) -> DrawFlat<V, U> {
+        DrawFlat {
+            _pd: PhantomData,
+            transparency: self.transparency,
+            frustum_culling: self.frustum_culling,
+            frag_src,
+        }
+    }
+}
+
+impl<V, U> DrawFlat<V, U>
+where
+    V: Query<(Position, TexCoord)>,
+    Self: Pass,
+{
     /// Transparency is enabled by default.
     /// If you pass false to this function transparency will be disabled.
     ///
@@ -85,47 +128,76 @@ where
         self.transparency = Some((mask, blend, depth));
         self
     }
+
+    /// Skip entities whose `BoundingSphere` or `Aabb` is out of the camera's view.
+    ///
+    /// Entities without bounds are always drawn.
+    pub fn with_frustum_culling(mut self) -> Self {
+        self.frustum_culling = true;
+        self
+    }
 }
 
-impl<'a, V> PassData<'a> for DrawFlat<V>
+impl<'a, V, U> PassData<'a> for DrawFlat<V, U>
 where
     V: Query<(Position, TexCoord)>,
+    U: EntityUniform,
 {
     type Data = (
         Read<'a, ActiveCamera>,
         ReadStorage<'a, Camera>,
+        ReadStorage<'a, Viewport>,
+        ReadStorage<'a, CameraTarget>,
+        ReadStorage<'a, StereoCamera>,
         Read<'a, AssetStorage<Mesh>>,
         Read<'a, AssetStorage<Texture>>,
         ReadExpect<'a, MaterialDefaults>,
+        ReadExpect<'a, MissingTextures>,
+        Entities<'a>,
         Option<Read<'a, Visibility>>,
+        ReadStorage<'a, Transparent>,
+        ReadStorage<'a, SortKey>,
         ReadStorage<'a, Hidden>,
         ReadStorage<'a, HiddenPropagate>,
         ReadStorage<'a, MeshHandle>,
+        ReadStorage<'a, LodMesh>,
         ReadStorage<'a, Material>,
+        ReadStorage<'a, TextureRegion>,
         ReadStorage<'a, GlobalTransform>,
         ReadStorage<'a, Rgba>,
+        ReadStorage<'a, BoundingSphere>,
+        ReadStorage<'a, Aabb>,
+        ReadStorage<'a, U>,
     );
 }
 
-impl<V> Pass for DrawFlat<V>
+impl<V, U> Pass for DrawFlat<V, U>
 where
     V: Query<(Position, TexCoord)>,
+    U: EntityUniform,
 {
     fn compile(&mut self, effect: NewEffect<'_>) -> Result<Effect, Error> {
         use std::mem;
-        let mut builder = effect.simple(VERT_SRC, FRAG_SRC);
+        let vert_src = if has_vertex_colors::<V>() {
+            COLOR_VERT_SRC
+        } else {
+            VERT_SRC
+        };
+        let mut builder = effect.simple(vert_src, self.frag_src);
         builder
             .with_raw_constant_buffer(
                 "VertexArgs",
                 mem::size_of::<<VertexArgs as Uniform>::Std140>(),
                 1,
             )
-            .with_raw_vertex_buffer(V::QUERIED_ATTRIBUTES, V::size() as ElemStride, 0);
+            .with_raw_vertex_buffer(flat_attributes::<V>(), V::size() as ElemStride, 0);
+        with_entity_uniform::<U>(&mut builder);
         setup_textures(&mut builder, &TEXTURES);
         match self.transparency {
             Some((mask, blend, depth)) => builder.with_blended_output("color", mask, blend, depth),
             None => builder.with_output("color", Some(DepthMode::LessEqualWrite)),
         };
+        builder.with_two_sided_variant();
         builder.build()
     }
 
@@ -137,78 +209,74 @@ where
         (
             active,
             camera,
+            viewports,
+            camera_targets,
+            stereo_cameras,
             mesh_storage,
             tex_storage,
             material_defaults,
+            missing,
+            entities,
             visibility,
+            transparent,
+            sort_keys,
             hidden,
             hidden_prop,
             mesh,
+            lod_mesh,
             material,
+            regions,
             global,
             rgba,
+            bounding_sphere,
+            aabb,
+            uniforms,
         ): <Self as PassData<'a>>::Data,
     ) {
-        let camera = get_camera(active, &camera, &global);
-
-        match visibility {
-            None => {
-                for (mesh, material, global, rgba, _, _) in (
-                    &mesh,
-                    &material,
-                    &global,
-                    rgba.maybe(),
-                    !&hidden,
-                    !&hidden_prop,
-                )
-                    .join()
-                {
-                    draw_mesh(
-                        encoder,
-                        effect,
-                        false,
-                        mesh_storage.get(mesh),
-                        None,
-                        &tex_storage,
-                        Some(material),
-                        &material_defaults,
-                        rgba,
-                        camera,
-                        Some(global),
-                        &[V::QUERIED_ATTRIBUTES],
-                        &TEXTURES,
-                    );
-                }
-            }
-            Some(ref visibility) => {
-                for (mesh, material, global, rgba, _) in (
-                    &mesh,
-                    &material,
-                    &global,
-                    rgba.maybe(),
-                    &visibility.visible_unordered,
-                )
-                    .join()
-                {
-                    draw_mesh(
-                        encoder,
-                        effect,
-                        false,
-                        mesh_storage.get(mesh),
-                        None,
-                        &tex_storage,
-                        Some(material),
-                        &material_defaults,
-                        rgba,
-                        camera,
-                        Some(global),
-                        &[V::QUERIED_ATTRIBUTES],
-                        &TEXTURES,
-                    );
-                }
+        let cameras = get_cameras(
+            active,
+            &camera,
+            &viewports,
+            &camera_targets,
+            &stereo_cameras,
+            &global,
+            effect.target_name(),
+        );
+        for (index, view) in cameras.iter().enumerate() {
+            let lod_view = LodView::new(effect.target_name(), index);
+            let frustum = culling_frustum(self.frustum_culling, view);
+            let view = set_view(effect, view);
+            let camera = view.get();
 
-                for entity in &visibility.visible_ordered {
-                    if let Some(mesh) = mesh.get(*entity) {
+            let mut ordered = match visibility {
+                None => {
+                    for (
+                        (mesh, lod),
+                        (material, region),
+                        global,
+                        (rgba, uniform),
+                        bounds,
+                        _,
+                        _,
+                        _,
+                    ) in (
+                        (&mesh, lod_mesh.maybe()),
+                        (&material, regions.maybe()),
+                        &global,
+                        (rgba.maybe(), uniforms.maybe()),
+                        (bounding_sphere.maybe(), aabb.maybe()),
+                        !&transparent,
+                        !&hidden,
+                        !&hidden_prop,
+                    )
+                        .join()
+                    {
+                        if is_culled(&frustum, Some(global), bounds) {
+                            continue;
+                        }
+                        let mesh = select_lod(mesh, lod, lod_view, camera, Some(global));
+                        let material = region_material(material, region);
+                        set_entity_uniform(encoder, effect, uniform);
                         draw_mesh(
                             encoder,
                             effect,
@@ -216,15 +284,95 @@ where
                             mesh_storage.get(mesh),
                             None,
                             &tex_storage,
-                            material.get(*entity),
+                            Some(&*material),
                             &material_defaults,
-                            rgba.get(*entity),
+                            &missing,
+                            rgba,
                             camera,
-                            global.get(*entity),
-                            &[V::QUERIED_ATTRIBUTES],
+                            Some(global),
+                            &[flat_attributes::<V>()],
                             &TEXTURES,
                         );
                     }
+
+                    (&*entities, &transparent, !&hidden, !&hidden_prop)
+                        .join()
+                        .map(|(entity, _, _, _)| entity)
+                        .collect::<Vec<_>>()
+                }
+                Some(ref visibility) => {
+                    for ((mesh, lod), (material, region), global, (rgba, uniform), bounds, _) in (
+                        (&mesh, lod_mesh.maybe()),
+                        (&material, regions.maybe()),
+                        &global,
+                        (rgba.maybe(), uniforms.maybe()),
+                        (bounding_sphere.maybe(), aabb.maybe()),
+                        &visibility.visible_unordered,
+                    )
+                        .join()
+                    {
+                        if is_culled(&frustum, Some(global), bounds) {
+                            continue;
+                        }
+                        let mesh = select_lod(mesh, lod, lod_view, camera, Some(global));
+                        let material = region_material(material, region);
+                        set_entity_uniform(encoder, effect, uniform);
+                        draw_mesh(
+                            encoder,
+                            effect,
+                            false,
+                            mesh_storage.get(mesh),
+                            None,
+                            &tex_storage,
+                            Some(&*material),
+                            &material_defaults,
+                            &missing,
+                            rgba,
+                            camera,
+                            Some(global),
+                            &[flat_attributes::<V>()],
+                            &TEXTURES,
+                        );
+                    }
+
+                    visibility.visible_ordered.clone()
+                }
+            };
+
+            sort_back_to_front(&mut ordered, camera, &global, &sort_keys);
+            for entity in &ordered {
+                let bounds = (bounding_sphere.get(*entity), aabb.get(*entity));
+                if is_culled(&frustum, global.get(*entity), bounds) {
+                    continue;
+                }
+                if let Some(mesh) = mesh.get(*entity) {
+                    let mesh = select_lod(
+                        mesh,
+                        lod_mesh.get(*entity),
+                        lod_view,
+                        camera,
+                        global.get(*entity),
+                    );
+                    let material = material
+                        .get(*entity)
+                        .map(|material| region_material(material, regions.get(*entity)));
+                    set_entity_uniform(encoder, effect, uniforms.get(*entity));
+                    draw_mesh(
+                        encoder,
+                        effect,
+                        false,
+                        mesh_storage.get(mesh),
+                        None,
+                        &tex_storage,
+                        material.as_ref().map(|material| &**material),
+                        &material_defaults,
+                        &missing,
+                        rgba.get(*entity),
+                        camera,
+                        global.get(*entity),
+                        &[flat_attributes::<V>()],
+                        &TEXTURES,
+                    );
                 }
             }
         }
@@ -1,11 +1,181 @@
-pub use self::{interleaved::DrawFlat, separate::DrawFlatSeparate};
+pub use self::{
+    instanced::DrawFlatInstanced, interleaved::DrawFlat, separate::DrawFlatSeparate,
+};
 
+mod instanced;
 mod interleaved;
 mod separate;
 
-use crate::pass::util::TextureType;
+use gfx::{
+    format::{ChannelType, Format, SurfaceType},
+    pso::buffer::Element,
+    traits::Pod,
+};
+
+use crate::{
+    pass::util::TextureType,
+    vertex::{
+        Attribute, AttributeFormat, Attributes, Position, Query, TexCoord, VertexFormat, With,
+    },
+    Color,
+};
 
 static VERT_SRC: &[u8] = include_bytes!("../shaders/vertex/basic.glsl");
+static COLOR_VERT_SRC: &[u8] = include_bytes!("../shaders/vertex/vertex_color.glsl");
+static INSTANCED_VERT_SRC: &[u8] = include_bytes!("../shaders/vertex/instanced.glsl");
 static FRAG_SRC: &[u8] = include_bytes!("../shaders/fragment/flat.glsl");
 
 static TEXTURES: [TextureType; 1] = [TextureType::Albedo];
+
+/// Returns whether vertices of `V` carry a `Color`, which `DrawFlat` multiplies the texture with.
+fn has_vertex_colors<V: VertexFormat>() -> bool {
+    V::ATTRIBUTES.iter().any(|&(name, _)| name == Color::NAME)
+}
+
+/// Returns the attributes `DrawFlat` reads from vertices of `V`.
+///
+/// With vertex colors these are all attributes of `V`, the shader ignores the ones it doesn't
+/// declare.
+fn flat_attributes<V: Query<(Position, TexCoord)>>() -> Attributes<'static> {
+    if has_vertex_colors::<V>() {
+        V::ATTRIBUTES
+    } else {
+        V::QUERIED_ATTRIBUTES
+    }
+}
+
+#[derive(Clone, Debug)]
+enum ModelCol0 {}
+impl Attribute for ModelCol0 {
+    const NAME: &'static str = "model_col0";
+    const FORMAT: Format = Format(SurfaceType::R32_G32_B32_A32, ChannelType::Float);
+    const SIZE: u32 = 16;
+    type Repr = [f32; 4];
+}
+
+#[derive(Clone, Debug)]
+enum ModelCol1 {}
+impl Attribute for ModelCol1 {
+    const NAME: &'static str = "model_col1";
+    const FORMAT: Format = Format(SurfaceType::R32_G32_B32_A32, ChannelType::Float);
+    const SIZE: u32 = 16;
+    type Repr = [f32; 4];
+}
+
+#[derive(Clone, Debug)]
+enum ModelCol2 {}
+impl Attribute for ModelCol2 {
+    const NAME: &'static str = "model_col2";
+    const FORMAT: Format = Format(SurfaceType::R32_G32_B32_A32, ChannelType::Float);
+    const SIZE: u32 = 16;
+    type Repr = [f32; 4];
+}
+
+#[derive(Clone, Debug)]
+enum ModelCol3 {}
+impl Attribute for ModelCol3 {
+    const NAME: &'static str = "model_col3";
+    const FORMAT: Format = Format(SurfaceType::R32_G32_B32_A32, ChannelType::Float);
+    const SIZE: u32 = 16;
+    type Repr = [f32; 4];
+}
+
+/// Per-instance data of `DrawFlatInstanced`: the model matrix, one column per attribute, followed
+/// by the tint.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ModelInstance {
+    pub model: [[f32; 4]; 4],
+    pub color: [f32; 4],
+}
+
+unsafe impl Pod for ModelInstance {}
+
+impl VertexFormat for ModelInstance {
+    const ATTRIBUTES: Attributes<'static> = &[
+        (ModelCol0::NAME, <Self as With<ModelCol0>>::FORMAT),
+        (ModelCol1::NAME, <Self as With<ModelCol1>>::FORMAT),
+        (ModelCol2::NAME, <Self as With<ModelCol2>>::FORMAT),
+        (ModelCol3::NAME, <Self as With<ModelCol3>>::FORMAT),
+        (Color::NAME, <Self as With<Color>>::FORMAT),
+    ];
+}
+
+impl With<ModelCol0> for ModelInstance {
+    const FORMAT: AttributeFormat = Element {
+        offset: 0,
+        format: ModelCol0::FORMAT,
+    };
+}
+
+impl With<ModelCol1> for ModelInstance {
+    const FORMAT: AttributeFormat = Element {
+        offset: ModelCol0::SIZE,
+        format: ModelCol1::FORMAT,
+    };
+}
+
+impl With<ModelCol2> for ModelInstance {
+    const FORMAT: AttributeFormat = Element {
+        offset: ModelCol0::SIZE + ModelCol1::SIZE,
+        format: ModelCol2::FORMAT,
+    };
+}
+
+impl With<ModelCol3> for ModelInstance {
+    const FORMAT: AttributeFormat = Element {
+        offset: ModelCol0::SIZE + ModelCol1::SIZE + ModelCol2::SIZE,
+        format: ModelCol3::FORMAT,
+    };
+}
+
+impl With<Color> for ModelInstance {
+    const FORMAT: AttributeFormat = Element {
+        offset: ModelCol0::SIZE + ModelCol1::SIZE + ModelCol2::SIZE + ModelCol3::SIZE,
+        format: Color::FORMAT,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use amethyst_core::nalgebra::{Vector2, Vector3};
+
+    use crate::{
+        mesh::Mesh,
+        vertex::{Attribute, Color, PosColorTex, PosTex, Position, Query, TexCoord, VertexFormat},
+    };
+
+    use super::{flat_attributes, has_vertex_colors};
+
+    #[test]
+    fn vertex_colors_are_read_when_present() {
+        let vertices = [[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]]
+            .iter()
+            .map(|&[x, y]| PosColorTex {
+                position: Vector3::new(x, y, 0.0),
+                color: [x, y, 1.0, 1.0],
+                tex_coord: Vector2::new(x, y),
+            })
+            .collect::<Vec<_>>();
+        // Panics if the attributes are out of order.
+        let _ = Mesh::build(vertices);
+
+        assert!(has_vertex_colors::<PosColorTex>());
+        let attributes = flat_attributes::<PosColorTex>();
+        assert_eq!(attributes, PosColorTex::ATTRIBUTES);
+        let color = attributes.iter().find(|&&(name, _)| name == Color::NAME);
+        assert_eq!(
+            color.map(|&(_, format)| format.offset),
+            Some(Position::SIZE)
+        );
+    }
+
+    #[test]
+    fn meshes_without_colors_are_unchanged() {
+        assert!(!has_vertex_colors::<PosTex>());
+        assert_eq!(
+            flat_attributes::<PosTex>(),
+            <PosTex as Query<(Position, TexCoord)>>::QUERIED_ATTRIBUTES
+        );
+    }
+}
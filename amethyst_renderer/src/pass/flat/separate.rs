@@ -7,19 +7,23 @@ use glsl_layout::Uniform;
 
 use amethyst_assets::AssetStorage;
 use amethyst_core::{
-    specs::prelude::{Join, Read, ReadExpect, ReadStorage},
+    specs::prelude::{Entities, Join, Read, ReadExpect, ReadStorage},
     transform::GlobalTransform,
 };
 use amethyst_error::Error;
 
 use crate::{
-    cam::{ActiveCamera, Camera},
+    cam::{ActiveCamera, Camera, CameraTarget, StereoCamera, Viewport},
+    culling::{Aabb, BoundingSphere},
     hidden::{Hidden, HiddenPropagate},
-    mesh::{Mesh, MeshHandle},
-    mtl::{Material, MaterialDefaults},
+    mesh::{LodMesh, LodView, Mesh, MeshHandle},
+    mtl::{Material, MaterialDefaults, MissingTextures, TextureRegion},
     pass::{
         skinning::{create_skinning_effect, setup_skinning_buffers},
-        util::{default_transparency, draw_mesh, get_camera, setup_textures, VertexArgs},
+        util::{
+            culling_frustum, default_transparency, draw_mesh, get_cameras, is_culled,
+            region_material, select_lod, set_view, setup_textures, sort_back_to_front, VertexArgs,
+        },
     },
     pipe::{
         pass::{Pass, PassData},
@@ -27,6 +31,7 @@ use crate::{
     },
     skinning::JointTransforms,
     tex::Texture,
+    transparent::{SortKey, Transparent},
     types::{Encoder, Factory},
     vertex::{Attributes, Position, Separate, TexCoord, VertexFormat},
     visibility::Visibility,
@@ -54,6 +59,7 @@ pub struct DrawFlatSeparate {
     skinning: bool,
     #[derivative(Default(value = "default_transparency()"))]
     transparency: Option<(ColorMask, Blend, Option<DepthMode>)>,
+    frustum_culling: bool,
 }
 
 impl DrawFlatSeparate
@@ -97,23 +103,42 @@ where
         self.transparency = Some((mask, blend, depth));
         self
     }
+
+    /// Skip entities whose `BoundingSphere` or `Aabb` is out of the camera's view.
+    ///
+    /// Entities without bounds are always drawn.
+    pub fn with_frustum_culling(mut self) -> Self {
+        self.frustum_culling = true;
+        self
+    }
 }
 
 impl<'a> PassData<'a> for DrawFlatSeparate {
     type Data = (
         Read<'a, ActiveCamera>,
         ReadStorage<'a, Camera>,
+        ReadStorage<'a, Viewport>,
+        ReadStorage<'a, CameraTarget>,
+        ReadStorage<'a, StereoCamera>,
         Read<'a, AssetStorage<Mesh>>,
         Read<'a, AssetStorage<Texture>>,
         ReadExpect<'a, MaterialDefaults>,
+        ReadExpect<'a, MissingTextures>,
+        Entities<'a>,
         Option<Read<'a, Visibility>>,
+        ReadStorage<'a, Transparent>,
+        ReadStorage<'a, SortKey>,
         ReadStorage<'a, Hidden>,
         ReadStorage<'a, HiddenPropagate>,
         ReadStorage<'a, MeshHandle>,
+        ReadStorage<'a, LodMesh>,
         ReadStorage<'a, Material>,
+        ReadStorage<'a, TextureRegion>,
         ReadStorage<'a, GlobalTransform>,
         ReadStorage<'a, JointTransforms>,
         ReadStorage<'a, Rgba>,
+        ReadStorage<'a, BoundingSphere>,
+        ReadStorage<'a, Aabb>,
     );
 }
 
@@ -149,6 +174,7 @@ impl Pass for DrawFlatSeparate {
             Some((mask, blend, depth)) => builder.with_blended_output("color", mask, blend, depth),
             None => builder.with_output("color", Some(DepthMode::LessEqualWrite)),
         };
+        builder.with_two_sided_variant();
         builder.build()
     }
 
@@ -160,97 +186,160 @@ impl Pass for DrawFlatSeparate {
         (
             active,
             camera,
+            viewports,
+            camera_targets,
+            stereo_cameras,
             mesh_storage,
             tex_storage,
             material_defaults,
+            missing,
+            entities,
             visibility,
+            transparent,
+            sort_keys,
             hidden,
             hidden_prop,
             mesh,
+            lod_mesh,
             material,
+            regions,
             global,
             joints,
             rgba,
+            bounding_sphere,
+            aabb,
         ): <Self as PassData<'a>>::Data,
     ) {
-        let camera = get_camera(active, &camera, &global);
+        let cameras = get_cameras(
+            active,
+            &camera,
+            &viewports,
+            &camera_targets,
+            &stereo_cameras,
+            &global,
+            effect.target_name(),
+        );
+        for (index, view) in cameras.iter().enumerate() {
+            let lod_view = LodView::new(effect.target_name(), index);
+            let frustum = culling_frustum(self.frustum_culling, view);
+            let view = set_view(effect, view);
+            let camera = view.get();
 
-        match visibility {
-            None => {
-                for (joint, mesh, material, global, rgba, _, _) in (
-                    joints.maybe(),
-                    &mesh,
-                    &material,
-                    &global,
-                    rgba.maybe(),
-                    !&hidden,
-                    !&hidden_prop,
-                )
-                    .join()
-                {
-                    draw_mesh(
-                        encoder,
-                        effect,
-                        self.skinning,
-                        mesh_storage.get(mesh),
-                        joint,
-                        &tex_storage,
-                        Some(material),
-                        &material_defaults,
-                        rgba,
-                        camera,
-                        Some(global),
-                        &ATTRIBUTES,
-                        &TEXTURES,
-                    );
-                }
-            }
-            Some(ref visibility) => {
-                for (joint, mesh, material, global, rgba, _) in (
-                    joints.maybe(),
-                    &mesh,
-                    &material,
-                    &global,
-                    rgba.maybe(),
-                    &visibility.visible_unordered,
-                )
-                    .join()
-                {
-                    draw_mesh(
-                        encoder,
-                        effect,
-                        self.skinning,
-                        mesh_storage.get(mesh),
-                        joint,
-                        &tex_storage,
-                        Some(material),
-                        &material_defaults,
-                        rgba,
-                        camera,
-                        Some(global),
-                        &ATTRIBUTES,
-                        &TEXTURES,
-                    );
-                }
+            let mut ordered = match visibility {
+                None => {
+                    for (joint, (mesh, lod), (material, region), global, rgba, bounds, _, _, _) in (
+                        joints.maybe(),
+                        (&mesh, lod_mesh.maybe()),
+                        (&material, regions.maybe()),
+                        &global,
+                        rgba.maybe(),
+                        (bounding_sphere.maybe(), aabb.maybe()),
+                        !&transparent,
+                        !&hidden,
+                        !&hidden_prop,
+                    )
+                        .join()
+                    {
+                        if is_culled(&frustum, Some(global), bounds) {
+                            continue;
+                        }
+                        let mesh = select_lod(mesh, lod, lod_view, camera, Some(global));
+                        let material = region_material(material, region);
+                        draw_mesh(
+                            encoder,
+                            effect,
+                            self.skinning,
+                            mesh_storage.get(mesh),
+                            joint,
+                            &tex_storage,
+                            Some(&*material),
+                            &material_defaults,
+                            &missing,
+                            rgba,
+                            camera,
+                            Some(global),
+                            &ATTRIBUTES,
+                            &TEXTURES,
+                        );
+                    }
 
-                for entity in &visibility.visible_ordered {
-                    if let Some(mesh) = mesh.get(*entity) {
+                    (&*entities, &transparent, !&hidden, !&hidden_prop)
+                        .join()
+                        .map(|(entity, _, _, _)| entity)
+                        .collect::<Vec<_>>()
+                }
+                Some(ref visibility) => {
+                    for (joint, (mesh, lod), (material, region), global, rgba, bounds, _) in (
+                        joints.maybe(),
+                        (&mesh, lod_mesh.maybe()),
+                        (&material, regions.maybe()),
+                        &global,
+                        rgba.maybe(),
+                        (bounding_sphere.maybe(), aabb.maybe()),
+                        &visibility.visible_unordered,
+                    )
+                        .join()
+                    {
+                        if is_culled(&frustum, Some(global), bounds) {
+                            continue;
+                        }
+                        let mesh = select_lod(mesh, lod, lod_view, camera, Some(global));
+                        let material = region_material(material, region);
                         draw_mesh(
                             encoder,
                             effect,
                             self.skinning,
                             mesh_storage.get(mesh),
-                            joints.get(*entity),
+                            joint,
                             &tex_storage,
-                            material.get(*entity),
+                            Some(&*material),
                             &material_defaults,
-                            rgba.get(*entity),
+                            &missing,
+                            rgba,
                             camera,
-                            global.get(*entity),
+                            Some(global),
                             &ATTRIBUTES,
                             &TEXTURES,
                         );
                     }
+
+                    visibility.visible_ordered.clone()
+                }
+            };
+
+            sort_back_to_front(&mut ordered, camera, &global, &sort_keys);
+            for entity in &ordered {
+                let bounds = (bounding_sphere.get(*entity), aabb.get(*entity));
+                if is_culled(&frustum, global.get(*entity), bounds) {
+                    continue;
+                }
+                if let Some(mesh) = mesh.get(*entity) {
+                    let mesh = select_lod(
+                        mesh,
+                        lod_mesh.get(*entity),
+                        lod_view,
+                        camera,
+                        global.get(*entity),
+                    );
+                    let material = material
+                        .get(*entity)
+                        .map(|material| region_material(material, regions.get(*entity)));
+                    draw_mesh(
+                        encoder,
+                        effect,
+                        self.skinning,
+                        mesh_storage.get(mesh),
+                        joints.get(*entity),
+                        &tex_storage,
+                        material.as_ref().map(|material| &**material),
+                        &material_defaults,
+                        &missing,
+                        rgba.get(*entity),
+                        camera,
+                        global.get(*entity),
+                        &ATTRIBUTES,
+                        &TEXTURES,
+                    );
                 }
             }
         }
@@ -1,5 +1,7 @@
 //! Flat forward drawing pass that mimics a blit.
 
+use std::cmp::Ordering;
+
 use derivative::Derivative;
 use gfx::pso::buffer::ElemStride;
 use gfx_core::state::{Blend, ColorMask};
@@ -8,7 +10,7 @@ use log::warn;
 
 use amethyst_assets::{AssetStorage, Handle};
 use amethyst_core::{
-    nalgebra::Vector4,
+    nalgebra::{Vector3, Vector4},
     specs::prelude::{Join, Read, ReadStorage},
     transform::GlobalTransform,
 };
@@ -18,8 +20,10 @@ use crate::{
     cam::{ActiveCamera, Camera},
     hidden::{Hidden, HiddenPropagate},
     mesh::MeshHandle,
+    mtl::TextureRegion,
     pass::util::{
-        add_texture, default_transparency, get_camera, set_view_args, setup_textures, ViewArgs,
+        add_texture, default_transparency, get_camera, set_view_args, setup_textures, view_depth,
+        ViewArgs,
     },
     pipe::{
         pass::{Pass, PassData},
@@ -28,6 +32,7 @@ use crate::{
     sprite::{Flipped, SpriteRender, SpriteSheet},
     sprite_visibility::SpriteVisibility,
     tex::{Texture, TextureHandle},
+    transparent::SortKey,
     types::{Encoder, Factory, Slice},
     vertex::{Attributes, Query, VertexFormat},
     Color, Rgba,
@@ -36,11 +41,18 @@ use crate::{
 use super::*;
 
 /// Draws sprites on a 2D quad.
+///
+/// With transparency, which is enabled by default, the sprites are drawn back to front along the
+/// camera's view direction, so overlapping soft edges composite correctly. Without a
+/// `SpriteVisibility` resource all sprites are sorted that way, sprites with a `SortKey` by
+/// their key, with one only the sprites it orders. The pass draws the sprites whose
+/// `SpriteBlend` matches its own, `Alpha` by default.
 #[derive(Derivative, Clone, Debug)]
 #[derivative(Default(bound = "Self: Pass"))]
 pub struct DrawFlat2D {
     #[derivative(Default(value = "default_transparency()"))]
     transparency: Option<(ColorMask, Blend, Option<DepthMode>)>,
+    blend: SpriteBlend,
     batch: TextureBatch,
 }
 
@@ -69,6 +81,17 @@ where
         self
     }
 
+    /// Draw the sprites with the given blending mode instead, enabling transparency.
+    pub fn with_blend(mut self, blend: SpriteBlend) -> Self {
+        self.blend = blend;
+        self.transparency = Some((
+            ColorMask::all(),
+            blend.blend(),
+            Some(DepthMode::LessEqualWrite),
+        ));
+        self
+    }
+
     /// Set transparency settings to custom values.
     pub fn with_transparency_settings(
         mut self,
@@ -100,6 +123,9 @@ impl<'a> PassData<'a> for DrawFlat2D {
         ReadStorage<'a, Flipped>,
         ReadStorage<'a, MeshHandle>,
         ReadStorage<'a, Rgba>,
+        ReadStorage<'a, SpriteBlend>,
+        ReadStorage<'a, SortKey>,
+        ReadStorage<'a, TextureRegion>,
     );
 }
 
@@ -143,93 +169,157 @@ impl Pass for DrawFlat2D {
             flipped,
             mesh,
             rgba,
+            sprite_blend,
+            sort_keys,
+            regions,
         ): <Self as PassData<'a>>::Data,
     ) {
         let camera = get_camera(active, &camera, &global);
+        let blend = self.blend;
+        let drawn =
+            |sprite_blend: Option<&SpriteBlend>| sprite_blend.cloned().unwrap_or_default() == blend;
+        let transparent = self.transparency.is_some();
 
         match visibility {
             None => {
-                for (sprite_render, global, flipped, rgba, _, _) in (
+                for (sprite_render, global, flipped, rgba, sprite_blend, sort_key, _, _) in (
                     &sprite_render,
                     &global,
                     flipped.maybe(),
                     rgba.maybe(),
+                    sprite_blend.maybe(),
+                    sort_keys.maybe(),
                     !&hidden,
                     !&hidden_prop,
                 )
                     .join()
                 {
+                    if !drawn(sprite_blend) {
+                        continue;
+                    }
                     self.batch.add_sprite(
                         sprite_render,
                         Some(global),
                         flipped,
                         rgba,
+                        sort_key,
                         &sprite_sheet_storage,
                         &tex_storage,
                     );
                 }
 
-                for (image_render, global, flipped, rgba, _, _, _) in (
+                for (
+                    image_render,
+                    global,
+                    flipped,
+                    rgba,
+                    sprite_blend,
+                    sort_key,
+                    region,
+                    _,
+                    _,
+                    _,
+                ) in (
                     &texture_handle,
                     &global,
                     flipped.maybe(),
                     rgba.maybe(),
+                    sprite_blend.maybe(),
+                    sort_keys.maybe(),
+                    regions.maybe(),
                     !&hidden,
                     !&hidden_prop,
                     !&mesh,
                 )
                     .join()
                 {
-                    self.batch
-                        .add_image(image_render, Some(global), flipped, rgba, &tex_storage);
+                    if !drawn(sprite_blend) {
+                        continue;
+                    }
+                    self.batch.add_image(
+                        image_render,
+                        Some(global),
+                        flipped,
+                        rgba,
+                        sort_key,
+                        region,
+                        &tex_storage,
+                    );
                 }
 
-                self.batch.sort();
+                if transparent {
+                    self.batch.sort_back_to_front(camera);
+                } else {
+                    self.batch.sort();
+                }
             }
             Some(ref visibility) => {
-                for (sprite_render, global, flipped, rgba, _) in (
+                for (sprite_render, global, flipped, rgba, sprite_blend, sort_key, _) in (
                     &sprite_render,
                     &global,
                     flipped.maybe(),
                     rgba.maybe(),
+                    sprite_blend.maybe(),
+                    sort_keys.maybe(),
                     &visibility.visible_unordered,
                 )
                     .join()
                 {
+                    if !drawn(sprite_blend) {
+                        continue;
+                    }
                     self.batch.add_sprite(
                         sprite_render,
                         Some(global),
                         flipped,
                         rgba,
+                        sort_key,
                         &sprite_sheet_storage,
                         &tex_storage,
                     );
                 }
 
-                for (image_render, global, flipped, rgba, _, _) in (
+                for (image_render, global, flipped, rgba, sprite_blend, sort_key, region, _, _) in (
                     &texture_handle,
                     &global,
                     flipped.maybe(),
                     rgba.maybe(),
+                    sprite_blend.maybe(),
+                    sort_keys.maybe(),
+                    regions.maybe(),
                     &visibility.visible_unordered,
                     !&mesh,
                 )
                     .join()
                 {
-                    self.batch
-                        .add_image(image_render, Some(global), flipped, rgba, &tex_storage);
+                    if !drawn(sprite_blend) {
+                        continue;
+                    }
+                    self.batch.add_image(
+                        image_render,
+                        Some(global),
+                        flipped,
+                        rgba,
+                        sort_key,
+                        region,
+                        &tex_storage,
+                    );
                 }
 
                 // We are free to optimize the order of the opaque sprites.
                 self.batch.sort();
 
                 for entity in &visibility.visible_ordered {
+                    if !drawn(sprite_blend.get(*entity)) {
+                        continue;
+                    }
                     if let Some(sprite_render) = sprite_render.get(*entity) {
                         self.batch.add_sprite(
                             sprite_render,
                             global.get(*entity),
                             flipped.get(*entity),
                             rgba.get(*entity),
+                            sort_keys.get(*entity),
                             &sprite_sheet_storage,
                             &tex_storage,
                         );
@@ -239,6 +329,8 @@ impl Pass for DrawFlat2D {
                             global.get(*entity),
                             flipped.get(*entity),
                             rgba.get(*entity),
+                            sort_keys.get(*entity),
+                            regions.get(*entity),
                             &tex_storage,
                         )
                     }
@@ -250,6 +342,7 @@ impl Pass for DrawFlat2D {
             &mut factory,
             effect,
             camera,
+            blend,
             &sprite_sheet_storage,
             &tex_storage,
         );
@@ -264,6 +357,7 @@ enum TextureDrawData {
         render: SpriteRender,
         flipped: Option<Flipped>,
         rgba: Option<Rgba>,
+        sort_key: Option<SortKey>,
         transform: GlobalTransform,
     },
     Image {
@@ -271,6 +365,8 @@ enum TextureDrawData {
         transform: GlobalTransform,
         flipped: Option<Flipped>,
         rgba: Option<Rgba>,
+        sort_key: Option<SortKey>,
+        region: Option<TextureRegion>,
         width: usize,
         height: usize,
     },
@@ -297,6 +393,33 @@ impl TextureDrawData {
             TextureDrawData::Image { flipped, .. } => flipped,
         }
     }
+
+    pub fn sort_key(&self) -> Option<SortKey> {
+        match self {
+            TextureDrawData::Sprite { sort_key, .. } => *sort_key,
+            TextureDrawData::Image { sort_key, .. } => *sort_key,
+        }
+    }
+
+    pub fn translation(&self) -> Vector3<f32> {
+        match self {
+            TextureDrawData::Sprite { transform, .. } => transform.0.column(3).xyz(),
+            TextureDrawData::Image { transform, .. } => transform.0.column(3).xyz(),
+        }
+    }
+}
+
+/// Returns the left, right, bottom and top texture coordinates of an image quad showing
+/// `region`, or all of the image without one.
+fn image_tex_coords(
+    region: Option<&TextureRegion>,
+    flip_horizontal: bool,
+    flip_vertical: bool,
+) -> [f32; 4] {
+    let (u, v) = region.map_or(((0.0, 1.0), (0.0, 1.0)), |region| (region.0.u, region.0.v));
+    let (left, right) = if flip_horizontal { (u.1, u.0) } else { u };
+    let (bottom, top) = if flip_vertical { (v.1, v.0) } else { v };
+    [left, right, bottom, top]
 }
 
 #[derive(Clone, Default, Debug)]
@@ -311,6 +434,8 @@ impl TextureBatch {
         global: Option<&GlobalTransform>,
         flipped: Option<&Flipped>,
         rgba: Option<&Rgba>,
+        sort_key: Option<&SortKey>,
+        region: Option<&TextureRegion>,
         tex_storage: &AssetStorage<Texture>,
     ) {
         let global = match global {
@@ -331,6 +456,8 @@ impl TextureBatch {
             transform: *global,
             flipped: flipped.cloned(),
             rgba: rgba.cloned(),
+            sort_key: sort_key.cloned(),
+            region: region.cloned(),
             width: texture_dims.0,
             height: texture_dims.1,
         });
@@ -342,6 +469,7 @@ impl TextureBatch {
         global: Option<&GlobalTransform>,
         flipped: Option<&Flipped>,
         rgba: Option<&Rgba>,
+        sort_key: Option<&SortKey>,
         sprite_sheet_storage: &AssetStorage<SpriteSheet>,
         tex_storage: &AssetStorage<Texture>,
     ) {
@@ -376,6 +504,7 @@ impl TextureBatch {
             render: sprite_render.clone(),
             flipped: flipped.cloned(),
             rgba: rgba.cloned(),
+            sort_key: sort_key.cloned(),
             transform: *global,
         });
     }
@@ -386,12 +515,26 @@ impl TextureBatch {
         self.textures.sort_by(|a, b| a.tex_id().cmp(&b.tex_id()));
     }
 
+    /// Orders the sprites from the farthest to the nearest along the camera's view direction, so
+    /// they blend correctly. Sprites with a `SortKey` are sorted by their key instead.
+    pub fn sort_back_to_front(&mut self, camera: Option<(&Camera, &GlobalTransform)>) {
+        let depth = |quad: &TextureDrawData| {
+            quad.sort_key()
+                .map_or_else(|| view_depth(camera, &quad.translation()), |key| key.0)
+        };
+        // The sort is stable, so sprites at the same depth keep batching by texture.
+        self.sort();
+        self.textures
+            .sort_by(|a, b| depth(b).partial_cmp(&depth(a)).unwrap_or(Ordering::Equal));
+    }
+
     pub fn encode(
         &self,
         encoder: &mut Encoder,
         factory: &mut Factory,
         effect: &mut Effect,
         camera: Option<(&Camera, &GlobalTransform)>,
+        blend: SpriteBlend,
         sprite_sheet_storage: &AssetStorage<SpriteSheet>,
         tex_storage: &AssetStorage<Texture>,
     ) {
@@ -477,26 +620,20 @@ impl TextureBatch {
                 }
                 TextureDrawData::Image {
                     transform,
+                    region,
                     width,
                     height,
                     rgba,
                     ..
                 } => {
-                    let (uv_left, uv_right) = if flip_horizontal {
-                        (1.0, 0.0)
-                    } else {
-                        (0.0, 1.0)
-                    };
-                    let (uv_bottom, uv_top) = if flip_vertical {
-                        (1.0, 0.0)
-                    } else {
-                        (0.0, 1.0)
-                    };
+                    let [uv_left, uv_right, uv_bottom, uv_top] =
+                        image_tex_coords(region.as_ref(), flip_horizontal, flip_vertical);
 
                     let transform = &transform.0;
 
-                    let dir_x = transform.column(0) * (*width as f32);
-                    let dir_y = transform.column(1) * (*height as f32);
+                    // Regions draw at the size they have in the image.
+                    let dir_x = transform.column(0) * (*width as f32 * (uv_right - uv_left).abs());
+                    let dir_y = transform.column(1) * (*height as f32 * (uv_top - uv_bottom).abs());
 
                     let pos = transform * Vector4::new(1.0, 1.0, 0.0, 1.0);
 
@@ -505,10 +642,10 @@ impl TextureBatch {
                     )
                 }
             };
-            let rgba = rgba.unwrap_or(Rgba::WHITE);
+            let rgba = blend.tint(rgba.unwrap_or(Rgba::WHITE));
             instance_data.extend(&[
                 dir_x.x, dir_x.y, dir_y.x, dir_y.y, pos.x, pos.y, uv_left, uv_right, uv_bottom,
-                uv_top, pos.z, rgba.0, rgba.1, rgba.2, rgba.3,
+                uv_top, pos.z, rgba[0], rgba[1], rgba[2], rgba[3],
             ]);
             num_instances += 1;
 
@@ -553,3 +690,25 @@ impl TextureBatch {
         self.textures.clear();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::mtl::TextureRegion;
+
+    use super::image_tex_coords;
+
+    #[test]
+    fn images_sample_their_region() {
+        assert_eq!(image_tex_coords(None, false, false), [0.0, 1.0, 0.0, 1.0]);
+
+        let region = TextureRegion::tile(4, 4, 2, 1);
+        assert_eq!(
+            image_tex_coords(Some(&region), false, false),
+            [0.5, 0.75, 0.5, 0.75]
+        );
+        assert_eq!(
+            image_tex_coords(Some(&region), true, true),
+            [0.75, 0.5, 0.75, 0.5]
+        );
+    }
+}
@@ -9,10 +9,13 @@ use gfx::{
 };
 use serde::{Deserialize, Serialize};
 
+use amethyst_core::specs::prelude::{Component, DenseVecStorage};
+
 use crate::{
     pass::util::TextureType,
+    transparent::{Blend, ALPHA, PREMULTIPLIED},
     vertex::{Attribute, AttributeFormat, Attributes, VertexFormat, With},
-    Color,
+    Color, Rgba,
 };
 
 static VERT_SRC: &[u8] = include_bytes!("../shaders/vertex/sprite.glsl");
@@ -20,6 +23,53 @@ static FRAG_SRC: &[u8] = include_bytes!("../shaders/fragment/sprite.glsl");
 
 static TEXTURES: [TextureType; 1] = [TextureType::Albedo];
 
+/// How `DrawFlat2D` combines sprites with what is behind them
+///
+/// Add it to sprite and image entities that don't use straight alpha. A pass only draws the sprites
+/// with its own blending mode, set with `DrawFlat2D::with_blend`, so use one pass per mode.
+/// Sprites without this component are drawn with `Alpha`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SpriteBlend {
+    /// Regular alpha blending of textures with straight alpha.
+    Alpha,
+    /// Blending of textures whose colors are already multiplied by their alpha.
+    ///
+    /// Soft edges then composite without dark or bright fringes, even where the texture is
+    /// magnified or mipmapped. The `Rgba` tint is premultiplied too, so it can still fade the
+    /// sprite out.
+    Premultiplied,
+}
+
+impl SpriteBlend {
+    /// Returns the blend state of the mode.
+    pub(crate) fn blend(self) -> Blend {
+        match self {
+            SpriteBlend::Alpha => ALPHA,
+            SpriteBlend::Premultiplied => PREMULTIPLIED,
+        }
+    }
+
+    /// Returns the color the sprite's texture is multiplied with for a `tint`.
+    pub(crate) fn tint(self, tint: Rgba) -> [f32; 4] {
+        match self {
+            SpriteBlend::Alpha => [tint.0, tint.1, tint.2, tint.3],
+            SpriteBlend::Premultiplied => {
+                [tint.0 * tint.3, tint.1 * tint.3, tint.2 * tint.3, tint.3]
+            }
+        }
+    }
+}
+
+impl Default for SpriteBlend {
+    fn default() -> Self {
+        SpriteBlend::Alpha
+    }
+}
+
+impl Component for SpriteBlend {
+    type Storage = DenseVecStorage<Self>;
+}
+
 #[derive(Clone, Debug)]
 enum DirX {}
 impl Attribute for DirX {
@@ -148,3 +198,105 @@ impl With<Color> for SpriteInstance {
         format: Color::FORMAT,
     };
 }
+
+#[cfg(test)]
+mod tests {
+    //! Composites sprites with the blend states and tints `SpriteBlend` sets up, the way the
+    //! fixed function blending of the GPU does. The shader itself only multiplies the texel by
+    //! the tint.
+
+    use crate::{
+        transparent::{BlendChannel, BlendValue, Equation, Factor},
+        Rgba,
+    };
+
+    use super::SpriteBlend;
+
+    fn factor(factor: Factor, src: [f32; 4], dst: [f32; 4], i: usize) -> f32 {
+        let value = |value| match value {
+            BlendValue::SourceColor => src[i],
+            BlendValue::SourceAlpha => src[3],
+            BlendValue::DestColor => dst[i],
+            BlendValue::DestAlpha => dst[3],
+            v => unreachable!("blend value {:?} not used by SpriteBlend", v),
+        };
+        match factor {
+            Factor::Zero => 0.0,
+            Factor::One => 1.0,
+            Factor::ZeroPlus(v) => value(v),
+            Factor::OneMinus(v) => 1.0 - value(v),
+            f => unreachable!("blend factor {:?} not used by SpriteBlend", f),
+        }
+    }
+
+    fn blend_channel(channel: &BlendChannel, src: [f32; 4], dst: [f32; 4], i: usize) -> f32 {
+        assert_eq!(channel.equation, Equation::Add);
+        src[i] * factor(channel.source, src, dst, i)
+            + dst[i] * factor(channel.destination, src, dst, i)
+    }
+
+    /// Draws `sprites`, i.e. texel and tint pairs, over `background` like the GPU would.
+    fn composite(
+        mode: SpriteBlend,
+        background: [f32; 4],
+        sprites: &[([f32; 4], Rgba)],
+    ) -> [f32; 4] {
+        let blend = mode.blend();
+        sprites.iter().fold(background, |dst, &(texel, tint)| {
+            let tint = mode.tint(tint);
+            let mut src = texel;
+            for i in 0..4 {
+                src[i] *= tint[i];
+            }
+            [
+                blend_channel(&blend.color, src, dst, 0),
+                blend_channel(&blend.color, src, dst, 1),
+                blend_channel(&blend.color, src, dst, 2),
+                blend_channel(&blend.alpha, src, dst, 3),
+            ]
+        })
+    }
+
+    fn assert_rgb(actual: [f32; 4], expected: [f32; 3]) {
+        for i in 0..3 {
+            assert!(
+                (actual[i] - expected[i]).abs() < 1.0e-6,
+                "{:?} != {:?}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn overlapping_sprites_composite_alike() {
+        let background = [0.0, 0.0, 1.0, 1.0];
+        let expected = [0.25, 0.5, 0.25];
+
+        let straight = [
+            ([1.0, 0.0, 0.0, 0.5], Rgba::WHITE),
+            ([0.0, 1.0, 0.0, 0.5], Rgba::WHITE),
+        ];
+        assert_rgb(
+            composite(SpriteBlend::Alpha, background, &straight),
+            expected,
+        );
+
+        let premultiplied = [
+            ([0.5, 0.0, 0.0, 0.5], Rgba::WHITE),
+            ([0.0, 0.5, 0.0, 0.5], Rgba::WHITE),
+        ];
+        let color = composite(SpriteBlend::Premultiplied, background, &premultiplied);
+        assert_rgb(color, expected);
+        assert!((color[3] - 1.0).abs() < 1.0e-6);
+    }
+
+    #[test]
+    fn premultiplied_tint_fades_sprites() {
+        let background = [0.0, 0.0, 0.0, 0.0];
+        let sprite = [([1.0, 1.0, 1.0, 1.0], Rgba(1.0, 0.5, 0.0, 0.5))];
+        let color = composite(SpriteBlend::Premultiplied, background, &sprite);
+        assert_rgb(color, [0.5, 0.25, 0.0]);
+        assert!((color[3] - 0.5).abs() < 1.0e-6);
+    }
+}
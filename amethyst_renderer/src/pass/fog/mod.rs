@@ -0,0 +1,327 @@
+//! Depth based fog post-processing pass.
+
+use derivative::Derivative;
+use gfx::memory::Typed;
+use log::error;
+
+use amethyst_assets::AssetStorage;
+use amethyst_core::{
+    nalgebra::Matrix4,
+    specs::prelude::{Join, Read, ReadExpect, ReadStorage},
+    transform::GlobalTransform,
+};
+use amethyst_error::Error;
+
+use crate::{
+    cam::{ActiveCamera, Camera},
+    error,
+    mtl::MaterialDefaults,
+    pass::{
+        sky::SkyBox,
+        util::{fullscreen_slice, get_camera, target_view},
+    },
+    pipe::{
+        pass::{Pass, PassData},
+        Effect, NewEffect, Targets,
+    },
+    tex::{FilterMethod, SamplerInfo, Texture, WrapMode},
+    types::{Encoder, Factory, Sampler, ShaderResourceView},
+};
+
+static VERT_SRC: &[u8] = include_bytes!("../shaders/vertex/fullscreen.glsl");
+static FRAG_SRC: &[u8] = include_bytes!("../shaders/fragment/fog.glsl");
+
+/// How the fog thickens with the distance from the camera
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FogMode {
+    /// No fog closer than `start`, rising evenly to full fog at `end`.
+    Linear {
+        /// Distance at which the fog starts
+        start: f32,
+        /// Distance at which the fog hides everything
+        end: f32,
+    },
+    /// Fog absorbing the fraction `density` of the remaining view per unit of distance.
+    Exponential {
+        /// How thick the fog is, `0.02` hides half the view after about 35 units.
+        density: f32,
+    },
+}
+
+impl Default for FogMode {
+    fn default() -> Self {
+        FogMode::Exponential { density: 0.02 }
+    }
+}
+
+impl FogMode {
+    /// Returns the `mode` and `fog_params` uniforms of `fog.glsl`.
+    fn uniforms(self) -> (f32, [f32; 2]) {
+        match self {
+            FogMode::Linear { start, end } => (0.0, [start, end]),
+            FogMode::Exponential { density } => (1.0, [density, 0.0]),
+        }
+    }
+}
+
+/// Blends fog into a rendered scene by the distance of each pixel to the camera
+///
+/// The pass reads the color and depth buffers of the target named in `new`, so the scene has to
+/// be drawn into a target with a depth buffer first, and draws the fogged scene into the stage's
+/// target.
+///
+/// Unless a color is set with `with_color`, the fog takes the color of the `SkyBox` in the
+/// direction of each pixel, so distant geometry fades seamlessly into the sky behind it.
+/// Everything beyond the horizon fade, a fraction of the far plane distance, fades into the fog
+/// completely, hiding where the geometry ends. Pixels at the maximum depth of `1.0` are taken to
+/// be sky and keep their color, so the sky mustn't be drawn closer than the far plane, see
+/// `DrawSkyBox::with_depth_write`.
+///
+/// ```ignore
+/// Pipeline::build()
+///     .with_target(Target::named("scene").with_depth_buf(true))
+///     .with_stage(
+///         Stage::with_target("scene")
+///             .clear_target([0.0, 0.0, 0.0, 1.0], 1.0)
+///             .with_pass(DrawShaded::<PosNormTex>::new())
+///             .with_pass(DrawSkyBox::new()),
+///     )
+///     .with_stage(Stage::with_backbuffer().with_pass(DrawFog::new("scene")))
+/// ```
+#[derive(Derivative, Clone)]
+#[derivative(Debug)]
+pub struct DrawFog {
+    input: String,
+    mode: FogMode,
+    color: Option<[f32; 4]>,
+    base_height: f32,
+    height_falloff: f32,
+    horizon_fade: f32,
+    #[derivative(Debug = "ignore")]
+    state: Option<FogState>,
+}
+
+#[derive(Clone)]
+struct FogState {
+    scene: ShaderResourceView<[f32; 4]>,
+    depth: ShaderResourceView<f32>,
+    sampler: Sampler,
+    sky_sampler: Sampler,
+}
+
+impl DrawFog {
+    /// Create instance of `DrawFog` pass reading the scene from the target named `input`
+    pub fn new<N: Into<String>>(input: N) -> Self {
+        DrawFog {
+            input: input.into(),
+            mode: FogMode::default(),
+            color: None,
+            base_height: 0.0,
+            height_falloff: 0.0,
+            horizon_fade: 0.8,
+            state: None,
+        }
+    }
+
+    /// Set how the fog thickens with distance, exponential with a density of 0.02 by default.
+    pub fn with_mode(mut self, mode: FogMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Give the fog a fixed linear color instead of the color of the sky.
+    ///
+    /// The alpha scales how much of the scene the fog covers at most, for both fixed and sky
+    /// colored fog.
+    pub fn with_color(mut self, color: [f32; 4]) -> Self {
+        self.color = Some(color);
+        self
+    }
+
+    /// Thin the fog out above `base_height`, to a third every `1.0 / falloff` units higher.
+    ///
+    /// A falloff of `0.0`, the default, keeps the fog equally thick at every height.
+    pub fn with_height_falloff(mut self, base_height: f32, falloff: f32) -> Self {
+        self.base_height = base_height;
+        self.height_falloff = falloff;
+        self
+    }
+
+    /// Set the fraction of the far plane distance beyond which everything fades into the fog
+    /// completely, 0.8 by default. `1.0` disables the fade.
+    pub fn with_horizon_fade(mut self, start: f32) -> Self {
+        self.horizon_fade = start;
+        self
+    }
+}
+
+impl<'a> PassData<'a> for DrawFog {
+    type Data = (
+        Read<'a, ActiveCamera>,
+        ReadStorage<'a, Camera>,
+        ReadStorage<'a, GlobalTransform>,
+        Read<'a, AssetStorage<Texture>>,
+        ReadExpect<'a, MaterialDefaults>,
+        ReadStorage<'a, SkyBox>,
+    );
+}
+
+impl Pass for DrawFog {
+    fn compile(&mut self, effect: NewEffect<'_>) -> Result<Effect, Error> {
+        use gfx::Factory;
+
+        let input = effect
+            .target(&self.input)
+            .ok_or_else(|| error::Error::NoSuchTarget(self.input.clone()))?;
+        let scene = target_view(input)
+            .ok_or_else(|| error::Error::TargetNotReadable(self.input.clone()))?;
+        let depth = input
+            .depth_buf()
+            .and_then(|db| db.as_input.clone())
+            .ok_or_else(|| error::Error::TargetNotReadable(self.input.clone()))?;
+        let sampler = effect
+            .factory
+            .create_sampler(SamplerInfo::new(FilterMethod::Scale, WrapMode::Clamp));
+        let sky_sampler = effect
+            .factory
+            .create_sampler(SamplerInfo::new(FilterMethod::Trilinear, WrapMode::Clamp));
+        self.state = Some(FogState {
+            scene,
+            depth,
+            sampler,
+            sky_sampler,
+        });
+
+        effect
+            .simple(VERT_SRC, FRAG_SRC)
+            .with_texture("scene")
+            .with_texture("depth")
+            .with_texture("sky")
+            .with_raw_global("inv_proj")
+            .with_raw_global("inv_view")
+            .with_raw_global("sky_rotation")
+            .with_raw_global("sky_tint")
+            .with_raw_global("use_sky")
+            .with_raw_global("fog_color")
+            .with_raw_global("mode")
+            .with_raw_global("fog_params")
+            .with_raw_global("base_height")
+            .with_raw_global("height_falloff")
+            .with_raw_global("horizon_fade")
+            .with_output("color", None)
+            .build()
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        _factory: Factory,
+        (
+            active,
+            camera,
+            global,
+            tex_storage,
+            material_defaults,
+            skybox,
+        ): <Self as PassData<'a>>::Data,
+    ) {
+        let state = match self.state {
+            Some(ref state) => state,
+            None => return,
+        };
+
+        let (proj, camera_transform) = get_camera(active, &camera, &global)
+            .map(|(camera, transform)| (camera.proj, transform.0))
+            .unwrap_or_else(|| (Matrix4::identity(), Matrix4::identity()));
+        let inv_proj = proj.try_inverse().unwrap_or_else(Matrix4::identity);
+
+        let sky = if self.color.is_none() {
+            (&skybox)
+                .join()
+                .next()
+                .and_then(|sky| tex_storage.get(&sky.texture).map(|tex| (sky, tex)))
+        } else {
+            None
+        };
+        // Without a sky the default albedo is bound in its place, so the sampler stays complete.
+        let sky_texture = sky
+            .map(|(_, tex)| tex)
+            .or_else(|| tex_storage.get(&material_defaults.0.albedo));
+        let sky_texture = match sky_texture {
+            Some(texture) => texture,
+            None => return,
+        };
+        let (sky_rotation, sky_tint) = sky
+            .map(|(sky, _)| {
+                let [r, g, b, a] = sky.tint;
+                let rotation = sky.orientation().inverse().to_homogeneous();
+                (
+                    rotation,
+                    [r * sky.exposure, g * sky.exposure, b * sky.exposure, a],
+                )
+            })
+            .unwrap_or_else(|| (Matrix4::identity(), [1.0; 4]));
+        let fog_color = self.color.unwrap_or([1.0; 4]);
+        let (mode, params) = self.mode.uniforms();
+
+        effect.data.textures.push(state.scene.raw().clone());
+        effect.data.samplers.push(state.sampler.clone());
+        effect.data.textures.push(state.depth.raw().clone());
+        effect.data.samplers.push(state.sampler.clone());
+        effect.data.textures.push(sky_texture.view().clone());
+        effect.data.samplers.push(state.sky_sampler.clone());
+
+        let inv_proj: [[f32; 4]; 4] = inv_proj.into();
+        let inv_view: [[f32; 4]; 4] = camera_transform.into();
+        let sky_rotation: [[f32; 4]; 4] = sky_rotation.into();
+        effect.update_global("inv_proj", inv_proj);
+        effect.update_global("inv_view", inv_view);
+        effect.update_global("sky_rotation", sky_rotation);
+        effect.update_global("sky_tint", sky_tint);
+        effect.update_global("use_sky", if sky.is_some() { 1.0 } else { 0.0 });
+        effect.update_global("fog_color", fog_color);
+        effect.update_global("mode", mode);
+        effect.update_global("fog_params", params);
+        effect.update_global("base_height", self.base_height);
+        effect.update_global("height_falloff", self.height_falloff);
+        effect.update_global("horizon_fade", self.horizon_fade);
+        effect.draw(&fullscreen_slice(), encoder);
+        effect.clear();
+    }
+
+    fn new_targets(&mut self, targets: &Targets) {
+        let state = match self.state {
+            Some(ref mut state) => state,
+            None => return,
+        };
+        let input = match targets.get(&self.input) {
+            Some(input) => input,
+            None => {
+                error!("Fog input target {:?} not found!", self.input);
+                return;
+            }
+        };
+        if let Some(scene) = target_view(input) {
+            state.scene = scene;
+        }
+        if let Some(depth) = input.depth_buf().and_then(|db| db.as_input.clone()) {
+            state.depth = depth;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FogMode;
+
+    #[test]
+    fn modes_pack_their_parameters() {
+        let linear = FogMode::Linear {
+            start: 10.0,
+            end: 50.0,
+        };
+        assert_eq!(linear.uniforms(), (0.0, [10.0, 50.0]));
+        assert_eq!(FogMode::default().uniforms(), (1.0, [0.02, 0.0]));
+    }
+}
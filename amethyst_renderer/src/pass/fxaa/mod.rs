@@ -0,0 +1,161 @@
+//! FXAA anti-aliasing post-processing pass.
+
+use derivative::Derivative;
+use gfx::memory::Typed;
+use log::error;
+
+use amethyst_error::Error;
+
+use crate::{
+    error,
+    pass::util::{fullscreen_slice, target_view},
+    pipe::{
+        pass::{Pass, PassData},
+        Effect, NewEffect, Targets,
+    },
+    tex::{FilterMethod, SamplerInfo, WrapMode},
+    types::{Encoder, Factory, Sampler, ShaderResourceView},
+};
+
+static VERT_SRC: &[u8] = include_bytes!("../shaders/vertex/fullscreen.glsl");
+static FRAG_SRC: &[u8] = include_bytes!("../shaders/fragment/fxaa.glsl");
+
+/// Smooths jagged edges of a rendered scene with fast approximate anti-aliasing
+///
+/// The scene has to be rendered into a separate pipeline target first, whose name is given to
+/// `new`; the smoothed image is drawn into the stage's target, usually the backbuffer. Unlike
+/// multisampling this needs no changes to the scene's targets, but it also softens textures a
+/// little.
+///
+/// FXAA expects colors in display range, so it belongs after `DrawTonemap` if that is used.
+///
+/// ```ignore
+/// Pipeline::build()
+///     .with_target(Target::named("scene").with_depth_buf(true))
+///     .with_stage(
+///         Stage::with_target("scene")
+///             .clear_target([0.0, 0.0, 0.0, 1.0], 1.0)
+///             .with_pass(DrawShaded::<PosNormTex>::new()),
+///     )
+///     .with_stage(Stage::with_backbuffer().with_pass(DrawFxaa::new("scene")))
+/// ```
+#[derive(Derivative, Clone)]
+#[derivative(Debug)]
+pub struct DrawFxaa {
+    input: String,
+    span_max: f32,
+    reduce_mul: f32,
+    edge_threshold: f32,
+    size: (u32, u32),
+    #[derivative(Debug = "ignore")]
+    source: Option<(ShaderResourceView<[f32; 4]>, Sampler)>,
+}
+
+impl DrawFxaa {
+    /// Create instance of `DrawFxaa` pass reading the scene from the target named `input`
+    pub fn new<N: Into<String>>(input: N) -> Self {
+        DrawFxaa {
+            input: input.into(),
+            span_max: 8.0,
+            reduce_mul: 1.0 / 8.0,
+            edge_threshold: 1.0 / 8.0,
+            size: (1, 1),
+            source: None,
+        }
+    }
+
+    /// Set the longest distance in texels that is searched along an edge, 8.0 by default.
+    ///
+    /// Longer searches smooth edges that are close to horizontal or vertical better.
+    pub fn with_span_max(mut self, span_max: f32) -> Self {
+        self.span_max = span_max;
+        self
+    }
+
+    /// Set how much the search is shortened in bright areas, 1/8 by default.
+    ///
+    /// Lower values blend more sub-pixel detail, making the image softer.
+    pub fn with_subpixel_reduce(mut self, reduce_mul: f32) -> Self {
+        self.reduce_mul = reduce_mul;
+        self
+    }
+
+    /// Set the minimum local contrast, relative to the brightest neighbour, that is treated as an
+    /// edge, 1/8 by default.
+    ///
+    /// Higher values are faster and keep more texture detail, but leave faint edges aliased.
+    pub fn with_edge_threshold(mut self, edge_threshold: f32) -> Self {
+        self.edge_threshold = edge_threshold;
+        self
+    }
+}
+
+impl<'a> PassData<'a> for DrawFxaa {
+    type Data = ();
+}
+
+impl Pass for DrawFxaa {
+    fn compile(&mut self, effect: NewEffect<'_>) -> Result<Effect, Error> {
+        use gfx::Factory;
+
+        let input = effect
+            .target(&self.input)
+            .ok_or_else(|| error::Error::NoSuchTarget(self.input.clone()))?;
+        let view =
+            target_view(input).ok_or_else(|| error::Error::TargetNotReadable(self.input.clone()))?;
+        let sampler = effect
+            .factory
+            .create_sampler(SamplerInfo::new(FilterMethod::Bilinear, WrapMode::Clamp));
+        self.size = input.size();
+        self.source = Some((view, sampler));
+
+        effect
+            .simple(VERT_SRC, FRAG_SRC)
+            .with_texture("source")
+            .with_raw_global("texel_size")
+            .with_raw_global("span_max")
+            .with_raw_global("reduce_mul")
+            .with_raw_global("edge_threshold")
+            .with_output("color", None)
+            .build()
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        _factory: Factory,
+        (): <Self as PassData<'a>>::Data,
+    ) {
+        let (view, sampler) = match self.source {
+            Some(ref source) => source,
+            None => return,
+        };
+
+        effect.data.textures.push(view.raw().clone());
+        effect.data.samplers.push(sampler.clone());
+        effect.update_global(
+            "texel_size",
+            [1.0 / self.size.0 as f32, 1.0 / self.size.1 as f32],
+        );
+        effect.update_global("span_max", self.span_max);
+        effect.update_global("reduce_mul", self.reduce_mul);
+        effect.update_global("edge_threshold", self.edge_threshold);
+        effect.draw(&fullscreen_slice(), encoder);
+        effect.clear();
+    }
+
+    fn new_targets(&mut self, targets: &Targets) {
+        let input = match targets.get(&self.input) {
+            Some(input) => input,
+            None => {
+                error!("FXAA input target {:?} not found!", self.input);
+                return;
+            }
+        };
+        self.size = input.size();
+        if let (Some(view), Some(source)) = (target_view(input), self.source.as_mut()) {
+            source.0 = view;
+        }
+    }
+}
@@ -0,0 +1,356 @@
+//! Screen-space light shafts from the sun of an `Atmosphere`.
+
+use derivative::Derivative;
+use gfx::memory::Typed;
+use log::error;
+
+use amethyst_core::{
+    nalgebra as na,
+    specs::prelude::{Join, Read, ReadStorage},
+    transform::GlobalTransform,
+};
+use amethyst_error::{Error, ResultExt};
+
+use crate::{
+    cam::{ActiveCamera, Camera},
+    error,
+    pass::{
+        sky::Atmosphere,
+        util::{fullscreen_slice, get_camera, target_view},
+    },
+    pipe::{
+        pass::{Pass, PassData},
+        Effect, NewEffect, Target, TargetBuilder, Targets,
+    },
+    tex::{FilterMethod, SamplerInfo, WrapMode},
+    types::{Encoder, Factory, Sampler, ShaderResourceView},
+};
+
+static VERT_SRC: &[u8] = include_bytes!("../shaders/vertex/fullscreen.glsl");
+static EXTRACT_SRC: &[u8] = include_bytes!("../shaders/fragment/bloom_extract.glsl");
+static RAYS_SRC: &[u8] = include_bytes!("../shaders/fragment/godrays.glsl");
+static COMPOSITE_SRC: &[u8] = include_bytes!("../shaders/fragment/bloom_composite.glsl");
+
+/// Adds shafts of light shining from the sun of an `Atmosphere` past the objects in front of it
+///
+/// Like `DrawBloom`, the scene has to be rendered into a separate pipeline target first, whose
+/// name is given to `new`. The pass extracts the pixels brighter than the threshold, usually the
+/// sun disc and the sky around it, into a half resolution buffer and blurs them radially towards
+/// the sun's position on the screen. The scene is drawn with the rays added on top into the
+/// stage's target.
+///
+/// The rays fade out as the sun leaves the screen or sets, and are left out while no camera or
+/// `Atmosphere` with `godrays` enabled exists. The scene is copied through unchanged then.
+#[derive(Derivative, Clone)]
+#[derivative(Debug)]
+pub struct DrawGodrays {
+    input: String,
+    threshold: f32,
+    intensity: f32,
+    samples: u32,
+    density: f32,
+    decay: f32,
+    #[derivative(Debug = "ignore")]
+    state: Option<GodraysState>,
+}
+
+#[derive(Clone)]
+struct GodraysState {
+    extract: Effect,
+    rays: Effect,
+    bright: Target,
+    blurred: Target,
+    scene: ShaderResourceView<[f32; 4]>,
+    size: (u32, u32),
+    resized: bool,
+    sampler: Sampler,
+}
+
+impl DrawGodrays {
+    /// Create instance of `DrawGodrays` pass reading the scene from the target named `input`
+    pub fn new<N: Into<String>>(input: N) -> Self {
+        DrawGodrays {
+            input: input.into(),
+            threshold: 1.0,
+            intensity: 0.5,
+            samples: 64,
+            density: 0.9,
+            decay: 0.96,
+            state: None,
+        }
+    }
+
+    /// Set the brightness above which pixels cast rays, 1.0 by default.
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Set how strongly the rays are added to the scene, 0.5 by default. `0.0` turns them off.
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    /// Set how many samples each pixel takes along its way to the sun, 64 by default.
+    ///
+    /// Fewer samples show up as bands in the rays, more cost fill rate.
+    pub fn with_samples(mut self, samples: u32) -> Self {
+        self.samples = samples.max(1);
+        self
+    }
+
+    /// Set the fraction of the way to the sun the samples cover, 0.9 by default, and how much
+    /// each sample counts less than the one before it, 0.96 by default.
+    ///
+    /// Lower decays give shorter rays.
+    pub fn with_falloff(mut self, density: f32, decay: f32) -> Self {
+        self.density = density;
+        self.decay = decay;
+        self
+    }
+}
+
+/// Returns where the sun in `direction` appears on the screen, from `(0, 0)` at the bottom left
+/// to `(1, 1)` at the top right, or `None` if it is behind the camera.
+///
+/// The sun is infinitely far away, so only the rotation of `view` matters.
+fn sun_screen_position(
+    proj: &na::Matrix4<f32>,
+    view: &na::Matrix4<f32>,
+    direction: &na::Vector3<f32>,
+) -> Option<[f32; 2]> {
+    let clip = proj * view * direction.to_homogeneous();
+    if clip.w <= 0.0 {
+        return None;
+    }
+    Some([clip.x / clip.w * 0.5 + 0.5, clip.y / clip.w * 0.5 + 0.5])
+}
+
+/// Returns how strongly the rays of a sun at `position` on the screen are drawn, fading out over
+/// half a screen beyond the edges.
+fn ray_visibility(position: [f32; 2]) -> f32 {
+    let outside = position
+        .iter()
+        .map(|p| (-p).max(p - 1.0).max(0.0))
+        .fold(0.0f32, f32::max);
+    (1.0 - outside * 2.0).max(0.0)
+}
+
+impl<'a> PassData<'a> for DrawGodrays {
+    type Data = (
+        Read<'a, ActiveCamera>,
+        ReadStorage<'a, Camera>,
+        ReadStorage<'a, GlobalTransform>,
+        ReadStorage<'a, Atmosphere>,
+    );
+}
+
+impl Pass for DrawGodrays {
+    fn compile(&mut self, mut effect: NewEffect<'_>) -> Result<Effect, Error> {
+        use gfx::Factory;
+
+        let input = effect
+            .target(&self.input)
+            .ok_or_else(|| error::Error::NoSuchTarget(self.input.clone()))?;
+        let scene = target_view(input)
+            .ok_or_else(|| error::Error::TargetNotReadable(self.input.clone()))?;
+        let size = input.size();
+        let (bright, blurred) = godrays_targets(effect.factory, size)?;
+
+        let extract = effect
+            .simple_into(&bright, VERT_SRC, EXTRACT_SRC)
+            .with_texture("source")
+            .with_raw_global("threshold")
+            .with_output("color", None)
+            .build()?;
+        let rays = effect
+            .simple_into(&blurred, VERT_SRC, RAYS_SRC)
+            .with_texture("source")
+            .with_raw_global("sun_position")
+            .with_raw_global("density")
+            .with_raw_global("decay")
+            .with_raw_global("samples")
+            .with_output("color", None)
+            .build()?;
+        let sampler = effect
+            .factory
+            .create_sampler(SamplerInfo::new(FilterMethod::Bilinear, WrapMode::Clamp));
+
+        self.state = Some(GodraysState {
+            extract,
+            rays,
+            bright,
+            blurred,
+            scene,
+            size,
+            resized: false,
+            sampler,
+        });
+
+        effect
+            .simple(VERT_SRC, COMPOSITE_SRC)
+            .with_texture("scene")
+            .with_texture("bloom")
+            .with_raw_global("intensity")
+            .with_output("color", None)
+            .build()
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        mut factory: Factory,
+        (active, camera, global, atmosphere): <Self as PassData<'a>>::Data,
+    ) {
+        let state = match self.state {
+            Some(ref mut state) => state,
+            None => return,
+        };
+
+        if state.resized {
+            match godrays_targets(&mut factory, state.size) {
+                Ok((bright, blurred)) => {
+                    state.bright = bright;
+                    state.blurred = blurred;
+                    state.extract.retarget(&state.bright);
+                    state.rays.retarget(&state.blurred);
+                    state.resized = false;
+                }
+                Err(err) => {
+                    error!("Failed to resize the godrays targets: {}", err);
+                    return;
+                }
+            }
+        }
+
+        let camera = get_camera(active, &camera, &global);
+        let sun = (&atmosphere)
+            .join()
+            .find(|sky| sky.godrays)
+            .map(Atmosphere::sun);
+        let rays = match (camera, sun) {
+            (Some((camera, transform)), Some(sun)) => transform
+                .0
+                .try_inverse()
+                .and_then(|view| sun_screen_position(&camera.proj, &view, &sun))
+                .map(|position| {
+                    // Like the sky, the rays fade out while the sun sets.
+                    let sunset = (sun.y * 10.0 + 1.0).max(0.0).min(1.0);
+                    (position, ray_visibility(position) * sunset)
+                }),
+            _ => None,
+        };
+        let scene = state.scene.raw().clone();
+        let intensity = rays.map_or(0.0, |(_, visibility)| self.intensity * visibility);
+
+        let mut bloom = scene.clone();
+        if let Some((position, _)) = rays.filter(|_| intensity > 0.0) {
+            state.extract.data.textures.push(scene.clone());
+            state.extract.data.samplers.push(state.sampler.clone());
+            state.extract.update_global("threshold", self.threshold);
+            state.extract.draw(&fullscreen_slice(), encoder);
+            state.extract.clear();
+
+            let bright = match state
+                .bright
+                .color_buf(0)
+                .and_then(|cb| cb.as_input.as_ref())
+            {
+                Some(bright) => bright.raw().clone(),
+                None => return,
+            };
+            state.rays.data.textures.push(bright);
+            state.rays.data.samplers.push(state.sampler.clone());
+            state.rays.update_global("sun_position", position);
+            state.rays.update_global("density", self.density);
+            state.rays.update_global("decay", self.decay);
+            state.rays.update_global("samples", self.samples as f32);
+            state.rays.draw(&fullscreen_slice(), encoder);
+            state.rays.clear();
+
+            bloom = match state
+                .blurred
+                .color_buf(0)
+                .and_then(|cb| cb.as_input.as_ref())
+            {
+                Some(blurred) => blurred.raw().clone(),
+                None => return,
+            };
+        }
+
+        // Without rays the scene is bound in their place and added with no weight.
+        effect.data.textures.push(scene);
+        effect.data.samplers.push(state.sampler.clone());
+        effect.data.textures.push(bloom);
+        effect.data.samplers.push(state.sampler.clone());
+        effect.update_global("intensity", intensity);
+        effect.draw(&fullscreen_slice(), encoder);
+        effect.clear();
+    }
+
+    fn new_targets(&mut self, targets: &Targets) {
+        let state = match self.state {
+            Some(ref mut state) => state,
+            None => return,
+        };
+        let input = match targets.get(&self.input) {
+            Some(input) => input,
+            None => {
+                error!("Godrays input target {:?} not found!", self.input);
+                return;
+            }
+        };
+        if let Some(scene) = target_view(input) {
+            state.scene = scene;
+        }
+        if input.size() != state.size {
+            state.size = input.size();
+            state.resized = true;
+        }
+    }
+}
+
+/// Creates the half resolution buffers for the bright pass and the rays.
+fn godrays_targets(factory: &mut Factory, size: (u32, u32)) -> Result<(Target, Target), Error> {
+    let half = ((size.0 / 2).max(1), (size.1 / 2).max(1));
+    let (_, bright) = TargetBuilder::new("godrays_bright")
+        .build(factory, half)
+        .with_context(|_| error::Error::CreateTextureError)?;
+    let (_, blurred) = TargetBuilder::new("godrays_blurred")
+        .build(factory, half)
+        .with_context(|_| error::Error::CreateTextureError)?;
+    Ok((bright, blurred))
+}
+
+#[cfg(test)]
+mod tests {
+    use amethyst_core::nalgebra as na;
+
+    use super::{ray_visibility, sun_screen_position};
+
+    #[test]
+    fn sun_projects_onto_the_screen() {
+        let proj = na::Perspective3::new(1.0, 1.0, 0.1, 100.0).to_homogeneous();
+        // The camera looks down -Z from far away, which doesn't move the sun.
+        let view = na::Matrix4::new_translation(&na::Vector3::new(50.0, 0.0, -30.0));
+
+        let ahead = sun_screen_position(&proj, &view, &na::Vector3::new(0.0, 0.0, -1.0));
+        let [x, y] = ahead.unwrap();
+        assert!((x - 0.5).abs() < 1e-6 && (y - 0.5).abs() < 1e-6);
+
+        let [_, y] = sun_screen_position(&proj, &view, &na::Vector3::new(0.0, 0.2, -1.0)).unwrap();
+        assert!(y > 0.5);
+
+        assert!(sun_screen_position(&proj, &view, &na::Vector3::new(0.0, 0.0, 1.0)).is_none());
+    }
+
+    #[test]
+    fn rays_fade_beyond_the_screen() {
+        assert_eq!(ray_visibility([0.3, 0.9]), 1.0);
+        assert!((ray_visibility([1.25, 0.5]) - 0.5).abs() < 1e-6);
+        assert!((ray_visibility([0.5, -0.25]) - 0.5).abs() < 1e-6);
+        assert_eq!(ray_visibility([-1.0, 0.5]), 0.0);
+    }
+}
@@ -1,24 +1,64 @@
 //! Different kinds of render passes.
 //
 pub use self::{
+    bloom::DrawBloom,
+    color_grading::DrawColorGrading,
     debug_lines::*,
+    decal::{Decal, DrawDecals},
+    deferred::{DrawDeferredLighting, DrawGBuffer, GBUFFER_COLOR_BUFS},
+    depth::DrawDepth,
     flat::*,
     flat2d::*,
+    fog::{DrawFog, FogMode},
+    fxaa::DrawFxaa,
+    godrays::DrawGodrays,
+    outline::{DrawOutline, Selected},
+    particles::{DrawParticles, Particle, ParticleBlend, Particles},
     pbm::*,
+    probe::{DrawReflectionProbes, ReflectionProbe},
+    sdf_text::{DrawSdfText, SdfFont, SdfGlyph, SdfGlyphQuad, SdfText},
     shaded::*,
+    shadow::{DrawShadowMap, ShadowCaster, ShadowReceiver, ShadowSettings},
     skinning::set_skinning_buffers,
     sky::*,
     skybox::*,
-    util::{get_camera, set_vertex_args},
+    ssao::DrawSsao,
+    tonemap::{AutoExposure, DrawTonemap, TonemapOperator},
+    uniform::{EntityUniform, NoUniform},
+    util::{get_camera, get_cameras, in_frustum, set_vertex_args, sort_back_to_front},
+    wireframe::*,
 };
 
+pub(crate) use self::{
+    probe::is_capture_cubemap,
+    sky::filter_cubemap,
+    util::{fullscreen_slice, target_view},
+};
+
+mod bloom;
+mod color_grading;
 mod debug_lines;
+mod decal;
+mod deferred;
+mod depth;
 mod flat;
 mod flat2d;
+mod fog;
+mod fxaa;
+mod godrays;
+mod outline;
+mod particles;
 mod pbm;
+mod probe;
+mod sdf_text;
 mod shaded;
 mod shaded_util;
+mod shadow;
 mod skinning;
 mod sky;
 mod skybox;
+mod ssao;
+mod tonemap;
+mod uniform;
 mod util;
+mod wireframe;
@@ -0,0 +1,152 @@
+//! Outline pass
+
+use std::marker::PhantomData;
+
+use derivative::Derivative;
+use gfx::pso::buffer::ElemStride;
+
+use amethyst_assets::AssetStorage;
+use amethyst_core::{
+    specs::prelude::{Join, Read, ReadStorage},
+    transform::GlobalTransform,
+};
+use amethyst_error::Error;
+
+use crate::{
+    cam::{ActiveCamera, Camera},
+    hidden::{Hidden, HiddenPropagate},
+    mesh::{Mesh, MeshHandle},
+    pass::util::{get_camera, set_attribute_buffers, set_vertex_args, setup_vertex_args},
+    pipe::{
+        pass::{Pass, PassData},
+        DepthMode, Effect, NewEffect,
+    },
+    transparent::{ColorMask, ALPHA},
+    types::{Encoder, Factory},
+    vertex::{Normal, Position, Query},
+    Rgba,
+};
+
+use super::*;
+
+/// Draw a silhouette around the meshes of entities with a `Selected` component
+///
+/// Each selected mesh is drawn again as a hull, with its vertices pushed out along their
+/// normals by the outline thickness and only its back faces kept. The hull is depth tested but
+/// does not write depth, so the selected mesh itself covers the inside of the hull and only a
+/// rim of the outline color remains around it, while unselected geometry in front still hides
+/// the outline. Add the pass after the passes drawing the scene into the same target.
+///
+/// The hull is only closed if vertices on hard edges share their normals, so meshes with split
+/// normals get gaps at their corners.
+///
+/// # Type Parameters:
+///
+/// * `V`: `VertexFormat`
+#[derive(Derivative, Clone, Debug, PartialEq)]
+#[derivative(Default(bound = "V: Query<(Position, Normal)>"))]
+pub struct DrawOutline<V> {
+    _pd: PhantomData<V>,
+    #[derivative(Default(value = "Rgba(1.0, 0.6, 0.0, 1.0)"))]
+    color: Rgba,
+    #[derivative(Default(value = "0.02"))]
+    thickness: f32,
+}
+
+impl<V> DrawOutline<V>
+where
+    V: Query<(Position, Normal)>,
+{
+    /// Create instance of `DrawOutline` pass
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Set the color of the outline, orange by default. The alpha blends it with the scene.
+    pub fn with_color(mut self, color: Rgba) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Set the width of the outline in world units, 0.02 by default.
+    pub fn with_thickness(mut self, thickness: f32) -> Self {
+        self.thickness = thickness;
+        self
+    }
+}
+
+impl<'a, V> PassData<'a> for DrawOutline<V>
+where
+    V: Query<(Position, Normal)>,
+{
+    type Data = (
+        Read<'a, ActiveCamera>,
+        ReadStorage<'a, Camera>,
+        Read<'a, AssetStorage<Mesh>>,
+        ReadStorage<'a, Hidden>,
+        ReadStorage<'a, HiddenPropagate>,
+        ReadStorage<'a, MeshHandle>,
+        ReadStorage<'a, GlobalTransform>,
+        ReadStorage<'a, Selected>,
+    );
+}
+
+impl<V> Pass for DrawOutline<V>
+where
+    V: Query<(Position, Normal)>,
+{
+    fn compile(&mut self, effect: NewEffect<'_>) -> Result<Effect, Error> {
+        let mut builder = effect.simple(VERT_SRC, FRAG_SRC);
+        setup_vertex_args(&mut builder);
+        builder
+            .with_front_face_culling()
+            .with_raw_vertex_buffer(V::QUERIED_ATTRIBUTES, V::size() as ElemStride, 0)
+            .with_raw_global("outline_color")
+            .with_raw_global("thickness")
+            .with_blended_output(
+                "color",
+                ColorMask::all(),
+                ALPHA,
+                Some(DepthMode::LessEqualTest),
+            );
+        builder.build()
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        _factory: Factory,
+        (
+            active,
+            camera,
+            mesh_storage,
+            hidden,
+            hidden_prop,
+            mesh,
+            global,
+            selected,
+        ): <Self as PassData<'a>>::Data,
+    ) {
+        let camera = get_camera(active, &camera, &global);
+        let color: [f32; 4] = self.color.into();
+
+        for (mesh, global, _, _, _) in (&mesh, &global, &selected, !&hidden, !&hidden_prop).join() {
+            let mesh = match mesh_storage.get(mesh) {
+                Some(mesh) => mesh,
+                None => continue,
+            };
+
+            if !set_attribute_buffers(effect, mesh, &[V::QUERIED_ATTRIBUTES]) {
+                effect.clear();
+                continue;
+            }
+
+            set_vertex_args(effect, encoder, camera, global, Rgba::WHITE);
+            effect.update_global("outline_color", color);
+            effect.update_global("thickness", self.thickness);
+            effect.draw(mesh.slice(), encoder);
+            effect.clear();
+        }
+    }
+}
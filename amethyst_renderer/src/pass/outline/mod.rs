@@ -0,0 +1,19 @@
+pub use self::interleaved::DrawOutline;
+
+use amethyst_core::specs::{prelude::Component, storage::NullStorage};
+
+mod interleaved;
+
+static VERT_SRC: &[u8] = include_bytes!("../shaders/vertex/outline.glsl");
+static FRAG_SRC: &[u8] = include_bytes!("../shaders/fragment/outline.glsl");
+
+/// Marks an entity's mesh to be outlined by `DrawOutline`.
+///
+/// Adding or removing the component toggles the outline of that entity at runtime, e.g. to
+/// highlight the current selection. The mesh itself is still drawn by the regular passes.
+#[derive(Clone, Debug, Default)]
+pub struct Selected;
+
+impl Component for Selected {
+    type Storage = NullStorage<Self>;
+}
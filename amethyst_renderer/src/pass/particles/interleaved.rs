@@ -0,0 +1,249 @@
+//! Forward drawing pass for camera facing particles.
+
+use std::cmp::Ordering;
+
+use derivative::Derivative;
+use gfx::{memory::Typed, pso::buffer::ElemStride};
+use glsl_layout::Uniform;
+use log::error;
+
+use amethyst_assets::AssetStorage;
+use amethyst_core::{
+    nalgebra::{Matrix4, Vector3},
+    specs::prelude::{Join, Read, ReadExpect, ReadStorage},
+    transform::GlobalTransform,
+};
+use amethyst_error::Error;
+
+use crate::{
+    cam::{ActiveCamera, Camera},
+    error,
+    hidden::{Hidden, HiddenPropagate},
+    mtl::{MaterialDefaults, MissingTextures},
+    pass::util::{add_texture, get_camera, set_view_args, ViewArgs},
+    pipe::{
+        pass::{Pass, PassData},
+        DepthMode, Effect, NewEffect, Targets,
+    },
+    tex::Texture,
+    transparent::{ColorMask, ADDITIVE, ALPHA},
+    types::{Encoder, Factory, ShaderResourceView, Slice},
+    vertex::VertexFormat,
+};
+
+use super::*;
+
+/// Draws `Particles` as quads facing the camera
+///
+/// A pass only draws the particles with its own `ParticleBlend`, so use one pass per blending
+/// mode. The particles are depth tested against the scene but don't write depth, so the pass
+/// should run after the opaque geometry.
+///
+/// Soft particles, which fade out instead of cutting sharply into the geometry behind them,
+/// read the depth buffer of the target named in `with_soft_particles`. That target can't be the
+/// one the particles are drawn into, so the scene's depth has to be drawn into a separate
+/// target first, like for `DrawSsao`.
+#[derive(Derivative, Clone)]
+#[derivative(Debug, Default)]
+pub struct DrawParticles {
+    blend: ParticleBlend,
+    soft: Option<(String, f32)>,
+    #[derivative(Debug = "ignore")]
+    depth: Option<ShaderResourceView<f32>>,
+}
+
+impl DrawParticles {
+    /// Create instance of `DrawParticles` pass drawing alpha blended particles
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Draw the particles with the given blending mode instead.
+    pub fn with_blend(mut self, blend: ParticleBlend) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    /// Fade particles out over `distance` world units in front of the scene, reading its depth
+    /// from the target named `depth`.
+    pub fn with_soft_particles<N: Into<String>>(mut self, depth: N, distance: f32) -> Self {
+        self.soft = Some((depth.into(), distance));
+        self
+    }
+}
+
+impl<'a> PassData<'a> for DrawParticles {
+    type Data = (
+        Read<'a, ActiveCamera>,
+        ReadStorage<'a, Camera>,
+        ReadStorage<'a, GlobalTransform>,
+        Read<'a, AssetStorage<Texture>>,
+        ReadExpect<'a, MaterialDefaults>,
+        ReadExpect<'a, MissingTextures>,
+        ReadStorage<'a, Hidden>,
+        ReadStorage<'a, HiddenPropagate>,
+        ReadStorage<'a, Particles>,
+    );
+}
+
+impl Pass for DrawParticles {
+    fn compile(&mut self, mut effect: NewEffect<'_>) -> Result<Effect, Error> {
+        use std::mem;
+
+        if let Some((ref name, _)) = self.soft {
+            let target = effect
+                .target(name)
+                .ok_or_else(|| error::Error::NoSuchTarget(name.clone()))?;
+            let depth = target
+                .depth_buf()
+                .and_then(|db| db.as_input.clone())
+                .ok_or_else(|| error::Error::TargetNotReadable(name.clone()))?;
+            self.depth = Some(depth);
+        }
+
+        let blend = match self.blend {
+            ParticleBlend::Alpha => ALPHA,
+            ParticleBlend::Additive => ADDITIVE,
+        };
+        effect
+            .simple(VERT_SRC, FRAG_SRC)
+            .without_back_face_culling()
+            .with_raw_constant_buffer(
+                "ViewArgs",
+                mem::size_of::<<ViewArgs as Uniform>::Std140>(),
+                1,
+            )
+            .with_raw_vertex_buffer(Particle::ATTRIBUTES, Particle::size() as ElemStride, 1)
+            .with_raw_global("camera_right")
+            .with_raw_global("camera_up")
+            .with_raw_global("use_depth_fade")
+            .with_raw_global("fade_distance")
+            .with_raw_global("inv_proj")
+            .with_texture("albedo")
+            .with_texture("depth")
+            .with_blended_output(
+                "color",
+                ColorMask::all(),
+                blend,
+                Some(DepthMode::LessEqualTest),
+            )
+            .build()
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        mut factory: Factory,
+        (
+            active,
+            camera,
+            global,
+            tex_storage,
+            material_defaults,
+            missing,
+            hidden,
+            hidden_prop,
+            particles,
+        ): <Self as PassData<'a>>::Data,
+    ) {
+        use gfx::{buffer, memory::Bind, Factory};
+
+        let camera = get_camera(active, &camera, &global);
+        set_view_args(effect, encoder, camera);
+
+        let transform = camera.map(|(_, transform)| transform.0);
+        let transform = transform.unwrap_or_else(Matrix4::identity);
+        let axis = |i: usize| -> Vector3<f32> { transform.column(i).xyz().normalize() };
+        let (right, up, forward) = (axis(0), axis(1), -axis(2));
+        let eye = transform.column(3).xyz();
+        effect.update_global("camera_right", [right.x, right.y, right.z]);
+        effect.update_global("camera_up", [up.x, up.y, up.z]);
+
+        let inv_proj = camera
+            .and_then(|(camera, _)| camera.proj.try_inverse())
+            .unwrap_or_else(Matrix4::identity);
+        effect.update_global("inv_proj", Into::<[[f32; 4]; 4]>::into(inv_proj));
+        effect.update_global(
+            "use_depth_fade",
+            if self.depth.is_some() { 1.0 } else { 0.0 },
+        );
+        let fade_distance = self.soft.as_ref().map_or(1.0, |&(_, distance)| distance);
+        effect.update_global("fade_distance", fade_distance.max(std::f32::EPSILON));
+
+        let mut sorted = Vec::new();
+        for (particles, _, _) in (&particles, !&hidden, !&hidden_prop).join() {
+            if particles.blend != self.blend || particles.particles.is_empty() {
+                continue;
+            }
+
+            let data = match self.blend {
+                ParticleBlend::Alpha => {
+                    let depth = |p: &Particle| (Vector3::from(p.position) - eye).dot(&forward);
+                    sorted.clear();
+                    sorted.extend_from_slice(&particles.particles);
+                    sorted
+                        .sort_by(|a, b| depth(b).partial_cmp(&depth(a)).unwrap_or(Ordering::Equal));
+                    &sorted
+                }
+                ParticleBlend::Additive => &particles.particles,
+            };
+
+            let vbuf =
+                match factory.create_buffer_immutable(data, buffer::Role::Vertex, Bind::empty()) {
+                    Ok(vbuf) => vbuf,
+                    Err(err) => {
+                        error!("Unable to create the particle buffer: {}", err);
+                        return;
+                    }
+                };
+
+            // A texture that isn't loaded shows up as missing, no texture at all as the default.
+            let texture = match particles.texture {
+                Some(ref handle) => tex_storage
+                    .get(handle)
+                    .or_else(|| tex_storage.get(&missing.color)),
+                None => None,
+            }
+            .or_else(|| tex_storage.get(&material_defaults.0.albedo))
+            .unwrap();
+            add_texture(effect, texture);
+            // Without soft particles the depth texture is never read, so bind the albedo twice.
+            match self.depth {
+                Some(ref depth) => {
+                    effect.data.textures.push(depth.raw().clone());
+                    effect.data.samplers.push(texture.sampler().clone());
+                }
+                None => add_texture(effect, texture),
+            }
+            for _ in Particle::ATTRIBUTES {
+                effect.data.vertex_bufs.push(vbuf.raw().clone());
+            }
+
+            effect.draw(
+                &Slice {
+                    start: 0,
+                    end: 6,
+                    base_vertex: 0,
+                    instances: Some((data.len() as u32, 0)),
+                    buffer: Default::default(),
+                },
+                encoder,
+            );
+            effect.clear();
+        }
+    }
+
+    fn new_targets(&mut self, targets: &Targets) {
+        if let Some((ref name, _)) = self.soft {
+            match targets
+                .get(name)
+                .and_then(|target| target.depth_buf())
+                .and_then(|db| db.as_input.clone())
+            {
+                Some(depth) => self.depth = Some(depth),
+                None => error!("Soft particle depth target {:?} not found!", name),
+            }
+        }
+    }
+}
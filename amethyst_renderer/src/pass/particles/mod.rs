@@ -0,0 +1,158 @@
+//! Camera facing particle drawing pass.
+
+pub use self::interleaved::DrawParticles;
+
+mod interleaved;
+
+use gfx::{
+    format::{ChannelType, Format, SurfaceType},
+    pso::buffer::Element,
+    traits::Pod,
+};
+use serde::{Deserialize, Serialize};
+
+use amethyst_core::specs::prelude::{Component, DenseVecStorage};
+
+use crate::{
+    tex::TextureHandle,
+    vertex::{Attribute, AttributeFormat, Attributes, Color, Position, VertexFormat, With},
+};
+
+static VERT_SRC: &[u8] = include_bytes!("../shaders/vertex/particle.glsl");
+static FRAG_SRC: &[u8] = include_bytes!("../shaders/fragment/particle.glsl");
+
+#[derive(Clone, Debug)]
+enum Size {}
+impl Attribute for Size {
+    const NAME: &'static str = "size";
+    const FORMAT: Format = Format(SurfaceType::R32, ChannelType::Float);
+    const SIZE: u32 = 4;
+    type Repr = f32;
+}
+
+#[derive(Clone, Debug)]
+enum Rotation {}
+impl Attribute for Rotation {
+    const NAME: &'static str = "rotation";
+    const FORMAT: Format = Format(SurfaceType::R32, ChannelType::Float);
+    const SIZE: u32 = 4;
+    type Repr = f32;
+}
+
+/// A single quad drawn facing the camera by `DrawParticles`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Particle {
+    /// Center of the quad in world space.
+    pub position: [f32; 3],
+    /// Width and height of the quad in world units.
+    pub size: f32,
+    /// Color the texture is multiplied with, e.g. to fade the particle out over its lifetime.
+    pub color: [f32; 4],
+    /// Counter clockwise rotation of the quad around the view direction, in radians.
+    pub rotation: f32,
+}
+
+unsafe impl Pod for Particle {}
+
+impl Particle {
+    /// Creates an unrotated white particle.
+    pub fn new(position: [f32; 3], size: f32) -> Self {
+        Particle {
+            position,
+            size,
+            color: [1.0; 4],
+            rotation: 0.0,
+        }
+    }
+}
+
+impl VertexFormat for Particle {
+    const ATTRIBUTES: Attributes<'static> = &[
+        (Position::NAME, <Self as With<Position>>::FORMAT),
+        (Size::NAME, <Self as With<Size>>::FORMAT),
+        (Color::NAME, <Self as With<Color>>::FORMAT),
+        (Rotation::NAME, <Self as With<Rotation>>::FORMAT),
+    ];
+}
+
+impl With<Position> for Particle {
+    const FORMAT: AttributeFormat = Element {
+        offset: 0,
+        format: Position::FORMAT,
+    };
+}
+
+impl With<Size> for Particle {
+    const FORMAT: AttributeFormat = Element {
+        offset: Position::SIZE,
+        format: Size::FORMAT,
+    };
+}
+
+impl With<Color> for Particle {
+    const FORMAT: AttributeFormat = Element {
+        offset: Position::SIZE + Size::SIZE,
+        format: Color::FORMAT,
+    };
+}
+
+impl With<Rotation> for Particle {
+    const FORMAT: AttributeFormat = Element {
+        offset: Position::SIZE + Size::SIZE + Color::SIZE,
+        format: Rotation::FORMAT,
+    };
+}
+
+/// How `DrawParticles` combines particles with what is behind them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ParticleBlend {
+    /// Regular alpha blending, for smoke and dust.
+    ///
+    /// The particles of each `Particles` component are sorted back to front every frame.
+    Alpha,
+    /// Adds the particles' color weighted by their alpha, for fire, sparks and magic.
+    ///
+    /// The order of the particles doesn't matter, so they are not sorted.
+    Additive,
+}
+
+impl Default for ParticleBlend {
+    fn default() -> Self {
+        ParticleBlend::Alpha
+    }
+}
+
+/// Component holding the particles of an emitter, drawn by `DrawParticles`
+///
+/// Moving, spawning and removing the particles is left to the game's own systems, which
+/// update `particles` in place every frame.
+#[derive(Clone, Debug, Default)]
+pub struct Particles {
+    /// Particles to draw, in world space.
+    pub particles: Vec<Particle>,
+    /// Texture of every particle, or the default material's albedo if `None`.
+    pub texture: Option<TextureHandle>,
+    /// Blending mode; only `DrawParticles` passes with the same mode draw these particles.
+    pub blend: ParticleBlend,
+}
+
+impl Particles {
+    /// Creates an empty set of alpha blended particles with the given texture.
+    pub fn new(texture: Option<TextureHandle>) -> Self {
+        Particles {
+            texture,
+            ..Default::default()
+        }
+    }
+
+    /// Sets the blending mode.
+    pub fn with_blend(mut self, blend: ParticleBlend) -> Self {
+        self.blend = blend;
+        self
+    }
+}
+
+impl Component for Particles {
+    type Storage = DenseVecStorage<Self>;
+}
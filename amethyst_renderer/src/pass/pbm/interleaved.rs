@@ -8,27 +8,37 @@ use gfx_core::state::{Blend, ColorMask};
 
 use amethyst_assets::AssetStorage;
 use amethyst_core::{
-    specs::prelude::{Join, Read, ReadExpect, ReadStorage},
+    specs::prelude::{Entities, Join, Read, ReadExpect, ReadStorage},
     transform::GlobalTransform,
 };
 use amethyst_error::Error;
 
 use crate::{
-    cam::{ActiveCamera, Camera},
+    cam::{ActiveCamera, Camera, CameraTarget, StereoCamera, Viewport},
+    culling::{Aabb, BoundingSphere},
     hidden::{Hidden, HiddenPropagate},
     light::Light,
-    mesh::{Mesh, MeshHandle},
-    mtl::{Material, MaterialDefaults},
+    mesh::{LodMesh, LodView, Mesh, MeshHandle},
+    mtl::{Material, MaterialDefaults, MissingTextures},
     pass::{
+        probe::ReflectionProbe,
         shaded_util::{set_light_args, setup_light_buffers},
-        util::{default_transparency, draw_mesh, get_camera, setup_textures, setup_vertex_args},
+        shadow::{bind_shadows, ShadowInput, ShadowReceiver, ShadowSettings},
+        sky::SkyBox,
+        ssao::{bind_occlusion, OcclusionInput},
+        util::{
+            culling_frustum, default_transparency, draw_mesh, get_cameras, is_culled, select_lod,
+            set_alpha_cutoff, set_emission, set_view, setup_alpha_cutoff, setup_emission,
+            setup_textures, setup_vertex_args, sort_back_to_front,
+        },
     },
     pipe::{
         pass::{Pass, PassData},
-        DepthMode, Effect, NewEffect,
+        DepthMode, Effect, EffectBuilder, NewEffect, Targets,
     },
     resources::AmbientColor,
     tex::Texture,
+    transparent::{SortKey, Transparent},
     types::{Encoder, Factory},
     vertex::{Normal, Position, Query, Tangent, TexCoord},
     visibility::Visibility,
@@ -51,6 +61,14 @@ pub struct DrawPbm<V> {
     _pd: PhantomData<V>,
     #[derivative(Default(value = "default_transparency()"))]
     transparency: Option<(ColorMask, Blend, Option<DepthMode>)>,
+    frustum_culling: bool,
+    depth_prepass: bool,
+    transparent_effect: Option<Effect>,
+    shadows: Option<ShadowInput>,
+    ssao: Option<OcclusionInput>,
+    #[derivative(Default(value = "1.0"))]
+    environment_intensity: f32,
+    environment_fallback: Option<Texture>,
 }
 
 impl<V> DrawPbm<V>
@@ -88,6 +106,89 @@ where
         self.transparency = Some((mask, blend, depth));
         self
     }
+
+    /// Skip entities whose `BoundingSphere` or `Aabb` is out of the camera's view.
+    ///
+    /// Entities without bounds are always drawn.
+    pub fn with_frustum_culling(mut self) -> Self {
+        self.frustum_culling = true;
+        self
+    }
+
+    /// Only shade the surfaces whose depth `DrawDepth` drew into the target before this pass.
+    ///
+    /// The depth test is set to equal, so every pixel is shaded once no matter how many meshes
+    /// overlap it, which pays off in scenes with a lot of overdraw. Transparent entities are
+    /// not part of the prepass, so they are still drawn back to front with the depth test of
+    /// the transparency settings.
+    pub fn with_depth_prepass(mut self) -> Self {
+        self.depth_prepass = true;
+        self
+    }
+
+    /// Darken `ShadowReceiver`s using the shadow map that `DrawShadowMap` renders into the
+    /// target named `target`.
+    pub fn with_shadows<N: Into<String>>(mut self, target: N) -> Self {
+        self.shadows = Some(ShadowInput::new(target.into()));
+        self
+    }
+
+    /// Multiply the ambient light with the occlusion that `DrawSsao` draws into the target
+    /// named `target`.
+    pub fn with_ssao<N: Into<String>>(mut self, target: N) -> Self {
+        self.ssao = Some(OcclusionInput::new(target.into()));
+        self
+    }
+
+    /// Scale the light that the sky's environment map adds on top of the direct lights.
+    ///
+    /// The sky's contribution is darkened by ambient occlusion, the occlusion of `with_ssao`
+    /// and the shadow of `with_shadows`, so interiors aren't lit by the sky as much as the open.
+    /// Meshes within the radius of a `ReflectionProbe` reflect the probe's capture instead of
+    /// the sky, scaled the same way. Defaults to `1.0`.
+    pub fn with_environment_intensity(mut self, intensity: f32) -> Self {
+        self.environment_intensity = intensity;
+        self
+    }
+}
+
+impl<V> DrawPbm<V>
+where
+    V: Query<(Position, Normal, Tangent, TexCoord)>,
+{
+    /// Declares the inputs and output of the shader, with an equal depth test for the surfaces
+    /// of a depth prepass.
+    fn setup(&self, builder: &mut EffectBuilder<'_>, depth_prepass: bool) {
+        builder.with_raw_vertex_buffer(V::QUERIED_ATTRIBUTES, V::size() as ElemStride, 0);
+        setup_vertex_args(builder);
+        setup_light_buffers(builder);
+        setup_emission(builder);
+        setup_alpha_cutoff(builder);
+        // The environment map goes first so it can be added ahead of the material textures.
+        builder.with_texture("environment");
+        builder.with_raw_global("environment_intensity");
+        if self.shadows.is_some() {
+            ShadowInput::setup(builder);
+        }
+        if self.ssao.is_some() {
+            OcclusionInput::setup(builder);
+        }
+        setup_textures(builder, &TEXTURES);
+        let depth = |depth| {
+            if depth_prepass {
+                Some(DepthMode::EqualTest)
+            } else {
+                depth
+            }
+        };
+        match self.transparency {
+            Some((mask, blend, mode)) => {
+                builder.with_blended_output("color", mask, blend, depth(mode))
+            }
+            None => builder.with_output("color", depth(Some(DepthMode::LessEqualWrite))),
+        };
+        builder.with_two_sided_variant();
+    }
 }
 
 impl<'a, V> PassData<'a> for DrawPbm<V>
@@ -97,18 +198,32 @@ where
     type Data = (
         Read<'a, ActiveCamera>,
         ReadStorage<'a, Camera>,
+        ReadStorage<'a, Viewport>,
+        ReadStorage<'a, CameraTarget>,
+        ReadStorage<'a, StereoCamera>,
         Read<'a, AmbientColor>,
         Read<'a, AssetStorage<Mesh>>,
         Read<'a, AssetStorage<Texture>>,
         ReadExpect<'a, MaterialDefaults>,
+        ReadExpect<'a, MissingTextures>,
+        Entities<'a>,
         Option<Read<'a, Visibility>>,
+        ReadStorage<'a, Transparent>,
+        ReadStorage<'a, SortKey>,
         ReadStorage<'a, Hidden>,
         ReadStorage<'a, HiddenPropagate>,
         ReadStorage<'a, MeshHandle>,
+        ReadStorage<'a, LodMesh>,
         ReadStorage<'a, Material>,
         ReadStorage<'a, GlobalTransform>,
         ReadStorage<'a, Light>,
         ReadStorage<'a, Rgba>,
+        ReadStorage<'a, SkyBox>,
+        ReadStorage<'a, ReflectionProbe>,
+        Read<'a, ShadowSettings>,
+        ReadStorage<'a, ShadowReceiver>,
+        ReadStorage<'a, BoundingSphere>,
+        ReadStorage<'a, Aabb>,
     );
 }
 
@@ -116,16 +231,23 @@ impl<V> Pass for DrawPbm<V>
 where
     V: Query<(Position, Normal, Tangent, TexCoord)>,
 {
-    fn compile(&mut self, effect: NewEffect<'_>) -> Result<Effect, Error> {
-        let mut builder = effect.simple(VERT_SRC, FRAG_SRC);
-        builder.with_raw_vertex_buffer(V::QUERIED_ATTRIBUTES, V::size() as ElemStride, 0);
-        setup_vertex_args(&mut builder);
-        setup_light_buffers(&mut builder);
-        setup_textures(&mut builder, &TEXTURES);
-        match self.transparency {
-            Some((mask, blend, depth)) => builder.with_blended_output("color", mask, blend, depth),
-            None => builder.with_output("color", Some(DepthMode::LessEqualWrite)),
+    fn compile(&mut self, mut effect: NewEffect<'_>) -> Result<Effect, Error> {
+        if let Some(ref mut shadows) = self.shadows {
+            shadows.compile(&mut effect)?;
+        }
+        if let Some(ref mut ssao) = self.ssao {
+            ssao.compile(&mut effect)?;
+        }
+        self.environment_fallback = Some(environment_fallback(&mut effect.factory)?);
+        self.transparent_effect = if self.depth_prepass {
+            let mut builder = effect.simple_variant(VERT_SRC, FRAG_SRC);
+            self.setup(&mut builder, false);
+            Some(builder.build()?)
+        } else {
+            None
         };
+        let mut builder = effect.simple(VERT_SRC, FRAG_SRC);
+        self.setup(&mut builder, self.depth_prepass);
         builder.build()
     }
 
@@ -137,82 +259,138 @@ where
         (
             active,
             camera,
+            viewports,
+            camera_targets,
+            stereo_cameras,
             ambient,
             mesh_storage,
             tex_storage,
             material_defaults,
+            missing,
+            entities,
             visibility,
+            transparent,
+            sort_keys,
             hidden,
             hidden_prop,
             mesh,
+            lod_mesh,
             material,
             global,
             light,
             rgba,
+            skybox,
+            probes,
+            shadow_settings,
+            shadow_receiver,
+            bounding_sphere,
+            aabb,
         ): <Self as PassData<'a>>::Data,
     ) {
-        let camera = get_camera(active, &camera, &global);
-
-        set_light_args(effect, encoder, &light, &global, &ambient, camera);
-
-        match visibility {
-            None => {
-                for (mesh, material, global, rgba, _, _) in (
-                    &mesh,
-                    &material,
-                    &global,
-                    rgba.maybe(),
-                    !&hidden,
-                    !&hidden_prop,
-                )
-                    .join()
-                {
-                    draw_mesh(
-                        encoder,
-                        effect,
-                        false,
-                        mesh_storage.get(mesh),
-                        None,
-                        &tex_storage,
-                        Some(material),
-                        &material_defaults,
-                        rgba,
-                        camera,
-                        Some(global),
-                        &[V::QUERIED_ATTRIBUTES],
-                        &TEXTURES,
-                    );
-                }
+        let cameras = get_cameras(
+            active,
+            &camera,
+            &viewports,
+            &camera_targets,
+            &stereo_cameras,
+            &global,
+            effect.target_name(),
+        );
+        for (index, view) in cameras.iter().enumerate() {
+            let lod_view = LodView::new(effect.target_name(), index);
+            let frustum = culling_frustum(self.frustum_culling, view);
+            if let Some(ref mut transparent) = self.transparent_effect {
+                set_view(transparent, view);
             }
-            Some(ref visibility) => {
-                for (mesh, material, global, rgba, _) in (
-                    &mesh,
-                    &material,
-                    &global,
-                    rgba.maybe(),
-                    &visibility.visible_unordered,
-                )
-                    .join()
-                {
-                    draw_mesh(
-                        encoder,
-                        effect,
-                        false,
-                        mesh_storage.get(mesh),
-                        None,
-                        &tex_storage,
-                        Some(material),
-                        &material_defaults,
-                        rgba,
-                        camera,
-                        Some(global),
-                        &[V::QUERIED_ATTRIBUTES],
-                        &TEXTURES,
-                    );
+            let view = set_view(effect, view);
+            let camera = view.get();
+
+            for effect in Some(&mut *effect)
+                .into_iter()
+                .chain(&mut self.transparent_effect)
+            {
+                set_light_args(effect, encoder, &light, &global, &ambient, camera);
+                if let Some(ref shadows) = self.shadows {
+                    shadows.set_args(effect, &shadow_settings, &light);
                 }
+            }
+            let environment = Environment::new(
+                &skybox,
+                &probes,
+                &global,
+                &tex_storage,
+                &self.environment_fallback,
+                self.environment_intensity,
+            );
+
+            let mut ordered = match visibility {
+                None => {
+                    for ((mesh, lod), material, global, rgba, bounds, receiver, _, _, _) in (
+                        (&mesh, lod_mesh.maybe()),
+                        &material,
+                        &global,
+                        rgba.maybe(),
+                        (bounding_sphere.maybe(), aabb.maybe()),
+                        shadow_receiver.maybe(),
+                        !&transparent,
+                        !&hidden,
+                        !&hidden_prop,
+                    )
+                        .join()
+                    {
+                        if is_culled(&frustum, Some(global), bounds) {
+                            continue;
+                        }
+                        let mesh = select_lod(mesh, lod, lod_view, camera, Some(global));
+                        environment.bind(effect, Some(global));
+                        bind_shadows(effect, &self.shadows, receiver);
+                        bind_occlusion(effect, &self.ssao);
+                        set_emission(effect, Some(material));
+                        set_alpha_cutoff(effect, Some(material));
+                        draw_mesh(
+                            encoder,
+                            effect,
+                            false,
+                            mesh_storage.get(mesh),
+                            None,
+                            &tex_storage,
+                            Some(material),
+                            &material_defaults,
+                            &missing,
+                            rgba,
+                            camera,
+                            Some(global),
+                            &[V::QUERIED_ATTRIBUTES],
+                            &TEXTURES,
+                        );
+                    }
 
-                for entity in &visibility.visible_ordered {
-                    if let Some(mesh) = mesh.get(*entity) {
+                    (&*entities, &transparent, !&hidden, !&hidden_prop)
+                        .join()
+                        .map(|(entity, _, _, _)| entity)
+                        .collect::<Vec<_>>()
+                }
+                Some(ref visibility) => {
+                    for ((mesh, lod), material, global, rgba, bounds, receiver, _) in (
+                        (&mesh, lod_mesh.maybe()),
+                        &material,
+                        &global,
+                        rgba.maybe(),
+                        (bounding_sphere.maybe(), aabb.maybe()),
+                        shadow_receiver.maybe(),
+                        &visibility.visible_unordered,
+                    )
+                        .join()
+                    {
+                        if is_culled(&frustum, Some(global), bounds) {
+                            continue;
+                        }
+                        let mesh = select_lod(mesh, lod, lod_view, camera, Some(global));
+                        environment.bind(effect, Some(global));
+                        bind_shadows(effect, &self.shadows, receiver);
+                        bind_occlusion(effect, &self.ssao);
+                        set_emission(effect, Some(material));
+                        set_alpha_cutoff(effect, Some(material));
                         draw_mesh(
                             encoder,
                             effect,
@@ -220,17 +398,70 @@ where
                             mesh_storage.get(mesh),
                             None,
                             &tex_storage,
-                            material.get(*entity),
+                            Some(material),
                             &material_defaults,
-                            rgba.get(*entity),
+                            &missing,
+                            rgba,
                             camera,
-                            global.get(*entity),
+                            Some(global),
                             &[V::QUERIED_ATTRIBUTES],
                             &TEXTURES,
                         );
                     }
+
+                    visibility.visible_ordered.clone()
+                }
+            };
+
+            // The prepass leaves out transparent entities, so they are drawn with the depth test
+            // they would have without it.
+            let effect = self.transparent_effect.as_mut().unwrap_or(&mut *effect);
+            sort_back_to_front(&mut ordered, camera, &global, &sort_keys);
+            for entity in &ordered {
+                let bounds = (bounding_sphere.get(*entity), aabb.get(*entity));
+                if is_culled(&frustum, global.get(*entity), bounds) {
+                    continue;
+                }
+                if let Some(mesh) = mesh.get(*entity) {
+                    let mesh = select_lod(
+                        mesh,
+                        lod_mesh.get(*entity),
+                        lod_view,
+                        camera,
+                        global.get(*entity),
+                    );
+                    environment.bind(effect, global.get(*entity));
+                    bind_shadows(effect, &self.shadows, shadow_receiver.get(*entity));
+                    bind_occlusion(effect, &self.ssao);
+                    set_emission(effect, material.get(*entity));
+                    set_alpha_cutoff(effect, material.get(*entity));
+                    draw_mesh(
+                        encoder,
+                        effect,
+                        false,
+                        mesh_storage.get(mesh),
+                        None,
+                        &tex_storage,
+                        material.get(*entity),
+                        &material_defaults,
+                        &missing,
+                        rgba.get(*entity),
+                        camera,
+                        global.get(*entity),
+                        &[V::QUERIED_ATTRIBUTES],
+                        &TEXTURES,
+                    );
                 }
             }
         }
     }
+
+    fn new_targets(&mut self, targets: &Targets) {
+        if let Some(ref mut shadows) = self.shadows {
+            shadows.new_targets(targets);
+        }
+        if let Some(ref mut ssao) = self.ssao {
+            ssao.new_targets(targets);
+        }
+    }
 }
@@ -3,7 +3,28 @@ pub use self::{interleaved::DrawPbm, separate::DrawPbmSeparate};
 mod interleaved;
 mod separate;
 
-use crate::pass::util::TextureType;
+use std::cmp::Ordering;
+
+use gfx::texture::Kind;
+
+use amethyst_assets::AssetStorage;
+use amethyst_core::{
+    nalgebra::Vector3,
+    specs::prelude::{Join, ReadStorage},
+    transform::GlobalTransform,
+};
+use amethyst_error::Error;
+
+use crate::{
+    pass::{
+        probe::{is_capture_cubemap, ReflectionProbe},
+        sky::SkyBox,
+        util::{add_texture, TextureType},
+    },
+    pipe::Effect,
+    tex::{Texture, TextureBuilder},
+    types::Factory,
+};
 
 static VERT_SRC: &[u8] = include_bytes!("../shaders/vertex/basic.glsl");
 static FRAG_SRC: &[u8] = include_bytes!("../shaders/fragment/pbm.glsl");
@@ -17,3 +38,109 @@ static TEXTURES: [TextureType; 7] = [
     TextureType::Normal,
     TextureType::Albedo,
 ];
+
+/// Builds the 1×1 black cubemap bound when the scene has no environment map, so the
+/// `environment` sampler is always complete.
+fn environment_fallback(factory: &mut Factory) -> Result<Texture, Error> {
+    TextureBuilder::new([0u8; 24])
+        .with_kind(Kind::Cube(1))
+        .build(factory)
+}
+
+/// Environment maps for the specular reflections of one frame.
+///
+/// Meshes within the radius of a captured `ReflectionProbe` reflect the nearest such probe,
+/// all others the cubemap of the first loaded `SkyBox`. The reflections are scaled by
+/// `intensity`. Without a sky the fallback is bound and the reflections are switched off
+/// through `environment_intensity`, leaving the output as it was before environment maps
+/// existed.
+struct Environment<'a> {
+    sky: Option<&'a Texture>,
+    fallback: &'a Texture,
+    intensity: f32,
+    probes: Vec<(Vector3<f32>, f32, &'a Texture)>,
+}
+
+impl<'a> Environment<'a> {
+    fn new(
+        skybox: &ReadStorage<'_, SkyBox>,
+        probes: &ReadStorage<'_, ReflectionProbe>,
+        global: &ReadStorage<'_, GlobalTransform>,
+        tex_storage: &'a AssetStorage<Texture>,
+        fallback: &'a Option<Texture>,
+        intensity: f32,
+    ) -> Self {
+        let sky = skybox
+            .join()
+            .filter_map(|sky| tex_storage.get(&sky.texture))
+            .next();
+        // Probes whose cubemap hasn't been created yet still hold their placeholder.
+        let probes = (probes, global)
+            .join()
+            .filter_map(|(probe, global)| {
+                let texture = tex_storage.get(&probe.texture)?;
+                if !is_capture_cubemap(texture, probe.resolution) {
+                    return None;
+                }
+                Some((global.0.column(3).xyz(), probe.radius, texture))
+            })
+            .collect();
+        Environment {
+            sky,
+            fallback: fallback
+                .as_ref()
+                .expect("Pass doesn't seem to be compiled."),
+            intensity,
+            probes,
+        }
+    }
+
+    /// Binds the environment map of a mesh at `global`. It has to be added before the material
+    /// textures of every draw.
+    fn bind(&self, effect: &mut Effect, global: Option<&GlobalTransform>) {
+        let probe =
+            global.and_then(|global| nearest_probe(&self.probes, &global.0.column(3).xyz()));
+        let (texture, intensity) = match (probe, self.sky) {
+            (Some(probe), _) => (*probe, self.intensity),
+            (None, Some(sky)) => (sky, self.intensity),
+            (None, None) => (self.fallback, 0.0),
+        };
+        effect.update_global("environment_intensity", intensity);
+        add_texture(effect, texture);
+    }
+}
+
+/// Returns the nearest of `probes` whose radius contains `position`.
+fn nearest_probe<'p, T>(
+    probes: &'p [(Vector3<f32>, f32, T)],
+    position: &Vector3<f32>,
+) -> Option<&'p T> {
+    probes
+        .iter()
+        .map(|(center, radius, probe)| ((center - position).norm(), *radius, probe))
+        .filter(|&(distance, radius, _)| distance <= radius)
+        .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal))
+        .map(|(_, _, probe)| probe)
+}
+
+#[cfg(test)]
+mod tests {
+    use amethyst_core::nalgebra::Vector3;
+
+    use super::nearest_probe;
+
+    #[test]
+    fn nearest_containing_probe_is_picked() {
+        let probes = [
+            (Vector3::new(0.0, 0.0, 0.0), 10.0, "far"),
+            (Vector3::new(4.0, 0.0, 0.0), 2.0, "near"),
+            (Vector3::new(5.0, 0.0, 0.0), 0.5, "small"),
+        ];
+        let position = Vector3::new(4.0, 1.0, 0.0);
+        assert_eq!(nearest_probe(&probes, &position), Some(&"near"));
+        let position = Vector3::new(-3.0, 0.0, 0.0);
+        assert_eq!(nearest_probe(&probes, &position), Some(&"far"));
+        let position = Vector3::new(20.0, 0.0, 0.0);
+        assert_eq!(nearest_probe(&probes, &position), None);
+    }
+}
@@ -9,29 +9,39 @@ use thread_profiler::profile_scope;
 
 use amethyst_assets::AssetStorage;
 use amethyst_core::{
-    specs::prelude::{Join, Read, ReadExpect, ReadStorage},
+    specs::prelude::{Entities, Join, Read, ReadExpect, ReadStorage},
     transform::GlobalTransform,
 };
 use amethyst_error::Error;
 
 use crate::{
-    cam::{ActiveCamera, Camera},
+    cam::{ActiveCamera, Camera, CameraTarget, StereoCamera, Viewport},
+    culling::{Aabb, BoundingSphere},
     hidden::{Hidden, HiddenPropagate},
     light::Light,
-    mesh::{Mesh, MeshHandle},
-    mtl::{Material, MaterialDefaults},
+    mesh::{LodMesh, LodView, Mesh, MeshHandle},
+    mtl::{Material, MaterialDefaults, MissingTextures},
     pass::{
+        probe::ReflectionProbe,
         shaded_util::{set_light_args, setup_light_buffers},
+        shadow::{bind_shadows, ShadowInput, ShadowReceiver, ShadowSettings},
+        sky::SkyBox,
         skinning::{create_skinning_effect, setup_skinning_buffers},
-        util::{default_transparency, draw_mesh, get_camera, setup_textures, setup_vertex_args},
+        ssao::{bind_occlusion, OcclusionInput},
+        util::{
+            culling_frustum, default_transparency, draw_mesh, get_cameras, is_culled, select_lod,
+            set_alpha_cutoff, set_emission, set_view, setup_alpha_cutoff, setup_emission,
+            setup_textures, setup_vertex_args, sort_back_to_front,
+        },
     },
     pipe::{
         pass::{Pass, PassData},
-        DepthMode, Effect, NewEffect,
+        DepthMode, Effect, NewEffect, Targets,
     },
     resources::AmbientColor,
     skinning::JointTransforms,
     tex::Texture,
+    transparent::{SortKey, Transparent},
     types::{Encoder, Factory},
     vertex::{Attributes, Normal, Position, Separate, Tangent, TexCoord, VertexFormat},
     visibility::Visibility,
@@ -57,6 +67,12 @@ pub struct DrawPbmSeparate {
     skinning: bool,
     #[derivative(Default(value = "default_transparency()"))]
     transparency: Option<(ColorMask, Blend, Option<DepthMode>)>,
+    frustum_culling: bool,
+    shadows: Option<ShadowInput>,
+    ssao: Option<OcclusionInput>,
+    #[derivative(Default(value = "1.0"))]
+    environment_intensity: f32,
+    environment_fallback: Option<Texture>,
 }
 
 impl DrawPbmSeparate {
@@ -97,33 +113,87 @@ impl DrawPbmSeparate {
         self.transparency = Some((mask, blend, depth));
         self
     }
+
+    /// Skip entities whose `BoundingSphere` or `Aabb` is out of the camera's view.
+    ///
+    /// Entities without bounds are always drawn.
+    pub fn with_frustum_culling(mut self) -> Self {
+        self.frustum_culling = true;
+        self
+    }
+
+    /// Darken `ShadowReceiver`s using the shadow map that `DrawShadowMap` renders into the
+    /// target named `target`.
+    pub fn with_shadows<N: Into<String>>(mut self, target: N) -> Self {
+        self.shadows = Some(ShadowInput::new(target.into()));
+        self
+    }
+
+    /// Multiply the ambient light with the occlusion that `DrawSsao` draws into the target
+    /// named `target`.
+    pub fn with_ssao<N: Into<String>>(mut self, target: N) -> Self {
+        self.ssao = Some(OcclusionInput::new(target.into()));
+        self
+    }
+
+    /// Scale the light that the sky's environment map adds on top of the direct lights.
+    ///
+    /// The sky's contribution is darkened by ambient occlusion, the occlusion of `with_ssao`
+    /// and the shadow of `with_shadows`, so interiors aren't lit by the sky as much as the open.
+    /// Meshes within the radius of a `ReflectionProbe` reflect the probe's capture instead of
+    /// the sky, scaled the same way. Defaults to `1.0`.
+    pub fn with_environment_intensity(mut self, intensity: f32) -> Self {
+        self.environment_intensity = intensity;
+        self
+    }
 }
 
 impl<'a> PassData<'a> for DrawPbmSeparate {
     type Data = (
         Read<'a, ActiveCamera>,
         ReadStorage<'a, Camera>,
+        ReadStorage<'a, Viewport>,
+        ReadStorage<'a, CameraTarget>,
+        ReadStorage<'a, StereoCamera>,
         Read<'a, AmbientColor>,
         Read<'a, AssetStorage<Mesh>>,
         Read<'a, AssetStorage<Texture>>,
         ReadExpect<'a, MaterialDefaults>,
+        ReadExpect<'a, MissingTextures>,
+        Entities<'a>,
         Option<Read<'a, Visibility>>,
+        ReadStorage<'a, Transparent>,
+        ReadStorage<'a, SortKey>,
         ReadStorage<'a, Hidden>,
         ReadStorage<'a, HiddenPropagate>,
         ReadStorage<'a, MeshHandle>,
+        ReadStorage<'a, LodMesh>,
         ReadStorage<'a, Material>,
         ReadStorage<'a, GlobalTransform>,
         ReadStorage<'a, Light>,
         ReadStorage<'a, JointTransforms>,
         ReadStorage<'a, Rgba>,
+        ReadStorage<'a, SkyBox>,
+        ReadStorage<'a, ReflectionProbe>,
+        Read<'a, ShadowSettings>,
+        ReadStorage<'a, ShadowReceiver>,
+        ReadStorage<'a, BoundingSphere>,
+        ReadStorage<'a, Aabb>,
     );
 }
 
 impl Pass for DrawPbmSeparate {
-    fn compile(&mut self, effect: NewEffect<'_>) -> Result<Effect, Error> {
+    fn compile(&mut self, mut effect: NewEffect<'_>) -> Result<Effect, Error> {
         #[cfg(feature = "profiler")]
         profile_scope!("render_pass_pbm_compile");
 
+        if let Some(ref mut shadows) = self.shadows {
+            shadows.compile(&mut effect)?;
+        }
+        if let Some(ref mut ssao) = self.ssao {
+            ssao.compile(&mut effect)?;
+        }
+        self.environment_fallback = Some(environment_fallback(&mut effect.factory)?);
         let mut builder = if self.skinning {
             create_skinning_effect(effect, FRAG_SRC)
         } else {
@@ -155,11 +225,23 @@ impl Pass for DrawPbmSeparate {
         }
         setup_vertex_args(&mut builder);
         setup_light_buffers(&mut builder);
+        setup_emission(&mut builder);
+        setup_alpha_cutoff(&mut builder);
+        // The environment map goes first so it can be added ahead of the material textures.
+        builder.with_texture("environment");
+        builder.with_raw_global("environment_intensity");
+        if self.shadows.is_some() {
+            ShadowInput::setup(&mut builder);
+        }
+        if self.ssao.is_some() {
+            OcclusionInput::setup(&mut builder);
+        }
         setup_textures(&mut builder, &TEXTURES);
         match self.transparency {
             Some((mask, blend, depth)) => builder.with_blended_output("color", mask, blend, depth),
             None => builder.with_output("color", Some(DepthMode::LessEqualWrite)),
         };
+        builder.with_two_sided_variant();
         builder.build()
     }
 
@@ -171,106 +253,204 @@ impl Pass for DrawPbmSeparate {
         (
             active,
             camera,
+            viewports,
+            camera_targets,
+            stereo_cameras,
             ambient,
             mesh_storage,
             tex_storage,
             material_defaults,
+            missing,
+            entities,
             visibility,
+            transparent,
+            sort_keys,
             hidden,
             hidden_prop,
             mesh,
+            lod_mesh,
             material,
             global,
             light,
             joints,
             rgba,
+            skybox,
+            probes,
+            shadow_settings,
+            shadow_receiver,
+            bounding_sphere,
+            aabb,
         ): <Self as PassData<'a>>::Data,
     ) {
         #[cfg(feature = "profiler")]
         profile_scope!("render_pass_pbm_apply");
 
-        let camera = get_camera(active, &camera, &global);
-
-        set_light_args(effect, encoder, &light, &global, &ambient, camera);
+        let cameras = get_cameras(
+            active,
+            &camera,
+            &viewports,
+            &camera_targets,
+            &stereo_cameras,
+            &global,
+            effect.target_name(),
+        );
+        for (index, view) in cameras.iter().enumerate() {
+            let lod_view = LodView::new(effect.target_name(), index);
+            let frustum = culling_frustum(self.frustum_culling, view);
+            let view = set_view(effect, view);
+            let camera = view.get();
 
-        match visibility {
-            None => {
-                for (joint, mesh, material, global, rgba, _, _) in (
-                    joints.maybe(),
-                    &mesh,
-                    &material,
-                    &global,
-                    rgba.maybe(),
-                    !&hidden,
-                    !&hidden_prop,
-                )
-                    .join()
-                {
-                    draw_mesh(
-                        encoder,
-                        effect,
-                        self.skinning,
-                        mesh_storage.get(mesh),
-                        joint,
-                        &tex_storage,
-                        Some(material),
-                        &material_defaults,
-                        rgba,
-                        camera,
-                        Some(global),
-                        &ATTRIBUTES,
-                        &TEXTURES,
-                    );
-                }
+            set_light_args(effect, encoder, &light, &global, &ambient, camera);
+            if let Some(ref shadows) = self.shadows {
+                shadows.set_args(effect, &shadow_settings, &light);
             }
-            Some(ref visibility) => {
-                for (joint, mesh, material, global, rgba, _) in (
-                    joints.maybe(),
-                    &mesh,
-                    &material,
-                    &global,
-                    rgba.maybe(),
-                    &visibility.visible_unordered,
-                )
-                    .join()
-                {
-                    draw_mesh(
-                        encoder,
-                        effect,
-                        self.skinning,
-                        mesh_storage.get(mesh),
-                        joint,
-                        &tex_storage,
-                        Some(material),
-                        &material_defaults,
-                        rgba,
-                        camera,
-                        Some(global),
-                        &ATTRIBUTES,
-                        &TEXTURES,
-                    );
-                }
+            let environment = Environment::new(
+                &skybox,
+                &probes,
+                &global,
+                &tex_storage,
+                &self.environment_fallback,
+                self.environment_intensity,
+            );
+
+            let mut ordered = match visibility {
+                None => {
+                    for (joint, (mesh, lod), material, global, rgba, bounds, receiver, _, _, _) in (
+                        joints.maybe(),
+                        (&mesh, lod_mesh.maybe()),
+                        &material,
+                        &global,
+                        rgba.maybe(),
+                        (bounding_sphere.maybe(), aabb.maybe()),
+                        shadow_receiver.maybe(),
+                        !&transparent,
+                        !&hidden,
+                        !&hidden_prop,
+                    )
+                        .join()
+                    {
+                        if is_culled(&frustum, Some(global), bounds) {
+                            continue;
+                        }
+                        let mesh = select_lod(mesh, lod, lod_view, camera, Some(global));
+                        environment.bind(effect, Some(global));
+                        bind_shadows(effect, &self.shadows, receiver);
+                        bind_occlusion(effect, &self.ssao);
+                        set_emission(effect, Some(material));
+                        set_alpha_cutoff(effect, Some(material));
+                        draw_mesh(
+                            encoder,
+                            effect,
+                            self.skinning,
+                            mesh_storage.get(mesh),
+                            joint,
+                            &tex_storage,
+                            Some(material),
+                            &material_defaults,
+                            &missing,
+                            rgba,
+                            camera,
+                            Some(global),
+                            &ATTRIBUTES,
+                            &TEXTURES,
+                        );
+                    }
 
-                for entity in &visibility.visible_ordered {
-                    if let Some(mesh) = mesh.get(*entity) {
+                    (&*entities, &transparent, !&hidden, !&hidden_prop)
+                        .join()
+                        .map(|(entity, _, _, _)| entity)
+                        .collect::<Vec<_>>()
+                }
+                Some(ref visibility) => {
+                    for (joint, (mesh, lod), material, global, rgba, bounds, receiver, _) in (
+                        joints.maybe(),
+                        (&mesh, lod_mesh.maybe()),
+                        &material,
+                        &global,
+                        rgba.maybe(),
+                        (bounding_sphere.maybe(), aabb.maybe()),
+                        shadow_receiver.maybe(),
+                        &visibility.visible_unordered,
+                    )
+                        .join()
+                    {
+                        if is_culled(&frustum, Some(global), bounds) {
+                            continue;
+                        }
+                        let mesh = select_lod(mesh, lod, lod_view, camera, Some(global));
+                        environment.bind(effect, Some(global));
+                        bind_shadows(effect, &self.shadows, receiver);
+                        bind_occlusion(effect, &self.ssao);
+                        set_emission(effect, Some(material));
+                        set_alpha_cutoff(effect, Some(material));
                         draw_mesh(
                             encoder,
                             effect,
                             self.skinning,
                             mesh_storage.get(mesh),
-                            joints.get(*entity),
+                            joint,
                             &tex_storage,
-                            material.get(*entity),
+                            Some(material),
                             &material_defaults,
-                            rgba.get(*entity),
+                            &missing,
+                            rgba,
                             camera,
-                            global.get(*entity),
+                            Some(global),
                             &ATTRIBUTES,
                             &TEXTURES,
                         );
                     }
+
+                    visibility.visible_ordered.clone()
+                }
+            };
+
+            sort_back_to_front(&mut ordered, camera, &global, &sort_keys);
+            for entity in &ordered {
+                let bounds = (bounding_sphere.get(*entity), aabb.get(*entity));
+                if is_culled(&frustum, global.get(*entity), bounds) {
+                    continue;
+                }
+                if let Some(mesh) = mesh.get(*entity) {
+                    let mesh = select_lod(
+                        mesh,
+                        lod_mesh.get(*entity),
+                        lod_view,
+                        camera,
+                        global.get(*entity),
+                    );
+                    environment.bind(effect, global.get(*entity));
+                    bind_shadows(effect, &self.shadows, shadow_receiver.get(*entity));
+                    bind_occlusion(effect, &self.ssao);
+                    set_emission(effect, material.get(*entity));
+                    set_alpha_cutoff(effect, material.get(*entity));
+                    draw_mesh(
+                        encoder,
+                        effect,
+                        self.skinning,
+                        mesh_storage.get(mesh),
+                        joints.get(*entity),
+                        &tex_storage,
+                        material.get(*entity),
+                        &material_defaults,
+                        &missing,
+                        rgba.get(*entity),
+                        camera,
+                        global.get(*entity),
+                        &ATTRIBUTES,
+                        &TEXTURES,
+                    );
                 }
             }
         }
     }
+
+    fn new_targets(&mut self, targets: &Targets) {
+        if let Some(ref mut shadows) = self.shadows {
+            shadows.new_targets(targets);
+        }
+        if let Some(ref mut ssao) = self.ssao {
+            ssao.new_targets(targets);
+        }
+    }
 }
@@ -0,0 +1,426 @@
+//! Reflection probes capturing the scene into cubemaps.
+
+use std::{f32::consts::FRAC_PI_2, marker::PhantomData, mem};
+
+use derivative::Derivative;
+use fnv::FnvHashMap as HashMap;
+use gfx::{memory::Bind, pso::buffer::ElemStride, texture::Kind};
+use glsl_layout::Uniform;
+use log::error;
+
+use amethyst_assets::AssetStorage;
+use amethyst_core::{
+    nalgebra::{Isometry3, Matrix4, Point3, Vector3},
+    specs::prelude::{
+        Component, Entities, Entity, HashMapStorage, Join, Read, ReadExpect, ReadStorage,
+    },
+    transform::GlobalTransform,
+};
+use amethyst_error::Error;
+
+use crate::{
+    cam::{Camera, Projection},
+    error,
+    hidden::{Hidden, HiddenPropagate},
+    light::Light,
+    mesh::{Mesh, MeshHandle},
+    mtl::{Material, MaterialDefaults, MissingTextures},
+    pass::{
+        shaded::{set_normal_map, setup_normal_map, FRAG_SRC, TEXTURES, VERT_SRC},
+        shaded_util::{set_light_args, setup_light_buffers},
+        sky::{self, vertex_args, SkyBox, VertexArgs},
+        util::{
+            draw_mesh, fullscreen_slice, set_alpha_cutoff, set_emission, setup_alpha_cutoff,
+            setup_emission, setup_textures, setup_vertex_args,
+        },
+    },
+    pipe::{
+        pass::{Pass, PassData},
+        ColorBuffer, DepthBuffer, DepthMode, Effect, NewEffect, Target,
+    },
+    resources::AmbientColor,
+    tex::{Texture, TextureHandle},
+    types::{ChannelFormat, DepthFormat, Encoder, Factory, RenderTargetView},
+    vertex::{Normal, Position, Query, TexCoord, VertexFormat},
+    Rgba,
+};
+
+/// Captures the scene around its entity into a cubemap, which `DrawPbm` reflects on meshes
+/// within `radius` instead of the sky
+///
+/// The probe sits at the translation of the entity's `GlobalTransform`. `texture` is replaced by
+/// the captured cubemap, so create it as a placeholder, e.g. a color loaded with
+/// `Loader::load_from_data`, and give every probe its own. `DrawReflectionProbes` captures the
+/// scene once the placeholder has loaded and again after every call to `recapture`, e.g. when
+/// something near the probe moved. Capturing draws the scene six times, so keep it rare.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReflectionProbe {
+    /// Cubemap the scene is captured into.
+    pub texture: TextureHandle,
+    /// Width and height of every face of the cubemap, in pixels.
+    pub resolution: u16,
+    /// Distance from the probe up to which meshes reflect it.
+    pub radius: f32,
+    generation: u32,
+}
+
+impl ReflectionProbe {
+    /// Creates a probe capturing `resolution`×`resolution` faces into `texture`.
+    pub fn new(texture: TextureHandle, resolution: u16, radius: f32) -> Self {
+        ReflectionProbe {
+            texture,
+            resolution,
+            radius,
+            generation: 0,
+        }
+    }
+
+    /// Captures the scene again on the next frame.
+    pub fn recapture(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+    }
+}
+
+impl Component for ReflectionProbe {
+    type Storage = HashMapStorage<Self>;
+}
+
+/// Returns whether `texture` is a cubemap the scene can be captured into at `resolution`.
+pub(crate) fn is_capture_cubemap(texture: &Texture, resolution: u16) -> bool {
+    let info = texture.raw().get_info();
+    info.kind == Kind::Cube(resolution) && info.bind.contains(Bind::RENDER_TARGET)
+}
+
+/// View direction and up vector of each cubemap face, in `+X, -X, +Y, -Y, +Z, -Z` order.
+pub(crate) const FACES: [([f32; 3], [f32; 3]); 6] = [
+    ([1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ([-1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ([0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+    ([0.0, -1.0, 0.0], [0.0, 0.0, -1.0]),
+    ([0.0, 0.0, 1.0], [0.0, -1.0, 0.0]),
+    ([0.0, 0.0, -1.0], [0.0, -1.0, 0.0]),
+];
+
+/// Returns the transform of a camera at `position` looking through cubemap face `face`.
+pub(crate) fn face_transform(face: usize, position: &Point3<f32>) -> Matrix4<f32> {
+    let (direction, up) = FACES[face];
+    let target = position + Vector3::from(direction);
+    Isometry3::look_at_rh(position, &target, &Vector3::from(up))
+        .inverse()
+        .to_homogeneous()
+}
+
+/// Creates a target for every face of mip level `level` of `cubemap`, sharing one depth buffer.
+///
+/// `resolution` is the width of the faces at that level.
+pub(crate) fn face_targets(
+    factory: &mut Factory,
+    cubemap: &Texture,
+    level: u8,
+    resolution: u16,
+) -> Result<Vec<Target>, Error> {
+    use gfx::{format::ChannelTyped, memory::Typed, texture::RenderDesc, Factory};
+
+    let depth = DepthBuffer {
+        as_input: None,
+        as_output: factory.create_depth_stencil_view_only::<DepthFormat>(resolution, resolution)?,
+    };
+    let size = (u32::from(resolution), u32::from(resolution));
+    (0..FACES.len())
+        .map(|face| {
+            let desc = RenderDesc {
+                channel: ChannelFormat::get_channel_type(),
+                level,
+                layer: Some(face as u16),
+            };
+            let view = factory.view_texture_as_render_target_raw(cubemap.raw(), desc)?;
+            let color = ColorBuffer {
+                as_input: None,
+                as_output: RenderTargetView::new(view),
+                texture: None,
+            };
+            Ok(Target::new(color, depth.clone(), size))
+        })
+        .collect()
+}
+
+/// Faces of a probe's cubemap and the capture they hold.
+#[derive(Clone, Debug)]
+struct Capture {
+    cubemap: Texture,
+    faces: Vec<Target>,
+    generation: u32,
+}
+
+/// Captures the scene into the cubemaps of `ReflectionProbe`s
+///
+/// Meshes are lit like `DrawShaded` lights them, in front of the first `SkyBox` if there is one.
+/// The pass draws into the cubemaps only, not into its stage's target, so it can go anywhere
+/// before the `DrawPbm` pass reflecting the probes:
+///
+/// ```ignore
+/// Stage::with_backbuffer()
+///     .clear_target([0.0, 0.0, 0.0, 1.0], 1.0)
+///     .with_pass(DrawReflectionProbes::<PosNormTex>::new())
+///     .with_pass(DrawPbm::<PosNormTangTex>::new())
+///     .with_pass(DrawSkyBox::new())
+/// ```
+///
+/// Meshes right at a probe's position block its view, so place probes in open space.
+///
+/// # Type Parameters:
+///
+/// * `V`: `VertexFormat`
+#[derive(Derivative, Clone)]
+#[derivative(Debug, Default(bound = "V: Query<(Position, Normal, TexCoord)>"))]
+pub struct DrawReflectionProbes<V> {
+    _pd: PhantomData<V>,
+    #[derivative(Default(value = "[0.0, 0.0, 0.0, 1.0]"))]
+    clear_color: [f32; 4],
+    #[derivative(Debug = "ignore")]
+    sky: Option<Effect>,
+    #[derivative(Debug = "ignore")]
+    captures: HashMap<Entity, Capture>,
+}
+
+impl<V> DrawReflectionProbes<V>
+where
+    V: Query<(Position, Normal, TexCoord)>,
+{
+    /// Create instance of `DrawReflectionProbes` pass
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Set the color captured where neither a mesh nor the sky is, black by default.
+    pub fn with_clear_color(mut self, color: [f32; 4]) -> Self {
+        self.clear_color = color;
+        self
+    }
+}
+
+impl<'a, V> PassData<'a> for DrawReflectionProbes<V>
+where
+    V: Query<(Position, Normal, TexCoord)>,
+{
+    type Data = (
+        Entities<'a>,
+        Read<'a, AmbientColor>,
+        Read<'a, AssetStorage<Mesh>>,
+        Read<'a, AssetStorage<Texture>>,
+        ReadExpect<'a, MaterialDefaults>,
+        ReadExpect<'a, MissingTextures>,
+        ReadStorage<'a, Hidden>,
+        ReadStorage<'a, HiddenPropagate>,
+        ReadStorage<'a, MeshHandle>,
+        ReadStorage<'a, Material>,
+        ReadStorage<'a, GlobalTransform>,
+        ReadStorage<'a, Light>,
+        ReadStorage<'a, Rgba>,
+        ReadStorage<'a, SkyBox>,
+        ReadStorage<'a, ReflectionProbe>,
+    );
+}
+
+impl<V> Pass for DrawReflectionProbes<V>
+where
+    V: Query<(Position, Normal, TexCoord)>,
+{
+    fn compile(&mut self, mut effect: NewEffect<'_>) -> Result<Effect, Error> {
+        // The effects are retargeted to the cubemap faces before every draw, the backbuffer
+        // only provides the formats to build them with.
+        let out = effect
+            .target("")
+            .ok_or_else(|| error::Error::NoSuchTarget(String::new()))?;
+        let sky_effect = effect
+            .simple_into(out, sky::VERT_SRC, sky::FRAG_SRC)
+            .with_raw_constant_buffer(
+                "VertexArgs",
+                mem::size_of::<<VertexArgs as Uniform>::Std140>(),
+                1,
+            )
+            .with_texture("skybox")
+            .with_texture("skybox_b")
+            .with_raw_global("blend")
+            .with_raw_global("tint")
+            .with_raw_global("exposure")
+            .with_raw_global("lod_bias")
+            .with_raw_global("encode_srgb")
+            .with_output("color", Some(DepthMode::LessEqualTest))
+            // The captured faces are lit like the rest of the scene, in linear color.
+            .with_srgb_output(false)
+            .build()?;
+        self.sky = Some(sky_effect);
+
+        let mut builder = effect.simple(VERT_SRC, FRAG_SRC);
+        builder.with_raw_vertex_buffer(V::ATTRIBUTES, V::size() as ElemStride, 0);
+        setup_vertex_args(&mut builder);
+        setup_light_buffers(&mut builder);
+        setup_emission(&mut builder);
+        setup_alpha_cutoff(&mut builder);
+        setup_normal_map(&mut builder);
+        setup_textures(&mut builder, &TEXTURES);
+        builder.with_output("color", Some(DepthMode::LessEqualWrite));
+        builder.with_two_sided_variant();
+        builder.build()
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        mut factory: Factory,
+        (
+            entities,
+            ambient,
+            mesh_storage,
+            tex_storage,
+            material_defaults,
+            missing,
+            hidden,
+            hidden_prop,
+            mesh,
+            material,
+            global,
+            light,
+            rgba,
+            skybox,
+            probes,
+        ): <Self as PassData<'a>>::Data,
+    ) {
+        self.captures.retain(|entity, _| probes.contains(*entity));
+
+        let camera = Camera::from(Projection::perspective(1.0, FRAC_PI_2));
+        let sky = skybox
+            .join()
+            .filter_map(|sky| Some((sky, tex_storage.get(&sky.texture)?)))
+            .next();
+
+        for (entity, probe, probe_global) in (&*entities, &probes, &global).join() {
+            let cubemap = match tex_storage.get(&probe.texture) {
+                Some(cubemap) if is_capture_cubemap(cubemap, probe.resolution) => cubemap,
+                _ => continue,
+            };
+            // The render system replaces the texture when the resolution changes, which needs
+            // new face targets.
+            let current = self
+                .captures
+                .get(&entity)
+                .filter(|capture| capture.cubemap == *cubemap);
+            match current {
+                Some(capture) if capture.generation == probe.generation => continue,
+                Some(_) => {}
+                None => match face_targets(&mut factory, cubemap, 0, probe.resolution) {
+                    Ok(faces) => {
+                        let capture = Capture {
+                            cubemap: cubemap.clone(),
+                            faces,
+                            generation: probe.generation,
+                        };
+                        self.captures.insert(entity, capture);
+                    }
+                    Err(err) => {
+                        error!("Unable to create the reflection probe targets: {}", err);
+                        continue;
+                    }
+                },
+            }
+            let capture = match self.captures.get_mut(&entity) {
+                Some(capture) => capture,
+                None => continue,
+            };
+            capture.generation = probe.generation;
+
+            let position = Point3::from(probe_global.0.column(3).xyz());
+            for (face, target) in capture.faces.iter().enumerate() {
+                let transform = GlobalTransform(face_transform(face, &position));
+                let view = Some((&camera, &transform));
+                target.clear_color(encoder, self.clear_color);
+                target.clear_depth_stencil(encoder, 1.0);
+
+                if let (Some(sky_effect), Some((sky, texture))) = (&mut self.sky, sky) {
+                    let texture_b = sky
+                        .texture_b
+                        .as_ref()
+                        .and_then(|handle| tex_storage.get(handle))
+                        .unwrap_or(texture);
+                    sky_effect.retarget(target);
+                    let args = vertex_args(view, &sky.orientation());
+                    sky_effect.update_constant_buffer("VertexArgs", &args.std140(), encoder);
+                    sky_effect.add_texture(texture);
+                    sky_effect.add_texture(texture_b);
+                    sky_effect.update_global("blend", sky.blend);
+                    sky_effect.update_global("tint", sky.tint);
+                    sky_effect.update_global("exposure", sky.exposure);
+                    sky_effect.update_global("lod_bias", sky.lod_bias);
+                    let encode_srgb = if sky_effect.srgb_output() { 1.0 } else { 0.0 };
+                    sky_effect.update_global("encode_srgb", encode_srgb);
+                    sky_effect.draw(&fullscreen_slice(), encoder);
+                    sky_effect.clear_textures();
+                }
+
+                effect.retarget(target);
+                set_light_args(effect, encoder, &light, &global, &ambient, view);
+                for (mesh, material, global, rgba, _, _) in (
+                    &mesh,
+                    &material,
+                    &global,
+                    rgba.maybe(),
+                    !&hidden,
+                    !&hidden_prop,
+                )
+                    .join()
+                {
+                    set_normal_map(effect, Some(material), &material_defaults);
+                    set_emission(effect, Some(material));
+                    set_alpha_cutoff(effect, Some(material));
+                    draw_mesh(
+                        encoder,
+                        effect,
+                        false,
+                        mesh_storage.get(mesh),
+                        None,
+                        &tex_storage,
+                        Some(material),
+                        &material_defaults,
+                        &missing,
+                        rgba,
+                        view,
+                        Some(global),
+                        &[V::QUERIED_ATTRIBUTES],
+                        &TEXTURES,
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use amethyst_core::nalgebra::{Point3, Vector4};
+
+    use super::face_transform;
+
+    #[test]
+    fn face_cameras_look_along_their_faces() {
+        let position = Point3::new(1.0, 2.0, 3.0);
+        let forward = Vector4::new(0.0, 0.0, -1.0, 0.0);
+        let expected = [
+            [1.0, 0.0, 0.0],
+            [-1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, -1.0, 0.0],
+            [0.0, 0.0, 1.0],
+            [0.0, 0.0, -1.0],
+        ];
+        for (face, direction) in expected.iter().enumerate() {
+            let transform = face_transform(face, &position);
+            let look = transform * forward;
+            for axis in 0..3 {
+                assert!((look[axis] - direction[axis]).abs() < 1.0e-6);
+            }
+            assert!((transform.column(3).xyz() - position.coords).norm() < 1.0e-6);
+        }
+    }
+}
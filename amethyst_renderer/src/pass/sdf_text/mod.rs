@@ -0,0 +1,443 @@
+//! Signed distance field text drawing pass.
+
+use fnv::FnvHashMap as HashMap;
+use gfx::{
+    format::{ChannelType, Format, SurfaceType},
+    memory::Typed,
+    pso::buffer::{ElemStride, Element},
+    traits::Pod,
+};
+use glsl_layout::Uniform;
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use amethyst_assets::AssetStorage;
+use amethyst_core::{
+    specs::prelude::{Component, DenseVecStorage, Join, Read, ReadStorage},
+    transform::GlobalTransform,
+};
+use amethyst_error::Error;
+
+use crate::{
+    cam::{ActiveCamera, Camera},
+    hidden::{Hidden, HiddenPropagate},
+    pass::util::{add_texture, get_camera, set_view_args, ViewArgs},
+    pipe::{
+        pass::{Pass, PassData},
+        DepthMode, Effect, NewEffect,
+    },
+    sprite::TextureCoordinates,
+    tex::{Texture, TextureHandle},
+    transparent::{ColorMask, ALPHA},
+    types::{Encoder, Factory, Slice},
+    vertex::{Attribute, AttributeFormat, Attributes, VertexFormat, With},
+};
+
+static VERT_SRC: &[u8] = include_bytes!("../shaders/vertex/sdf_text.glsl");
+static FRAG_SRC: &[u8] = include_bytes!("../shaders/fragment/sdf_text.glsl");
+
+#[derive(Clone, Debug)]
+enum GlyphPosition {}
+impl Attribute for GlyphPosition {
+    const NAME: &'static str = "glyph_position";
+    const FORMAT: Format = Format(SurfaceType::R32_G32, ChannelType::Float);
+    const SIZE: u32 = 8;
+    type Repr = [f32; 2];
+}
+
+#[derive(Clone, Debug)]
+enum GlyphSize {}
+impl Attribute for GlyphSize {
+    const NAME: &'static str = "glyph_size";
+    const FORMAT: Format = Format(SurfaceType::R32_G32, ChannelType::Float);
+    const SIZE: u32 = 8;
+    type Repr = [f32; 2];
+}
+
+#[derive(Clone, Debug)]
+enum GlyphUv {}
+impl Attribute for GlyphUv {
+    const NAME: &'static str = "glyph_uv";
+    const FORMAT: Format = Format(SurfaceType::R32_G32_B32_A32, ChannelType::Float);
+    const SIZE: u32 = 16;
+    type Repr = [f32; 4];
+}
+
+/// A single glyph of an `SdfText`, drawn as a quad by `DrawSdfText`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SdfGlyphQuad {
+    /// Bottom left corner of the quad, in the text's local units.
+    pub position: [f32; 2],
+    /// Width and height of the quad.
+    pub size: [f32; 2],
+    /// Left, right, bottom and top texture coordinate of the glyph in the atlas.
+    pub uv: [f32; 4],
+}
+
+unsafe impl Pod for SdfGlyphQuad {}
+
+impl VertexFormat for SdfGlyphQuad {
+    const ATTRIBUTES: Attributes<'static> = &[
+        (GlyphPosition::NAME, <Self as With<GlyphPosition>>::FORMAT),
+        (GlyphSize::NAME, <Self as With<GlyphSize>>::FORMAT),
+        (GlyphUv::NAME, <Self as With<GlyphUv>>::FORMAT),
+    ];
+}
+
+impl With<GlyphPosition> for SdfGlyphQuad {
+    const FORMAT: AttributeFormat = Element {
+        offset: 0,
+        format: GlyphPosition::FORMAT,
+    };
+}
+
+impl With<GlyphSize> for SdfGlyphQuad {
+    const FORMAT: AttributeFormat = Element {
+        offset: GlyphPosition::SIZE,
+        format: GlyphSize::FORMAT,
+    };
+}
+
+impl With<GlyphUv> for SdfGlyphQuad {
+    const FORMAT: AttributeFormat = Element {
+        offset: GlyphPosition::SIZE + GlyphSize::SIZE,
+        format: GlyphUv::FORMAT,
+    };
+}
+
+/// Metrics of a glyph in an `SdfFont`, for a font size of 1.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SdfGlyph {
+    /// Area of the glyph in the atlas.
+    pub tex_coords: TextureCoordinates,
+    /// Width and height of the glyph's quad. Zero for glyphs without a shape, like spaces.
+    pub size: [f32; 2],
+    /// Offset from the pen position on the baseline to the bottom left corner of the quad.
+    pub offset: [f32; 2],
+    /// Distance the pen moves to the right after the glyph.
+    pub advance: f32,
+}
+
+/// Signed distance field font atlas, laying out strings for `SdfText`
+///
+/// The atlas texture holds every glyph's distance field in its alpha channel, 0.5 being the edge
+/// of the glyph, like the atlases of most SDF font generators. Load it with linear filtering,
+/// the distance field is blurry with nearest filtering. The glyphs need some padding around
+/// them in the atlas, which outlines and shadows are drawn into.
+#[derive(Clone, Debug)]
+pub struct SdfFont {
+    /// Atlas holding the distance fields of the glyphs.
+    pub texture: TextureHandle,
+    /// Glyphs of the font, by character.
+    pub glyphs: HashMap<char, SdfGlyph>,
+    /// Distance between the baselines of two lines, for a font size of 1.
+    pub line_height: f32,
+}
+
+impl SdfFont {
+    /// Creates a font without glyphs for the atlas `texture`.
+    pub fn new(texture: TextureHandle, line_height: f32) -> Self {
+        SdfFont {
+            texture,
+            glyphs: HashMap::default(),
+            line_height,
+        }
+    }
+
+    /// Adds the glyph of `character`.
+    pub fn with_glyph(mut self, character: char, glyph: SdfGlyph) -> Self {
+        self.glyphs.insert(character, glyph);
+        self
+    }
+
+    /// Lays out `text` into glyph quads at font size `size`.
+    ///
+    /// The first line starts at the origin, on its baseline, and every `\n` starts a new line
+    /// below. Characters without a glyph are skipped.
+    pub fn layout(&self, text: &str, size: f32) -> Vec<SdfGlyphQuad> {
+        layout_glyphs(&self.glyphs, self.line_height, text, size)
+    }
+}
+
+fn layout_glyphs(
+    glyphs: &HashMap<char, SdfGlyph>,
+    line_height: f32,
+    text: &str,
+    size: f32,
+) -> Vec<SdfGlyphQuad> {
+    let mut pen = [0.0, 0.0];
+    let mut quads = Vec::with_capacity(text.len());
+    for character in text.chars() {
+        if character == '\n' {
+            pen = [0.0, pen[1] - line_height * size];
+            continue;
+        }
+        let glyph = match glyphs.get(&character) {
+            Some(glyph) => glyph,
+            None => continue,
+        };
+        if glyph.size[0] > 0.0 && glyph.size[1] > 0.0 {
+            let tex = &glyph.tex_coords;
+            quads.push(SdfGlyphQuad {
+                position: [
+                    pen[0] + glyph.offset[0] * size,
+                    pen[1] + glyph.offset[1] * size,
+                ],
+                size: [glyph.size[0] * size, glyph.size[1] * size],
+                uv: [tex.left, tex.right, tex.bottom, tex.top],
+            });
+        }
+        pen[0] += glyph.advance * size;
+    }
+    quads
+}
+
+/// Component holding text drawn by `DrawSdfText`
+///
+/// The glyphs lie in the XY plane of the entity's `GlobalTransform`, reading along X with Y up.
+#[derive(Clone, Debug)]
+pub struct SdfText {
+    /// Glyph quads, usually laid out by `SdfFont::layout`.
+    pub glyphs: Vec<SdfGlyphQuad>,
+    /// Atlas the glyphs are sampled from.
+    pub texture: TextureHandle,
+    /// Color of the glyphs.
+    pub color: [f32; 4],
+    /// Color of the outline.
+    pub outline_color: [f32; 4],
+    /// Width of the outline in distance field units, up to 0.5. Zero for no outline.
+    pub outline_width: f32,
+    /// Color of the shadow, transparent for no shadow.
+    pub shadow_color: [f32; 4],
+    /// Offset of the shadow in texture coordinates, so it has to fit the atlas' padding.
+    pub shadow_offset: [f32; 2],
+    /// Blur of the shadow in distance field units.
+    pub shadow_softness: f32,
+}
+
+impl SdfText {
+    /// Lays out white `text` at font size `size`, without outline or shadow.
+    pub fn new(font: &SdfFont, text: &str, size: f32) -> Self {
+        SdfText {
+            glyphs: font.layout(text, size),
+            texture: font.texture.clone(),
+            color: [1.0; 4],
+            outline_color: [0.0, 0.0, 0.0, 1.0],
+            outline_width: 0.0,
+            shadow_color: [0.0; 4],
+            shadow_offset: [0.0; 2],
+            shadow_softness: 0.0,
+        }
+    }
+
+    /// Sets the color of the glyphs.
+    pub fn with_color(mut self, color: [f32; 4]) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Outlines the glyphs with `color`, `width` distance field units wide.
+    pub fn with_outline(mut self, color: [f32; 4], width: f32) -> Self {
+        self.outline_color = color;
+        self.outline_width = width;
+        self
+    }
+
+    /// Casts a shadow of `color` offset by `offset` in texture coordinates and blurred by
+    /// `softness` distance field units.
+    pub fn with_shadow(mut self, color: [f32; 4], offset: [f32; 2], softness: f32) -> Self {
+        self.shadow_color = color;
+        self.shadow_offset = offset;
+        self.shadow_softness = softness;
+        self
+    }
+
+    /// Replaces the text, keeping the colors, outline and shadow.
+    pub fn set_text(&mut self, font: &SdfFont, text: &str, size: f32) {
+        self.glyphs = font.layout(text, size);
+        self.texture = font.texture.clone();
+    }
+}
+
+impl Component for SdfText {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Draws `SdfText`s with crisp edges at any scale
+///
+/// The text is alpha blended and depth tested against the scene without writing depth, so the
+/// pass should run after the opaque geometry. For UI and debug overlays, draw it with an
+/// orthographic camera like `DrawFlat2D` sprites.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DrawSdfText;
+
+impl DrawSdfText {
+    /// Create instance of `DrawSdfText` pass
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl<'a> PassData<'a> for DrawSdfText {
+    type Data = (
+        Read<'a, ActiveCamera>,
+        ReadStorage<'a, Camera>,
+        ReadStorage<'a, GlobalTransform>,
+        Read<'a, AssetStorage<Texture>>,
+        ReadStorage<'a, Hidden>,
+        ReadStorage<'a, HiddenPropagate>,
+        ReadStorage<'a, SdfText>,
+    );
+}
+
+impl Pass for DrawSdfText {
+    fn compile(&mut self, effect: NewEffect<'_>) -> Result<Effect, Error> {
+        use std::mem;
+
+        effect
+            .simple(VERT_SRC, FRAG_SRC)
+            .without_back_face_culling()
+            .with_raw_constant_buffer(
+                "ViewArgs",
+                mem::size_of::<<ViewArgs as Uniform>::Std140>(),
+                1,
+            )
+            .with_raw_vertex_buffer(
+                SdfGlyphQuad::ATTRIBUTES,
+                SdfGlyphQuad::size() as ElemStride,
+                1,
+            )
+            .with_raw_global("model")
+            .with_raw_global("text_color")
+            .with_raw_global("outline_color")
+            .with_raw_global("outline_width")
+            .with_raw_global("shadow_color")
+            .with_raw_global("shadow_offset")
+            .with_raw_global("shadow_softness")
+            .with_texture("atlas")
+            .with_blended_output(
+                "color",
+                ColorMask::all(),
+                ALPHA,
+                Some(DepthMode::LessEqualTest),
+            )
+            .build()
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        mut factory: Factory,
+        (active, camera, global, tex_storage, hidden, hidden_prop, text): <Self as PassData<
+            'a,
+        >>::Data,
+    ) {
+        use gfx::{buffer, memory::Bind, Factory};
+
+        let camera = get_camera(active, &camera, &global);
+        set_view_args(effect, encoder, camera);
+
+        for (text, global, _, _) in (&text, &global, !&hidden, !&hidden_prop).join() {
+            if text.glyphs.is_empty() {
+                continue;
+            }
+            let texture = match tex_storage.get(&text.texture) {
+                Some(texture) => texture,
+                None => continue,
+            };
+            let vbuf = match factory.create_buffer_immutable(
+                &text.glyphs,
+                buffer::Role::Vertex,
+                Bind::empty(),
+            ) {
+                Ok(vbuf) => vbuf,
+                Err(err) => {
+                    error!("Unable to create the glyph buffer: {}", err);
+                    return;
+                }
+            };
+
+            add_texture(effect, texture);
+            for _ in SdfGlyphQuad::ATTRIBUTES {
+                effect.data.vertex_bufs.push(vbuf.raw().clone());
+            }
+            effect.update_global("model", Into::<[[f32; 4]; 4]>::into(global.0));
+            effect.update_global("text_color", text.color);
+            effect.update_global("outline_color", text.outline_color);
+            effect.update_global("outline_width", text.outline_width);
+            effect.update_global("shadow_color", text.shadow_color);
+            effect.update_global("shadow_offset", text.shadow_offset);
+            effect.update_global("shadow_softness", text.shadow_softness);
+
+            effect.draw(
+                &Slice {
+                    start: 0,
+                    end: 6,
+                    base_vertex: 0,
+                    instances: Some((text.glyphs.len() as u32, 0)),
+                    buffer: Default::default(),
+                },
+                encoder,
+            );
+            effect.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fnv::FnvHashMap as HashMap;
+
+    use crate::sprite::TextureCoordinates;
+
+    use super::{layout_glyphs, SdfGlyph, SdfGlyphQuad};
+
+    fn glyph(size: [f32; 2], offset: [f32; 2], advance: f32) -> SdfGlyph {
+        SdfGlyph {
+            tex_coords: TextureCoordinates {
+                left: 0.0,
+                right: 0.5,
+                bottom: 0.25,
+                top: 0.75,
+            },
+            size,
+            offset,
+            advance,
+        }
+    }
+
+    fn layout(text: &str, size: f32) -> Vec<SdfGlyphQuad> {
+        let mut glyphs = HashMap::default();
+        glyphs.insert('a', glyph([0.5, 0.5], [0.1, 0.0], 0.6));
+        glyphs.insert('g', glyph([0.5, 0.75], [0.0, -0.25], 0.5));
+        glyphs.insert(' ', glyph([0.0, 0.0], [0.0, 0.0], 0.3));
+        layout_glyphs(&glyphs, 1.5, text, size)
+    }
+
+    fn assert_near(actual: [f32; 2], expected: [f32; 2]) {
+        let near =
+            (actual[0] - expected[0]).abs() < 1.0e-5 && (actual[1] - expected[1]).abs() < 1.0e-5;
+        assert!(near, "{:?} != {:?}", actual, expected);
+    }
+
+    #[test]
+    fn glyphs_advance_along_the_baseline() {
+        let quads = layout("ag a", 2.0);
+        // The space only advances the pen.
+        assert_eq!(quads.len(), 3);
+        assert_near(quads[0].position, [0.2, 0.0]);
+        assert_near(quads[1].position, [1.2, -0.5]);
+        assert_near(quads[2].position, [3.0, 0.0]);
+        assert_near(quads[1].size, [1.0, 1.5]);
+        assert_eq!(quads[0].uv, [0.0, 0.5, 0.25, 0.75]);
+    }
+
+    #[test]
+    fn newlines_restart_below_and_unknown_characters_are_skipped() {
+        let quads = layout("a?\na", 1.0);
+        assert_eq!(quads.len(), 2);
+        assert_near(quads[1].position, [0.1, -1.5]);
+    }
+}
@@ -8,27 +8,35 @@ use gfx_core::state::{Blend, ColorMask};
 
 use amethyst_assets::AssetStorage;
 use amethyst_core::{
-    specs::prelude::{Join, Read, ReadExpect, ReadStorage},
+    specs::prelude::{Entities, Join, Read, ReadExpect, ReadStorage},
     transform::GlobalTransform,
 };
 use amethyst_error::Error;
 
 use crate::{
-    cam::{ActiveCamera, Camera},
+    cam::{ActiveCamera, Camera, CameraTarget, StereoCamera, Viewport},
+    culling::{Aabb, BoundingSphere},
     hidden::{Hidden, HiddenPropagate},
     light::Light,
-    mesh::{Mesh, MeshHandle},
-    mtl::{Material, MaterialDefaults},
+    mesh::{LodMesh, LodView, Mesh, MeshHandle},
+    mtl::{Material, MaterialDefaults, MissingTextures},
     pass::{
         shaded_util::{set_light_args, setup_light_buffers},
-        util::{default_transparency, draw_mesh, get_camera, setup_textures, setup_vertex_args},
+        shadow::{bind_shadows, ShadowInput, ShadowReceiver, ShadowSettings},
+        ssao::{bind_occlusion, OcclusionInput},
+        util::{
+            culling_frustum, default_transparency, draw_mesh, get_cameras, is_culled, select_lod,
+            set_alpha_cutoff, set_emission, set_view, setup_alpha_cutoff, setup_emission,
+            setup_textures, setup_vertex_args, sort_back_to_front,
+        },
     },
     pipe::{
         pass::{Pass, PassData},
-        DepthMode, Effect, NewEffect,
+        DepthMode, Effect, NewEffect, Targets,
     },
     resources::AmbientColor,
     tex::Texture,
+    transparent::{SortKey, Transparent},
     types::{Encoder, Factory},
     vertex::{Normal, Position, Query, TexCoord},
     visibility::Visibility,
@@ -42,6 +50,14 @@ use super::*;
 /// See the [crate level documentation](index.html) for information about interleaved and separate
 /// passes.
 ///
+/// Normal maps of materials are applied using the tangents of vertex formats that have them.
+///
+/// Every mesh is lit by the ambient color and all point, directional and spot `Light`s. At most
+/// 128 point, 16 directional and 128 spot lights are used; in larger scenes the ones closest to
+/// the camera are kept. Point lights fall off with the square of the distance and fade out to
+/// nothing at their `radius`, spot lights fade out over their `range` and towards the rim of
+/// their cone.
+///
 /// # Type Parameters:
 ///
 /// * `V`: `VertexFormat`
@@ -51,6 +67,9 @@ pub struct DrawShaded<V> {
     _pd: PhantomData<V>,
     #[derivative(Default(value = "default_transparency()"))]
     transparency: Option<(ColorMask, Blend, Option<DepthMode>)>,
+    frustum_culling: bool,
+    shadows: Option<ShadowInput>,
+    ssao: Option<OcclusionInput>,
 }
 
 impl<V> DrawShaded<V>
@@ -88,6 +107,28 @@ where
         self.transparency = Some((mask, blend, depth));
         self
     }
+
+    /// Skip entities whose `BoundingSphere` or `Aabb` is out of the camera's view.
+    ///
+    /// Entities without bounds are always drawn.
+    pub fn with_frustum_culling(mut self) -> Self {
+        self.frustum_culling = true;
+        self
+    }
+
+    /// Darken `ShadowReceiver`s using the shadow map that `DrawShadowMap` renders into the
+    /// target named `target`.
+    pub fn with_shadows<N: Into<String>>(mut self, target: N) -> Self {
+        self.shadows = Some(ShadowInput::new(target.into()));
+        self
+    }
+
+    /// Multiply the ambient light with the occlusion that `DrawSsao` draws into the target
+    /// named `target`.
+    pub fn with_ssao<N: Into<String>>(mut self, target: N) -> Self {
+        self.ssao = Some(OcclusionInput::new(target.into()));
+        self
+    }
 }
 
 impl<'a, V> PassData<'a> for DrawShaded<V>
@@ -97,18 +138,30 @@ where
     type Data = (
         Read<'a, ActiveCamera>,
         ReadStorage<'a, Camera>,
+        ReadStorage<'a, Viewport>,
+        ReadStorage<'a, CameraTarget>,
+        ReadStorage<'a, StereoCamera>,
         Read<'a, AmbientColor>,
         Read<'a, AssetStorage<Mesh>>,
         Read<'a, AssetStorage<Texture>>,
         ReadExpect<'a, MaterialDefaults>,
+        ReadExpect<'a, MissingTextures>,
+        Entities<'a>,
         Option<Read<'a, Visibility>>,
+        ReadStorage<'a, Transparent>,
+        ReadStorage<'a, SortKey>,
         ReadStorage<'a, Hidden>,
         ReadStorage<'a, HiddenPropagate>,
         ReadStorage<'a, MeshHandle>,
+        ReadStorage<'a, LodMesh>,
         ReadStorage<'a, Material>,
         ReadStorage<'a, GlobalTransform>,
         ReadStorage<'a, Light>,
         ReadStorage<'a, Rgba>,
+        Read<'a, ShadowSettings>,
+        ReadStorage<'a, ShadowReceiver>,
+        ReadStorage<'a, BoundingSphere>,
+        ReadStorage<'a, Aabb>,
     );
 }
 
@@ -116,16 +169,33 @@ impl<V> Pass for DrawShaded<V>
 where
     V: Query<(Position, Normal, TexCoord)>,
 {
-    fn compile(&mut self, effect: NewEffect<'_>) -> Result<Effect, Error> {
+    fn compile(&mut self, mut effect: NewEffect<'_>) -> Result<Effect, Error> {
+        if let Some(ref mut shadows) = self.shadows {
+            shadows.compile(&mut effect)?;
+        }
+        if let Some(ref mut ssao) = self.ssao {
+            ssao.compile(&mut effect)?;
+        }
         let mut builder = effect.simple(VERT_SRC, FRAG_SRC);
-        builder.with_raw_vertex_buffer(V::QUERIED_ATTRIBUTES, V::size() as ElemStride, 0);
+        // All attributes of `V` are registered, so formats with tangents use them for normal maps.
+        builder.with_raw_vertex_buffer(V::ATTRIBUTES, V::size() as ElemStride, 0);
         setup_vertex_args(&mut builder);
         setup_light_buffers(&mut builder);
+        setup_emission(&mut builder);
+        setup_alpha_cutoff(&mut builder);
+        setup_normal_map(&mut builder);
+        if self.shadows.is_some() {
+            ShadowInput::setup(&mut builder);
+        }
+        if self.ssao.is_some() {
+            OcclusionInput::setup(&mut builder);
+        }
         setup_textures(&mut builder, &TEXTURES);
         match self.transparency {
             Some((mask, blend, depth)) => builder.with_blended_output("color", mask, blend, depth),
             None => builder.with_output("color", Some(DepthMode::LessEqualWrite)),
         };
+        builder.with_two_sided_variant();
         builder.build()
     }
 
@@ -137,82 +207,120 @@ where
         (
             active,
             camera,
+            viewports,
+            camera_targets,
+            stereo_cameras,
             ambient,
             mesh_storage,
             tex_storage,
             material_defaults,
+            missing,
+            entities,
             visibility,
+            transparent,
+            sort_keys,
             hidden,
             hidden_prop,
             mesh,
+            lod_mesh,
             material,
             global,
             light,
             rgba,
+            shadow_settings,
+            shadow_receiver,
+            bounding_sphere,
+            aabb,
         ): <Self as PassData<'a>>::Data,
     ) {
-        let camera = get_camera(active, &camera, &global);
-
-        set_light_args(effect, encoder, &light, &global, &ambient, camera);
-
-        match visibility {
-            None => {
-                for (mesh, material, global, rgba, _, _) in (
-                    &mesh,
-                    &material,
-                    &global,
-                    rgba.maybe(),
-                    !&hidden,
-                    !&hidden_prop,
-                )
-                    .join()
-                {
-                    draw_mesh(
-                        encoder,
-                        effect,
-                        false,
-                        mesh_storage.get(mesh),
-                        None,
-                        &tex_storage,
-                        Some(material),
-                        &material_defaults,
-                        rgba,
-                        camera,
-                        Some(global),
-                        &[V::QUERIED_ATTRIBUTES],
-                        &TEXTURES,
-                    );
-                }
+        let cameras = get_cameras(
+            active,
+            &camera,
+            &viewports,
+            &camera_targets,
+            &stereo_cameras,
+            &global,
+            effect.target_name(),
+        );
+        for (index, view) in cameras.iter().enumerate() {
+            let lod_view = LodView::new(effect.target_name(), index);
+            let frustum = culling_frustum(self.frustum_culling, view);
+            let view = set_view(effect, view);
+            let camera = view.get();
+
+            set_light_args(effect, encoder, &light, &global, &ambient, camera);
+            if let Some(ref shadows) = self.shadows {
+                shadows.set_args(effect, &shadow_settings, &light);
             }
-            Some(ref visibility) => {
-                for (mesh, material, global, rgba, _) in (
-                    &mesh,
-                    &material,
-                    &global,
-                    rgba.maybe(),
-                    &visibility.visible_unordered,
-                )
-                    .join()
-                {
-                    draw_mesh(
-                        encoder,
-                        effect,
-                        false,
-                        mesh_storage.get(mesh),
-                        None,
-                        &tex_storage,
-                        Some(material),
-                        &material_defaults,
-                        rgba,
-                        camera,
-                        Some(global),
-                        &[V::QUERIED_ATTRIBUTES],
-                        &TEXTURES,
-                    );
-                }
 
-                for entity in &visibility.visible_ordered {
-                    if let Some(mesh) = mesh.get(*entity) {
+            let mut ordered = match visibility {
+                None => {
+                    for ((mesh, lod), material, global, rgba, bounds, receiver, _, _, _) in (
+                        (&mesh, lod_mesh.maybe()),
+                        &material,
+                        &global,
+                        rgba.maybe(),
+                        (bounding_sphere.maybe(), aabb.maybe()),
+                        shadow_receiver.maybe(),
+                        !&transparent,
+                        !&hidden,
+                        !&hidden_prop,
+                    )
+                        .join()
+                    {
+                        if is_culled(&frustum, Some(global), bounds) {
+                            continue;
+                        }
+                        let mesh = select_lod(mesh, lod, lod_view, camera, Some(global));
+                        bind_shadows(effect, &self.shadows, receiver);
+                        bind_occlusion(effect, &self.ssao);
+                        set_normal_map(effect, Some(material), &material_defaults);
+                        set_emission(effect, Some(material));
+                        set_alpha_cutoff(effect, Some(material));
+                        draw_mesh(
+                            encoder,
+                            effect,
+                            false,
+                            mesh_storage.get(mesh),
+                            None,
+                            &tex_storage,
+                            Some(material),
+                            &material_defaults,
+                            &missing,
+                            rgba,
+                            camera,
+                            Some(global),
+                            &[V::QUERIED_ATTRIBUTES],
+                            &TEXTURES,
+                        );
+                    }
+
+                    (&*entities, &transparent, !&hidden, !&hidden_prop)
+                        .join()
+                        .map(|(entity, _, _, _)| entity)
+                        .collect::<Vec<_>>()
+                }
+                Some(ref visibility) => {
+                    for ((mesh, lod), material, global, rgba, bounds, receiver, _) in (
+                        (&mesh, lod_mesh.maybe()),
+                        &material,
+                        &global,
+                        rgba.maybe(),
+                        (bounding_sphere.maybe(), aabb.maybe()),
+                        shadow_receiver.maybe(),
+                        &visibility.visible_unordered,
+                    )
+                        .join()
+                    {
+                        if is_culled(&frustum, Some(global), bounds) {
+                            continue;
+                        }
+                        let mesh = select_lod(mesh, lod, lod_view, camera, Some(global));
+                        bind_shadows(effect, &self.shadows, receiver);
+                        bind_occlusion(effect, &self.ssao);
+                        set_normal_map(effect, Some(material), &material_defaults);
+                        set_emission(effect, Some(material));
+                        set_alpha_cutoff(effect, Some(material));
                         draw_mesh(
                             encoder,
                             effect,
@@ -220,17 +328,67 @@ where
                             mesh_storage.get(mesh),
                             None,
                             &tex_storage,
-                            material.get(*entity),
+                            Some(material),
                             &material_defaults,
-                            rgba.get(*entity),
+                            &missing,
+                            rgba,
                             camera,
-                            global.get(*entity),
+                            Some(global),
                             &[V::QUERIED_ATTRIBUTES],
                             &TEXTURES,
                         );
                     }
+
+                    visibility.visible_ordered.clone()
+                }
+            };
+
+            sort_back_to_front(&mut ordered, camera, &global, &sort_keys);
+            for entity in &ordered {
+                let bounds = (bounding_sphere.get(*entity), aabb.get(*entity));
+                if is_culled(&frustum, global.get(*entity), bounds) {
+                    continue;
+                }
+                if let Some(mesh) = mesh.get(*entity) {
+                    let mesh = select_lod(
+                        mesh,
+                        lod_mesh.get(*entity),
+                        lod_view,
+                        camera,
+                        global.get(*entity),
+                    );
+                    bind_shadows(effect, &self.shadows, shadow_receiver.get(*entity));
+                    bind_occlusion(effect, &self.ssao);
+                    set_normal_map(effect, material.get(*entity), &material_defaults);
+                    set_emission(effect, material.get(*entity));
+                    set_alpha_cutoff(effect, material.get(*entity));
+                    draw_mesh(
+                        encoder,
+                        effect,
+                        false,
+                        mesh_storage.get(mesh),
+                        None,
+                        &tex_storage,
+                        material.get(*entity),
+                        &material_defaults,
+                        &missing,
+                        rgba.get(*entity),
+                        camera,
+                        global.get(*entity),
+                        &[V::QUERIED_ATTRIBUTES],
+                        &TEXTURES,
+                    );
                 }
             }
         }
     }
+
+    fn new_targets(&mut self, targets: &Targets) {
+        if let Some(ref mut shadows) = self.shadows {
+            shadows.new_targets(targets);
+        }
+        if let Some(ref mut ssao) = self.ssao {
+            ssao.new_targets(targets);
+        }
+    }
 }
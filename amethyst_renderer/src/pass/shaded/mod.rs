@@ -3,9 +3,32 @@ pub use self::{interleaved::DrawShaded, separate::DrawShadedSeparate};
 mod interleaved;
 mod separate;
 
-use crate::pass::util::TextureType;
+use crate::{
+    mtl::{Material, MaterialDefaults},
+    pass::util::TextureType,
+    pipe::{Effect, EffectBuilder},
+};
 
-static VERT_SRC: &[u8] = include_bytes!("../shaders/vertex/basic.glsl");
-static FRAG_SRC: &[u8] = include_bytes!("../shaders/fragment/shaded.glsl");
+pub(crate) static VERT_SRC: &[u8] = include_bytes!("../shaders/vertex/basic.glsl");
+pub(crate) static FRAG_SRC: &[u8] = include_bytes!("../shaders/fragment/shaded.glsl");
 
-static TEXTURES: [TextureType; 2] = [TextureType::Albedo, TextureType::Emission];
+pub(crate) static TEXTURES: [TextureType; 3] = [
+    TextureType::Albedo,
+    TextureType::Emission,
+    TextureType::Normal,
+];
+
+pub(crate) fn setup_normal_map(builder: &mut EffectBuilder<'_>) {
+    builder.with_raw_global("use_normal_map");
+}
+
+/// Switches normal mapping off for materials using the default normal map, so their vertex
+/// normals are lit unchanged.
+pub(crate) fn set_normal_map(
+    effect: &mut Effect,
+    material: Option<&Material>,
+    defaults: &MaterialDefaults,
+) {
+    let mapped = material.map_or(false, |material| material.normal != defaults.0.normal);
+    effect.update_global("use_normal_map", if mapped { 1.0 } else { 0.0 });
+}
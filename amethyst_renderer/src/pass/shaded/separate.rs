@@ -7,31 +7,39 @@ use log::{debug, trace};
 
 use amethyst_assets::AssetStorage;
 use amethyst_core::{
-    specs::prelude::{Join, Read, ReadExpect, ReadStorage},
+    specs::prelude::{Entities, Join, Read, ReadExpect, ReadStorage},
     transform::GlobalTransform,
 };
 use amethyst_error::Error;
 
 use crate::{
-    cam::{ActiveCamera, Camera},
+    cam::{ActiveCamera, Camera, CameraTarget, StereoCamera, Viewport},
+    culling::{Aabb, BoundingSphere},
     hidden::{Hidden, HiddenPropagate},
     light::Light,
-    mesh::{Mesh, MeshHandle},
-    mtl::{Material, MaterialDefaults},
+    mesh::{LodMesh, LodView, Mesh, MeshHandle},
+    mtl::{Material, MaterialDefaults, MissingTextures},
     pass::{
         shaded_util::{set_light_args, setup_light_buffers},
+        shadow::{bind_shadows, ShadowInput, ShadowReceiver, ShadowSettings},
         skinning::{create_skinning_effect, setup_skinning_buffers},
-        util::{default_transparency, draw_mesh, get_camera, setup_textures, setup_vertex_args},
+        ssao::{bind_occlusion, OcclusionInput},
+        util::{
+            culling_frustum, default_transparency, draw_mesh, get_cameras, is_culled, select_lod,
+            set_alpha_cutoff, set_emission, set_view, setup_alpha_cutoff, setup_emission,
+            setup_textures, setup_vertex_args, sort_back_to_front,
+        },
     },
     pipe::{
         pass::{Pass, PassData},
-        DepthMode, Effect, NewEffect,
+        DepthMode, Effect, NewEffect, Targets,
     },
     resources::AmbientColor,
     skinning::JointTransforms,
     tex::Texture,
+    transparent::{SortKey, Transparent},
     types::{Encoder, Factory},
-    vertex::{Attributes, Normal, Position, Separate, TexCoord, VertexFormat},
+    vertex::{Attributes, Normal, Position, Separate, Tangent, TexCoord, VertexFormat},
     visibility::Visibility,
     Rgba,
 };
@@ -44,16 +52,30 @@ static ATTRIBUTES: [Attributes<'static>; 3] = [
     Separate::<TexCoord>::ATTRIBUTES,
 ];
 
+// Same order as the vertex buffers are added in `compile`.
+static TANGENT_ATTRIBUTES: [Attributes<'static>; 4] = [
+    Separate::<Position>::ATTRIBUTES,
+    Separate::<Normal>::ATTRIBUTES,
+    Separate::<TexCoord>::ATTRIBUTES,
+    Separate::<Tangent>::ATTRIBUTES,
+];
+
 /// Draw mesh with simple lighting technique
 ///
 /// See the [crate level documentation](index.html) for information about interleaved and separate
 /// passes.
+///
+/// Normal maps of materials are applied, see `with_tangents`.
 #[derive(Derivative, Clone, Debug, PartialEq)]
 #[derivative(Default)]
 pub struct DrawShadedSeparate {
     skinning: bool,
+    tangents: bool,
     #[derivative(Default(value = "default_transparency()"))]
     transparency: Option<(ColorMask, Blend, Option<DepthMode>)>,
+    frustum_culling: bool,
+    shadows: Option<ShadowInput>,
+    ssao: Option<OcclusionInput>,
 }
 
 impl DrawShadedSeparate {
@@ -68,6 +90,23 @@ impl DrawShadedSeparate {
         self
     }
 
+    /// Read tangents for normal mapping from the meshes' `Tangent` buffers.
+    ///
+    /// Meshes without tangents aren't drawn by the pass then. Without this the tangents are
+    /// derived from the texture coordinates in the fragment shader.
+    pub fn with_tangents(mut self) -> Self {
+        self.tangents = true;
+        self
+    }
+
+    fn attributes(&self) -> &'static [Attributes<'static>] {
+        if self.tangents {
+            &TANGENT_ATTRIBUTES
+        } else {
+            &ATTRIBUTES
+        }
+    }
+
     /// Transparency is enabled by default.
     /// If you pass false to this function transparency will be disabled.
     ///
@@ -94,30 +133,70 @@ impl DrawShadedSeparate {
         self.transparency = Some((mask, blend, depth));
         self
     }
+
+    /// Skip entities whose `BoundingSphere` or `Aabb` is out of the camera's view.
+    ///
+    /// Entities without bounds are always drawn.
+    pub fn with_frustum_culling(mut self) -> Self {
+        self.frustum_culling = true;
+        self
+    }
+
+    /// Darken `ShadowReceiver`s using the shadow map that `DrawShadowMap` renders into the
+    /// target named `target`.
+    pub fn with_shadows<N: Into<String>>(mut self, target: N) -> Self {
+        self.shadows = Some(ShadowInput::new(target.into()));
+        self
+    }
+
+    /// Multiply the ambient light with the occlusion that `DrawSsao` draws into the target
+    /// named `target`.
+    pub fn with_ssao<N: Into<String>>(mut self, target: N) -> Self {
+        self.ssao = Some(OcclusionInput::new(target.into()));
+        self
+    }
 }
 
 impl<'a> PassData<'a> for DrawShadedSeparate {
     type Data = (
         Read<'a, ActiveCamera>,
         ReadStorage<'a, Camera>,
+        ReadStorage<'a, Viewport>,
+        ReadStorage<'a, CameraTarget>,
+        ReadStorage<'a, StereoCamera>,
         Read<'a, AmbientColor>,
         Read<'a, AssetStorage<Mesh>>,
         Read<'a, AssetStorage<Texture>>,
         ReadExpect<'a, MaterialDefaults>,
+        ReadExpect<'a, MissingTextures>,
+        Entities<'a>,
         Option<Read<'a, Visibility>>,
+        ReadStorage<'a, Transparent>,
+        ReadStorage<'a, SortKey>,
         ReadStorage<'a, Hidden>,
         ReadStorage<'a, HiddenPropagate>,
         ReadStorage<'a, MeshHandle>,
+        ReadStorage<'a, LodMesh>,
         ReadStorage<'a, Material>,
         ReadStorage<'a, GlobalTransform>,
         ReadStorage<'a, Light>,
         ReadStorage<'a, JointTransforms>,
         ReadStorage<'a, Rgba>,
+        Read<'a, ShadowSettings>,
+        ReadStorage<'a, ShadowReceiver>,
+        ReadStorage<'a, BoundingSphere>,
+        ReadStorage<'a, Aabb>,
     );
 }
 
 impl Pass for DrawShadedSeparate {
-    fn compile(&mut self, effect: NewEffect<'_>) -> Result<Effect, Error> {
+    fn compile(&mut self, mut effect: NewEffect<'_>) -> Result<Effect, Error> {
+        if let Some(ref mut shadows) = self.shadows {
+            shadows.compile(&mut effect)?;
+        }
+        if let Some(ref mut ssao) = self.ssao {
+            ssao.compile(&mut effect)?;
+        }
         debug!("Building shaded pass");
         let mut builder = if self.skinning {
             create_skinning_effect(effect, FRAG_SRC)
@@ -141,16 +220,33 @@ impl Pass for DrawShadedSeparate {
                 Separate::<TexCoord>::size() as ElemStride,
                 0,
             );
+        if self.tangents {
+            builder.with_raw_vertex_buffer(
+                Separate::<Tangent>::ATTRIBUTES,
+                Separate::<Tangent>::size() as ElemStride,
+                0,
+            );
+        }
         if self.skinning {
             setup_skinning_buffers(&mut builder);
         }
         setup_vertex_args(&mut builder);
         setup_light_buffers(&mut builder);
+        setup_emission(&mut builder);
+        setup_alpha_cutoff(&mut builder);
+        setup_normal_map(&mut builder);
+        if self.shadows.is_some() {
+            ShadowInput::setup(&mut builder);
+        }
+        if self.ssao.is_some() {
+            OcclusionInput::setup(&mut builder);
+        }
         setup_textures(&mut builder, &TEXTURES);
         match self.transparency {
             Some((mask, blend, depth)) => builder.with_blended_output("color", mask, blend, depth),
             None => builder.with_output("color", Some(DepthMode::LessEqualWrite)),
         };
+        builder.with_two_sided_variant();
         builder.build()
     }
 
@@ -162,104 +258,192 @@ impl Pass for DrawShadedSeparate {
         (
             active,
             camera,
+            viewports,
+            camera_targets,
+            stereo_cameras,
             ambient,
             mesh_storage,
             tex_storage,
             material_defaults,
+            missing,
+            entities,
             visibility,
+            transparent,
+            sort_keys,
             hidden,
             hidden_prop,
             mesh,
+            lod_mesh,
             material,
             global,
             light,
             joints,
             rgba,
+            shadow_settings,
+            shadow_receiver,
+            bounding_sphere,
+            aabb,
         ): <Self as PassData<'a>>::Data,
     ) {
         trace!("Drawing shaded pass");
-        let camera = get_camera(active, &camera, &global);
-
-        set_light_args(effect, encoder, &light, &global, &ambient, camera);
+        let cameras = get_cameras(
+            active,
+            &camera,
+            &viewports,
+            &camera_targets,
+            &stereo_cameras,
+            &global,
+            effect.target_name(),
+        );
+        for (index, view) in cameras.iter().enumerate() {
+            let lod_view = LodView::new(effect.target_name(), index);
+            let frustum = culling_frustum(self.frustum_culling, view);
+            let view = set_view(effect, view);
+            let camera = view.get();
 
-        match visibility {
-            None => {
-                for (joint, mesh, material, global, rgba, _, _) in (
-                    joints.maybe(),
-                    &mesh,
-                    &material,
-                    &global,
-                    rgba.maybe(),
-                    !&hidden,
-                    !&hidden_prop,
-                )
-                    .join()
-                {
-                    draw_mesh(
-                        encoder,
-                        effect,
-                        self.skinning,
-                        mesh_storage.get(mesh),
-                        joint,
-                        &tex_storage,
-                        Some(material),
-                        &material_defaults,
-                        rgba,
-                        camera,
-                        Some(global),
-                        &ATTRIBUTES,
-                        &TEXTURES,
-                    );
-                }
+            set_light_args(effect, encoder, &light, &global, &ambient, camera);
+            if let Some(ref shadows) = self.shadows {
+                shadows.set_args(effect, &shadow_settings, &light);
             }
-            Some(ref visibility) => {
-                for (joint, mesh, material, global, rgba, _) in (
-                    joints.maybe(),
-                    &mesh,
-                    &material,
-                    &global,
-                    rgba.maybe(),
-                    &visibility.visible_unordered,
-                )
-                    .join()
-                {
-                    draw_mesh(
-                        encoder,
-                        effect,
-                        self.skinning,
-                        mesh_storage.get(mesh),
-                        joint,
-                        &tex_storage,
-                        Some(material),
-                        &material_defaults,
-                        rgba,
-                        camera,
-                        Some(global),
-                        &ATTRIBUTES,
-                        &TEXTURES,
-                    );
-                }
 
-                for entity in &visibility.visible_ordered {
-                    if let Some(mesh) = mesh.get(*entity) {
+            let mut ordered = match visibility {
+                None => {
+                    for (joint, (mesh, lod), material, global, rgba, bounds, receiver, _, _, _) in (
+                        joints.maybe(),
+                        (&mesh, lod_mesh.maybe()),
+                        &material,
+                        &global,
+                        rgba.maybe(),
+                        (bounding_sphere.maybe(), aabb.maybe()),
+                        shadow_receiver.maybe(),
+                        !&transparent,
+                        !&hidden,
+                        !&hidden_prop,
+                    )
+                        .join()
+                    {
+                        if is_culled(&frustum, Some(global), bounds) {
+                            continue;
+                        }
+                        let mesh = select_lod(mesh, lod, lod_view, camera, Some(global));
+                        bind_shadows(effect, &self.shadows, receiver);
+                        bind_occlusion(effect, &self.ssao);
+                        set_normal_map(effect, Some(material), &material_defaults);
+                        set_emission(effect, Some(material));
+                        set_alpha_cutoff(effect, Some(material));
+                        draw_mesh(
+                            encoder,
+                            effect,
+                            self.skinning,
+                            mesh_storage.get(mesh),
+                            joint,
+                            &tex_storage,
+                            Some(material),
+                            &material_defaults,
+                            &missing,
+                            rgba,
+                            camera,
+                            Some(global),
+                            self.attributes(),
+                            &TEXTURES,
+                        );
+                    }
+
+                    (&*entities, &transparent, !&hidden, !&hidden_prop)
+                        .join()
+                        .map(|(entity, _, _, _)| entity)
+                        .collect::<Vec<_>>()
+                }
+                Some(ref visibility) => {
+                    for (joint, (mesh, lod), material, global, rgba, bounds, receiver, _) in (
+                        joints.maybe(),
+                        (&mesh, lod_mesh.maybe()),
+                        &material,
+                        &global,
+                        rgba.maybe(),
+                        (bounding_sphere.maybe(), aabb.maybe()),
+                        shadow_receiver.maybe(),
+                        &visibility.visible_unordered,
+                    )
+                        .join()
+                    {
+                        if is_culled(&frustum, Some(global), bounds) {
+                            continue;
+                        }
+                        let mesh = select_lod(mesh, lod, lod_view, camera, Some(global));
+                        bind_shadows(effect, &self.shadows, receiver);
+                        bind_occlusion(effect, &self.ssao);
+                        set_normal_map(effect, Some(material), &material_defaults);
+                        set_emission(effect, Some(material));
+                        set_alpha_cutoff(effect, Some(material));
                         draw_mesh(
                             encoder,
                             effect,
                             self.skinning,
                             mesh_storage.get(mesh),
-                            joints.get(*entity),
+                            joint,
                             &tex_storage,
-                            material.get(*entity),
+                            Some(material),
                             &material_defaults,
-                            rgba.get(*entity),
+                            &missing,
+                            rgba,
                             camera,
-                            global.get(*entity),
-                            &ATTRIBUTES,
+                            Some(global),
+                            self.attributes(),
                             &TEXTURES,
                         );
                     }
+
+                    visibility.visible_ordered.clone()
+                }
+            };
+
+            sort_back_to_front(&mut ordered, camera, &global, &sort_keys);
+            for entity in &ordered {
+                let bounds = (bounding_sphere.get(*entity), aabb.get(*entity));
+                if is_culled(&frustum, global.get(*entity), bounds) {
+                    continue;
+                }
+                if let Some(mesh) = mesh.get(*entity) {
+                    let mesh = select_lod(
+                        mesh,
+                        lod_mesh.get(*entity),
+                        lod_view,
+                        camera,
+                        global.get(*entity),
+                    );
+                    bind_shadows(effect, &self.shadows, shadow_receiver.get(*entity));
+                    bind_occlusion(effect, &self.ssao);
+                    set_normal_map(effect, material.get(*entity), &material_defaults);
+                    set_emission(effect, material.get(*entity));
+                    set_alpha_cutoff(effect, material.get(*entity));
+                    draw_mesh(
+                        encoder,
+                        effect,
+                        self.skinning,
+                        mesh_storage.get(mesh),
+                        joints.get(*entity),
+                        &tex_storage,
+                        material.get(*entity),
+                        &material_defaults,
+                        &missing,
+                        rgba.get(*entity),
+                        camera,
+                        global.get(*entity),
+                        self.attributes(),
+                        &TEXTURES,
+                    );
                 }
             }
         }
     }
+
+    fn new_targets(&mut self, targets: &Targets) {
+        if let Some(ref mut shadows) = self.shadows {
+            shadows.new_targets(targets);
+        }
+        if let Some(ref mut ssao) = self.ssao {
+            ssao.new_targets(targets);
+        }
+    }
 }
@@ -1,4 +1,12 @@
-use std::mem;
+//! Light lists shared by the lit forward and deferred passes.
+//!
+//! Every frame `set_light_args` joins the `Light` components with their `GlobalTransform`s
+//! from the pass's `PassData` and uploads them into one uniform buffer per light type, so a
+//! light affects every mesh drawn by the pass. The buffers hold at most `MAX_POINT_LIGHTS`,
+//! `MAX_DIRECTIONAL_LIGHTS` and `MAX_SPOT_LIGHTS` lights, matching the arrays declared by the
+//! shaders; when a scene has more, the ones closest to the camera are kept.
+
+use std::{cmp::Ordering, mem};
 
 use glsl_layout::*;
 
@@ -22,12 +30,21 @@ pub(crate) struct FragmentArgs {
     spot_light_count: uint,
 }
 
+/// Maximum number of point lights uploaded per pass, the size of the shaders' `plight` array.
+pub(crate) const MAX_POINT_LIGHTS: usize = 128;
+/// Maximum number of directional lights uploaded per pass, the size of the `dlight` array.
+pub(crate) const MAX_DIRECTIONAL_LIGHTS: usize = 16;
+/// Maximum number of spot lights uploaded per pass, the size of the `slight` array.
+pub(crate) const MAX_SPOT_LIGHTS: usize = 128;
+
 #[derive(Clone, Copy, Debug, Uniform)]
 pub(crate) struct PointLightPod {
     position: vec3,
     color: vec3,
     pad: float,
     intensity: float,
+    radius: float,
+    smoothness: float,
 }
 
 #[derive(Clone, Copy, Debug, Uniform)]
@@ -55,25 +72,33 @@ pub(crate) fn set_light_args(
     ambient: &AmbientColor,
     camera: Option<(&Camera, &GlobalTransform)>,
 ) {
+    let eye = camera.map(|(_, transform)| transform.0.column(3).xyz());
+    let distance = |transform: &GlobalTransform| {
+        eye.map_or(0.0, |eye| {
+            (transform.0.column(3).xyz() - eye).norm_squared()
+        })
+    };
+
     let point_lights: Vec<_> = (light, global)
         .join()
         .filter_map(|(light, transform)| {
             if let Light::Point(ref light) = *light {
                 let position: [f32; 3] = transform.0.column(3).xyz().into();
-                Some(
-                    PointLightPod {
-                        position: position.into(),
-                        color: light.color.into(),
-                        intensity: light.intensity,
-                        pad: 0.0,
-                    }
-                    .std140(),
-                )
+                let pod = PointLightPod {
+                    position: position.into(),
+                    color: light.color.into(),
+                    intensity: light.intensity,
+                    radius: light.radius,
+                    smoothness: light.smoothness,
+                    pad: 0.0,
+                };
+                Some((distance(transform), pod.std140()))
             } else {
                 None
             }
         })
         .collect();
+    let point_lights = nearest(point_lights, MAX_POINT_LIGHTS);
 
     let directional_lights: Vec<_> = light
         .join()
@@ -81,7 +106,7 @@ pub(crate) fn set_light_args(
             if let Light::Directional(ref light) = *light {
                 Some(
                     DirectionalLightPod {
-                        color: light.color.into(),
+                        color: light.tinted_color().into(),
                         direction: light.direction.into(),
                     }
                     .std140(),
@@ -90,6 +115,7 @@ pub(crate) fn set_light_args(
                 None
             }
         })
+        .take(MAX_DIRECTIONAL_LIGHTS)
         .collect();
 
     let spot_lights: Vec<_> = (light, global)
@@ -97,23 +123,22 @@ pub(crate) fn set_light_args(
         .filter_map(|(light, transform)| {
             if let Light::Spot(ref light) = *light {
                 let position: [f32; 3] = transform.0.column(3).xyz().into();
-                Some(
-                    SpotLightPod {
-                        position: position.into(),
-                        color: light.color.into(),
-                        direction: light.direction.into(),
-                        angle: light.angle.cos(),
-                        intensity: light.intensity,
-                        range: light.range,
-                        smoothness: light.smoothness,
-                    }
-                    .std140(),
-                )
+                let pod = SpotLightPod {
+                    position: position.into(),
+                    color: light.color.into(),
+                    direction: light.direction.into(),
+                    angle: light.angle.cos(),
+                    intensity: light.intensity,
+                    range: light.range,
+                    smoothness: light.smoothness,
+                };
+                Some((distance(transform), pod.std140()))
             } else {
                 None
             }
         })
         .collect();
+    let spot_lights = nearest(spot_lights, MAX_SPOT_LIGHTS);
 
     let fragment_args = FragmentArgs {
         point_light_count: point_lights.len() as u32,
@@ -147,18 +172,46 @@ pub(crate) fn setup_light_buffers(builder: &mut EffectBuilder<'_>) {
         .with_raw_constant_buffer(
             "PointLights",
             mem::size_of::<<PointLightPod as Uniform>::Std140>(),
-            128,
+            MAX_POINT_LIGHTS,
         )
         .with_raw_constant_buffer(
             "DirectionalLights",
             mem::size_of::<<DirectionalLightPod as Uniform>::Std140>(),
-            16,
+            MAX_DIRECTIONAL_LIGHTS,
         )
         .with_raw_constant_buffer(
             "SpotLights",
             mem::size_of::<<SpotLightPod as Uniform>::Std140>(),
-            128,
+            MAX_SPOT_LIGHTS,
         )
         .with_raw_global("ambient_color")
         .with_raw_global("camera_position");
 }
+
+/// Keeps the `max` lights with the smallest squared distance to the camera.
+///
+/// Lights keep their order when they all fit, so the result is stable from frame to frame.
+fn nearest<T>(mut lights: Vec<(f32, T)>, max: usize) -> Vec<T> {
+    if lights.len() > max {
+        lights.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+        lights.truncate(max);
+    }
+    lights.into_iter().map(|(_, light)| light).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::nearest;
+
+    #[test]
+    fn lights_within_capacity_keep_their_order() {
+        let lights = vec![(9.0, 'a'), (1.0, 'b'), (4.0, 'c')];
+        assert_eq!(nearest(lights, 3), vec!['a', 'b', 'c']);
+    }
+
+    #[test]
+    fn only_nearest_lights_are_kept() {
+        let lights = vec![(9.0, 'a'), (1.0, 'b'), (4.0, 'c'), (16.0, 'd')];
+        assert_eq!(nearest(lights, 2), vec!['b', 'c']);
+    }
+}
@@ -0,0 +1,80 @@
+// Evaluates the Preetham daylight model for the direction of the sky cube.
+//
+// The Perez coefficients and zenith values come precomputed from `Atmosphere`, each as a vector
+// over luminance Y and the chromaticities x and y. `zenith` is already divided by the
+// distribution at the zenith, so scaling it by the distribution gives the sky color directly.
+
+#version 330 core
+
+uniform vec3 perez_a;
+uniform vec3 perez_b;
+uniform vec3 perez_c;
+uniform vec3 perez_d;
+uniform vec3 perez_e;
+uniform vec3 zenith;
+// Normalized direction towards the sun, with +Y pointing up.
+uniform vec3 sun_direction;
+uniform vec3 ground_albedo;
+// Scale from the model's luminance in kcd/m² to the output.
+uniform float intensity;
+// Angular radius of the sun disc in radians, 0.0 to leave it out.
+uniform float sun_size;
+// Brightness of the sun disc in output units.
+uniform float sun_intensity;
+// 1.0 to encode the linear sky to sRGB for a target that is displayed as it is.
+uniform float encode_srgb;
+
+in vec3 TexCoords;
+
+out vec4 color;
+
+vec3 linear_to_srgb(vec3 linear) {
+    vec3 low = linear * 12.92;
+    vec3 high = 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055;
+    return mix(high, low, vec3(lessThanEqual(linear, vec3(0.0031308))));
+}
+
+vec3 perez(float cos_theta, float gamma) {
+    float cos_gamma = cos(gamma);
+    return (1.0 + perez_a * exp(perez_b / cos_theta))
+        * (1.0 + perez_c * exp(perez_d * gamma) + perez_e * cos_gamma * cos_gamma);
+}
+
+vec3 yxy_to_linear_rgb(vec3 yxy) {
+    float y = max(yxy.z, 1e-4);
+    vec3 xyz = vec3(yxy.y * yxy.x / y, yxy.x, (1.0 - yxy.y - yxy.z) * yxy.x / y);
+    mat3 xyz_to_rgb = mat3(
+        3.2406, -0.9689, 0.0557,
+        -1.5372, 1.8758, -0.2040,
+        -0.4986, 0.0415, 1.0570
+    );
+    return max(xyz_to_rgb * xyz, vec3(0.0));
+}
+
+vec3 sky(vec3 direction) {
+    // The model diverges below the horizon, so directions are kept just above it.
+    float cos_theta = max(direction.y, 0.01);
+    float gamma = acos(clamp(dot(direction, sun_direction), -1.0, 1.0));
+    return yxy_to_linear_rgb(zenith * perez(cos_theta, gamma));
+}
+
+void main() {
+    vec3 direction = normalize(TexCoords);
+    vec3 rgb = sky(direction);
+    // Below the horizon the ground reflects the light of the sky above it.
+    vec3 ground = ground_albedo * sky(vec3(0.0, 1.0, 0.0));
+    rgb *= intensity;
+    if (sun_size > 0.0) {
+        // The disc takes the hue of the sky around the sun, turning red as it sets.
+        vec3 around_sun = sky(sun_direction);
+        vec3 tint = around_sun / max(max(around_sun.r, max(around_sun.g, around_sun.b)), 1e-4);
+        float angle = acos(clamp(dot(direction, sun_direction), -1.0, 1.0));
+        float edge = max(fwidth(angle), 1e-5);
+        rgb += tint * sun_intensity * (1.0 - smoothstep(sun_size - edge, sun_size + edge, angle));
+    }
+    rgb = mix(rgb, ground * intensity, smoothstep(0.0, -0.02, direction.y));
+    if (encode_srgb != 0.0) {
+        rgb = linear_to_srgb(clamp(rgb, 0.0, 1.0));
+    }
+    color = vec4(rgb, 1.0);
+}
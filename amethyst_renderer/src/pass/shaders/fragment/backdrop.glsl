@@ -0,0 +1,30 @@
+// Draws one layer of a `Backdrop`, tinted and blended over the layers behind it.
+
+#version 150 core
+
+uniform sampler2D layer;
+uniform vec4 tint;
+// 1.0 to encode the linear colors to sRGB for a target that is displayed as it is.
+uniform float encode_srgb;
+
+in vec2 tex_coord;
+
+out vec4 color;
+
+vec3 linear_to_srgb(vec3 linear) {
+    vec3 low = linear * 12.92;
+    vec3 high = 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055;
+    return mix(high, low, vec3(lessThanEqual(linear, vec3(0.0031308))));
+}
+
+void main() {
+    vec4 texel = texture(layer, tex_coord) * tint;
+    if (texel.a <= 0.0) {
+        discard;
+    }
+    vec3 rgb = texel.rgb;
+    if (encode_srgb != 0.0) {
+        rgb = linear_to_srgb(clamp(rgb, 0.0, 1.0));
+    }
+    color = vec4(rgb, texel.a);
+}
@@ -0,0 +1,22 @@
+// One direction of a separable 9-tap Gaussian blur.
+
+#version 150 core
+
+uniform sampler2D source;
+// Distance between two taps in texture coordinates.
+uniform vec2 direction;
+
+in vec2 tex_coord;
+
+out vec4 color;
+
+const float weights[5] = float[](0.2270270270, 0.1945945946, 0.1216216216, 0.0540540541, 0.0162162162);
+
+void main() {
+    vec3 sum = texture(source, tex_coord).rgb * weights[0];
+    for (int i = 1; i < 5; i++) {
+        sum += texture(source, tex_coord + direction * float(i)).rgb * weights[i];
+        sum += texture(source, tex_coord - direction * float(i)).rgb * weights[i];
+    }
+    color = vec4(sum, 1.0);
+}
@@ -0,0 +1,16 @@
+// Adds the blurred bright pass on top of the scene.
+
+#version 150 core
+
+uniform sampler2D scene;
+uniform sampler2D bloom;
+uniform float intensity;
+
+in vec2 tex_coord;
+
+out vec4 color;
+
+void main() {
+    vec4 base = texture(scene, tex_coord);
+    color = vec4(base.rgb + texture(bloom, tex_coord).rgb * intensity, base.a);
+}
@@ -0,0 +1,17 @@
+// Keeps the part of each pixel that is brighter than the threshold.
+
+#version 150 core
+
+uniform sampler2D source;
+uniform float threshold;
+
+in vec2 tex_coord;
+
+out vec4 color;
+
+void main() {
+    vec3 scene = texture(source, tex_coord).rgb;
+    float brightness = dot(scene, vec3(0.2126, 0.7152, 0.0722));
+    float weight = max(brightness - threshold, 0.0) / max(brightness, 0.0001);
+    color = vec4(scene * weight, 1.0);
+}
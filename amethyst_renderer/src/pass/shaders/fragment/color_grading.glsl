@@ -0,0 +1,53 @@
+// Grades the colors of a target through a 3D lookup table unwrapped into a 2D texture.
+
+#version 150 core
+
+uniform sampler2D source;
+// `lut_size` slices of `lut_size` by `lut_size` texels side by side, blue selecting the slice,
+// red running across each slice and green down from the top row.
+uniform sampler2D lut;
+uniform float lut_size;
+uniform float amount;
+// 1.0 to write the graded colors as sRGB for a target that is displayed as it is.
+uniform float encode_srgb;
+
+in vec2 tex_coord;
+
+out vec4 color;
+
+vec3 linear_to_srgb(vec3 linear) {
+    vec3 low = linear * 12.92;
+    vec3 high = 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055;
+    return mix(high, low, vec3(lessThanEqual(linear, vec3(0.0031308))));
+}
+
+vec3 srgb_to_linear(vec3 srgb) {
+    vec3 low = srgb / 12.92;
+    vec3 high = pow((srgb + 0.055) / 1.055, vec3(2.4));
+    return mix(high, low, vec3(lessThanEqual(srgb, vec3(0.04045))));
+}
+
+vec3 lookup(vec3 rgb) {
+    float last = lut_size - 1.0;
+    float slice = rgb.b * last;
+    float lower = floor(slice);
+    float upper = min(lower + 1.0, last);
+    // Texel centers, so the bilinear filter interpolates red and green within a slice.
+    float u = rgb.r * last + 0.5;
+    float v = (rgb.g * last + 0.5) / lut_size;
+    float width = lut_size * lut_size;
+    vec3 a = texture(lut, vec2((lower * lut_size + u) / width, v)).rgb;
+    vec3 b = texture(lut, vec2((upper * lut_size + u) / width, v)).rgb;
+    return mix(a, b, slice - lower);
+}
+
+void main() {
+    vec4 scene = texture(source, tex_coord);
+    // Lookup tables are authored for sRGB colors.
+    vec3 rgb = linear_to_srgb(clamp(scene.rgb, 0.0, 1.0));
+    rgb = mix(rgb, lookup(rgb), amount);
+    if (encode_srgb == 0.0) {
+        rgb = srgb_to_linear(rgb);
+    }
+    color = vec4(rgb, scene.a);
+}
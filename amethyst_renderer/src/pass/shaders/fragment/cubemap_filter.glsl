@@ -0,0 +1,96 @@
+// Convolves a cubemap for image based lighting, drawn once per face and mip level.
+//
+// With `irradiance` set, every texel averages the cosine weighted hemisphere around its
+// direction, for diffuse lighting. Otherwise the source is prefiltered with the GGX distribution
+// of `roughness`, for specular reflections.
+
+#version 150 core
+
+uniform samplerCube source;
+// Width of a face of the source's first mip level, in pixels.
+uniform float source_size;
+uniform float roughness;
+uniform float irradiance;
+
+in vec3 TexCoords;
+
+out vec4 color;
+
+const float PI = 3.14159265359;
+const uint SAMPLE_COUNT = 512u;
+
+float radical_inverse(uint bits) {
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return float(bits) * 2.3283064365386963e-10;
+}
+
+vec2 hammersley(uint i) {
+    return vec2(float(i) / float(SAMPLE_COUNT), radical_inverse(i));
+}
+
+// Turns a direction around +Z into one around `normal`.
+vec3 to_world(vec3 local, vec3 normal) {
+    vec3 up = abs(normal.z) < 0.999 ? vec3(0.0, 0.0, 1.0) : vec3(1.0, 0.0, 0.0);
+    vec3 tangent = normalize(cross(up, normal));
+    vec3 bitangent = cross(normal, tangent);
+    return normalize(tangent * local.x + bitangent * local.y + normal * local.z);
+}
+
+// Picks the mip level whose texels cover about the solid angle of one sample of density `pdf`.
+float sample_lod(float pdf) {
+    float texel = 4.0 * PI / (6.0 * source_size * source_size);
+    float sample_angle = 1.0 / (float(SAMPLE_COUNT) * pdf + 0.0001);
+    return max(0.5 * log2(sample_angle / texel), 0.0);
+}
+
+vec3 convolve_irradiance(vec3 normal) {
+    vec3 total = vec3(0.0);
+    for (uint i = 0u; i < SAMPLE_COUNT; i++) {
+        vec2 xi = hammersley(i);
+        float phi = 2.0 * PI * xi.x;
+        float cos_theta = sqrt(1.0 - xi.y);
+        float sin_theta = sqrt(xi.y);
+        vec3 local = vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+        total += textureLod(source, to_world(local, normal), sample_lod(cos_theta / PI)).rgb;
+    }
+    return total / float(SAMPLE_COUNT);
+}
+
+vec3 prefilter_specular(vec3 normal) {
+    if (roughness <= 0.0) {
+        return textureLod(source, normal, 0.0).rgb;
+    }
+    // The view direction is assumed to be the normal, as usual for split sum prefiltering.
+    float a = roughness * roughness;
+    float a2 = a * a;
+    vec3 total = vec3(0.0);
+    float weight = 0.0;
+    for (uint i = 0u; i < SAMPLE_COUNT; i++) {
+        vec2 xi = hammersley(i);
+        float phi = 2.0 * PI * xi.x;
+        float cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a2 - 1.0) * xi.y));
+        float sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+        vec3 half_vector = to_world(vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta), normal);
+        vec3 light = normalize(2.0 * dot(normal, half_vector) * half_vector - normal);
+        float n_dot_l = dot(normal, light);
+        if (n_dot_l > 0.0) {
+            float denom = cos_theta * cos_theta * (a2 - 1.0) + 1.0;
+            float distribution = a2 / (PI * denom * denom);
+            // With the view along the normal, the pdf of the reflected direction is D / 4.
+            float lod = sample_lod(distribution / 4.0);
+            total += textureLod(source, light, lod).rgb * n_dot_l;
+            weight += n_dot_l;
+        }
+    }
+    return total / max(weight, 0.0001);
+}
+
+void main() {
+    vec3 direction = normalize(TexCoords);
+    vec3 rgb = irradiance != 0.0 ? convolve_irradiance(direction) : prefilter_specular(direction);
+    color = vec4(rgb, 1.0);
+}
@@ -0,0 +1,27 @@
+// Projects the decal texture onto the scene inside the decal's box.
+
+#version 150 core
+
+uniform sampler2D albedo;
+// Depth buffer of the scene the decal is projected onto.
+uniform sampler2D depth;
+uniform mat4 inv_proj;
+// Camera transform, from view to world space.
+uniform mat4 inv_view;
+// From world space into the box, which spans -1 to 1 along every axis.
+uniform mat4 inv_model;
+uniform vec4 tint;
+
+out vec4 color;
+
+void main() {
+    float scene = texelFetch(depth, ivec2(gl_FragCoord.xy), 0).r;
+    vec2 ndc = gl_FragCoord.xy / vec2(textureSize(depth, 0)) * 2.0 - 1.0;
+    vec4 view_position = inv_proj * vec4(ndc, scene * 2.0 - 1.0, 1.0);
+    vec4 world = inv_view * vec4(view_position.xyz / view_position.w, 1.0);
+    vec3 local = (inv_model * world).xyz;
+    if (any(greaterThan(abs(local), vec3(1.0)))) {
+        discard;
+    }
+    color = texture(albedo, local.xy * 0.5 + 0.5) * tint;
+}
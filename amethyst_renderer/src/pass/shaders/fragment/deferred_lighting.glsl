@@ -0,0 +1,112 @@
+// Lights the G-buffer filled by `DrawGBuffer`, one fullscreen fragment per pixel.
+
+#version 330 core
+
+layout (std140) uniform FragmentArgs {
+    uint point_light_count;
+    uint directional_light_count;
+    uint spot_light_count;
+};
+
+struct PointLight {
+    vec3 position;
+    vec3 color;
+    float pad; // Workaround for bug in mac's implementation of opengl (loads garbage when accessing members of structures in arrays with dynamic indices).
+    float intensity;
+    float radius;
+    float smoothness;
+};
+
+layout (std140) uniform PointLights {
+    PointLight plight[128];
+};
+
+struct DirectionalLight {
+    vec3 color;
+    vec3 direction;
+};
+
+layout (std140) uniform DirectionalLights {
+    DirectionalLight dlight[16];
+};
+
+struct SpotLight {
+    vec3 position;
+    vec3 color;
+    vec3 direction;
+    float angle;
+    float intensity;
+    float range;
+    float smoothness;
+};
+
+layout (std140) uniform SpotLights {
+    SpotLight slight[128];
+};
+
+uniform vec3 ambient_color;
+
+uniform sampler2D gbuffer_albedo;
+uniform sampler2D gbuffer_normal;
+uniform sampler2D gbuffer_emission;
+uniform sampler2D gbuffer_depth;
+
+uniform mat4 inv_proj;
+uniform mat4 inv_view;
+
+// Same falloff as `DrawShaded`.
+float point_attenuation(vec3 light_vec, float intensity, float radius, float smoothness) {
+    float dist2 = max(dot(light_vec, light_vec), 1.0e-4);
+    float ratio = sqrt(dist2) / max(radius, 1.0e-5);
+    float window = clamp(1.0 - pow(ratio, max(smoothness, 1.0e-5)), 0.0, 1.0);
+    return intensity / dist2 * window * window;
+}
+
+float spot_attenuation(vec3 light_vec, float cos_angle, vec3 direction, float range, float smoothness) {
+    float range_attenuation = max(0.0, 1.0 - length(light_vec) / max(range, 1.0e-5));
+    float spot_angle = max(cos_angle, 1.0e-5);
+    float frag_angle = max(dot(normalize(direction), -normalize(light_vec)), spot_angle);
+    float rim_attenuation = pow(max((1.0 - frag_angle) / (1.0 - spot_angle), 1.0e-5), 1.0 - smoothness);
+    return range_attenuation * (1.0 - rim_attenuation);
+}
+
+in vec2 tex_coord;
+
+out vec4 out_color;
+
+void main() {
+    ivec2 texel = ivec2(gl_FragCoord.xy);
+    float depth = texelFetch(gbuffer_depth, texel, 0).r;
+    // Nothing was drawn here, leave whatever is behind the scene visible.
+    if (depth == 1.0) {
+        discard;
+    }
+
+    vec4 ndc = vec4(tex_coord * 2.0 - 1.0, depth * 2.0 - 1.0, 1.0);
+    vec4 view_position = inv_proj * ndc;
+    vec3 position = (inv_view * vec4(view_position.xyz / view_position.w, 1.0)).xyz;
+
+    vec3 color = texelFetch(gbuffer_albedo, texel, 0).rgb;
+    vec3 normal = normalize(texelFetch(gbuffer_normal, texel, 0).rgb * 2.0 - 1.0);
+    vec3 emission = texelFetch(gbuffer_emission, texel, 0).rgb;
+
+    vec3 lighting = vec3(0.0);
+    for (uint i = 0u; i < point_light_count; i++) {
+        vec3 dist = plight[i].position - position;
+        float diff = max(dot(normalize(dist), normal), 0.0);
+        float attenuation = point_attenuation(dist, plight[i].intensity, plight[i].radius, plight[i].smoothness);
+        lighting += diff * normalize(plight[i].color) * attenuation;
+    }
+    for (uint i = 0u; i < directional_light_count; i++) {
+        float diff = max(dot(-dlight[i].direction, normal), 0.0);
+        lighting += diff * dlight[i].color;
+    }
+    for (uint i = 0u; i < spot_light_count; i++) {
+        vec3 light_vec = slight[i].position - position;
+        float diff = max(dot(normalize(light_vec), normal), 0.0);
+        float attenuation = spot_attenuation(light_vec, slight[i].angle, slight[i].direction, slight[i].range, slight[i].smoothness);
+        lighting += diff * slight[i].color * attenuation * slight[i].intensity;
+    }
+    lighting += ambient_color;
+    out_color = vec4(lighting * color + emission, 1.0);
+}
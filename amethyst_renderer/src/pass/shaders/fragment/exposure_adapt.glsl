@@ -0,0 +1,20 @@
+// Moves the adapted exposure towards the measured one. Keep in sync with `adapt` in
+// `pass/tonemap/auto_exposure.rs`.
+
+#version 150 core
+
+uniform sampler2D measured;
+// The adapted exposure of the last frame, in red.
+uniform sampler2D previous;
+// How far to move towards the measured exposure, 1.0 to jump there.
+uniform float factor;
+
+in vec2 tex_coord;
+
+out vec4 color;
+
+void main() {
+    float target = texture(measured, vec2(0.5)).r;
+    float last = texture(previous, vec2(0.5)).r;
+    color = vec4(vec3(mix(last, target, factor)), 1.0);
+}
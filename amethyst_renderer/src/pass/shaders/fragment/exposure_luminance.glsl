@@ -0,0 +1,29 @@
+// Measures the exposure of the scene in stops above middle gray, the first step of the auto
+// exposure of `DrawTonemap`. The stops are mapped from the EV range onto `[0, 1]`.
+
+#version 150 core
+
+uniform sampler2D source;
+// Quarter of a texel of the target, in texture coordinates.
+uniform vec2 offset;
+// The lowest and highest stops the exposure adapts to.
+uniform vec2 ev_range;
+
+in vec2 tex_coord;
+
+out vec4 color;
+
+float stops(vec2 coord) {
+    vec3 scene = texture(source, coord).rgb;
+    float luminance = dot(scene, vec3(0.2126, 0.7152, 0.0722));
+    float ev = log2(max(luminance, 0.00001) / 0.18);
+    return (clamp(ev, ev_range.x, ev_range.y) - ev_range.x) / (ev_range.y - ev_range.x);
+}
+
+void main() {
+    float sum = stops(tex_coord + vec2(-offset.x, -offset.y))
+        + stops(tex_coord + vec2(offset.x, -offset.y))
+        + stops(tex_coord + vec2(-offset.x, offset.y))
+        + stops(tex_coord + vec2(offset.x, offset.y));
+    color = vec4(vec3(sum * 0.25), 1.0);
+}
@@ -0,0 +1,21 @@
+// Averages four by four texels of the source into each pixel, down to the one pixel holding
+// the average exposure of the scene.
+
+#version 150 core
+
+uniform sampler2D source;
+// One texel of the source, in texture coordinates.
+uniform vec2 offset;
+
+in vec2 tex_coord;
+
+out vec4 color;
+
+void main() {
+    // Each bilinear tap falls between, and so averages, two by two texels.
+    float sum = texture(source, tex_coord + vec2(-offset.x, -offset.y)).r
+        + texture(source, tex_coord + vec2(offset.x, -offset.y)).r
+        + texture(source, tex_coord + vec2(-offset.x, offset.y)).r
+        + texture(source, tex_coord + vec2(offset.x, offset.y)).r;
+    color = vec4(vec3(sum * 0.25), 1.0);
+}
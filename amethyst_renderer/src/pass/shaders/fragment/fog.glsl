@@ -0,0 +1,59 @@
+// Blends fog into a rendered scene by the distance of each pixel, reconstructed from depth.
+
+#version 150 core
+
+uniform sampler2D scene;
+uniform sampler2D depth;
+uniform samplerCube sky;
+
+uniform mat4 inv_proj;
+// The camera's transform, from view to world space.
+uniform mat4 inv_view;
+// From world space to the coordinates of the sky cubemap.
+uniform mat4 sky_rotation;
+// SkyBox tint scaled by its exposure.
+uniform vec4 sky_tint;
+// 1.0 to take the fog color from `sky` in the direction of each pixel instead of `fog_color`.
+uniform float use_sky;
+uniform vec4 fog_color;
+// 0.0 for linear fog between `fog_params.x` and `fog_params.y`, 1.0 for exponential fog with
+// the density `fog_params.x`.
+uniform float mode;
+uniform vec2 fog_params;
+uniform float base_height;
+uniform float height_falloff;
+// Fraction of the far plane distance from where everything fades into the fog completely.
+uniform float horizon_fade;
+
+in vec2 tex_coord;
+
+out vec4 color;
+
+void main() {
+    vec4 base = texture(scene, tex_coord);
+    float d = texture(depth, tex_coord).r;
+    if (d >= 1.0) {
+        // Nothing but sky here, which the fog fades into anyway.
+        color = base;
+        return;
+    }
+
+    vec4 view = inv_proj * vec4(vec3(tex_coord, d) * 2.0 - 1.0, 1.0);
+    view /= view.w;
+    vec4 far = inv_proj * vec4(0.0, 0.0, 1.0, 1.0);
+    float far_distance = -far.z / far.w;
+    float distance = length(view.xyz);
+    vec3 world = (inv_view * vec4(view.xyz, 1.0)).xyz;
+    vec3 eye = inv_view[3].xyz;
+
+    float amount = mode < 0.5
+        ? clamp((distance - fog_params.x) / max(fog_params.y - fog_params.x, 1e-4), 0.0, 1.0)
+        : 1.0 - exp(-fog_params.x * distance);
+    amount *= exp(-height_falloff * max(world.y - base_height, 0.0));
+    amount = max(amount, smoothstep(horizon_fade, 1.0, -view.z / far_distance));
+
+    vec3 fog = use_sky > 0.5
+        ? texture(sky, mat3(sky_rotation) * normalize(world - eye)).rgb * sky_tint.rgb
+        : fog_color.rgb;
+    color = vec4(mix(base.rgb, fog, amount * fog_color.a), base.a);
+}
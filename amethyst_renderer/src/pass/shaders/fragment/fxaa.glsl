@@ -0,0 +1,60 @@
+// Luma based fast approximate anti-aliasing, after Timothy Lottes' FXAA.
+
+#version 150 core
+
+uniform sampler2D source;
+// Size of one texel in texture coordinates.
+uniform vec2 texel_size;
+// Longest distance in texels searched along an edge.
+uniform float span_max;
+// How much the search direction is shortened in bright areas, 0 keeps the full length.
+uniform float reduce_mul;
+// Minimum local contrast, relative to the brightest neighbour, for a pixel to be filtered.
+uniform float edge_threshold;
+
+in vec2 tex_coord;
+
+out vec4 color;
+
+const float REDUCE_MIN = 1.0 / 128.0;
+const float EDGE_THRESHOLD_MIN = 1.0 / 32.0;
+const vec3 LUMA = vec3(0.299, 0.587, 0.114);
+
+vec3 sample_at(vec2 offset) {
+    return texture(source, tex_coord + offset * texel_size).rgb;
+}
+
+void main() {
+    vec4 center = texture(source, tex_coord);
+    float luma_m = dot(center.rgb, LUMA);
+    float luma_nw = dot(sample_at(vec2(-1.0, -1.0)), LUMA);
+    float luma_ne = dot(sample_at(vec2(1.0, -1.0)), LUMA);
+    float luma_sw = dot(sample_at(vec2(-1.0, 1.0)), LUMA);
+    float luma_se = dot(sample_at(vec2(1.0, 1.0)), LUMA);
+
+    float luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    float luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    if (luma_max - luma_min < max(EDGE_THRESHOLD_MIN, luma_max * edge_threshold)) {
+        color = center;
+        return;
+    }
+
+    vec2 dir = vec2(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se)
+    );
+    float dir_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * reduce_mul, REDUCE_MIN);
+    float rcp_dir_min = 1.0 / (min(abs(dir.x), abs(dir.y)) + dir_reduce);
+    dir = clamp(dir * rcp_dir_min, vec2(-span_max), vec2(span_max));
+
+    vec3 rgb_a = 0.5 * (
+        sample_at(dir * (1.0 / 3.0 - 0.5)) +
+        sample_at(dir * (2.0 / 3.0 - 0.5)));
+    vec3 rgb_b = rgb_a * 0.5 + 0.25 * (
+        sample_at(dir * -0.5) +
+        sample_at(dir * 0.5));
+
+    float luma_b = dot(rgb_b, LUMA);
+    color = vec4((luma_b < luma_min || luma_b > luma_max) ? rgb_a : rgb_b, center.a);
+}
@@ -0,0 +1,52 @@
+// Writes the surface attributes of each fragment into the G-buffer for deferred lighting.
+
+#version 330 core
+
+uniform float alpha_cutoff;
+
+uniform sampler2D albedo;
+uniform sampler2D emission;
+uniform float emission_intensity;
+
+layout (std140) uniform AlbedoOffset {
+    vec2 u_offset;
+    vec2 v_offset;
+} albedo_offset;
+
+layout (std140) uniform EmissionOffset {
+    vec2 u_offset;
+    vec2 v_offset;
+} emission_offset;
+
+in VertexData {
+    vec3 position;
+    vec3 normal;
+    vec3 tangent;
+    vec2 tex_coord;
+    vec4 color;
+} vertex;
+
+// Explicit locations keep the outputs in the order of the target's color buffers.
+layout (location = 0) out vec4 out_albedo;
+layout (location = 1) out vec4 out_normal;
+layout (location = 2) out vec4 out_emission;
+
+float tex_coord(float coord, vec2 offset) {
+    return offset.x + coord * (offset.y - offset.x);
+}
+
+vec2 tex_coords(vec2 coord, vec2 u, vec2 v) {
+    return vec2(tex_coord(coord.x, u), tex_coord(coord.y, v));
+}
+
+void main() {
+    vec4 color = texture(albedo, tex_coords(vertex.tex_coord, albedo_offset.u_offset, albedo_offset.v_offset));
+    if (color.a < alpha_cutoff) discard;
+    vec4 ecolor = texture(emission, tex_coords(vertex.tex_coord, emission_offset.u_offset, emission_offset.v_offset));
+    out_albedo = vec4((color * vertex.color).rgb, 1.0);
+    // Back faces are only drawn for two-sided materials, and are lit from their own side.
+    vec3 normal = gl_FrontFacing ? vertex.normal : -vertex.normal;
+    // World space normal, mapped from -1..1 into the displayable range of the buffer.
+    out_normal = vec4(normalize(normal) * 0.5 + 0.5, 1.0);
+    out_emission = vec4(ecolor.rgb * emission_intensity * vertex.color.rgb, 1.0);
+}
@@ -0,0 +1,30 @@
+// Smears the bright pass radially towards the sun, which makes the light appear to shine through
+// gaps between the objects in front of it.
+
+#version 150 core
+
+uniform sampler2D source;
+// Position of the sun on the screen, from (0, 0) at the bottom left to (1, 1) at the top right.
+uniform vec2 sun_position;
+// Fraction of the way to the sun the samples cover.
+uniform float density;
+// How much each sample counts less than the one before it.
+uniform float decay;
+uniform float samples;
+
+in vec2 tex_coord;
+
+out vec4 color;
+
+void main() {
+    vec2 step = (tex_coord - sun_position) * density / samples;
+    vec2 coord = tex_coord;
+    float weight = 1.0;
+    vec3 total = vec3(0.0);
+    for (float i = 0.0; i < samples; i += 1.0) {
+        coord -= step;
+        total += texture(source, coord).rgb * weight;
+        weight *= decay;
+    }
+    color = vec4(total / samples, 1.0);
+}
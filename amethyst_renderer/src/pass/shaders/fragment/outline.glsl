@@ -0,0 +1,11 @@
+// Draws every fragment of the outline hull in the outline color.
+
+#version 150 core
+
+uniform vec4 outline_color;
+
+out vec4 color;
+
+void main() {
+    color = outline_color;
+}
@@ -0,0 +1,29 @@
+// Textured particles, optionally fading out where they intersect the scene.
+
+#version 150 core
+
+uniform sampler2D albedo;
+// Depth buffer of the scene drawn before the particles.
+uniform sampler2D depth;
+uniform float use_depth_fade;
+// View space distance over which particles fade out in front of the scene.
+uniform float fade_distance;
+uniform mat4 inv_proj;
+
+in VertexData {
+    vec2 tex_uv;
+    vec4 color;
+    float view_depth;
+} vertex;
+
+out vec4 color;
+
+void main() {
+    color = texture(albedo, vertex.tex_uv) * vertex.color;
+    if (use_depth_fade != 0.0) {
+        float scene = texelFetch(depth, ivec2(gl_FragCoord.xy), 0).r;
+        vec4 scene_position = inv_proj * vec4(0.0, 0.0, scene * 2.0 - 1.0, 1.0);
+        float scene_depth = scene_position.z / scene_position.w;
+        color.a *= clamp((vertex.view_depth - scene_depth) / fade_distance, 0.0, 1.0);
+    }
+}
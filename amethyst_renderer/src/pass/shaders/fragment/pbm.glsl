@@ -13,6 +13,8 @@ struct PointLight {
     vec3 color;
     float pad; // Workaround for bug in mac's implementation of opengl (loads garbage when accessing members of structures in arrays with dynamic indices).
     float intensity;
+    float radius;
+    float smoothness;
 };
 
 layout (std140) uniform PointLights {
@@ -49,12 +51,27 @@ uniform float alpha_cutoff;
 
 uniform sampler2D albedo;
 uniform sampler2D emission;
+uniform float emission_intensity;
 uniform sampler2D normal;
 uniform sampler2D metallic;
 uniform sampler2D roughness;
 uniform sampler2D ambient_occlusion;
 uniform sampler2D caveat;
 
+uniform samplerCube environment;
+uniform float environment_intensity;
+
+uniform sampler2D shadow_map;
+uniform mat4 shadow_matrix;
+uniform float shadow_depth_bias;
+uniform float shadow_slope_bias;
+// 1.0 for `ShadowReceiver`s of passes with shadows enabled, 0.0 otherwise.
+uniform float receive_shadows;
+
+// Ambient occlusion drawn by `DrawSsao`, used when `use_ssao` is 1.0.
+uniform sampler2D ssao_map;
+uniform float use_ssao;
+
 layout (std140) uniform AlbedoOffset {
     vec2 u_offset;
     vec2 v_offset;
@@ -165,6 +182,36 @@ vec3 compute_light(vec3 attenuation,
     return resulting_light;
 }
 
+// Fraction of ambient light reaching the fragment.
+float ssao_factor() {
+    if (use_ssao == 0.0) {
+        return 1.0;
+    }
+    return texture(ssao_map, gl_FragCoord.xy / vec2(textureSize(ssao_map, 0))).r;
+}
+
+// Fraction of light from the shadow casting directional light reaching the fragment.
+float shadow_factor(vec3 position, vec3 normal, vec3 light_direction) {
+    if (receive_shadows == 0.0) {
+        return 1.0;
+    }
+    vec4 clip = shadow_matrix * vec4(position, 1.0);
+    vec3 coords = clip.xyz / clip.w * 0.5 + 0.5;
+    if (any(lessThan(coords, vec3(0.0))) || any(greaterThan(coords, vec3(1.0)))) {
+        return 1.0;
+    }
+    float bias = shadow_depth_bias + shadow_slope_bias * (1.0 - max(dot(normal, light_direction), 0.0));
+    vec2 texel = 1.0 / vec2(textureSize(shadow_map, 0));
+    float lit = 0.0;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            float depth = texture(shadow_map, coords.xy + vec2(x, y) * texel).r;
+            lit += coords.z - bias > depth ? 0.0 : 1.0;
+        }
+    }
+    return lit / 9.0;
+}
+
 void main() {
     vec4 albedo_alpha       = texture(albedo, tex_coords(vertex.tex_coord, albedo_offset.u_offset, albedo_offset.v_offset)).rgba;
 
@@ -172,7 +219,7 @@ void main() {
     if(alpha < alpha_cutoff) discard;
 
     vec3 albedo             = albedo_alpha.rgb;
-    vec3 emission           = texture(emission, tex_coords(vertex.tex_coord, emission_offset.u_offset, emission_offset.v_offset)).rgb;
+    vec3 emission           = texture(emission, tex_coords(vertex.tex_coord, emission_offset.u_offset, emission_offset.v_offset)).rgb * emission_intensity;
     vec3 normal             = texture(normal, tex_coords(vertex.tex_coord, normal_offset.u_offset, normal_offset.v_offset)).rgb;
     float metallic          = texture(metallic, tex_coords(vertex.tex_coord, metallic_offset.u_offset, metallic_offset.v_offset)).r;
     float roughness         = texture(roughness, tex_coords(vertex.tex_coord, roughness_offset.u_offset, roughness_offset.v_offset)).r;
@@ -186,6 +233,10 @@ void main() {
     vec3 fresnel_base = mix(vec3(0.04), albedo, metallic);
 
     vec3 vertex_normal = normalize(vertex.normal);
+    // Back faces are only drawn for two-sided materials, and are lit from their own side.
+    if (!gl_FrontFacing) {
+        vertex_normal = -vertex_normal;
+    }
     vec3 vertex_tangent = normalize(vertex.tangent - vertex_normal * dot(vertex_normal, vertex.tangent));
     vec3 vertex_bitangent = normalize(cross(vertex_normal, vertex_tangent));
     mat3 vertex_basis = mat3(vertex_tangent, vertex_bitangent, vertex_normal);
@@ -194,6 +245,8 @@ void main() {
 
     vec3 view_direction = normalize(camera_position - vertex.position);
     vec3 lighted = vec3(0.0);
+    // Shadow of the first directional light, also applied to the light coming from the sky.
+    float sun_shadow = directional_light_count > 0 ? shadow_factor(vertex.position, normal, -normalize(dlight[0].direction)) : 1.0;
     for (int i = 0; i < point_light_count; i++) {
         vec3 light_direction = normalize(plight[i].position - vertex.position);
         float attenuation = plight[i].intensity / dot(light_direction, light_direction);
@@ -213,7 +266,7 @@ void main() {
 
     for (int i = 0; i < directional_light_count; i++) {
         vec3 light_direction = -normalize(dlight[i].direction);
-        float attenuation = 1.0;
+        float attenuation = i == 0 ? sun_shadow : 1.0;
 
         vec3 light = compute_light(vec3(attenuation),
                                    dlight[i].color,
@@ -280,8 +333,15 @@ void main() {
         lighted += light;
     }
 
-    vec3 ambient = ambient_color * albedo * ambient_occlusion;
-    vec3 color = ambient + lighted + emission;
+    float occlusion = ambient_occlusion * ssao_factor();
+    vec3 ambient = ambient_color * albedo * occlusion;
+
+    vec3 reflected = reflect(-view_direction, normal);
+    vec3 environment_fresnel = fresnel(max(dot(normal, view_direction), 0.0), fresnel_base);
+    vec3 reflection = texture(environment, reflected).rgb * environment_fresnel * (1.0 - roughness)
+                      * occlusion * sun_shadow * environment_intensity;
+
+    vec3 color = ambient + lighted + emission + reflection;
 
     out_color = vec4(color, alpha) * vertex.color;
 }
@@ -0,0 +1,44 @@
+// Draws glyphs from a signed distance field atlas, with an optional outline and soft shadow.
+//
+// The distance is read from the atlas' alpha channel, 0.5 being the edge of the glyph and
+// larger values inside it.
+
+#version 150 core
+
+uniform sampler2D atlas;
+uniform vec4 text_color;
+uniform vec4 outline_color;
+// Width of the outline in distance field units, 0.0 for none.
+uniform float outline_width;
+uniform vec4 shadow_color;
+// Offset of the shadow in texture coordinates.
+uniform vec2 shadow_offset;
+// Blur of the shadow in distance field units.
+uniform float shadow_softness;
+
+in VertexData {
+    vec2 tex_uv;
+} vertex;
+
+out vec4 color;
+
+void main() {
+    float distance = texture(atlas, vertex.tex_uv).a;
+    // Smoothing over about a pixel keeps the edges crisp at any scale.
+    float smoothing = max(0.5 * fwidth(distance), 0.0001);
+    float fill = smoothstep(0.5 - smoothing, 0.5 + smoothing, distance);
+    float edge = 0.5 - max(outline_width, 0.0);
+    float outline = smoothstep(edge - smoothing, edge + smoothing, distance);
+
+    vec4 glyph = outline_width > 0.0 ? mix(outline_color, text_color, fill) : text_color;
+    glyph.a *= outline;
+
+    float shadow_distance = texture(atlas, vertex.tex_uv - shadow_offset).a;
+    float softness = max(shadow_softness, smoothing);
+    float shadow = smoothstep(edge - softness, edge + softness, shadow_distance) * shadow_color.a;
+
+    // The glyph goes over its shadow.
+    float alpha = glyph.a + shadow * (1.0 - glyph.a);
+    vec3 rgb = glyph.rgb * glyph.a + shadow_color.rgb * shadow * (1.0 - glyph.a);
+    color = vec4(rgb / max(alpha, 0.0001), alpha);
+}
@@ -5,6 +5,7 @@
 layout (std140) uniform FragmentArgs {
     uint point_light_count;
     uint directional_light_count;
+    uint spot_light_count;
 };
 
 struct PointLight {
@@ -12,6 +13,8 @@ struct PointLight {
     vec3 color;
     float pad; // Workaround for bug in mac's implementation of opengl (loads garbage when accessing members of structures in arrays with dynamic indices).
     float intensity;
+    float radius;
+    float smoothness;
 };
 
 layout (std140) uniform PointLights {
@@ -27,11 +30,42 @@ layout (std140) uniform DirectionalLights {
     DirectionalLight dlight[16];
 };
 
+struct SpotLight {
+    vec3 position;
+    vec3 color;
+    vec3 direction;
+    float angle;
+    float intensity;
+    float range;
+    float smoothness;
+};
+
+layout (std140) uniform SpotLights {
+    SpotLight slight[128];
+};
+
 uniform vec3 ambient_color;
 uniform vec3 camera_position;
 
+uniform float alpha_cutoff;
+
 uniform sampler2D albedo;
 uniform sampler2D emission;
+uniform float emission_intensity;
+uniform sampler2D normal;
+// 1.0 when the material has a normal map of its own, 0.0 keeps the vertex normal.
+uniform float use_normal_map;
+
+uniform sampler2D shadow_map;
+uniform mat4 shadow_matrix;
+uniform float shadow_depth_bias;
+uniform float shadow_slope_bias;
+// 1.0 for `ShadowReceiver`s of passes with shadows enabled, 0.0 otherwise.
+uniform float receive_shadows;
+
+// Ambient occlusion drawn by `DrawSsao`, used when `use_ssao` is 1.0.
+uniform sampler2D ssao_map;
+uniform float use_ssao;
 
 layout (std140) uniform AlbedoOffset {
     vec2 u_offset;
@@ -43,6 +77,11 @@ layout (std140) uniform EmissionOffset {
     vec2 v_offset;
 } emission_offset;
 
+layout (std140) uniform NormalOffset {
+    vec2 u_offset;
+    vec2 v_offset;
+} normal_offset;
+
 in VertexData {
     vec3 position;
     vec3 normal;
@@ -61,28 +100,127 @@ vec2 tex_coords(vec2 coord, vec2 u, vec2 v) {
     return vec2(tex_coord(coord.x, u), tex_coord(coord.y, v));
 }
 
+// Applies the material's tangent space normal map to the vertex normal.
+//
+// The vertex tangent is used when the mesh has one, otherwise the tangent frame is derived from
+// the screen-space derivatives of position and texture coordinates.
+vec3 surface_normal() {
+    vec3 vertex_normal = normalize(vertex.normal);
+    // Back faces are only drawn for two-sided materials, and are lit from their own side.
+    if (!gl_FrontFacing) {
+        vertex_normal = -vertex_normal;
+    }
+    if (use_normal_map == 0.0) {
+        return vertex_normal;
+    }
+
+    vec2 uv = tex_coords(vertex.tex_coord, normal_offset.u_offset, normal_offset.v_offset);
+    vec3 mapped = texture(normal, uv).rgb * 2.0 - 1.0;
+
+    // Derivatives have to be taken outside of the non-uniform branch below.
+    vec3 dp1 = dFdx(vertex.position);
+    vec3 dp2 = dFdy(vertex.position);
+    vec2 duv1 = dFdx(uv);
+    vec2 duv2 = dFdy(uv);
+
+    vec3 tangent = vertex.tangent - vertex_normal * dot(vertex_normal, vertex.tangent);
+    vec3 bitangent;
+    if (dot(tangent, tangent) > 1.0e-8) {
+        tangent = normalize(tangent);
+        bitangent = normalize(cross(vertex_normal, tangent));
+    } else {
+        vec3 dp2perp = cross(dp2, vertex_normal);
+        vec3 dp1perp = cross(vertex_normal, dp1);
+        tangent = dp2perp * duv1.x + dp1perp * duv2.x;
+        bitangent = dp2perp * duv1.y + dp1perp * duv2.y;
+        float scale = inversesqrt(max(max(dot(tangent, tangent), dot(bitangent, bitangent)), 1.0e-16));
+        tangent *= scale;
+        bitangent *= scale;
+    }
+    return normalize(mat3(tangent, bitangent, vertex_normal) * mapped);
+}
+
+// Inverse square falloff of a point light, windowed to reach zero at its radius.
+//
+// With the default smoothness of 4 this is the common `(1 - (d / r)^4)^2` window; lower values
+// fade the light out earlier, higher ones keep it bright closer to the radius.
+float point_attenuation(vec3 light_vec, float intensity, float radius, float smoothness) {
+    float dist2 = max(dot(light_vec, light_vec), 1.0e-4);
+    float ratio = sqrt(dist2) / max(radius, 1.0e-5);
+    float window = clamp(1.0 - pow(ratio, max(smoothness, 1.0e-5)), 0.0, 1.0);
+    return intensity / dist2 * window * window;
+}
+
+// Linear falloff over the range of a spot light, faded out towards the rim of its cone.
+float spot_attenuation(vec3 light_vec, float cos_angle, vec3 direction, float range, float smoothness) {
+    float range_attenuation = max(0.0, 1.0 - length(light_vec) / max(range, 1.0e-5));
+    float spot_angle = max(cos_angle, 1.0e-5);
+    float frag_angle = max(dot(normalize(direction), -normalize(light_vec)), spot_angle);
+    float rim_attenuation = pow(max((1.0 - frag_angle) / (1.0 - spot_angle), 1.0e-5), 1.0 - smoothness);
+    return range_attenuation * (1.0 - rim_attenuation);
+}
+
+// Fraction of ambient light reaching the fragment.
+float ssao_factor() {
+    if (use_ssao == 0.0) {
+        return 1.0;
+    }
+    return texture(ssao_map, gl_FragCoord.xy / vec2(textureSize(ssao_map, 0))).r;
+}
+
+// Fraction of light from the shadow casting directional light reaching the fragment.
+float shadow_factor(vec3 position, vec3 normal, vec3 light_direction) {
+    if (receive_shadows == 0.0) {
+        return 1.0;
+    }
+    vec4 clip = shadow_matrix * vec4(position, 1.0);
+    vec3 coords = clip.xyz / clip.w * 0.5 + 0.5;
+    if (any(lessThan(coords, vec3(0.0))) || any(greaterThan(coords, vec3(1.0)))) {
+        return 1.0;
+    }
+    float bias = shadow_depth_bias + shadow_slope_bias * (1.0 - max(dot(normal, light_direction), 0.0));
+    vec2 texel = 1.0 / vec2(textureSize(shadow_map, 0));
+    float lit = 0.0;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            float depth = texture(shadow_map, coords.xy + vec2(x, y) * texel).r;
+            lit += coords.z - bias > depth ? 0.0 : 1.0;
+        }
+    }
+    return lit / 9.0;
+}
+
 void main() {
     vec4 color = texture(albedo, tex_coords(vertex.tex_coord, albedo_offset.u_offset, albedo_offset.v_offset));
+    if (color.a < alpha_cutoff) discard;
     vec4 ecolor = texture(emission, tex_coords(vertex.tex_coord, emission_offset.u_offset, emission_offset.v_offset));
+    ecolor.rgb *= emission_intensity;
     vec3 lighting = vec3(0.0);
-    vec3 normal = normalize(vertex.normal);
+    vec3 normal = surface_normal();
     for (uint i = 0u; i < point_light_count; i++) {
         // Calculate diffuse light
-        vec3 light_dir = normalize(plight[i].position - vertex.position);
-        float diff = max(dot(light_dir, normal), 0.0);
+        vec3 light_vec = plight[i].position - vertex.position;
+        float diff = max(dot(normalize(light_vec), normal), 0.0);
         vec3 diffuse = diff * normalize(plight[i].color);
         // Calculate attenuation
-        vec3 dist = plight[i].position - vertex.position;
-        float dist2 = dot(dist, dist);
-        float attenuation = (plight[i].intensity / dist2);
+        float attenuation = point_attenuation(light_vec, plight[i].intensity, plight[i].radius, plight[i].smoothness);
         lighting += diffuse * attenuation;
     }
     for (uint i = 0u; i < directional_light_count; i++) {
         vec3 dir = dlight[i].direction;
         float diff = max(dot(-dir, normal), 0.0);
         vec3 diffuse = diff * dlight[i].color;
+        if (i == 0u) {
+            diffuse *= shadow_factor(vertex.position, normal, normalize(-dir));
+        }
         lighting += diffuse;
     }
-    lighting += ambient_color;
+    for (uint i = 0u; i < spot_light_count; i++) {
+        vec3 light_vec = slight[i].position - vertex.position;
+        float diff = max(dot(normalize(light_vec), normal), 0.0);
+        float attenuation = spot_attenuation(light_vec, slight[i].angle, slight[i].direction, slight[i].range, slight[i].smoothness);
+        lighting += diff * slight[i].color * attenuation * slight[i].intensity;
+    }
+    lighting += ambient_color * ssao_factor();
     out_color = (vec4(lighting, 1.0) * color + ecolor) * vertex.color;
 }
@@ -0,0 +1,32 @@
+// Only the depth of shadow casters matters, the color output is unused. Fragments below the
+// material's alpha cutoff are discarded so cut-out parts don't cast shadows.
+
+#version 150 core
+
+uniform sampler2D albedo;
+uniform float alpha_cutoff;
+
+layout (std140) uniform AlbedoOffset {
+    vec2 u_offset;
+    vec2 v_offset;
+} albedo_offset;
+
+in VertexData {
+    vec2 tex_coord;
+} vertex;
+
+out vec4 color;
+
+float tex_coord(float coord, vec2 offset) {
+    return offset.x + coord * (offset.y - offset.x);
+}
+
+vec2 tex_coords(vec2 coord, vec2 u, vec2 v) {
+    return vec2(tex_coord(coord.x, u), tex_coord(coord.y, v));
+}
+
+void main() {
+    float alpha = texture(albedo, tex_coords(vertex.tex_coord, albedo_offset.u_offset, albedo_offset.v_offset)).a;
+    if (alpha < alpha_cutoff) discard;
+    color = vec4(1.0);
+}
@@ -1,13 +1,34 @@
-// TODO: Needs documentation.
+// Samples the cubemaps of a `SkyBox`, crossfading, tinting and exposing them in linear space.
 
 #version 330 core
 
 uniform samplerCube skybox;
+uniform samplerCube skybox_b;
+uniform float blend;
+uniform vec4 tint;
+uniform float exposure;
+// Added to the mip level the cubemaps are sampled at.
+uniform float lod_bias;
+// 1.0 to encode the linear sky to sRGB for a target that is displayed as it is.
+uniform float encode_srgb;
 
 in vec3 TexCoords;
 
 out vec4 color;
 
+vec3 linear_to_srgb(vec3 linear) {
+    vec3 low = linear * 12.92;
+    vec3 high = 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055;
+    return mix(high, low, vec3(lessThanEqual(linear, vec3(0.0031308))));
+}
+
 void main() {
-    color = texture(skybox, TexCoords);
+    vec4 sky_a = texture(skybox, TexCoords, lod_bias);
+    vec4 sky_b = texture(skybox_b, TexCoords, lod_bias);
+    vec4 sky = mix(sky_a, sky_b, blend) * tint;
+    vec3 rgb = sky.rgb * exposure;
+    if (encode_srgb != 0.0) {
+        rgb = linear_to_srgb(clamp(rgb, 0.0, 1.0));
+    }
+    color = vec4(rgb, sky.a);
 }
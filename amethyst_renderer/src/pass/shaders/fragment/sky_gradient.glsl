@@ -0,0 +1,19 @@
+// Fragment shader drawing a three color horizon-to-zenith gradient.
+
+#version 330 core
+
+uniform vec4 top_color;
+uniform vec4 horizon_color;
+uniform vec4 bottom_color;
+uniform float exponent;
+
+in vec3 TexCoords;
+
+out vec4 color;
+
+void main() {
+    float height = normalize(TexCoords).y;
+    float t = pow(abs(height), exponent);
+    vec4 far_color = height >= 0.0 ? top_color : bottom_color;
+    color = mix(horizon_color, far_color, t);
+}
@@ -0,0 +1,42 @@
+// Draws a `SkyOverlay` as a disc with a soft glow around a direction of the sky.
+
+#version 330 core
+
+// Normalized direction from the viewer towards the center of the overlay.
+uniform vec3 direction;
+// Angular radius of the disc, in radians.
+uniform float radius;
+uniform vec4 overlay_color;
+// Angular width over which the glow fades to a third, 0.0 to disable it.
+uniform float glow_radius;
+uniform vec4 glow_color;
+// 1.0 to encode the linear colors to sRGB for a target that is displayed as it is.
+uniform float encode_srgb;
+
+in vec3 TexCoords;
+
+out vec4 color;
+
+vec3 linear_to_srgb(vec3 linear) {
+    vec3 low = linear * 12.92;
+    vec3 high = 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055;
+    return mix(high, low, vec3(lessThanEqual(linear, vec3(0.0031308))));
+}
+
+void main() {
+    float angle = acos(clamp(dot(normalize(TexCoords), direction), -1.0, 1.0));
+    // Antialiases the rim of the disc over about one pixel.
+    float edge = max(fwidth(angle), 1e-5);
+    float disc = 1.0 - smoothstep(radius - edge, radius + edge, angle);
+    float glow = glow_radius > 0.0 ? exp(-max(angle - radius, 0.0) / glow_radius) : 0.0;
+
+    vec3 rgb = mix(glow_color.rgb, overlay_color.rgb, disc);
+    float alpha = max(disc * overlay_color.a, glow * glow_color.a);
+    if (alpha <= 0.0) {
+        discard;
+    }
+    if (encode_srgb != 0.0) {
+        rgb = linear_to_srgb(clamp(rgb, 0.0, 1.0));
+    }
+    color = vec4(rgb, alpha);
+}
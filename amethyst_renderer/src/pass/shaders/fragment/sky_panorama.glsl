@@ -0,0 +1,19 @@
+// Fragment shader sampling an equirectangular panorama along the view ray.
+
+#version 330 core
+
+const float PI = 3.14159265359;
+
+uniform sampler2D panorama;
+
+in vec3 TexCoords;
+
+out vec4 color;
+
+void main() {
+    vec3 direction = normalize(TexCoords);
+    // Images are flipped on upload, so the top row of the panorama is at v = 1.
+    vec2 uv = vec2(0.5 + atan(direction.x, -direction.z) / (2.0 * PI),
+                   1.0 - acos(clamp(direction.y, -1.0, 1.0)) / PI);
+    color = texture(panorama, uv);
+}
@@ -4,7 +4,6 @@
 
 in VertexData {
     vec3 position;
-    vec2 tex_coord;
 } vertex;
 
 out vec4 out_color;
@@ -0,0 +1,67 @@
+// Screen-space ambient occlusion from the depth buffer, sampling a hemisphere around each pixel.
+
+#version 150 core
+
+const int MAX_SAMPLES = 64;
+
+layout (std140) uniform SsaoKernel {
+    vec4 samples[MAX_SAMPLES];
+};
+
+uniform sampler2D depth;
+uniform sampler2D noise;
+uniform sampler2D normals;
+
+uniform mat4 proj;
+uniform mat4 inv_proj;
+uniform float radius;
+uniform float bias;
+uniform int sample_count;
+// Number of times the 4x4 noise texture is repeated across the screen.
+uniform vec2 noise_scale;
+// 1.0 when `normals` holds view space normals, otherwise they are derived from depth.
+uniform float use_normals;
+
+in vec2 tex_coord;
+
+out vec4 color;
+
+vec3 view_position(vec2 uv) {
+    float d = texture(depth, uv).r;
+    vec4 position = inv_proj * vec4(vec3(uv, d) * 2.0 - 1.0, 1.0);
+    return position.xyz / position.w;
+}
+
+void main() {
+    if (texture(depth, tex_coord).r >= 1.0) {
+        // Background, nothing to occlude.
+        color = vec4(1.0);
+        return;
+    }
+
+    vec3 position = view_position(tex_coord);
+    vec3 normal = use_normals > 0.5
+        ? normalize(texture(normals, tex_coord).xyz * 2.0 - 1.0)
+        : normalize(cross(dFdx(position), dFdy(position)));
+
+    vec3 random = vec3(texture(noise, tex_coord * noise_scale).xy * 2.0 - 1.0, 0.0);
+    vec3 tangent = normalize(random - normal * dot(random, normal));
+    vec3 bitangent = cross(normal, tangent);
+    mat3 tbn = mat3(tangent, bitangent, normal);
+
+    int count = min(sample_count, MAX_SAMPLES);
+    float occlusion = 0.0;
+    for (int i = 0; i < count; ++i) {
+        vec3 sample_position = position + tbn * samples[i].xyz * radius;
+
+        vec4 offset = proj * vec4(sample_position, 1.0);
+        vec2 uv = offset.xy / offset.w * 0.5 + 0.5;
+        float scene_z = view_position(uv).z;
+
+        // Ignore geometry far outside the radius, it shouldn't darken silhouettes.
+        float range = smoothstep(0.0, 1.0, radius / abs(position.z - scene_z));
+        occlusion += (scene_z >= sample_position.z + bias ? 1.0 : 0.0) * range;
+    }
+
+    color = vec4(vec3(1.0 - occlusion / float(max(count, 1))), 1.0);
+}
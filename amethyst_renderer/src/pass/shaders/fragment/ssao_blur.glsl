@@ -0,0 +1,21 @@
+// Box blur over the 4x4 tile of the SSAO noise texture, removing its pattern.
+
+#version 150 core
+
+uniform sampler2D source;
+// Size of one texel in texture coordinates.
+uniform vec2 texel_size;
+
+in vec2 tex_coord;
+
+out vec4 color;
+
+void main() {
+    float occlusion = 0.0;
+    for (int x = -2; x < 2; ++x) {
+        for (int y = -2; y < 2; ++y) {
+            occlusion += texture(source, tex_coord + vec2(x, y) * texel_size).r;
+        }
+    }
+    color = vec4(vec3(occlusion / 16.0), 1.0);
+}
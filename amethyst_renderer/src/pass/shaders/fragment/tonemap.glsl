@@ -0,0 +1,45 @@
+// Maps HDR colors into the displayable range. Keep in sync with `TonemapOperator::apply`.
+
+#version 150 core
+
+uniform sampler2D source;
+uniform float exposure;
+// 0: Reinhard, 1: ACES filmic
+uniform int tonemap_operator;
+// The exposure adapted to by the auto exposure, mapped from `ev_range` onto `[0, 1]`. Read
+// while `auto_exposure` isn't zero.
+uniform sampler2D adapted;
+uniform float auto_exposure;
+// The lowest and highest stops the auto exposure adapts to.
+uniform vec2 ev_range;
+
+in vec2 tex_coord;
+
+out vec4 color;
+
+vec3 reinhard(vec3 x) {
+    return x / (1.0 + x);
+}
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve.
+vec3 aces_filmic(vec3 x) {
+    const float a = 2.51;
+    const float b = 0.03;
+    const float c = 2.43;
+    const float d = 0.59;
+    const float e = 0.14;
+    return clamp((x * (a * x + b)) / (x * (c * x + d) + e), 0.0, 1.0);
+}
+
+void main() {
+    vec4 hdr = texture(source, tex_coord);
+    float scale = exposure;
+    if (auto_exposure != 0.0) {
+        float ev = mix(ev_range.x, ev_range.y, texture(adapted, vec2(0.5)).r);
+        // Brings the scene's average back to middle gray.
+        scale *= exp2(-ev);
+    }
+    vec3 exposed = hdr.rgb * scale;
+    vec3 mapped = tonemap_operator == 0 ? reinhard(exposed) : aces_filmic(exposed);
+    color = vec4(mapped, hdr.a);
+}
@@ -0,0 +1,11 @@
+// Draws every fragment in a single line color.
+
+#version 150 core
+
+uniform vec4 line_color;
+
+out vec4 color;
+
+void main() {
+    color = line_color;
+}
@@ -0,0 +1,18 @@
+// Covers the screen with a single triangle on the far plane, like `sky.glsl`, passing on the
+// texture coordinates of a `BackdropLayer` at each pixel. Needs no vertex buffers.
+
+#version 150 core
+
+// Texture repeats across the screen on each axis.
+uniform vec2 scale;
+// Texture coordinates at the bottom left corner of the screen.
+uniform vec2 offset;
+
+out vec2 tex_coord;
+
+void main() {
+    vec2 position = vec2(float((gl_VertexID << 1) & 2), float(gl_VertexID & 2));
+    tex_coord = position * scale + offset;
+    // Exactly at the far depth, which passes the `LessEqual` test against a cleared depth buffer.
+    gl_Position = vec4(position * 2.0 - 1.0, 1.0, 1.0);
+}
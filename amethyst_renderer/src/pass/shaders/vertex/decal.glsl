@@ -0,0 +1,16 @@
+// Draws the box of a decal, covering the part of the scene it may project onto.
+
+#version 150 core
+
+layout (std140) uniform ViewArgs {
+    mat4 proj;
+    mat4 view;
+};
+
+uniform mat4 model;
+
+in vec3 position;
+
+void main() {
+    gl_Position = proj * view * model * vec4(position, 1.0);
+}
@@ -0,0 +1,11 @@
+// Covers the screen with a single triangle, without any vertex buffers.
+
+#version 150 core
+
+out vec2 tex_coord;
+
+void main() {
+    vec2 position = vec2(float((gl_VertexID << 1) & 2), float(gl_VertexID & 2));
+    tex_coord = position;
+    gl_Position = vec4(position * 2.0 - 1.0, 0.0, 1.0);
+}
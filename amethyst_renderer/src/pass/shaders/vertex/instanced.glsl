@@ -0,0 +1,38 @@
+// Same as basic.glsl, but takes the model matrix and tint per instance.
+
+#version 150 core
+
+layout (std140) uniform ViewArgs {
+    uniform mat4 proj;
+    uniform mat4 view;
+};
+
+in vec3 position;
+in vec3 normal;
+in vec3 tangent;
+in vec2 tex_coord;
+
+in vec4 model_col0;
+in vec4 model_col1;
+in vec4 model_col2;
+in vec4 model_col3;
+in vec4 color;
+
+out VertexData {
+    vec3 position;
+    vec3 normal;
+    vec3 tangent;
+    vec2 tex_coord;
+    vec4 color;
+} vertex;
+
+void main() {
+    mat4 model = mat4(model_col0, model_col1, model_col2, model_col3);
+    vec4 vertex_position = model * vec4(position, 1.0);
+    vertex.position = vertex_position.xyz;
+    vertex.normal = mat3(model) * normal;
+    vertex.tangent = mat3(model) * tangent;
+    vertex.tex_coord = tex_coord;
+    vertex.color = color;
+    gl_Position = proj * view * vertex_position;
+}
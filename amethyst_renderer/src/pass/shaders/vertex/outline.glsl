@@ -0,0 +1,22 @@
+// Pushes the vertices of a mesh out along their normals to form its outline hull.
+
+#version 150 core
+
+layout (std140) uniform VertexArgs {
+    uniform mat4 proj;
+    uniform mat4 view;
+    uniform mat4 model;
+    uniform vec4 color;
+};
+
+uniform float thickness;
+
+in vec3 position;
+in vec3 normal;
+
+void main() {
+    vec4 vertex_position = model * vec4(position, 1.0);
+    vec3 vertex_normal = normalize(mat3(model) * normal);
+    vertex_position.xyz += vertex_normal * thickness;
+    gl_Position = proj * view * vertex_position;
+}
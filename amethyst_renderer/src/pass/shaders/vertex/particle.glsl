@@ -0,0 +1,49 @@
+// Expands every particle into a quad facing the camera.
+
+#version 150 core
+
+layout (std140) uniform ViewArgs {
+    mat4 proj;
+    mat4 view;
+};
+
+// Camera axes in world space, which the quads are aligned with.
+uniform vec3 camera_right;
+uniform vec3 camera_up;
+
+in vec3 position;
+in float size;
+in vec4 color;
+in float rotation;
+
+out VertexData {
+    vec2 tex_uv;
+    vec4 color;
+    float view_depth;
+} vertex;
+
+const vec2 positions[6] = vec2[](
+    // First triangle
+    vec2(-0.5, -0.5), // Left bottom
+    vec2(0.5, -0.5), // Right bottom
+    vec2(0.5, 0.5), // Right top
+
+    // Second triangle
+    vec2(0.5, 0.5), // Right top
+    vec2(-0.5, 0.5), // Left top
+    vec2(-0.5, -0.5)  // Left bottom
+);
+
+void main() {
+    vec2 corner = positions[gl_VertexID];
+    float s = sin(rotation);
+    float c = cos(rotation);
+    vec2 rotated = vec2(c * corner.x - s * corner.y, s * corner.x + c * corner.y);
+
+    vec3 world = position + (rotated.x * camera_right + rotated.y * camera_up) * size;
+    vec4 view_position = view * vec4(world, 1.0);
+    vertex.tex_uv = corner + 0.5;
+    vertex.color = color;
+    vertex.view_depth = view_position.z;
+    gl_Position = proj * view_position;
+}
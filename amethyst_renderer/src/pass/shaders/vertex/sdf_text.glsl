@@ -0,0 +1,39 @@
+// Expands every glyph into a quad in the XY plane of its text's transform.
+
+#version 150 core
+
+layout (std140) uniform ViewArgs {
+    mat4 proj;
+    mat4 view;
+};
+
+uniform mat4 model;
+
+// Bottom left corner and size of the quad, in the text's local units.
+in vec2 glyph_position;
+in vec2 glyph_size;
+// Left, right, bottom and top texture coordinate of the glyph in the atlas.
+in vec4 glyph_uv;
+
+out VertexData {
+    vec2 tex_uv;
+} vertex;
+
+const vec2 corners[6] = vec2[](
+    // First triangle
+    vec2(0.0, 0.0), // Left bottom
+    vec2(1.0, 0.0), // Right bottom
+    vec2(1.0, 1.0), // Right top
+
+    // Second triangle
+    vec2(1.0, 1.0), // Right top
+    vec2(0.0, 1.0), // Left top
+    vec2(0.0, 0.0)  // Left bottom
+);
+
+void main() {
+    vec2 corner = corners[gl_VertexID];
+    vec2 local = glyph_position + corner * glyph_size;
+    vertex.tex_uv = vec2(mix(glyph_uv.x, glyph_uv.y, corner.x), mix(glyph_uv.z, glyph_uv.w, corner.y));
+    gl_Position = proj * view * model * vec4(local, 0.0, 1.0);
+}
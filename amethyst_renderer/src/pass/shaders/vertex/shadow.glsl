@@ -0,0 +1,18 @@
+// Transforms shadow casters into the light's clip space.
+
+#version 150 core
+
+uniform mat4 light_matrix;
+uniform mat4 model;
+
+in vec3 position;
+in vec2 tex_coord;
+
+out VertexData {
+    vec2 tex_coord;
+} vertex;
+
+void main() {
+    vertex.tex_coord = tex_coord;
+    gl_Position = light_matrix * model * vec4(position, 1.0);
+}
@@ -1,8 +1,10 @@
-// TODO: Needs documentation.
+// Like `basic.glsl`, but first moves each vertex by a weighted blend of up to four joints.
+// Keep in sync with the reference implementation in the tests of `pass/skinning.rs`.
 
 #version 150 core
 
 layout (std140) uniform JointTransforms {
+    // Sized by `MAX_JOINTS`.
     mat4 joints[100];
 };
 
@@ -28,18 +30,26 @@ out VertexData {
     vec4 color;
 } vertex;
 
-
+// Transforms normals like the inverse transpose of `m` up to a scale, which the fragment shaders
+// normalize away. Unlike the matrix itself it keeps normals perpendicular to the surface under
+// non-uniform scaling and the shearing a blend of joints with different rotations amounts to.
+mat3 cofactor(mat3 m) {
+    return mat3(cross(m[1], m[2]), cross(m[2], m[0]), cross(m[0], m[1]));
+}
 
 void main() {
-    mat4 joint_transform = joint_weights.x * joints[int(joint_ids.x)] +
-        joint_weights.y * joints[int(joint_ids.y)] +
-        joint_weights.z * joints[int(joint_ids.z)] +
-        joint_weights.w * joints[int(joint_ids.w)];
+    // Weights that don't quite add up to one, e.g. after quantization, would scale the vertex.
+    vec4 weights = joint_weights / max(dot(joint_weights, vec4(1.0)), 1e-4);
+    mat4 joint_transform = weights.x * joints[int(joint_ids.x)] +
+        weights.y * joints[int(joint_ids.y)] +
+        weights.z * joints[int(joint_ids.z)] +
+        weights.w * joints[int(joint_ids.w)];
 
     vec4 vertex_position = model * joint_transform * vec4(position, 1.0);
     mat3 mat3_transform = mat3(model) * mat3(joint_transform);
     vertex.position = vertex_position.xyz;
-    vertex.normal = mat3_transform * normal;
+    vertex.normal = cofactor(mat3_transform) * normal;
+    // Tangents lie in the surface, so they follow the surface itself.
     vertex.tangent = mat3_transform * tangent;
     vertex.tex_coord = tex_coord;
     vertex.color = color;
@@ -1,18 +1,22 @@
-// TODO: Needs documentation.
+// Covers the screen with a single triangle on the far plane, passing on the direction of the sky
+// each pixel shows. Needs no vertex buffers, see `unprojection` in `pass/sky/mod.rs`.
 
 #version 150 core
 
 layout (std140) uniform VertexArgs {
-    uniform mat4 proj;
-    uniform mat4 view;
+    // Inverse of the projection times the view's rotation.
+    uniform mat4 unproject;
 };
 
-in vec3 position;
-
 out vec3 TexCoords;
 
 void main() {
-    TexCoords = position;
-    mat4 v = mat4(mat3(view)); //remove translation from view matrix
-    gl_Position = proj * v * vec4(position, 1.0);
+    vec2 position = vec2(float((gl_VertexID << 1) & 2), float(gl_VertexID & 2)) * 2.0 - 1.0;
+    vec4 far = unproject * vec4(position, 1.0, 1.0);
+    // Points on the far plane are in the direction of `far.xyz / far.w`. Flipping instead of
+    // dividing keeps the direction linear across the triangle and valid for an infinite far
+    // plane, where `w` is zero.
+    TexCoords = far.w < 0.0 ? -far.xyz : far.xyz;
+    // Exactly at the far depth, which passes the `LessEqual` test against a cleared depth buffer.
+    gl_Position = vec4(position, 1.0, 1.0);
 }
@@ -10,11 +10,9 @@ layout (std140) uniform VertexArgs {
 };
 
 in vec3 position;
-in vec2 tex_coord;
 
 out VertexData {
     vec3 position;
-    vec2 tex_coord;
 } vertex;
 
 void main() {
@@ -23,7 +21,6 @@ void main() {
     vec4 vertex_position = model * vec4(position, 1.0);
 
     vertex.position = vertex_position.xyz;
-    vertex.tex_coord = tex_coord;
 
     gl_Position = (proj * view_without_translation * vertex_position).xyww;
 }
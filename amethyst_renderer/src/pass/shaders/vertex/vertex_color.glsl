@@ -0,0 +1,34 @@
+// Like `basic.glsl`, but multiplies the color of each vertex into the tint.
+
+#version 150 core
+
+// Same layout as in `basic.glsl`. Only the last member is renamed, as `color` names the vertex
+// attribute here.
+layout (std140) uniform VertexArgs {
+    uniform mat4 proj;
+    uniform mat4 view;
+    uniform mat4 model;
+    uniform vec4 tint;
+};
+
+in vec3 position;
+in vec4 color;
+in vec2 tex_coord;
+
+out VertexData {
+    vec3 position;
+    vec3 normal;
+    vec3 tangent;
+    vec2 tex_coord;
+    vec4 color;
+} vertex;
+
+void main() {
+    vec4 vertex_position = model * vec4(position, 1.0);
+    vertex.position = vertex_position.xyz;
+    vertex.normal = vec3(0.0);
+    vertex.tangent = vec3(0.0);
+    vertex.tex_coord = tex_coord;
+    vertex.color = tint * color;
+    gl_Position = proj * view * vertex_position;
+}
@@ -0,0 +1,181 @@
+//! Shadow map pass
+
+use std::marker::PhantomData;
+
+use derivative::Derivative;
+use gfx::pso::buffer::ElemStride;
+
+use amethyst_assets::AssetStorage;
+use amethyst_core::{
+    specs::prelude::{Join, Read, ReadExpect, ReadStorage},
+    transform::GlobalTransform,
+};
+use amethyst_error::Error;
+
+use crate::{
+    hidden::{Hidden, HiddenPropagate},
+    light::Light,
+    mesh::{Mesh, MeshHandle},
+    mtl::{Material, MaterialDefaults, MissingTextures},
+    pass::util::{
+        add_textures, set_alpha_cutoff, set_attribute_buffers, setup_alpha_cutoff, setup_textures,
+        TextureType,
+    },
+    pipe::{
+        pass::{Pass, PassData},
+        DepthMode, Effect, NewEffect, Target, TargetBuilder,
+    },
+    tex::Texture,
+    types::{Encoder, Factory},
+    vertex::{Position, Query, TexCoord},
+};
+
+use super::*;
+
+/// Render the depth of `ShadowCaster` meshes as seen from the first directional light
+///
+/// The pass has to run in its own stage, rendering into a target created with
+/// `DrawShadowMap::target`, before the lit passes that sample it. Lit passes read the map once
+/// they are built `with_shadows` and the name of that target. The area covered by the map is
+/// configured through the `ShadowSettings` resource. Casters with a `Material` are cut out
+/// below its `alpha_cutoff`, like the lit passes cut them out.
+///
+/// ```ignore
+/// Pipeline::build()
+///     .with_target(DrawShadowMap::<PosNormTex>::target("shadow_map", 2048))
+///     .with_stage(
+///         Stage::with_target("shadow_map")
+///             .clear_target([1.0; 4], 1.0)
+///             .with_pass(DrawShadowMap::<PosNormTex>::new()),
+///     )
+///     .with_stage(
+///         Stage::with_backbuffer()
+///             .clear_target([0.0, 0.0, 0.0, 1.0], 1.0)
+///             .with_pass(DrawShaded::<PosNormTex>::new().with_shadows("shadow_map")),
+///     )
+/// ```
+///
+/// # Type Parameters:
+///
+/// * `V`: `VertexFormat`
+#[derive(Derivative, Clone, Debug, PartialEq)]
+#[derivative(Default(bound = "V: Query<(Position, TexCoord)>"))]
+pub struct DrawShadowMap<V> {
+    _pd: PhantomData<V>,
+}
+
+impl<V> DrawShadowMap<V>
+where
+    V: Query<(Position, TexCoord)>,
+{
+    /// Create instance of `DrawShadowMap` pass
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns a builder for a square shadow map target with `resolution` texels per side.
+    pub fn target<N: Into<String>>(name: N, resolution: u32) -> TargetBuilder {
+        Target::named(name)
+            .with_depth_buf(true)
+            .with_size((resolution, resolution))
+    }
+}
+
+impl<'a, V> PassData<'a> for DrawShadowMap<V>
+where
+    V: Query<(Position, TexCoord)>,
+{
+    type Data = (
+        Read<'a, ShadowSettings>,
+        Read<'a, AssetStorage<Mesh>>,
+        Read<'a, AssetStorage<Texture>>,
+        ReadExpect<'a, MaterialDefaults>,
+        ReadExpect<'a, MissingTextures>,
+        ReadStorage<'a, Hidden>,
+        ReadStorage<'a, HiddenPropagate>,
+        ReadStorage<'a, MeshHandle>,
+        ReadStorage<'a, Material>,
+        ReadStorage<'a, GlobalTransform>,
+        ReadStorage<'a, Light>,
+        ReadStorage<'a, ShadowCaster>,
+    );
+}
+
+impl<V> Pass for DrawShadowMap<V>
+where
+    V: Query<(Position, TexCoord)>,
+{
+    fn compile(&mut self, effect: NewEffect<'_>) -> Result<Effect, Error> {
+        let mut builder = effect.simple(VERT_SRC, FRAG_SRC);
+        setup_textures(&mut builder, &[TextureType::Albedo]);
+        setup_alpha_cutoff(&mut builder);
+        builder
+            .with_raw_vertex_buffer(V::QUERIED_ATTRIBUTES, V::size() as ElemStride, 0)
+            .with_raw_global("light_matrix")
+            .with_raw_global("model")
+            .with_output("color", Some(DepthMode::LessEqualWrite))
+            .build()
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        _factory: Factory,
+        (
+            settings,
+            mesh_storage,
+            tex_storage,
+            material_defaults,
+            missing,
+            hidden,
+            hidden_prop,
+            mesh,
+            material,
+            global,
+            light,
+            caster,
+        ): <Self as PassData<'a>>::Data,
+    ) {
+        let light = match shadow_light(&light) {
+            Some(light) => light,
+            None => return,
+        };
+        let light_matrix: [[f32; 4]; 4] = settings.light_matrix(light.direction).into();
+        effect.update_global("light_matrix", light_matrix);
+
+        for (mesh, material, global, _, _, _) in (
+            &mesh,
+            material.maybe(),
+            &global,
+            &caster,
+            !&hidden,
+            !&hidden_prop,
+        )
+            .join()
+        {
+            let mesh = match mesh_storage.get(mesh) {
+                Some(mesh) => mesh,
+                None => continue,
+            };
+            if !set_attribute_buffers(effect, mesh, &[V::QUERIED_ATTRIBUTES]) {
+                effect.clear();
+                continue;
+            }
+            let model: [[f32; 4]; 4] = global.0.into();
+            effect.update_global("model", model);
+            set_alpha_cutoff(effect, material);
+            add_textures(
+                effect,
+                encoder,
+                &tex_storage,
+                material.unwrap_or(&material_defaults.0),
+                &material_defaults.0,
+                &missing,
+                &[TextureType::Albedo],
+            );
+            effect.draw(mesh.slice(), encoder);
+            effect.clear();
+        }
+    }
+}
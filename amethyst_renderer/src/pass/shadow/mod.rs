@@ -0,0 +1,224 @@
+pub use self::interleaved::DrawShadowMap;
+
+use amethyst_core::{
+    nalgebra::{Matrix4, Orthographic3, Point3, Vector3},
+    specs::{
+        prelude::{Component, Join, ReadStorage},
+        storage::NullStorage,
+    },
+};
+use amethyst_error::Error;
+use gfx::memory::Typed;
+
+use crate::{
+    error,
+    light::{DirectionalLight, Light},
+    pipe::{Effect, EffectBuilder, NewEffect, Targets},
+    tex::{FilterMethod, SamplerInfo, WrapMode},
+    types::{Sampler, ShaderResourceView},
+};
+
+mod interleaved;
+
+static VERT_SRC: &[u8] = include_bytes!("../shaders/vertex/shadow.glsl");
+static FRAG_SRC: &[u8] = include_bytes!("../shaders/fragment/shadow.glsl");
+
+/// Marks an entity's mesh to be drawn into the shadow map by `DrawShadowMap`.
+#[derive(Clone, Debug, Default)]
+pub struct ShadowCaster;
+
+impl Component for ShadowCaster {
+    type Storage = NullStorage<Self>;
+}
+
+/// Marks an entity to be darkened by shadows in lit passes that have shadows enabled.
+#[derive(Clone, Debug, Default)]
+pub struct ShadowReceiver;
+
+impl Component for ShadowReceiver {
+    type Storage = NullStorage<Self>;
+}
+
+/// Area covered by the shadow map and the biases used when sampling it.
+///
+/// Shadows are cast by the first directional light, from a box of `2 * extent` units around
+/// `center`. A smaller box gives sharper shadows. Objects outside of it neither cast nor receive
+/// shadows.
+///
+/// Shadow acne, stripes of self-shadowing on lit surfaces, is removed by raising the biases.
+/// Too much bias detaches shadows from their casters ("peter-panning"), so keep them as small as
+/// the scene allows.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShadowSettings {
+    /// Center of the shadowed area in world space.
+    pub center: [f32; 3],
+    /// Half the size of the shadowed area along each axis of the light.
+    pub extent: f32,
+    /// Depth offset applied to every receiving surface, in shadow map depth units.
+    pub depth_bias: f32,
+    /// Additional depth offset for surfaces facing away from the light.
+    pub slope_bias: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        ShadowSettings {
+            center: [0.0; 3],
+            extent: 20.0,
+            depth_bias: 0.002,
+            slope_bias: 0.01,
+        }
+    }
+}
+
+impl ShadowSettings {
+    /// Returns the matrix transforming world positions into the shadow map's clip space for a
+    /// directional light shining along `direction`.
+    pub fn light_matrix(&self, direction: [f32; 3]) -> Matrix4<f32> {
+        let direction = Vector3::new(direction[0], direction[1], direction[2]);
+        let direction = direction.try_normalize(1.0e-6).unwrap_or(-Vector3::y());
+        let center = Point3::new(self.center[0], self.center[1], self.center[2]);
+        let eye = center - direction * self.extent;
+        let up = if direction.x.abs() < 1.0e-3 && direction.z.abs() < 1.0e-3 {
+            Vector3::z()
+        } else {
+            Vector3::y()
+        };
+        let view = Matrix4::look_at_rh(&eye, &center, &up);
+        let e = self.extent;
+        let proj = Orthographic3::new(-e, e, -e, e, 0.0, 2.0 * e);
+        proj.as_matrix() * view
+    }
+}
+
+/// Returns the first directional light, which is the one casting shadows.
+fn shadow_light<'a>(light: &'a ReadStorage<'_, Light>) -> Option<&'a DirectionalLight> {
+    light.join().find_map(|light| match *light {
+        Light::Directional(ref light) => Some(light),
+        _ => None,
+    })
+}
+
+/// Shadow map sampled by a lit pass.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ShadowInput {
+    target: String,
+    map: Option<(ShaderResourceView<f32>, Sampler)>,
+}
+
+impl ShadowInput {
+    pub(crate) fn new(target: String) -> Self {
+        ShadowInput { target, map: None }
+    }
+
+    /// Looks up the depth buffer of the shadow map target.
+    pub(crate) fn compile(&mut self, effect: &mut NewEffect<'_>) -> Result<(), Error> {
+        use gfx::Factory;
+
+        let target = effect
+            .target(&self.target)
+            .ok_or_else(|| error::Error::NoSuchTarget(self.target.clone()))?;
+        let view = target
+            .depth_buf()
+            .and_then(|db| db.as_input.clone())
+            .ok_or_else(|| error::Error::TargetNotReadable(self.target.clone()))?;
+        let sampler = effect
+            .factory
+            .create_sampler(SamplerInfo::new(FilterMethod::Scale, WrapMode::Clamp));
+        self.map = Some((view, sampler));
+        Ok(())
+    }
+
+    /// Registers the shadow map and its uniforms. Call this before `setup_textures`, so the map
+    /// can be added ahead of the material textures.
+    pub(crate) fn setup(builder: &mut EffectBuilder<'_>) {
+        builder
+            .with_texture("shadow_map")
+            .with_raw_global("shadow_matrix")
+            .with_raw_global("shadow_depth_bias")
+            .with_raw_global("shadow_slope_bias")
+            .with_raw_global("receive_shadows");
+    }
+
+    /// Updates the uniforms shared by all draws of a frame.
+    pub(crate) fn set_args(
+        &self,
+        effect: &mut Effect,
+        settings: &ShadowSettings,
+        light: &ReadStorage<'_, Light>,
+    ) {
+        let matrix: [[f32; 4]; 4] = shadow_light(light)
+            .map(|light| settings.light_matrix(light.direction))
+            .unwrap_or_else(Matrix4::identity)
+            .into();
+        effect.update_global("shadow_matrix", matrix);
+        effect.update_global("shadow_depth_bias", settings.depth_bias);
+        effect.update_global("shadow_slope_bias", settings.slope_bias);
+    }
+
+    pub(crate) fn new_targets(&mut self, targets: &Targets) {
+        let view = targets
+            .get(&self.target)
+            .and_then(|target| target.depth_buf())
+            .and_then(|db| db.as_input.clone());
+        if let (Some(view), Some(map)) = (view, self.map.as_mut()) {
+            map.0 = view;
+        }
+    }
+}
+
+/// Adds the shadow map ahead of the next draw, if the pass has shadows enabled.
+pub(crate) fn bind_shadows(
+    effect: &mut Effect,
+    shadows: &Option<ShadowInput>,
+    receiver: Option<&ShadowReceiver>,
+) {
+    if let Some((ref view, ref sampler)) = shadows.as_ref().and_then(|s| s.map.as_ref()) {
+        effect.data.textures.push(view.raw().clone());
+        effect.data.samplers.push(sampler.clone());
+        let receive = if receiver.is_some() { 1.0 } else { 0.0 };
+        effect.update_global("receive_shadows", receive);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use amethyst_core::nalgebra::Vector4;
+
+    use super::ShadowSettings;
+
+    fn project(settings: &ShadowSettings, direction: [f32; 3], point: [f32; 3]) -> Vector4<f32> {
+        settings.light_matrix(direction) * Vector4::new(point[0], point[1], point[2], 1.0)
+    }
+
+    #[test]
+    fn light_box_maps_to_clip_space() {
+        let settings = ShadowSettings {
+            center: [1.0, 2.0, 3.0],
+            extent: 10.0,
+            ..ShadowSettings::default()
+        };
+        let direction = [0.0, -1.0, -1.0];
+
+        let center = project(&settings, direction, [1.0, 2.0, 3.0]);
+        assert!((center - Vector4::new(0.0, 0.0, 0.0, 1.0)).norm() < 1e-5);
+
+        // Closest to the light ends up on the near plane, farthest on the far plane.
+        let offset = 10.0 / 2.0f32.sqrt();
+        let near = project(&settings, direction, [1.0, 2.0 + offset, 3.0 + offset]);
+        let far = project(&settings, direction, [1.0, 2.0 - offset, 3.0 - offset]);
+        assert!((near.z + 1.0).abs() < 1e-5);
+        assert!((far.z - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn straight_down_light_is_valid() {
+        let settings = ShadowSettings::default();
+        let matrix = settings.light_matrix([0.0, -1.0, 0.0]);
+        assert!(matrix.iter().all(|v| v.is_finite()));
+
+        let ground = project(&settings, [0.0, -1.0, 0.0], [5.0, 0.0, -5.0]);
+        assert!(ground.x.abs() <= 1.0 && ground.y.abs() <= 1.0);
+        assert!(ground.z.abs() < 1e-5);
+    }
+}
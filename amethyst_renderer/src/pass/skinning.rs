@@ -8,7 +8,7 @@ use crate::{
     mesh::Mesh,
     pass::util::set_attribute_buffers,
     pipe::{Effect, EffectBuilder, NewEffect},
-    skinning::{JointIds, JointWeights},
+    skinning::{JointIds, JointWeights, MAX_JOINTS},
     vertex::{Attributes, Separate, VertexFormat},
 };
 
@@ -37,9 +37,86 @@ pub(crate) fn setup_skinning_buffers<'a>(builder: &mut EffectBuilder<'a>) {
             Separate::<JointWeights>::size() as ElemStride,
             0,
         )
-        .with_raw_constant_buffer("JointTransforms", mem::size_of::<[[f32; 4]; 4]>(), 100);
+        .with_raw_constant_buffer(
+            "JointTransforms",
+            mem::size_of::<[[f32; 4]; 4]>(),
+            MAX_JOINTS,
+        );
 }
 
 pub fn set_skinning_buffers(effect: &mut Effect, mesh: &Mesh) -> bool {
     set_attribute_buffers(effect, mesh, &ATTRIBUTES)
 }
+
+#[cfg(test)]
+mod tests {
+    //! Checks the skinned normals of a two joint mesh, using a CPU port of `skinned.glsl`.
+
+    use amethyst_core::nalgebra::{Matrix3, Matrix4, Vector3, Vector4, U3};
+
+    fn cofactor(m: &Matrix3<f32>) -> Matrix3<f32> {
+        let (c0, c1, c2) = (m.column(0), m.column(1), m.column(2));
+        Matrix3::from_columns(&[c1.cross(&c2), c2.cross(&c0), c0.cross(&c1)])
+    }
+
+    /// Returns the skinned tangent and normal of a vertex, as the vertex shader computes them.
+    fn skin(
+        joints: &[Matrix4<f32>],
+        weights: Vector4<f32>,
+        tangent: Vector3<f32>,
+        normal: Vector3<f32>,
+    ) -> (Vector3<f32>, Vector3<f32>) {
+        let weights = weights / weights.sum().max(1e-4);
+        let transform = joints
+            .iter()
+            .zip(weights.iter())
+            .fold(Matrix4::zeros(), |sum, (joint, weight)| {
+                sum + joint * *weight
+            });
+        let transform = transform.fixed_slice::<U3, U3>(0, 0).into_owned();
+        (
+            transform * tangent,
+            (cofactor(&transform) * normal).normalize(),
+        )
+    }
+
+    fn joints(angle: f32, scale: f32) -> [Matrix4<f32>; 2] {
+        [
+            Matrix4::identity(),
+            Matrix4::new_rotation(Vector3::z() * angle)
+                * Matrix4::new_nonuniform_scaling(&Vector3::new(scale, 1.0, 1.0)),
+        ]
+    }
+
+    #[test]
+    fn normals_follow_the_bent_joint() {
+        let joints = joints(std::f32::consts::FRAC_PI_2, 1.0);
+        let (_, normal) = skin(
+            &joints,
+            Vector4::new(0.0, 1.0, 0.0, 0.0),
+            Vector3::x(),
+            Vector3::y(),
+        );
+        assert!((normal - Vector3::new(-1.0, 0.0, 0.0)).norm() < 1e-6);
+    }
+
+    #[test]
+    fn normals_stay_perpendicular_to_the_surface() {
+        let tangent = Vector3::new(1.0, 1.0, 0.0);
+        let normal = Vector3::new(1.0, -1.0, 0.0);
+        for &(angle, scale) in &[(0.0, 2.0), (1.0, 1.0), (1.0, 3.0)] {
+            let joints = joints(angle, scale);
+            for &blend in &[0.0, 0.25, 0.5, 1.0] {
+                // Weights off by a bit, as after quantization.
+                let weights = Vector4::new(1.0 - blend, blend, 0.0, 0.0) * 0.98;
+                let (tangent, normal) = skin(&joints, weights, tangent, normal);
+                assert!(
+                    tangent.normalize().dot(&normal).abs() < 1e-5,
+                    "normal {:?} isn't perpendicular to {:?}",
+                    normal,
+                    tangent
+                );
+            }
+        }
+    }
+}
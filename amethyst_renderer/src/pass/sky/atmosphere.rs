@@ -0,0 +1,310 @@
+//! Physically based daylight sky pass
+
+use std::f32::consts::{FRAC_PI_2, PI};
+
+use amethyst_core::{
+    nalgebra as na,
+    specs::prelude::{Component, Join, ReadStorage, VecStorage},
+};
+use amethyst_error::Error;
+
+use glsl_layout::Uniform;
+
+use crate::{
+    pass::util::fullscreen_slice,
+    pipe::{
+        pass::{Pass, PassData},
+        DepthMode, Effect, NewEffect,
+    },
+    types::{Encoder, Factory},
+};
+
+use super::{sky_view_args, sky_views, SkyCameras, VertexArgs, VERT_SRC};
+
+const FRAG_SRC: &[u8] = include_bytes!("../shaders/fragment/atmosphere.glsl");
+
+/// Component describing a daylight sky drawn by `DrawAtmosphere`
+///
+/// The sky follows the Preetham model from the sun direction and the haziness of the air, so
+/// moving the sun gives a time of day cycle without any textures. `+Y` is up. Colors are linear
+/// and can get much brighter than `1.0` with a high `intensity`, which suits a pipeline with
+/// `DrawTonemap`.
+#[derive(Clone, Debug)]
+pub struct Atmosphere {
+    /// Direction towards the sun, in world space
+    pub sun_direction: na::Vector3<f32>,
+    /// Haziness of the air. `2.0` is a very clear sky and `10.0` a hazy one, the model is
+    /// meant for values from about `1.7` to `10.0`.
+    pub turbidity: f32,
+    /// Color of the ground below the horizon, which reflects the light of the sky above it
+    pub ground_albedo: [f32; 3],
+    /// Scale from the model's luminance in kilocandela per square meter to the output colors
+    pub intensity: f32,
+    /// Angular radius of the sun disc in radians. `0.0` hides the disc.
+    pub sun_size: f32,
+    /// Brightness of the sun disc in output colors, not scaled by `intensity`. Values above
+    /// `1.0` let it stand out through `DrawTonemap` and light up `DrawBloom` and `DrawGodrays`.
+    pub sun_intensity: f32,
+    /// Whether `DrawGodrays` casts rays from the sun of this sky.
+    pub godrays: bool,
+}
+
+impl Default for Atmosphere {
+    fn default() -> Self {
+        Atmosphere {
+            sun_direction: na::Vector3::new(0.0, 1.0, 1.0),
+            turbidity: 2.5,
+            ground_albedo: [0.3, 0.3, 0.3],
+            intensity: 0.05,
+            sun_size: 0.01,
+            sun_intensity: 20.0,
+            godrays: true,
+        }
+    }
+}
+
+impl Atmosphere {
+    /// Creates a sky lit by the sun in the given direction, with the other settings at their
+    /// defaults.
+    pub fn new(sun_direction: na::Vector3<f32>) -> Self {
+        Atmosphere {
+            sun_direction,
+            ..Default::default()
+        }
+    }
+
+    /// Moves the sun, taking effect on the next frame.
+    pub fn set_sun_direction(&mut self, sun_direction: na::Vector3<f32>) {
+        self.sun_direction = sun_direction;
+    }
+
+    /// Sets the angular radius and brightness of the sun disc, see `sun_size`.
+    pub fn with_sun_disc(mut self, sun_size: f32, sun_intensity: f32) -> Self {
+        self.sun_size = sun_size;
+        self.sun_intensity = sun_intensity;
+        self
+    }
+
+    /// Returns the normalized direction towards the sun, straight up if `sun_direction` is zero.
+    pub(crate) fn sun(&self) -> na::Vector3<f32> {
+        self.sun_direction
+            .try_normalize(0.0)
+            .unwrap_or_else(na::Vector3::y)
+    }
+}
+
+impl Component for Atmosphere {
+    type Storage = VecStorage<Self>;
+}
+
+/// The Preetham model fitted to a sun position and turbidity, as vectors over luminance and the
+/// `x` and `y` chromaticities.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Preetham {
+    perez: [[f32; 3]; 5],
+    /// Zenith values already divided by the distribution at the zenith.
+    zenith: [f32; 3],
+}
+
+/// Returns the Perez distribution of `coefficients` for a view at `theta` from the zenith and
+/// `gamma` from the sun.
+fn perez_distribution(coefficients: [f32; 5], theta: f32, gamma: f32) -> f32 {
+    let [a, b, c, d, e] = coefficients;
+    (1.0 + a * (b / theta.cos()).exp()) * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+}
+
+/// Fits the Preetham model to the sun in `sun_direction` and `turbidity`.
+fn preetham(sun_direction: &na::Vector3<f32>, turbidity: f32) -> Preetham {
+    let t = turbidity;
+    let sun = sun_direction
+        .try_normalize(0.0)
+        .unwrap_or_else(na::Vector3::y);
+    // The model only holds for the sun above the horizon. Below it the sky keeps the colors of
+    // a setting sun and fades to black instead.
+    let theta_s = sun.y.max(-1.0).min(1.0).acos().min(FRAC_PI_2 - 0.01);
+    let fade = ((sun.y + 0.1) / 0.1).max(0.0).min(1.0);
+
+    let coefficients = [
+        [
+            0.1787 * t - 1.4630,
+            -0.3554 * t + 0.4275,
+            -0.0227 * t + 5.3251,
+            0.1206 * t - 2.5771,
+            -0.0670 * t + 0.3703,
+        ],
+        [
+            -0.0193 * t - 0.2592,
+            -0.0665 * t + 0.0008,
+            -0.0004 * t + 0.2125,
+            -0.0641 * t - 0.8989,
+            -0.0033 * t + 0.0452,
+        ],
+        [
+            -0.0167 * t - 0.2608,
+            -0.0950 * t + 0.0092,
+            -0.0079 * t + 0.2102,
+            -0.0441 * t - 1.6537,
+            -0.0109 * t + 0.0529,
+        ],
+    ];
+
+    let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+    let luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+    let (s1, s2, s3) = (theta_s, theta_s.powi(2), theta_s.powi(3));
+    let x = t * t * (0.00166 * s3 - 0.00375 * s2 + 0.00209 * s1)
+        + t * (-0.02903 * s3 + 0.06377 * s2 - 0.03202 * s1 + 0.00394)
+        + (0.11693 * s3 - 0.21196 * s2 + 0.06052 * s1 + 0.25886);
+    let y = t * t * (0.00275 * s3 - 0.00610 * s2 + 0.00317 * s1)
+        + t * (-0.04214 * s3 + 0.08970 * s2 - 0.04153 * s1 + 0.00516)
+        + (0.15346 * s3 - 0.26756 * s2 + 0.06670 * s1 + 0.26688);
+    let zenith = [luminance.max(0.0) * fade, x, y];
+
+    let mut model = Preetham {
+        perez: [[0.0; 3]; 5],
+        zenith: [0.0; 3],
+    };
+    for (channel, coefficients) in coefficients.iter().enumerate() {
+        for (i, &coefficient) in coefficients.iter().enumerate() {
+            model.perez[i][channel] = coefficient;
+        }
+        model.zenith[channel] = zenith[channel] / perez_distribution(*coefficients, 0.0, theta_s);
+    }
+    model
+}
+
+/// Draws a daylight sky from the sun direction and turbidity of an `Atmosphere`
+///
+/// Use it instead of `DrawSkyBox` for a sky driven by the sun rather than by cubemaps. Like the
+/// other sky passes it draws on the far plane with a `LessEqual` depth test, so it covers only the
+/// background wherever it is added to the stage. It doesn't write depth, leaving the sky at the
+/// maximum depth that `DrawFog` and `DrawSsao` expect, like `DrawSkyBox` does by default. Colors
+/// are encoded like `DrawSkyBox` encodes them, see `with_srgb_output`.
+#[derive(Clone, Debug, Default)]
+pub struct DrawAtmosphere {
+    srgb_output: Option<bool>,
+}
+
+impl DrawAtmosphere {
+    /// Create instance of `DrawAtmosphere` pass
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Whether to encode the sky to sRGB before writing it, instead of deciding by the target.
+    pub fn with_srgb_output(mut self, srgb: bool) -> Self {
+        self.srgb_output = Some(srgb);
+        self
+    }
+}
+
+impl<'a> PassData<'a> for DrawAtmosphere {
+    type Data = (SkyCameras<'a>, ReadStorage<'a, Atmosphere>);
+}
+
+impl Pass for DrawAtmosphere {
+    fn compile(&mut self, effect: NewEffect<'_>) -> Result<Effect, Error> {
+        use std::mem;
+        let mut builder = effect.simple(VERT_SRC, FRAG_SRC);
+        builder
+            .with_raw_constant_buffer(
+                "VertexArgs",
+                mem::size_of::<<VertexArgs as Uniform>::Std140>(),
+                1,
+            )
+            .with_raw_global("perez_a")
+            .with_raw_global("perez_b")
+            .with_raw_global("perez_c")
+            .with_raw_global("perez_d")
+            .with_raw_global("perez_e")
+            .with_raw_global("zenith")
+            .with_raw_global("sun_direction")
+            .with_raw_global("ground_albedo")
+            .with_raw_global("intensity")
+            .with_raw_global("sun_size")
+            .with_raw_global("sun_intensity")
+            .with_raw_global("encode_srgb")
+            .with_output("color", Some(DepthMode::LessEqualTest));
+        if let Some(srgb) = self.srgb_output {
+            builder.with_srgb_output(srgb);
+        }
+        builder.build()
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        _factory: Factory,
+        (cameras, atmosphere): <Self as PassData<'a>>::Data,
+    ) {
+        let encode_srgb = if effect.srgb_output() { 1.0 } else { 0.0 };
+        effect.update_global("encode_srgb", encode_srgb);
+
+        for view in sky_views(cameras, effect.target_name()) {
+            if view.0.is_none() {
+                continue;
+            }
+            let vertex_args = sky_view_args(effect, &view, &na::UnitQuaternion::identity());
+            effect.update_constant_buffer("VertexArgs", &vertex_args.std140(), encoder);
+
+            for sky in (&atmosphere).join() {
+                let model = preetham(&sky.sun_direction, sky.turbidity);
+                let sun: [f32; 3] = sky.sun().into();
+                for (name, perez) in ["perez_a", "perez_b", "perez_c", "perez_d", "perez_e"]
+                    .iter()
+                    .zip(&model.perez)
+                {
+                    effect.update_global(name, *perez);
+                }
+                effect.update_global("zenith", model.zenith);
+                effect.update_global("sun_direction", sun);
+                effect.update_global("ground_albedo", sky.ground_albedo);
+                effect.update_global("intensity", sky.intensity);
+                effect.update_global("sun_size", sky.sun_size);
+                effect.update_global("sun_intensity", sky.sun_intensity);
+                effect.draw(&fullscreen_slice(), encoder);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use amethyst_core::nalgebra as na;
+
+    use super::{perez_distribution, preetham};
+
+    /// Returns the luminance and chromaticities the model gives straight up.
+    fn zenith(sun: na::Vector3<f32>, turbidity: f32) -> Vec<f32> {
+        let model = preetham(&sun, turbidity);
+        let theta_s = sun.normalize().y.acos();
+        (0..3)
+            .map(|channel| {
+                let mut coefficients = [0.0; 5];
+                for (i, coefficient) in coefficients.iter_mut().enumerate() {
+                    *coefficient = model.perez[i][channel];
+                }
+                model.zenith[channel] * perez_distribution(coefficients, 0.0, theta_s)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn zenith_is_a_blue_sky() {
+        let zenith = zenith(na::Vector3::new(0.0, 1.0, 1.0), 3.0);
+        assert!((zenith[0] - 7.32).abs() < 0.01);
+        assert!((zenith[1] - 0.2457).abs() < 1e-3);
+        assert!((zenith[2] - 0.2515).abs() < 1e-3);
+    }
+
+    #[test]
+    fn sky_darkens_after_sunset() {
+        let day = zenith(na::Vector3::new(0.0, 1.0, 0.5), 2.5);
+        let night = zenith(na::Vector3::new(0.0, -1.0, 0.5), 2.5);
+        assert!(day[0] > 0.0);
+        assert_eq!(night[0], 0.0);
+        // Hazier air scatters more light towards the zenith.
+        let hazy = zenith(na::Vector3::new(0.0, 1.0, 0.5), 8.0);
+        assert!(hazy[0] > day[0]);
+    }
+}
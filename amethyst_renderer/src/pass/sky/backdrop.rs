@@ -0,0 +1,231 @@
+//! Scrolling 2D backdrop pass
+
+use amethyst_assets::AssetStorage;
+use amethyst_core::{
+    nalgebra as na,
+    specs::prelude::{Component, Join, Read, ReadExpect, ReadStorage, VecStorage},
+};
+use amethyst_error::Error;
+
+use crate::{
+    mtl::MissingTextures,
+    pass::util::{fullscreen_slice, set_view},
+    pipe::{
+        pass::{Pass, PassData},
+        DepthMode, Effect, NewEffect,
+    },
+    tex::{Texture, TextureHandle},
+    transparent::{ColorMask, ALPHA},
+    types::{Encoder, Factory},
+};
+
+use super::{sky_views, SkyCameras};
+
+const VERT_SRC: &[u8] = include_bytes!("../shaders/vertex/backdrop.glsl");
+const FRAG_SRC: &[u8] = include_bytes!("../shaders/fragment/backdrop.glsl");
+
+/// One textured layer of a `Backdrop`
+///
+/// The texture is laid flat over the screen, one repeat spanning `height` of the target's height
+/// and keeping its aspect ratio across. Load it with `WrapMode::Tile` to repeat it as it scrolls
+/// past the edges.
+#[derive(Clone, Debug)]
+pub struct BackdropLayer {
+    /// Handle to the 2D texture of the layer
+    pub texture: TextureHandle,
+    /// Height of one repeat of the texture, as a fraction of the target's height. `1.0` by
+    /// default.
+    pub height: f32,
+    /// How far the layer scrolls as the camera moves along the world's X and Y axes, in texture
+    /// repeats per unit. Zero by default, which keeps the layer still like a sky at infinity;
+    /// layers further in the front of a backdrop usually scroll faster.
+    pub parallax: [f32; 2],
+    /// Offset of the texture in repeats, e.g. advanced by a system for drifting clouds.
+    pub scroll: [f32; 2],
+    /// Color the layer is multiplied with, its alpha blending the layer over the ones behind it.
+    pub tint: [f32; 4],
+}
+
+impl BackdropLayer {
+    /// Creates an untinted layer that doesn't scroll.
+    pub fn new(texture: TextureHandle) -> Self {
+        BackdropLayer {
+            texture,
+            height: 1.0,
+            parallax: [0.0; 2],
+            scroll: [0.0; 2],
+            tint: [1.0; 4],
+        }
+    }
+
+    /// Sets how far the layer scrolls per unit the camera moves.
+    pub fn with_parallax(mut self, x: f32, y: f32) -> Self {
+        self.parallax = [x, y];
+        self
+    }
+
+    /// Sets the height of one repeat of the texture, as a fraction of the target's height.
+    pub fn with_height(mut self, height: f32) -> Self {
+        self.height = height;
+        self
+    }
+
+    /// Returns the offset of the texture seen from a camera at `camera`, in repeats.
+    fn offset(&self, camera: &na::Vector3<f32>) -> [f32; 2] {
+        [
+            self.scroll[0] + camera.x * self.parallax[0],
+            self.scroll[1] + camera.y * self.parallax[1],
+        ]
+    }
+}
+
+/// Returns the `scale` and `offset` uniforms of `backdrop.glsl` for a layer one repeat of which
+/// spans `height` of the target.
+///
+/// `aspect` is the aspect ratio of the texture divided by that of the target. Without an
+/// `offset`, in repeats, the texture is centered on the screen.
+fn layer_uniforms(height: f32, aspect: f32, offset: [f32; 2]) -> ([f32; 2], [f32; 2]) {
+    let height = height.max(1e-4);
+    let scale = [1.0 / (aspect * height), 1.0 / height];
+    let offset = [
+        offset[0] + 0.5 - scale[0] * 0.5,
+        offset[1] + 0.5 - scale[1] * 0.5,
+    ];
+    (scale, offset)
+}
+
+/// Component holding the layers of a backdrop drawn by `DrawBackdrop`, from the back to the front
+#[derive(Clone, Debug, Default)]
+pub struct Backdrop {
+    /// The layers, the first one drawn first, behind all others
+    pub layers: Vec<BackdropLayer>,
+}
+
+impl Backdrop {
+    /// Creates a backdrop of the given layers, the first one at the back.
+    pub fn new(layers: Vec<BackdropLayer>) -> Self {
+        Backdrop { layers }
+    }
+}
+
+impl Component for Backdrop {
+    type Storage = VecStorage<Self>;
+}
+
+/// Draws the flat, scrolling layers of each `Backdrop` behind everything, e.g. parallax layers of
+/// a side-scroller
+///
+/// A lighter alternative to the cubemap of `DrawSkyBox`: the layers are laid over the screen,
+/// centered on the camera position scaled by each layer's parallax, and ignore the camera's
+/// rotation. Like the sky passes they are drawn on the far plane with a `LessEqual` depth test and
+/// without writing depth, so they only cover the background wherever the pass is added to the
+/// stage, and depth-reading passes like `DrawFog` take them to be sky. Each layer is alpha blended
+/// over the ones behind it, so clear the target to the color behind the last opaque layer.
+///
+/// Colors are encoded like `DrawSkyBox` encodes the sky, see `with_srgb_output`.
+#[derive(Clone, Debug, Default)]
+pub struct DrawBackdrop {
+    srgb_output: Option<bool>,
+}
+
+impl DrawBackdrop {
+    /// Create instance of `DrawBackdrop` pass
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Whether to encode the layers to sRGB before writing them, instead of deciding by the
+    /// target.
+    pub fn with_srgb_output(mut self, srgb: bool) -> Self {
+        self.srgb_output = Some(srgb);
+        self
+    }
+}
+
+impl<'a> PassData<'a> for DrawBackdrop {
+    type Data = (
+        SkyCameras<'a>,
+        Read<'a, AssetStorage<Texture>>,
+        ReadExpect<'a, MissingTextures>,
+        ReadStorage<'a, Backdrop>,
+    );
+}
+
+impl Pass for DrawBackdrop {
+    fn compile(&mut self, effect: NewEffect<'_>) -> Result<Effect, Error> {
+        let mut builder = effect.simple(VERT_SRC, FRAG_SRC);
+        builder
+            .with_raw_global("scale")
+            .with_raw_global("offset")
+            .with_raw_global("tint")
+            .with_raw_global("encode_srgb")
+            .with_texture("layer")
+            .with_blended_output(
+                "color",
+                ColorMask::all(),
+                ALPHA,
+                Some(DepthMode::LessEqualTest),
+            );
+        if let Some(srgb) = self.srgb_output {
+            builder.with_srgb_output(srgb);
+        }
+        builder.build()
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        _factory: Factory,
+        (cameras, tex_storage, missing, backdrop): <Self as PassData<'a>>::Data,
+    ) {
+        let target_aspect = match effect.target_size() {
+            Some((width, height)) if height > 0 => f32::from(width) / f32::from(height),
+            _ => return,
+        };
+        let encode_srgb = if effect.srgb_output() { 1.0 } else { 0.0 };
+        effect.update_global("encode_srgb", encode_srgb);
+
+        for view in sky_views(cameras, effect.target_name()) {
+            let position = match set_view(effect, &view).get() {
+                Some((_, transform)) => transform.0.column(3).xyz(),
+                None => continue,
+            };
+
+            for layer in (&backdrop).join().flat_map(|backdrop| &backdrop.layers) {
+                let texture = match tex_storage
+                    .get(&layer.texture)
+                    .or_else(|| tex_storage.get(&missing.color))
+                {
+                    Some(texture) => texture,
+                    None => continue,
+                };
+                let (width, height) = texture.size();
+                let aspect = width.max(1) as f32 / height.max(1) as f32 / target_aspect;
+                let (scale, offset) = layer_uniforms(layer.height, aspect, layer.offset(&position));
+
+                effect.update_global("scale", scale);
+                effect.update_global("offset", offset);
+                effect.update_global("tint", layer.tint);
+                effect.add_texture(texture);
+                effect.draw(&fullscreen_slice(), encoder);
+                effect.clear_textures();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::layer_uniforms;
+
+    #[test]
+    fn layers_are_centered_on_their_offset() {
+        // A square texture at half the height of a target twice as wide as high.
+        let (scale, offset) = layer_uniforms(0.5, 0.5, [0.25, 0.0]);
+        assert_eq!(scale, [4.0, 2.0]);
+        // The center of the screen shows the center of the texture, moved by the offset.
+        assert_eq!(0.5 * scale[0] + offset[0], 0.75);
+        assert_eq!(0.5 * scale[1] + offset[1], 0.5);
+    }
+}
@@ -0,0 +1,79 @@
+//! Bundle wiring up cubemapped skyboxes
+
+use amethyst_core::{
+    bundle::SystemBundle,
+    specs::prelude::{DispatcherBuilder, Join, Read, System, WriteStorage},
+    Time,
+};
+use amethyst_error::Error;
+use hetseq::Queue;
+
+use crate::pipe::StageBuilder;
+
+use super::{DrawSkyBox, SkyBox};
+
+/// Registers the `SkyBox` storage, so skybox entities can be created before anything else
+/// reads them, and advances the `drift` of skyboxes with an `angular_velocity`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SkyBoxSystem;
+
+impl<'a> System<'a> for SkyBoxSystem {
+    type SystemData = (Read<'a, Time>, WriteStorage<'a, SkyBox>);
+
+    fn run(&mut self, (time, mut skybox): Self::SystemData) {
+        for sky in (&mut skybox).join() {
+            sky.advance(time.delta_seconds());
+        }
+    }
+}
+
+/// Skybox bundle
+///
+/// Registers the `SkyBox` component and holds the `DrawSkyBox` pass to add to the render
+/// pipeline with `SkyBoxBundle::with_pass`:
+///
+/// ```rust,ignore
+/// let sky = SkyBoxBundle::new();
+/// let pipe = Pipeline::build().with_stage(
+///     sky.with_pass(
+///         Stage::with_backbuffer()
+///             .clear_target([0.0, 0.0, 0.0, 1.0], 1.0)
+///             .with_pass(DrawShaded::<PosNormTex>::new()),
+///     ),
+/// );
+/// game_data.with_bundle(sky)?.with_bundle(RenderBundle::new(pipe, Some(config)))?;
+/// ```
+///
+/// The pass draws the sky around the active camera, so the scene needs an `ActiveCamera`
+/// resource or an entity with a `Camera` component. Without one nothing is drawn.
+#[derive(Clone, Debug, Default)]
+pub struct SkyBoxBundle {
+    pass: DrawSkyBox,
+}
+
+impl SkyBoxBundle {
+    /// Create a new skybox bundle drawing with a default `DrawSkyBox`
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Use the given, already configured pass instead of the default one
+    pub fn with_draw(pass: DrawSkyBox) -> Self {
+        SkyBoxBundle { pass }
+    }
+
+    /// Appends the `DrawSkyBox` pass of this bundle to a stage of the render pipeline.
+    pub fn with_pass<Q>(
+        &self,
+        stage: StageBuilder<Queue<Q>>,
+    ) -> StageBuilder<Queue<(Queue<Q>, DrawSkyBox)>> {
+        stage.with_pass(self.pass.clone())
+    }
+}
+
+impl<'a, 'b> SystemBundle<'a, 'b> for SkyBoxBundle {
+    fn build(self, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
+        builder.add(SkyBoxSystem, "sky_box_system", &[]);
+        Ok(())
+    }
+}
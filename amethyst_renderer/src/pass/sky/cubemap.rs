@@ -0,0 +1,953 @@
+//! Loaders producing cubemapped textures for `DrawSkyBox`
+
+use std::{
+    f32::consts::PI,
+    fs::{self, File},
+    sync::Arc,
+};
+
+use amethyst_assets::{AssetStorage, Format, FormatValue, Loader, Progress, Source};
+use amethyst_core::nalgebra as na;
+use amethyst_error::{Error, ResultExt};
+
+use gfx::{
+    format::{ChannelType, SurfaceType},
+    texture::Kind,
+};
+use image::{ImageBuffer, Pixel, Rgb, Rgba, RgbaImage};
+use log::warn;
+
+use crate::{
+    error,
+    formats::{dds, ImageData, TextureData, TextureMetadata},
+    tex::{Texture, TextureHandle},
+};
+
+/// How the faces of a cubemap are stored, shared by all cubemap loaders
+///
+/// With `srgb`, the default, 8-bit faces are stored as sRGB and decoded to linear colors when
+/// sampled, which is right for regular photos and paintings. Set it to `false` for faces that
+/// already hold linear values, e.g. baked lighting, so they are sampled as they are instead of
+/// coming out too dark. Floating point faces are always linear.
+///
+/// The faces are expected in the OpenGL convention `DrawSkyBox` samples them with, see
+/// `CubemapOrientation`. Skies exported for another convention, which show up mirrored or with
+/// rotated faces, are corrected by `orientation` while loading.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CubemapOptions {
+    /// Whether 8-bit faces are stored as sRGB
+    pub srgb: bool,
+    /// Corrections for faces exported in another convention
+    pub orientation: CubemapOrientation,
+}
+
+impl Default for CubemapOptions {
+    fn default() -> Self {
+        CubemapOptions {
+            srgb: true,
+            orientation: CubemapOrientation::default(),
+        }
+    }
+}
+
+impl CubemapOptions {
+    /// Sets whether 8-bit faces are stored as sRGB.
+    pub fn with_srgb(mut self, srgb: bool) -> Self {
+        self.srgb = srgb;
+        self
+    }
+
+    /// Sets the corrections for faces exported in another convention.
+    pub fn with_orientation(mut self, orientation: CubemapOrientation) -> Self {
+        self.orientation = orientation;
+        self
+    }
+}
+
+/// Load a set of 6 textures as cubemapped texture array
+///
+/// Returns an error naming the offending face if any of the images can't be read or decoded.
+/// The faces are stored as described by `options`. Besides the formats of the `image` crate,
+/// faces can be uncompressed RGBA8 DDS files, whose mip levels are dropped; block compressed
+/// DDS faces are refused, see `DdsFormat`.
+pub fn load_cubemap<N>(
+    names: [N; 6],
+    size: u16,
+    options: CubemapOptions,
+    loader: &Loader,
+    storage: &AssetStorage<Texture>,
+) -> Result<TextureHandle, Error>
+where
+    N: Into<String> + Copy,
+{
+    load_cubemap_levels(names, size, 1, options, loader, storage)
+}
+
+/// Load a set of 6 textures as cubemapped texture array with a full chain of mip levels
+///
+/// The mip levels are generated on the CPU while loading by repeatedly halving every face,
+/// which reduces shimmering on distant skies and reflections.
+pub fn load_cubemap_with_mips<N>(
+    names: [N; 6],
+    size: u16,
+    options: CubemapOptions,
+    loader: &Loader,
+    storage: &AssetStorage<Texture>,
+) -> Result<TextureHandle, Error>
+where
+    N: Into<String> + Copy,
+{
+    load_cubemap_levels(names, size, full_mip_levels(size), options, loader, storage)
+}
+
+/// Create a cubemapped texture array from 6 already decoded images
+///
+/// Takes the faces in `+X, -X, +Y, -Y, +Z, -Z` order, for example generated at runtime, and
+/// checks that they are all `size`×`size`.
+pub fn load_cubemap_from_images(
+    faces: [ImageData; 6],
+    size: u16,
+    options: CubemapOptions,
+    loader: &Loader,
+    storage: &AssetStorage<Texture>,
+) -> Result<TextureHandle, Error> {
+    let texture_data = cubemap_texture_data(faces, size, 1, options)?;
+    Ok(loader.load_from_data(texture_data, (), storage))
+}
+
+/// Load a set of 6 encoded images from memory as cubemapped texture array
+///
+/// Like `load_cubemap`, but the faces are the contents of image files, for example downloaded
+/// ones, instead of paths.
+pub fn load_cubemap_from_memory<B>(
+    faces: [B; 6],
+    size: u16,
+    options: CubemapOptions,
+    loader: &Loader,
+    storage: &AssetStorage<Texture>,
+) -> Result<TextureHandle, Error>
+where
+    B: AsRef<[u8]>,
+{
+    let decode = |face: usize| decode_texture(face, "<memory>", faces[face].as_ref());
+    let data = [
+        decode(0)?,
+        decode(1)?,
+        decode(2)?,
+        decode(3)?,
+        decode(4)?,
+        decode(5)?,
+    ];
+    load_cubemap_from_images(data, size, options, loader, storage)
+}
+
+/// Number of mip levels needed to halve a `size`×`size` face down to a single texel.
+fn full_mip_levels(size: u16) -> u8 {
+    (16 - size.leading_zeros()) as u8
+}
+
+fn load_cubemap_levels<N>(
+    names: [N; 6],
+    size: u16,
+    mip_levels: u8,
+    options: CubemapOptions,
+    loader: &Loader,
+    storage: &AssetStorage<Texture>,
+) -> Result<TextureHandle, Error>
+where
+    N: Into<String> + Copy,
+{
+    let data: [ImageData; 6] = [
+        load_texture(0, names[0])?,
+        load_texture(1, names[1])?,
+        load_texture(2, names[2])?,
+        load_texture(3, names[3])?,
+        load_texture(4, names[4])?,
+        load_texture(5, names[5])?,
+    ];
+    let texture_data = cubemap_texture_data(data, size, mip_levels, options)?;
+    Ok(loader.load_from_data(texture_data, (), storage))
+}
+
+fn cubemap_texture_data(
+    data: [ImageData; 6],
+    size: u16,
+    mip_levels: u8,
+    options: CubemapOptions,
+) -> Result<TextureData, Error> {
+    let data = options.orientation.apply(data);
+    let meta = if options.srgb {
+        TextureMetadata::srgb()
+    } else {
+        TextureMetadata::unorm()
+    };
+    let meta = meta.with_kind(Kind::Cube(size)).with_mip_levels(mip_levels);
+
+    let texture_data = TextureData::CubeImage(data, meta);
+    texture_data.validate()?;
+    Ok(texture_data)
+}
+
+/// Load a set of 6 textures as cubemapped texture array on the loader's worker threads
+///
+/// Unlike `load_cubemap` this returns right away. The faces are read from the loader's asset
+/// source rather than the file system and decoded in the background, and the texture is
+/// uploaded once the texture storage has processed it. Until then `DrawSkyBox` draws the
+/// default material texture. Errors are logged by the storage instead of being returned.
+pub fn load_cubemap_async<N, P>(
+    names: [N; 6],
+    size: u16,
+    options: CubemapOptions,
+    progress: P,
+    loader: &Loader,
+    storage: &AssetStorage<Texture>,
+) -> TextureHandle
+where
+    N: Into<String> + Copy,
+    P: Progress,
+{
+    let faces = [
+        names[0].into(),
+        names[1].into(),
+        names[2].into(),
+        names[3].into(),
+        names[4].into(),
+        names[5].into(),
+    ];
+    let name = faces[0].clone();
+    let faces = CubemapFaces {
+        faces,
+        size,
+        mip_levels: 1,
+        options,
+    };
+    loader.load(name, CubemapFormat, faces, progress, storage)
+}
+
+/// Format reading the six faces of a cubemap from an asset source
+///
+/// `Loader::load` runs it on the loader's worker threads, see `load_cubemap_async`. The asset
+/// name only shows up in log messages, the faces are named by the `CubemapFaces`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CubemapFormat;
+
+/// Faces to load with `CubemapFormat`, and how to store them
+#[derive(Clone, Debug)]
+pub struct CubemapFaces {
+    /// Names of the faces in `+X, -X, +Y, -Y, +Z, -Z` order
+    pub faces: [String; 6],
+    /// Width and height of every face
+    pub size: u16,
+    /// Number of mip levels, which are generated on the worker like with
+    /// `load_cubemap_with_mips`
+    pub mip_levels: u8,
+    /// How the faces are stored, like with the other loaders
+    pub options: CubemapOptions,
+}
+
+impl Format<Texture> for CubemapFormat {
+    const NAME: &'static str = "Cubemap";
+
+    type Options = CubemapFaces;
+
+    fn import(
+        &self,
+        _name: String,
+        source: Arc<dyn Source>,
+        faces: CubemapFaces,
+        _create_reload: bool,
+    ) -> Result<FormatValue<Texture>, Error> {
+        let load = |face: usize| -> Result<ImageData, Error> {
+            let path = &faces.faces[face];
+            let data = source
+                .load(path)
+                .with_context(|_| error::Error::CubemapFaceIo(face, path.clone()))?;
+            decode_texture(face, path, &data)
+        };
+        let data = [load(0)?, load(1)?, load(2)?, load(3)?, load(4)?, load(5)?];
+        cubemap_texture_data(data, faces.size, faces.mip_levels, faces.options)
+            .map(FormatValue::data)
+    }
+}
+
+/// Load a set of 6 Radiance HDR (`.hdr`) images as a floating point cubemapped texture array
+///
+/// Unlike `load_cubemap` this keeps the full range of the images, storing them as linear
+/// 32-bit float RGBA. That matters for image based lighting and bloom, where clamping bright
+/// texels to 8 bits loses the highlights. Only the `orientation` of `options` applies, the
+/// faces are linear whatever `srgb` says.
+pub fn load_cubemap_hdr<N>(
+    names: [N; 6],
+    size: u16,
+    options: CubemapOptions,
+    loader: &Loader,
+    storage: &AssetStorage<Texture>,
+) -> Result<TextureHandle, Error>
+where
+    N: Into<String> + Copy,
+{
+    let load = |face: usize| -> Result<HdrImage, Error> {
+        let (dimensions, pixels) = load_hdr_texture(face, names[face])?;
+        check_face_size(face, size, dimensions)?;
+        let pixels = pixels.iter().flat_map(|pixel| pixel.data.iter().cloned());
+        let image = HdrImage::from_raw(dimensions.0, dimensions.1, pixels.collect());
+        Ok(image.expect("HDR face holds one pixel per texel"))
+    };
+    let faces = [load(0)?, load(1)?, load(2)?, load(3)?, load(4)?, load(5)?];
+    let mut data = Vec::with_capacity(6 * 4 * usize::from(size) * usize::from(size));
+    for face in options.orientation.arrange(faces).iter() {
+        for pixel in face.pixels() {
+            data.extend_from_slice(&[pixel.data[0], pixel.data[1], pixel.data[2], 1.0]);
+        }
+    }
+    let meta = TextureMetadata::unorm()
+        .with_format(SurfaceType::R32_G32_B32_A32)
+        .with_channel(ChannelType::Float)
+        .with_kind(Kind::Cube(size));
+
+    let texture_data = TextureData::CubeF32(data, meta);
+    texture_data.validate()?;
+    Ok(loader.load_from_data(texture_data, (), storage))
+}
+
+/// Face of a Radiance HDR cubemap as it is decoded.
+type HdrImage = ImageBuffer<Rgb<f32>, Vec<f32>>;
+
+/// How a loaded cubemap face is turned or mirrored to fix its orientation
+///
+/// Rotations are clockwise, as the face image is viewed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaceTransform {
+    /// Keep the face as it is
+    Identity,
+    /// Turn the face a quarter clockwise
+    Rotate90,
+    /// Turn the face upside down
+    Rotate180,
+    /// Turn the face a quarter counter-clockwise
+    Rotate270,
+    /// Mirror the face left to right
+    FlipHorizontal,
+    /// Mirror the face top to bottom
+    FlipVertical,
+}
+
+impl Default for FaceTransform {
+    fn default() -> Self {
+        FaceTransform::Identity
+    }
+}
+
+impl FaceTransform {
+    fn apply<P>(self, image: ImageBuffer<P, Vec<P::Subpixel>>) -> ImageBuffer<P, Vec<P::Subpixel>>
+    where
+        P: Pixel + 'static,
+    {
+        use image::imageops;
+
+        match self {
+            FaceTransform::Identity => image,
+            FaceTransform::Rotate90 => imageops::rotate90(&image),
+            FaceTransform::Rotate180 => imageops::rotate180(&image),
+            FaceTransform::Rotate270 => imageops::rotate270(&image),
+            FaceTransform::FlipHorizontal => imageops::flip_horizontal(&image),
+            FaceTransform::FlipVertical => imageops::flip_vertical(&image),
+        }
+    }
+}
+
+/// Which loaded image ends up as each face of a cubemap, and how it is turned or mirrored
+///
+/// `DrawSkyBox` follows the OpenGL convention: a right-handed world with `+Y` up, where the
+/// default camera looks at the `-Z` face. Faces are given in `+X, -X, +Y, -Y, +Z, -Z` order and
+/// each image is seen as from inside the cube, with its top towards `+Y` for the side faces, its
+/// top towards `-Z` for `+Y` and towards `+Z` for `-Y`. That is how most tools export cubemaps
+/// for OpenGL, and the default keeps the faces as they are.
+///
+/// Skies made for left-handed engines like Unity or Direct3D come out mirrored, which
+/// `left_handed` corrects. Single faces that are turned are fixed with `with_face`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CubemapOrientation {
+    /// For each face in `+X, -X, +Y, -Y, +Z, -Z` order, the loaded image it is taken from and
+    /// how that image is transformed.
+    pub faces: [(CubemapFace, FaceTransform); 6],
+}
+
+impl Default for CubemapOrientation {
+    fn default() -> Self {
+        let face = |face| (face, FaceTransform::Identity);
+        let [px, nx, py, ny, pz, nz] = CubemapFace::ORDER;
+        CubemapOrientation {
+            faces: [face(px), face(nx), face(py), face(ny), face(pz), face(nz)],
+        }
+    }
+}
+
+impl CubemapOrientation {
+    /// Converts faces exported for a left-handed world, mirroring the sky along the Z axis.
+    ///
+    /// The `+Z` and `-Z` images swap places and every face is mirrored, the side faces left to
+    /// right and the top and bottom ones top to bottom.
+    pub fn left_handed() -> Self {
+        use self::{CubemapFace::*, FaceTransform::*};
+
+        CubemapOrientation {
+            faces: [
+                (PositiveX, FlipHorizontal),
+                (NegativeX, FlipHorizontal),
+                (PositiveY, FlipVertical),
+                (NegativeY, FlipVertical),
+                (NegativeZ, FlipHorizontal),
+                (PositiveZ, FlipHorizontal),
+            ],
+        }
+    }
+
+    /// Sets how the image ending up as `face` is transformed, replacing the previous transform.
+    pub fn with_face(mut self, face: CubemapFace, transform: FaceTransform) -> Self {
+        self.faces[face as usize].1 = transform;
+        self
+    }
+
+    /// Rearranges and transforms `faces`, all of the same square size, as described.
+    fn apply(&self, faces: [ImageData; 6]) -> [ImageData; 6] {
+        if *self == CubemapOrientation::default() {
+            return faces;
+        }
+        let [a, b, c, d, e, f] = faces;
+        let [a, b, c, d, e, f] = self.arrange([a.rgba, b.rgba, c.rgba, d.rgba, e.rgba, f.rgba]);
+        let face = |rgba| ImageData { rgba };
+        [face(a), face(b), face(c), face(d), face(e), face(f)]
+    }
+
+    /// Rearranges and transforms the images of `faces` of any pixel type, see `apply`.
+    fn arrange<P>(
+        &self,
+        faces: [ImageBuffer<P, Vec<P::Subpixel>>; 6],
+    ) -> [ImageBuffer<P, Vec<P::Subpixel>>; 6]
+    where
+        P: Pixel + 'static,
+    {
+        let face = |index: usize| {
+            let (source, transform) = self.faces[index];
+            transform.apply(faces[source as usize].clone())
+        };
+        [face(0), face(1), face(2), face(3), face(4), face(5)]
+    }
+}
+
+/// Checks that every face is a `size`×`size` square.
+fn check_face_size(face: usize, size: u16, dimensions: (u32, u32)) -> Result<(), Error> {
+    if dimensions != (u32::from(size), u32::from(size)) {
+        return Err(Error::from(error::Error::CubemapFaceSize(face, size, dimensions)));
+    }
+    Ok(())
+}
+
+fn load_hdr_texture<P: Into<String>>(
+    face: usize,
+    path: P,
+) -> Result<((u32, u32), Vec<Rgb<f32>>), Error> {
+    use image::hdr::HDRDecoder;
+    use std::io::BufReader;
+
+    let path = path.into();
+    let file =
+        File::open(&path).with_context(|_| error::Error::CubemapFaceIo(face, path.clone()))?;
+    let decoder = HDRDecoder::new(BufReader::new(file))
+        .with_context(|_| error::Error::CubemapFaceDecode(face, path.clone()))?;
+    let metadata = decoder.metadata();
+    let pixels = decoder
+        .read_image_hdr()
+        .with_context(|_| error::Error::CubemapFaceDecode(face, path))?;
+    Ok(((metadata.width, metadata.height), pixels))
+}
+
+fn load_texture<P: Into<String>>(face: usize, path: P) -> Result<ImageData, Error> {
+    let path = path.into();
+    let data = fs::read(&path).with_context(|_| error::Error::CubemapFaceIo(face, path.clone()))?;
+    decode_texture(face, &path, &data)
+}
+
+fn decode_texture(face: usize, path: &str, data: &[u8]) -> Result<ImageData, Error> {
+    use image::{load_from_memory, DynamicImage};
+
+    if dds::is_dds(data) {
+        let rgba = dds::decode_image(data)
+            .with_context(|_| error::Error::CubemapFaceDecode(face, path.to_string()))?;
+        return Ok(ImageData { rgba });
+    }
+    let image = load_from_memory(data)
+        .with_context(|_| error::Error::CubemapFaceDecode(face, path.to_string()))?;
+    let rgba = match image {
+        DynamicImage::ImageRgba8(im) => im,
+        _ => {
+            warn!(
+                "Cubemap face {} ({:?}) is stored as {:?} and has to be converted to RGBA8 on \
+                 every load",
+                face,
+                path,
+                image.color()
+            );
+            image.to_rgba()
+        }
+    };
+    Ok(ImageData { rgba })
+}
+
+/// One face of a cubemap, named by the axis it faces
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CubemapFace {
+    /// The face in the direction of positive X
+    PositiveX,
+    /// The face in the direction of negative X
+    NegativeX,
+    /// The face in the direction of positive Y, the top
+    PositiveY,
+    /// The face in the direction of negative Y, the bottom
+    NegativeY,
+    /// The face in the direction of positive Z
+    PositiveZ,
+    /// The face in the direction of negative Z
+    NegativeZ,
+}
+
+impl CubemapFace {
+    /// The faces in `+X, -X, +Y, -Y, +Z, -Z` order, the order of the other cubemap loaders
+    pub const ORDER: [CubemapFace; 6] = [
+        CubemapFace::PositiveX,
+        CubemapFace::NegativeX,
+        CubemapFace::PositiveY,
+        CubemapFace::NegativeY,
+        CubemapFace::PositiveZ,
+        CubemapFace::NegativeZ,
+    ];
+}
+
+/// Arrangement of the six faces of a cubemap in a single image, see `load_cubemap_layout`
+///
+/// Faces are expected as they are stored in a cubemap, like the separate images taken by
+/// `load_cubemap`. The crosses are the usual unfolded cube, with `+Z` in the middle and the
+/// edges of neighbouring faces lining up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CubemapLayout {
+    /// Six faces side by side, from left to right in the given order
+    HorizontalStrip([CubemapFace; 6]),
+    /// Six faces stacked on top of each other, from top to bottom in the given order
+    VerticalStrip([CubemapFace; 6]),
+    /// Four faces wide and three high: `+Y` at the top, `-X, +Z, +X, -Z` across the middle and
+    /// `-Y` at the bottom, all in the second column except for the middle row.
+    HorizontalCross,
+    /// Three faces wide and four high: `+Y` at the top, `-X, +Z, +X` across the second row,
+    /// then `-Y` and finally `-Z`, upside down, all in the middle column except for the second
+    /// row.
+    VerticalCross,
+}
+
+impl CubemapLayout {
+    /// Returns the number of columns and rows of faces.
+    fn grid(&self) -> (u32, u32) {
+        match *self {
+            CubemapLayout::HorizontalStrip(_) => (6, 1),
+            CubemapLayout::VerticalStrip(_) => (1, 6),
+            CubemapLayout::HorizontalCross => (4, 3),
+            CubemapLayout::VerticalCross => (3, 4),
+        }
+    }
+
+    /// Returns the column and row of each face in `+X, -X, +Y, -Y, +Z, -Z` order, and whether
+    /// it is upside down.
+    fn cells(&self) -> Result<[(u32, u32, bool); 6], Error> {
+        let strip = |order: &[CubemapFace; 6], horizontal: bool| {
+            let mut cells = [None; 6];
+            for (i, face) in order.iter().enumerate() {
+                let i = i as u32;
+                let cell = if horizontal { (i, 0) } else { (0, i) };
+                cells[*face as usize] = Some((cell.0, cell.1, false));
+            }
+            let mut out = [(0, 0, false); 6];
+            for (out, cell) in out.iter_mut().zip(&cells) {
+                *out = cell.ok_or(error::Error::CubemapLayoutOrder)?;
+            }
+            Ok::<_, Error>(out)
+        };
+        match *self {
+            CubemapLayout::HorizontalStrip(ref order) => strip(order, true),
+            CubemapLayout::VerticalStrip(ref order) => strip(order, false),
+            CubemapLayout::HorizontalCross => Ok([
+                (2, 1, false),
+                (0, 1, false),
+                (1, 0, false),
+                (1, 2, false),
+                (1, 1, false),
+                (3, 1, false),
+            ]),
+            CubemapLayout::VerticalCross => Ok([
+                (2, 1, false),
+                (0, 1, false),
+                (1, 0, false),
+                (1, 2, false),
+                (1, 1, false),
+                (1, 3, true),
+            ]),
+        }
+    }
+}
+
+/// Load a cubemap laid out in a single image, like a strip or cross exported by a painting
+/// or baking tool, as a cubemapped texture array
+///
+/// The size of the faces follows from the size of the image, which has to split evenly into
+/// square faces. They are stored as described by `options`.
+pub fn load_cubemap_layout<P>(
+    path: P,
+    layout: CubemapLayout,
+    options: CubemapOptions,
+    loader: &Loader,
+    storage: &AssetStorage<Texture>,
+) -> Result<TextureHandle, Error>
+where
+    P: Into<String>,
+{
+    let path = path.into();
+    let data = fs::read(&path).with_context(|_| error::Error::CubemapLayoutIo(path.clone()))?;
+    let image =
+        image::load_from_memory(&data).with_context(|_| error::Error::CubemapLayoutDecode(path))?;
+    let faces = cubemap_faces_from_layout(&image.to_rgba(), layout)?;
+    let size = faces[0].rgba.width() as u16;
+    load_cubemap_from_images(faces, size, options, loader, storage)
+}
+
+/// Slice a single image laid out as `layout` into the six faces of a cubemap
+///
+/// Returns the faces in `+X, -X, +Y, -Y, +Z, -Z` order, ready for `load_cubemap_from_images`.
+pub fn cubemap_faces_from_layout(
+    image: &RgbaImage,
+    layout: CubemapLayout,
+) -> Result<[ImageData; 6], Error> {
+    let (columns, rows) = layout.grid();
+    let (width, height) = image.dimensions();
+    let size = width / columns;
+    if size == 0
+        || size > u32::from(u16::max_value())
+        || size * columns != width
+        || size * rows != height
+    {
+        return Err(error::Error::CubemapLayoutSize((width, height), (columns, rows)).into());
+    }
+    let cells = layout.cells()?;
+    let face = |face: usize| {
+        let (column, row, flipped) = cells[face];
+        let rgba = RgbaImage::from_fn(size, size, |x, y| {
+            let (x, y) = if flipped {
+                (size - 1 - x, size - 1 - y)
+            } else {
+                (x, y)
+            };
+            *image.get_pixel(column * size + x, row * size + y)
+        });
+        ImageData { rgba }
+    };
+    Ok([face(0), face(1), face(2), face(3), face(4), face(5)])
+}
+
+/// Load an equirectangular panorama as a cubemapped texture array with `size`×`size` faces
+///
+/// The panorama is resampled into the six faces on the CPU while loading, with its center
+/// looking down the negative Z axis and its top row straight up. The faces are stored as
+/// described by `options`.
+pub fn load_equirect<P>(
+    path: P,
+    size: u16,
+    options: CubemapOptions,
+    loader: &Loader,
+    storage: &AssetStorage<Texture>,
+) -> Result<TextureHandle, Error>
+where
+    P: Into<String>,
+{
+    let panorama = load_panorama(path.into())?;
+    let faces = equirect_to_faces(&panorama, size);
+    load_cubemap_from_images(faces, size, options, loader, storage)
+}
+
+fn load_panorama(path: String) -> Result<RgbaImage, Error> {
+    let data = fs::read(&path).with_context(|_| error::Error::PanoramaIo(path.clone()))?;
+
+    let image =
+        image::load_from_memory(&data).with_context(|_| error::Error::PanoramaDecode(path))?;
+    Ok(image.to_rgba())
+}
+
+/// Resamples an equirectangular panorama into cubemap faces, in `+X, -X, +Y, -Y, +Z, -Z` order.
+fn equirect_to_faces(panorama: &RgbaImage, size: u16) -> [ImageData; 6] {
+    let face = |index| {
+        let size = u32::from(size);
+        let rgba = RgbaImage::from_fn(size, size, |x, y| {
+            let s = 2.0 * (x as f32 + 0.5) / size as f32 - 1.0;
+            let t = 2.0 * (y as f32 + 0.5) / size as f32 - 1.0;
+            sample_equirect(panorama, &face_direction(index, s, t))
+        });
+        ImageData { rgba }
+    };
+    [face(0), face(1), face(2), face(3), face(4), face(5)]
+}
+
+/// Direction through the point `(s, t)` in `[-1, 1]` of the given cubemap face.
+fn face_direction(face: usize, s: f32, t: f32) -> na::Vector3<f32> {
+    match face {
+        0 => na::Vector3::new(1.0, -t, -s),
+        1 => na::Vector3::new(-1.0, -t, s),
+        2 => na::Vector3::new(s, 1.0, t),
+        3 => na::Vector3::new(s, -1.0, -t),
+        4 => na::Vector3::new(s, -t, 1.0),
+        _ => na::Vector3::new(-s, -t, -1.0),
+    }
+}
+
+/// Bilinearly samples the panorama in the given direction, wrapping around horizontally.
+fn sample_equirect(panorama: &RgbaImage, direction: &na::Vector3<f32>) -> Rgba<u8> {
+    let direction = direction.normalize();
+    let u = 0.5 + direction.x.atan2(-direction.z) / (2.0 * PI);
+    let v = direction.y.max(-1.0).min(1.0).acos() / PI;
+
+    let (width, height) = panorama.dimensions();
+    let x = u * width as f32 - 0.5;
+    let y = v * height as f32 - 0.5;
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+
+    let wrap = i64::from(width);
+    let column = |x: f32| (((x as i64 % wrap) + wrap) % wrap) as u32;
+    let row = |y: f32| y.max(0.0).min((height - 1) as f32) as u32;
+    let (c0, c1) = (column(x0), column(x0 + 1.0));
+    let (r0, r1) = (row(y0), row(y0 + 1.0));
+
+    let mut out = [0u8; 4];
+    for (i, channel) in out.iter_mut().enumerate() {
+        let texel = |c, r| f32::from(panorama.get_pixel(c, r).data[i]);
+        let top = texel(c0, r0) * (1.0 - fx) + texel(c1, r0) * fx;
+        let bottom = texel(c0, r1) * (1.0 - fx) + texel(c1, r1) * fx;
+        *channel = (top * (1.0 - fy) + bottom * fy).round() as u8;
+    }
+    Rgba { data: out }
+}
+
+#[cfg(test)]
+mod tests {
+    use amethyst_core::nalgebra as na;
+    use amethyst_error::Error;
+    use gfx::format::ChannelType;
+    use image::{Rgba, RgbaImage};
+
+    use crate::formats::TextureData;
+
+    use super::{
+        cubemap_faces_from_layout, cubemap_texture_data, equirect_to_faces, face_direction,
+        full_mip_levels, CubemapFace, CubemapLayout, CubemapOptions, CubemapOrientation,
+        FaceTransform, ImageData,
+    };
+
+    fn faces(sizes: [(u32, u32); 6]) -> [ImageData; 6] {
+        let face = |(w, h)| ImageData {
+            rgba: RgbaImage::new(w, h),
+        };
+        [
+            face(sizes[0]),
+            face(sizes[1]),
+            face(sizes[2]),
+            face(sizes[3]),
+            face(sizes[4]),
+            face(sizes[5]),
+        ]
+    }
+
+    fn validate_faces(faces: [ImageData; 6], size: u16) -> Result<TextureData, Error> {
+        cubemap_texture_data(faces, size, 1, CubemapOptions::default())
+    }
+
+    #[test]
+    fn matching_faces_are_valid() {
+        assert!(validate_faces(faces([(4, 4); 6]), 4).is_ok());
+    }
+
+    #[test]
+    fn mismatched_face_is_reported() {
+        let mut sizes = [(4, 4); 6];
+        sizes[3] = (4, 8);
+        let err = validate_faces(faces(sizes), 4).expect_err("face 3 is not square");
+        assert_eq!(err.to_string(), "Cubemap face 3 is 4x8, expected 4x4");
+    }
+
+    #[test]
+    fn faces_must_match_requested_size() {
+        assert!(validate_faces(faces([(8, 8); 6]), 4).is_err());
+    }
+
+    #[test]
+    fn equirect_faces_have_requested_size() {
+        let grey = Rgba {
+            data: [10, 20, 30, 255],
+        };
+        let faces = equirect_to_faces(&RgbaImage::from_pixel(16, 8, grey), 4);
+        assert_eq!(faces[5].rgba.get_pixel(1, 2), &grey);
+        assert!(validate_faces(faces, 4).is_ok());
+    }
+
+    #[test]
+    fn equirect_top_row_maps_to_positive_y() {
+        let red = Rgba {
+            data: [255, 0, 0, 255],
+        };
+        let blue = Rgba {
+            data: [0, 0, 255, 255],
+        };
+        let panorama = RgbaImage::from_fn(16, 8, |_, y| if y < 4 { red } else { blue });
+        let faces = equirect_to_faces(&panorama, 4);
+        assert_eq!(faces[2].rgba.get_pixel(2, 2), &red);
+        assert_eq!(faces[3].rgba.get_pixel(2, 2), &blue);
+    }
+
+    #[test]
+    fn linear_faces_are_stored_as_unorm() {
+        let options = CubemapOptions::default();
+        let data = |srgb| cubemap_texture_data(faces([(4, 4); 6]), 4, 1, options.with_srgb(srgb));
+        let channel = |srgb| match data(srgb) {
+            Ok(TextureData::CubeImage(_, meta)) => meta.channel,
+            _ => panic!("expected cubemap image data"),
+        };
+        assert_eq!(channel(true), ChannelType::Srgb);
+        assert_eq!(channel(false), ChannelType::Unorm);
+    }
+
+    #[test]
+    fn full_mip_chain_ends_at_one_texel() {
+        assert_eq!(full_mip_levels(1), 1);
+        assert_eq!(full_mip_levels(4), 3);
+        assert_eq!(full_mip_levels(512), 10);
+    }
+
+    /// An image whose texels hold their own coordinates.
+    fn coordinates(width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| Rgba {
+            data: [x as u8, y as u8, 0, 255],
+        })
+    }
+
+    #[test]
+    fn strips_follow_the_face_order() {
+        let mut order = CubemapFace::ORDER;
+        order.swap(0, 4);
+        let layout = CubemapLayout::HorizontalStrip(order);
+        let faces = cubemap_faces_from_layout(&coordinates(12, 2), layout).unwrap();
+        assert_eq!(faces[0].rgba.get_pixel(1, 0).data, [9, 0, 0, 255]);
+        assert_eq!(faces[4].rgba.get_pixel(0, 1).data, [0, 1, 0, 255]);
+
+        let layout = CubemapLayout::VerticalStrip(CubemapFace::ORDER);
+        let faces = cubemap_faces_from_layout(&coordinates(2, 12), layout).unwrap();
+        assert_eq!(faces[3].rgba.get_pixel(1, 1).data, [1, 7, 0, 255]);
+    }
+
+    #[test]
+    fn strips_need_every_face() {
+        let mut order = CubemapFace::ORDER;
+        order[5] = CubemapFace::PositiveX;
+        let layout = CubemapLayout::HorizontalStrip(order);
+        let err = cubemap_faces_from_layout(&RgbaImage::new(12, 2), layout).unwrap_err();
+        assert!(err.to_string().contains("exactly once"));
+    }
+
+    #[test]
+    fn layouts_have_to_split_into_square_faces() {
+        let layout = CubemapLayout::HorizontalCross;
+        assert!(cubemap_faces_from_layout(&RgbaImage::new(16, 12), layout).is_ok());
+        let err = cubemap_faces_from_layout(&RgbaImage::new(16, 16), layout).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Cubemap layout is 16x16, which doesn't split into 4x3 square faces"
+        );
+    }
+
+    #[test]
+    fn cross_neighbours_look_in_nearby_directions() {
+        let size = 4;
+        for &(layout, columns, rows) in &[
+            (CubemapLayout::HorizontalCross, 4, 3),
+            (CubemapLayout::VerticalCross, 3, 4),
+        ] {
+            let faces =
+                cubemap_faces_from_layout(&coordinates(columns * size, rows * size), layout)
+                    .unwrap();
+            // Direction of the texel that came from each pixel of the layout image.
+            let mut directions = vec![None; (columns * rows * size * size) as usize];
+            for (index, face) in faces.iter().enumerate() {
+                for (x, y, pixel) in face.rgba.enumerate_pixels() {
+                    let texel = |v: u32| 2.0 * (v as f32 + 0.5) / size as f32 - 1.0;
+                    let (ix, iy) = (u32::from(pixel.data[0]), u32::from(pixel.data[1]));
+                    directions[(iy * columns * size + ix) as usize] =
+                        Some(face_direction(index, texel(x), texel(y)).normalize());
+                }
+            }
+            let at = |x: u32, y: u32| directions[(y * columns * size + x) as usize];
+            // Neighbours within a face are about 28° apart and across a shared edge 16°, while
+            // faces put next to the wrong neighbour are much further off.
+            for y in 0..rows * size {
+                for x in 0..columns * size {
+                    let here = match at(x, y) {
+                        Some(here) => here,
+                        None => continue,
+                    };
+                    let right = if x + 1 < columns * size {
+                        at(x + 1, y)
+                    } else {
+                        None
+                    };
+                    let below = if y + 1 < rows * size {
+                        at(x, y + 1)
+                    } else {
+                        None
+                    };
+                    for next in right.into_iter().chain(below) {
+                        assert!(here.dot(&next) > 0.85, "{:?} at {}, {}", layout, x, y);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Faces whose texels hold their face index and coordinates.
+    fn marked_faces(size: u32) -> [ImageData; 6] {
+        let face = |index| ImageData {
+            rgba: RgbaImage::from_fn(size, size, |x, y| Rgba {
+                data: [index, x as u8, y as u8, 255],
+            }),
+        };
+        [face(0), face(1), face(2), face(3), face(4), face(5)]
+    }
+
+    #[test]
+    fn single_faces_are_turned() {
+        let orientation = CubemapOrientation::default()
+            .with_face(CubemapFace::NegativeY, FaceTransform::Rotate90);
+        let faces = orientation.apply(marked_faces(4));
+        // Turning clockwise moves the top left corner to the top right.
+        assert_eq!(faces[3].rgba.get_pixel(3, 0).data, [3, 0, 0, 255]);
+        assert_eq!(faces[2].rgba.get_pixel(3, 0).data, [2, 3, 0, 255]);
+    }
+
+    #[test]
+    fn left_handed_faces_mirror_the_sky_along_z() {
+        let size = 4;
+        let faces = CubemapOrientation::left_handed().apply(marked_faces(size));
+        let texel = |v: u8| 2.0 * (f32::from(v) + 0.5) / size as f32 - 1.0;
+        for (index, face) in faces.iter().enumerate() {
+            for (x, y, pixel) in face.rgba.enumerate_pixels() {
+                let [source, sx, sy, _] = pixel.data;
+                let direction = face_direction(index, texel(x as u8), texel(y as u8));
+                let loaded = face_direction(usize::from(source), texel(sx), texel(sy));
+                let mirrored = na::Vector3::new(direction.x, direction.y, -direction.z);
+                assert!((loaded - mirrored).norm() < 1e-6, "{:?}", (index, x, y));
+            }
+        }
+    }
+}
@@ -0,0 +1,106 @@
+//! Procedural gradient sky pass
+
+use amethyst_core::{
+    nalgebra as na,
+    specs::prelude::{Component, Join, ReadStorage, VecStorage},
+};
+use amethyst_error::Error;
+
+use glsl_layout::Uniform;
+
+use crate::{
+    pass::util::fullscreen_slice,
+    pipe::{
+        pass::{Pass, PassData},
+        DepthMode, Effect, NewEffect,
+    },
+    types::{Encoder, Factory},
+};
+
+use super::{sky_view_args, sky_views, SkyCameras, VertexArgs, VERT_SRC};
+
+const FRAG_SRC: &[u8] = include_bytes!("../shaders/fragment/sky_gradient.glsl");
+
+/// Component describing a horizon-to-zenith gradient sky drawn by `DrawSkyGradient`
+#[derive(Clone, Debug)]
+pub struct SkyGradient {
+    /// The color directly above the viewer
+    pub top: [f32; 4],
+    /// The color at the horizon
+    pub horizon: [f32; 4],
+    /// The color directly below the viewer
+    pub bottom: [f32; 4],
+    /// Controls how sharply the colors blend away from the horizon. `1.0` blends linearly,
+    /// smaller values keep the horizon band narrow and larger values widen it.
+    pub exponent: f32,
+}
+
+impl Default for SkyGradient {
+    fn default() -> Self {
+        SkyGradient {
+            top: [0.25, 0.5, 0.9, 1.0],
+            horizon: [0.75, 0.85, 1.0, 1.0],
+            bottom: [0.1, 0.3, 0.35, 1.0],
+            exponent: 1.0,
+        }
+    }
+}
+
+impl Component for SkyGradient {
+    type Storage = VecStorage<Self>;
+}
+
+/// Draws a sky from the colors of a `SkyGradient`, without needing any textures
+#[derive(Clone, Debug, Default)]
+pub struct DrawSkyGradient;
+
+impl DrawSkyGradient {
+    /// Create instance of `DrawSkyGradient` pass
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl<'a> PassData<'a> for DrawSkyGradient {
+    type Data = (SkyCameras<'a>, ReadStorage<'a, SkyGradient>);
+}
+
+impl Pass for DrawSkyGradient {
+    fn compile(&mut self, effect: NewEffect<'_>) -> Result<Effect, Error> {
+        use std::mem;
+        effect
+            .simple(VERT_SRC, FRAG_SRC)
+            .with_raw_constant_buffer(
+                "VertexArgs",
+                mem::size_of::<<VertexArgs as Uniform>::Std140>(),
+                1,
+            )
+            .with_raw_global("top_color")
+            .with_raw_global("horizon_color")
+            .with_raw_global("bottom_color")
+            .with_raw_global("exponent")
+            .with_output("color", Some(DepthMode::LessEqualTest))
+            .build()
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        _factory: Factory,
+        (cameras, gradient): <Self as PassData<'a>>::Data,
+    ) {
+        for view in sky_views(cameras, effect.target_name()) {
+            let vertex_args = sky_view_args(effect, &view, &na::UnitQuaternion::identity());
+            effect.update_constant_buffer("VertexArgs", &vertex_args.std140(), encoder);
+
+            for sky in (&gradient).join() {
+                effect.update_global("top_color", sky.top);
+                effect.update_global("horizon_color", sky.horizon);
+                effect.update_global("bottom_color", sky.bottom);
+                effect.update_global("exponent", sky.exponent);
+                effect.draw(&fullscreen_slice(), encoder);
+            }
+        }
+    }
+}
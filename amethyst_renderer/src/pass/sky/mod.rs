@@ -1,266 +1,748 @@
+pub use self::{
+    atmosphere::{Atmosphere, DrawAtmosphere},
+    backdrop::{Backdrop, BackdropLayer, DrawBackdrop},
+    bundle::{SkyBoxBundle, SkyBoxSystem},
+    cubemap::{
+        cubemap_faces_from_layout, load_cubemap, load_cubemap_async, load_cubemap_from_images,
+        load_cubemap_from_memory, load_cubemap_hdr, load_cubemap_layout, load_cubemap_with_mips,
+        load_equirect, CubemapFace, CubemapFaces, CubemapFormat, CubemapLayout, CubemapOptions,
+        CubemapOrientation, FaceTransform,
+    },
+    gradient::{DrawSkyGradient, SkyGradient},
+    overlay::{DrawSkyOverlays, SkyOverlay},
+    panorama::{DrawSkyPanorama, SkyPanorama},
+    prefilter::{irradiance_cubemap, prefilter_cubemap, CubemapFilter, CubemapFilters},
+};
+
+pub(crate) use self::prefilter::filter_cubemap;
+
+mod atmosphere;
+mod backdrop;
+mod bundle;
+mod cubemap;
+mod gradient;
+mod overlay;
+mod panorama;
+mod prefilter;
+
 use amethyst_assets::{AssetStorage, Loader};
 use amethyst_core::{
     nalgebra as na,
-    specs::prelude::{Component, Join, Read, ReadExpect, ReadStorage, VecStorage},
+    specs::prelude::{Component, Entities, Join, Read, ReadExpect, ReadStorage, VecStorage},
     transform::GlobalTransform,
 };
 
 use amethyst_error::Error;
 
-use gfx::{
-    pso::buffer::{ElemStride, Element},
-    texture::Kind,
-};
+use derivative::Derivative;
+
 use glsl_layout::*;
 
 use crate::{
-    cam::{ActiveCamera, Camera},
-    formats::{ImageData, TextureData, TextureMetadata},
-    mesh::Mesh,
-    mtl::MaterialDefaults,
-    pass::util::get_camera,
+    cam::{ActiveCamera, Camera, CameraTarget, StereoCamera, Viewport},
+    mtl::{MaterialDefaults, MissingTextures},
+    pass::util::{fullscreen_slice, get_cameras, set_view, CameraView},
     pipe::{
         pass::{Pass, PassData},
-        Effect, NewEffect,
+        DepthMode, Effect, EffectBuilder, NewEffect, Targets,
     },
-    tex::{Texture, TextureHandle},
-    types::{Encoder, Factory},
-    vertex::{Attribute, AttributeFormat, Attributes, Position, VertexFormat, With},
+    tex::{FilterMethod, SamplerInfo, Texture, TextureHandle, WrapMode},
+    transparent::{ColorMask, ALPHA},
+    types::{Encoder, Factory, Sampler},
 };
 
-const VERT_SRC: &[u8] = include_bytes!("../shaders/vertex/sky.glsl");
-const FRAG_SRC: &[u8] = include_bytes!("../shaders/fragment/sky.glsl");
+pub(crate) const VERT_SRC: &[u8] = include_bytes!("../shaders/vertex/sky.glsl");
+pub(crate) const FRAG_SRC: &[u8] = include_bytes!("../shaders/fragment/sky.glsl");
 
 #[repr(C, align(16))]
 #[derive(Clone, Copy, Debug, Uniform)]
-struct VertexArgs {
-    proj: mat4,
-    view: mat4,
+pub(crate) struct VertexArgs {
+    unproject: mat4,
+}
+
+/// Cameras the sky passes draw the sky for.
+pub(crate) type SkyCameras<'a> = (
+    Read<'a, ActiveCamera>,
+    ReadStorage<'a, Camera>,
+    ReadStorage<'a, Viewport>,
+    ReadStorage<'a, CameraTarget>,
+    ReadStorage<'a, StereoCamera>,
+    ReadStorage<'a, GlobalTransform>,
+);
+
+/// Returns the cameras drawing into `target` with their regions of it, see `get_cameras`.
+pub(crate) fn sky_views(
+    (active, camera, viewport, camera_target, stereo, global): SkyCameras<'_>,
+    target: &str,
+) -> Vec<CameraView> {
+    get_cameras(
+        active,
+        &camera,
+        &viewport,
+        &camera_target,
+        &stereo,
+        &global,
+        target,
+    )
+}
+
+/// Restricts drawing to the viewport of `view` and returns the `VertexArgs` showing the sky from
+/// its camera in it.
+pub(crate) fn sky_view_args(
+    effect: &mut Effect,
+    view: &CameraView,
+    rotation: &na::UnitQuaternion<f32>,
+) -> VertexArgs {
+    vertex_args(set_view(effect, view).get(), rotation)
+}
+
+pub(crate) fn vertex_args(
+    camera: Option<(&Camera, &GlobalTransform)>,
+    rotation: &na::UnitQuaternion<f32>,
+) -> VertexArgs {
+    let (proj, view) = sky_matrices(camera, rotation);
+    let unproject: [[f32; 4]; 4] = unprojection(&proj, &view).into();
+    VertexArgs {
+        unproject: unproject.into(),
+    }
+}
+
+/// Returns the projection and rotated view matrices used to draw the sky.
+fn sky_matrices(
+    camera: Option<(&Camera, &GlobalTransform)>,
+    rotation: &na::UnitQuaternion<f32>,
+) -> (na::Matrix4<f32>, na::Matrix4<f32>) {
+    let (proj, view) = camera
+        .map(|(cam, transform)| (sky_projection(cam), transform.0.try_inverse().unwrap()))
+        .unwrap_or_else(|| (na::Matrix4::identity(), na::Matrix4::identity()));
+    (proj, view * rotation.to_homogeneous())
+}
+
+/// Returns the matrix taking the screen's clip space back to directions of the sky.
+///
+/// The sky passes draw a single triangle covering the screen at the far plane, instead of a cube
+/// around the camera, and look up the sky in the direction each pixel shows. That works with any
+/// near and far plane: the triangle sits exactly at the far depth of `1.0` and nothing of it is
+/// ever in front of the near plane, which a cube close to the camera could cross and get clipped
+/// by. A projection that can't be inverted falls back to the identity.
+fn unprojection(proj: &na::Matrix4<f32>, view: &na::Matrix4<f32>) -> na::Matrix4<f32> {
+    let mut rotation = *view;
+    rotation.fixed_slice_mut::<na::U3, na::U1>(0, 3).fill(0.0);
+    (proj * rotation)
+        .try_inverse()
+        .unwrap_or_else(na::Matrix4::identity)
+}
+
+/// Vertical field of view skies are shown with to orthographic cameras.
+const BACKDROP_FOV: f32 = std::f32::consts::FRAC_PI_3;
+
+/// Returns the projection the sky is drawn with for `camera`.
+///
+/// Every pixel of an orthographic camera looks in the same direction, which would cover the
+/// screen in a single color of the sky. Such cameras see the sky through a perspective
+/// projection of their aspect ratio and a vertical field of view of `BACKDROP_FOV` instead, so
+/// the sky becomes a backdrop that turns with the camera but stays put when it moves.
+pub(crate) fn sky_projection(camera: &Camera) -> na::Matrix4<f32> {
+    if !camera.is_orthographic() {
+        return camera.proj;
+    }
+    let aspect = (camera.proj[(1, 1)] / camera.proj[(0, 0)]).abs();
+    let aspect = if aspect.is_normal() { aspect } else { 1.0 };
+    na::Perspective3::new(aspect, BACKDROP_FOV, 0.1, 2000.0).to_homogeneous()
+}
+
+/// Returns the sampler for `info`, creating it on first use.
+fn cached_sampler(
+    samplers: &mut Vec<(SamplerInfo, Sampler)>,
+    factory: &mut Factory,
+    info: SamplerInfo,
+) -> Sampler {
+    use gfx::Factory;
+
+    if let Some(&(_, ref sampler)) = samplers.iter().find(|&&(ref i, _)| *i == info) {
+        return sampler.clone();
+    }
+    let sampler = factory.create_sampler(info);
+    samplers.push((info, sampler.clone()));
+    sampler
 }
 
 /// Component to keep a handle to a cubemapped texture array
+///
+/// `DrawSkyBox` reads this component every frame, so systems can swap the texture at runtime
+/// (for example on a level change) without rebuilding the pass. Until the new texture has finished
+/// loading the pass samples the color texture of `MissingTextures` instead.
+///
+/// `tint` and `exposure` are applied in linear space. Cubemaps loaded as sRGB, which is what
+/// `load_cubemap` does unless told otherwise, have their texels decoded to linear before
+/// tinting, and `tint` should be given as a linear color too.
+///
+/// Several skies are drawn as layers, ordered by `layer`, see `DrawSkyBox`.
+#[derive(Clone, Debug)]
 pub struct SkyBox {
     /// Handle to cubemapped texture array
     pub texture: TextureHandle,
+    /// Orientation of the cubemap relative to the world axes
+    pub rotation: na::UnitQuaternion<f32>,
+    /// Continuous rotation of the cubemap, e.g. for drifting clouds, as the axis scaled by the
+    /// speed in radians per second. Zero by default.
+    ///
+    /// Only takes effect while `SkyBoxSystem` runs, which `SkyBoxBundle` adds.
+    pub angular_velocity: na::Vector3<f32>,
+    /// How far the cubemap has turned by `angular_velocity` so far, applied on top of `rotation`.
+    ///
+    /// `SkyBoxSystem` advances it by the frame's `Time::delta_seconds`, so the same sequence of
+    /// frame times always gives the same drift. Set it back to identity to restart the motion.
+    pub drift: na::UnitQuaternion<f32>,
+    /// Optional second cubemap to crossfade towards, e.g. a night sky
+    pub texture_b: Option<TextureHandle>,
+    /// How far to crossfade from `texture` to `texture_b`, from `0.0` to `1.0`.
+    /// Ignored while `texture_b` is `None`.
+    pub blend: f32,
+    /// Color the sampled sky is multiplied by
+    pub tint: [f32; 4],
+    /// Scale applied to the brightness of the sky
+    pub exposure: f32,
+    /// Added to the mip level the cubemaps are sampled at, `0.0` by default.
+    ///
+    /// Positive values sample the smaller mips of a cubemap loaded or prefiltered with mip levels,
+    /// for a softer, blurrier sky, or to check how its mips look. Cubemaps without mips and
+    /// samplers without mipmap filtering are unaffected.
+    pub lod_bias: f32,
+    /// How `DrawSkyBox` samples the cubemaps, trilinear clamp-to-edge by default.
+    ///
+    /// Wrapping modes other than clamping blend in texels from the opposite edge of a face,
+    /// which shows up as visible seams along the cube edges.
+    pub sampler: SamplerInfo,
+    /// Where the sky is drawn among the other skies, `0` by default. Skies of higher layers are
+    /// blended over those of lower ones by their alpha.
+    pub layer: i32,
 }
 
-impl Component for SkyBox {
-    type Storage = VecStorage<Self>;
-}
+impl SkyBox {
+    /// Creates a new `SkyBox` drawing the given cubemap aligned with the world axes.
+    pub fn new(texture: TextureHandle) -> Self {
+        SkyBox {
+            texture,
+            rotation: na::UnitQuaternion::identity(),
+            angular_velocity: na::Vector3::zeros(),
+            drift: na::UnitQuaternion::identity(),
+            texture_b: None,
+            blend: 0.0,
+            tint: [1.0; 4],
+            exposure: 1.0,
+            lod_bias: 0.0,
+            sampler: SamplerInfo::new(FilterMethod::Trilinear, WrapMode::Clamp),
+            layer: 0,
+        }
+    }
+
+    /// Sets how the cubemaps are sampled, e.g. to use anisotropic filtering with
+    /// `FilterMethod::Anisotropic`.
+    pub fn set_sampler(&mut self, sampler: SamplerInfo) {
+        self.sampler = sampler;
+    }
 
-struct PosOnly {
-    pub position: [f32; 3],
+    /// Loads the six faces as sRGB with `load_cubemap` and creates a `SkyBox` drawing them.
+    pub fn from_faces<N>(
+        names: [N; 6],
+        size: u16,
+        loader: &Loader,
+        storage: &AssetStorage<Texture>,
+    ) -> Result<Self, Error>
+    where
+        N: Into<String> + Copy,
+    {
+        load_cubemap(names, size, CubemapOptions::default(), loader, storage).map(SkyBox::new)
+    }
+
+    /// Sets the cubemap to crossfade towards and how far along the crossfade is.
+    /// Passing `None` draws only `texture` again.
+    pub fn set_blend(&mut self, texture_b: Option<TextureHandle>, blend: f32) {
+        self.texture_b = texture_b;
+        self.blend = blend;
+    }
+
+    /// Sets the color the sky is multiplied by and its brightness scale.
+    pub fn set_tint(&mut self, tint: [f32; 4], exposure: f32) {
+        self.tint = tint;
+        self.exposure = exposure;
+    }
+
+    /// Rotates the cubemap relative to the world axes, taking effect on the next frame.
+    pub fn set_rotation(&mut self, rotation: na::UnitQuaternion<f32>) {
+        self.rotation = rotation;
+    }
+
+    /// Sets the continuous rotation of the cubemap, see `angular_velocity`.
+    pub fn set_angular_velocity(&mut self, angular_velocity: na::Vector3<f32>) {
+        self.angular_velocity = angular_velocity;
+    }
+
+    /// Returns the orientation the cubemap is drawn with, `rotation` followed by `drift`.
+    pub(crate) fn orientation(&self) -> na::UnitQuaternion<f32> {
+        self.rotation * self.drift
+    }
+
+    /// Turns `drift` by `angular_velocity` over `seconds`.
+    pub(crate) fn advance(&mut self, seconds: f32) {
+        if self.angular_velocity != na::Vector3::zeros() {
+            self.drift = advance_drift(&self.drift, &self.angular_velocity, seconds);
+        }
+    }
+
+    /// Replaces the cubemap drawn by `DrawSkyBox`, taking effect on the next frame.
+    pub fn set_texture(&mut self, texture: TextureHandle) {
+        self.texture = texture;
+    }
+
+    /// Sets where the sky is drawn among the other skies, see `layer`.
+    pub fn set_layer(&mut self, layer: i32) {
+        self.layer = layer;
+    }
 }
 
-unsafe impl Pod for PosOnly {}
+/// Sorts skies, given with their layer and entity id, into the order they are drawn in.
+///
+/// Skies of the same layer keep the order of their entities, so the order doesn't change from
+/// frame to frame.
+fn sort_layers<T>(skies: &mut [(i32, u32, T)]) {
+    skies.sort_by_key(|&(layer, id, _)| (layer, id));
+}
 
-impl VertexFormat for PosOnly {
-    const ATTRIBUTES: Attributes<'static> = &[(Position::NAME, <Self as With<Position>>::FORMAT)];
+/// Returns `drift` turned further by `angular_velocity` over `seconds`.
+fn advance_drift(
+    drift: &na::UnitQuaternion<f32>,
+    angular_velocity: &na::Vector3<f32>,
+    seconds: f32,
+) -> na::UnitQuaternion<f32> {
+    let mut drift = na::UnitQuaternion::from_scaled_axis(angular_velocity * seconds) * drift;
+    // Keeps rounding errors from piling up over long sessions.
+    drift.renormalize();
+    drift
 }
 
-impl With<Position> for PosOnly {
-    const FORMAT: AttributeFormat = Element {
-        offset: 0,
-        format: Position::FORMAT,
-    };
+impl Component for SkyBox {
+    type Storage = VecStorage<Self>;
 }
 
 /// Draws a sky box using cubemapped texture
+///
+/// The sky is drawn on the far plane with a `LessEqual` depth test by default, so it only covers
+/// pixels no geometry has been drawn to and can be added after the other passes of a stage.
+/// It is drawn as a single triangle over the whole screen rather than as a cube around the camera,
+/// so no near plane, however far out, cuts into it.
+///
+/// Cameras with an orthographic projection, like the ones of 2D games, see the sky as a backdrop
+/// through a perspective projection of their aspect ratio and a vertical field of view of 60
+/// degrees. It stays put as they pan and zoom, and only turns when they rotate. Leave the sky
+/// passes out of the pipeline of a 2D game that shouldn't have a sky, `DrawBackdrop` draws
+/// layers of images behind 2D scenes instead.
+/// Glows, sun discs and other draws that belong onto the sky go right after it, see
+/// `DrawSkyOverlays`.
+///
+/// Every `SkyBox` is drawn, as a layer over the ones with a lower `SkyBox::layer`, e.g. a layer
+/// of clouds over a far sky. The first layer replaces what was in the target, whatever its alpha,
+/// and each one after it is blended over the layers below by the alpha of its cubemap times its
+/// `tint`. Among skies of the same layer, the one whose entity has the highest id is drawn last
+/// and so covers the others. Cubemaps are usually opaque, so give the layers above the lowest one
+/// cubemaps with transparent texels or a `tint` alpha below one.
+///
+/// Passes reading the depth buffer afterwards, like `DrawFog` and `DrawSsao`, take pixels at the
+/// maximum depth of `1.0` to be sky and leave them alone. By default the sky doesn't write depth,
+/// so they see the value the depth buffer was cleared to, which should be `1.0`. Enable
+/// `with_depth_write` when the depth buffer is cleared to anything else.
+///
+/// Render targets store colors as they are written, without converting them to sRGB. So that a
+/// cubemap loaded as sRGB looks like its images on screen, the sky is encoded back to sRGB when
+/// drawn into the backbuffer. Into any other target it is written as linear color, which is what
+/// `DrawTonemap` and the other post-processing passes reading that target expect, see
+/// `Effect::srgb_output`. Use `with_srgb_output` when that guess doesn't match the pipeline.
+#[derive(Derivative, Clone)]
+#[derivative(Default, Debug)]
 pub struct DrawSkyBox {
-    mesh: Option<Mesh>,
+    #[derivative(Default(value = "true"))]
+    clamp: bool,
+    /// Draws the lowest layer, without blending.
+    #[derivative(Debug = "ignore")]
+    base: Option<Effect>,
+    samplers: Vec<(SamplerInfo, Sampler)>,
+    #[derivative(Default(value = "Some(DepthMode::LessEqualTest)"))]
+    depth: Option<DepthMode>,
+    draw_without_camera: bool,
+    srgb_output: Option<bool>,
 }
 
 impl DrawSkyBox {
     /// Create instance of `DrawSkyBox` pass
     pub fn new() -> Self {
-        DrawSkyBox { mesh: None }
+        Default::default()
+    }
+
+    /// Samples the cubemaps with the sampler configured on each `SkyBox`, which clamps to the
+    /// edges by default. Passing `false` uses the sampler the cubemaps were loaded with instead.
+    /// Enabled by default.
+    pub fn with_clamp(mut self, clamp: bool) -> Self {
+        self.clamp = clamp;
+        self
+    }
+
+    /// Sets the depth mode of the sky, `DepthMode::LessEqualTest` by default.
+    /// `None` draws the sky over everything, e.g. for a stage without a depth buffer.
+    pub fn with_depth(mut self, depth: Option<DepthMode>) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    /// Whether the sky writes its depth, the maximum of `1.0`, into the depth buffer. Disabled
+    /// by default.
+    ///
+    /// Either way the sky is depth tested, so this also undoes `with_depth(None)`.
+    pub fn with_depth_write(mut self, write: bool) -> Self {
+        self.depth = Some(if write {
+            DepthMode::LessEqualWrite
+        } else {
+            DepthMode::LessEqualTest
+        });
+        self
+    }
+
+    /// Whether to draw the sky with identity projection and view matrices while there is no
+    /// camera. Disabled by default.
+    ///
+    /// Without a camera there is no meaningful view of the sky, and drawing anyway flashes a
+    /// stretched view of it over the screen until a camera is created.
+    pub fn with_draw_without_camera(mut self, draw: bool) -> Self {
+        self.draw_without_camera = draw;
+        self
+    }
+
+    /// Whether to encode the sky to sRGB before writing it, instead of deciding by the target.
+    ///
+    /// Pass `false` when drawing into the backbuffer with passes that output linear colors, so the
+    /// sky matches the rest of the scene, and `true` when a named target is shown as it is.
+    pub fn with_srgb_output(mut self, srgb: bool) -> Self {
+        self.srgb_output = Some(srgb);
+        self
+    }
+}
+
+impl DrawSkyBox {
+    /// Declares the inputs of the sky shader, shared by the effects of both kinds of layers.
+    fn setup<'a, 'b>(&self, builder: &'a mut EffectBuilder<'b>) -> &'a mut EffectBuilder<'b> {
+        use std::mem;
+        builder
+            .with_raw_constant_buffer(
+                "VertexArgs",
+                mem::size_of::<<VertexArgs as Uniform>::Std140>(),
+                1,
+            )
+            .with_texture("skybox")
+            .with_texture("skybox_b")
+            .with_raw_global("blend")
+            .with_raw_global("tint")
+            .with_raw_global("exposure")
+            .with_raw_global("lod_bias")
+            .with_raw_global("encode_srgb");
+        if let Some(srgb) = self.srgb_output {
+            builder.with_srgb_output(srgb);
+        }
+        builder
     }
 }
 
 impl<'a> PassData<'a> for DrawSkyBox {
     type Data = (
-        Read<'a, ActiveCamera>,
-        ReadStorage<'a, Camera>,
+        SkyCameras<'a>,
         Read<'a, AssetStorage<Texture>>,
         ReadExpect<'a, MaterialDefaults>,
-        ReadStorage<'a, GlobalTransform>,
+        ReadExpect<'a, MissingTextures>,
+        Entities<'a>,
         ReadStorage<'a, SkyBox>,
     );
 }
 
 impl Pass for DrawSkyBox {
     fn compile(&mut self, mut effect: NewEffect<'_>) -> Result<Effect, Error> {
-        let data: Vec<PosOnly> = SKYBOX_VERTICES
-            .iter()
-            .map(|v| PosOnly {
-                position: v.clone(),
-            })
-            .collect();
-        self.mesh = Some(Mesh::build(data).build(&mut effect.factory)?);
-        use std::mem;
-        effect
-            .simple(VERT_SRC, FRAG_SRC)
-            .with_raw_vertex_buffer(PosOnly::ATTRIBUTES, PosOnly::size() as ElemStride, 0)
-            .with_raw_constant_buffer(
-                "VertexArgs",
-                mem::size_of::<<VertexArgs as Uniform>::Std140>(),
-                1,
-            )
-            .with_texture("top")
-            .with_output("color", None)
-            .build()
+        let out = effect.out();
+        let mut base = effect.simple_into(out, VERT_SRC, FRAG_SRC);
+        self.setup(&mut base).with_output("color", self.depth);
+        self.base = Some(base.build()?);
+
+        let mut builder = effect.simple(VERT_SRC, FRAG_SRC);
+        self.setup(&mut builder)
+            .with_blended_output("color", ColorMask::all(), ALPHA, self.depth);
+        builder.build()
     }
 
     fn apply<'a, 'b: 'a>(
         &'a mut self,
         encoder: &mut Encoder,
         effect: &mut Effect,
-        _factory: Factory,
-        (
-            active,
-            camera,
-            tex_storage,
-            material_defaults,
-            global,
-            skybox,
-        ): <Self as PassData<'a>>::Data,
+        mut factory: Factory,
+        (cameras, tex_storage, defaults, missing, entities, skybox): <Self as PassData<'a>>::Data,
     ) {
-        let camera = get_camera(active, &camera, &global);
-        let vertex_args = camera
-            .as_ref()
-            .map(|&(ref cam, ref transform)| {
-                let proj: [[f32; 4]; 4] = cam.proj.into();
-                let view: [[f32; 4]; 4] = transform.0.try_inverse().unwrap().into();
-                VertexArgs {
-                    proj: proj.into(),
-                    view: view.into(),
-                }
-            })
-            .unwrap_or_else(|| {
-                let proj: [[f32; 4]; 4] = na::Matrix4::identity().into();
-                let view: [[f32; 4]; 4] = na::Matrix4::identity().into();
-                VertexArgs {
-                    proj: proj.into(),
-                    view: view.into(),
-                }
-            });
-
-        for sky in (&skybox).join() {
-            let mesh = self.mesh.as_ref().unwrap();
-
-            //FIXME: it is probably not necessary to push the mesh to the GPU every frame. Loading
-            //it once should be enough
-            match mesh.buffer(PosOnly::ATTRIBUTES) {
-                Some(vbuf) => effect.data.vertex_bufs.push(vbuf.clone()),
-                None => {
-                    effect.clear();
-                    return;
-                }
+        let encode_srgb = if effect.srgb_output() { 1.0 } else { 0.0 };
+        effect.update_global("encode_srgb", encode_srgb);
+        let mut base = self.base.as_mut();
+        if let Some(ref mut base) = base {
+            base.update_global("encode_srgb", encode_srgb);
+        }
+
+        let mut layers = (&entities, &skybox)
+            .join()
+            .map(|(entity, sky)| (sky.layer, entity.id(), sky))
+            .collect::<Vec<_>>();
+        sort_layers(&mut layers);
+
+        for view in sky_views(cameras, effect.target_name()) {
+            if view.0.is_none() && !self.draw_without_camera {
+                continue;
             }
+            for (i, &(_, _, sky)) in layers.iter().enumerate() {
+                let effect = match (i, base.as_mut()) {
+                    (0, Some(base)) => &mut **base,
+                    _ => &mut *effect,
+                };
+                // Every camera, and both eyes of a stereo one, sees the sky in the same
+                // orientation, only through its own projection.
+                let vertex_args = sky_view_args(effect, &view, &sky.orientation());
+                effect.update_constant_buffer("VertexArgs", &vertex_args.std140(), encoder);
 
-            effect.update_constant_buffer("VertexArgs", &vertex_args.std140(), encoder);
+                let texture = tex_storage
+                    .get(&sky.texture)
+                    .or_else(|| tex_storage.get(&missing.color))
+                    .or_else(|| tex_storage.get(&defaults.0.albedo))
+                    .unwrap();
+                // Without a second cubemap the first one is bound twice, making the blend a no-op.
+                let texture_b = sky
+                    .texture_b
+                    .as_ref()
+                    .and_then(|handle| tex_storage.get(handle))
+                    .unwrap_or(texture);
+                let sampler = if self.clamp {
+                    Some(cached_sampler(
+                        &mut self.samplers,
+                        &mut factory,
+                        sky.sampler,
+                    ))
+                } else {
+                    None
+                };
+                for tex in &[texture, texture_b] {
+                    effect.add_raw_texture(
+                        tex.view(),
+                        sampler.as_ref().unwrap_or_else(|| tex.sampler()),
+                    );
+                }
+                effect.update_global("blend", sky.blend);
+                effect.update_global("tint", sky.tint);
+                effect.update_global("exposure", sky.exposure);
+                effect.update_global("lod_bias", sky.lod_bias);
 
-            //TODO: Related to the above comment, the skybox texture most likely doesnt change
-            //after scene setup. Having an option to access the Pass from within a system to update
-            //the texture drawn would even elimitate the need for a seperate skybox component, the
-            //texture could be stored in the pass directly.
-            let texture = tex_storage
-                .get(&sky.texture)
-                .or_else(|| tex_storage.get(&material_defaults.0.albedo));
-            effect.data.textures.push(texture.unwrap().view().clone());
-            effect
-                .data
-                .samplers
-                .push(texture.unwrap().sampler().clone());
+                effect.draw(&fullscreen_slice(), encoder);
 
-            effect.draw(mesh.slice(), encoder);
+                effect.clear_textures();
+            }
+        }
+    }
 
-            effect.clear();
+    fn new_targets(&mut self, targets: &Targets) {
+        if let Some(ref mut base) = self.base {
+            let name = base.target_name().to_string();
+            if let Some(target) = targets.get(&name) {
+                base.retarget(target);
+            }
         }
     }
 }
 
-/// Load a set of 6 textures as cubemapped texture array
-pub fn load_cubemap<N>(
-    names: [N; 6],
-    size: u16,
-    loader: &Loader,
-    storage: &AssetStorage<Texture>,
-) -> TextureHandle
-where
-    N: Into<String> + Copy,
-{
-    let data: [ImageData; 6] = [
-        load_texture(names[0]),
-        load_texture(names[1]),
-        load_texture(names[2]),
-        load_texture(names[3]),
-        load_texture(names[4]),
-        load_texture(names[5]),
-    ];
-    let meta = TextureMetadata::srgb().with_kind(Kind::Cube(size));
-
-    let texture_data = TextureData::CubeImage(data, meta);
-    loader.load_from_data(texture_data, (), storage)
-}
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
 
-fn load_texture<P: Into<String>>(path: P) -> ImageData {
-    use image::{load_from_memory, DynamicImage};
-    use std::{fs::File, io::Read};
-
-    let mut data = Vec::new();
-    let mut file = File::open(path.into()).unwrap();
-    file.read_to_end(&mut data);
-
-    load_from_memory(&data)
-        .map(|image| {
-            match image {
-                DynamicImage::ImageRgba8(im) => im,
-                _ => {
-                    // TODO: Log performance warning.
-                    image.to_rgba()
-                }
-            }
-        })
-        .map(|rgba| ImageData { rgba })
-        .unwrap()
-}
+    use amethyst_core::{nalgebra as na, specs::prelude::Builder, transform::GlobalTransform};
+    use amethyst_error::Error;
+
+    use crate::{
+        cam::{Camera, Projection},
+        headless::tests::{cube, insert, world},
+        pipe::{
+            pass::{Pass, PassData},
+            Effect, NewEffect, Pipeline, Stage,
+        },
+        types::{Encoder, Factory},
+        HeadlessRenderer,
+    };
+
+    use super::{
+        advance_drift, sky_matrices, sort_layers, unprojection, DrawSkyBox, SkyBox, BACKDROP_FOV,
+    };
+
+    /// Draws with `DrawSkyBox` and records how many vertex buffers its effects hold each frame.
+    struct CountVertexBuffers(DrawSkyBox, Arc<Mutex<Vec<usize>>>);
 
-const SKYBOX_VERTICES: [[f32; 3]; 36] = [
-    [-1.0, 1.0, -1.0],
-    [-1.0, -1.0, -1.0],
-    [1.0, -1.0, -1.0],
-    [1.0, -1.0, -1.0],
-    [1.0, 1.0, -1.0],
-    [-1.0, 1.0, -1.0],
-    [-1.0, -1.0, 1.0],
-    [-1.0, -1.0, -1.0],
-    [-1.0, 1.0, -1.0],
-    [-1.0, 1.0, -1.0],
-    [-1.0, 1.0, 1.0],
-    [-1.0, -1.0, 1.0],
-    [1.0, -1.0, -1.0],
-    [1.0, -1.0, 1.0],
-    [1.0, 1.0, 1.0],
-    [1.0, 1.0, 1.0],
-    [1.0, 1.0, -1.0],
-    [1.0, -1.0, -1.0],
-    [-1.0, -1.0, 1.0],
-    [-1.0, 1.0, 1.0],
-    [1.0, 1.0, 1.0],
-    [1.0, 1.0, 1.0],
-    [1.0, -1.0, 1.0],
-    [-1.0, -1.0, 1.0],
-    [-1.0, 1.0, -1.0],
-    [1.0, 1.0, -1.0],
-    [1.0, 1.0, 1.0],
-    [1.0, 1.0, 1.0],
-    [-1.0, 1.0, 1.0],
-    [-1.0, 1.0, -1.0],
-    [-1.0, -1.0, -1.0],
-    [-1.0, -1.0, 1.0],
-    [1.0, -1.0, -1.0],
-    [1.0, -1.0, -1.0],
-    [-1.0, -1.0, 1.0],
-    [1.0, -1.0, 1.0],
-];
+    impl<'a> PassData<'a> for CountVertexBuffers {
+        type Data = <DrawSkyBox as PassData<'a>>::Data;
+    }
+
+    impl Pass for CountVertexBuffers {
+        fn compile(&mut self, effect: NewEffect<'_>) -> Result<Effect, Error> {
+            self.0.compile(effect)
+        }
+
+        fn apply<'a, 'b: 'a>(
+            &'a mut self,
+            encoder: &mut Encoder,
+            effect: &mut Effect,
+            factory: Factory,
+            data: <Self as PassData<'b>>::Data,
+        ) {
+            self.0.apply(encoder, effect, factory, data);
+            let base = self.0.base.as_ref().map(Effect::vertex_buffers);
+            let buffers = effect.vertex_buffers().len() + base.map_or(0, <[_]>::len);
+            self.1.lock().unwrap().push(buffers);
+        }
+    }
+
+    /// Direction of the sky at `ndc` on the far plane, as the vertex shader computes it.
+    fn sky_direction(unproject: &na::Matrix4<f32>, ndc: [f32; 2]) -> na::Vector3<f32> {
+        let far = unproject * na::Vector4::new(ndc[0], ndc[1], 1.0, 1.0);
+        let direction = if far.w < 0.0 { -far.xyz() } else { far.xyz() };
+        direction.normalize()
+    }
+
+    #[test]
+    fn skies_are_drawn_by_layer_then_entity() {
+        let mut skies = vec![
+            (1, 0, "clouds"),
+            (0, 3, "stars"),
+            (-1, 5, "far"),
+            (0, 2, "sky"),
+        ];
+        sort_layers(&mut skies);
+        let order = skies.iter().map(|&(_, _, name)| name).collect::<Vec<_>>();
+        assert_eq!(order, ["far", "sky", "stars", "clouds"]);
+    }
+
+    #[test]
+    fn rotation_is_folded_into_view() {
+        let camera = Camera {
+            proj: na::Matrix4::identity(),
+        };
+        let transform = GlobalTransform(na::Matrix4::new_translation(&na::Vector3::new(
+            1.0, 2.0, 3.0,
+        )));
+        let rotation = na::UnitQuaternion::from_axis_angle(&na::Vector3::y_axis(), 1.0);
+
+        let (_, view) = sky_matrices(Some((&camera, &transform)), &rotation);
+
+        let rotation_part = view.fixed_slice::<na::U3, na::U3>(0, 0).into_owned();
+        assert!((rotation_part - rotation.to_rotation_matrix().matrix()).norm() < 1e-6);
+        // The camera translation survives untouched, `unprojection` strips it.
+        let translation_part = view.fixed_slice::<na::U3, na::U1>(0, 3).into_owned();
+        assert!((translation_part - na::Vector3::new(-1.0, -2.0, -3.0)).norm() < 1e-6);
+    }
+
+    #[test]
+    fn pixels_look_along_the_camera_for_any_projection() {
+        let (aspect, fovy) = (1.5, 1.0);
+        #[rustfmt::skip]
+        let infinite = {
+            let f = 1.0 / (fovy / 2.0f32).tan();
+            na::Matrix4::new(
+                f / aspect, 0.0, 0.0, 0.0,
+                0.0, f, 0.0, 0.0,
+                0.0, 0.0, -1.0, -0.2,
+                0.0, 0.0, -1.0, 0.0,
+            )
+        };
+        let projections = [
+            na::Perspective3::new(aspect, fovy, 0.1, 1000.0).to_homogeneous(),
+            // Planes far from the camera and close to each other, where a cube would be clipped.
+            na::Perspective3::new(aspect, fovy, 500.0, 501.0).to_homogeneous(),
+            infinite,
+        ];
+        let turn = na::UnitQuaternion::from_axis_angle(&na::Vector3::y_axis(), 0.7);
+        let camera = na::Isometry3::from_parts(na::Translation3::new(5.0, -2.0, 8.0), turn);
+        let view = camera.inverse().to_homogeneous();
+
+        for proj in &projections {
+            let unproject = unprojection(proj, &view);
+
+            let ahead = sky_direction(&unproject, [0.0, 0.0]);
+            assert!((ahead - turn * -na::Vector3::z()).norm() < 1e-4);
+
+            // The top edge of the screen is half the vertical field of view above it.
+            let up = sky_direction(&unproject, [0.0, 1.0]);
+            assert!((ahead.angle(&up) - fovy / 2.0).abs() < 1e-4);
+            assert!(up.y > 0.0);
+        }
+    }
+
+    #[test]
+    fn orthographic_cameras_see_a_backdrop() {
+        let camera = Camera::from(Projection::orthographic(-400.0, 400.0, -300.0, 300.0));
+        assert!(camera.is_orthographic());
+        assert!(!Camera::standard_3d(800.0, 600.0).is_orthographic());
+
+        let rotation = na::UnitQuaternion::identity();
+        let unproject = |transform: &GlobalTransform| {
+            let (proj, view) = sky_matrices(Some((&camera, transform)), &rotation);
+            unprojection(&proj, &view)
+        };
+        let still = unproject(&GlobalTransform(na::one()));
+        let panned = unproject(&GlobalTransform(na::Matrix4::new_translation(
+            &na::Vector3::new(250.0, -80.0, 0.0),
+        )));
+
+        let ahead = sky_direction(&still, [0.0, 0.0]);
+        let up = sky_direction(&still, [0.0, 1.0]);
+        let right = sky_direction(&still, [1.0, 0.0]);
+        assert!((ahead.angle(&up) - BACKDROP_FOV / 2.0).abs() < 1e-4);
+        let half_width = (4.0 / 3.0 * (BACKDROP_FOV / 2.0).tan()).atan();
+        assert!((ahead.angle(&right) - half_width).abs() < 1e-4);
+
+        let pixel = [0.3, -0.6];
+        assert!((sky_direction(&panned, pixel) - sky_direction(&still, pixel)).norm() < 1e-5);
+    }
+
+    #[test]
+    fn drift_follows_the_angular_velocity() {
+        let velocity = na::Vector3::new(0.0, 0.5, 0.0);
+        let mut drift = na::UnitQuaternion::identity();
+        for _ in 0..120 {
+            drift = advance_drift(&drift, &velocity, 1.0 / 60.0);
+        }
+        let expected = na::UnitQuaternion::from_axis_angle(&na::Vector3::y_axis(), 1.0);
+        assert!(drift.angle_to(&expected) < 1e-4);
+    }
+
+    #[test]
+    #[ignore] // Needs an OpenGL context, see `HeadlessRenderer`.
+    fn no_vertex_buffer_is_pushed_per_frame() {
+        let mut renderer = HeadlessRenderer::new((4, 4)).unwrap();
+        let mut world = world(&mut renderer);
+        let red = cube(&mut renderer, [255, 0, 0, 255]);
+        let red = insert(&mut world.res, [1.0, 0.0, 0.0, 1.0].into(), red);
+        // Two layers, so both the base and the blending effect draw.
+        let mut near = SkyBox::new(red.clone());
+        near.set_layer(1);
+        world.register::<SkyBox>();
+        world.create_entity().with(SkyBox::new(red)).build();
+        world.create_entity().with(near).build();
+
+        let counts = Arc::new(Mutex::new(Vec::new()));
+        let pass = CountVertexBuffers(
+            DrawSkyBox::new().with_draw_without_camera(true),
+            counts.clone(),
+        );
+        let mut pipe = renderer
+            .create_pipe(Pipeline::build().with_stage(Stage::with_backbuffer().with_pass(pass)))
+            .unwrap();
+        for _ in 0..3 {
+            renderer.draw_world(&mut pipe, &mut world.res);
+        }
+        assert_eq!(*counts.lock().unwrap(), [0, 0, 0]);
+    }
+}
@@ -0,0 +1,236 @@
+//! Overlays drawn onto the sky, such as a sun disc
+
+use amethyst_core::{
+    nalgebra as na,
+    specs::prelude::{Component, DenseVecStorage, Join, ReadStorage},
+};
+use amethyst_error::Error;
+
+use glsl_layout::Uniform;
+
+use crate::{
+    light::DirectionalLight,
+    pass::util::fullscreen_slice,
+    pipe::{
+        pass::{Pass, PassData},
+        DepthMode, Effect, NewEffect,
+    },
+    transparent::{ColorMask, ALPHA},
+    types::{Encoder, Factory},
+};
+
+use super::{sky_view_args, sky_views, SkyCameras, VertexArgs, VERT_SRC};
+
+const FRAG_SRC: &[u8] = include_bytes!("../shaders/fragment/sky_overlay.glsl");
+
+/// Component describing a disc with a soft glow drawn onto the sky by `DrawSkyOverlays`
+///
+/// Overlays are placed by direction only, so like the sky they stay at infinity however the
+/// camera moves. Colors are linear.
+#[derive(Clone, Debug)]
+pub struct SkyOverlay {
+    /// Direction from the viewer towards the center of the overlay, in world space
+    pub direction: na::Vector3<f32>,
+    /// Angular radius of the disc in radians. `0.0` draws only the glow.
+    pub radius: f32,
+    /// Color of the disc
+    pub color: [f32; 4],
+    /// Angular width in radians over which the glow around the disc fades out. `0.0` disables
+    /// the glow.
+    pub glow_radius: f32,
+    /// Color of the glow, its alpha being the opacity right at the rim of the disc
+    pub glow_color: [f32; 4],
+    /// Overlays are drawn in ascending `order`, so higher ones cover lower ones.
+    pub order: i32,
+}
+
+impl SkyOverlay {
+    /// Creates a white disc without glow in the given direction.
+    pub fn new(direction: na::Vector3<f32>, radius: f32) -> Self {
+        SkyOverlay {
+            direction,
+            radius,
+            color: [1.0; 4],
+            glow_radius: 0.0,
+            glow_color: [0.0; 4],
+            order: 0,
+        }
+    }
+
+    /// Creates a sun disc in the color of `light`, in the direction the light comes from.
+    ///
+    /// The disc is about as wide as the sun seen from the earth, with a faint glow around it.
+    pub fn sun(light: &DirectionalLight) -> Self {
+        let [x, y, z] = light.direction;
+        let [r, g, b, _]: [f32; 4] = light.tinted_color().into();
+        SkyOverlay {
+            glow_radius: 0.05,
+            glow_color: [r, g, b, 0.5],
+            ..SkyOverlay::new(-na::Vector3::new(x, y, z), 0.01).with_color([r, g, b, 1.0])
+        }
+    }
+
+    /// Sets the color of the disc.
+    pub fn with_color(mut self, color: [f32; 4]) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Sets the width and color of the glow around the disc.
+    pub fn with_glow(mut self, glow_radius: f32, glow_color: [f32; 4]) -> Self {
+        self.glow_radius = glow_radius;
+        self.glow_color = glow_color;
+        self
+    }
+
+    /// Sets the position of the overlay in the drawing order.
+    pub fn with_order(mut self, order: i32) -> Self {
+        self.order = order;
+        self
+    }
+}
+
+impl Component for SkyOverlay {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Returns the overlays that can be drawn, in the order they are drawn in.
+fn draw_order<'a, I>(overlays: I) -> Vec<&'a SkyOverlay>
+where
+    I: IntoIterator<Item = &'a SkyOverlay>,
+{
+    let mut overlays = overlays
+        .into_iter()
+        .filter(|overlay| overlay.direction.norm_squared() > 0.0)
+        .collect::<Vec<_>>();
+    overlays.sort_by_key(|overlay| overlay.order);
+    overlays
+}
+
+/// Draws the `SkyOverlay`s onto the sky
+///
+/// Add it to the stage right after the sky pass, e.g. `DrawSkyBox`, `DrawSkyGradient` or
+/// `DrawSkyPanorama`. Like the sky, the overlays are drawn on the far plane with a
+/// `LessEqual` depth test and without writing depth. So they blend onto the sky wherever no
+/// geometry covers it, whether the sky and overlays are drawn before or after the opaque passes,
+/// and any custom pass placed between them draws in the same slot.
+///
+/// Colors are encoded like `DrawSkyBox` encodes the sky, see `with_srgb_output`.
+#[derive(Clone, Debug, Default)]
+pub struct DrawSkyOverlays {
+    srgb_output: Option<bool>,
+}
+
+impl DrawSkyOverlays {
+    /// Create instance of `DrawSkyOverlays` pass
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Whether to encode the overlays to sRGB before writing them, instead of deciding by the
+    /// target. Use the same setting as for the sky pass.
+    pub fn with_srgb_output(mut self, srgb: bool) -> Self {
+        self.srgb_output = Some(srgb);
+        self
+    }
+}
+
+impl<'a> PassData<'a> for DrawSkyOverlays {
+    type Data = (SkyCameras<'a>, ReadStorage<'a, SkyOverlay>);
+}
+
+impl Pass for DrawSkyOverlays {
+    fn compile(&mut self, effect: NewEffect<'_>) -> Result<Effect, Error> {
+        use std::mem;
+        let mut builder = effect.simple(VERT_SRC, FRAG_SRC);
+        builder
+            .with_raw_constant_buffer(
+                "VertexArgs",
+                mem::size_of::<<VertexArgs as Uniform>::Std140>(),
+                1,
+            )
+            .with_raw_global("direction")
+            .with_raw_global("radius")
+            .with_raw_global("overlay_color")
+            .with_raw_global("glow_radius")
+            .with_raw_global("glow_color")
+            .with_raw_global("encode_srgb")
+            .with_blended_output(
+                "color",
+                ColorMask::all(),
+                ALPHA,
+                Some(DepthMode::LessEqualTest),
+            );
+        if let Some(srgb) = self.srgb_output {
+            builder.with_srgb_output(srgb);
+        }
+        builder.build()
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        _factory: Factory,
+        (cameras, overlay): <Self as PassData<'a>>::Data,
+    ) {
+        let encode_srgb = if effect.srgb_output() { 1.0 } else { 0.0 };
+        effect.update_global("encode_srgb", encode_srgb);
+
+        let overlays = draw_order(overlay.join());
+        for view in sky_views(cameras, effect.target_name()) {
+            if view.0.is_none() {
+                continue;
+            }
+            let vertex_args = sky_view_args(effect, &view, &na::UnitQuaternion::identity());
+            effect.update_constant_buffer("VertexArgs", &vertex_args.std140(), encoder);
+
+            for overlay in &overlays {
+                let direction: [f32; 3] = overlay.direction.normalize().into();
+                effect.update_global("direction", direction);
+                effect.update_global("radius", overlay.radius);
+                effect.update_global("overlay_color", overlay.color);
+                effect.update_global("glow_radius", overlay.glow_radius);
+                effect.update_global("glow_color", overlay.glow_color);
+                effect.draw(&fullscreen_slice(), encoder);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use amethyst_core::nalgebra as na;
+
+    use crate::light::DirectionalLight;
+
+    use super::{draw_order, SkyOverlay};
+
+    #[test]
+    fn sun_faces_the_light() {
+        let light = DirectionalLight {
+            color: [1.0, 0.9, 0.8, 1.0].into(),
+            direction: [0.0, -1.0, 0.0],
+            ..DirectionalLight::default()
+        };
+        let sun = SkyOverlay::sun(&light);
+        assert_eq!(sun.direction, na::Vector3::new(0.0, 1.0, 0.0));
+        assert_eq!(sun.color, [1.0, 0.9, 0.8, 1.0]);
+    }
+
+    #[test]
+    fn overlays_are_drawn_in_order() {
+        let up = na::Vector3::y();
+        let overlays = [
+            SkyOverlay::new(up, 0.1).with_order(2),
+            SkyOverlay::new(na::Vector3::zeros(), 0.1),
+            SkyOverlay::new(up, 0.2).with_order(-1),
+            SkyOverlay::new(up, 0.3).with_order(2),
+        ];
+        let radii = draw_order(overlays.iter())
+            .iter()
+            .map(|overlay| overlay.radius)
+            .collect::<Vec<_>>();
+        assert_eq!(radii, vec![0.2, 0.1, 0.3]);
+    }
+}
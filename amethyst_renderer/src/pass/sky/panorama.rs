@@ -0,0 +1,119 @@
+//! Equirectangular panorama sky pass
+
+use amethyst_assets::AssetStorage;
+use amethyst_core::{
+    nalgebra as na,
+    specs::prelude::{Component, Join, Read, ReadExpect, ReadStorage, VecStorage},
+};
+use amethyst_error::Error;
+
+use glsl_layout::Uniform;
+
+use crate::{
+    mtl::{MaterialDefaults, MissingTextures},
+    pass::util::fullscreen_slice,
+    pipe::{
+        pass::{Pass, PassData},
+        DepthMode, Effect, NewEffect,
+    },
+    tex::{Texture, TextureHandle},
+    types::{Encoder, Factory},
+};
+
+use super::{sky_view_args, sky_views, SkyCameras, VertexArgs, VERT_SRC};
+
+const FRAG_SRC: &[u8] = include_bytes!("../shaders/fragment/sky_panorama.glsl");
+
+/// Component to keep a handle to the equirectangular panorama drawn by `DrawSkyPanorama`
+///
+/// The center of the panorama looks down the negative Z axis and its top row is straight up,
+/// like for `load_equirect`.
+#[derive(Clone, Debug)]
+pub struct SkyPanorama {
+    /// Handle to the 2D panorama texture
+    pub texture: TextureHandle,
+}
+
+impl SkyPanorama {
+    /// Creates a new `SkyPanorama` drawing the given texture.
+    pub fn new(texture: TextureHandle) -> Self {
+        SkyPanorama { texture }
+    }
+}
+
+impl Component for SkyPanorama {
+    type Storage = VecStorage<Self>;
+}
+
+/// Draws a sky from a single equirectangular texture
+///
+/// Unlike `DrawSkyBox` with `load_equirect` this needs no conversion and only a single texture
+/// in memory, at the cost of computing the texture coordinates per pixel.
+///
+/// Known limitations: the texels of the panorama are stretched towards the poles, so straight up
+/// and down look pinched unless the panorama was authored for it. With mipmapped textures a thin
+/// seam can appear where the panorama wraps around behind the -Z axis.
+#[derive(Clone, Debug, Default)]
+pub struct DrawSkyPanorama;
+
+impl DrawSkyPanorama {
+    /// Create instance of `DrawSkyPanorama` pass
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl<'a> PassData<'a> for DrawSkyPanorama {
+    type Data = (
+        SkyCameras<'a>,
+        Read<'a, AssetStorage<Texture>>,
+        ReadExpect<'a, MaterialDefaults>,
+        ReadExpect<'a, MissingTextures>,
+        ReadStorage<'a, SkyPanorama>,
+    );
+}
+
+impl Pass for DrawSkyPanorama {
+    fn compile(&mut self, effect: NewEffect<'_>) -> Result<Effect, Error> {
+        use std::mem;
+        effect
+            .simple(VERT_SRC, FRAG_SRC)
+            .with_raw_constant_buffer(
+                "VertexArgs",
+                mem::size_of::<<VertexArgs as Uniform>::Std140>(),
+                1,
+            )
+            .with_texture("panorama")
+            .with_output("color", Some(DepthMode::LessEqualTest))
+            .build()
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        _factory: Factory,
+        (cameras, tex_storage, material_defaults, missing, panorama): <Self as PassData<'a>>::Data,
+    ) {
+        for view in sky_views(cameras, effect.target_name()) {
+            if view.0.is_none() {
+                continue;
+            }
+            let vertex_args = sky_view_args(effect, &view, &na::UnitQuaternion::identity());
+            effect.update_constant_buffer("VertexArgs", &vertex_args.std140(), encoder);
+
+            for sky in (&panorama).join() {
+                let texture = tex_storage
+                    .get(&sky.texture)
+                    .or_else(|| tex_storage.get(&missing.color))
+                    .or_else(|| tex_storage.get(&material_defaults.0.albedo))
+                    .unwrap();
+                effect.add_texture(texture);
+
+                effect.draw(&fullscreen_slice(), encoder);
+
+                effect.clear_textures();
+            }
+        }
+    }
+}
@@ -0,0 +1,230 @@
+//! GPU convolution of cubemaps for image based lighting
+
+use std::{f32::consts::FRAC_PI_2, mem};
+
+use gfx::texture::Kind;
+use glsl_layout::Uniform;
+
+use amethyst_assets::AssetStorage;
+use amethyst_core::{
+    nalgebra::{Point3, UnitQuaternion},
+    transform::GlobalTransform,
+};
+use amethyst_error::Error;
+
+use crate::{
+    cam::{Camera, Projection},
+    error,
+    pass::{
+        probe::{face_targets, face_transform},
+        util::fullscreen_slice,
+    },
+    pipe::{EffectBuilder, ProgramSource},
+    tex::{FilterMethod, SamplerInfo, Texture, TextureHandle, WrapMode},
+    types::{Encoder, Factory},
+};
+
+use super::{vertex_args, VertexArgs, VERT_SRC};
+
+static FRAG_SRC: &[u8] = include_bytes!("../shaders/fragment/cubemap_filter.glsl");
+
+/// Largest face width of specular cubemaps, the sharpest level doesn't need more.
+const MAX_SPECULAR_SIZE: u16 = 256;
+/// Number of roughness levels of specular cubemaps.
+const SPECULAR_LEVELS: u8 = 5;
+/// Face width of irradiance cubemaps, which hold low frequencies only.
+const IRRADIANCE_SIZE: u16 = 32;
+
+/// How a cubemap is convolved for image based lighting.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum CubemapFilter {
+    /// GGX prefiltered reflections for specular lighting, sampled in the reflected direction.
+    ///
+    /// Mip level `i` of `n` holds the reflections of roughness `i / (n - 1)`, so sample it with
+    /// `textureLod(environment, reflected, roughness * (n - 1))`.
+    Specular,
+    /// Cosine weighted irradiance for diffuse lighting, sampled in the direction of the normal.
+    ///
+    /// The irradiance is divided by pi, so multiplying it with the albedo gives the reflected
+    /// light.
+    Irradiance,
+}
+
+/// Cubemaps waiting to be convolved on the GPU by the render system.
+///
+/// Filled by `prefilter_cubemap` and `irradiance_cubemap`. Every entry is convolved once its
+/// source is loaded and removed afterwards.
+#[derive(Clone, Debug, Default)]
+pub struct CubemapFilters {
+    pending: Vec<(TextureHandle, TextureHandle, CubemapFilter)>,
+}
+
+impl CubemapFilters {
+    /// Replaces `texture` with `source` convolved by `filter` once `source` is loaded.
+    pub fn insert(&mut self, texture: TextureHandle, source: TextureHandle, filter: CubemapFilter) {
+        self.pending.push((texture, source, filter));
+    }
+
+    /// Returns whether `texture` still waits for its convolution.
+    pub fn is_pending(&self, texture: &TextureHandle) -> bool {
+        self.pending
+            .iter()
+            .any(|(pending, _, _)| pending == texture)
+    }
+
+    /// Keeps the entries, given as texture, source and filter, for which `f` returns `true`.
+    pub(crate) fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&TextureHandle, &TextureHandle, CubemapFilter) -> bool,
+    {
+        self.pending
+            .retain(|(texture, source, filter)| f(texture, source, *filter));
+    }
+}
+
+/// Prefilters the cubemap of `handle` for specular image based lighting, see
+/// `CubemapFilter::Specular`.
+///
+/// Returns a new texture, or `None` if `handle` isn't loaded yet. The texture shows the unfiltered
+/// cubemap until the render system has run the convolution on the GPU, usually before the next
+/// frame is drawn.
+pub fn prefilter_cubemap(
+    handle: &TextureHandle,
+    storage: &mut AssetStorage<Texture>,
+    filters: &mut CubemapFilters,
+) -> Option<TextureHandle> {
+    filtered_cubemap(handle, storage, filters, CubemapFilter::Specular)
+}
+
+/// Convolves the cubemap of `handle` into irradiance for diffuse image based lighting, see
+/// `CubemapFilter::Irradiance`.
+///
+/// Returns a new texture like `prefilter_cubemap` does.
+pub fn irradiance_cubemap(
+    handle: &TextureHandle,
+    storage: &mut AssetStorage<Texture>,
+    filters: &mut CubemapFilters,
+) -> Option<TextureHandle> {
+    filtered_cubemap(handle, storage, filters, CubemapFilter::Irradiance)
+}
+
+fn filtered_cubemap(
+    handle: &TextureHandle,
+    storage: &mut AssetStorage<Texture>,
+    filters: &mut CubemapFilters,
+    filter: CubemapFilter,
+) -> Option<TextureHandle> {
+    let texture = storage.clone_asset(handle)?;
+    filters.insert(texture.clone(), handle.clone(), filter);
+    Some(texture)
+}
+
+/// Returns the face width and mip level count of `filter` applied to a cubemap with `size` wide
+/// faces.
+fn filtered_size(filter: CubemapFilter, size: u16) -> (u16, u8) {
+    match filter {
+        CubemapFilter::Specular => {
+            let size = size.min(MAX_SPECULAR_SIZE).max(1);
+            let levels = (16 - size.leading_zeros()) as u8;
+            (size, levels.min(SPECULAR_LEVELS))
+        }
+        CubemapFilter::Irradiance => (size.min(IRRADIANCE_SIZE).max(1), 1),
+    }
+}
+
+/// Returns the roughness prefiltered into mip level `level` of `levels`.
+fn level_roughness(level: u8, levels: u8) -> f32 {
+    if levels > 1 {
+        f32::from(level) / f32::from(levels - 1)
+    } else {
+        0.0
+    }
+}
+
+/// Convolves the cubemap `source` with `filter` into a new cubemap.
+///
+/// The draws are only encoded, so the result holds the convolution once `encoder` is flushed.
+pub(crate) fn filter_cubemap(
+    source: &Texture,
+    filter: CubemapFilter,
+    factory: &mut Factory,
+    encoder: &mut Encoder,
+) -> Result<Texture, Error> {
+    use gfx::Factory;
+
+    let source_size = match source.raw().get_info().kind {
+        Kind::Cube(size) => size,
+        _ => return Err(error::Error::NotCubemap.into()),
+    };
+    let (size, levels) = filtered_size(filter, source_size);
+    let texture = Texture::render_cubemap(size, levels, factory)?;
+    let targets = (0..levels)
+        .map(|level| face_targets(factory, &texture, level, (size >> level).max(1)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Samples are spread over the source's mip levels, so it is read with a trilinear sampler
+    // whatever its own sampler is.
+    let sampler =
+        factory.create_sampler(SamplerInfo::new(FilterMethod::Trilinear, WrapMode::Clamp));
+    let mut effect = EffectBuilder::new(
+        factory,
+        &targets[0][0],
+        0,
+        ProgramSource::Simple(VERT_SRC, FRAG_SRC),
+    )
+    .with_raw_constant_buffer(
+        "VertexArgs",
+        mem::size_of::<<VertexArgs as Uniform>::Std140>(),
+        1,
+    )
+    .with_texture("source")
+    .with_raw_global("source_size")
+    .with_raw_global("roughness")
+    .with_raw_global("irradiance")
+    .with_output("color", None)
+    .build()?;
+
+    let irradiance = if filter == CubemapFilter::Irradiance {
+        1.0
+    } else {
+        0.0
+    };
+    effect.update_global("source_size", f32::from(source_size));
+    effect.update_global("irradiance", irradiance);
+    let camera = Camera::from(Projection::perspective(1.0, FRAC_PI_2));
+    for (level, faces) in targets.iter().enumerate() {
+        effect.update_global("roughness", level_roughness(level as u8, levels));
+        for (face, target) in faces.iter().enumerate() {
+            let transform = GlobalTransform(face_transform(face, &Point3::origin()));
+            let args = vertex_args(Some((&camera, &transform)), &UnitQuaternion::identity());
+            effect.retarget(target);
+            effect.update_constant_buffer("VertexArgs", &args.std140(), encoder);
+            effect.add_raw_texture(source.view(), &sampler);
+            effect.draw(&fullscreen_slice(), encoder);
+            effect.clear_textures();
+        }
+    }
+    Ok(texture)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{filtered_size, level_roughness, CubemapFilter};
+
+    #[test]
+    fn filtered_sizes_are_clamped() {
+        assert_eq!(filtered_size(CubemapFilter::Specular, 1024), (256, 5));
+        assert_eq!(filtered_size(CubemapFilter::Specular, 4), (4, 3));
+        assert_eq!(filtered_size(CubemapFilter::Specular, 0), (1, 1));
+        assert_eq!(filtered_size(CubemapFilter::Irradiance, 512), (32, 1));
+        assert_eq!(filtered_size(CubemapFilter::Irradiance, 8), (8, 1));
+    }
+
+    #[test]
+    fn roughness_spans_the_levels() {
+        assert_eq!(level_roughness(0, 5), 0.0);
+        assert_eq!(level_roughness(2, 5), 0.5);
+        assert_eq!(level_roughness(4, 5), 1.0);
+        assert_eq!(level_roughness(0, 1), 0.0);
+    }
+}
@@ -1,5 +1,11 @@
 //! Skybox pass
 
+#[cfg(debug_assertions)]
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
 use amethyst_core::{
     nalgebra as na,
     specs::{Read, ReadStorage},
@@ -8,13 +14,16 @@ use amethyst_core::{
 use amethyst_error::Error;
 
 use crate::{
-    get_camera,
+    cam::{ActiveCamera, Camera, CameraTarget, StereoCamera, Viewport},
+    pass::{
+        sky::sky_projection,
+        util::{build_cube_mesh, get_cameras, set_attribute_buffers, set_view, CubeVertex},
+    },
     pipe::{
         pass::{Pass, PassData},
         DepthMode, Effect, NewEffect,
     },
-    set_vertex_args, ActiveCamera, Camera, Encoder, Factory, Mesh, PosTex, Rgba, Shape,
-    VertexFormat,
+    set_vertex_args, Encoder, Factory, Mesh, Rgba, VertexFormat,
 };
 
 use gfx::pso::buffer::ElemStride;
@@ -32,15 +41,35 @@ pub(crate) struct VertexArgs {
 }
 
 /// Draw a simple gradient skybox
+///
+/// The vertex buffer of the cube is bound once after compiling and kept bound across frames.
+///
+/// Orthographic cameras see the gradient as a backdrop, through the projection `DrawSkyBox`
+/// shows them its sky with.
 #[derive(Clone, Debug)]
 pub struct DrawSkybox {
     mesh: Option<Mesh>,
+    #[cfg(debug_assertions)]
+    buffer_pushes: Arc<AtomicUsize>,
 }
 
 impl DrawSkybox {
     /// Create instance of `DrawSkybox` pass
     pub fn new() -> Self {
-        DrawSkybox { mesh: None }
+        DrawSkybox {
+            mesh: None,
+            #[cfg(debug_assertions)]
+            buffer_pushes: Arc::default(),
+        }
+    }
+
+    /// Returns how often the pass bound the vertex buffer of its cube, in debug builds only.
+    ///
+    /// The count is shared with the clones of the pass, so a clone kept outside of a pipeline
+    /// tells how often the pass inside bound it: once per compiled effect, not once per frame.
+    #[cfg(debug_assertions)]
+    pub fn buffer_pushes(&self) -> usize {
+        self.buffer_pushes.load(Ordering::Relaxed)
     }
 }
 
@@ -48,6 +77,9 @@ impl<'a> PassData<'a> for DrawSkybox {
     type Data = (
         Read<'a, ActiveCamera>,
         ReadStorage<'a, Camera>,
+        ReadStorage<'a, Viewport>,
+        ReadStorage<'a, CameraTarget>,
+        ReadStorage<'a, StereoCamera>,
         ReadStorage<'a, GlobalTransform>,
         Read<'a, SkyboxColor>,
     );
@@ -55,18 +87,17 @@ impl<'a> PassData<'a> for DrawSkybox {
 
 impl Pass for DrawSkybox {
     fn compile(&mut self, mut effect: NewEffect<'_>) -> Result<Effect, Error> {
-        let verts = Shape::Cube.generate_vertices::<Vec<PosTex>>(None);
-        self.mesh = Some(Mesh::build(verts).build(&mut effect.factory)?);
+        // The camera is inside the cube, looking at the front of its inward faces.
+        self.mesh = Some(build_cube_mesh(&mut effect.factory, "DrawSkybox")?);
 
         effect
             .simple(VERT_SRC, FRAG_SRC)
-            .without_back_face_culling()
             .with_raw_constant_buffer(
                 "VertexArgs",
                 std::mem::size_of::<<VertexArgs as Uniform>::Std140>(),
                 1,
             )
-            .with_raw_vertex_buffer(PosTex::ATTRIBUTES, PosTex::size() as ElemStride, 0)
+            .with_raw_vertex_buffer(CubeVertex::ATTRIBUTES, CubeVertex::size() as ElemStride, 0)
             .with_raw_global("camera_position")
             .with_raw_global("zenith_color")
             .with_raw_global("nadir_color")
@@ -79,33 +110,141 @@ impl Pass for DrawSkybox {
         encoder: &mut Encoder,
         effect: &mut Effect,
         mut _factory: Factory,
-        (active, camera, global, skybox_color): <Self as PassData<'a>>::Data,
+        (
+            active,
+            camera,
+            viewports,
+            camera_targets,
+            stereo_cameras,
+            global,
+            skybox_color,
+        ): <Self as PassData<'a>>::Data,
     ) {
-        let camera = get_camera(active, &camera, &global);
-
         let mesh = self
             .mesh
             .as_ref()
             .expect("Pass doesn't seem to be compiled.");
 
-        set_vertex_args(
-            effect,
-            encoder,
-            camera,
-            &GlobalTransform(na::one()),
-            Rgba::WHITE,
-        );
-
-        if let Some(vbuf) = mesh.buffer(PosTex::ATTRIBUTES) {
-            effect.data.vertex_bufs.push(vbuf.clone());
-        } else {
-            effect.clear();
-            return;
+        let bound = !effect.data.vertex_bufs.is_empty();
+        match bind_cube(bound, || {
+            set_attribute_buffers(effect, mesh, &[CubeVertex::ATTRIBUTES])
+        }) {
+            CubeBinding::Missing => {
+                effect.clear();
+                return;
+            }
+            CubeBinding::Pushed => {
+                #[cfg(debug_assertions)]
+                self.buffer_pushes.fetch_add(1, Ordering::Relaxed);
+            }
+            CubeBinding::Bound => {}
         }
 
         effect.update_global("zenith_color", Into::<[f32; 3]>::into(skybox_color.zenith));
         effect.update_global("nadir_color", Into::<[f32; 3]>::into(skybox_color.nadir));
-        effect.draw(mesh.slice(), encoder);
-        effect.clear();
+
+        let cameras = get_cameras(
+            active,
+            &camera,
+            &viewports,
+            &camera_targets,
+            &stereo_cameras,
+            &global,
+            effect.target_name(),
+        );
+        for view in &cameras {
+            let view = set_view(effect, view).map_projection(sky_projection);
+
+            set_vertex_args(
+                effect,
+                encoder,
+                view.get(),
+                &GlobalTransform(na::one()),
+                Rgba::WHITE,
+            );
+            effect.draw(mesh.slice(), encoder);
+        }
+    }
+}
+
+/// What `bind_cube` did with the vertex buffer of the cube.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CubeBinding {
+    /// It was still bound from an earlier frame.
+    Bound,
+    /// It was pushed to the effect.
+    Pushed,
+    /// The mesh lacks the attributes of the cube, nothing is bound.
+    Missing,
+}
+
+/// Pushes the buffer of the cube with `push` unless it is still `bound`.
+///
+/// The buffer stays bound until the effect is recompiled, which empties its vertex buffers.
+fn bind_cube<F>(bound: bool, push: F) -> CubeBinding
+where
+    F: FnOnce() -> bool,
+{
+    if bound {
+        CubeBinding::Bound
+    } else if push() {
+        CubeBinding::Pushed
+    } else {
+        CubeBinding::Missing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(debug_assertions)]
+    use amethyst_core::specs::prelude::World;
+
+    #[cfg(debug_assertions)]
+    use crate::{
+        pipe::{Pipeline, Stage},
+        HeadlessRenderer,
+    };
+
+    #[cfg(debug_assertions)]
+    use super::DrawSkybox;
+    use super::{bind_cube, CubeBinding};
+
+    #[test]
+    fn the_cube_is_pushed_until_it_is_bound() {
+        let mut bound = false;
+        let mut pushes = 0;
+        for _ in 0..3 {
+            let binding = bind_cube(bound, || {
+                pushes += 1;
+                true
+            });
+            bound = binding != CubeBinding::Missing;
+        }
+        assert_eq!(pushes, 1);
+        assert_eq!(bind_cube(true, || unreachable!()), CubeBinding::Bound);
+    }
+
+    #[test]
+    fn a_cube_without_its_attributes_is_retried() {
+        assert_eq!(bind_cube(false, || false), CubeBinding::Missing);
+        assert_eq!(bind_cube(false, || true), CubeBinding::Pushed);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[ignore] // Needs an OpenGL context, see `HeadlessRenderer`.
+    fn the_cube_is_bound_once() {
+        let pass = DrawSkybox::new();
+        let pushes = pass.clone();
+        let mut renderer = HeadlessRenderer::new((4, 4)).unwrap();
+        let mut pipe = renderer
+            .create_pipe(Pipeline::build().with_stage(Stage::with_backbuffer().with_pass(pass)))
+            .unwrap();
+
+        let mut world = World::new();
+        for _ in 0..3 {
+            renderer.draw_world(&mut pipe, &mut world.res);
+        }
+        assert_eq!(pushes.buffer_pushes(), 1);
     }
 }
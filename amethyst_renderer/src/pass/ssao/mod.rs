@@ -0,0 +1,505 @@
+//! Screen-space ambient occlusion pass.
+
+use std::mem;
+
+use derivative::Derivative;
+use gfx::memory::Typed;
+use log::error;
+
+use amethyst_core::{
+    nalgebra::Matrix4,
+    specs::prelude::{Read, ReadStorage},
+    transform::GlobalTransform,
+};
+use amethyst_error::{Error, ResultExt};
+
+use crate::{
+    cam::{ActiveCamera, Camera},
+    error,
+    pass::util::{fullscreen_slice, get_camera, target_view},
+    pipe::{
+        pass::{Pass, PassData},
+        Effect, EffectBuilder, NewEffect, Target, TargetBuilder, Targets,
+    },
+    tex::{FilterMethod, SamplerInfo, Texture, TextureBuilder, WrapMode},
+    types::{Encoder, Factory, Sampler, ShaderResourceView},
+};
+
+static VERT_SRC: &[u8] = include_bytes!("../shaders/vertex/fullscreen.glsl");
+static FRAG_SRC: &[u8] = include_bytes!("../shaders/fragment/ssao.glsl");
+static BLUR_SRC: &[u8] = include_bytes!("../shaders/fragment/ssao_blur.glsl");
+
+/// Largest number of samples per pixel, the size of the kernel buffer in `ssao.glsl`.
+const MAX_SAMPLES: usize = 64;
+
+/// Width and height of the noise texture rotating the kernel.
+const NOISE_SIZE: usize = 4;
+
+/// Computes how much ambient light reaches each pixel of a rendered scene
+///
+/// The pass reads the depth buffer of the target named in `new`, so the scene has to be drawn
+/// into a target with a depth buffer before it runs, for example by a cheap `DrawFlat` stage.
+/// The occlusion, 1.0 for unoccluded pixels down to 0.0, is drawn into the stage's target, which
+/// lit passes built `with_ssao` multiply their ambient term with.
+///
+/// ```ignore
+/// Pipeline::build()
+///     .with_target(Target::named("depth").with_depth_buf(true))
+///     .with_target(Target::named("ssao"))
+///     .with_stage(
+///         Stage::with_target("depth")
+///             .clear_target([0.0; 4], 1.0)
+///             .with_pass(DrawFlat::<PosNormTex>::new()),
+///     )
+///     .with_stage(Stage::with_target("ssao").with_pass(DrawSsao::new("depth")))
+///     .with_stage(
+///         Stage::with_backbuffer()
+///             .clear_target([0.0, 0.0, 0.0, 1.0], 1.0)
+///             .with_pass(DrawShaded::<PosNormTex>::new().with_ssao("ssao")),
+///     )
+/// ```
+#[derive(Derivative, Clone)]
+#[derivative(Debug)]
+pub struct DrawSsao {
+    input: String,
+    normals: Option<String>,
+    radius: f32,
+    bias: f32,
+    samples: usize,
+    blur: bool,
+    #[derivative(Debug = "ignore")]
+    state: Option<SsaoState>,
+}
+
+#[derive(Clone)]
+struct SsaoState {
+    sources: SsaoSources,
+    /// Effect drawing the unfiltered occlusion and the target it is drawn into, when blurring.
+    blur: Option<(Effect, Target)>,
+    size: (u32, u32),
+    resized: bool,
+}
+
+#[derive(Clone)]
+struct SsaoSources {
+    depth: ShaderResourceView<f32>,
+    normals: Option<ShaderResourceView<[f32; 4]>>,
+    noise: Texture,
+    sampler: Sampler,
+    kernel: Vec<[f32; 4]>,
+}
+
+impl DrawSsao {
+    /// Create instance of `DrawSsao` pass reading depth from the target named `input`
+    pub fn new<N: Into<String>>(input: N) -> Self {
+        DrawSsao {
+            input: input.into(),
+            normals: None,
+            radius: 0.5,
+            bias: 0.025,
+            samples: 16,
+            blur: true,
+            state: None,
+        }
+    }
+
+    /// Read view space normals, encoded as `n * 0.5 + 0.5`, from the first color buffer of the
+    /// target named `normals` instead of deriving them from depth.
+    ///
+    /// Derived normals are flat per triangle and noisy along edges.
+    pub fn with_normals<N: Into<String>>(mut self, normals: N) -> Self {
+        self.normals = Some(normals.into());
+        self
+    }
+
+    /// Set how far around a pixel occluders are searched for in world units, 0.5 by default.
+    pub fn with_radius(mut self, radius: f32) -> Self {
+        self.radius = radius;
+        self
+    }
+
+    /// Set the depth difference below which geometry doesn't occlude, 0.025 by default.
+    ///
+    /// Raise it if flat surfaces show a pattern of dark speckles.
+    pub fn with_bias(mut self, bias: f32) -> Self {
+        self.bias = bias;
+        self
+    }
+
+    /// Set the number of samples per pixel, 16 by default and at most 64.
+    pub fn with_samples(mut self, samples: usize) -> Self {
+        self.samples = samples.max(1).min(MAX_SAMPLES);
+        self
+    }
+
+    /// Blur the occlusion to hide the noise pattern, enabled by default.
+    pub fn with_blur(mut self, blur: bool) -> Self {
+        self.blur = blur;
+        self
+    }
+}
+
+impl<'a> PassData<'a> for DrawSsao {
+    type Data = (
+        Read<'a, ActiveCamera>,
+        ReadStorage<'a, Camera>,
+        ReadStorage<'a, GlobalTransform>,
+    );
+}
+
+impl Pass for DrawSsao {
+    fn compile(&mut self, mut effect: NewEffect<'_>) -> Result<Effect, Error> {
+        use gfx::Factory;
+
+        let input = effect
+            .target(&self.input)
+            .ok_or_else(|| error::Error::NoSuchTarget(self.input.clone()))?;
+        let depth = input
+            .depth_buf()
+            .and_then(|db| db.as_input.clone())
+            .ok_or_else(|| error::Error::TargetNotReadable(self.input.clone()))?;
+        let size = input.size();
+        let normals = match self.normals {
+            Some(ref name) => {
+                let target = effect
+                    .target(name)
+                    .ok_or_else(|| error::Error::NoSuchTarget(name.clone()))?;
+                Some(
+                    target_view(target)
+                        .ok_or_else(|| error::Error::TargetNotReadable(name.clone()))?,
+                )
+            }
+            None => None,
+        };
+        let sampler = effect
+            .factory
+            .create_sampler(SamplerInfo::new(FilterMethod::Scale, WrapMode::Clamp));
+        let noise = TextureBuilder::new(noise())
+            .with_size(NOISE_SIZE as u16, NOISE_SIZE as u16)
+            .with_sampler(SamplerInfo::new(FilterMethod::Scale, WrapMode::Tile))
+            .build(effect.factory)?;
+        let sources = SsaoSources {
+            depth,
+            normals,
+            noise,
+            sampler,
+            kernel: kernel(self.samples),
+        };
+
+        let blur = if self.blur {
+            let raw = raw_target(effect.factory, size)?;
+            let mut builder = effect.simple_into(&raw, VERT_SRC, FRAG_SRC);
+            setup_occlusion(&mut builder);
+            let occlusion = builder.build()?;
+            Some((occlusion, raw))
+        } else {
+            None
+        };
+        self.state = Some(SsaoState {
+            sources,
+            blur,
+            size,
+            resized: false,
+        });
+
+        if self.blur {
+            effect
+                .simple(VERT_SRC, BLUR_SRC)
+                .with_texture("source")
+                .with_raw_global("texel_size")
+                .with_output("color", None)
+                .build()
+        } else {
+            let mut builder = effect.simple(VERT_SRC, FRAG_SRC);
+            setup_occlusion(&mut builder);
+            builder.build()
+        }
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        mut factory: Factory,
+        (active, camera, global): <Self as PassData<'a>>::Data,
+    ) {
+        let state = match self.state {
+            Some(ref mut state) => state,
+            None => return,
+        };
+
+        if state.resized {
+            if let Some((ref mut occlusion, ref mut raw)) = state.blur {
+                match raw_target(&mut factory, state.size) {
+                    Ok(target) => {
+                        *raw = target;
+                        occlusion.retarget(raw);
+                    }
+                    Err(err) => {
+                        error!("Failed to resize the SSAO target: {}", err);
+                        return;
+                    }
+                }
+            }
+            state.resized = false;
+        }
+
+        let proj = get_camera(active, &camera, &global)
+            .map(|(camera, _)| camera.proj)
+            .unwrap_or_else(Matrix4::identity);
+        let args = OcclusionArgs {
+            proj: proj.into(),
+            inv_proj: proj.try_inverse().unwrap_or_else(Matrix4::identity).into(),
+            radius: self.radius,
+            bias: self.bias,
+            sample_count: state.sources.kernel.len() as i32,
+            noise_scale: [
+                state.size.0 as f32 / NOISE_SIZE as f32,
+                state.size.1 as f32 / NOISE_SIZE as f32,
+            ],
+        };
+
+        match state.blur {
+            Some((ref mut occlusion, ref raw)) => {
+                draw_occlusion(occlusion, encoder, &state.sources, &args);
+
+                let source = match raw.color_buf(0).and_then(|cb| cb.as_input.as_ref()) {
+                    Some(source) => source.raw().clone(),
+                    None => return,
+                };
+                effect.data.textures.push(source);
+                effect.data.samplers.push(state.sources.sampler.clone());
+                effect.update_global(
+                    "texel_size",
+                    [1.0 / state.size.0 as f32, 1.0 / state.size.1 as f32],
+                );
+                effect.draw(&fullscreen_slice(), encoder);
+                effect.clear();
+            }
+            None => draw_occlusion(effect, encoder, &state.sources, &args),
+        }
+    }
+
+    fn new_targets(&mut self, targets: &Targets) {
+        let state = match self.state {
+            Some(ref mut state) => state,
+            None => return,
+        };
+        let input = match targets.get(&self.input) {
+            Some(input) => input,
+            None => {
+                error!("SSAO input target {:?} not found!", self.input);
+                return;
+            }
+        };
+        if let Some(depth) = input.depth_buf().and_then(|db| db.as_input.clone()) {
+            state.sources.depth = depth;
+        }
+        if let Some(ref name) = self.normals {
+            if let Some(normals) = targets.get(name).and_then(target_view) {
+                state.sources.normals = Some(normals);
+            }
+        }
+        if input.size() != state.size {
+            state.size = input.size();
+            state.resized = true;
+        }
+    }
+}
+
+/// Per frame uniforms of `ssao.glsl`.
+struct OcclusionArgs {
+    proj: [[f32; 4]; 4],
+    inv_proj: [[f32; 4]; 4],
+    radius: f32,
+    bias: f32,
+    sample_count: i32,
+    noise_scale: [f32; 2],
+}
+
+fn setup_occlusion(builder: &mut EffectBuilder<'_>) {
+    builder
+        .with_raw_constant_buffer("SsaoKernel", mem::size_of::<[f32; 4]>(), MAX_SAMPLES)
+        .with_texture("depth")
+        .with_texture("noise")
+        .with_texture("normals")
+        .with_raw_global("proj")
+        .with_raw_global("inv_proj")
+        .with_raw_global("radius")
+        .with_raw_global("bias")
+        .with_raw_global("sample_count")
+        .with_raw_global("noise_scale")
+        .with_raw_global("use_normals")
+        .with_output("color", None);
+}
+
+fn draw_occlusion(
+    effect: &mut Effect,
+    encoder: &mut Encoder,
+    sources: &SsaoSources,
+    args: &OcclusionArgs,
+) {
+    effect.update_buffer("SsaoKernel", &sources.kernel[..], encoder);
+
+    effect.data.textures.push(sources.depth.raw().clone());
+    effect.data.samplers.push(sources.sampler.clone());
+    effect.data.textures.push(sources.noise.view().clone());
+    effect.data.samplers.push(sources.noise.sampler().clone());
+    // Without a normal buffer the depth is bound in its place, so the sampler stays complete.
+    match sources.normals {
+        Some(ref normals) => effect.data.textures.push(normals.raw().clone()),
+        None => effect.data.textures.push(sources.depth.raw().clone()),
+    }
+    effect.data.samplers.push(sources.sampler.clone());
+
+    effect.update_global("proj", args.proj);
+    effect.update_global("inv_proj", args.inv_proj);
+    effect.update_global("radius", args.radius);
+    effect.update_global("bias", args.bias);
+    effect.update_global("sample_count", args.sample_count);
+    effect.update_global("noise_scale", args.noise_scale);
+    let use_normals = if sources.normals.is_some() { 1.0 } else { 0.0 };
+    effect.update_global("use_normals", use_normals);
+    effect.draw(&fullscreen_slice(), encoder);
+    effect.clear();
+}
+
+/// Creates the full resolution buffer the unfiltered occlusion is drawn into.
+fn raw_target(factory: &mut Factory, size: (u32, u32)) -> Result<Target, Error> {
+    let (_, target) = TargetBuilder::new("ssao_raw")
+        .build(factory, size)
+        .with_context(|_| error::Error::CreateTextureError)?;
+    Ok(target)
+}
+
+/// Small deterministic generator, so the kernel and noise are the same on every run.
+struct XorShift(u32);
+
+impl XorShift {
+    /// Returns a value in `[0, 1)`.
+    fn next(&mut self) -> f32 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+        (x >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
+/// Returns `count` sample offsets inside the unit hemisphere around +z, placed more densely
+/// close to the center, where occluders matter most.
+fn kernel(count: usize) -> Vec<[f32; 4]> {
+    let mut rng = XorShift(0x9E37_79B9);
+    let mut kernel = Vec::with_capacity(count);
+    while kernel.len() < count {
+        let v = [rng.next() * 2.0 - 1.0, rng.next() * 2.0 - 1.0, rng.next()];
+        let length = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+        if length > 1.0 || length < 1.0e-2 {
+            continue;
+        }
+        let t = kernel.len() as f32 / count as f32;
+        let scale = 0.1 + 0.9 * t * t;
+        kernel.push([v[0] * scale, v[1] * scale, v[2] * scale, 0.0]);
+    }
+    kernel
+}
+
+/// Returns the RGBA8 texels of the noise texture, random rotations around +z encoded as
+/// `v * 0.5 + 0.5`.
+fn noise() -> Vec<u8> {
+    let mut rng = XorShift(0x2545_F491);
+    let mut noise = vec![0; NOISE_SIZE * NOISE_SIZE * 4];
+    for texel in noise.chunks_mut(4) {
+        let angle = rng.next() * 2.0 * std::f32::consts::PI;
+        texel[0] = ((angle.cos() * 0.5 + 0.5) * 255.0) as u8;
+        texel[1] = ((angle.sin() * 0.5 + 0.5) * 255.0) as u8;
+        texel[2] = 0;
+        texel[3] = 255;
+    }
+    noise
+}
+
+/// Ambient occlusion texture sampled by a lit pass.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct OcclusionInput {
+    target: String,
+    map: Option<(ShaderResourceView<[f32; 4]>, Sampler)>,
+}
+
+impl OcclusionInput {
+    pub(crate) fn new(target: String) -> Self {
+        OcclusionInput { target, map: None }
+    }
+
+    /// Looks up the color buffer `DrawSsao` draws into.
+    pub(crate) fn compile(&mut self, effect: &mut NewEffect<'_>) -> Result<(), Error> {
+        use gfx::Factory;
+
+        let target = effect
+            .target(&self.target)
+            .ok_or_else(|| error::Error::NoSuchTarget(self.target.clone()))?;
+        let view = target_view(target)
+            .ok_or_else(|| error::Error::TargetNotReadable(self.target.clone()))?;
+        let sampler = effect
+            .factory
+            .create_sampler(SamplerInfo::new(FilterMethod::Bilinear, WrapMode::Clamp));
+        self.map = Some((view, sampler));
+        Ok(())
+    }
+
+    /// Registers the occlusion texture. Call this before `setup_textures`, so it can be added
+    /// ahead of the material textures.
+    pub(crate) fn setup(builder: &mut EffectBuilder<'_>) {
+        builder.with_texture("ssao_map").with_raw_global("use_ssao");
+    }
+
+    pub(crate) fn new_targets(&mut self, targets: &Targets) {
+        let view = targets.get(&self.target).and_then(target_view);
+        if let (Some(view), Some(map)) = (view, self.map.as_mut()) {
+            map.0 = view;
+        }
+    }
+}
+
+/// Adds the occlusion texture ahead of the next draw, if the pass has SSAO enabled.
+pub(crate) fn bind_occlusion(effect: &mut Effect, occlusion: &Option<OcclusionInput>) {
+    if let Some((ref view, ref sampler)) = occlusion.as_ref().and_then(|o| o.map.as_ref()) {
+        effect.data.textures.push(view.raw().clone());
+        effect.data.samplers.push(sampler.clone());
+        effect.update_global("use_ssao", 1.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{kernel, noise, NOISE_SIZE};
+
+    #[test]
+    fn kernel_lies_in_hemisphere() {
+        let samples = kernel(64);
+        assert_eq!(samples.len(), 64);
+        for sample in &samples {
+            let length =
+                (sample[0] * sample[0] + sample[1] * sample[1] + sample[2] * sample[2]).sqrt();
+            assert!(length <= 1.0, "{:?} is outside the unit sphere", sample);
+            assert!(sample[2] >= 0.0, "{:?} is below the surface", sample);
+        }
+    }
+
+    #[test]
+    fn noise_encodes_rotations_around_the_normal() {
+        let noise = noise();
+        assert_eq!(noise.len(), NOISE_SIZE * NOISE_SIZE * 4);
+        for texel in noise.chunks(4) {
+            let x = f32::from(texel[0]) / 255.0 * 2.0 - 1.0;
+            let y = f32::from(texel[1]) / 255.0 * 2.0 - 1.0;
+            assert!(
+                ((x * x + y * y).sqrt() - 1.0).abs() < 0.02,
+                "{:?} is not a unit vector",
+                texel
+            );
+            assert_eq!(texel[2..], [0, 255]);
+        }
+    }
+}
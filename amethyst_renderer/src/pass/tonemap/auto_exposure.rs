@@ -0,0 +1,277 @@
+//! Eye adaptation for `DrawTonemap`, measuring the brightness of the scene on the GPU.
+
+use gfx::memory::Typed;
+use serde::{Deserialize, Serialize};
+
+use amethyst_error::{Error, ResultExt};
+
+use crate::{
+    error,
+    pass::util::{fullscreen_slice, target_view},
+    pipe::{Effect, NewEffect, Target, TargetBuilder},
+    tex::{FilterMethod, SamplerInfo, WrapMode},
+    types::{Encoder, Factory, RawShaderResourceView, Sampler},
+};
+
+static VERT_SRC: &[u8] = include_bytes!("../shaders/vertex/fullscreen.glsl");
+static LUMINANCE_SRC: &[u8] = include_bytes!("../shaders/fragment/exposure_luminance.glsl");
+static REDUCE_SRC: &[u8] = include_bytes!("../shaders/fragment/exposure_reduce.glsl");
+static ADAPT_SRC: &[u8] = include_bytes!("../shaders/fragment/exposure_adapt.glsl");
+
+/// Sizes of the square targets the exposure of the scene is averaged down in, each a quarter of
+/// the one before.
+const CHAIN_SIZES: [u32; 5] = [256, 64, 16, 4, 1];
+
+/// Settings of the auto exposure of `DrawTonemap`, which adapts to the brightness of the scene
+/// like an eye does
+///
+/// Each frame the scene is measured in stops above middle gray: the base-two logarithm of each
+/// pixel's luminance divided by `0.18` is averaged over the screen, and clamped to the range
+/// between `min_ev` and `max_ev`. The adapted exposure moves towards the measured one, and the
+/// scene is multiplied with `2^-EV` of it before tonemapping, on top of the exposure of the pass
+/// and the camera's `CameraExposure`. A scene averaging middle gray thus keeps its brightness,
+/// one averaging a stop brighter is halved.
+///
+/// Adaptation runs in fixed steps of `Time::fixed_seconds`, each moving `1 - e^(-speed * step)`
+/// of the remaining way, with the time left over carried to the next frame. The adapted exposure
+/// therefore only depends on the time passed, not on how it is split into frames. The first
+/// frame starts out fully adapted.
+///
+/// The scene target has to be built `with_hdr`. Colors in a normalized target stop at 1.0, about
+/// two and a half stops above middle gray, so a bright scene would never be measured as such.
+/// The exposure is averaged and adapted in float targets too, which keeps the small steps of a
+/// slow adaptation from being rounded away.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct AutoExposure {
+    /// The lowest exposure value adapted to, brightening dark scenes by at most `-min_ev` stops.
+    /// `-4.0` by default.
+    pub min_ev: f32,
+    /// The highest exposure value adapted to, darkening bright scenes by at most `max_ev` stops.
+    /// `4.0` by default.
+    pub max_ev: f32,
+    /// How fast the exposure adapts, per second, `1.5` by default. Higher is faster.
+    pub speed: f32,
+}
+
+impl Default for AutoExposure {
+    fn default() -> Self {
+        AutoExposure {
+            min_ev: -4.0,
+            max_ev: 4.0,
+            speed: 1.5,
+        }
+    }
+}
+
+impl AutoExposure {
+    /// Sets the range of exposure values adapted to.
+    pub fn with_ev_range(mut self, min_ev: f32, max_ev: f32) -> Self {
+        self.min_ev = min_ev;
+        self.max_ev = max_ev;
+        self
+    }
+
+    /// Sets how fast the exposure adapts, per second.
+    pub fn with_speed(mut self, speed: f32) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// Returns the range of exposure values, widened to at least a stop.
+    pub(super) fn ev_range(&self) -> [f32; 2] {
+        [self.min_ev, self.max_ev.max(self.min_ev + 1.0)]
+    }
+}
+
+/// How far to move towards the measured exposure after `steps` fixed steps of `step` seconds.
+fn adaptation_factor(speed: f32, step: f32, steps: u32) -> f32 {
+    1.0 - (-speed.max(0.0) * step * steps as f32).exp()
+}
+
+/// Accumulates frame times into fixed steps.
+#[derive(Clone, Debug, Default)]
+struct StepClock {
+    accumulated: f32,
+}
+
+impl StepClock {
+    /// Returns the number of whole steps of `step` seconds that passed with `delta`.
+    fn advance(&mut self, delta: f32, step: f32) -> u32 {
+        if step <= 0.0 {
+            return 0;
+        }
+        self.accumulated += delta.max(0.0);
+        let steps = (self.accumulated / step).floor();
+        self.accumulated -= steps * step;
+        steps as u32
+    }
+}
+
+/// The GPU side of the auto exposure.
+#[derive(Clone)]
+pub(super) struct Adaptation {
+    luminance: Effect,
+    reduce: Effect,
+    adapt: Effect,
+    chain: Vec<Target>,
+    adapted: [Target; 2],
+    current: usize,
+    primed: bool,
+    clock: StepClock,
+    sampler: Sampler,
+}
+
+impl Adaptation {
+    /// Builds the targets and effects of the auto exposure.
+    pub(super) fn new(effect: &mut NewEffect<'_>) -> Result<Self, Error> {
+        use gfx::Factory;
+
+        let chain = CHAIN_SIZES
+            .iter()
+            .map(|&size| exposure_target(effect.factory, "exposure", size))
+            .collect::<Result<Vec<_>, _>>()?;
+        let adapted = [
+            exposure_target(effect.factory, "exposure_adapted", 1)?,
+            exposure_target(effect.factory, "exposure_adapted", 1)?,
+        ];
+
+        let luminance = effect
+            .simple_into(&chain[0], VERT_SRC, LUMINANCE_SRC)
+            .with_texture("source")
+            .with_raw_global("offset")
+            .with_raw_global("ev_range")
+            .with_output("color", None)
+            .build()?;
+        let reduce = effect
+            .simple_into(&chain[1], VERT_SRC, REDUCE_SRC)
+            .with_texture("source")
+            .with_raw_global("offset")
+            .with_output("color", None)
+            .build()?;
+        let adapt = effect
+            .simple_into(&adapted[0], VERT_SRC, ADAPT_SRC)
+            .with_texture("measured")
+            .with_texture("previous")
+            .with_raw_global("factor")
+            .with_output("color", None)
+            .build()?;
+        // The reductions rely on bilinear filtering to average texels.
+        let sampler = effect
+            .factory
+            .create_sampler(SamplerInfo::new(FilterMethod::Bilinear, WrapMode::Clamp));
+
+        Ok(Adaptation {
+            luminance,
+            reduce,
+            adapt,
+            chain,
+            adapted,
+            current: 0,
+            primed: false,
+            clock: StepClock::default(),
+            sampler,
+        })
+    }
+
+    /// Measures the exposure of `scene` and adapts to it, returning the view of the target
+    /// holding the adapted exposure.
+    pub(super) fn apply(
+        &mut self,
+        encoder: &mut Encoder,
+        scene: &RawShaderResourceView,
+        settings: &AutoExposure,
+        (delta, step): (f32, f32),
+    ) -> Option<RawShaderResourceView> {
+        let steps = self.clock.advance(delta, step);
+        let sampler = &self.sampler;
+
+        let first = 0.25 / CHAIN_SIZES[0] as f32;
+        self.luminance.add_raw_texture(scene, sampler);
+        self.luminance.update_global("offset", [first, first]);
+        self.luminance
+            .update_global("ev_range", settings.ev_range());
+        self.luminance.draw(&fullscreen_slice(), encoder);
+        self.luminance.clear();
+
+        for pair in self.chain.windows(2) {
+            let source = target_view(&pair[0])?;
+            let texel = 1.0 / pair[0].size().0 as f32;
+            self.reduce.retarget(&pair[1]);
+            self.reduce.add_raw_texture(source.raw(), sampler);
+            self.reduce.update_global("offset", [texel, texel]);
+            self.reduce.draw(&fullscreen_slice(), encoder);
+            self.reduce.clear();
+        }
+
+        let measured = target_view(self.chain.last()?)?;
+        let previous = target_view(&self.adapted[self.current])?;
+        let factor = if self.primed {
+            adaptation_factor(settings.speed, step, steps)
+        } else {
+            1.0
+        };
+        let next = 1 - self.current;
+        self.adapt.retarget(&self.adapted[next]);
+        self.adapt.add_raw_texture(measured.raw(), sampler);
+        self.adapt.add_raw_texture(previous.raw(), sampler);
+        self.adapt.update_global("factor", factor);
+        self.adapt.draw(&fullscreen_slice(), encoder);
+        self.adapt.clear();
+        self.current = next;
+        self.primed = true;
+
+        target_view(&self.adapted[next]).map(|view| view.raw().clone())
+    }
+}
+
+/// Creates a square float target of `size` pixels for measuring the exposure.
+fn exposure_target(factory: &mut Factory, name: &str, size: u32) -> Result<Target, Error> {
+    TargetBuilder::new(name)
+        .with_hdr()
+        .build(factory, (size, size))
+        .map(|(_, target)| target)
+        .with_context(|_| error::Error::CreateTextureError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{adaptation_factor, StepClock};
+
+    /// Adapts like the shader does.
+    fn adapt(last: f32, target: f32, factor: f32) -> f32 {
+        last + (target - last) * factor
+    }
+
+    #[test]
+    fn adaptation_only_depends_on_the_time_passed() {
+        // Binary fractions of a step, so no time is lost to rounding.
+        let (speed, step) = (1.5, 1.0 / 64.0);
+        let run = |deltas: &[f32]| {
+            let mut clock = StepClock::default();
+            deltas.iter().fold(0.0, |exposure, &delta| {
+                let steps = clock.advance(delta, step);
+                adapt(exposure, 1.0, adaptation_factor(speed, step, steps))
+            })
+        };
+
+        let steady = run(&[step; 12]);
+        let uneven = run(&[step * 0.5, step * 2.5, step * 0.25, step * 4.75, step * 4.0]);
+        let stalled = run(&[step * 12.0]);
+        assert!((steady - uneven).abs() < 1e-5, "{} != {}", steady, uneven);
+        assert!((steady - stalled).abs() < 1e-5, "{} != {}", steady, stalled);
+
+        let expected = 1.0 - (-speed * step * 12.0f32).exp();
+        assert!((steady - expected).abs() < 1e-5);
+    }
+
+    #[test]
+    fn partial_steps_carry_over() {
+        let mut clock = StepClock::default();
+        assert_eq!(clock.advance(0.25, 1.0), 0);
+        assert_eq!(clock.advance(0.875, 1.0), 1);
+        assert_eq!(clock.advance(0.875, 1.0), 1);
+        assert_eq!(clock.advance(2.5, 1.0), 2);
+        assert_eq!(clock.advance(0.0, 0.0), 0);
+    }
+}
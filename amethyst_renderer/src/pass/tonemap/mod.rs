@@ -0,0 +1,341 @@
+//! Tonemapping post-processing pass.
+
+use derivative::Derivative;
+use gfx::memory::Typed;
+use log::error;
+
+use amethyst_core::{
+    specs::prelude::{Entity, Join, Read, ReadStorage},
+    Time,
+};
+use amethyst_error::Error;
+
+use crate::{
+    cam::{ActiveCamera, Camera, CameraExposure},
+    error,
+    pass::util::{fullscreen_slice, target_view},
+    pipe::{
+        pass::{Pass, PassData},
+        Effect, NewEffect, Targets,
+    },
+    tex::{FilterMethod, SamplerInfo, WrapMode},
+    types::{Encoder, Factory, Sampler, ShaderResourceView},
+};
+
+pub use self::auto_exposure::AutoExposure;
+
+use self::auto_exposure::Adaptation;
+
+mod auto_exposure;
+
+static VERT_SRC: &[u8] = include_bytes!("../shaders/vertex/fullscreen.glsl");
+static FRAG_SRC: &[u8] = include_bytes!("../shaders/fragment/tonemap.glsl");
+
+/// Curve used by `DrawTonemap` to map HDR colors into `[0, 1]`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TonemapOperator {
+    /// `x / (1 + x)`, which keeps colors close to the input but looks flat in bright areas.
+    Reinhard,
+    /// An approximation of the ACES filmic curve, with more contrast and a softer roll-off.
+    AcesFilmic,
+}
+
+impl Default for TonemapOperator {
+    fn default() -> Self {
+        TonemapOperator::AcesFilmic
+    }
+}
+
+impl TonemapOperator {
+    /// Applies the operator to a linear color after multiplying it with `exposure`.
+    ///
+    /// This matches what the shader of `DrawTonemap` does per pixel.
+    pub fn apply(self, color: [f32; 3], exposure: f32) -> [f32; 3] {
+        let map = |x: f32| {
+            let x = x * exposure;
+            match self {
+                TonemapOperator::Reinhard => x / (1.0 + x),
+                TonemapOperator::AcesFilmic => {
+                    let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+                    ((x * (a * x + b)) / (x * (c * x + d) + e)).max(0.0).min(1.0)
+                }
+            }
+        };
+        [map(color[0]), map(color[1]), map(color[2])]
+    }
+
+    fn shader_index(self) -> i32 {
+        match self {
+            TonemapOperator::Reinhard => 0,
+            TonemapOperator::AcesFilmic => 1,
+        }
+    }
+}
+
+/// Maps the colors of an HDR scene target into the displayable range
+///
+/// The scene has to be rendered into a separate pipeline target first, whose name is given to
+/// `new`. Build it `with_hdr`, or the colors are clamped to `[0, 1]` before they get here. Add
+/// this pass after the scene passes and before the UI, which is already in display range.
+///
+/// ```ignore
+/// Pipeline::build()
+///     .with_target(Target::named("scene").with_depth_buf(true).with_hdr())
+///     .with_stage(
+///         Stage::with_target("scene")
+///             .clear_target([0.0, 0.0, 0.0, 1.0], 1.0)
+///             .with_pass(DrawPbm::<PosNormTangTex>::new()),
+///     )
+///     .with_stage(
+///         Stage::with_backbuffer()
+///             .with_pass(DrawTonemap::new("scene").with_exposure(1.5))
+///             .with_pass(DrawUi::new()),
+///     )
+/// ```
+///
+/// A `CameraExposure` on the active camera, or on the first camera if none is active, scales the
+/// colors further, by the exposure of its photographic settings, and so does the eye adaptation
+/// enabled with `with_auto_exposure`.
+///
+/// The pass expects linear colors and writes the mapped ones unchanged. A `DrawSkyBox` in the
+/// scene stage writes linear colors into the named target by default, so its sky goes through
+/// the same exposure and curve as the rest of the scene.
+#[derive(Derivative, Clone)]
+#[derivative(Debug)]
+pub struct DrawTonemap {
+    input: String,
+    operator: TonemapOperator,
+    exposure: f32,
+    auto_exposure: Option<AutoExposure>,
+    #[derivative(Debug = "ignore")]
+    source: Option<(ShaderResourceView<[f32; 4]>, Sampler)>,
+    #[derivative(Debug = "ignore")]
+    adaptation: Option<Adaptation>,
+}
+
+impl DrawTonemap {
+    /// Create instance of `DrawTonemap` pass reading the scene from the target named `input`
+    pub fn new<N: Into<String>>(input: N) -> Self {
+        DrawTonemap {
+            input: input.into(),
+            operator: TonemapOperator::default(),
+            exposure: 1.0,
+            auto_exposure: None,
+            source: None,
+            adaptation: None,
+        }
+    }
+
+    /// Set the tonemapping curve, `TonemapOperator::AcesFilmic` by default.
+    pub fn with_operator(mut self, operator: TonemapOperator) -> Self {
+        self.operator = operator;
+        self
+    }
+
+    /// Set the factor the scene colors are multiplied with before tonemapping, 1.0 by default.
+    ///
+    /// This multiplies with the exposure of the camera's `CameraExposure`.
+    pub fn with_exposure(mut self, exposure: f32) -> Self {
+        self.exposure = exposure;
+        self
+    }
+
+    /// Adapt the exposure to the brightness of the scene over time, see `AutoExposure`.
+    pub fn with_auto_exposure(mut self, settings: AutoExposure) -> Self {
+        self.auto_exposure = Some(settings);
+        self
+    }
+}
+
+impl<'a> PassData<'a> for DrawTonemap {
+    type Data = (
+        Read<'a, ActiveCamera>,
+        ReadStorage<'a, Camera>,
+        ReadStorage<'a, CameraExposure>,
+        Read<'a, Time>,
+    );
+}
+
+impl Pass for DrawTonemap {
+    fn compile(&mut self, mut effect: NewEffect<'_>) -> Result<Effect, Error> {
+        use gfx::Factory;
+
+        let input = effect
+            .target(&self.input)
+            .ok_or_else(|| error::Error::NoSuchTarget(self.input.clone()))?;
+        let view =
+            target_view(input).ok_or_else(|| error::Error::TargetNotReadable(self.input.clone()))?;
+        let sampler = effect
+            .factory
+            .create_sampler(SamplerInfo::new(FilterMethod::Scale, WrapMode::Clamp));
+        self.source = Some((view, sampler));
+        self.adaptation = match self.auto_exposure {
+            Some(_) => Some(Adaptation::new(&mut effect)?),
+            None => None,
+        };
+
+        effect
+            .simple(VERT_SRC, FRAG_SRC)
+            .with_texture("source")
+            .with_texture("adapted")
+            .with_raw_global("exposure")
+            .with_raw_global("tonemap_operator")
+            .with_raw_global("auto_exposure")
+            .with_raw_global("ev_range")
+            .with_output("color", None)
+            .build()
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        _factory: Factory,
+        (active, camera, camera_exposure, time): <Self as PassData<'a>>::Data,
+    ) {
+        let (view, sampler) = match self.source {
+            Some(ref source) => source,
+            None => return,
+        };
+
+        let settings = self.auto_exposure.clone().unwrap_or_default();
+        let adapted = self.adaptation.as_mut().and_then(|adaptation| {
+            let timing = (time.delta_seconds(), time.fixed_seconds());
+            adaptation.apply(encoder, view.raw(), &settings, timing)
+        });
+
+        effect.data.textures.push(view.raw().clone());
+        effect.data.samplers.push(sampler.clone());
+        // Without auto exposure the sampler is bound to the scene, which the shader ignores.
+        effect.add_raw_texture(adapted.as_ref().unwrap_or_else(|| view.raw()), sampler);
+        let auto_exposure = if adapted.is_some() { 1.0 } else { 0.0 };
+        effect.update_global("auto_exposure", auto_exposure);
+        effect.update_global("ev_range", settings.ev_range());
+        let camera_exposure = active_exposure(active.entity, &camera, &camera_exposure)
+            .map_or(1.0, CameraExposure::exposure);
+        effect.update_global("exposure", self.exposure * camera_exposure);
+        effect.update_global("tonemap_operator", self.operator.shader_index());
+        effect.draw(&fullscreen_slice(), encoder);
+        effect.clear();
+    }
+
+    fn new_targets(&mut self, targets: &Targets) {
+        match (targets.get(&self.input).and_then(target_view), self.source.as_mut()) {
+            (Some(view), Some(source)) => source.0 = view,
+            (None, _) => error!("Tonemap input target {:?} not found!", self.input),
+            _ => {}
+        }
+    }
+}
+
+/// Returns the `CameraExposure` of the `active` camera, or of the first camera if none is active.
+///
+/// An active camera without one keeps the exposure at 1.0, instead of using another camera's.
+fn active_exposure<'a>(
+    active: Option<Entity>,
+    camera: &ReadStorage<'_, Camera>,
+    camera_exposure: &'a ReadStorage<'_, CameraExposure>,
+) -> Option<&'a CameraExposure> {
+    match active {
+        Some(entity) => camera_exposure.get(entity),
+        None => (camera, camera_exposure).join().map(|(_, e)| e).next(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use amethyst_core::specs::prelude::{Builder, World};
+
+    use crate::{
+        cam::{Camera, CameraExposure},
+        pipe::{Pipeline, Stage, Target},
+        HeadlessRenderer,
+    };
+
+    use super::{
+        active_exposure, DrawTonemap,
+        TonemapOperator::{self, *},
+    };
+
+    fn assert_maps(operator: TonemapOperator, input: f32, exposure: f32, expected: f32) {
+        let out = operator.apply([input; 3], exposure);
+        for channel in &out {
+            assert!(
+                (channel - expected).abs() < 1e-4,
+                "{:?} mapped {} to {}, expected {}",
+                operator,
+                input,
+                channel,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn reinhard_maps_known_values() {
+        assert_maps(Reinhard, 0.0, 1.0, 0.0);
+        assert_maps(Reinhard, 1.0, 1.0, 0.5);
+        assert_maps(Reinhard, 3.0, 1.0, 0.75);
+        assert_maps(Reinhard, 1.5, 2.0, 0.75);
+    }
+
+    #[test]
+    fn aces_filmic_maps_known_values() {
+        assert_maps(AcesFilmic, 0.0, 1.0, 0.0);
+        assert_maps(AcesFilmic, 1.0, 1.0, 2.54 / 3.16);
+        assert_maps(AcesFilmic, 0.5, 2.0, 2.54 / 3.16);
+        assert_maps(AcesFilmic, 100.0, 1.0, 1.0);
+    }
+
+    #[test]
+    fn only_cameras_without_an_active_one_fall_back() {
+        let mut world = World::new();
+        world.register::<Camera>();
+        world.register::<CameraExposure>();
+        let exposed = world
+            .create_entity()
+            .with(Camera::standard_2d())
+            .with(CameraExposure::default())
+            .build();
+        let plain = world.create_entity().with(Camera::standard_2d()).build();
+
+        let camera = world.read_storage::<Camera>();
+        let exposure = world.read_storage::<CameraExposure>();
+        assert!(active_exposure(Some(exposed), &camera, &exposure).is_some());
+        assert!(active_exposure(Some(plain), &camera, &exposure).is_none());
+        assert!(active_exposure(None, &camera, &exposure).is_some());
+    }
+
+    fn tonemapped_scene(hdr: bool, value: f32) -> [u8; 4] {
+        let scene = Target::named("scene");
+        let scene = if hdr { scene.with_hdr() } else { scene };
+        let mut renderer = HeadlessRenderer::new((4, 4)).unwrap();
+        let mut pipe = renderer
+            .create_pipe(
+                Pipeline::build()
+                    .with_target(scene)
+                    .with_stage(Stage::with_target("scene").clear_target([value; 4], 1.0))
+                    .with_stage(
+                        Stage::with_backbuffer()
+                            .with_pass(DrawTonemap::new("scene").with_operator(Reinhard)),
+                    ),
+            )
+            .unwrap();
+        renderer.draw_world(&mut pipe, &mut World::new().res);
+        renderer.read_pixels().unwrap().rgba.get_pixel(2, 2).data
+    }
+
+    #[test]
+    #[ignore] // Needs an OpenGL context, see `HeadlessRenderer`.
+    fn hdr_targets_keep_colors_above_one() {
+        let white = tonemapped_scene(false, 1.0);
+        assert_eq!(tonemapped_scene(false, 4.0), white);
+        let bright = tonemapped_scene(true, 4.0);
+        assert!(
+            bright[0] > white[0],
+            "{:?} is not brighter than {:?}",
+            bright,
+            white
+        );
+    }
+}
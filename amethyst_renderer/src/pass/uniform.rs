@@ -0,0 +1,96 @@
+//! Per-entity uniform blocks of custom shaders.
+
+use glsl_layout::{float, Uniform};
+
+use amethyst_core::specs::prelude::{Component, NullStorage};
+
+use crate::{
+    pipe::{Effect, EffectBuilder},
+    types::Encoder,
+};
+
+/// A component holding constants of its entity, which passes supporting it upload into the
+/// uniform block `NAME` of their shaders before drawing the entity
+///
+/// This is how custom shaders get parameters the built-in passes know nothing about, e.g. the
+/// phase of an animation or a material parameter, without forking the pass. `DrawFlat` supports
+/// them through `DrawFlat::with_uniform`. Entities without the component are drawn with the block
+/// of `Self::default()`.
+///
+/// The block is laid out by `glsl_layout` like the blocks of the built-in passes, and declared in
+/// the shader as `layout (std140) uniform NAME { ... }`:
+///
+/// ```rust,ignore
+/// #[repr(C, align(16))]
+/// #[derive(Clone, Copy, Debug, Uniform)]
+/// struct DissolveArgs {
+///     amount: float,
+/// }
+///
+/// #[derive(Clone, Debug, Default, PartialEq)]
+/// struct Dissolve(f32);
+///
+/// impl Component for Dissolve {
+///     type Storage = VecStorage<Self>;
+/// }
+///
+/// impl EntityUniform for Dissolve {
+///     const NAME: &'static str = "Dissolve";
+///     type Uniform = DissolveArgs;
+///
+///     fn uniform(&self) -> DissolveArgs {
+///         DissolveArgs { amount: self.0 }
+///     }
+/// }
+/// ```
+pub trait EntityUniform: Component + Default + Send + Sync {
+    /// Name of the uniform block in the shaders. The block of `NoUniform` has none, and is never
+    /// uploaded.
+    const NAME: &'static str;
+
+    /// The uniform block.
+    type Uniform: Uniform;
+
+    /// Returns the uniform block of the entity.
+    fn uniform(&self) -> Self::Uniform;
+}
+
+/// The `EntityUniform` of passes without one, which uploads nothing
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct NoUniform;
+
+impl Component for NoUniform {
+    type Storage = NullStorage<Self>;
+}
+
+impl EntityUniform for NoUniform {
+    const NAME: &'static str = "";
+    type Uniform = float;
+
+    fn uniform(&self) -> float {
+        0.0
+    }
+}
+
+/// Declares the uniform block of `U`, if it has one.
+pub(crate) fn with_entity_uniform<U: EntityUniform>(builder: &mut EffectBuilder<'_>) {
+    if !U::NAME.is_empty() {
+        builder.with_constant_buffer::<U::Uniform>(U::NAME, 1);
+    }
+}
+
+/// Uploads the uniform block of `uniform`, or of `U::default()` for entities without one.
+pub(crate) fn set_entity_uniform<U: EntityUniform>(
+    encoder: &mut Encoder,
+    effect: &mut Effect,
+    uniform: Option<&U>,
+) {
+    if U::NAME.is_empty() {
+        return;
+    }
+    let block = match uniform {
+        Some(uniform) => uniform.uniform(),
+        None => U::default().uniform(),
+    };
+    effect.update_constant_buffer(U::NAME, &block.std140(), encoder);
+}
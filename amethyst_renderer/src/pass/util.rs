@@ -1,29 +1,33 @@
-use std::mem;
+use std::{borrow::Cow, cmp::Ordering, mem};
 
+use gfx::{pso::buffer::Element, traits::Pod};
 use gfx_core::state::{Blend, ColorMask};
 use glsl_layout::*;
-use log::error;
 
 #[cfg(feature = "profiler")]
 use thread_profiler::profile_scope;
 
 use amethyst_assets::AssetStorage;
 use amethyst_core::{
-    nalgebra::Matrix4,
-    specs::prelude::{Join, Read, ReadStorage},
+    nalgebra::{Matrix4, Vector3},
+    specs::prelude::{Entity, Join, Read, ReadStorage},
     GlobalTransform,
 };
+use amethyst_error::{Error, ResultExt};
 
 use crate::{
-    cam::{ActiveCamera, Camera},
-    mesh::Mesh,
-    mtl::{Material, MaterialDefaults, TextureOffset},
+    cam::{ActiveCamera, Camera, CameraTarget, StereoCamera, Viewport},
+    culling::{Aabb, BoundingSphere, Frustum},
+    error,
+    mesh::{LodMesh, LodView, Mesh, MeshBuilder, MeshHandle, VertexDataSet},
+    mtl::{Material, MaterialDefaults, MissingTextures, TextureOffset, TextureRegion},
     pass::set_skinning_buffers,
-    pipe::{DepthMode, Effect, EffectBuilder},
-    skinning::JointTransforms,
+    pipe::{DepthMode, Effect, EffectBuilder, Target},
+    skinning::{JointTransforms, MAX_JOINTS},
     tex::Texture,
-    types::Encoder,
-    vertex::Attributes,
+    transparent::SortKey,
+    types::{Encoder, Factory, ShaderResourceView, Slice},
+    vertex::{Attribute, AttributeFormat, Attributes, Position, VertexFormat, With},
     Rgba,
 };
 
@@ -80,10 +84,7 @@ pub(crate) fn set_attribute_buffers(
         match mesh.buffer(attr) {
             Some(vbuf) => effect.data.vertex_bufs.push(vbuf.clone()),
             None => {
-                error!(
-                    "Required vertex attribute buffer with format {:?} missing in mesh",
-                    attr
-                );
+                effect.warn_once(describe_missing_buffer(mesh, attr));
                 return false;
             }
         }
@@ -91,9 +92,100 @@ pub(crate) fn set_attribute_buffers(
     true
 }
 
+/// Explains why `mesh` has no vertex buffer with all of `attributes`, listing its buffers.
+fn describe_missing_buffer(mesh: &Mesh, attributes: Attributes<'_>) -> String {
+    let names = attributes.iter().map(|&(name, _)| name).collect::<Vec<_>>();
+    let missing = mesh.missing_attributes(attributes);
+    let mut message = if missing.is_empty() {
+        format!(
+            "Skipping a mesh whose vertex attributes {:?} are spread over several buffers, the \
+             pass needs them in one",
+            names
+        )
+    } else {
+        format!(
+            "Skipping a mesh without the vertex attributes {:?} in one buffer, {:?} are \
+             missing or have another format or offset",
+            names, missing
+        )
+    };
+    for (i, vbuf) in mesh.vertex_buffers().iter().enumerate() {
+        message.push_str(&format!("\n  buffer {}: {}", i, vbuf));
+    }
+    message
+}
+
+/// Builds a mesh drawn by a pass, naming the pass and the mesh in the error if that fails.
+pub(crate) fn build_pass_mesh<T: VertexDataSet>(
+    builder: MeshBuilder<T>,
+    factory: &mut Factory,
+    pass: &'static str,
+    mesh: &'static str,
+) -> Result<Mesh, Error> {
+    builder
+        .build(factory)
+        .with_context(|_| error::Error::CreatePassMesh(pass, mesh))
+}
+
+/// Vertex of the cube built by `build_cube_mesh`, which only has a `Position`.
+pub(crate) struct CubeVertex {
+    position: [f32; 3],
+}
+
+unsafe impl Pod for CubeVertex {}
+
+impl VertexFormat for CubeVertex {
+    const ATTRIBUTES: Attributes<'static> = &[(Position::NAME, <Self as With<Position>>::FORMAT)];
+}
+
+impl With<Position> for CubeVertex {
+    const FORMAT: AttributeFormat = Element {
+        offset: 0,
+        format: Position::FORMAT,
+    };
+}
+
+/// Builds a cube spanning `-1.0` to `1.0` on every axis for the pass named `pass`.
+///
+/// The cube shares its 8 corners between 36 indices, two triangles per face. The triangles are
+/// wound counter-clockwise as seen from inside the cube, so its faces point inwards: with the
+/// default back face culling only the faces behind the viewer are drawn, from inside the cube
+/// as well as from outside. `EffectBuilder::with_front_face_culling` draws the faces facing the
+/// viewer instead. Bind it with `CubeVertex::ATTRIBUTES`.
+pub(crate) fn build_cube_mesh(factory: &mut Factory, pass: &'static str) -> Result<Mesh, Error> {
+    let data: Vec<CubeVertex> = CUBE_CORNERS
+        .iter()
+        .map(|&position| CubeVertex { position })
+        .collect();
+    let builder = Mesh::build(data).with_indices(CUBE_INDICES.to_vec());
+    build_pass_mesh(builder, factory, pass, "cube")
+}
+
+/// Corners of the cube, the bits of each index telling whether it lies on the positive X, Y and Z
+/// side.
+const CUBE_CORNERS: [[f32; 3]; 8] = [
+    [-1.0, -1.0, -1.0],
+    [-1.0, -1.0, 1.0],
+    [-1.0, 1.0, -1.0],
+    [-1.0, 1.0, 1.0],
+    [1.0, -1.0, -1.0],
+    [1.0, -1.0, 1.0],
+    [1.0, 1.0, -1.0],
+    [1.0, 1.0, 1.0],
+];
+
+/// Two triangles for each face, counter-clockwise seen from inside the cube.
+const CUBE_INDICES: [u16; 36] = [
+    2, 0, 4, 4, 6, 2, // -Z
+    1, 0, 2, 2, 3, 1, // -X
+    4, 5, 7, 7, 6, 4, // +X
+    1, 3, 7, 7, 5, 1, // +Z
+    2, 6, 7, 7, 3, 2, // +Y
+    0, 1, 4, 4, 1, 5, // -Y
+];
+
 pub(crate) fn add_texture(effect: &mut Effect, texture: &Texture) {
-    effect.data.textures.push(texture.view().clone());
-    effect.data.samplers.push(texture.sampler().clone());
+    effect.add_texture(texture);
 }
 
 pub(crate) fn setup_textures(builder: &mut EffectBuilder<'_>, types: &[TextureType]) {
@@ -116,12 +208,46 @@ pub(crate) fn setup_textures(builder: &mut EffectBuilder<'_>, types: &[TextureTy
     setup_texture_offsets(builder, types);
 }
 
+pub(crate) fn setup_emission(builder: &mut EffectBuilder<'_>) {
+    builder.with_raw_global("emission_intensity");
+}
+
+pub(crate) fn set_emission(effect: &mut Effect, material: Option<&Material>) {
+    let intensity = material.map_or(1.0, |material| material.emission_intensity);
+    effect.update_global("emission_intensity", intensity);
+}
+
+/// Returns `material` sampling only `region` of its albedo map, if there is a region.
+pub(crate) fn region_material<'m>(
+    material: &'m Material,
+    region: Option<&TextureRegion>,
+) -> Cow<'m, Material> {
+    match region {
+        Some(region) => Cow::Owned(Material {
+            albedo_offset: region.0.clone(),
+            ..material.clone()
+        }),
+        None => Cow::Borrowed(material),
+    }
+}
+
+pub(crate) fn setup_alpha_cutoff(builder: &mut EffectBuilder<'_>) {
+    builder.with_raw_global("alpha_cutoff");
+}
+
+/// Sets the albedo alpha below which the material's fragments are discarded.
+pub(crate) fn set_alpha_cutoff(effect: &mut Effect, material: Option<&Material>) {
+    let cutoff = material.map_or(0.0, |material| material.alpha_cutoff);
+    effect.update_global("alpha_cutoff", cutoff);
+}
+
 pub(crate) fn add_textures(
     effect: &mut Effect,
     encoder: &mut Encoder,
     storage: &AssetStorage<Texture>,
     material: &Material,
     default: &Material,
+    missing: &MissingTextures,
     types: &[TextureType],
 ) {
     use self::TextureType::*;
@@ -130,12 +256,15 @@ pub(crate) fn add_textures(
         let texture = match *ty {
             Albedo => storage
                 .get(&material.albedo)
+                .or_else(|| storage.get(&missing.color))
                 .or_else(|| storage.get(&default.albedo)),
             Emission => storage
                 .get(&material.emission)
+                .or_else(|| storage.get(&missing.color))
                 .or_else(|| storage.get(&default.emission)),
             Normal => storage
                 .get(&material.normal)
+                .or_else(|| storage.get(&missing.normal))
                 .or_else(|| storage.get(&default.normal)),
             Metallic => storage
                 .get(&material.metallic)
@@ -342,6 +471,7 @@ pub(crate) fn draw_mesh(
     tex_storage: &AssetStorage<Texture>,
     material: Option<&Material>,
     material_defaults: &MaterialDefaults,
+    missing: &MissingTextures,
     rgba: Option<&Rgba>,
     camera: Option<(&Camera, &GlobalTransform)>,
     global: Option<&GlobalTransform>,
@@ -353,9 +483,13 @@ pub(crate) fn draw_mesh(
 
     // Return straight away if some parameters are none
     // Consider changing function signature?
+    // Clear anyway, callers may have added their own resources ahead of this draw.
     let (mesh, material, global) = match (mesh, material, global) {
         (Some(v1), Some(v2), Some(v3)) => (v1, v2, v3),
-        _ => return,
+        _ => {
+            effect.clear();
+            return;
+        }
     };
 
     if !set_attribute_buffers(effect, mesh, attributes)
@@ -375,7 +509,9 @@ pub(crate) fn draw_mesh(
 
     if skinning {
         if let Some(joint) = joint {
-            effect.update_buffer("JointTransforms", &joint.matrices[..], encoder);
+            // Longer skins are refused when attached, but the buffer mustn't overflow regardless.
+            let count = joint.matrices.len().min(MAX_JOINTS);
+            effect.update_buffer("JointTransforms", &joint.matrices[..count], encoder);
         }
     }
 
@@ -385,13 +521,77 @@ pub(crate) fn draw_mesh(
         &tex_storage,
         material,
         &material_defaults.0,
+        missing,
         textures,
     );
 
-    effect.draw(mesh.slice(), encoder);
+    if material.two_sided {
+        effect.draw_two_sided(mesh.slice(), encoder);
+    } else {
+        effect.draw(mesh.slice(), encoder);
+    }
     effect.clear();
 }
 
+/// Returns a view of the first color buffer of `target`, if it can be sampled.
+pub(crate) fn target_view(target: &Target) -> Option<ShaderResourceView<[f32; 4]>> {
+    target.color_buf(0).and_then(|cb| cb.as_input.clone())
+}
+
+/// A single triangle covering the screen, for use with the `fullscreen.glsl` vertex shader.
+pub(crate) fn fullscreen_slice() -> Slice {
+    Slice {
+        start: 0,
+        end: 3,
+        base_vertex: 0,
+        instances: None,
+        buffer: Default::default(),
+    }
+}
+
+/// Returns how far `position` lies in front of `camera` along its view direction.
+///
+/// Without a camera, the distance is measured from the origin along -Z.
+pub(crate) fn view_depth(
+    camera: Option<(&Camera, &GlobalTransform)>,
+    position: &Vector3<f32>,
+) -> f32 {
+    let transform = camera.map_or_else(Matrix4::identity, |(_, transform)| transform.0);
+    let eye = transform.column(3).xyz();
+    let forward = -transform.column(2).xyz();
+    (position - eye).dot(&forward)
+}
+
+/// Orders `entities` from the farthest to the nearest along the view direction of `camera`, so
+/// transparent entities drawn in that order blend over what's behind them.
+///
+/// Entities are sorted by their `SortKey` if they have one and by the `view_depth` of their
+/// origin otherwise. The sort is stable, entities at the same depth keep their order.
+pub fn sort_back_to_front(
+    entities: &mut Vec<Entity>,
+    camera: Option<(&Camera, &GlobalTransform)>,
+    global: &ReadStorage<'_, GlobalTransform>,
+    sort_keys: &ReadStorage<'_, SortKey>,
+) {
+    #[cfg(feature = "profiler")]
+    profile_scope!("render_sortbacktofront");
+
+    let mut keyed = entities
+        .drain(..)
+        .map(|entity| {
+            let key = sort_keys.get(entity).map(|key| key.0).unwrap_or_else(|| {
+                let position = global
+                    .get(entity)
+                    .map_or_else(Vector3::zeros, |global| global.0.column(3).xyz());
+                view_depth(camera, &position)
+            });
+            (key, entity)
+        })
+        .collect::<Vec<_>>();
+    keyed.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+    entities.extend(keyed.into_iter().map(|(_, entity)| entity));
+}
+
 /// Returns the main camera and its `GlobalTransform`
 pub fn get_camera<'a>(
     active: Read<'a, ActiveCamera>,
@@ -411,6 +611,140 @@ pub fn get_camera<'a>(
         .or_else(|| (camera, global).join().next())
 }
 
+/// Returns the cameras to draw the scene into `target` with, each with the region of the target
+/// it covers.
+///
+/// These are the cameras with a `CameraTarget` naming `target`. If there are none, they are all
+/// other cameras with a `Viewport`, and failing that the active camera, or the first camera
+/// without a `CameraTarget`, covering the whole target. A camera with a `StereoCamera` is
+/// returned once for each eye.
+pub fn get_cameras<'a>(
+    active: Read<'a, ActiveCamera>,
+    camera: &'a ReadStorage<'a, Camera>,
+    viewport: &'a ReadStorage<'a, Viewport>,
+    camera_target: &'a ReadStorage<'a, CameraTarget>,
+    stereo: &'a ReadStorage<'a, StereoCamera>,
+    global: &'a ReadStorage<'a, GlobalTransform>,
+    target: &str,
+) -> Vec<(Option<(Camera, GlobalTransform)>, Option<Viewport>)> {
+    let targeted: Vec<_> = (
+        camera,
+        viewport.maybe(),
+        stereo.maybe(),
+        camera_target,
+        global,
+    )
+        .join()
+        .filter(|(_, _, _, camera_target, _)| camera_target.target == target)
+        .flat_map(|(camera, viewport, stereo, _, global)| {
+            camera_views(camera, global, viewport, stereo)
+        })
+        .collect();
+    if !targeted.is_empty() {
+        return targeted;
+    }
+
+    let split: Vec<_> = (camera, viewport, stereo.maybe(), !camera_target, global)
+        .join()
+        .flat_map(|(camera, viewport, stereo, _, global)| {
+            camera_views(camera, global, Some(viewport), stereo)
+        })
+        .collect();
+    if !split.is_empty() {
+        return split;
+    }
+
+    let main = active
+        .entity
+        .and_then(|entity| Some((camera.get(entity)?, global.get(entity)?, stereo.get(entity))))
+        .or_else(|| {
+            (camera, stereo.maybe(), !camera_target, global)
+                .join()
+                .map(|(camera, stereo, _, global)| (camera, global, stereo))
+                .next()
+        });
+    match main {
+        Some((camera, global, stereo)) => camera_views(camera, global, None, stereo),
+        None => vec![(None, None)],
+    }
+}
+
+/// Returns the view of a single camera, or one for each eye of a `StereoCamera`.
+fn camera_views(
+    camera: &Camera,
+    global: &GlobalTransform,
+    viewport: Option<&Viewport>,
+    stereo: Option<&StereoCamera>,
+) -> Vec<(Option<(Camera, GlobalTransform)>, Option<Viewport>)> {
+    match stereo {
+        Some(stereo) => stereo
+            .eyes(global, viewport)
+            .iter()
+            .map(|(camera, global, viewport)| (Some((camera.clone(), *global)), Some(*viewport)))
+            .collect(),
+        None => vec![(Some((camera.clone(), *global)), viewport.cloned())],
+    }
+}
+
+/// Returns the mesh to draw for an entity, its `LodMesh` level for the distance from `camera` in
+/// `view` if it has one.
+pub(crate) fn select_lod<'a>(
+    mesh: &'a MeshHandle,
+    lod: Option<&'a LodMesh>,
+    view: LodView,
+    camera: Option<(&Camera, &GlobalTransform)>,
+    global: Option<&GlobalTransform>,
+) -> &'a MeshHandle {
+    match (lod, camera, global) {
+        (Some(lod), Some((_, camera)), Some(global)) => {
+            let offset = global.0.column(3).xyz() - camera.0.column(3).xyz();
+            lod.mesh(view, offset.norm())
+        }
+        _ => mesh,
+    }
+}
+
+/// A camera and the part of the target it covers, as returned by `get_cameras`.
+pub(crate) type CameraView = (Option<(Camera, GlobalTransform)>, Option<Viewport>);
+
+/// The camera a pass draws a `CameraView` with, its projection mapped onto the viewport.
+pub(crate) struct ViewCamera(Option<(Camera, GlobalTransform)>);
+
+impl ViewCamera {
+    /// Returns the camera and its transform the way the other helpers take them.
+    pub(crate) fn get(&self) -> Option<(&Camera, &GlobalTransform)> {
+        self.0
+            .as_ref()
+            .map(|(camera, transform)| (camera, transform))
+    }
+
+    /// Replaces the projection of the camera with `f` of it.
+    pub(crate) fn map_projection<F>(self, f: F) -> Self
+    where
+        F: FnOnce(&Camera) -> Matrix4<f32>,
+    {
+        ViewCamera(
+            self.0
+                .map(|(camera, transform)| (Camera { proj: f(&camera) }, transform)),
+        )
+    }
+}
+
+/// Restricts drawing to the viewport of `view` and returns the camera to draw it with.
+pub(crate) fn set_view(effect: &mut Effect, (camera, viewport): &CameraView) -> ViewCamera {
+    let scissor = match (viewport, effect.target_size()) {
+        (Some(viewport), Some((width, height))) => Some(viewport.scissor(width, height)),
+        _ => None,
+    };
+    effect.set_scissor(scissor);
+    ViewCamera(camera.as_ref().map(|(camera, transform)| {
+        let proj = viewport
+            .as_ref()
+            .map_or(camera.proj, |viewport| viewport.projection(&camera.proj));
+        (Camera { proj }, *transform)
+    }))
+}
+
 pub fn default_transparency() -> Option<(ColorMask, Blend, Option<DepthMode>)> {
     Some((
         ColorMask::all(),
@@ -418,3 +752,148 @@ pub fn default_transparency() -> Option<(ColorMask, Blend, Option<DepthMode>)> {
         Some(DepthMode::LessEqualWrite),
     ))
 }
+
+/// Returns whether an entity at `global` with the given bounds is in view of `camera`.
+///
+/// Entities without bounds always are. When testing many entities against the same camera,
+/// build a `Frustum` once and use `Frustum::contains` instead.
+pub fn in_frustum(
+    camera: (&Camera, &GlobalTransform),
+    global: &GlobalTransform,
+    sphere: Option<&BoundingSphere>,
+    aabb: Option<&Aabb>,
+) -> bool {
+    Frustum::new(camera.0, camera.1).contains(global, sphere, aabb)
+}
+
+/// Returns the frustum passes cull the entities of `view` against, if culling is enabled and there
+/// is a camera.
+pub(crate) fn culling_frustum(enabled: bool, (camera, _): &CameraView) -> Option<Frustum> {
+    if enabled {
+        camera
+            .as_ref()
+            .map(|(camera, transform)| Frustum::new(camera, transform))
+    } else {
+        None
+    }
+}
+
+/// Returns whether an entity should be skipped because its bounds are out of view.
+pub(crate) fn is_culled(
+    frustum: &Option<Frustum>,
+    global: Option<&GlobalTransform>,
+    (sphere, aabb): (Option<&BoundingSphere>, Option<&Aabb>),
+) -> bool {
+    match (frustum, global) {
+        (Some(frustum), Some(global)) => !frustum.contains(global, sphere, aabb),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use amethyst_core::{
+        nalgebra::{Matrix4, Vector3},
+        specs::prelude::{Builder, World},
+        GlobalTransform,
+    };
+
+    use crate::{cam::Camera, transparent::SortKey};
+
+    use super::{sort_back_to_front, CUBE_CORNERS, CUBE_INDICES};
+
+    #[test]
+    fn entities_are_sorted_far_to_near() {
+        let mut world = World::new();
+        world.register::<GlobalTransform>();
+        world.register::<SortKey>();
+        let at = |z: f32| GlobalTransform(Matrix4::new_translation(&Vector3::new(0.0, 0.0, z)));
+        let near = world.create_entity().with(at(-2.0)).build();
+        let far = world.create_entity().with(at(-10.0)).build();
+        let behind = world.create_entity().with(at(3.0)).build();
+        let keyed = world
+            .create_entity()
+            .with(at(-1.0))
+            .with(SortKey(5.0))
+            .build();
+
+        let camera = Camera::standard_3d(1.0, 1.0);
+        let eye = GlobalTransform::default();
+        let mut entities = vec![near, far, behind, keyed];
+        sort_back_to_front(
+            &mut entities,
+            Some((&camera, &eye)),
+            &world.read_storage(),
+            &world.read_storage(),
+        );
+        assert_eq!(entities, vec![far, keyed, near, behind]);
+
+        // Turned around, the camera sees the entity behind it as the farthest.
+        let turned = GlobalTransform(Matrix4::new_rotation(Vector3::y() * std::f32::consts::PI));
+        sort_back_to_front(
+            &mut entities,
+            Some((&camera, &turned)),
+            &world.read_storage(),
+            &world.read_storage(),
+        );
+        assert_eq!(entities, vec![keyed, behind, near, far]);
+    }
+
+    #[test]
+    fn cube_faces_point_inwards() {
+        for triangle in CUBE_INDICES.chunks(3) {
+            let corner = |i: usize| Vector3::from(CUBE_CORNERS[triangle[i] as usize]);
+            let normal = (corner(1) - corner(0)).cross(&(corner(2) - corner(0)));
+            let center = (corner(0) + corner(1) + corner(2)) / 3.0;
+            assert!(normal.dot(&center) < 0.0, "{:?} faces outwards", triangle);
+        }
+    }
+
+    #[test]
+    fn indexed_cube_draws_the_non_indexed_triangles() {
+        // The vertices drawn before the cube was indexed, in order.
+        let non_indexed = [
+            [-1.0, 1.0, -1.0],
+            [-1.0, -1.0, -1.0],
+            [1.0, -1.0, -1.0],
+            [1.0, -1.0, -1.0],
+            [1.0, 1.0, -1.0],
+            [-1.0, 1.0, -1.0],
+            [-1.0, -1.0, 1.0],
+            [-1.0, -1.0, -1.0],
+            [-1.0, 1.0, -1.0],
+            [-1.0, 1.0, -1.0],
+            [-1.0, 1.0, 1.0],
+            [-1.0, -1.0, 1.0],
+            [1.0, -1.0, -1.0],
+            [1.0, -1.0, 1.0],
+            [1.0, 1.0, 1.0],
+            [1.0, 1.0, 1.0],
+            [1.0, 1.0, -1.0],
+            [1.0, -1.0, -1.0],
+            [-1.0, -1.0, 1.0],
+            [-1.0, 1.0, 1.0],
+            [1.0, 1.0, 1.0],
+            [1.0, 1.0, 1.0],
+            [1.0, -1.0, 1.0],
+            [-1.0, -1.0, 1.0],
+            [-1.0, 1.0, -1.0],
+            [1.0, 1.0, -1.0],
+            [1.0, 1.0, 1.0],
+            [1.0, 1.0, 1.0],
+            [-1.0, 1.0, 1.0],
+            [-1.0, 1.0, -1.0],
+            [-1.0, -1.0, -1.0],
+            [-1.0, -1.0, 1.0],
+            [1.0, -1.0, -1.0],
+            [1.0, -1.0, -1.0],
+            [-1.0, -1.0, 1.0],
+            [1.0, -1.0, 1.0],
+        ];
+        let indexed = CUBE_INDICES
+            .iter()
+            .map(|&i| CUBE_CORNERS[i as usize])
+            .collect::<Vec<_>>();
+        assert_eq!(indexed, &non_indexed[..]);
+    }
+}
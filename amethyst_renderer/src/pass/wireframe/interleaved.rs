@@ -0,0 +1,151 @@
+//! Wireframe pass
+
+use std::marker::PhantomData;
+
+use derivative::Derivative;
+use gfx::pso::buffer::ElemStride;
+
+use amethyst_assets::AssetStorage;
+use amethyst_core::{
+    specs::prelude::{Join, Read, ReadStorage},
+    transform::GlobalTransform,
+};
+use amethyst_error::Error;
+
+use crate::{
+    cam::{ActiveCamera, Camera},
+    hidden::{Hidden, HiddenPropagate},
+    mesh::{Mesh, MeshHandle},
+    pass::util::{get_camera, set_attribute_buffers, set_vertex_args, setup_vertex_args},
+    pipe::{
+        pass::{Pass, PassData},
+        DepthMode, Effect, NewEffect,
+    },
+    types::{Encoder, Factory},
+    vertex::{Position, Query},
+    Rgba,
+};
+
+use super::*;
+
+/// Draw the edges of meshes in a single color
+///
+/// By default only entities with a `Wireframe` component are drawn; `with_all_meshes` draws
+/// every visible mesh instead. The mesh's own vertex buffers are reused, with the rasterizer set
+/// to line mode. Lines are depth tested but do not write depth, so adding the pass after the
+/// regular passes outlines the visible geometry.
+///
+/// # Type Parameters:
+///
+/// * `V`: `VertexFormat`
+#[derive(Derivative, Clone, Debug, PartialEq)]
+#[derivative(Default(bound = "V: Query<(Position,)>"))]
+pub struct DrawWireframe<V> {
+    _pd: PhantomData<V>,
+    #[derivative(Default(value = "Rgba::WHITE"))]
+    color: Rgba,
+    all_meshes: bool,
+}
+
+impl<V> DrawWireframe<V>
+where
+    V: Query<(Position,)>,
+{
+    /// Create instance of `DrawWireframe` pass
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Set the color of the lines, white by default.
+    pub fn with_color(mut self, color: Rgba) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Draw every visible mesh, not just the ones marked with a `Wireframe` component.
+    pub fn with_all_meshes(mut self, all_meshes: bool) -> Self {
+        self.all_meshes = all_meshes;
+        self
+    }
+}
+
+impl<'a, V> PassData<'a> for DrawWireframe<V>
+where
+    V: Query<(Position,)>,
+{
+    type Data = (
+        Read<'a, ActiveCamera>,
+        ReadStorage<'a, Camera>,
+        Read<'a, AssetStorage<Mesh>>,
+        ReadStorage<'a, Hidden>,
+        ReadStorage<'a, HiddenPropagate>,
+        ReadStorage<'a, MeshHandle>,
+        ReadStorage<'a, GlobalTransform>,
+        ReadStorage<'a, Wireframe>,
+    );
+}
+
+impl<V> Pass for DrawWireframe<V>
+where
+    V: Query<(Position,)>,
+{
+    fn compile(&mut self, effect: NewEffect<'_>) -> Result<Effect, Error> {
+        let mut builder = effect.simple(VERT_SRC, FRAG_SRC);
+        setup_vertex_args(&mut builder);
+        builder
+            .with_wireframe()
+            .with_raw_vertex_buffer(V::QUERIED_ATTRIBUTES, V::size() as ElemStride, 0)
+            .with_raw_global("line_color")
+            .with_output("color", Some(DepthMode::LessEqualTest));
+        builder.build()
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        _factory: Factory,
+        (
+            active,
+            camera,
+            mesh_storage,
+            hidden,
+            hidden_prop,
+            mesh,
+            global,
+            wireframe,
+        ): <Self as PassData<'a>>::Data,
+    ) {
+        let camera = get_camera(active, &camera, &global);
+        let color: [f32; 4] = self.color.into();
+
+        for (mesh, global, wireframe, _, _) in (
+            &mesh,
+            &global,
+            wireframe.maybe(),
+            !&hidden,
+            !&hidden_prop,
+        )
+            .join()
+        {
+            if !self.all_meshes && wireframe.is_none() {
+                continue;
+            }
+
+            let mesh = match mesh_storage.get(mesh) {
+                Some(mesh) => mesh,
+                None => continue,
+            };
+
+            if !set_attribute_buffers(effect, mesh, &[V::QUERIED_ATTRIBUTES]) {
+                effect.clear();
+                continue;
+            }
+
+            set_vertex_args(effect, encoder, camera, global, Rgba::WHITE);
+            effect.update_global("line_color", color);
+            effect.draw(mesh.slice(), encoder);
+            effect.clear();
+        }
+    }
+}
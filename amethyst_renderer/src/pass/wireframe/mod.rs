@@ -0,0 +1,19 @@
+pub use self::interleaved::DrawWireframe;
+
+use amethyst_core::specs::{prelude::Component, storage::NullStorage};
+
+mod interleaved;
+
+static VERT_SRC: &[u8] = include_bytes!("../shaders/vertex/basic.glsl");
+static FRAG_SRC: &[u8] = include_bytes!("../shaders/fragment/wireframe.glsl");
+
+/// Marks an entity's mesh to be drawn by `DrawWireframe`.
+///
+/// Adding or removing the component toggles the wireframe of that entity at runtime. It has no
+/// effect on passes other than `DrawWireframe`, so the filled mesh is still drawn as usual.
+#[derive(Clone, Debug, Default)]
+pub struct Wireframe;
+
+impl Component for Wireframe {
+    type Storage = NullStorage<Self>;
+}
@@ -4,38 +4,57 @@
 
 pub use self::pso::{Data, Init, Meta};
 
-use amethyst_error::{Error, ResultExt};
+use std::mem;
+
+use amethyst_error::Error;
 
 use derivative::Derivative;
-use fnv::FnvHashMap as HashMap;
+use fnv::{FnvHashMap as HashMap, FnvHashSet as HashSet};
 use gfx::{
     buffer::{Info as BufferInfo, Role as BufferRole},
     handle::{Buffer, RawBuffer},
     memory::{Bind, Usage},
     preset::depth::{LESS_EQUAL_TEST, LESS_EQUAL_WRITE},
-    pso::buffer::{ElemStride, InstanceRate},
+    pso::{
+        buffer::{ElemStride, InstanceRate},
+        PipelineStateError,
+    },
     shade::{core::UniformValue, ProgramError, ToUniform},
-    state::{Blend, ColorMask, Comparison, CullFace, Depth, MultiSample, Rasterizer, Stencil},
+    state::{
+        Blend, ColorMask, Comparison, CullFace, Depth, Equation, Factor, MultiSample, RasterMethod,
+        Rasterizer, Stencil,
+    },
     traits::Pod,
-    Primitive, ShaderSet,
+    Primitive, Rect, ShaderSet,
 };
-use glsl_layout::Std140;
+use gfx_core::shade::CreateShaderError;
+use glsl_layout::{Std140, Uniform};
 use log::{debug, warn};
 use serde::{Deserialize, Serialize};
 
 use crate::{
     error,
-    pipe::Target,
-    types::{Encoder, Factory, PipelineState, Resources, Slice},
+    pipe::{reload::shader_source, Target, Targets},
+    tex::Texture,
+    types::{Encoder, Factory, PipelineState, RawShaderResourceView, Resources, Sampler, Slice},
     vertex::Attributes,
 };
 
 mod pso;
 
+const EQUAL_TEST: Depth = Depth {
+    fun: Comparison::Equal,
+    write: false,
+};
+
 #[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub enum DepthMode {
     LessEqualTest,
     LessEqualWrite,
+    /// Only passes fragments at exactly the stored depth, without writing it.
+    ///
+    /// Used after a depth prepass, so only the visible surface of each pixel gets shaded.
+    EqualTest,
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
@@ -47,34 +66,87 @@ pub(crate) enum ProgramSource<'a> {
 
 impl<'a> ProgramSource<'a> {
     pub fn compile(&self, fac: &mut Factory) -> Result<ShaderSet<Resources>, Error> {
-        use gfx::{traits::FactoryExt, Factory};
-
+        use gfx::Factory;
+
+        let vs = match *self {
+            ProgramSource::Simple(vs, _)
+            | ProgramSource::Geometry(vs, _, _)
+            | ProgramSource::Tessellated(vs, _, _, _) => fac
+                .create_shader_vertex(&shader_source(vs))
+                .map_err(|e| shader_error("vertex", e))?,
+        };
         match *self {
-            ProgramSource::Simple(ref vs, ref ps) => fac
-                .create_shader_set(vs, ps)
-                .with_context(|_| error::Error::ProgramCreation),
-            ProgramSource::Geometry(ref vs, ref gs, ref ps) => {
-                let v = fac.create_shader_vertex(vs).map_err(ProgramError::Vertex)?;
-                let g = fac
-                    .create_shader_geometry(gs)
-                    .expect("Geometry shader creation failed");
-                let p = fac.create_shader_pixel(ps).map_err(ProgramError::Pixel)?;
-                Ok(ShaderSet::Geometry(v, g, p))
+            ProgramSource::Simple(_, ps) => {
+                let ps = fac
+                    .create_shader_pixel(&shader_source(ps))
+                    .map_err(|e| shader_error("fragment", e))?;
+                Ok(ShaderSet::Simple(vs, ps))
+            }
+            ProgramSource::Geometry(_, gs, ps) => {
+                let gs = fac
+                    .create_shader_geometry(&shader_source(gs))
+                    .map_err(|e| shader_error("geometry", e))?;
+                let ps = fac
+                    .create_shader_pixel(&shader_source(ps))
+                    .map_err(|e| shader_error("fragment", e))?;
+                Ok(ShaderSet::Geometry(vs, gs, ps))
+            }
+            ProgramSource::Tessellated(_, hs, ds, ps) => {
+                let hs = fac
+                    .create_shader_hull(&shader_source(hs))
+                    .map_err(|e| shader_error("tessellation control", e))?;
+                let ds = fac
+                    .create_shader_domain(&shader_source(ds))
+                    .map_err(|e| shader_error("tessellation evaluation", e))?;
+                let ps = fac
+                    .create_shader_pixel(&shader_source(ps))
+                    .map_err(|e| shader_error("fragment", e))?;
+                Ok(ShaderSet::Tessellated(vs, hs, ds, ps))
             }
-            ProgramSource::Tessellated(ref vs, ref hs, ref ds, ref ps) => fac
-                .create_shader_set_tessellation(vs, hs, ds, ps)
-                .with_context(|_| error::Error::ProgramCreation),
         }
     }
 }
 
+/// Turns a failure to compile the shader of `stage` into an error carrying the driver's log.
+fn shader_error(stage: &'static str, err: CreateShaderError) -> Error {
+    let log = match err {
+        CreateShaderError::CompilationFailed(log) => log,
+        err => err.to_string(),
+    };
+    error::Error::ShaderCompile(stage, log).into()
+}
+
+/// Surfaces link failures with the driver's log, the shaders themselves compiled already.
+fn pipeline_error(err: PipelineStateError<String>) -> Error {
+    match err {
+        PipelineStateError::Program(ProgramError::Link(err)) => {
+            error::Error::ProgramLink(err.to_string()).into()
+        }
+        err => err.into(),
+    }
+}
+
+/// A compiled pipeline state together with the resources bound to it.
+///
+/// Passes bind textures, vertex buffers and uniforms here in `Pass::apply` before each
+/// `draw`, in the order they were declared on the `EffectBuilder`, and clear them afterwards.
+/// The bind methods cover what the built-in passes need; `data` stays public for anything
+/// they don't.
 #[derive(Derivative)]
 #[derivative(Clone, Debug, Eq, PartialEq)]
 pub struct Effect {
     pub pso: PipelineState<Meta>,
     pub data: Data,
+    two_sided_pso: Option<PipelineState<Meta>>,
     const_bufs: HashMap<String, usize>,
     globals: HashMap<String, usize>,
+    target: String,
+    srgb_output: Option<bool>,
+    /// Name of the pass the effect was compiled for, for diagnostics.
+    pass: String,
+    /// The warnings logged by `warn_once` so far.
+    #[derivative(PartialEq = "ignore")]
+    warned: HashSet<String>,
 }
 
 impl Effect {
@@ -138,32 +210,208 @@ impl Effect {
         }
     }
 
-    pub fn clear(&mut self) {
+    /// Returns whether a global named `name` was declared with `EffectBuilder::with_raw_global`.
+    pub fn has_global<N: AsRef<str>>(&self, name: N) -> bool {
+        self.globals.contains_key(name.as_ref())
+    }
+
+    /// Returns whether a constant buffer named `name` was declared when building this `Effect`.
+    pub fn has_constant_buffer<N: AsRef<str>>(&self, name: N) -> bool {
+        self.const_bufs.contains_key(name.as_ref())
+    }
+
+    /// Binds `texture` and its sampler to the next texture declared with
+    /// `EffectBuilder::with_texture`.
+    ///
+    /// Textures are bound in the order they were declared, so the first call binds the first
+    /// declared texture. `clear_textures` or `clear` start over at the first one.
+    pub fn add_texture(&mut self, texture: &Texture) {
+        self.add_raw_texture(texture.view(), texture.sampler());
+    }
+
+    /// Binds a texture view with the given sampler, like `add_texture`.
+    ///
+    /// Useful for views that aren't `Texture`s, like the color or depth buffers of a `Target`.
+    pub fn add_raw_texture(&mut self, view: &RawShaderResourceView, sampler: &Sampler) {
+        self.data.textures.push(view.clone());
+        self.data.samplers.push(sampler.clone());
+    }
+
+    /// Returns the texture views bound since the last clear, in declaration order.
+    pub fn textures(&self) -> &[RawShaderResourceView] {
+        &self.data.textures
+    }
+
+    /// Returns the samplers bound since the last clear, one for each bound texture.
+    pub fn samplers(&self) -> &[Sampler] {
+        &self.data.samplers
+    }
+
+    /// Binds `buffer` to the next vertex buffer declared with
+    /// `EffectBuilder::with_raw_vertex_buffer`.
+    pub fn add_vertex_buffer(&mut self, buffer: &RawBuffer<Resources>) {
+        self.data.vertex_bufs.push(buffer.clone());
+    }
+
+    /// Returns the vertex buffers bound since the last clear, in declaration order.
+    pub fn vertex_buffers(&self) -> &[RawBuffer<Resources>] {
+        &self.data.vertex_bufs
+    }
+
+    /// Unbinds all textures and samplers, but keeps the vertex buffers bound.
+    ///
+    /// Passes drawing the same geometry with different textures can bind its vertex buffers
+    /// once and only clear the textures between draws.
+    pub fn clear_textures(&mut self) {
         self.data.textures.clear();
         self.data.samplers.clear();
+    }
+
+    /// Unbinds all textures, samplers and vertex buffers, usually after each draw.
+    pub fn clear(&mut self) {
+        self.clear_textures();
         self.data.vertex_bufs.clear();
     }
 
+    /// Restricts drawing to `rect` of the targets, in pixels from the bottom left corner.
+    ///
+    /// `None` draws to the whole target again.
+    pub fn set_scissor(&mut self, rect: Option<Rect>) {
+        self.data.scissor = rect;
+    }
+
+    /// Returns the name of the target this `Effect` draws into, `""` being the backbuffer.
+    pub fn target_name(&self) -> &str {
+        &self.target
+    }
+
+    /// Returns whether colors written by this `Effect` have to be encoded to sRGB by its shaders.
+    ///
+    /// Targets store colors as they are written, so the backbuffer, which is displayed as it is,
+    /// is sRGB-encoded and any other target linear, unless the effect was built with
+    /// `EffectBuilder::with_srgb_output`. The guess follows the effect when it is retargeted.
+    pub fn srgb_output(&self) -> bool {
+        self.srgb_output.unwrap_or_else(|| self.target.is_empty())
+    }
+
+    /// Returns the width and height of the targets this `Effect` draws into.
+    pub fn target_size(&self) -> Option<(u16, u16)> {
+        self.data.target_size()
+    }
+
+    /// Names the pass this `Effect` belongs to in its warnings.
+    pub(crate) fn set_pass_name(&mut self, pass: String) {
+        self.pass = pass;
+    }
+
+    /// Logs a warning about the effect's pass, unless the same warning was logged before.
+    ///
+    /// Meant for problems passes skip over each frame, which would flood the log otherwise.
+    pub(crate) fn warn_once(&mut self, message: String) {
+        if !self.warned.contains(&message) {
+            if self.pass.is_empty() {
+                warn!("{}", message);
+            } else {
+                warn!("{}: {}", self.pass, message);
+            }
+            self.warned.insert(message);
+        }
+    }
+
     pub fn draw(&mut self, slice: &Slice, enc: &mut Encoder) {
         enc.draw(&slice, &self.pso, &self.data);
     }
+
+    /// Draws like `draw`, but without back face culling, e.g. for two-sided materials.
+    ///
+    /// Only effects built with `EffectBuilder::with_two_sided_variant` can do this, others draw
+    /// with their usual culling.
+    pub fn draw_two_sided(&mut self, slice: &Slice, enc: &mut Encoder) {
+        let pso = self.two_sided_pso.as_ref().unwrap_or(&self.pso);
+        enc.draw(&slice, pso, &self.data);
+    }
+
+    /// Points the outputs of this `Effect` at the buffers of `target`.
+    pub fn retarget(&mut self, target: &Target) {
+        // Distribute new targets that don't blend.
+        self.data.out_colors.clear();
+        self.data
+            .out_colors
+            .extend(target.color_bufs().iter().map(|cb| &cb.as_output).cloned());
+
+        // Distribute new blend targets
+        self.data.out_blends.clear();
+        self.data
+            .out_blends
+            .extend(target.color_bufs().iter().map(|cb| &cb.as_output).cloned());
+
+        // Distribute new depth buffer
+        self.data.out_depth = target.depth_buf().map(|db| (db.as_output.clone(), (0, 0)));
+
+        self.target = target.name().to_string();
+    }
 }
 
+/// Handed to `Pass::compile` to build the pass's `Effect`.
+///
+/// Besides starting the builder, it gives access to the factory and the pipeline's other
+/// targets, e.g. to read from them.
 pub struct NewEffect<'f> {
     pub factory: &'f mut Factory,
     out: &'f Target,
+    targets: &'f Targets,
     multisampling: u16,
 }
 
 impl<'f> NewEffect<'f> {
-    pub(crate) fn new(fac: &'f mut Factory, out: &'f Target, multisampling: u16) -> Self {
+    pub(crate) fn new(
+        fac: &'f mut Factory,
+        out: &'f Target,
+        targets: &'f Targets,
+        multisampling: u16,
+    ) -> Self {
         NewEffect {
             factory: fac,
             out,
+            targets,
             multisampling,
         }
     }
 
+    /// Returns the target the pass renders into.
+    pub fn out(&self) -> &'f Target {
+        self.out
+    }
+
+    /// Returns the pipeline's target with the given name, `""` being the backbuffer.
+    pub fn target(&self, name: &str) -> Option<&'f Target> {
+        self.targets.get(name)
+    }
+
+    /// Starts building an additional effect that renders into `out` instead of the pass's target.
+    ///
+    /// Passes that need more than one pipeline state, e.g. to fill intermediate render targets,
+    /// can build the others with this. The returned builder does not multisample.
+    pub fn simple_into<'a, S: Into<&'a [u8]>>(
+        &'a mut self,
+        out: &'a Target,
+        vs: S,
+        ps: S,
+    ) -> EffectBuilder<'a> {
+        let src = ProgramSource::Simple(vs.into(), ps.into());
+        EffectBuilder::new(&mut *self.factory, out, 0, src)
+    }
+
+    /// Starts building an additional effect that renders into the pass's target, multisampled
+    /// like the one `simple` builds.
+    ///
+    /// Passes drawing part of their entities with another pipeline state, e.g. another depth
+    /// test, can build it with this.
+    pub fn simple_variant<'a, S: Into<&'a [u8]>>(&'a mut self, vs: S, ps: S) -> EffectBuilder<'a> {
+        let src = ProgramSource::Simple(vs.into(), ps.into());
+        EffectBuilder::new(&mut *self.factory, self.out, self.multisampling, src)
+    }
+
     pub fn simple<S: Into<&'f [u8]>>(self, vs: S, ps: S) -> EffectBuilder<'f> {
         let src = ProgramSource::Simple(vs.into(), ps.into());
         EffectBuilder::new(self.factory, self.out, self.multisampling, src)
@@ -180,6 +428,7 @@ impl<'f> NewEffect<'f> {
     }
 }
 
+/// Declares the shaders, inputs and outputs of an `Effect`.
 pub struct EffectBuilder<'a> {
     factory: &'a mut Factory,
     out: &'a Target,
@@ -187,6 +436,9 @@ pub struct EffectBuilder<'a> {
     prim: Primitive,
     prog: ProgramSource<'a>,
     rast: Rasterizer,
+    two_sided: bool,
+    srgb_output: Option<bool>,
+    blend: Option<Blend>,
     const_bufs: Vec<BufferInfo>,
 }
 
@@ -207,6 +459,9 @@ impl<'a> EffectBuilder<'a> {
             init: Init::default(),
             prim: Primitive::TriangleList,
             rast,
+            two_sided: false,
+            srgb_output: None,
+            blend: None,
             prog: src,
             const_bufs: Vec::new(),
         }
@@ -218,12 +473,41 @@ impl<'a> EffectBuilder<'a> {
         self
     }
 
+    /// Cull front faces instead of back faces, drawing only the inside of closed meshes.
+    pub fn with_front_face_culling(&mut self) -> &mut Self {
+        self.rast.cull_face = CullFace::Front;
+        self
+    }
+
+    /// Also builds a pipeline state without back face culling, for `Effect::draw_two_sided`.
+    pub fn with_two_sided_variant(&mut self) -> &mut Self {
+        self.two_sided = true;
+        self
+    }
+
+    /// Rasterize polygons as their outlines instead of filling them.
+    ///
+    /// Face culling is left as configured, so back faces stay hidden unless back face culling is
+    /// disabled as well.
+    pub fn with_wireframe(&mut self) -> &mut Self {
+        self.rast.method = RasterMethod::Line(1);
+        self
+    }
+
     /// Adds a global constant to this `Effect`.
     pub fn with_raw_global(&mut self, name: &'a str) -> &mut Self {
         self.init.globals.push(name);
         self
     }
 
+    /// Adds a constant buffer holding `num` values of the uniform block `T` to this `Effect`.
+    ///
+    /// The buffer is sized for the std140 layout of `T`, which `Effect::update_constant_buffer`
+    /// writes with `T::std140()`.
+    pub fn with_constant_buffer<T: Uniform>(&mut self, name: &'a str, num: usize) -> &mut Self {
+        self.with_raw_constant_buffer(name, mem::size_of::<T::Std140>(), num)
+    }
+
     /// Adds a raw uniform constant to this `Effect`.
     ///
     /// Requests a new constant buffer to be created
@@ -250,15 +534,29 @@ impl<'a> EffectBuilder<'a> {
         self
     }
 
+    /// Sets several outputs of the PSO at once, for targets with more than one color buffer.
+    ///
+    /// The shader output named `names[i]` is written to the target's color buffer `i`. If the
+    /// target contains a depth buffer, its mode will be set by `depth`.
+    pub fn with_outputs(&mut self, names: &[&'a str], depth: Option<DepthMode>) -> &mut Self {
+        for name in names {
+            self.with_output(name, depth);
+        }
+        self
+    }
+
     /// Sets the output target of the PSO.
     ///
-    /// If the target contains a depth buffer, its mode will be set by `depth`.
+    /// If the target contains a depth buffer, its mode will be set by `depth`. Calling this
+    /// again adds another output, written to the target's next color buffer. Whether the target
+    /// is sRGB-encoded is declared with `with_srgb_output`.
     pub fn with_output(&mut self, name: &'a str, depth: Option<DepthMode>) -> &mut Self {
         if let Some(depth) = depth {
             self.init.out_depth = Some((
                 match depth {
                     DepthMode::LessEqualTest => LESS_EQUAL_TEST,
                     DepthMode::LessEqualWrite => LESS_EQUAL_WRITE,
+                    DepthMode::EqualTest => EQUAL_TEST,
                 },
                 Stencil::default(),
             ));
@@ -292,6 +590,7 @@ impl<'a> EffectBuilder<'a> {
                 match depth {
                     DepthMode::LessEqualTest => LESS_EQUAL_TEST,
                     DepthMode::LessEqualWrite => LESS_EQUAL_WRITE,
+                    DepthMode::EqualTest => EQUAL_TEST,
                 },
                 Stencil::default(),
             ));
@@ -310,7 +609,51 @@ impl<'a> EffectBuilder<'a> {
         self
     }
 
+    /// Blends the outputs set with `with_output` and `with_outputs` with what the target holds,
+    /// instead of replacing it.
+    ///
+    /// `transparent` has presets for straight alpha (`ALPHA`), additive (`ADDITIVE`) and
+    /// premultiplied alpha (`PREMULTIPLIED`) blending. Outputs set with `with_blended_output`
+    /// keep their own blend state.
+    ///
+    /// ```rust,ignore
+    /// effect
+    ///     .simple(VERT_SRC, FRAG_SRC)
+    ///     .with_output("color", Some(DepthMode::LessEqualTest))
+    ///     .with_blend(ADDITIVE)
+    ///     .build()
+    /// ```
+    pub fn with_blend(&mut self, blend: Blend) -> &mut Self {
+        self.blend = Some(blend);
+        self
+    }
+
+    /// Blends the outputs like `with_blend`, with the color and alpha channels both combined by
+    /// `equation` from the source times `source` and the target's color times `destination`.
+    pub fn with_blend_factors(
+        &mut self,
+        equation: Equation,
+        source: Factor,
+        destination: Factor,
+    ) -> &mut Self {
+        self.with_blend(Blend::new(equation, source, destination))
+    }
+
+    /// Declares whether the outputs are sRGB-encoded, instead of guessing by the target.
+    ///
+    /// The color formats of the targets are linear, so the target's encoding isn't applied or
+    /// undone when writing and blending, and shaders encode to sRGB themselves when
+    /// `Effect::srgb_output` says so. Without this, only the backbuffer is taken to be
+    /// sRGB-encoded. Declare `false` for a backbuffer the other passes write linear colors to,
+    /// and `true` for a named target that is shown as it is.
+    pub fn with_srgb_output(&mut self, srgb: bool) -> &mut Self {
+        self.srgb_output = Some(srgb);
+        self
+    }
+
     /// Adds a texture sampler to this `Effect`.
+    ///
+    /// Textures are bound in the order they are declared, see `Effect::add_texture`.
     pub fn with_texture(&mut self, name: &'a str) -> &mut Self {
         self.init.samplers.push(name);
         self.init.textures.push(name);
@@ -337,8 +680,24 @@ impl<'a> EffectBuilder<'a> {
         let fac = &mut self.factory;
         let prog = self.prog.compile(fac)?;
 
+        if let Some(blend) = self.blend.take() {
+            blend_outputs(&mut self.init, blend);
+        }
+
         debug!("Creating pipeline state");
-        let pso = fac.create_pipeline_state(&prog, self.prim, self.rast, self.init.clone())?;
+        let pso = fac
+            .create_pipeline_state(&prog, self.prim, self.rast, self.init.clone())
+            .map_err(pipeline_error)?;
+        let two_sided_pso = if self.two_sided {
+            let mut rast = self.rast;
+            rast.cull_face = CullFace::Nothing;
+            Some(
+                fac.create_pipeline_state(&prog, self.prim, rast, self.init.clone())
+                    .map_err(pipeline_error)?,
+            )
+        } else {
+            None
+        };
         let mut data = Data::default();
 
         debug!("Creating raw constant buffers");
@@ -392,8 +751,54 @@ impl<'a> EffectBuilder<'a> {
         Ok(Effect {
             pso,
             data,
+            two_sided_pso,
             const_bufs,
             globals,
+            target: self.out.name().to_string(),
+            srgb_output: self.srgb_output,
+            pass: String::new(),
+            warned: HashSet::default(),
         })
     }
 }
+
+/// Turns the unblended outputs of `init` into outputs blended with `blend`.
+///
+/// They are bound to the target's color buffers ahead of the outputs blended already, in the
+/// order they were declared.
+fn blend_outputs(init: &mut Init<'_>, blend: Blend) {
+    let blended = init
+        .out_colors
+        .drain(..)
+        .map(|name| (name, ColorMask::all(), blend));
+    let previous = mem::replace(&mut init.out_blends, blended.collect());
+    init.out_blends.extend(previous);
+}
+
+#[cfg(test)]
+mod tests {
+    use gfx::{
+        preset::blend::{ALPHA, REPLACE},
+        state::ColorMask,
+    };
+
+    use super::{blend_outputs, Init};
+
+    #[test]
+    fn unblended_outputs_come_first() {
+        let mut init = Init::default();
+        init.out_colors = vec!["color", "normal"];
+        init.out_blends = vec![("glow", ColorMask::RED, REPLACE)];
+        blend_outputs(&mut init, ALPHA);
+
+        assert!(init.out_colors.is_empty());
+        assert_eq!(
+            init.out_blends,
+            vec![
+                ("color", ColorMask::all(), ALPHA),
+                ("normal", ColorMask::all(), ALPHA),
+                ("glow", ColorMask::RED, REPLACE),
+            ]
+        );
+    }
+}
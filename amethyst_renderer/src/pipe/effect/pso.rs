@@ -7,6 +7,7 @@ use gfx::{
         target, DataBind, DataLink, Descriptor, InitError, PipelineData, PipelineInit,
     },
     shade::core::{BaseType, ContainerType, OutputVar, ProgramInfo},
+    Rect,
 };
 use serde::Serialize;
 
@@ -131,7 +132,6 @@ impl<'d> PipelineInit for Init<'d> {
         if let Some(depth) = self.out_depth {
             let mut meta_depth = <DepthStencilTarget as DataLink<'d>>::new();
             if let Some(d) = meta_depth.link_depth_stencil(&depth) {
-                desc.scissor = meta_depth.link_scissor();
                 desc.depth_stencil = Some(d);
             }
             meta.out_depth = Some(meta_depth);
@@ -176,6 +176,9 @@ impl<'d> PipelineInit for Init<'d> {
             meta.vertex_bufs.push(meta_vbuf);
         }
 
+        // Always on, `Data::scissor` defaults to the whole target.
+        desc.scissor = true;
+
         Ok(meta)
     }
 }
@@ -190,6 +193,24 @@ pub struct Data {
     pub samplers: Vec<<Sampler as DataBind<Resources>>::Data>,
     pub textures: Vec<<RawShaderResource as DataBind<Resources>>::Data>,
     pub vertex_bufs: Vec<<RawVertexBuffer as DataBind<Resources>>::Data>,
+    /// Region of the targets to draw into, or all of them if `None`.
+    pub scissor: Option<Rect>,
+}
+
+impl Data {
+    /// Returns the width and height of the targets drawn into.
+    pub fn target_size(&self) -> Option<(u16, u16)> {
+        self.out_colors
+            .first()
+            .or_else(|| self.out_blends.first())
+            .map(|view| view.get_dimensions())
+            .or_else(|| {
+                self.out_depth
+                    .as_ref()
+                    .map(|(view, _)| view.get_dimensions())
+            })
+            .map(|(width, height, _, _)| (width, height))
+    }
 }
 
 impl PipelineData<Resources> for Data {
@@ -235,5 +256,10 @@ impl PipelineData<Resources> for Data {
         for (meta_vbuf, vbuf) in vertex_bufs {
             meta_vbuf.bind_to(out, &vbuf, mgr, acc);
         }
+
+        let full = self.target_size().map(|(w, h)| Rect { x: 0, y: 0, w, h });
+        if let Some(scissor) = self.scissor.or(full) {
+            out.scissor = scissor;
+        }
     }
 }
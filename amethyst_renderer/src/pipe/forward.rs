@@ -0,0 +1,112 @@
+//! The standard forward pipeline, composed from the built-in passes.
+
+use hetseq::Queue;
+
+use crate::pass::{DrawFlat2D, DrawSkyBox};
+
+use super::{
+    pass::Pass,
+    pipe::{Pipeline, PipelineBuilder},
+    stage::{Stage, StageBuilder},
+};
+
+/// The `PipelineBuilder` returned by `ForwardPipeline::build`, drawing a `DrawSkyBox`, the scene
+/// pass `P` and a `DrawFlat2D` into the backbuffer, one stage each.
+pub type ForwardPipelineBuilder<P> = PipelineBuilder<
+    Queue<(
+        Queue<(
+            Queue<(Queue<()>, StageBuilder<Queue<(Queue<()>, DrawSkyBox)>>)>,
+            StageBuilder<Queue<(Queue<()>, P)>>,
+        )>,
+        StageBuilder<Queue<(Queue<()>, DrawFlat2D)>>,
+    )>,
+>;
+
+/// Composes the common forward pipeline into the backbuffer around a scene pass, e.g.
+/// `DrawShaded` or `DrawPbm`
+///
+/// The stages are drawn in this order:
+///
+/// 1. The `SkyBox`, first so transparent objects blend over the sky rather than the clear color.
+///    The stage skips its color clear wherever the sky covers the window.
+/// 2. The scene pass, which draws its opaque objects and then its transparent ones, sorted.
+/// 3. Sprites with `DrawFlat2D`, blended over the scene.
+///
+/// Stages that are turned off are skipped, the clear moving on to the scene stage without the
+/// skybox. The result is a plain `PipelineBuilder`, so more stages can be appended, e.g. the UI
+/// of `amethyst_ui`, which has to come last:
+///
+/// ```rust,ignore
+/// let pipe = ForwardPipeline::new(DrawShaded::<PosNormTex>::new())
+///     .with_clear_color([0.1, 0.1, 0.1, 1.0])
+///     .with_sprites(false)
+///     .build()
+///     .with_stage(Stage::with_backbuffer().with_pass(DrawUi::new()));
+/// ```
+#[derive(Clone, Debug)]
+pub struct ForwardPipeline<P> {
+    scene: P,
+    clear_color: [f32; 4],
+    skybox: bool,
+    sprites: bool,
+}
+
+impl<P: Pass> ForwardPipeline<P> {
+    /// Creates a pipeline drawing the scene with `scene`, clearing to black and drawing both
+    /// the skybox and sprites.
+    pub fn new(scene: P) -> Self {
+        ForwardPipeline {
+            scene,
+            clear_color: [0.0, 0.0, 0.0, 1.0],
+            skybox: true,
+            sprites: true,
+        }
+    }
+
+    /// Sets the color the backbuffer is cleared to behind the scene.
+    pub fn with_clear_color<C: Into<[f32; 4]>>(mut self, color: C) -> Self {
+        self.clear_color = color.into();
+        self
+    }
+
+    /// Sets whether to draw the `SkyBox` behind the scene.
+    pub fn with_skybox(mut self, skybox: bool) -> Self {
+        self.skybox = skybox;
+        self
+    }
+
+    /// Sets whether to draw sprites over the scene.
+    pub fn with_sprites(mut self, sprites: bool) -> Self {
+        self.sprites = sprites;
+        self
+    }
+
+    /// Returns the builder of the pipeline, ready to be handed to the renderer or extended.
+    pub fn build(self) -> ForwardPipelineBuilder<P> {
+        // The first stage drawn clears the backbuffer.
+        let (sky_clear, scene_clear) = if self.skybox {
+            (Some(self.clear_color), None)
+        } else {
+            (None, Some(self.clear_color))
+        };
+
+        Pipeline::build()
+            .with_stage(
+                Stage::with_backbuffer()
+                    .clear_target(sky_clear, sky_clear.map(|_| 1.0))
+                    .skip_clear_under_sky(true)
+                    .enabled(self.skybox)
+                    .with_pass(DrawSkyBox::new()),
+            )
+            .with_stage(
+                Stage::with_backbuffer()
+                    .clear_target(scene_clear, scene_clear.map(|_| 1.0))
+                    .with_pass(self.scene),
+            )
+            .with_stage(
+                Stage::with_backbuffer()
+                    .enabled(self.sprites)
+                    .with_pass(DrawFlat2D::new()),
+            )
+    }
+}
@@ -18,14 +18,27 @@
 
 pub use self::{
     effect::{Data, DepthMode, Effect, EffectBuilder, Init, Meta, NewEffect},
+    forward::{ForwardPipeline, ForwardPipelineBuilder},
     pipe::{Pipeline, PipelineBuild, PipelineBuilder, PipelineData, PolyPipeline, PolyStages},
+    reload::{watch_shaders, BUILTIN_SHADER_DIR},
     stage::{PolyStage, Stage, StageBuilder},
     target::{ColorBuffer, DepthBuffer, Target, TargetBuilder, Targets},
+    timing::PassTimer,
 };
 
+#[cfg(feature = "gpu_timing")]
+pub use self::timing::PassTimings;
+
+#[cfg(feature = "gpu_timing")]
+pub(crate) use self::timing::GpuTimer;
+pub(crate) use self::{effect::ProgramSource, reload::shaders_changed};
+
 pub mod pass;
 
 mod effect;
+mod forward;
 mod pipe;
+mod reload;
 mod stage;
 mod target;
+mod timing;
@@ -1,13 +1,25 @@
 //! Types for constructing render passes.
 
+use std::{any::TypeId, fmt::Debug};
+
+use derivative::Derivative;
+use fnv::FnvHashSet as HashSet;
+use gfx::memory::Typed;
+use log::error;
+
 use amethyst_core::specs::prelude::SystemData;
-use amethyst_error::Error;
+use amethyst_error::{Error, ResultExt};
 
 use crate::{
-    pipe::{Effect, NewEffect, Target},
-    types::{Encoder, Factory},
+    error,
+    pass::{fullscreen_slice, target_view},
+    pipe::{timing::short_type_name, Effect, NewEffect, PassTimer, Target, Targets},
+    tex::{FilterMethod, SamplerInfo, WrapMode},
+    types::{Encoder, Factory, Sampler, ShaderResourceView},
 };
 
+static FULLSCREEN_SRC: &[u8] = include_bytes!("../pass/shaders/vertex/fullscreen.glsl");
+
 /// Used to fetch data from the game world for rendering in the pass.
 pub trait PassData<'a> {
     /// The data itself.
@@ -15,6 +27,8 @@ pub trait PassData<'a> {
 }
 
 /// Structures implementing this provide a renderer pass.
+///
+/// Full-screen effects that only read other targets can implement the simpler `PostPass` instead.
 pub trait Pass: for<'a> PassData<'a> {
     /// The pass is given an opportunity to compile shaders and store them in an `Effect`
     /// which is then passed to the pass in `apply`.
@@ -29,6 +43,204 @@ pub trait Pass: for<'a> PassData<'a> {
         factory: Factory,
         data: <Self as PassData<'b>>::Data,
     );
+
+    /// Called whenever the pipeline's targets were recreated, e.g. after the window was resized.
+    ///
+    /// Passes that read from targets other than the one they render to can pick up the new views
+    /// here. The pass's own output is updated by the renderer.
+    fn new_targets(&mut self, _targets: &Targets) {}
+}
+
+/// A full-screen effect that reads the color buffers of other targets and nothing from the world
+///
+/// Post effects are added to a stage wrapped in a `PostProcess`, which implements `Pass` for them.
+/// It looks up the inputs, binds them, covers the stage's target with the fragment shader and
+/// picks up the new inputs when the targets are recreated. The shader gets the position of each
+/// pixel as `in vec2 tex_coord`, from `(0, 0)` at the bottom left to `(1, 1)` at the top right,
+/// and writes `out vec4 color`.
+///
+/// ```ignore
+/// struct Invert;
+///
+/// impl PostPass for Invert {
+///     fn fragment_shader(&self) -> &'static [u8] {
+///         include_bytes!("invert.glsl")
+///     }
+///
+///     fn inputs(&self) -> Vec<(&'static str, &str)> {
+///         vec![("source", "scene")]
+///     }
+/// }
+///
+/// Stage::with_backbuffer().with_pass(PostProcess::new(Invert))
+/// ```
+pub trait PostPass {
+    /// Returns the source of the fragment shader.
+    fn fragment_shader(&self) -> &'static [u8];
+
+    /// Returns the textures the shader samples, as the name of each sampler uniform and of the
+    /// target whose first color buffer is bound to it.
+    fn inputs(&self) -> Vec<(&'static str, &str)>;
+
+    /// Returns the names of the shader's other uniforms, none by default.
+    fn globals(&self) -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    /// Returns how the inputs are sampled, bilinear and clamped to their edges by default.
+    fn sampler(&self) -> SamplerInfo {
+        SamplerInfo::new(FilterMethod::Bilinear, WrapMode::Clamp)
+    }
+
+    /// Sets the uniforms named in `globals` before the effect is drawn.
+    ///
+    /// `size` is the size of the first input in pixels.
+    fn update(&mut self, _effect: &mut Effect, _size: (u32, u32)) {}
+}
+
+/// Applies a `PostPass` as a pass of a stage
+///
+/// Disabling the pass in `DisabledPasses` goes by the wrapped type too, e.g.
+/// `disable::<PostProcess<Invert>>()`.
+#[derive(Derivative, Clone)]
+#[derivative(Debug(bound = "P: Debug"))]
+pub struct PostProcess<P> {
+    inner: P,
+    size: (u32, u32),
+    #[derivative(Debug = "ignore")]
+    views: Vec<ShaderResourceView<[f32; 4]>>,
+    #[derivative(Debug = "ignore")]
+    sampler: Option<Sampler>,
+}
+
+impl<P: PostPass> PostProcess<P> {
+    /// Wraps the post effect `inner`.
+    pub fn new(inner: P) -> Self {
+        PostProcess {
+            inner,
+            size: (1, 1),
+            views: Vec::new(),
+            sampler: None,
+        }
+    }
+}
+
+impl<'a, P> PassData<'a> for PostProcess<P> {
+    type Data = ();
+}
+
+impl<P: PostPass> Pass for PostProcess<P> {
+    fn compile(&mut self, effect: NewEffect<'_>) -> Result<Effect, Error> {
+        use gfx::Factory;
+
+        let mut views = Vec::new();
+        for (_, name) in self.inner.inputs() {
+            let input = effect
+                .target(name)
+                .ok_or_else(|| error::Error::NoSuchTarget(name.to_string()))?;
+            let view = target_view(input)
+                .ok_or_else(|| error::Error::TargetNotReadable(name.to_string()))?;
+            if views.is_empty() {
+                self.size = input.size();
+            }
+            views.push(view);
+        }
+        self.views = views;
+        self.sampler = Some(effect.factory.create_sampler(self.inner.sampler()));
+
+        let mut builder = effect.simple(FULLSCREEN_SRC, self.inner.fragment_shader());
+        for (sampler, _) in self.inner.inputs() {
+            builder.with_texture(sampler);
+        }
+        for global in self.inner.globals() {
+            builder.with_raw_global(global);
+        }
+        builder.with_output("color", None).build()
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        _factory: Factory,
+        (): <Self as PassData<'a>>::Data,
+    ) {
+        let sampler = match self.sampler {
+            Some(ref sampler) => sampler,
+            None => return,
+        };
+
+        for view in &self.views {
+            effect.add_raw_texture(view.raw(), sampler);
+        }
+        self.inner.update(effect, self.size);
+        effect.draw(&fullscreen_slice(), encoder);
+        effect.clear();
+    }
+
+    fn new_targets(&mut self, targets: &Targets) {
+        for (i, (_, name)) in self.inner.inputs().into_iter().enumerate() {
+            let input = match targets.get(name) {
+                Some(input) => input,
+                None => {
+                    error!("Post-process input target {:?} not found!", name);
+                    continue;
+                }
+            };
+            if i == 0 {
+                self.size = input.size();
+            }
+            if let (Some(view), Some(slot)) = (target_view(input), self.views.get_mut(i)) {
+                *slot = view;
+            }
+        }
+    }
+}
+
+/// Resource listing the passes to skip, by their type
+///
+/// A disabled pass draws nothing and keeps its state until it is enabled again, so passes can be
+/// toggled at runtime, e.g. from a debug menu, without rebuilding the pipeline. Type parameters
+/// count, `DrawShaded<PosNormTex>` and `DrawShaded<PosNormTangTex>` are toggled separately.
+///
+/// While `DrawSkyBox` is disabled, stages set to skip their color clear under the sky clear it as
+/// usual, so the last frame drawn with the sky doesn't stay behind.
+#[derive(Clone, Debug, Default)]
+pub struct DisabledPasses {
+    passes: HashSet<TypeId>,
+}
+
+impl DisabledPasses {
+    /// Skips the pass `P` from the next frame on.
+    pub fn disable<P: Pass + 'static>(&mut self) {
+        self.passes.insert(TypeId::of::<P>());
+    }
+
+    /// Applies the pass `P` again from the next frame on.
+    pub fn enable<P: Pass + 'static>(&mut self) {
+        self.passes.remove(&TypeId::of::<P>());
+    }
+
+    /// Enables or disables the pass `P`.
+    pub fn set_enabled<P: Pass + 'static>(&mut self, enabled: bool) {
+        if enabled {
+            self.enable::<P>();
+        } else {
+            self.disable::<P>();
+        }
+    }
+
+    /// Flips whether the pass `P` is enabled, returning whether it is now.
+    pub fn toggle<P: Pass + 'static>(&mut self) -> bool {
+        let enabled = !self.is_enabled::<P>();
+        self.set_enabled::<P>(enabled);
+        enabled
+    }
+
+    /// Returns whether the pass `P` is applied, which all passes are by default.
+    pub fn is_enabled<P: Pass + 'static>(&self) -> bool {
+        !self.passes.contains(&TypeId::of::<P>())
+    }
 }
 
 /// A compiled pass.  These are created and managed by the `Renderer`.  This should not be
@@ -47,9 +259,14 @@ where
         mut pass: P,
         fac: &mut Factory,
         out: &Target,
+        targets: &Targets,
         multisampling: u16,
     ) -> Result<Self, Error> {
-        let effect = pass.compile(NewEffect::new(fac, out, multisampling))?;
+        let name = short_type_name(std::any::type_name::<P>());
+        let mut effect = pass
+            .compile(NewEffect::new(fac, out, targets, multisampling))
+            .with_context(|_| error::Error::CompilePass(name.clone()))?;
+        effect.set_pass_name(name);
         Ok(CompiledPass {
             effect,
             inner: pass,
@@ -58,35 +275,83 @@ where
 }
 
 impl<P> CompiledPass<P> {
-    /// Applies the inner pass.
+    /// Applies the inner pass, unless it is disabled.
     pub fn apply<'a, 'b: 'a>(
         &'a mut self,
         encoder: &mut Encoder,
+        timer: &mut PassTimer<'_>,
+        disabled: &DisabledPasses,
         factory: Factory,
         data: <P as PassData<'b>>::Data,
+    ) where
+        P: Pass + 'static,
+    {
+        if !disabled.is_enabled::<P>() {
+            return;
+        }
+        let CompiledPass {
+            ref mut effect,
+            ref mut inner,
+        } = *self;
+        timer.time::<P, _>(encoder, |encoder| {
+            inner.apply(encoder, effect, factory, data)
+        })
+    }
+
+    /// Compiles the inner pass again, keeping the current effect if that fails.
+    pub(crate) fn recompile(
+        &mut self,
+        fac: &mut Factory,
+        out: &Target,
+        targets: &Targets,
+        multisampling: u16,
     ) where
         P: Pass,
     {
-        self.inner.apply(encoder, &mut self.effect, factory, data)
+        match self
+            .inner
+            .compile(NewEffect::new(fac, out, targets, multisampling))
+        {
+            Ok(mut effect) => {
+                effect.set_pass_name(short_type_name(std::any::type_name::<P>()));
+                self.effect = effect;
+            }
+            Err(err) => error!(
+                "Failed to recompile pass {}: {}",
+                short_type_name(std::any::type_name::<P>()),
+                err
+            ),
+        }
     }
 
     /// Distributes new target data to the pass.
-    pub fn new_target(&mut self, target: &Target) {
-        // Distribute new targets that don't blend.
-        self.effect.data.out_colors.clear();
-        self.effect
-            .data
-            .out_colors
-            .extend(target.color_bufs().iter().map(|cb| &cb.as_output).cloned());
-
-        // Distribute new blend targets
-        self.effect.data.out_blends.clear();
-        self.effect
-            .data
-            .out_blends
-            .extend(target.color_bufs().iter().map(|cb| &cb.as_output).cloned());
-
-        // Distribute new depth buffer
-        self.effect.data.out_depth = target.depth_buf().map(|db| (db.as_output.clone(), (0, 0)));
+    pub fn new_target(&mut self, target: &Target, targets: &Targets)
+    where
+        P: Pass,
+    {
+        self.effect.retarget(target);
+        self.inner.new_targets(targets);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pass::{DrawSkyBox, DrawSkyGradient};
+
+    use super::DisabledPasses;
+
+    #[test]
+    fn passes_toggle_by_type() {
+        let mut disabled = DisabledPasses::default();
+        assert!(disabled.is_enabled::<DrawSkyBox>());
+
+        disabled.disable::<DrawSkyBox>();
+        assert!(!disabled.is_enabled::<DrawSkyBox>());
+        assert!(disabled.is_enabled::<DrawSkyGradient>());
+
+        assert!(disabled.toggle::<DrawSkyBox>());
+        assert!(!disabled.toggle::<DrawSkyGradient>());
+        assert!(disabled.is_enabled::<DrawSkyBox>());
+        assert!(!disabled.is_enabled::<DrawSkyGradient>());
     }
 }
@@ -6,13 +6,14 @@ use amethyst_error::Error;
 
 use crate::types::{Encoder, Factory};
 
-use super::{stage::*, target::*};
+use super::{stage::*, target::*, timing::PassTimer};
 
 /// Defines how the rendering pipeline should be configured.
 #[derive(Clone, Debug)]
 pub struct Pipeline<L> {
     stages: L,
     targets: HashMap<String, Target>,
+    builders: Vec<TargetBuilder>,
 }
 
 impl Pipeline<List<()>> {
@@ -41,12 +42,16 @@ pub trait PolyStages: for<'a> StagesData<'a> {
     fn apply<'a, 'b: 'a>(
         &'a mut self,
         encoders: &mut Encoder,
+        timer: &mut PassTimer<'_>,
         factory: Factory,
         data: <Self as StagesData<'b>>::Data,
     );
 
     /// Distributes new targets
     fn new_targets(&mut self, new_targets: &HashMap<String, Target>);
+
+    /// Compiles the passes of every stage again
+    fn recompile(&mut self, fac: &mut Factory, targets: &Targets, multisampling: u16);
 }
 
 impl<'a, HS> StagesData<'a> for List<(HS, List<()>)>
@@ -63,17 +68,23 @@ where
     fn apply<'a, 'b: 'a>(
         &'a mut self,
         encoders: &mut Encoder,
+        timer: &mut PassTimer<'_>,
         factory: Factory,
         hd: <HS as StageData<'b>>::Data,
     ) {
         let List((ref mut hs, _)) = *self;
-        hs.apply(encoders, factory, hd);
+        hs.apply(encoders, timer, factory, hd);
     }
 
     fn new_targets(&mut self, new_targets: &HashMap<String, Target>) {
         let List((ref mut hs, _)) = *self;
         HS::new_targets(hs, new_targets);
     }
+
+    fn recompile(&mut self, fac: &mut Factory, targets: &Targets, multisampling: u16) {
+        let List((ref mut hs, _)) = *self;
+        hs.recompile(fac, targets, multisampling);
+    }
 }
 
 impl<'a, HS, TS> StagesData<'a> for List<(HS, TS)>
@@ -92,12 +103,13 @@ where
     fn apply<'a, 'b: 'a>(
         &'a mut self,
         encoders: &mut Encoder,
+        timer: &mut PassTimer<'_>,
         factory: Factory,
         (hd, td): <Self as StagesData<'b>>::Data,
     ) {
         let List((ref mut hs, ref mut ts)) = *self;
-        hs.apply(encoders, factory.clone(), hd);
-        ts.apply(encoders, factory, td);
+        hs.apply(encoders, timer, factory.clone(), hd);
+        ts.apply(encoders, timer, factory, td);
     }
 
     fn new_targets(&mut self, new_targets: &HashMap<String, Target>) {
@@ -105,6 +117,12 @@ where
         HS::new_targets(hs, new_targets);
         TS::new_targets(ts, new_targets);
     }
+
+    fn recompile(&mut self, fac: &mut Factory, targets: &Targets, multisampling: u16) {
+        let List((ref mut hs, ref mut ts)) = *self;
+        hs.recompile(fac, targets, multisampling);
+        ts.recompile(fac, targets, multisampling);
+    }
 }
 
 /// The data requested from the `specs::World` by the Pipeline.
@@ -119,6 +137,7 @@ pub trait PolyPipeline: for<'a> PipelineData<'a> {
     fn apply<'a, 'b: 'a>(
         &'a mut self,
         encoder: &mut Encoder,
+        timer: &mut PassTimer<'_>,
         factory: Factory,
         data: <Self as PipelineData<'b>>::Data,
     );
@@ -126,8 +145,31 @@ pub trait PolyPipeline: for<'a> PipelineData<'a> {
     /// Resizes the pipeline targets
     fn new_targets(&mut self, new_targets: HashMap<String, Target>);
 
+    /// Recreates the targets for a window of `size` pixels drawing into `main`, and hands them to
+    /// the passes, which keep their shaders.
+    ///
+    /// By default every target is rebuilt at the new size with as many buffers as before.
+    fn resize(&mut self, fac: &mut Factory, main: Target, size: (u32, u32)) -> Result<(), Error> {
+        let mut targets = HashMap::default();
+        for (key, value) in self.targets().iter().filter(|&(k, _)| !k.is_empty()) {
+            let (key, target) = TargetBuilder::new(key.clone())
+                .with_num_color_bufs(value.color_bufs().len())
+                .with_depth_buf(value.depth_buf().is_some())
+                .build(fac, size)?;
+            targets.insert(key, target);
+        }
+        targets.insert(String::new(), main);
+        self.new_targets(targets);
+        Ok(())
+    }
+
     /// Returns an immutable reference to all targets and their name strings.
     fn targets(&self) -> &HashMap<String, Target>;
+
+    /// Compiles the passes again after their shaders changed, see `watch_shaders`.
+    ///
+    /// Does nothing by default.
+    fn recompile(&mut self, _fac: &mut Factory, _multisampling: u16) {}
 }
 
 impl<'a, L> PipelineData<'a> for Pipeline<L>
@@ -144,10 +186,11 @@ where
     fn apply<'a, 'b: 'a>(
         &'a mut self,
         encoders: &mut Encoder,
+        timer: &mut PassTimer<'_>,
         factory: Factory,
         data: <L as StagesData<'b>>::Data,
     ) {
-        self.stages.apply(encoders, factory, data);
+        self.stages.apply(encoders, timer, factory, data);
     }
 
     fn new_targets(&mut self, new_targets: HashMap<String, Target>) {
@@ -155,10 +198,32 @@ where
         self.targets = new_targets;
     }
 
+    /// Rebuilds the targets from the builders they were declared with, so fixed size targets
+    /// are kept as they are.
+    fn resize(&mut self, fac: &mut Factory, main: Target, size: (u32, u32)) -> Result<(), Error> {
+        let mut targets = HashMap::default();
+        for builder in &self.builders {
+            let (name, target) = match self.targets.get(builder.name()) {
+                Some(target) if target.size() == builder.size_for(size) => {
+                    (builder.name().to_string(), target.clone())
+                }
+                _ => builder.clone().build(fac, size)?,
+            };
+            targets.insert(name, target);
+        }
+        targets.insert(String::new(), main);
+        self.new_targets(targets);
+        Ok(())
+    }
+
     /// Returns an immutable reference to all targets and their name strings.
     fn targets(&self) -> &HashMap<String, Target> {
         self.targets()
     }
+
+    fn recompile(&mut self, fac: &mut Factory, multisampling: u16) {
+        self.stages.recompile(fac, &self.targets, multisampling);
+    }
 }
 
 /// Constructs a new pipeline with the given render targets and layers.
@@ -228,15 +293,15 @@ where
 {
     type Pipeline = Pipeline<R>;
     fn build(
-        mut self,
+        self,
         fac: &mut Factory,
         out: &Target,
         multisampling: u16,
     ) -> Result<Pipeline<R>, Error> {
-        let mut targets = self
-            .targets
-            .drain(..)
-            .map(|tb| tb.build(fac, out.size()))
+        let builders = self.targets;
+        let mut targets = builders
+            .iter()
+            .map(|tb| tb.clone().build(fac, out.size()))
             .collect::<Result<Targets, Error>>()?;
 
         targets.insert("".into(), out.clone());
@@ -249,7 +314,11 @@ where
             .fmap(BuildStage::new(fac, &targets, multisampling))
             .r#try()?;
 
-        Ok(Pipeline { stages, targets })
+        Ok(Pipeline {
+            stages,
+            targets,
+            builders,
+        })
     }
 }
 
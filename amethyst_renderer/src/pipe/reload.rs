@@ -0,0 +1,195 @@
+//! Reloading shaders from disk while they are being edited, in debug builds.
+
+use std::{borrow::Cow, path::PathBuf};
+
+#[cfg(debug_assertions)]
+use std::{
+    cell::RefCell,
+    fs,
+    path::Path,
+    time::{Duration, Instant, SystemTime},
+};
+
+#[cfg(debug_assertions)]
+use fnv::FnvHashMap as HashMap;
+#[cfg(debug_assertions)]
+use log::{error, info};
+
+/// How often the watched shader files are checked for changes.
+#[cfg(debug_assertions)]
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Directory holding the shaders of the built-in passes, in the renderer's source tree.
+pub const BUILTIN_SHADER_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/pass/shaders");
+
+#[cfg(debug_assertions)]
+thread_local! {
+    static WATCHER: RefCell<Watcher> = RefCell::new(Watcher::default());
+}
+
+/// Recompiles the passes whenever one of their shaders in `dir` changes, in debug builds only.
+///
+/// Passes embed their shaders with `include_bytes!`. Once a directory is watched, each embedded
+/// shader is matched to the file under `dir` with the same contents as its effect is built, and
+/// read from that file from then on. `BUILTIN_SHADER_DIR` holds the shaders of the built-in
+/// passes; custom passes can watch the directory of their own. Call this on the rendering thread
+/// before the pipeline is built.
+///
+/// A shader that fails to compile is reported in the log, and its pass keeps the previous
+/// effect until the file is fixed. Release builds always use the embedded shaders.
+#[cfg(debug_assertions)]
+pub fn watch_shaders<P: Into<PathBuf>>(dir: P) {
+    WATCHER.with(|watcher| watcher.borrow_mut().watch(dir.into()));
+}
+
+/// Recompiles the passes whenever one of their shaders in `dir` changes, in debug builds only.
+#[cfg(not(debug_assertions))]
+pub fn watch_shaders<P: Into<PathBuf>>(_dir: P) {}
+
+/// Returns the current source of the embedded shader `embedded`, read from its watched file if
+/// it has one.
+#[cfg(debug_assertions)]
+pub(crate) fn shader_source(embedded: &[u8]) -> Cow<'_, [u8]> {
+    if let Some(path) = WATCHER.with(|watcher| watcher.borrow_mut().file_of(embedded)) {
+        match fs::read(&path) {
+            Ok(source) => return Cow::Owned(source),
+            Err(err) => error!(
+                "Failed to read shader {:?}, using the embedded one: {}",
+                path, err
+            ),
+        }
+    }
+    Cow::Borrowed(embedded)
+}
+
+#[cfg(not(debug_assertions))]
+pub(crate) fn shader_source(embedded: &[u8]) -> Cow<'_, [u8]> {
+    Cow::Borrowed(embedded)
+}
+
+/// Returns whether any watched shader file changed since the last call.
+#[cfg(debug_assertions)]
+pub(crate) fn shaders_changed() -> bool {
+    WATCHER.with(|watcher| watcher.borrow_mut().poll())
+}
+
+#[cfg(not(debug_assertions))]
+pub(crate) fn shaders_changed() -> bool {
+    false
+}
+
+#[cfg(debug_assertions)]
+#[derive(Default)]
+struct Watcher {
+    dirs: Vec<PathBuf>,
+    /// The files under `dirs` with the contents they had when they were first looked at.
+    scanned: Option<Vec<(PathBuf, Vec<u8>)>>,
+    /// Indices into `files` by the address and length of the embedded shaders.
+    sources: HashMap<(usize, usize), Option<usize>>,
+    /// The files embedded shaders are read from, with the time they were last modified.
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+    last_poll: Option<Instant>,
+}
+
+#[cfg(debug_assertions)]
+impl Watcher {
+    fn watch(&mut self, dir: PathBuf) {
+        self.dirs.push(dir);
+        self.scanned = None;
+        // Shaders that matched nothing so far may be in the new directory.
+        self.sources.retain(|_, file| file.is_some());
+    }
+
+    fn file_of(&mut self, embedded: &[u8]) -> Option<PathBuf> {
+        if self.dirs.is_empty() {
+            return None;
+        }
+        let key = (embedded.as_ptr() as usize, embedded.len());
+        if let Some(file) = self.sources.get(&key) {
+            return file.map(|i| self.files[i].0.clone());
+        }
+
+        let path = self
+            .scan()
+            .iter()
+            .find(|&&(_, ref contents)| &contents[..] == embedded)
+            .map(|&(ref path, _)| path.clone());
+        let file = path.map(|path| self.file_index(path));
+        self.sources.insert(key, file);
+        file.map(|i| self.files[i].0.clone())
+    }
+
+    /// Starts watching the file at `path`, unless another embedded shader uses it already.
+    fn file_index(&mut self, path: PathBuf) -> usize {
+        match self.files.iter().position(|&(ref file, _)| *file == path) {
+            Some(i) => i,
+            None => {
+                let modified = modified(&path);
+                self.files.push((path, modified));
+                self.files.len() - 1
+            }
+        }
+    }
+
+    fn scan(&mut self) -> &[(PathBuf, Vec<u8>)] {
+        if self.scanned.is_none() {
+            let mut files = Vec::new();
+            for dir in &self.dirs {
+                read_dir(dir, &mut files);
+            }
+            self.scanned = Some(files);
+        }
+        self.scanned.as_ref().map_or(&[], |files| &files[..])
+    }
+
+    fn poll(&mut self) -> bool {
+        if self.files.is_empty() {
+            return false;
+        }
+        let now = Instant::now();
+        if let Some(last) = self.last_poll {
+            if now.duration_since(last) < POLL_INTERVAL {
+                return false;
+            }
+        }
+        self.last_poll = Some(now);
+
+        let mut changed = false;
+        for &mut (ref path, ref mut last_modified) in &mut self.files {
+            let current = modified(path);
+            if current != *last_modified {
+                info!("Shader {:?} changed, recompiling the passes", path);
+                *last_modified = current;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+/// Reads the contents of every file under `dir` into `files`.
+#[cfg(debug_assertions)]
+fn read_dir(dir: &Path, files: &mut Vec<(PathBuf, Vec<u8>)>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            error!("Failed to watch shaders in {:?}: {}", dir, err);
+            return;
+        }
+    };
+    for path in entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+    {
+        if path.is_dir() {
+            read_dir(&path, files);
+        } else if let Ok(contents) = fs::read(&path) {
+            files.push((path, contents));
+        }
+    }
+}
+
+#[cfg(debug_assertions)]
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
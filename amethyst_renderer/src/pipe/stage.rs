@@ -5,14 +5,19 @@ use fnv::FnvHashMap as HashMap;
 use hetseq::*;
 use log::error;
 
-use amethyst_core::specs::prelude::SystemData;
+use amethyst_core::{
+    specs::prelude::{Join, Read, ReadStorage, SystemData},
+    transform::GlobalTransform,
+};
 use amethyst_error::Error;
 
 use crate::{
+    cam::{ActiveCamera, Camera, CameraTarget, StereoCamera, Viewport},
     error,
+    pass::{get_cameras, DrawSkyBox, SkyBox},
     pipe::{
-        pass::{CompiledPass, Pass, PassData},
-        Target, Targets,
+        pass::{CompiledPass, DisabledPasses, Pass, PassData},
+        PassTimer, Target, Targets,
     },
     types::{Encoder, Factory},
 };
@@ -22,6 +27,7 @@ use crate::{
 pub struct Stage<L> {
     clear_color: Option<[f32; 4]>,
     clear_depth: Option<f32>,
+    sky_clear: bool,
     enabled: bool,
     passes: L,
     target_name: String,
@@ -65,12 +71,17 @@ pub trait Passes: for<'a> PassesData<'a> {
     fn apply<'a, 'b: 'a>(
         &'a mut self,
         encoder: &mut Encoder,
+        timer: &mut PassTimer<'_>,
+        disabled: &DisabledPasses,
         factory: Factory,
         data: <Self as PassesData<'b>>::Data,
     );
 
     /// Distributes new targets
-    fn new_target(&mut self, new_target: &Target);
+    fn new_target(&mut self, new_target: &Target, targets: &Targets);
+
+    /// Compiles the passes again
+    fn recompile(&mut self, fac: &mut Factory, out: &Target, targets: &Targets, multisampling: u16);
 }
 
 impl<'a, HP> PassesData<'a> for List<(CompiledPass<HP>, List<()>)>
@@ -82,21 +93,34 @@ where
 
 impl<HP> Passes for List<(CompiledPass<HP>, List<()>)>
 where
-    HP: Pass,
+    HP: Pass + 'static,
 {
     fn apply<'a, 'b: 'a>(
         &'a mut self,
         encoder: &mut Encoder,
+        timer: &mut PassTimer<'_>,
+        disabled: &DisabledPasses,
         factory: Factory,
         hd: <HP as PassData<'b>>::Data,
     ) {
         let List((ref mut hp, _)) = *self;
-        hp.apply(encoder, factory, hd);
+        hp.apply(encoder, timer, disabled, factory, hd);
+    }
+
+    fn new_target(&mut self, new_target: &Target, targets: &Targets) {
+        let List((ref mut hp, _)) = *self;
+        hp.new_target(new_target, targets);
     }
 
-    fn new_target(&mut self, new_target: &Target) {
+    fn recompile(
+        &mut self,
+        fac: &mut Factory,
+        out: &Target,
+        targets: &Targets,
+        multisampling: u16,
+    ) {
         let List((ref mut hp, _)) = *self;
-        hp.new_target(new_target);
+        hp.recompile(fac, out, targets, multisampling);
     }
 }
 
@@ -110,24 +134,38 @@ where
 
 impl<HP, TP> Passes for List<(CompiledPass<HP>, TP)>
 where
-    HP: Pass,
+    HP: Pass + 'static,
     TP: Passes,
 {
     fn apply<'a, 'b: 'a>(
         &'a mut self,
         encoder: &mut Encoder,
+        timer: &mut PassTimer<'_>,
+        disabled: &DisabledPasses,
         factory: Factory,
         (hd, td): (<HP as PassData<'b>>::Data, <TP as PassesData<'b>>::Data),
     ) {
         let List((ref mut hp, ref mut tp)) = *self;
-        hp.apply(encoder, factory.clone(), hd);
-        tp.apply(encoder, factory, td);
+        hp.apply(encoder, timer, disabled, factory.clone(), hd);
+        tp.apply(encoder, timer, disabled, factory, td);
     }
 
-    fn new_target(&mut self, new_target: &Target) {
+    fn new_target(&mut self, new_target: &Target, targets: &Targets) {
         let List((ref mut hp, ref mut tp)) = *self;
-        hp.new_target(new_target);
-        tp.new_target(new_target);
+        hp.new_target(new_target, targets);
+        tp.new_target(new_target, targets);
+    }
+
+    fn recompile(
+        &mut self,
+        fac: &mut Factory,
+        out: &Target,
+        targets: &Targets,
+        multisampling: u16,
+    ) {
+        let List((ref mut hp, ref mut tp)) = *self;
+        hp.recompile(fac, out, targets, multisampling);
+        tp.recompile(fac, out, targets, multisampling);
     }
 }
 
@@ -136,25 +174,68 @@ pub trait StageData<'a> {
     type Data: SystemData<'a> + Send;
 }
 
+/// World data a `Stage` reads to find out whether a `SkyBox` will cover its whole target.
+type SkyCoverage<'a> = (
+    Read<'a, ActiveCamera>,
+    ReadStorage<'a, Camera>,
+    ReadStorage<'a, Viewport>,
+    ReadStorage<'a, CameraTarget>,
+    ReadStorage<'a, StereoCamera>,
+    ReadStorage<'a, GlobalTransform>,
+    ReadStorage<'a, SkyBox>,
+);
+
+/// Returns whether the sky is drawn over every pixel of `target` this frame.
+///
+/// That requires a `SkyBox` and cameras that all have a view of the whole target, so targets
+/// without a camera or split between several viewports are still cleared.
+fn sky_covers_target(
+    (active, camera, viewport, camera_target, stereo, global, skybox): SkyCoverage<'_>,
+    target: &str,
+) -> bool {
+    if skybox.join().next().is_none() {
+        return false;
+    }
+    get_cameras(
+        active,
+        &camera,
+        &viewport,
+        &camera_target,
+        &stereo,
+        &global,
+        target,
+    )
+    .into_iter()
+    .all(|(camera, viewport)| camera.is_some() && viewport.map_or(true, |v| v.covers_target()))
+}
+
 /// A stage in the rendering.  Contains multiple passes.
 pub trait PolyStage: for<'a> StageData<'a> {
     ///
     fn apply<'a, 'b: 'a>(
         &'a mut self,
         encoder: &mut Encoder,
+        timer: &mut PassTimer<'_>,
         factory: Factory,
         data: <Self as StageData<'b>>::Data,
     );
 
     /// Distributes new targets
     fn new_targets(&mut self, new_targets: &HashMap<String, Target>);
+
+    /// Compiles the passes again, e.g. after their shaders changed
+    fn recompile(&mut self, fac: &mut Factory, targets: &Targets, multisampling: u16);
 }
 
 impl<'a, L> StageData<'a> for Stage<L>
 where
     L: Passes,
 {
-    type Data = <L as PassesData<'a>>::Data;
+    type Data = (
+        SkyCoverage<'a>,
+        Read<'a, DisabledPasses>,
+        <L as PassesData<'a>>::Data,
+    );
 }
 
 impl<L> PolyStage for Stage<L>
@@ -164,31 +245,49 @@ where
     fn apply<'a, 'b: 'a>(
         &'a mut self,
         encoder: &mut Encoder,
+        timer: &mut PassTimer<'_>,
         factory: Factory,
-        data: <L as PassesData<'b>>::Data,
+        (sky, disabled, data): (
+            SkyCoverage<'b>,
+            Read<'b, DisabledPasses>,
+            <L as PassesData<'b>>::Data,
+        ),
     ) {
+        if !self.enabled {
+            return;
+        }
+
         if let Some(color) = self.clear_color {
-            self.target.clear_color(encoder, color);
+            let sky_drawn = disabled.is_enabled::<DrawSkyBox>();
+            if !self.sky_clear || !sky_drawn || !sky_covers_target(sky, &self.target_name) {
+                self.target.clear_color(encoder, color);
+            }
         }
 
         if let Some(depth) = self.clear_depth {
             self.target.clear_depth_stencil(encoder, depth);
         }
 
-        self.passes.apply(encoder, factory, data);
+        timer.begin_stage(&self.target_name);
+        self.passes.apply(encoder, timer, &disabled, factory, data);
     }
 
     fn new_targets(&mut self, new_targets: &HashMap<String, Target>) {
         match new_targets.get(&self.target_name) {
             Some(target) => {
                 self.target = target.clone();
-                self.passes.new_target(target);
+                self.passes.new_target(target, new_targets);
             }
             None => {
                 error!("Target name {:?} not found!", self.target_name);
             }
         }
     }
+
+    fn recompile(&mut self, fac: &mut Factory, targets: &Targets, multisampling: u16) {
+        self.passes
+            .recompile(fac, &self.target, targets, multisampling);
+    }
 }
 
 /// Constructs a new rendering stage.
@@ -197,6 +296,7 @@ where
 pub struct StageBuilder<Q> {
     clear_color: Option<[f32; 4]>,
     clear_depth: Option<f32>,
+    sky_clear: bool,
     enabled: bool,
     passes: Q,
     target_name: String,
@@ -208,6 +308,7 @@ impl StageBuilder<Queue<()>> {
         StageBuilder {
             clear_color: None,
             clear_depth: None,
+            sky_clear: false,
             enabled: true,
             passes: Queue::new(),
             target_name: target_name.into(),
@@ -228,7 +329,33 @@ impl<Q> StageBuilder<Q> {
         self
     }
 
+    /// Clears the color buffers of the stage's target to `color` before the passes run.
+    pub fn clear_color<C: Into<[f32; 4]>>(mut self, color: C) -> Self {
+        self.clear_color = Some(color.into());
+        self
+    }
+
+    /// Clears the depth buffer of the stage's target to `depth` before the passes run.
+    pub fn clear_depth(mut self, depth: f32) -> Self {
+        self.clear_depth = Some(depth);
+        self
+    }
+
+    /// Skips the color clear on frames where a `SkyBox` is drawn over the whole target.
+    ///
+    /// Only enable this for stages with a `DrawSkyBox` pass, which draws behind everything else and
+    /// so overwrites every pixel anyway: its lowest `SkyBox` layer replaces what was there. The
+    /// clear still happens while there is no `SkyBox` or camera, and for cameras with a
+    /// `Viewport` smaller than the target, so the parts outside the views keep the clear color.
+    /// The depth buffer is always cleared. Disabled by default.
+    pub fn skip_clear_under_sky(mut self, skip: bool) -> Self {
+        self.sky_clear = skip;
+        self
+    }
+
     /// Sets whether the `PolyStage` is turned on by default.
+    ///
+    /// A disabled stage neither clears its target nor applies its passes.
     pub fn enabled(mut self, val: bool) -> Self {
         self.enabled = val;
         self
@@ -256,12 +383,13 @@ impl<Q> StageBuilder<Q> {
         let passes = self
             .passes
             .into_list()
-            .fmap(CompilePass::new(fac, &out, multisampling))
+            .fmap(CompilePass::new(fac, &out, targets, multisampling))
             .r#try()?;
 
         Ok(Stage {
             clear_color: self.clear_color,
             clear_depth: self.clear_depth,
+            sky_clear: self.sky_clear,
             enabled: self.enabled,
             passes,
             target: out,
@@ -276,6 +404,7 @@ impl<Q> StageBuilder<Queue<Q>> {
         StageBuilder {
             clear_color: self.clear_color,
             clear_depth: self.clear_depth,
+            sky_clear: self.sky_clear,
             enabled: self.enabled,
             passes: self.passes.push(pass),
             target_name: self.target_name,
@@ -286,14 +415,21 @@ impl<Q> StageBuilder<Queue<Q>> {
 pub struct CompilePass<'a> {
     factory: &'a mut Factory,
     target: &'a Target,
+    targets: &'a Targets,
     multisampling: u16,
 }
 
 impl<'a> CompilePass<'a> {
-    fn new(factory: &'a mut Factory, target: &'a Target, multisampling: u16) -> Self {
+    fn new(
+        factory: &'a mut Factory,
+        target: &'a Target,
+        targets: &'a Targets,
+        multisampling: u16,
+    ) -> Self {
         CompilePass {
             factory,
             target,
+            targets,
             multisampling,
         }
     }
@@ -305,7 +441,13 @@ where
 {
     type Output = Result<CompiledPass<P>, Error>;
     fn call_once(self, (pass,): (P,)) -> Result<CompiledPass<P>, Error> {
-        CompiledPass::compile(pass, self.factory, self.target, self.multisampling)
+        CompiledPass::compile(
+            pass,
+            self.factory,
+            self.target,
+            self.targets,
+            self.multisampling,
+        )
     }
 }
 impl<'a, P> HetFnMut<(P,)> for CompilePass<'a>
@@ -313,6 +455,12 @@ where
     P: Pass,
 {
     fn call_mut(&mut self, (pass,): (P,)) -> Result<CompiledPass<P>, Error> {
-        CompiledPass::compile(pass, self.factory, self.target, self.multisampling)
+        CompiledPass::compile(
+            pass,
+            self.factory,
+            self.target,
+            self.targets,
+            self.multisampling,
+        )
     }
 }
@@ -8,7 +8,7 @@ use serde::{Deserialize, Serialize};
 use thread_profiler::profile_scope;
 
 use crate::types::{
-    DepthStencilView, Encoder, Factory, RenderTargetView, ShaderResourceView, Window,
+    DepthStencilView, Encoder, Factory, RawTexture, RenderTargetView, ShaderResourceView, Window,
 };
 
 /// Target color buffer.
@@ -18,6 +18,8 @@ pub struct ColorBuffer {
     pub as_input: Option<ShaderResourceView<[f32; 4]>>,
     /// Target view.
     pub as_output: RenderTargetView,
+    /// Texture holding the buffer, if it can be sampled.
+    pub texture: Option<RawTexture>,
 }
 
 /// Target depth-stencil buffer.
@@ -29,6 +31,9 @@ pub struct DepthBuffer {
     pub as_output: DepthStencilView,
 }
 
+/// Color format of the targets built with `TargetBuilder::with_hdr`.
+type HdrFormat = (gfx::format::R16_G16_B16_A16, gfx::format::Float);
+
 /// A hash map containing named render targets.
 pub type Targets = HashMap<String, Target>;
 
@@ -38,15 +43,17 @@ pub type Targets = HashMap<String, Target>;
 /// optional depth buffer.
 #[derive(Clone, Debug, PartialEq)]
 pub struct Target {
+    name: String,
     color_bufs: Vec<ColorBuffer>,
     depth_buf: Option<DepthBuffer>,
     size: (u32, u32),
 }
 
 impl Target {
-    /// Creates a new `Target` from a single color buffer and depth buffer pair.
+    /// Creates a new backbuffer `Target` from a single color buffer and depth buffer pair.
     pub(crate) fn new(cb: ColorBuffer, db: DepthBuffer, size: (u32, u32)) -> Self {
         Target {
+            name: String::new(),
             color_bufs: vec![cb],
             depth_buf: Some(db),
             size,
@@ -77,6 +84,11 @@ impl Target {
         }
     }
 
+    /// Returns the name of the render target, `""` being the backbuffer.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     /// Returns the width and height of the render target, measured in pixels.
     pub fn size(&self) -> (u32, u32) {
         self.size
@@ -122,6 +134,10 @@ impl Target {
                 );
             }
         }
+        if let Some(color_buf) = self.color_bufs.first() {
+            let (w, h, _, _) = color_buf.as_output.get_dimensions();
+            self.size = (u32::from(w), u32::from(h));
+        }
     }
 }
 
@@ -134,6 +150,7 @@ pub struct TargetBuilder {
     custom_size: Option<(u32, u32)>,
     name: String,
     has_depth_buf: bool,
+    hdr: bool,
     num_color_bufs: usize,
 }
 
@@ -144,6 +161,7 @@ impl TargetBuilder {
             custom_size: None,
             name: name.into(),
             has_depth_buf: false,
+            hdr: false,
             num_color_bufs: 1,
         }
     }
@@ -156,6 +174,16 @@ impl TargetBuilder {
         self
     }
 
+    /// Stores the color buffers as 16-bit floats instead of 8-bit normalized values.
+    ///
+    /// Colors written into a normalized buffer are clamped to `[0, 1]`, so a scene that is
+    /// tonemapped, exposed or bloomed later has to be drawn into a target built with this. The
+    /// views keep the type of the other color buffers, so any pass can draw into them.
+    pub fn with_hdr(mut self) -> Self {
+        self.hdr = true;
+        self
+    }
+
     /// Sets how many color buffers the render target will have. This number
     /// must be greater than zero or else `build()` will fail.
     ///
@@ -166,31 +194,53 @@ impl TargetBuilder {
     }
 
     /// Specifies a custom target size.
+    ///
+    /// Targets without one take the size of the window and are recreated when it is resized.
     pub fn with_size(mut self, size: (u32, u32)) -> Self {
         self.custom_size = Some(size);
         self
     }
 
+    /// Returns the name of the target.
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the size of the target built for a window of `window_size` pixels.
+    pub(crate) fn size_for(&self, window_size: (u32, u32)) -> (u32, u32) {
+        self.custom_size.unwrap_or(window_size)
+    }
+
     /// Builds and returns the new render target.
     pub(crate) fn build(
         self,
         fac: &mut Factory,
         size: (u32, u32),
     ) -> Result<(String, Target), Error> {
-        use gfx::Factory;
+        use gfx::{memory::Typed, Factory};
 
         #[cfg(feature = "profiler")]
         profile_scope!("render_target_build");
 
-        let size = self.custom_size.unwrap_or(size);
+        let size = self.size_for(size);
+        let hdr = self.hdr;
 
         let color_bufs = (0..self.num_color_bufs)
             .map(|_| {
                 let (w, h) = (size.0 as u16, size.1 as u16);
-                let (_, res, rt) = fac.create_render_target(w, h)?;
+                if hdr {
+                    let (tex, res, rt) = fac.create_render_target::<HdrFormat>(w, h)?;
+                    return Ok(ColorBuffer {
+                        as_input: Some(res),
+                        as_output: Typed::new(rt.raw().clone()),
+                        texture: Some(tex.raw().clone()),
+                    });
+                }
+                let (tex, res, rt) = fac.create_render_target(w, h)?;
                 Ok(ColorBuffer {
                     as_input: Some(res),
                     as_output: rt,
+                    texture: Some(tex.raw().clone()),
                 })
             })
             .collect::<Result<_, Error>>()?;
@@ -208,6 +258,7 @@ impl TargetBuilder {
         };
 
         let target = Target {
+            name: self.name.clone(),
             color_bufs,
             depth_buf,
             size,
@@ -216,3 +267,69 @@ impl TargetBuilder {
         Ok((self.name, target))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        pipe::{Pipeline, PolyPipeline, Stage},
+        HeadlessRenderer,
+    };
+
+    use super::{Target, TargetBuilder};
+
+    #[test]
+    fn only_window_sized_targets_follow_a_resize() {
+        let scene = TargetBuilder::new("scene").with_depth_buf(true);
+        let shadow = TargetBuilder::new("shadow").with_size((1024, 1024));
+
+        assert_eq!(scene.size_for((800, 600)), (800, 600));
+        assert_eq!(scene.size_for((1920, 1080)), (1920, 1080));
+        assert_eq!(shadow.size_for((800, 600)), (1024, 1024));
+        assert_eq!(shadow.size_for((1920, 1080)), (1024, 1024));
+    }
+
+    #[test]
+    #[ignore] // Needs an OpenGL context, see `HeadlessRenderer`.
+    fn resize_rebuilds_window_sized_buffers() {
+        let mut renderer = HeadlessRenderer::new((4, 4)).unwrap();
+        let mut pipe = renderer
+            .create_pipe(
+                Pipeline::build()
+                    .with_target(
+                        Target::named("scene")
+                            .with_num_color_bufs(2)
+                            .with_depth_buf(true)
+                            .with_hdr(),
+                    )
+                    .with_target(
+                        Target::named("shadow")
+                            .with_depth_buf(true)
+                            .with_size((16, 16)),
+                    )
+                    .with_stage(Stage::with_backbuffer()),
+            )
+            .unwrap();
+        let (_, main) = TargetBuilder::new("")
+            .with_depth_buf(true)
+            .build(&mut renderer.factory, (32, 8))
+            .unwrap();
+        pipe.resize(&mut renderer.factory, main, (32, 8)).unwrap();
+
+        for &(name, colors, size) in &[("scene", 2, (32, 8)), ("shadow", 1, (16, 16))] {
+            let target = &pipe.targets()[name];
+            let dimensions = |(w, h, _, _): (u16, u16, u16, _)| (u32::from(w), u32::from(h));
+            assert_eq!(target.size(), size, "size of {:?}", name);
+            assert_eq!(
+                target.color_bufs().len(),
+                colors,
+                "color buffers of {:?}",
+                name
+            );
+            for color in target.color_bufs() {
+                assert_eq!(dimensions(color.as_output.get_dimensions()), size);
+            }
+            let depth = target.depth_buf().expect("depth buffer lost on resize");
+            assert_eq!(dimensions(depth.as_output.get_dimensions()), size);
+        }
+    }
+}
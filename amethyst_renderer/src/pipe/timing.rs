@@ -0,0 +1,271 @@
+//! Optional GPU timer queries measuring how long each pass takes on the device.
+//!
+//! Timing is compiled in with the `gpu_timing` feature only, which requires the OpenGL backend
+//! and a driver supporting `ARB_timer_query`. Every pass is then flushed to the device on its own
+//! and wrapped in a `GL_TIME_ELAPSED` query, which costs some performance. Results are read back
+//! a few frames later, once the GPU has caught up, and published in the `PassTimings` resource.
+
+use std::marker::PhantomData;
+
+#[cfg(feature = "gpu_timing")]
+use std::{collections::VecDeque, time::Duration};
+
+#[cfg(feature = "gpu_timing")]
+use fnv::FnvHashMap as HashMap;
+#[cfg(feature = "gpu_timing")]
+use gfx_device_gl::gl;
+#[cfg(feature = "gpu_timing")]
+use log::warn;
+
+#[cfg(feature = "gpu_timing")]
+use crate::types::Device;
+use crate::types::Encoder;
+
+/// Frames whose queries may still be in flight before the timer waits for the oldest one.
+#[cfg(feature = "gpu_timing")]
+const MAX_PENDING_FRAMES: usize = 4;
+
+/// Measures the passes of a pipeline as it is applied.
+///
+/// Without the `gpu_timing` feature this does nothing.
+pub struct PassTimer<'a> {
+    #[cfg(feature = "gpu_timing")]
+    frame: Option<(&'a mut GpuTimer, &'a mut Device)>,
+    #[cfg(feature = "gpu_timing")]
+    stage: String,
+    marker: PhantomData<&'a mut ()>,
+}
+
+impl<'a> PassTimer<'a> {
+    /// Returns a timer that measures nothing.
+    pub fn disabled() -> Self {
+        PassTimer {
+            #[cfg(feature = "gpu_timing")]
+            frame: None,
+            #[cfg(feature = "gpu_timing")]
+            stage: String::new(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Returns a timer recording the passes of this frame into `timer`.
+    #[cfg(feature = "gpu_timing")]
+    pub(crate) fn new(timer: &'a mut GpuTimer, device: &'a mut Device) -> Self {
+        PassTimer {
+            frame: if timer.supported {
+                Some((timer, device))
+            } else {
+                None
+            },
+            stage: String::new(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Names the timings of the following passes after the stage's target.
+    #[cfg_attr(not(feature = "gpu_timing"), allow(unused_variables))]
+    pub(crate) fn begin_stage(&mut self, target_name: &str) {
+        #[cfg(feature = "gpu_timing")]
+        {
+            self.stage.clear();
+            self.stage.push_str(if target_name.is_empty() {
+                "backbuffer"
+            } else {
+                target_name
+            });
+        }
+    }
+
+    /// Applies the pass `P` with `apply`, measuring how long its commands take on the GPU.
+    pub(crate) fn time<P, F>(&mut self, encoder: &mut Encoder, apply: F)
+    where
+        F: FnOnce(&mut Encoder),
+    {
+        #[cfg(feature = "gpu_timing")]
+        {
+            if let Some((ref mut timer, ref mut device)) = self.frame {
+                let name = format!(
+                    "{}/{}",
+                    self.stage,
+                    short_type_name(std::any::type_name::<P>())
+                );
+                // The commands of earlier passes mustn't end up in this pass's query.
+                encoder.flush(&mut **device);
+                timer.begin(device, name);
+                apply(encoder);
+                encoder.flush(&mut **device);
+                timer.end(device);
+                return;
+            }
+        }
+        apply(encoder);
+    }
+}
+
+/// How long each pass took on the GPU in the latest frame whose timer queries have finished
+///
+/// Passes are keyed by the name of their stage's target, `backbuffer` for the window, and their
+/// type without module paths, e.g. `backbuffer/DrawSkyBox` or `scene/DrawPbm<PosNormTangTex>`.
+/// Passes of the same type in the same stage are summed up. The resource stays empty while the
+/// driver doesn't support timer queries.
+#[cfg(feature = "gpu_timing")]
+#[derive(Clone, Debug, Default)]
+pub struct PassTimings {
+    timings: HashMap<String, Duration>,
+}
+
+#[cfg(feature = "gpu_timing")]
+impl PassTimings {
+    /// Returns how long the pass with the given name took.
+    pub fn get(&self, pass: &str) -> Option<Duration> {
+        self.timings.get(pass).cloned()
+    }
+
+    /// Iterates over the names and timings of all measured passes, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Duration)> {
+        self.timings
+            .iter()
+            .map(|(name, duration)| (name.as_str(), *duration))
+    }
+
+    /// Returns how long all passes took together.
+    pub fn total(&self) -> Duration {
+        self.timings.values().sum()
+    }
+}
+
+/// Pool of GL timer queries and the frames waiting for their results.
+#[cfg(feature = "gpu_timing")]
+pub(crate) struct GpuTimer {
+    supported: bool,
+    free: Vec<gl::types::GLuint>,
+    recording: Vec<(String, gl::types::GLuint)>,
+    pending: VecDeque<Vec<(String, gl::types::GLuint)>>,
+    timings: PassTimings,
+}
+
+#[cfg(feature = "gpu_timing")]
+impl GpuTimer {
+    pub(crate) fn new(device: &mut Device) -> Self {
+        let mut supported = false;
+        unsafe {
+            device.with_gl(|gl| {
+                supported = gl.GenQueries.is_loaded() && gl.GetQueryObjectui64v.is_loaded();
+            });
+        }
+        if !supported {
+            warn!("The driver doesn't support timer queries, passes won't be timed.");
+        }
+        GpuTimer {
+            supported,
+            free: Vec::new(),
+            recording: Vec::new(),
+            pending: VecDeque::new(),
+            timings: PassTimings::default(),
+        }
+    }
+
+    pub(crate) fn timings(&self) -> &PassTimings {
+        &self.timings
+    }
+
+    fn begin(&mut self, device: &mut Device, name: String) {
+        let query = self.free.pop().unwrap_or_else(|| {
+            let mut query = 0;
+            unsafe { device.with_gl(|gl| gl.GenQueries(1, &mut query)) };
+            query
+        });
+        unsafe { device.with_gl(|gl| gl.BeginQuery(gl::TIME_ELAPSED, query)) };
+        self.recording.push((name, query));
+    }
+
+    fn end(&mut self, device: &mut Device) {
+        unsafe { device.with_gl(|gl| gl.EndQuery(gl::TIME_ELAPSED)) };
+    }
+
+    /// Queues the queries of the frame just submitted and collects the finished frames.
+    pub(crate) fn end_frame(&mut self, device: &mut Device) {
+        if !self.recording.is_empty() {
+            self.pending.push_back(self.recording.drain(..).collect());
+        }
+        while let Some(frame) = self.pending.pop_front() {
+            // Queries finish in order, so the last one tells whether the whole frame is done.
+            let waiting = self.pending.len() + 1 < MAX_PENDING_FRAMES;
+            if waiting
+                && !frame
+                    .last()
+                    .map_or(true, |&(_, query)| available(device, query))
+            {
+                self.pending.push_front(frame);
+                break;
+            }
+            let mut timings = HashMap::default();
+            for (name, query) in frame {
+                let mut nanos = 0;
+                unsafe {
+                    device.with_gl(|gl| gl.GetQueryObjectui64v(query, gl::QUERY_RESULT, &mut nanos))
+                };
+                *timings.entry(name).or_insert_with(Duration::default) +=
+                    Duration::from_nanos(nanos);
+                self.free.push(query);
+            }
+            self.timings = PassTimings { timings };
+        }
+    }
+
+    /// Deletes all queries, while the device is still around.
+    pub(crate) fn delete(&mut self, device: &mut Device) {
+        let queries = self
+            .free
+            .drain(..)
+            .chain(self.recording.drain(..).map(|(_, query)| query))
+            .chain(self.pending.drain(..).flatten().map(|(_, query)| query))
+            .collect::<Vec<_>>();
+        if !queries.is_empty() {
+            unsafe {
+                device.with_gl(|gl| gl.DeleteQueries(queries.len() as _, queries.as_ptr()));
+            }
+        }
+    }
+}
+
+#[cfg(feature = "gpu_timing")]
+fn available(device: &mut Device, query: gl::types::GLuint) -> bool {
+    let mut available = 0;
+    unsafe {
+        device.with_gl(|gl| gl.GetQueryObjectiv(query, gl::QUERY_RESULT_AVAILABLE, &mut available));
+    }
+    available != 0
+}
+
+/// Strips the module paths from every type in `name`.
+pub(crate) fn short_type_name(name: &str) -> String {
+    let mut short = String::with_capacity(name.len());
+    let mut path_start = 0;
+    for c in name.chars() {
+        short.push(c);
+        if short.ends_with("::") {
+            short.truncate(path_start);
+        } else if !(c.is_alphanumeric() || c == '_' || c == ':') {
+            path_start = short.len();
+        }
+    }
+    short
+}
+
+#[cfg(test)]
+mod tests {
+    use super::short_type_name;
+
+    #[test]
+    fn type_names_lose_their_paths() {
+        assert_eq!(
+            short_type_name("amethyst_renderer::pass::sky::DrawSkyBox"),
+            "DrawSkyBox"
+        );
+        assert_eq!(
+            short_type_name("pbm::DrawPbm<vertex::PosNormTangTex, (a::B, [c::D; 4])>"),
+            "DrawPbm<PosNormTangTex, (B, [D; 4])>"
+        );
+    }
+}
@@ -1,5 +1,4 @@
 use amethyst_error::{format_err, Error};
-use fnv::FnvHashMap as HashMap;
 use gfx::memory::Pod;
 use winit::{dpi::LogicalSize, EventsLoop, Window as WinitWindow, WindowBuilder};
 
@@ -10,13 +9,21 @@ use crate::{
     config::DisplayConfig,
     error,
     mesh::{Mesh, MeshBuilder, VertexDataSet},
+    pass::{filter_cubemap, CubemapFilter},
     pipe::{
-        ColorBuffer, DepthBuffer, PipelineBuild, PipelineData, PolyPipeline, Target, TargetBuilder,
+        shaders_changed, ColorBuffer, DepthBuffer, PassTimer, PipelineBuild, PipelineData,
+        PolyPipeline, Target,
     },
+    screenshot::Screenshots,
     tex::{Texture, TextureBuilder},
     types::{ColorFormat, DepthFormat, Device, Encoder, Factory, Window},
 };
 
+#[cfg(feature = "gpu_timing")]
+use crate::pipe::{GpuTimer, PassTimings};
+#[cfg(feature = "opengl")]
+use crate::screenshot::ScreenshotReader;
+
 /// Generic renderer.
 pub struct Renderer {
     /// The gfx factory used for creation of buffers.
@@ -28,8 +35,13 @@ pub struct Renderer {
     window: Window,
     events: EventsLoop,
     multisampling: u16,
+    max_anisotropy: u8,
     cached_size: LogicalSize,
     cached_hidpi_factor: f64,
+    #[cfg(feature = "gpu_timing")]
+    gpu_timer: GpuTimer,
+    #[cfg(feature = "opengl")]
+    screenshots: ScreenshotReader,
 }
 
 impl Renderer {
@@ -62,7 +74,24 @@ impl Renderer {
         D: AsRef<[T]>,
         T: Pod + Copy,
     {
-        tb.build(&mut self.factory)
+        tb.clamp_anisotropy(self.max_anisotropy)
+            .build(&mut self.factory)
+    }
+
+    /// Returns the most samples anisotropic filtering takes on the device, `1` if it has none.
+    pub fn max_anisotropy(&self) -> u8 {
+        self.max_anisotropy
+    }
+
+    /// Convolves the cubemap `source` with `filter` into a new cubemap.
+    ///
+    /// The convolution is encoded ahead of the next frame, which can use the result.
+    pub(crate) fn filter_cubemap(
+        &mut self,
+        source: &Texture,
+        filter: CubemapFilter,
+    ) -> Result<Texture, Error> {
+        filter_cubemap(source, filter, &mut self.factory, &mut self.encoder)
     }
 
     /// Builds a new renderer pipeline.
@@ -74,6 +103,20 @@ impl Renderer {
         pb.build(&mut self.factory, &self.main_target, self.multisampling)
     }
 
+    /// Returns how long each pass took on the GPU in a recent frame.
+    #[cfg(feature = "gpu_timing")]
+    pub fn pass_timings(&self) -> &PassTimings {
+        self.gpu_timer.timings()
+    }
+
+    /// Hands the screenshots read back so far to `screenshots`, and captures the frame drawn next
+    /// if one was requested.
+    #[cfg_attr(not(feature = "opengl"), allow(unused_variables))]
+    pub(crate) fn exchange_screenshots(&mut self, screenshots: &mut Screenshots) {
+        #[cfg(feature = "opengl")]
+        self.screenshots.exchange(screenshots);
+    }
+
     /// Draws a scene with the given pipeline.
     #[cfg_attr(feature = "cargo-clippy", allow(float_cmp))] // cmp just used to recognize change
     pub fn draw<'a, P>(&mut self, pipe: &mut P, data: <P as PipelineData<'a>>::Data)
@@ -84,6 +127,10 @@ impl Renderer {
         #[cfg(feature = "opengl")]
         use glutin::dpi::PhysicalSize;
 
+        if shaders_changed() {
+            pipe.recompile(&mut self.factory, self.multisampling);
+        }
+
         if let Some(size) = self.window().get_inner_size() {
             #[cfg(feature = "profiler")]
             profile_scope!("render_system_draw_size");
@@ -101,12 +148,26 @@ impl Renderer {
         {
             #[cfg(feature = "profiler")]
             profile_scope!("render_system_draw_pipeapply");
-            pipe.apply(&mut self.encoder, self.factory.clone(), data);
+            #[cfg(feature = "gpu_timing")]
+            let mut timer = PassTimer::new(&mut self.gpu_timer, &mut self.device);
+            #[cfg(not(feature = "gpu_timing"))]
+            let mut timer = PassTimer::disabled();
+            pipe.apply(&mut self.encoder, &mut timer, self.factory.clone(), data);
         }
         {
             #[cfg(feature = "profiler")]
             profile_scope!("render_system_draw_encoderflush");
             self.encoder.flush(&mut self.device);
+            #[cfg(feature = "gpu_timing")]
+            self.gpu_timer.end_frame(&mut self.device);
+        }
+        #[cfg(feature = "opengl")]
+        {
+            #[cfg(feature = "profiler")]
+            profile_scope!("render_system_draw_screenshots");
+            self.screenshots.collect(&mut self.device);
+            self.screenshots
+                .capture(&mut self.device, self.main_target.size());
         }
         {
             #[cfg(feature = "profiler")]
@@ -129,19 +190,13 @@ impl Renderer {
     }
 
     /// Resize the targets associated with this renderer and pipeline.
+    ///
+    /// Offscreen targets without a fixed size are recreated at `new_size`, and the passes are
+    /// handed the new targets without recompiling their shaders.
     pub fn resize<P: PolyPipeline>(&mut self, pipe: &mut P, new_size: (u32, u32)) {
         self.main_target.resize_main_target(&self.window);
-        let mut targets = HashMap::default();
-        targets.insert("".to_string(), self.main_target.clone());
-        for (key, value) in pipe.targets().iter().filter(|&(k, _)| !k.is_empty()) {
-            let (key, target) = TargetBuilder::new(key.clone())
-                .with_num_color_bufs(value.color_bufs().len())
-                .with_depth_buf(value.depth_buf().is_some())
-                .build(&mut self.factory, new_size)
-                .expect("Unable to create new target when resizing");
-            targets.insert(key, target);
-        }
-        pipe.new_targets(targets);
+        pipe.resize(&mut self.factory, self.main_target.clone(), new_size)
+            .expect("Unable to create new target when resizing");
     }
 
     /// Retrieves an immutable borrow of the window.
@@ -167,6 +222,10 @@ impl Renderer {
 impl Drop for Renderer {
     fn drop(&mut self) {
         use gfx::Device;
+        #[cfg(feature = "gpu_timing")]
+        self.gpu_timer.delete(&mut self.device);
+        #[cfg(feature = "opengl")]
+        self.screenshots.delete(&mut self.device);
         self.device.cleanup();
     }
 }
@@ -205,7 +264,7 @@ impl RendererBuilder {
 
     /// Consumes the builder and creates the new `Renderer`.
     pub fn build(self) -> Result<Renderer, Error> {
-        let Backend(device, mut factory, main_target, window) =
+        let Backend(mut device, mut factory, main_target, window) =
             init_backend(self.window_builder, &self.events, &self.config)?;
 
         let cached_size = window
@@ -215,6 +274,9 @@ impl RendererBuilder {
         let cached_hidpi_factor = window.get_hidpi_factor();
 
         let encoder = factory.create_command_buffer().into();
+        let max_anisotropy = max_anisotropy(&mut device);
+        #[cfg(feature = "gpu_timing")]
+        let gpu_timer = GpuTimer::new(&mut device);
         Ok(Renderer {
             device,
             encoder,
@@ -223,12 +285,44 @@ impl RendererBuilder {
             window,
             events: self.events,
             multisampling: self.config.multisampling,
+            max_anisotropy,
             cached_size,
             cached_hidpi_factor,
+            #[cfg(feature = "gpu_timing")]
+            gpu_timer,
+            #[cfg(feature = "opengl")]
+            screenshots: ScreenshotReader::default(),
         })
     }
 }
 
+/// Returns the most samples anisotropic filtering takes on `device`, `1` if it has none.
+#[cfg(feature = "opengl")]
+pub(crate) fn max_anisotropy(device: &mut Device) -> u8 {
+    use gfx_device_gl::gl;
+
+    let supported = {
+        let info = device.get_info();
+        info.is_extension_supported("GL_EXT_texture_filter_anisotropic")
+            || info.is_extension_supported("GL_ARB_texture_filter_anisotropic")
+    };
+    if !supported {
+        return 1;
+    }
+    let mut max = 1.0;
+    unsafe {
+        device.with_gl(|api| api.GetFloatv(gl::MAX_TEXTURE_MAX_ANISOTROPY_EXT, &mut max));
+    }
+    max.max(1.0).min(f32::from(u8::max_value())) as u8
+}
+
+/// Returns the most samples anisotropic filtering takes on `device`, which Direct3D 11 and Metal
+/// devices support up to 16 of.
+#[cfg(not(feature = "opengl"))]
+pub(crate) fn max_anisotropy(_device: &mut Device) -> u8 {
+    16
+}
+
 /// Represents a graphics backend for the renderer.
 struct Backend(pub Device, pub Factory, pub Target, pub Window);
 
@@ -253,6 +347,7 @@ fn init_backend(
         ColorBuffer {
             as_input: None,
             as_output: color,
+            texture: None,
         },
         DepthBuffer {
             as_input: None,
@@ -283,6 +378,7 @@ fn init_backend(
         ColorBuffer {
             as_input: None,
             as_output: color,
+            texture: None,
         },
         DepthBuffer {
             as_input: None,
@@ -322,6 +418,7 @@ fn init_backend(
         ColorBuffer {
             as_input: None,
             as_output: color,
+            texture: None,
         },
         DepthBuffer {
             as_input: None,
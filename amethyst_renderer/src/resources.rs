@@ -1,5 +1,6 @@
 //! `amethyst` rendering ecs resources
 //!
+use fnv::FnvHashMap as HashMap;
 use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
 use winit::Window;
@@ -8,7 +9,7 @@ use amethyst_assets::PrefabData;
 use amethyst_core::specs::{Entity, Write};
 use amethyst_error::Error;
 
-use crate::color::Rgba;
+use crate::{color::Rgba, tex::TextureHandle};
 
 /// The ambient color of a scene
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
@@ -132,3 +133,45 @@ impl ScreenDimensions {
         self.hidpi = factor;
     }
 }
+
+/// Textures showing what was rendered into pipeline targets, by target name.
+///
+/// Register a target together with a placeholder texture, e.g. a color loaded with
+/// `Loader::load_from_data`. Once the placeholder is loaded, the render system replaces it with
+/// the target's first color buffer, and does so again whenever the target is recreated. The
+/// handle can then be used like any other texture, on a `Material` or a `SkyPanorama` for
+/// example, as long as the stage drawing into the target comes before the stages sampling it.
+/// Targets are 2D, so they can't be used where a cubemap is expected, like on a `SkyBox`.
+#[derive(Clone, Debug, Default)]
+pub struct TargetTextures {
+    textures: HashMap<String, TextureHandle>,
+}
+
+impl TargetTextures {
+    /// Shows the target with the given name through `texture`, returning the texture that showed
+    /// it before.
+    pub fn insert<N: Into<String>>(
+        &mut self,
+        target: N,
+        texture: TextureHandle,
+    ) -> Option<TextureHandle> {
+        self.textures.insert(target.into(), texture)
+    }
+
+    /// Returns the texture showing the target with the given name.
+    pub fn get(&self, target: &str) -> Option<&TextureHandle> {
+        self.textures.get(target)
+    }
+
+    /// Stops updating the texture showing the target with the given name, and returns it.
+    pub fn remove(&mut self, target: &str) -> Option<TextureHandle> {
+        self.textures.remove(target)
+    }
+
+    /// Iterates over the target names and their textures.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &TextureHandle)> {
+        self.textures
+            .iter()
+            .map(|(target, texture)| (target.as_str(), texture))
+    }
+}
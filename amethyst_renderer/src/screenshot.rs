@@ -0,0 +1,200 @@
+//! Reading the frames drawn into the window back from the GPU.
+
+use std::collections::VecDeque;
+
+use image::RgbaImage;
+
+#[cfg(feature = "opengl")]
+use std::ptr;
+
+#[cfg(feature = "opengl")]
+use gfx_device_gl::gl;
+
+use crate::formats::ImageData;
+#[cfg(feature = "opengl")]
+use crate::types::Device;
+
+/// Resource requesting screenshots of the window and receiving them
+///
+/// After `request`, the `RenderSystem` captures the next frame once all of its passes are drawn.
+/// The GPU copies the frame in the background, so the image shows up in `take` a frame or two
+/// later without stalling the renderer. Requests made during the same frame share a screenshot.
+///
+/// ```ignore
+/// let mut screenshots = world.write_resource::<Screenshots>();
+/// while let Some(screenshot) = screenshots.take() {
+///     screenshot.rgba.save("screenshot.png")?;
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct Screenshots {
+    requested: bool,
+    taken: VecDeque<ImageData>,
+}
+
+impl Screenshots {
+    /// Captures the next frame.
+    pub fn request(&mut self) {
+        self.requested = true;
+    }
+
+    /// Returns the oldest screenshot that was read back and not taken yet.
+    pub fn take(&mut self) -> Option<ImageData> {
+        self.taken.pop_front()
+    }
+}
+
+/// Pixel buffers the GPU copies requested frames into.
+#[cfg(feature = "opengl")]
+#[derive(Default)]
+pub(crate) struct ScreenshotReader {
+    requested: bool,
+    pending: VecDeque<PendingScreenshot>,
+    finished: Vec<ImageData>,
+}
+
+#[cfg(feature = "opengl")]
+struct PendingScreenshot {
+    buffer: gl::types::GLuint,
+    fence: gl::types::GLsync,
+    size: (u32, u32),
+}
+
+#[cfg(feature = "opengl")]
+impl ScreenshotReader {
+    /// Hands the screenshots read back so far to `screenshots` and takes over its request.
+    pub(crate) fn exchange(&mut self, screenshots: &mut Screenshots) {
+        screenshots.taken.extend(self.finished.drain(..));
+        self.requested |= screenshots.requested;
+        screenshots.requested = false;
+    }
+
+    /// Starts copying the window's framebuffer of `size` pixels if a screenshot was requested.
+    ///
+    /// Called after the frame is submitted and before the buffers are swapped.
+    pub(crate) fn capture(&mut self, device: &mut Device, size: (u32, u32)) {
+        if !self.requested {
+            return;
+        }
+        self.requested = false;
+
+        let mut buffer = 0;
+        let mut fence = ptr::null();
+        unsafe {
+            device.with_gl(|gl| {
+                gl.GenBuffers(1, &mut buffer);
+                gl.BindBuffer(gl::PIXEL_PACK_BUFFER, buffer);
+                gl.BufferData(
+                    gl::PIXEL_PACK_BUFFER,
+                    (size.0 * size.1 * 4) as _,
+                    ptr::null(),
+                    gl::STREAM_READ,
+                );
+                gl.BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+                gl.ReadBuffer(gl::BACK);
+                // Reading into a bound pack buffer returns right away, the copy runs on the GPU.
+                gl.ReadPixels(
+                    0,
+                    0,
+                    size.0 as _,
+                    size.1 as _,
+                    gl::RGBA,
+                    gl::UNSIGNED_BYTE,
+                    ptr::null_mut(),
+                );
+                gl.BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+                fence = gl.FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+            });
+        }
+        self.pending.push_back(PendingScreenshot {
+            buffer,
+            fence,
+            size,
+        });
+    }
+
+    /// Reads back the screenshots whose copies have finished.
+    pub(crate) fn collect(&mut self, device: &mut Device) {
+        while let Some(screenshot) = self.pending.pop_front() {
+            let mut status = gl::TIMEOUT_EXPIRED;
+            unsafe {
+                device.with_gl(|gl| status = gl.ClientWaitSync(screenshot.fence, 0, 0));
+            }
+            // Copies finish in order, so the later ones aren't done either.
+            if status == gl::TIMEOUT_EXPIRED {
+                self.pending.push_front(screenshot);
+                break;
+            }
+
+            let (width, height) = screenshot.size;
+            let mut pixels = vec![0; (width * height * 4) as usize];
+            unsafe {
+                device.with_gl(|gl| {
+                    gl.BindBuffer(gl::PIXEL_PACK_BUFFER, screenshot.buffer);
+                    gl.GetBufferSubData(
+                        gl::PIXEL_PACK_BUFFER,
+                        0,
+                        pixels.len() as _,
+                        pixels.as_mut_ptr() as *mut _,
+                    );
+                    gl.BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+                });
+            }
+            delete(device, &screenshot);
+            self.finished.push(ImageData {
+                rgba: frame_image(pixels, width, height),
+            });
+        }
+    }
+
+    /// Deletes the buffers of unfinished screenshots, while the device is still around.
+    pub(crate) fn delete(&mut self, device: &mut Device) {
+        for screenshot in self.pending.drain(..) {
+            delete(device, &screenshot);
+        }
+    }
+}
+
+#[cfg(feature = "opengl")]
+fn delete(device: &mut Device, screenshot: &PendingScreenshot) {
+    unsafe {
+        device.with_gl(|gl| {
+            gl.DeleteBuffers(1, &screenshot.buffer);
+            gl.DeleteSync(screenshot.fence);
+        });
+    }
+}
+
+/// Turns the pixels read from the framebuffer, whose rows run from the bottom up, into an opaque
+/// image.
+#[cfg_attr(not(feature = "opengl"), allow(dead_code))]
+pub(crate) fn frame_image(pixels: Vec<u8>, width: u32, height: u32) -> RgbaImage {
+    let row = width as usize * 4;
+    let mut flipped = Vec::with_capacity(pixels.len());
+    for line in pixels.chunks(row).rev() {
+        for pixel in line.chunks(4) {
+            flipped.extend_from_slice(&[pixel[0], pixel[1], pixel[2], 255]);
+        }
+    }
+    RgbaImage::from_raw(width, height, flipped).expect("Framebuffer size doesn't match its pixels")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::frame_image;
+
+    #[test]
+    fn frames_are_flipped_upright() {
+        // Two rows of two pixels, the bottom row first, as they come out of the framebuffer.
+        let pixels = vec![
+            10, 0, 0, 0, 20, 0, 0, 0, //
+            30, 0, 0, 128, 40, 0, 0, 255,
+        ];
+        let image = frame_image(pixels, 2, 2);
+
+        assert_eq!(image.get_pixel(0, 0).data, [30, 0, 0, 255]);
+        assert_eq!(image.get_pixel(1, 0).data, [40, 0, 0, 255]);
+        assert_eq!(image.get_pixel(0, 1).data, [10, 0, 0, 255]);
+        assert_eq!(image.get_pixel(1, 1).data, [20, 0, 0, 255]);
+    }
+}
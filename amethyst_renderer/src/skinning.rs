@@ -8,12 +8,22 @@ use amethyst_core::specs::prelude::{
 use amethyst_error::Error;
 
 use crate::{
+    error,
     formats::MeshCreator,
     mesh::{Mesh, MeshBuilder},
     renderer::Renderer,
     vertex::{Attribute, Color, Normal, Position, Separate, Tangent, TexCoord},
 };
 
+/// Most joints a skin can have for the skinning passes to draw the meshes using it.
+///
+/// The joint matrices of each mesh are uploaded into a constant buffer of this size, which keeps
+/// within the uniform limits of common hardware. Meshes with joint ids beyond it are refused when
+/// they are built and skins with more joints when they are attached. To draw a larger rig, split
+/// it in the modelling tool into meshes that each deform with at most this many joints, and give
+/// each of them its own skin.
+pub const MAX_JOINTS: usize = 100;
+
 /// Type for joint weights attribute of vertex
 #[derive(Clone, Debug)]
 pub enum JointWeights {}
@@ -58,11 +68,27 @@ pub type AnimatedVertexBufferCombination = (
     Option<Vec<Separate<JointWeights>>>,
 );
 
+/// Returns an error if any of the joint ids reaches beyond `MAX_JOINTS`.
+fn check_joint_ids(ids: &[Separate<JointIds>]) -> Result<(), Error> {
+    let max = ids
+        .iter()
+        .flat_map(|ids| ids.0.iter())
+        .map(|&id| id as usize)
+        .max();
+    match max {
+        Some(max) if max >= MAX_JOINTS => Err(error::Error::TooManyJoints(max + 1).into()),
+        _ => Ok(()),
+    }
+}
+
 /// Build Mesh with vertex buffer combination
 fn build_mesh_with_combo(
     combo: AnimatedVertexBufferCombination,
     renderer: &mut Renderer,
 ) -> Result<Mesh, Error> {
+    if let Some(ref ids) = combo.5 {
+        check_joint_ids(ids)?;
+    }
     build_mesh_with_some!(
         MeshBuilder::new(combo.0),
         renderer,
@@ -128,6 +154,9 @@ impl<'a> PrefabData<'a> for JointTransformsPrefab {
         storage: &mut Self::SystemData,
         entities: &[Entity],
     ) -> Result<(), Error> {
+        if self.size > MAX_JOINTS {
+            return Err(error::Error::TooManyJoints(self.size).into());
+        }
         storage
             .insert(
                 entity,
@@ -141,3 +170,20 @@ impl<'a> PrefabData<'a> for JointTransformsPrefab {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::vertex::Separate;
+
+    use super::{check_joint_ids, MAX_JOINTS};
+
+    #[test]
+    fn joint_ids_within_the_limit() {
+        let last = (MAX_JOINTS - 1) as u16;
+        assert!(check_joint_ids(&[Separate::new([0, 1, 2, last])]).is_ok());
+        assert!(check_joint_ids(&[]).is_ok());
+        assert!(
+            check_joint_ids(&[Separate::new([0; 4]), Separate::new([0, last + 1, 0, 0])]).is_err()
+        );
+    }
+}
@@ -14,7 +14,9 @@ use thread_profiler::profile_scope;
 use amethyst_assets::{AssetStorage, HotReloadStrategy};
 use amethyst_core::{
     shrev::EventChannel,
-    specs::prelude::{Read, ReadExpect, Resources, RunNow, SystemData, Write, WriteExpect},
+    specs::prelude::{
+        Join, Read, ReadExpect, ReadStorage, Resources, RunNow, SystemData, Write, WriteExpect,
+    },
     Time,
 };
 use amethyst_error::Error;
@@ -23,13 +25,18 @@ use crate::{
     config::DisplayConfig,
     formats::{create_mesh_asset, create_texture_asset},
     mesh::Mesh,
-    mtl::{Material, MaterialDefaults},
+    mtl::{Material, MaterialDefaults, MissingTextures},
+    pass::{is_capture_cubemap, CubemapFilters, ReflectionProbe},
     pipe::{PipelineBuild, PipelineData, PolyPipeline},
     renderer::Renderer,
-    resources::{ScreenDimensions, WindowMessages},
+    resources::{ScreenDimensions, TargetTextures, WindowMessages},
+    screenshot::Screenshots,
     tex::Texture,
 };
 
+#[cfg(feature = "gpu_timing")]
+use crate::pipe::PassTimings;
+
 /// Rendering system.
 #[derive(Derivative)]
 #[derivative(Debug)]
@@ -115,6 +122,67 @@ where
         );
     }
 
+    fn target_textures(&mut self, (targets, mut texture_storage): TargetTextureData<'_>) {
+        use gfx::memory::Typed;
+
+        for (name, handle) in targets.iter() {
+            let buf = match self.pipe.targets().get(name).and_then(|t| t.color_buf(0)) {
+                Some(buf) => buf,
+                None => continue,
+            };
+            let current = match texture_storage.get_mut(handle) {
+                Some(current) => current,
+                None => continue,
+            };
+            let up_to_date = buf
+                .as_input
+                .as_ref()
+                .map_or(true, |view| view.raw() == current.view());
+            if up_to_date {
+                continue;
+            }
+            match Texture::from_color_buf(buf, &mut self.renderer.factory) {
+                Some(texture) => *current = texture,
+                None => error!("Target {:?} can't be used as a texture", name),
+            }
+        }
+    }
+
+    fn reflection_probes(&mut self, (probes, mut texture_storage): ReflectionProbeData<'_>) {
+        for probe in probes.join() {
+            let current = match texture_storage.get_mut(&probe.texture) {
+                Some(current) => current,
+                None => continue,
+            };
+            if is_capture_cubemap(current, probe.resolution) {
+                continue;
+            }
+            match Texture::render_cubemap(probe.resolution, 1, &mut self.renderer.factory) {
+                Ok(texture) => *current = texture,
+                Err(err) => error!("Unable to create the reflection probe cubemap: {}", err),
+            }
+        }
+    }
+
+    fn cubemap_filters(&mut self, (mut filters, mut texture_storage): CubemapFilterData<'_>) {
+        let renderer = &mut self.renderer;
+        filters.retain(|texture, source, filter| {
+            let source = match texture_storage.get(source) {
+                Some(source) => source.clone(),
+                None => return true,
+            };
+            match renderer.filter_cubemap(&source, filter) {
+                Ok(filtered) => {
+                    if let Some(current) = texture_storage.get_mut(texture) {
+                        *current = filtered;
+                    }
+                }
+                Err(err) => error!("Unable to filter the cubemap: {}", err),
+            }
+            false
+        });
+    }
+
     fn window_management(&mut self, (mut window_messages, mut screen_dimensions): WindowData<'_>) {
         // Process window commands
         for mut command in window_messages.queue.drain() {
@@ -167,6 +235,15 @@ type AssetLoadingData<'a> = (
     Write<'a, AssetStorage<Texture>>,
 );
 
+type TargetTextureData<'a> = (Read<'a, TargetTextures>, Write<'a, AssetStorage<Texture>>);
+
+type ReflectionProbeData<'a> = (
+    ReadStorage<'a, ReflectionProbe>,
+    Write<'a, AssetStorage<Texture>>,
+);
+
+type CubemapFilterData<'a> = (Write<'a, CubemapFilters>, Write<'a, AssetStorage<Texture>>);
+
 type WindowData<'a> = (Write<'a, WindowMessages>, WriteExpect<'a, ScreenDimensions>);
 
 type RenderData<'a, P> = (
@@ -191,20 +268,51 @@ where
             profile_scope!("render_system_windowmanagement");
             self.window_management(WindowData::fetch(res));
         }
+        {
+            #[cfg(feature = "profiler")]
+            profile_scope!("render_system_targettextures");
+            self.target_textures(TargetTextureData::fetch(res));
+        }
+        {
+            #[cfg(feature = "profiler")]
+            profile_scope!("render_system_reflectionprobes");
+            self.reflection_probes(ReflectionProbeData::fetch(res));
+        }
+        {
+            #[cfg(feature = "profiler")]
+            profile_scope!("render_system_cubemapfilters");
+            self.cubemap_filters(CubemapFilterData::fetch(res));
+        }
         {
             #[cfg(feature = "profiler")]
             profile_scope!("render_system_render");
             self.render(RenderData::<P>::fetch(res));
         }
+        self.renderer
+            .exchange_screenshots(&mut Write::<'_, Screenshots>::fetch(res));
+        #[cfg(feature = "gpu_timing")]
+        {
+            *Write::<'_, PassTimings>::fetch(res) = self.renderer.pass_timings().clone();
+        }
     }
 
     fn setup(&mut self, res: &mut Resources) {
         AssetLoadingData::setup(res);
         WindowData::setup(res);
+        TargetTextureData::setup(res);
+        ReflectionProbeData::setup(res);
+        CubemapFilterData::setup(res);
         RenderData::<P>::setup(res);
+        Write::<'_, Screenshots>::setup(res);
+        #[cfg(feature = "gpu_timing")]
+        Write::<'_, PassTimings>::setup(res);
 
         let mat = create_default_mat(res);
         res.insert(MaterialDefaults(mat));
+        if !res.has_value::<MissingTextures>() {
+            let missing = create_missing_textures(res);
+            res.insert(missing);
+        }
         let (width, height) = self
             .renderer
             .window()
@@ -247,6 +355,7 @@ fn create_default_mat(res: &mut Resources) -> Material {
         albedo_offset: TextureOffset::default(),
         emission,
         emission_offset: TextureOffset::default(),
+        emission_intensity: 1.0,
         normal,
         normal_offset: TextureOffset::default(),
         metallic,
@@ -257,9 +366,22 @@ fn create_default_mat(res: &mut Resources) -> Material {
         ambient_occlusion_offset: TextureOffset::default(),
         caveat,
         caveat_offset: TextureOffset::default(),
+        two_sided: false,
     }
 }
 
+fn create_missing_textures(res: &mut Resources) -> MissingTextures {
+    use amethyst_assets::Loader;
+
+    let loader = res.fetch::<Loader>();
+    let tex_storage = res.fetch();
+
+    let color = loader.load_from_data(MissingTextures::checker(64, 8), (), &tex_storage);
+    let normal = loader.load_from_data([0.5, 0.5, 1.0, 1.0].into(), (), &tex_storage);
+
+    MissingTextures { color, normal }
+}
+
 /// Input devices can sometimes generate a lot of motion events per frame, these are
 /// useless as the extra precision is wasted and these events tend to overflow our
 /// otherwise very adequate event buffers.  So this function removes and compresses redundant
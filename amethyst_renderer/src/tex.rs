@@ -17,6 +17,7 @@ use amethyst_error::Error;
 
 use crate::{
     formats::TextureData,
+    pipe::ColorBuffer,
     types::{ChannelFormat, Factory, RawShaderResourceView, RawTexture, Sampler, SurfaceFormat},
 };
 
@@ -57,6 +58,71 @@ impl Texture {
         let (w, h, _, _) = self.texture.get_info().kind.get_dimensions();
         (w as usize, h as usize)
     }
+
+    /// Returns the underlying texture.
+    pub(crate) fn raw(&self) -> &RawTexture {
+        &self.texture
+    }
+
+    /// Creates a `size`×`size` cubemap with `levels` mip levels whose faces can be rendered
+    /// into, e.g. by a reflection probe. Its contents are undefined until something is drawn into
+    /// every face of every level.
+    pub(crate) fn render_cubemap(
+        size: u16,
+        levels: u8,
+        fac: &mut Factory,
+    ) -> Result<Texture, Error> {
+        use gfx::{
+            format::{SurfaceTyped, Swizzle},
+            memory::{Bind, Usage},
+            texture::ResourceDesc,
+            Factory,
+        };
+
+        let info = Info {
+            kind: Kind::Cube(size),
+            levels,
+            format: SurfaceFormat::get_surface_type(),
+            bind: Bind::SHADER_RESOURCE | Bind::RENDER_TARGET,
+            usage: Usage::Data,
+        };
+        let texture = fac.create_texture_raw(info, Some(ChannelType::Unorm), None)?;
+        let desc = ResourceDesc {
+            channel: ChannelType::Unorm,
+            layer: None,
+            min: 0,
+            max: levels - 1,
+            swizzle: Swizzle::new(),
+        };
+        let view = fac.view_texture_as_shader_resource_raw(&texture, desc)?;
+        let filter = if levels > 1 {
+            FilterMethod::Trilinear
+        } else {
+            FilterMethod::Bilinear
+        };
+        let sampler = fac.create_sampler(SamplerInfo::new(filter, WrapMode::Clamp));
+        Ok(Texture {
+            sampler,
+            texture,
+            view,
+        })
+    }
+
+    /// Wraps a render target's color buffer, so what is rendered into it can be sampled.
+    ///
+    /// Returns `None` for buffers that can't be sampled, like the backbuffer's.
+    pub(crate) fn from_color_buf(buf: &ColorBuffer, fac: &mut Factory) -> Option<Texture> {
+        use gfx::{memory::Typed, Factory};
+
+        let view = buf.as_input.as_ref()?.raw().clone();
+        let texture = buf.texture.clone()?;
+        let sampler = fac.create_sampler(SamplerInfo::new(FilterMethod::Bilinear, WrapMode::Clamp));
+        Some(Texture {
+            sampler,
+            texture,
+            view,
+        })
+    }
 }
 
 impl Asset for Texture {
@@ -126,6 +192,9 @@ where
 
     /// Sets the number of mipmap levels to generate.
     ///
+    /// If the data holds every level, each layer followed by its successively halved levels,
+    /// they are uploaded as they are.
+    ///
     /// FIXME: Only encoders can generate mipmap levels.
     pub fn mip_levels(mut self, val: u8) -> Self {
         self.info.levels = val;
@@ -163,6 +232,12 @@ where
         self
     }
 
+    /// Limits anisotropic filtering to the `max` samples the device supports.
+    pub(crate) fn clamp_anisotropy(mut self, max: u8) -> Self {
+        self.sampler.filter = clamp_anisotropy(self.sampler.filter, max);
+        self
+    }
+
     /// Builds and returns the new texture.
     pub fn build(self, fac: &mut Factory) -> Result<Texture, Error> {
         use std::mem::size_of;
@@ -180,8 +255,9 @@ where
             0 => 1 as usize,
             _ => a as usize,
         };
-        let slice_len = data.len() / a;
-        let mut arr = Vec::with_capacity(a);
+        let pixel_width = (self.info.format.get_total_bits() / 8) as usize / size_of::<T>();
+        let levels = usize::from(self.info.levels);
+        let mut regions = upload_regions(data.len(), a, (w, h), levels, pixel_width);
 
         if cfg!(feature = "opengl") {
             // Cubemaps in OpenGL are a special case that do not expect the images to be flipped vertically
@@ -189,23 +265,23 @@ where
                 Kind::Cube(_) => {}
                 _ => {
                     v_flip_buffer = Vec::with_capacity(data.len());
-                    let pixel_width =
-                        (self.info.format.get_total_bits() / 8) as usize / size_of::<T>();
-
-                    for y in 0..h {
-                        for x in 0..(w * pixel_width) {
-                            v_flip_buffer.push(data[x + (h - y - 1) * w * pixel_width]);
-                            // Uncomment this if you need to debug this.
-                            //println!("x: {}, y: {}, w: {}, h: {}, pw: {}", x, y, w, h, pixel_width);
+                    for region in &mut regions {
+                        let (start, _, w, h) = *region;
+                        let row = w * pixel_width;
+                        let flipped = v_flip_buffer.len();
+                        for y in (0..h).rev() {
+                            v_flip_buffer.extend_from_slice(&data[start + y * row..][..row]);
                         }
+                        *region = (flipped, w * h * pixel_width, w, h);
                     }
                     data = &v_flip_buffer;
                 }
             }
         };
-        for i in 0..a {
-            arr.push(cast_slice(&data[(i * slice_len)..((i + 1) * slice_len)]));
-        }
+        let arr: Vec<_> = regions
+            .iter()
+            .map(|&(start, len, _, _)| cast_slice(&data[start..start + len]))
+            .collect();
 
         let tex = fac.create_texture_raw(
             self.info,
@@ -231,3 +307,109 @@ where
         })
     }
 }
+
+/// Returns the start, length, width and height in `data` of every level of every layer.
+///
+/// Every layer is uploaded as one slice, unless the data holds all of its `levels` mip levels,
+/// each layer followed by its successively halved levels.
+fn upload_regions(
+    len: usize,
+    layers: usize,
+    (w, h): (usize, usize),
+    levels: usize,
+    pixel_width: usize,
+) -> Vec<(usize, usize, usize, usize)> {
+    let slice_len = len / layers;
+    let level_size = |level: usize| ((w >> level).max(1), (h >> level).max(1));
+    let level_len = |level: usize| {
+        let (w, h) = level_size(level);
+        w * h * pixel_width
+    };
+
+    let levels = levels.max(1);
+    let levels = if layers * (0..levels).map(level_len).sum::<usize>() == len {
+        levels
+    } else {
+        1
+    };
+    let mut regions = Vec::with_capacity(layers * levels);
+    for layer in 0..layers {
+        let mut start = layer * slice_len;
+        for level in 0..levels {
+            let (w, h) = level_size(level);
+            let len = if levels > 1 {
+                level_len(level)
+            } else {
+                slice_len
+            };
+            regions.push((start, len, w, h));
+            start += len;
+        }
+    }
+    regions
+}
+
+/// Clamps the samples of `FilterMethod::Anisotropic` to the `max` the device supports, falling
+/// back to trilinear filtering on devices without anisotropic filtering.
+fn clamp_anisotropy(filter: FilterMethod, max: u8) -> FilterMethod {
+    match filter {
+        FilterMethod::Anisotropic(_) if max <= 1 => FilterMethod::Trilinear,
+        FilterMethod::Anisotropic(level) => FilterMethod::Anisotropic(level.max(1).min(max)),
+        filter => filter,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{clamp_anisotropy, upload_regions, FilterMethod};
+
+    #[test]
+    fn every_level_of_every_face_is_sliced_at_its_offset() {
+        // Six 4x4 faces of 4 byte texels, each followed by its 2x2 and 1x1 levels.
+        let face = (16 + 4 + 1) * 4;
+        let regions = upload_regions(6 * face, 6, (4, 4), 3, 4);
+        assert_eq!(regions.len(), 18);
+        for (layer, levels) in regions.chunks(3).enumerate() {
+            let start = layer * face;
+            assert_eq!(
+                levels,
+                &[
+                    (start, 64, 4, 4),
+                    (start + 64, 16, 2, 2),
+                    (start + 80, 4, 1, 1),
+                ][..]
+            );
+        }
+    }
+
+    #[test]
+    fn data_without_its_levels_is_sliced_by_layer() {
+        let regions = upload_regions(6 * 64, 6, (4, 4), 3, 4);
+        assert_eq!(regions.len(), 6);
+        assert_eq!(regions[5], (5 * 64, 64, 4, 4));
+    }
+
+    #[test]
+    fn anisotropy_is_clamped_to_the_device() {
+        assert_eq!(
+            clamp_anisotropy(FilterMethod::Anisotropic(16), 8),
+            FilterMethod::Anisotropic(8)
+        );
+        assert_eq!(
+            clamp_anisotropy(FilterMethod::Anisotropic(4), 16),
+            FilterMethod::Anisotropic(4)
+        );
+        assert_eq!(
+            clamp_anisotropy(FilterMethod::Anisotropic(0), 16),
+            FilterMethod::Anisotropic(1)
+        );
+        assert_eq!(
+            clamp_anisotropy(FilterMethod::Anisotropic(16), 1),
+            FilterMethod::Trilinear
+        );
+        assert_eq!(
+            clamp_anisotropy(FilterMethod::Bilinear, 1),
+            FilterMethod::Bilinear
+        );
+    }
+}
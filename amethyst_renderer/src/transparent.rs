@@ -1,7 +1,44 @@
 pub use gfx::preset::blend::{ALPHA, REPLACE};
 pub use gfx_core::state::{Blend, BlendChannel, BlendValue, ColorMask, Equation, Factor};
 
-use amethyst_core::specs::{prelude::Component, storage::NullStorage};
+use amethyst_core::specs::{
+    prelude::{Component, DenseVecStorage},
+    storage::NullStorage,
+};
+use serde::{Deserialize, Serialize};
+
+/// Adds the source color weighted by its alpha to what is behind it, for fire, sparks and glows.
+///
+/// The result doesn't depend on the order of the draws, so additive geometry needs no sorting.
+pub const ADDITIVE: Blend = Blend {
+    color: BlendChannel {
+        equation: Equation::Add,
+        source: Factor::ZeroPlus(BlendValue::SourceAlpha),
+        destination: Factor::One,
+    },
+    alpha: BlendChannel {
+        equation: Equation::Add,
+        source: Factor::ZeroPlus(BlendValue::SourceAlpha),
+        destination: Factor::One,
+    },
+};
+
+/// Alpha blending of colors already multiplied by their alpha.
+///
+/// Soft edges of premultiplied textures composite without dark or bright fringes, even where
+/// they are filtered.
+pub const PREMULTIPLIED: Blend = Blend {
+    color: BlendChannel {
+        equation: Equation::Add,
+        source: Factor::One,
+        destination: Factor::OneMinus(BlendValue::SourceAlpha),
+    },
+    alpha: BlendChannel {
+        equation: Equation::Add,
+        source: Factor::One,
+        destination: Factor::OneMinus(BlendValue::SourceAlpha),
+    },
+};
 
 /// Transparent mesh component
 #[derive(Clone, Debug, Default)]
@@ -10,3 +47,17 @@ pub struct Transparent;
 impl Component for Transparent {
     type Storage = NullStorage<Self>;
 }
+
+/// Sorts a transparent entity as if it was this far in front of the camera
+///
+/// Transparent entities are drawn from the farthest to the nearest along the camera's view
+/// direction, so they blend over what's behind them. That goes wrong for entities whose origin
+/// doesn't tell how far away they are, like a window in front of a large glass dome centered
+/// behind it. Giving them a key fixes their place in the order, `f32::MAX` draws an entity before
+/// all others and `0.0` after everything in front of the camera.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct SortKey(pub f32);
+
+impl Component for SortKey {
+    type Storage = DenseVecStorage<Self>;
+}
@@ -0,0 +1,179 @@
+//! Implements a render pass outside of the renderer crate, using only its public API.
+//!
+//! `DrawFlatColor` draws every mesh in a single color, shading it darker with distance from
+//! the camera.
+
+use amethyst::{
+    assets::{AssetLoaderSystemData, AssetStorage},
+    core::{
+        nalgebra::{Matrix4, Vector3},
+        specs::prelude::{Join, Read, ReadStorage},
+        GlobalTransform, Transform, TransformBundle,
+    },
+    error::Error,
+    prelude::*,
+    renderer::{
+        get_camera,
+        pipe::pass::{Pass, PassData},
+        ActiveCamera, Camera, DepthMode, DisplayConfig, Effect, Encoder, Factory, Mesh, MeshHandle,
+        NewEffect, Pipeline, PosNormTex, Position, Projection, Query, RenderBundle, Rgba, Shape,
+        Stage, VertexFormat,
+    },
+    utils::application_root_dir,
+};
+
+const VERT_SRC: &[u8] = b"
+#version 150 core
+
+uniform mat4 mvp;
+uniform mat4 model_view;
+
+in vec3 position;
+
+out float distance;
+
+void main() {
+    distance = length((model_view * vec4(position, 1.0)).xyz);
+    gl_Position = mvp * vec4(position, 1.0);
+}
+";
+
+const FRAG_SRC: &[u8] = b"
+#version 150 core
+
+uniform vec4 flat_color;
+
+in float distance;
+
+out vec4 color;
+
+void main() {
+    color = vec4(flat_color.rgb / (1.0 + 0.05 * distance), flat_color.a);
+}
+";
+
+/// Draws meshes in one color, darkening with distance.
+struct DrawFlatColor {
+    color: Rgba,
+}
+
+impl<'a> PassData<'a> for DrawFlatColor {
+    type Data = (
+        Read<'a, ActiveCamera>,
+        ReadStorage<'a, Camera>,
+        Read<'a, AssetStorage<Mesh>>,
+        ReadStorage<'a, MeshHandle>,
+        ReadStorage<'a, GlobalTransform>,
+    );
+}
+
+impl Pass for DrawFlatColor {
+    fn compile(&mut self, effect: NewEffect<'_>) -> Result<Effect, Error> {
+        let stride = <PosNormTex as VertexFormat>::size() as u8;
+        effect
+            .simple(VERT_SRC, FRAG_SRC)
+            .with_raw_vertex_buffer(
+                <PosNormTex as Query<(Position,)>>::QUERIED_ATTRIBUTES,
+                stride,
+                0,
+            )
+            .with_raw_global("mvp")
+            .with_raw_global("model_view")
+            .with_raw_global("flat_color")
+            .with_output("color", Some(DepthMode::LessEqualWrite))
+            .build()
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        _factory: Factory,
+        (active, camera, mesh_storage, mesh, global): <Self as PassData<'a>>::Data,
+    ) {
+        let (proj, view) = match get_camera(active, &camera, &global) {
+            Some((camera, transform)) => (
+                camera.proj,
+                transform.0.try_inverse().unwrap_or_else(Matrix4::identity),
+            ),
+            None => return,
+        };
+        let color: [f32; 4] = self.color.into();
+        effect.update_global("flat_color", color);
+
+        for (mesh, global) in (&mesh, &global).join() {
+            let mesh = match mesh_storage.get(mesh) {
+                Some(mesh) => mesh,
+                None => continue,
+            };
+            let vbuf = match mesh.buffer(<PosNormTex as Query<(Position,)>>::QUERIED_ATTRIBUTES) {
+                Some(vbuf) => vbuf,
+                None => continue,
+            };
+            effect.add_vertex_buffer(vbuf);
+
+            let model_view = view * global.0;
+            effect.update_global("mvp", Into::<[[f32; 4]; 4]>::into(proj * model_view));
+            effect.update_global("model_view", Into::<[[f32; 4]; 4]>::into(model_view));
+            effect.draw(mesh.slice(), encoder);
+            effect.clear();
+        }
+    }
+}
+
+struct Example;
+
+impl SimpleState for Example {
+    fn on_start(&mut self, data: StateData<'_, GameData<'_, '_>>) {
+        let StateData { world, .. } = data;
+
+        let mesh = world.exec(|loader: AssetLoaderSystemData<'_, Mesh>| {
+            loader.load_from_data(Shape::Sphere(32, 32).generate::<Vec<PosNormTex>>(None), ())
+        });
+        for i in 0..5 {
+            let mut transform = Transform::default();
+            transform.set_xyz(2.5 * (i as f32 - 2.0), 0.0, 4.0 * i as f32);
+            world
+                .create_entity()
+                .with(transform)
+                .with(mesh.clone())
+                .build();
+        }
+
+        let mut transform = Transform::default();
+        transform.set_xyz(0.0, 0.0, -12.0);
+        transform.rotate_local(Vector3::y_axis(), std::f32::consts::PI);
+        world
+            .create_entity()
+            .with(Camera::from(Projection::perspective(
+                1.3,
+                std::f32::consts::FRAC_PI_3,
+            )))
+            .with(transform)
+            .build();
+    }
+}
+
+fn main() -> amethyst::Result<()> {
+    amethyst::start_logger(Default::default());
+
+    let app_root = application_root_dir()?;
+    let path = app_root.join("examples/custom_pass/resources/display_config.ron");
+    let resources = app_root.join("examples/assets/");
+    let display_config = DisplayConfig::load(&path);
+
+    let pipe = Pipeline::build().with_stage(
+        Stage::with_backbuffer()
+            .clear_target([0.1, 0.1, 0.1, 1.0], 1.0)
+            .with_pass(DrawFlatColor {
+                color: Rgba(0.2, 0.7, 1.0, 1.0),
+            }),
+    );
+
+    let game_data = GameDataBuilder::default()
+        .with_bundle(TransformBundle::new())?
+        .with_bundle(RenderBundle::new(pipe, Some(display_config)))?;
+    let mut game = Application::new(&resources, Example, game_data)?;
+    game.run();
+    Ok(())
+}
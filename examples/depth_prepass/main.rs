@@ -0,0 +1,143 @@
+//! Measures how much a depth prepass saves in a scene with a lot of overdraw.
+//!
+//! Hundreds of spheres are stacked in front of the camera and drawn back to front, so without a
+//! prepass every pixel is shaded many times over. Run it once as is and once with
+//! `--no-prepass` and compare the frame rates printed every few seconds; vsync is disabled so
+//! the difference shows.
+
+use amethyst::{
+    assets::AssetLoaderSystemData,
+    core::{nalgebra::Vector3, Time, Transform, TransformBundle},
+    ecs::prelude::{Read, System},
+    prelude::*,
+    renderer::*,
+    utils::{
+        application_root_dir,
+        fps_counter::{FPSCounter, FPSCounterBundle},
+    },
+};
+
+const LAYERS: usize = 16;
+const GRID: usize = 8;
+
+struct Example;
+
+impl SimpleState for Example {
+    fn on_start(&mut self, data: StateData<'_, GameData<'_, '_>>) {
+        let StateData { world, .. } = data;
+        let mat_defaults = world.read_resource::<MaterialDefaults>().0.clone();
+
+        let mesh = world.exec(|loader: AssetLoaderSystemData<'_, Mesh>| {
+            loader.load_from_data(
+                Shape::Sphere(32, 32).generate::<Vec<PosNormTangTex>>(None),
+                (),
+            )
+        });
+        let albedo = world.exec(|loader: AssetLoaderSystemData<'_, Texture>| {
+            loader.load_from_data([0.8, 0.8, 0.8, 1.0].into(), ())
+        });
+        let mtl = Material {
+            albedo,
+            ..mat_defaults
+        };
+
+        // The farthest layer is created first, so it's also drawn first and every closer layer
+        // shades the same pixels again unless the prepass rejects them.
+        for layer in (0..LAYERS).rev() {
+            for i in 0..GRID {
+                for j in 0..GRID {
+                    let offset = |n: usize| 1.5 * (n as f32 - (GRID - 1) as f32 / 2.0);
+                    let mut pos = Transform::default();
+                    pos.set_xyz(offset(i), offset(j), 2.0 * layer as f32);
+                    world
+                        .create_entity()
+                        .with(pos)
+                        .with(mesh.clone())
+                        .with(mtl.clone())
+                        .build();
+                }
+            }
+        }
+
+        for (i, color) in [[1.0f32, 0.5, 0.5], [0.5, 1.0, 0.5], [0.5, 0.5, 1.0]]
+            .iter()
+            .enumerate()
+        {
+            let light: Light = PointLight {
+                intensity: 8.0,
+                radius: 40.0,
+                color: (*color).into(),
+                ..PointLight::default()
+            }
+            .into();
+            let mut transform = Transform::default();
+            transform.set_xyz(6.0 * (i as f32 - 1.0), 6.0, -8.0);
+            world.create_entity().with(light).with(transform).build();
+        }
+
+        let mut transform = Transform::default();
+        transform.set_xyz(0.0, 0.0, -12.0);
+        transform.rotate_local(Vector3::y_axis(), std::f32::consts::PI);
+        world
+            .create_entity()
+            .with(Camera::from(Projection::perspective(
+                1.3,
+                std::f32::consts::FRAC_PI_3,
+            )))
+            .with(transform)
+            .build();
+    }
+}
+
+/// Prints the sampled frame rate every few seconds.
+#[derive(Default)]
+struct ReportSystem {
+    elapsed: f32,
+}
+
+impl<'a> System<'a> for ReportSystem {
+    type SystemData = (Read<'a, Time>, Read<'a, FPSCounter>);
+
+    fn run(&mut self, (time, fps): Self::SystemData) {
+        self.elapsed += time.delta_seconds();
+        if self.elapsed >= 3.0 {
+            self.elapsed = 0.0;
+            println!("FPS: {:.1}", fps.sampled_fps());
+        }
+    }
+}
+
+fn main() -> amethyst::Result<()> {
+    amethyst::start_logger(Default::default());
+
+    let app_root = application_root_dir()?;
+    let path = app_root.join("examples/depth_prepass/resources/display_config.ron");
+    let resources = app_root.join("examples/assets/");
+    let display_config = DisplayConfig::load(&path);
+
+    let prepass = std::env::args().all(|arg| arg != "--no-prepass");
+    println!(
+        "Drawing {} spheres {} a depth prepass",
+        LAYERS * GRID * GRID,
+        if prepass { "with" } else { "without" }
+    );
+
+    let stage = Stage::with_backbuffer().clear_target([0.0, 0.0, 0.0, 1.0], 1.0);
+    let stage = if prepass {
+        stage
+            .with_pass(DrawDepth::<PosNormTangTex>::new())
+            .with_pass(DrawPbm::<PosNormTangTex>::new().with_depth_prepass())
+    } else {
+        stage.with_pass(DrawPbm::<PosNormTangTex>::new())
+    };
+    let pipe = Pipeline::build().with_stage(stage);
+
+    let game_data = GameDataBuilder::default()
+        .with_bundle(TransformBundle::new())?
+        .with_bundle(FPSCounterBundle::default())?
+        .with(ReportSystem::default(), "report", &[])
+        .with_bundle(RenderBundle::new(pipe, Some(display_config)))?;
+    let mut game = Application::new(&resources, Example, game_data)?;
+    game.run();
+    Ok(())
+}
@@ -0,0 +1,181 @@
+//! Dissolves spheres drawn by `DrawFlat` through a per-entity uniform read by a custom shader.
+
+use amethyst::{
+    assets::AssetLoaderSystemData,
+    core::{nalgebra::Vector3, Time, Transform, TransformBundle},
+    ecs::prelude::{Component, Join, Read, System, VecStorage, WriteStorage},
+    prelude::*,
+    renderer::{
+        Camera, DrawFlat, EntityUniform, Material, MaterialDefaults, Mesh, PosNormTex, Projection,
+        Shape, Texture,
+    },
+    utils::application_root_dir,
+};
+use glsl_layout::{float, Uniform};
+
+/// `flat.glsl`, discarding the fragments whose noise lies below the amount of the `Dissolve`.
+const FRAG_SRC: &[u8] = b"
+#version 150 core
+
+uniform sampler2D albedo;
+
+layout (std140) uniform AlbedoOffset {
+    vec2 u_offset;
+    vec2 v_offset;
+} albedo_offset;
+
+layout (std140) uniform Dissolve {
+    float amount;
+} dissolve;
+
+in VertexData {
+    vec3 position;
+    vec3 normal;
+    vec3 tangent;
+    vec2 tex_coord;
+    vec4 color;
+} vertex;
+
+out vec4 color;
+
+float tex_coord(float coord, vec2 offset) {
+    return offset.x + coord * (offset.y - offset.x);
+}
+
+vec2 tex_coords(vec2 coord, vec2 u, vec2 v) {
+    return vec2(tex_coord(coord.x, u), tex_coord(coord.y, v));
+}
+
+float noise(vec2 coord) {
+    return fract(sin(dot(floor(coord * 64.0), vec2(12.9898, 78.233))) * 43758.5453);
+}
+
+void main() {
+    if (noise(vertex.tex_coord) < dissolve.amount) {
+        discard;
+    }
+    vec2 coords = tex_coords(
+        vertex.tex_coord,
+        albedo_offset.u_offset,
+        albedo_offset.v_offset
+    );
+    color = texture(albedo, coords) * vertex.color;
+}
+";
+
+/// The `Dissolve` uniform block of the shader.
+#[repr(C, align(16))]
+#[derive(Clone, Copy, Debug, Uniform)]
+struct DissolveArgs {
+    amount: float,
+}
+
+/// How much of a sphere has dissolved, from `0.0` for all of it drawn to `1.0` for none.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Dissolve {
+    amount: f32,
+    phase: f32,
+}
+
+impl Component for Dissolve {
+    type Storage = VecStorage<Self>;
+}
+
+impl EntityUniform for Dissolve {
+    const NAME: &'static str = "Dissolve";
+    type Uniform = DissolveArgs;
+
+    fn uniform(&self) -> DissolveArgs {
+        DissolveArgs {
+            amount: self.amount,
+        }
+    }
+}
+
+/// Dissolves the spheres and brings them back, each at its own phase.
+struct DissolveSystem;
+
+impl<'a> System<'a> for DissolveSystem {
+    type SystemData = (Read<'a, Time>, WriteStorage<'a, Dissolve>);
+
+    fn run(&mut self, (time, mut dissolves): Self::SystemData) {
+        let elapsed = time.absolute_time_seconds() as f32;
+        for dissolve in (&mut dissolves).join() {
+            dissolve.amount = 0.5 + 0.5 * (elapsed + dissolve.phase).sin();
+        }
+    }
+}
+
+struct Example;
+
+impl SimpleState for Example {
+    fn on_start(&mut self, data: StateData<'_, GameData<'_, '_>>) {
+        let StateData { world, .. } = data;
+        let mat_defaults = world.read_resource::<MaterialDefaults>().0.clone();
+
+        let mesh = world.exec(|loader: AssetLoaderSystemData<'_, Mesh>| {
+            loader.load_from_data(Shape::Sphere(32, 32).generate::<Vec<PosNormTex>>(None), ())
+        });
+        let albedo = world.exec(|loader: AssetLoaderSystemData<'_, Texture>| {
+            loader.load_from_data([0.9, 0.5, 0.1, 1.0].into(), ())
+        });
+        let material = Material {
+            albedo,
+            ..mat_defaults
+        };
+
+        for i in 0..5 {
+            let mut transform = Transform::default();
+            transform.set_xyz(2.5 * (i as f32 - 2.0), 0.0, 0.0);
+            world
+                .create_entity()
+                .with(transform)
+                .with(mesh.clone())
+                .with(material.clone())
+                .with(Dissolve {
+                    amount: 0.0,
+                    phase: i as f32 * 0.6,
+                })
+                .build();
+        }
+
+        // Entities without a `Dissolve` are drawn with the default, never dissolving.
+        let mut transform = Transform::default();
+        transform.set_xyz(0.0, -2.5, 0.0);
+        world
+            .create_entity()
+            .with(transform)
+            .with(mesh)
+            .with(material)
+            .build();
+
+        let mut transform = Transform::default();
+        transform.set_xyz(0.0, 0.0, -12.0);
+        transform.rotate_local(Vector3::y_axis(), std::f32::consts::PI);
+        world
+            .create_entity()
+            .with(Camera::from(Projection::perspective(
+                1.3,
+                std::f32::consts::FRAC_PI_3,
+            )))
+            .with(transform)
+            .build();
+    }
+}
+
+fn main() -> amethyst::Result<()> {
+    amethyst::start_logger(Default::default());
+
+    let app_root = application_root_dir()?;
+    let path = app_root.join("examples/dissolve/resources/display_config.ron");
+    let resources = app_root.join("examples/assets/");
+
+    let pass = DrawFlat::<PosNormTex>::new().with_uniform::<Dissolve>(FRAG_SRC);
+    let game_data = GameDataBuilder::default()
+        .with(DissolveSystem, "dissolve", &[])
+        .with_bundle(TransformBundle::new())?
+        .with_basic_renderer(path, pass, false)?;
+    let mut game = Application::new(&resources, Example, game_data)?;
+    game.run();
+    Ok(())
+}
@@ -0,0 +1,139 @@
+//! Draws a grid of cubes, either with one draw call per cube or with a single instanced draw.
+//!
+//! Run with `--instanced` to use `DrawFlatInstanced`, and optionally pass the grid width as the
+//! last argument (defaults to 64, i.e. 4096 cubes). The sampled frame rate is logged every two
+//! seconds, so running the example both ways compares N individual draws against one instanced
+//! draw. Vsync is disabled in the display config to keep the numbers meaningful.
+
+use amethyst::{
+    assets::AssetLoaderSystemData,
+    core::{
+        nalgebra::Vector3,
+        specs::prelude::{Read, System},
+        Time, Transform, TransformBundle,
+    },
+    prelude::*,
+    renderer::{pipe::pass::Pass, *},
+    utils::{
+        application_root_dir,
+        fps_counter::{FPSCounter, FPSCounterBundle},
+    },
+};
+use log::info;
+
+struct Example {
+    grid: usize,
+}
+
+impl SimpleState for Example {
+    fn on_start(&mut self, data: StateData<'_, GameData<'_, '_>>) {
+        let StateData { world, .. } = data;
+        let mat_defaults = world.read_resource::<MaterialDefaults>().0.clone();
+
+        let mesh = world.exec(|loader: AssetLoaderSystemData<'_, Mesh>| {
+            loader.load_from_data(
+                Shape::Cube.generate::<Vec<PosTex>>(Some((0.2, 0.2, 0.2))),
+                (),
+            )
+        });
+
+        let half = self.grid as f32 / 2.0;
+        for i in 0..self.grid {
+            for j in 0..self.grid {
+                let mut pos = Transform::default();
+                pos.set_xyz(i as f32 - half, j as f32 - half, 0.0);
+
+                let tint = Rgba(
+                    i as f32 / self.grid as f32,
+                    j as f32 / self.grid as f32,
+                    1.0,
+                    1.0,
+                );
+
+                world
+                    .create_entity()
+                    .with(pos)
+                    .with(mesh.clone())
+                    .with(mat_defaults.clone())
+                    .with(tint)
+                    .build();
+            }
+        }
+
+        let mut transform = Transform::default();
+        transform.set_xyz(0.0, 0.0, -self.grid as f32);
+        transform.rotate_local(Vector3::y_axis(), std::f32::consts::PI);
+
+        world
+            .create_entity()
+            .with(Camera::from(Projection::perspective(
+                1.3,
+                std::f32::consts::FRAC_PI_2,
+            )))
+            .with(transform)
+            .build();
+    }
+}
+
+/// Logs the sampled frame rate every two seconds.
+#[derive(Default)]
+struct FpsLogSystem {
+    elapsed: f32,
+}
+
+impl<'a> System<'a> for FpsLogSystem {
+    type SystemData = (Read<'a, Time>, Read<'a, FPSCounter>);
+
+    fn run(&mut self, (time, fps): Self::SystemData) {
+        self.elapsed += time.delta_seconds();
+        if self.elapsed >= 2.0 {
+            self.elapsed = 0.0;
+            info!("FPS: {:.1}", fps.sampled_fps());
+        }
+    }
+}
+
+fn run<P: Pass + 'static>(pass: P, grid: usize) -> amethyst::Result<()> {
+    let app_root = application_root_dir()?;
+
+    let path = app_root.join("examples/instancing/resources/display_config.ron");
+
+    let resources = app_root.join("examples/assets/");
+
+    let game_data = GameDataBuilder::default()
+        .with_bundle(TransformBundle::new())?
+        .with_bundle(FPSCounterBundle::default())?
+        .with(FpsLogSystem::default(), "fps_log", &[])
+        .with_basic_renderer(path, pass, false)?;
+    let mut game = Application::new(&resources, Example { grid }, game_data)?;
+    game.run();
+    Ok(())
+}
+
+fn main() -> amethyst::Result<()> {
+    amethyst::start_logger(Default::default());
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let instanced = args.iter().any(|arg| arg == "--instanced");
+    let grid = args
+        .iter()
+        .filter_map(|arg| arg.parse().ok())
+        .last()
+        .unwrap_or(64);
+
+    info!(
+        "Drawing {} cubes {}",
+        grid * grid,
+        if instanced {
+            "with one instanced draw"
+        } else {
+            "with one draw call each"
+        }
+    );
+
+    if instanced {
+        run(DrawFlatInstanced::<PosTex>::new(), grid)
+    } else {
+        run(DrawFlat::<PosTex>::new(), grid)
+    }
+}
@@ -0,0 +1,98 @@
+//! Shows the falloff of point lights hovering just above a plane.
+//!
+//! The three lights share the same intensity but differ in radius and smoothness, so each one
+//! lights a differently sized and shaped circle on the floor before fading out to black.
+
+use amethyst::{
+    assets::AssetLoaderSystemData,
+    core::{nalgebra::Vector3, Transform, TransformBundle},
+    prelude::*,
+    renderer::*,
+    utils::application_root_dir,
+};
+
+struct Example;
+
+impl SimpleState for Example {
+    fn on_start(&mut self, data: StateData<'_, GameData<'_, '_>>) {
+        let StateData { world, .. } = data;
+        let mat_defaults = world.read_resource::<MaterialDefaults>().0.clone();
+
+        let mesh = world.exec(|loader: AssetLoaderSystemData<'_, Mesh>| {
+            loader.load_from_data(
+                Shape::Plane(Some((32, 32))).generate::<Vec<PosNormTex>>(None),
+                (),
+            )
+        });
+        let albedo = world.exec(|loader: AssetLoaderSystemData<'_, Texture>| {
+            loader.load_from_data([0.8, 0.8, 0.8, 1.0].into(), ())
+        });
+        let mtl = Material {
+            albedo,
+            ..mat_defaults
+        };
+
+        // The plane is generated in the XY plane, turn it into a floor.
+        let mut floor = Transform::default();
+        floor.rotate_local(Vector3::x_axis(), -std::f32::consts::FRAC_PI_2);
+        floor.set_scale(12.0, 12.0, 1.0);
+        world
+            .create_entity()
+            .with(floor)
+            .with(mesh)
+            .with(mtl)
+            .build();
+
+        let lights = [
+            ([1.0, 0.4, 0.4], 2.0, 4.0),
+            ([0.4, 1.0, 0.4], 4.0, 4.0),
+            ([0.4, 0.4, 1.0], 4.0, 1.0),
+        ];
+        for (i, &(color, radius, smoothness)) in lights.iter().enumerate() {
+            let light: Light = PointLight {
+                color: color.into(),
+                intensity: 3.0,
+                radius,
+                smoothness,
+            }
+            .into();
+            let mut transform = Transform::default();
+            transform.set_xyz(6.0 * (i as f32 - 1.0), 0.5, 0.0);
+            world.create_entity().with(light).with(transform).build();
+        }
+
+        let mut transform = Transform::default();
+        transform.set_xyz(0.0, 8.0, 10.0);
+        transform.rotate_local(Vector3::x_axis(), -0.7);
+        world
+            .create_entity()
+            .with(Camera::from(Projection::perspective(
+                1.3,
+                std::f32::consts::FRAC_PI_3,
+            )))
+            .with(transform)
+            .build();
+    }
+}
+
+fn main() -> amethyst::Result<()> {
+    amethyst::start_logger(Default::default());
+
+    let app_root = application_root_dir()?;
+    let path = app_root.join("examples/point_light/resources/display_config.ron");
+    let resources = app_root.join("examples/assets/");
+    let display_config = DisplayConfig::load(&path);
+
+    let pipe = Pipeline::build().with_stage(
+        Stage::with_backbuffer()
+            .clear_target([0.0, 0.0, 0.0, 1.0], 1.0)
+            .with_pass(DrawShaded::<PosNormTex>::new()),
+    );
+
+    let game_data = GameDataBuilder::default()
+        .with_bundle(TransformBundle::new())?
+        .with_bundle(RenderBundle::new(pipe, Some(display_config)))?;
+    let mut game = Application::new(&resources, Example, game_data)?;
+    game.run();
+    Ok(())
+}
@@ -0,0 +1,76 @@
+//! Saves a screenshot of the window as a PNG each time F12 is pressed.
+
+use amethyst::{
+    assets::{PrefabLoader, PrefabLoaderSystem, RonFormat},
+    core::transform::TransformBundle,
+    ecs::prelude::{System, Write},
+    input::is_key_down,
+    prelude::*,
+    renderer::{DrawShaded, PosNormTex, Screenshots},
+    utils::{application_root_dir, scene::BasicScenePrefab},
+    winit::VirtualKeyCode,
+};
+
+type MyPrefabData = BasicScenePrefab<Vec<PosNormTex>>;
+
+struct Example;
+
+impl SimpleState for Example {
+    fn on_start(&mut self, data: StateData<'_, GameData<'_, '_>>) {
+        let handle = data.world.exec(|loader: PrefabLoader<'_, MyPrefabData>| {
+            loader.load("prefab/sphere.ron", RonFormat, (), ())
+        });
+        data.world.create_entity().with(handle).build();
+    }
+
+    fn handle_event(
+        &mut self,
+        data: StateData<'_, GameData<'_, '_>>,
+        event: StateEvent,
+    ) -> SimpleTrans {
+        if let StateEvent::Window(event) = event {
+            if is_key_down(&event, VirtualKeyCode::F12) {
+                data.world.write_resource::<Screenshots>().request();
+            }
+        }
+        Trans::None
+    }
+}
+
+/// Saves the screenshots as they arrive, a few frames after they were requested.
+#[derive(Default)]
+struct SaveScreenshotSystem {
+    saved: usize,
+}
+
+impl<'a> System<'a> for SaveScreenshotSystem {
+    type SystemData = Write<'a, Screenshots>;
+
+    fn run(&mut self, mut screenshots: Self::SystemData) {
+        while let Some(screenshot) = screenshots.take() {
+            self.saved += 1;
+            let path = format!("screenshot_{}.png", self.saved);
+            match screenshot.rgba.save(&path) {
+                Ok(()) => println!("Saved {}", path),
+                Err(err) => println!("Failed to save {}: {}", path, err),
+            }
+        }
+    }
+}
+
+fn main() -> amethyst::Result<()> {
+    amethyst::start_logger(Default::default());
+
+    let app_root = application_root_dir()?;
+    let display_config_path = app_root.join("examples/screenshot/resources/display_config.ron");
+    let resources = app_root.join("examples/assets/");
+
+    let game_data = GameDataBuilder::default()
+        .with(PrefabLoaderSystem::<MyPrefabData>::default(), "", &[])
+        .with(SaveScreenshotSystem::default(), "save_screenshots", &[])
+        .with_bundle(TransformBundle::new())?
+        .with_basic_renderer(display_config_path, DrawShaded::<PosNormTex>::new(), false)?;
+    let mut game = Application::new(resources, Example, game_data)?;
+    game.run();
+    Ok(())
+}
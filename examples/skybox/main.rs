@@ -0,0 +1,91 @@
+//! Displays a shaded sphere in front of a cubemapped skybox.
+//!
+//! The skybox is drawn after the sphere, so the sphere staying visible shows that the sky only
+//! covers the background. A sun disc is drawn onto the sky in the direction of a light.
+
+use amethyst::{
+    assets::{AssetStorage, Loader, PrefabLoader, PrefabLoaderSystem, RonFormat},
+    core::transform::TransformBundle,
+    prelude::*,
+    renderer::{
+        DirectionalLight, DisplayConfig, DrawShaded, DrawSkyBox, DrawSkyOverlays, Light, Pipeline,
+        PosNormTex, RenderBundle, Rgba, SkyBox, SkyOverlay, Stage, Texture,
+    },
+    utils::{application_root_dir, scene::BasicScenePrefab},
+    Error,
+};
+
+type MyPrefabData = BasicScenePrefab<Vec<PosNormTex>>;
+
+struct Example;
+
+impl SimpleState for Example {
+    fn on_start(&mut self, data: StateData<'_, GameData<'_, '_>>) {
+        let StateData { world, .. } = data;
+
+        let handle = world.exec(|loader: PrefabLoader<'_, MyPrefabData>| {
+            loader.load("prefab/sphere.ron", RonFormat, (), ())
+        });
+        world.create_entity().with(handle).build();
+
+        let app_root = application_root_dir().expect("Failed to find the application root");
+        let face = |name: &str| {
+            app_root
+                .join("examples/assets/texture")
+                .join(name)
+                .to_string_lossy()
+                .into_owned()
+        };
+        let grass = face("grass.png");
+        let logo = face("logo.png");
+        let sky = SkyBox::from_faces(
+            [&*grass, &*grass, &*logo, &*logo, &*grass, &*grass],
+            256,
+            &world.read_resource::<Loader>(),
+            &world.read_resource::<AssetStorage<Texture>>(),
+        )
+        .expect("Failed to load the skybox faces");
+        world.create_entity().with(sky).build();
+
+        let sun = DirectionalLight {
+            color: Rgba(1.0, 0.95, 0.8, 1.0),
+            direction: [-0.5, -0.4, -1.0],
+            ..DirectionalLight::default()
+        };
+        world
+            .create_entity()
+            .with(SkyOverlay::sun(&sun))
+            .with(Light::from(sun))
+            .build();
+    }
+}
+
+fn main() -> Result<(), Error> {
+    amethyst::start_logger(Default::default());
+
+    let app_root = application_root_dir()?;
+
+    let display_config_path = app_root.join("examples/skybox/resources/display_config.ron");
+
+    let resources = app_root.join("examples/assets/");
+
+    let pipe = Pipeline::build().with_stage(
+        Stage::with_backbuffer()
+            .clear_target([0.0, 0.0, 0.0, 1.0], 1.0)
+            .skip_clear_under_sky(true)
+            .with_pass(DrawShaded::<PosNormTex>::new())
+            .with_pass(DrawSkyBox::new())
+            .with_pass(DrawSkyOverlays::new()),
+    );
+
+    let game_data = GameDataBuilder::default()
+        .with(PrefabLoaderSystem::<MyPrefabData>::default(), "", &[])
+        .with_bundle(TransformBundle::new())?
+        .with_bundle(RenderBundle::new(
+            pipe,
+            Some(DisplayConfig::load(&display_config_path)),
+        ))?;
+    let mut game = Application::new(resources, Example, game_data)?;
+    game.run();
+    Ok(())
+}
@@ -0,0 +1,105 @@
+//! Renders a scene from two cameras, one on each half of the window.
+
+use amethyst::{
+    assets::AssetLoaderSystemData,
+    core::{nalgebra::Vector3, Transform, TransformBundle},
+    prelude::*,
+    renderer::*,
+    utils::application_root_dir,
+};
+
+/// Size of the window, from the display config.
+const WIDTH: f32 = 1024.0;
+const HEIGHT: f32 = 512.0;
+
+struct Example;
+
+impl SimpleState for Example {
+    fn on_start(&mut self, data: StateData<'_, GameData<'_, '_>>) {
+        let StateData { world, .. } = data;
+        let mat_defaults = world.read_resource::<MaterialDefaults>().0.clone();
+
+        let (sphere, cube) = world.exec(|loader: AssetLoaderSystemData<'_, Mesh>| {
+            (
+                loader.load_from_data(Shape::Sphere(32, 32).generate::<Vec<PosNormTex>>(None), ()),
+                loader.load_from_data(
+                    Shape::Cube.generate::<Vec<PosNormTex>>(Some((0.5, 0.5, 0.5))),
+                    (),
+                ),
+            )
+        });
+
+        world
+            .create_entity()
+            .with(Transform::default())
+            .with(sphere)
+            .with(mat_defaults.clone())
+            .build();
+
+        for i in 0..4 {
+            let angle = i as f32 * std::f32::consts::FRAC_PI_2;
+            let mut transform = Transform::default();
+            transform.set_xyz(3.0 * angle.cos(), 0.0, 3.0 * angle.sin());
+            world
+                .create_entity()
+                .with(transform)
+                .with(cube.clone())
+                .with(mat_defaults.clone())
+                .build();
+        }
+
+        let mut light_transform = Transform::default();
+        light_transform.set_xyz(5.0, 5.0, 5.0);
+        world
+            .create_entity()
+            .with(Light::from(PointLight {
+                intensity: 10.0,
+                radius: 20.0,
+                ..PointLight::default()
+            }))
+            .with(light_transform)
+            .build();
+
+        // One camera looking at the scene from the front, drawn on the left half of the window.
+        let left = Viewport::new(0.0, 0.0, 0.5, 1.0);
+        let mut front = Transform::default();
+        front.set_xyz(0.0, 0.0, 10.0);
+        create_camera(world, left, front);
+
+        // And one looking down from above, drawn on the right half.
+        let right = Viewport::new(0.5, 0.0, 0.5, 1.0);
+        let mut above = Transform::default();
+        above.set_xyz(0.0, 10.0, 0.0);
+        above.rotate_local(Vector3::x_axis(), -std::f32::consts::FRAC_PI_2);
+        create_camera(world, right, above);
+    }
+}
+
+fn create_camera(world: &mut World, viewport: Viewport, transform: Transform) {
+    world
+        .create_entity()
+        .with(Camera::from(Projection::perspective(
+            viewport.aspect_ratio(WIDTH, HEIGHT),
+            std::f32::consts::FRAC_PI_3,
+        )))
+        .with(viewport)
+        .with(transform)
+        .build();
+}
+
+fn main() -> amethyst::Result<()> {
+    amethyst::start_logger(Default::default());
+
+    let app_root = application_root_dir()?;
+
+    let display_config_path = app_root.join("examples/split_screen/resources/display_config.ron");
+
+    let resources = app_root.join("examples/assets/");
+
+    let game_data = GameDataBuilder::default()
+        .with_bundle(TransformBundle::new())?
+        .with_basic_renderer(display_config_path, DrawShaded::<PosNormTex>::new(), false)?;
+    let mut game = Application::new(resources, Example, game_data)?;
+    game.run();
+    Ok(())
+}